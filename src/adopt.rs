@@ -0,0 +1,166 @@
+//
+// src/adopt.rs
+//
+// Implementation of git-toolbox adopt
+//
+// Takes over a toolbox dictionary that is already tracked as an ordinary file:
+// the dictionary is added to the configuration file (the record marker is the
+// first marker after the dictionary header unless given), the repository is
+// configured and the dictionary is staged, which splits it into the managed
+// folder and replaces its index entry by the placeholder. The configuration file
+// is restored if the repository cannot be configured.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::repository::Repository;
+use crate::config::{CONFIG_FILE, Config};
+use crate::cli_app::style;
+
+use anyhow::{Result, bail};
+use crate::error;
+
+use std::convert::TryFrom;
+
+
+pub fn adopt(path: String, record_tag: Option<String>) -> Result<()> {
+    let workdir = Repository::workdir_for_repo_here()?;
+    let full_path = crate::util::absolute_path(&path);
+    let path = Repository::get_path_relative_to_repo_here(&full_path)?.to_string_lossy().into_owned();
+
+    if !full_path.is_file() {
+        bail!(
+            error::FileNotFound {
+                path : full_path
+            }
+        );
+    }
+    if !Repository::is_tracked_here(&path)? {
+        bail!(
+            error::FileNotTracked {
+                path
+            }
+        );
+    }
+
+    // the configuration file (if there is one already)
+    let config_path = workdir.join(CONFIG_FILE);
+    let config_text = if config_path.exists() {
+        std::fs::read_to_string(&config_path).map_err(|err| {
+            error::FileReadError {
+                path : config_path.clone(),
+                msg  : err.to_string()
+            }
+        })?
+    } else {
+        String::new()
+    };
+
+    if !config_text.trim().is_empty() {
+        let config = Config::try_from(config_text.as_bytes())?;
+
+        if config.dictionaries.iter().any(|cfg| cfg.path == path) {
+            bail!(
+                error::AlreadyManagedFile {
+                    path
+                }
+            );
+        }
+    }
+
+    // the record marker of the dictionary
+    let data = std::fs::read(&full_path).map_err(|err| {
+        error::FileReadError {
+            path : full_path.clone(),
+            msg  : err.to_string()
+        }
+    })?;
+    let text = String::from_utf8_lossy(&data);
+
+    let record_tag = record_tag.or_else(|| detect_record_tag(&text));
+    let records = record_tag.as_ref().map(|tag| count_records(&text, tag)).unwrap_or(0);
+    let record_tag = match record_tag {
+        Some(tag) if records > 0 => tag,
+        _                        => bail!(error::NoRecordsToAdopt { path : full_path })
+    };
+
+    // add the dictionary to the configuration file
+    let name = std::path::Path::new(&path).file_stem().map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.clone());
+    let entry = format!(
+        "[[dictionary]]\nname       = {}\npath       = {}\nrecord-tag = {}\n",
+        toml::Value::from(name),
+        toml::Value::from(path.as_str()),
+        toml::Value::from(record_tag.as_str())
+    );
+    let new_config_text = match config_text.trim_end() {
+        ""   => entry,
+        text => format!("{}\n\n{}", text, entry)
+    };
+
+    write_config(&config_path, &new_config_text)?;
+
+    stdout!("{} added {} to {} (record marker \\{})",
+        style("✓").green(),
+        style(&path).bold(),
+        CONFIG_FILE,
+        record_tag
+    );
+
+    // configure the repository, restoring the configuration file if it fails
+    let configured = Repository::configure();
+    if let Err(err) = configured {
+        if config_text.is_empty() {
+            std::fs::remove_file(&config_path).ok();
+        } else {
+            write_config(&config_path, &config_text)?;
+        }
+
+        bail!(err)
+    }
+
+    // split the dictionary into the managed folder and stage it
+    crate::stage::stage(vec!(full_path.to_string_lossy().into_owned()), false, None, vec!(), Default::default())?;
+
+    stdout!("\n✅  {} is now managed by git-toolbox ({} records). Use {} to record the change",
+        style(&path).bold(),
+        records,
+        style("\"git commit\"").bold()
+    );
+
+    Ok( () )
+}
+
+// The record marker of a dictionary (the first marker after the header)
+//
+// The markers starting with an underscore are part of the header
+fn detect_record_tag(text: &str) -> Option<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with('\\') && !line.starts_with("\\_"))
+        .map(|line| line[1 ..].split_whitespace().next().unwrap_or("").to_owned())
+        .find(|tag| !tag.is_empty())
+}
+
+// The number of records in the dictionary
+fn count_records(text: &str, record_tag: &str) -> usize {
+    let marker = format!("\\{}", record_tag);
+
+    text.lines()
+        .filter(|line| {
+            line.strip_prefix(&marker).map(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace)).unwrap_or(false)
+        })
+        .count()
+}
+
+// Write the configuration file
+fn write_config(path: &std::path::Path, text: &str) -> Result<()> {
+    std::fs::write(path, text).map_err(|err| {
+        error::FileWriteError {
+            path : path.to_owned(),
+            msg  : err.to_string()
+        }
+        .into()
+    })
+}
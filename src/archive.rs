@@ -0,0 +1,43 @@
+//
+// src/archive.rs
+//
+// Implementation of git-toolbox archive
+//
+// Writes the managed files reconstructed at a revision (e.g. a release tag) to
+// a zip or tar archive, so that a release of the dataset can be deposited with
+// an archive that does not use git-toolbox. The managed folders can be included
+// as well, so that the records can be traced back to the repository.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::repository::Repository;
+use crate::cli_app::style;
+
+use anyhow::Result;
+
+
+pub fn archive(output: String, rev: Option<String>, contents: bool) -> Result<()> {
+    // load the repository
+    let repo = Repository::open()?;
+
+    let rev = rev.unwrap_or_else(|| "HEAD".to_owned());
+    let archive = repo.write_archive(&rev, std::path::Path::new(&output), contents)?;
+
+    for path in archive.paths.iter() {
+        stdout!("  {} {}", style("+").green(), path);
+    }
+    stdout!("");
+    stdout!("{} Wrote {} dictionaries of {} ({}) to {}",
+        style("✓").green(),
+        archive.paths.len(),
+        style(&rev).bold(),
+        &archive.source[..8],
+        style(&output).italic()
+    );
+
+    repo.record_audit_entry("archive", &archive.paths.join(","), None, &format!("source={} output={}", archive.source, output))?;
+
+    Ok( () )
+}
@@ -0,0 +1,179 @@
+//
+// src/autostage.rs
+//
+// Implementation of git-toolbox autostage
+//
+// Stages the managed toolbox files and commits them locally at a regular
+// interval, so that the work done on an unattended machine (e.g. a fieldwork
+// laptop that is not synchronized for weeks) is not lost if nobody remembers
+// to commit it. Only the managed files and their managed folders are committed,
+// with a generated message, and every commit is recorded in the audit log.
+// Nothing is pushed.
+//
+// The command runs until it is stopped. With --detach, it is started as a
+// background process writing its output to a log file in the git-toolbox data
+// directory. With --once, a single round is performed (for scheduled jobs).
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::repository::Repository;
+use crate::cli_app::style;
+use crate::util::{parse_interval, format_time, local_time_offset};
+
+use crate::error;
+use anyhow::Result;
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// name of the log file of the background process (in the git-toolbox data directory)
+const AUTOSTAGE_LOG_FILE : &str = "autostage.log";
+
+
+pub fn autostage(interval: String, once: bool, detach: bool) -> Result<()> {
+    let seconds = parse_interval(&interval).ok_or_else(|| {
+        error::InvalidInterval {
+            interval : interval.clone()
+        }
+    })?;
+
+    // check the repository before we start
+    let repo = Repository::open()?;
+
+    if detach {
+        return start_in_background(&repo, &interval);
+    }
+
+    if once {
+        return autostage_round();
+    }
+
+    stdout!("Staging and committing the managed toolbox files every {} (stop with Ctrl+C)",
+        style(&interval).bold()
+    );
+
+    loop {
+        // errors are reported, the next round might succeed
+        if let Err(err) = autostage_round() {
+            stderr!("{} {}", timestamp(), err);
+        }
+
+        std::thread::sleep(Duration::from_secs(seconds));
+    }
+}
+
+
+// Stage the managed files and commit them
+fn autostage_round() -> Result<()> {
+    // the configuration could have changed since the last round
+    let repo = Repository::open()?;
+
+    // never interfere with a merge, rebase etc.
+    if repo.git_operation_in_progress() {
+        stdout!("{} a git operation is in progress, skipped", timestamp());
+        return Ok( () );
+    }
+    drop(repo);
+
+    stdout!("{} staging the managed toolbox files", timestamp());
+    crate::stage::stage(vec!(), false, None, vec!(), Default::default())?;
+
+    let repo = Repository::open()?;
+
+    // no other git-toolbox operations while we are commiting
+    let _lock = repo.lock_operation("autostage")?;
+
+    let commit = repo.commit_managed_changes(|paths, stats| {
+        format!(
+            "Autostage {}\n\n{} entry files added, {} modified, {} deleted\n(committed by git toolbox autostage)\n",
+            paths.join(", "),
+            stats.added,
+            stats.changed,
+            stats.deleted
+        )
+    })?;
+
+    match commit {
+        Some(commit) => {
+            repo.record_audit_entry(
+                "autostage",
+                &commit.paths.join(","),
+                Some(commit.commit.clone()),
+                &format!("added={} modified={} deleted={}",
+                    commit.stats.added, commit.stats.changed, commit.stats.deleted
+                )
+            )?;
+
+            stdout!("{} {} Committed {} ({})",
+                timestamp(),
+                style("✓").green(),
+                commit.paths.join(", "),
+                style(&commit.commit[.. 8]).yellow()
+            );
+        },
+        None => {
+            stdout!("{} nothing to commit", timestamp());
+        }
+    }
+
+    Ok( () )
+}
+
+
+// Start the command as a background process
+fn start_in_background(repo: &Repository, interval: &str) -> Result<()> {
+    use std::process::{Command, Stdio};
+    use std::fs::OpenOptions;
+
+    let log_path = repo.data_dir()?.join(AUTOSTAGE_LOG_FILE);
+    let log = OpenOptions::new().create(true).append(true).open(&log_path).map_err(|err| {
+        error::FileWriteError {
+            path : log_path.clone(),
+            msg  : err.to_string()
+        }
+    })?;
+
+    let start_error = |err: std::io::Error| error::AutostageStartFailed { msg : err.to_string() };
+
+    let mut command = Command::new(std::env::current_exe().map_err(start_error)?);
+    command
+        .args(["autostage", "--interval", interval])
+        .current_dir(repo.workdir()?)
+        .stdin(Stdio::null())
+        .stdout(log.try_clone().map_err(start_error)?)
+        .stderr(log);
+
+    // the process should outlive the terminal it was started from
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        // DETACHED_PROCESS
+        command.creation_flags(0x00000008);
+    }
+
+    let child = command.spawn().map_err(start_error)?;
+
+    stdout!("{} Started git toolbox autostage in the background (process {}, every {})",
+        style("✓").green(),
+        child.id(),
+        style(interval).bold()
+    );
+    stdout!("The output is written to {}",
+        crate::util::get_relative_path(&log_path).display()
+    );
+
+    Ok( () )
+}
+
+// The current local time for the output
+fn timestamp() -> String {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+
+    style(format_time(seconds, local_time_offset())).dim().to_string()
+}
@@ -0,0 +1,130 @@
+//
+// src/blame.rs
+//
+// Implementation of git-toolbox blame
+//
+// Shows the commit and the author that last changed each line of a record
+// (identified by its ID). The lines of the clob holding the record are blamed
+// by git and the record lines are labeled with their field markers (the
+// continuation lines of a field with the marker of that field).
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::repository::{Repository, BlamedLine};
+use crate::toolbox::{Scanner, Token, clob_paths_for_id};
+use crate::config::DictionaryConfig;
+use crate::cli_app::style;
+use crate::util::{truncate_text, format_time};
+
+use crate::error;
+use anyhow::{Result, bail};
+
+// the number of characters of the commit id to show
+const COMMIT_ID_WIDTH : usize = 8;
+// the width of the author column
+const AUTHOR_WIDTH : usize = 20;
+
+
+pub fn blame(path: String, id: String) -> Result<()> {
+    // load the repository
+    let repo = Repository::open()?;
+
+    // locate the dictionary
+    let repo_path = repo.get_path_relative_to_repo(&path)?.to_string_lossy().into_owned();
+    let cfg = repo.config().dictionary_by_path(&repo_path)?;
+
+    // the clob holding the record (for any of the record types)
+    let clob_paths = clob_paths_for_id(cfg, &id).into_iter()
+        .filter(|_| cfg.unique_id)
+        .map(|clob_path| format!("{}.contents/{}", cfg.path, clob_path))
+        .collect::<Vec<_>>();
+    if clob_paths.is_empty() {
+        bail!(
+            error::InvalidRecordId {
+                id   : id.clone(),
+                path : cfg.path.clone()
+            }
+        )
+    }
+
+    let lines = repo.blame_lines(&clob_paths)?;
+    let record = record_lines(cfg, &lines, id.trim());
+    if record.is_empty() {
+        bail!(
+            error::UncommittedRecord {
+                id   : id.clone(),
+                path : cfg.path.clone()
+            }
+        )
+    }
+
+    stdout!("{} {}", style(&cfg.path).bold(), style(format!("record {}", id.trim())).cyan());
+    stdout!("");
+
+    let marker_width = record.iter().map(|(marker, _, _)| marker.chars().count()).max().unwrap_or(0);
+    for (marker, value, line) in record {
+        let (seconds, offset) = line.time;
+        let author = line.author.split(" <").next().unwrap_or(&line.author);
+
+        stdout!("  {} {} {:author_width$} {:marker_width$} {}",
+            style(&line.commit[.. COMMIT_ID_WIDTH.min(line.commit.len())]).yellow(),
+            format_time(seconds, offset),
+            style(truncate_text(author, AUTHOR_WIDTH)).italic(),
+            style(marker).bold(),
+            value,
+            author_width = AUTHOR_WIDTH,
+            marker_width = marker_width
+        );
+    }
+    stdout!("");
+
+    Ok( () )
+}
+
+
+// The lines of the record with the ID, each with the marker of its field and its value
+//
+// The blank lines after the record are not included
+fn record_lines<'a>(cfg: &DictionaryConfig, lines: &'a [BlamedLine], id: &str) -> Vec<(String, String, &'a BlamedLine)> {
+    let text = lines.iter().map(|line| format!("{}\n", line.text)).collect::<String>();
+    let id_tag = cfg.id_tag.as_deref().unwrap_or("");
+
+    let mut record : Vec<(String, String, &'a BlamedLine)> = vec!();
+    let mut found = false;
+    let mut marker = String::new();
+
+    for (line, token) in Scanner::for_dictionary(&text, cfg) {
+        match token {
+            Token::RecordBegin | Token::RecordEnd { .. } => {
+                if found {
+                    break
+                }
+                record.clear();
+            },
+            Token::Tagged { tag, text } | Token::NestedRecordTag { tag, text } => {
+                found |= tag == id_tag && text.trim() == id;
+                marker = tag.to_owned();
+
+                record.push((marker.clone(), text.trim().to_owned(), &lines[line.line]));
+            },
+            Token::Continuation { text } | Token::Untagged { text } => {
+                record.push((marker.clone(), text.trim().to_owned(), &lines[line.line]));
+            },
+            Token::Blank => {
+                record.push((String::new(), String::new(), &lines[line.line]));
+            }
+        }
+    }
+
+    if !found {
+        return vec!()
+    }
+
+    while record.last().map(|(_, _, line)| line.text.trim().is_empty()).unwrap_or(false) {
+        record.pop();
+    }
+
+    record
+}
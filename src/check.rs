@@ -0,0 +1,106 @@
+//
+// src/check.rs 
+//
+// Implementation of git-toolbox check
+//
+// Lists the issues in the managed toolbox files that are not acknowledged in the
+// issue baseline (for continuous integration), or acknowledges all current issues
+// with --update-baseline.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::repository::{Repository, ISSUE_BASELINE_FILE};
+use crate::toolbox::{Dictionary, ToolboxFileIssue, validator};
+use crate::config::DictionaryConfig;
+use crate::cli_app::style;
+
+use crate::error;
+use anyhow::{Result, bail};
+
+const MAX_TO_SHOW: usize = 8;
+
+
+pub fn check(paths: Vec<String>, verbose: bool, update_baseline: bool) -> Result<()> {
+    // load the repository
+    let repo = Repository::open()?;
+
+    // dictionary selection
+    let dictionaries : Vec<&DictionaryConfig> = if paths.is_empty() {
+        repo.config().dictionaries.iter().collect()
+    } else {
+        paths.iter().map(|path| {
+            // convert the path to one relative to the repo
+            let path = repo.get_path_relative_to_repo(path)?.to_string_lossy().into_owned();
+
+            repo.config().dictionary_by_path(path)
+        })
+        .collect::<Result<Vec<_>>>()?
+    };
+
+    let mut baseline = repo.issue_baseline()?;
+    let mut count = 0;
+
+    for cfg in dictionaries.iter() {
+        let dictionary = Dictionary::load(&repo, cfg, false)?;
+
+        let mut issues = dictionary.split().1;
+        issues.extend(validator::validate(&dictionary));
+        issues.sort_by_key(ToolboxFileIssue::line);
+
+        if update_baseline {
+            count += baseline.acknowledge(&dictionary, &issues);
+        } else {
+            let (issues, _) = baseline.partition(&dictionary, issues);
+
+            // obtain the printable relative path to the file
+            let display_name = crate::util::get_relative_path(
+                repo.workdir()?.to_owned().join(&cfg.path)
+            ).display().to_string();
+
+            display_issues(&display_name, &issues, verbose);
+            count += issues.len();
+        }
+    }
+
+    if update_baseline {
+        repo.write_issue_baseline(&baseline)?;
+
+        stdout!("✅ Acknowledged {} issues in {} managed toolbox dictionaries.", count, dictionaries.len());
+        stdout!("   (commit {} to share them with the project)", style(ISSUE_BASELINE_FILE).bold());
+
+        return Ok( () );
+    }
+
+    if count != 0 {
+        stdout!("");
+        bail!(
+            error::UnacknowledgedIssues {
+                count
+            }
+        );
+    }
+
+    stdout!("✅ No new issues in the managed toolbox dictionaries.");
+
+    Ok( () )
+}
+
+// Print the issues of a managed file
+fn display_issues(display_name: &str, issues: &[ToolboxFileIssue], verbose: bool) {
+    if issues.is_empty() { return }
+
+    stdout!("\n  Issues in {}:\n", style(display_name).italic());
+    let to_show = if verbose { issues.len() } else { MAX_TO_SHOW };
+    for issue in issues.iter().take(to_show) {
+        stdout!("        {}", issue);
+    }
+    if to_show < issues.len() {
+        stdout!("        ...");
+        stdout!("        ({} other issues, use \"{}\" to see all)", 
+            issues.len() - to_show,
+            style("git toolbox check -v").bold()
+        );
+    }
+}
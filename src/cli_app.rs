@@ -13,20 +13,67 @@ use clap::App;
 fn clap_app_spec<'a, 'b>() -> App<'a, 'b> {
     clap_app!( ("git-toolbox") =>
         (author: "Taras Zakharko <taras.zakharko@uzh.ch>")
+        (version: env!("CARGO_PKG_VERSION"))
         (about: "Git support for Linguist's Toolbox")
         (@arg verbose: -v "Verbose output")
+        (@arg ascii: --ascii +global "Use only ASCII characters for symbols and progress indicators")
+        (@arg profile: --profile +global "Report the time spent processing each dictionary")
+        (@arg wait: --wait +global "Wait for other git-toolbox operations to finish instead of failing")
         (@setting SubcommandRequired)
         (@subcommand gitfilter => 
             (@setting Hidden)
             (@group filter +required => 
                 (@arg clean: --clean <FILE> !required)
                 (@arg smudge: --smudge <FILE> !required)
+                (@arg process: --process)
+                (@arg textconv: --textconv <FILE> !required)
             )
         )
+        (@subcommand mergedriver => 
+            (@setting Hidden)
+            (@arg BASE: +required)
+            (@arg OURS: +required)
+            (@arg THEIRS: +required)
+            (@arg PATH: +required)
+        )
+        (@subcommand hook => 
+            (@setting Hidden)
+            (@arg NAME: +required possible_values(&crate::repository::GIT_HOOKS))
+            (@arg ARGS: ... !required)
+        )
         (@subcommand setup =>
             (about: "updates the repository configuration according to the configuration file")
             (@arg verbose: -v "Verbose output")
-            (@arg init: --init "Create a sample configuration")
+            (@arg init: --init conflicts_with[adopt uninstall] "Create a sample configuration")
+            (@arg adopt: --adopt +takes_value requires[FILES] conflicts_with[uninstall]
+                "adopt the records of a directory with one file per record into the managed file"
+            )
+            (@arg uninstall: --uninstall 
+                "stop managing the files and stage them as ordinary files (all managed files if none are given)"
+            )
+            (@arg ("remove-contents"): --("remove-contents") requires[uninstall]
+                "remove the managed folders of the uninstalled files"
+            )
+            (@arg FILES: ... !required 
+                "the managed file to adopt the records into or the managed files to uninstall"
+            )
+        )
+        (@subcommand adopt =>
+            (about: "takes over a toolbox dictionary that is tracked as an ordinary file")
+            (@arg PATH: +required "the dictionary to manage")
+            (@arg ("record-tag"): --("record-tag") +takes_value 
+                "the record marker (detected from the dictionary by default)"
+            )
+        )
+        (@subcommand mv =>
+            (about: "moves a managed toolbox file along with its managed folder")
+            (@arg OLD: +required "the managed file to move")
+            (@arg NEW: +required "the new path (or the directory to move the file into)")
+        )
+        (@subcommand rm =>
+            (about: "stops managing a toolbox file and tracks it as an ordinary file")
+            (@arg PATH: +required "the managed file")
+            (@arg delete: --delete "delete the file instead of tracking it as an ordinary file")
         )
         (@subcommand stage =>
             (about: "adds the changes in the managed toolbox files to the git staged area")
@@ -37,6 +84,24 @@ fn clap_app_spec<'a, 'b>() -> App<'a, 'b> {
             (@arg ("discard-external-changes"): --("discard-external-changes")
                 "overwrite external changes to the managed files if nessesary"
             )
+            (@arg sign: --sign 
+                "sign the staging manifest (can be enabled permanently with git config toolbox.signStaging)"
+            )
+            (@arg namespace: --namespace +takes_value 
+                "only stage the records in this ID namespace ('public' for the public IDs)"
+            )
+            (@arg record: --record +takes_value +multiple number_of_values(1) use_delimiter(true)
+                "only stage the records with these IDs (e.g. --record 12 --record AWA3)"
+            )
+            (@arg ("allow-mass-deletion"): --("allow-mass-deletion")
+                "discard the external changes even if this deletes many records (see max-deleted-records)"
+            )
+            (@arg strict: --strict
+                "do not stage if the dictionaries have issues with the severity error (see strict-staging)"
+            )
+            (@arg format: --format +takes_value possible_values(&crate::output::OUTPUT_FORMATS)
+                "the output format: human (default), json, porcelain or quiet"
+            )
         )
         (@subcommand reset =>
             (about: "discards the changes in the managed toolbox files (analogue to git reset)")
@@ -45,11 +110,253 @@ fn clap_app_spec<'a, 'b>() -> App<'a, 'b> {
             )
             (@arg verbose: -v "Verbose output")
             (@arg force: -f --force "Force reset")
+            (@arg ("repair-contents"): --("repair-contents") 
+                "also restore the managed folders if they do not match the git index"
+            )
+            (@arg to: --to +takes_value
+                "restore the files (and their managed folders) from this revision instead of the index"
+            )
+            (@arg ("allow-mass-deletion"): --("allow-mass-deletion")
+                "reset the files even if this deletes many records (see max-deleted-records)"
+            )
+            (@arg format: --format +takes_value possible_values(&crate::output::OUTPUT_FORMATS)
+                "the output format: human (default), json, porcelain or quiet"
+            )
+        )
+        (@subcommand checkout =>
+            (about: "restores the managed toolbox files from a revision without moving HEAD (analogue to git checkout <rev> -- <path>)")
+            (@arg REV: +required "the revision to restore the files from")
+            (@arg FILES: ... !required 
+                "the managed file to restore (if not provided, all files will be restored)"
+            )
+            (@arg force: -f --force "discard the unstaged changes to the files")
+            (@arg ("allow-mass-deletion"): --("allow-mass-deletion")
+                "restore the files even if this deletes many records (see max-deleted-records)"
+            )
+            (@arg format: --format +takes_value possible_values(&crate::output::OUTPUT_FORMATS)
+                "the output format: human (default), json, porcelain or quiet"
+            )
+        )
+        (@subcommand recover =>
+            (about: "recovers from an interrupted stage or reset")
+            (@arg resume: --resume conflicts_with[rollback discard] "roll back the operation and run it again")
+            (@arg rollback: --rollback conflicts_with[discard] "restore the files to the state before the operation")
+            (@arg discard: --discard "keep the files as they are")
+        )
+        (@subcommand unstage =>
+            (about: "removes the staged changes in the managed toolbox files from the git staged area")
+            (@arg FILES: ... !required 
+                "the managed file to unstage (if not provided, all files will be unstaged)"
+            )
+            (@arg verbose: -v "Verbose output")
+        )
+        (@subcommand reingest =>
+            (about: "merges the records fixed in the quarantine files back into the managed toolbox files")
+            (@arg FILES: ... !required 
+                "the managed file to reingest (if not provided, all files will be reingested)"
+            )
+            (@arg verbose: -v "Verbose output")
+        )
+        (@subcommand reconcile =>
+            (about: "merges the edits of a managed toolbox file with the upstream changes of its records")
+            (@arg FILE: +required "the managed file to reconcile")
+            (@arg upstream: --upstream +takes_value "the revision with the upstream changes (default: the upstream branch)")
+            (@arg base: --base +takes_value "the revision the edits are based on (default: the index)")
+        )
+        (@subcommand resolve =>
+            (about: "resolves the conflicting records of a managed toolbox file (or of an entry file) field by field")
+            (@arg FILE: +required "the managed file or the entry file with the conflicts")
+        )
+        (@subcommand conflicts =>
+            (about: "lists the conflicting records left by a merge and resolves them by ID")
+            (@arg FILES: ... !required 
+                "the managed file to check (if not provided, all files will be checked)"
+            )
+            (@arg ours: --ours +takes_value conflicts_with[theirs union] value_name("ID")
+                "resolve the conflicts of the record with our version"
+            )
+            (@arg theirs: --theirs +takes_value conflicts_with[union] value_name("ID")
+                "resolve the conflicts of the record with their version"
+            )
+            (@arg union: --union +takes_value value_name("ID")
+                "resolve the conflicts of the record by keeping both versions"
+            )
+        )
+        (@subcommand doctor =>
+            (about: "detects and repairs problems with the repository")
+            (@arg verbose: -v "Verbose output")
+            (@arg repair: --repair visible_alias("fix") "repair the detected problems (where possible)")
+        )
+        (@subcommand autostage =>
+            (about: "stages and commits the managed toolbox files at a regular interval")
+            (@arg interval: --interval +takes_value "the time between the commits, e.g. 30m, 2h or 1d (default: 30m)")
+            (@arg once: --once conflicts_with[detach] "stage and commit once and exit (e.g. for a scheduled job)")
+            (@arg detach: --detach "run in the background (the output is written to .git/toolbox/autostage.log)")
+        )
+        (@subcommand snapshot =>
+            (about: "manages plain text backups of the managed toolbox files")
+            (@setting SubcommandRequired)
+            (@subcommand push =>
+                (about: "records the reconstructed toolbox files on a snapshot branch and pushes it")
+                (@arg REMOTE: !required 
+                    "the remote to push to (defaults to git config toolbox.snapshotRemote)"
+                )
+                (@arg branch: --branch +takes_value 
+                    "the snapshot branch (defaults to git config toolbox.snapshotBranch or toolbox-snapshots)"
+                )
+                (@arg rev: --rev +takes_value "the revision to take the snapshot of (defaults to HEAD)")
+            )
+        )
+        (@subcommand pick =>
+            (about: "copies a record from another revision into the working copy of a managed toolbox file")
+            (@arg REV: +required "the revision (e.g. a branch) to take the record from")
+            (@arg FILE: +required "the managed file")
+            (@arg record: --record +takes_value +required "the ID of the record")
+            (@arg force: -f --force "replace the record even if it was also changed locally")
+        )
+        (@subcommand archive =>
+            (about: "writes the reconstructed toolbox files to a zip or tar archive (e.g. for a data release)")
+            (@arg output: -o --output +takes_value +required 
+                "the archive file (the format is given by the extension: .zip, .tar, .tar.gz or .tgz)"
+            )
+            (@arg rev: --rev +takes_value "the revision to archive, e.g. a release tag (defaults to HEAD)")
+            (@arg contents: --contents "include the managed folders with the records as stored in git")
+        )
+        (@subcommand stash =>
+            (about: "parks the unstaged changes to the managed toolbox files")
+            (@setting SubcommandRequired)
+            (@subcommand push =>
+                (about: "stashes the unstaged changes and restores the files from the index")
+                (@arg FILES: ... !required 
+                    "the managed files to stash (if not provided, all files with changes will be stashed)"
+                )
+                (@arg message: -m --message +takes_value "describes the stashed changes")
+            )
+            (@subcommand pop =>
+                (about: "merges the latest stashed changes into the working copies and removes them from the stash")
+                (@arg force: -f --force "merge the stashed changes with the unstaged changes to the stashed files")
+            )
+            (@subcommand drop =>
+                (about: "removes the latest stashed changes from the stash")
+            )
+            (@subcommand list =>
+                (about: "lists the stashed changes")
+            )
         )
         (@subcommand status =>
             (about: "prints the information about the status of the managed toolbox files")
+            (@arg FILES: ... !required 
+                "the managed file to check (if not provided, all files will be checked)"
+            )
             (@arg verbose: -v "Verbose output")   
+            (@arg namespace: --namespace +takes_value 
+                "only show the records in this ID namespace ('public' for the public IDs)"
+            )
+            (@arg ("all-issues"): --("all-issues") 
+                "also list the issues that were already present in the last commit"
+            )
+            (@arg ("all-branches"): --("all-branches") 
+                "also check for record IDs that were independently added on the remote-tracking branches"
+            )
+            (@arg porcelain: --porcelain "print the status in a stable, machine-readable format")
+            (@arg format: --format conflicts_with[porcelain] +takes_value possible_values(&crate::output::OUTPUT_FORMATS)
+                "the output format: human (default), json, porcelain or quiet"
+            )
+            (@arg repo: --repo +takes_value +multiple number_of_values(1) 
+                "show the status of this repository (can be repeated to check several repositories)"
+            )
         )        
+        (@subcommand check =>
+            (about: "lists the issues in the managed toolbox files that were not acknowledged")
+            (@arg FILES: ... !required 
+                "the managed file to check (if not provided, all files will be checked)"
+            )
+            (@arg verbose: -v "Verbose output")
+            (@arg ("update-baseline"): --("update-baseline") 
+                "acknowledge all current issues (in .toolbox-ignore)"
+            )
+        )
+        (@subcommand ids =>
+            (about: "summarizes the record IDs used in the managed toolbox files")
+            (@arg FILES: ... !required 
+                "the managed file to summarize (if not provided, all files will be summarized)"
+            )
+            (@arg report: --report "list the used ID ranges, the gaps and the invalid IDs")
+        )
+        (@subcommand diff =>
+            (about: "describes the changes to the records of the managed toolbox files")
+            (@arg FILES: ... !required 
+                "the managed file to describe (if not provided, all files will be described)"
+            )
+            (@arg cached: --cached "describe the staged changes (relative to HEAD)")
+            (@arg ("side-by-side"): --("side-by-side") "show the old and new field values in two columns")
+            (@arg namespace: --namespace +takes_value 
+                "only describe the records in this ID namespace ('public' for the public IDs)"
+            )
+            (@arg format: --format +takes_value possible_values(&crate::output::OUTPUT_FORMATS)
+                "the output format: human (default), json, porcelain or quiet"
+            )
+        )
+        (@subcommand log =>
+            (about: "lists the commits that changed a record of a managed toolbox file")
+            (@arg FILE: +required "the managed file containing the record")
+            (@arg record: --record +takes_value +required "the ID of the record")
+        )
+        (@subcommand blame =>
+            (about: "shows the commit and author that last changed each field of a record")
+            (@arg FILE: +required "the managed file containing the record")
+            (@arg record: --record +takes_value +required "the ID of the record")
+        )
+        (@subcommand verify =>
+            (about: "checks that the records survive splitting and were only changed by git toolbox stage")
+            (@arg FILES: ... !required 
+                "the managed file to verify (if not provided, all files will be verified)"
+            )
+            (@arg deep: --deep "also check the record changes in all commits")
+            (@arg ("cross-platform"): --("cross-platform") "also check that the managed folders are the same on every platform")
+            (@arg format: --format +takes_value possible_values(&crate::output::OUTPUT_FORMATS)
+                "the output format: human (default), json, porcelain or quiet"
+            )
+            (@arg repo: --repo +takes_value +multiple number_of_values(1) 
+                "verify this repository (can be repeated to verify several repositories)"
+            )
+        )
+        (@subcommand query =>
+            (about: "lists the records of the managed toolbox files that match a query")
+            (@arg QUERY: +required "the query (e.g. '\\ps = \"n\" and missing(\\ge)')")
+            (@arg FILES: ... !required 
+                "the managed file to search (if not provided, all files will be searched)"
+            )
+            (@arg full: --full "print the full text of the matching records")
+            (@arg count: --count "only print the number of matching records")
+        )
+        (@subcommand export =>
+            (about: "exports the records of a managed toolbox file as a table (one row per record)")
+            (@arg FILE: +required "the managed file to export")
+            (@arg format: --format +takes_value possible_values(&crate::export::EXPORT_FORMATS)
+                "the output format: csv (default), tsv, json or ndjson (one JSON object per line)"
+            )
+            (@arg fields: --fields +takes_value use_delimiter(true)
+                "the markers of the exported fields (e.g. lx,ps,ge), all fields are exported by default"
+            )
+            (@arg rev: --rev +takes_value "export the records stored in this revision instead of the working copy")
+        )
+        (@subcommand import =>
+            (about: "merges the records of a CSV, TSV or LIFT file into a managed toolbox file")
+            (@arg INPUT: +required "the file with the records to import")
+            (@arg FILE: +required "the managed file to import the records into")
+            (@arg format: --format +takes_value possible_values(&crate::import::IMPORT_FORMATS)
+                "the format of the imported file: csv, tsv or lift (guessed from the file extension by default)"
+            )
+            (@arg ("dry-run"): -n --("dry-run") "only show the changes, do not write the file")
+        )
+        (@subcommand open =>
+            (about: "opens a record of the managed toolbox files in an editor")
+            (@arg NAME: +required "the ID or label of the record")
+            (@arg FILES: ... !required 
+                "the managed file to search (if not provided, all files will be searched)"
+            )
+        )
         (@subcommand show =>
             (about: "Prints the reconstituted contents of a managed toolbox file")
             (@arg PATHSPEC: +required 
@@ -59,8 +366,52 @@ fn clap_app_spec<'a, 'b>() -> App<'a, 'b> {
             (@arg bare: -n --bare
                 "the path is a contents directory path, not a managed file path"
             )   
+            (@arg ("merge-base"): --("merge-base") +takes_value number_of_values(2)
+                value_names(&["BRANCH_A", "BRANCH_B"])
+                "fetch the contents at the merge base of two branches"
+            )
+            (@arg record: --record +takes_value conflicts_with[bare label]
+                "only print the record with this ID"
+            )
+            (@arg label: --label +takes_value conflicts_with[bare]
+                "only print the records with this label (in dictionaries without unique IDs)"
+            )
         )
     )
+    // clap_app! does not support hyphenated subcommand names
+    .subcommand(clap_app!( ("new-record") =>
+        (about: "appends a new record (following the dictionary template) to a managed toolbox file")
+        (@arg FILE: +required "the managed file to add the record to")
+        (@arg label: --label +takes_value "the record label")
+        (@arg id: --id +takes_value "the record ID (for dictionaries with unique IDs)")
+    ))
+    .subcommand(clap_app!( ("new-id") =>
+        (about: "prints the next free record IDs of a managed toolbox file")
+        (@arg FILE: +required "the managed file to allocate the IDs in")
+        (@arg count: --count +takes_value "the number of IDs to allocate (1 by default)")
+        (@arg namespace: --namespace +takes_value
+            "the ID namespace ('public' for the public IDs, by default the namespace of the configured user)"
+        )
+    ))
+    .subcommand(clap_app!( ("edit-field") =>
+        (about: "replaces the values of a field in the records of a managed toolbox file")
+        (@arg FILE: +required "the managed file to edit")
+        (@arg tag: --tag +takes_value +required "the marker of the field to edit (e.g. '\\ps')")
+        (@arg replace: --replace +takes_value +required number_of_values(2) value_names(&["OLD", "NEW"])
+            "the value to replace and its replacement"
+        )
+        (@arg regex: --regex "replace all matches of OLD as a regular expression (NEW can refer to the groups as $1, $2 ...)")
+        (@arg ("record-filter"): --("record-filter") +takes_value 
+            "only edit the records matching this query (see git toolbox query)"
+        )
+        (@arg ("dry-run"): --("dry-run") "only preview the changes")
+    ))
+    .subcommand(clap_app!( ("rename-marker") =>
+        (about: "renames a field marker in a managed toolbox file and its configuration")
+        (@arg OLD: +required "the marker to rename (e.g. '\\gloss')")
+        (@arg NEW: +required "the new marker (e.g. '\\ge')")
+        (@arg FILE: +required "the managed file")
+    ))
 }
 
 
@@ -69,25 +420,228 @@ fn clap_app_spec<'a, 'b>() -> App<'a, 'b> {
 pub enum Command {
     /// git-toolbox setup
     Setup {
-        init: bool
+        init: bool,
+        // the directory and the managed file to adopt its records into
+        adopt: Option<(String, String)>,
+        // the managed files to uninstall (all managed files if empty)
+        uninstall: Option<Vec<String>>,
+        remove_contents: bool
+    },
+    /// git-toolbox adopt
+    Adopt {
+        path: String,
+        record_tag: Option<String>
+    },
+    /// git-toolbox mv
+    Mv {
+        old: String,
+        new: String
+    },
+    /// git-toolbox rm
+    Rm {
+        path: String,
+        delete: bool
+    },
+    /// git-toolbox recover
+    Recover {
+        recovery: Option<crate::recover::Recovery>
     },
     /// git-toolbox status
     Status {
         files: Vec<String>,
-        verbose: bool
+        verbose: bool,
+        namespace: Option<String>,
+        all_issues: bool,
+        all_branches: bool,
+        repos: Vec<String>
     },
     /// git-toolbox stage
     Stage {
         files: Vec<String>,
         verbose: bool,
-        discard_workdir_changes: bool
+        discard_workdir_changes: bool,
+        sign: bool,
+        namespace: Option<String>,
+        records: Vec<String>,
+        allow_mass_deletion: bool,
+        strict: bool
     },
     /// git-toolbox reset
     Reset {
         files: Vec<String>,
         verbose: bool,
+        force: bool,
+        repair_contents: bool,
+        to: Option<String>,
+        allow_mass_deletion: bool
+    },
+    /// git-toolbox checkout
+    Checkout {
+        rev: String,
+        files: Vec<String>,
+        force: bool,
+        allow_mass_deletion: bool
+    },
+    /// git-toolbox unstage
+    Unstage {
+        files: Vec<String>,
+        verbose: bool
+    },
+    /// git-toolbox reingest
+    Reingest {
+        files: Vec<String>,
+        verbose: bool
+    },
+    /// git-toolbox reconcile
+    Reconcile {
+        file: String,
+        upstream: String,
+        base: Option<String>
+    },
+    /// git-toolbox resolve
+    Resolve {
+        file: String
+    },
+    /// git-toolbox conflicts
+    Conflicts {
+        files: Vec<String>,
+        // the record (ID or entry file) to resolve with our version, their version or both
+        ours: Option<String>,
+        theirs: Option<String>,
+        union: Option<String>
+    },
+    /// git-toolbox new-record
+    NewRecord {
+        file: String,
+        label: Option<String>,
+        id: Option<String>
+    },
+    /// git-toolbox new-id
+    NewId {
+        file: String,
+        count: usize,
+        namespace: Option<String>
+    },
+    /// git-toolbox edit-field
+    EditField {
+        file: String,
+        tag: String,
+        old: String,
+        new: String,
+        regex: bool,
+        filter: Option<String>,
+        dry_run: bool
+    },
+    /// git-toolbox rename-marker
+    RenameMarker {
+        old: String,
+        new: String,
+        file: String
+    },
+    /// git-toolbox doctor
+    Doctor {
+        repair: bool,
+        verbose: bool
+    },
+    /// git-toolbox autostage
+    Autostage {
+        interval: String,
+        once: bool,
+        detach: bool
+    },
+    /// git-toolbox snapshot push
+    SnapshotPush {
+        remote: Option<String>,
+        branch: Option<String>,
+        rev: Option<String>
+    },
+    /// git-toolbox pick
+    Pick {
+        rev: String,
+        file: String,
+        record: String,
         force: bool
     },
+    /// git-toolbox archive
+    Archive {
+        output: String,
+        rev: Option<String>,
+        contents: bool
+    },
+    /// git-toolbox stash push
+    StashPush {
+        files: Vec<String>,
+        message: Option<String>
+    },
+    /// git-toolbox stash pop
+    StashPop {
+        force: bool
+    },
+    /// git-toolbox stash drop
+    StashDrop,
+    /// git-toolbox stash list
+    StashList,
+    /// git-toolbox check
+    Check {
+        files: Vec<String>,
+        verbose: bool,
+        update_baseline: bool
+    },
+    /// git-toolbox ids
+    Ids {
+        files: Vec<String>,
+        report: bool
+    },
+    /// git-toolbox diff
+    Diff {
+        files: Vec<String>,
+        cached: bool,
+        namespace: Option<String>,
+        side_by_side: bool
+    },
+    /// git-toolbox log
+    Log {
+        file: String,
+        record: String
+    },
+    /// git-toolbox blame
+    Blame {
+        file: String,
+        record: String
+    },
+    /// git-toolbox verify
+    Verify {
+        files: Vec<String>,
+        deep: bool,
+        cross_platform: bool,
+        repos: Vec<String>
+    },
+    /// git-toolbox query
+    Query {
+        query: String,
+        files: Vec<String>,
+        full: bool,
+        count: bool
+    },
+    /// git-toolbox export
+    Export {
+        file: String,
+        format: String,
+        fields: Vec<String>,
+        rev: Option<String>
+    },
+    /// git-toolbox import
+    Import {
+        input: String,
+        file: String,
+        format: Option<String>,
+        dry_run: bool
+    },
+    /// git-toolbox open
+    Open {
+        name: String,
+        files: Vec<String>
+    },
     /// git-toolbox gitfilter --clean
     FilterClean {
         path  : String  
@@ -96,10 +650,31 @@ pub enum Command {
     FilterSmudge {
         path  : String  
     },
+    /// git-toolbox gitfilter --process
+    FilterProcess,
+    /// git-toolbox gitfilter --textconv
+    FilterTextconv {
+        path  : String
+    },
+    /// git-toolbox hook
+    Hook {
+        name : String,
+        args : Vec<String>
+    },
+    /// git-toolbox mergedriver
+    MergeDriver {
+        base   : String,
+        ours   : String,
+        theirs : String,
+        path   : String
+    },
     /// git-toolbox gitfilter show
     Reconstruct {
         pathspec : String, 
-        bare : bool
+        bare : bool,
+        merge_base : Option<(String, String)>,
+        record : Option<String>,
+        label : Option<String>
     },
 }
 
@@ -109,15 +684,32 @@ pub fn style<D: std::fmt::Display>(obj: D) -> console::StyledObject<D> {
 }
 
 
+/// Prepare the text for output
+///
+/// Strips the ANSI codes if colors are disabled and replaces the symbols
+/// with their ASCII equivalents in ASCII mode
+pub fn output_text(text: String) -> String {
+    let text = if console::colors_enabled() {
+        text
+    } else {
+        console::strip_ansi_codes(&text).into_owned()
+    };
+
+    if ascii_output() {
+        SYMBOLS.iter().fold(text, |text, (symbol, ascii)| text.replace(symbol, ascii))
+    } else {
+        text
+    }
+}
+
 macro_rules! stdout {
     ($fmt:expr) => {
         stdout!("{}", $fmt);
     };
     ($fmt:expr, $($arg:tt)*) => {{
-        if ::console::colors_enabled() {
-            println!($fmt, $($arg)*);
-        } else {
-            println!("{}", ::console::strip_ansi_codes(&format!($fmt, $($arg)*)));
+        // the text is only shown in the human output format
+        if $crate::output::human_output() {
+            println!("{}", $crate::cli_app::output_text(format!($fmt, $($arg)*)));
         }
     }}    
 }
@@ -127,14 +719,63 @@ macro_rules! stderr {
         stderr!("{}", $fmt);
     };
     ($fmt:expr, $($arg:tt)*) => {{
-        if ::console::colors_enabled() {
-            eprintln!($fmt, $($arg)*);
-        } else {
-            eprintln!("{}", ::console::strip_ansi_codes(&format!($fmt, $($arg)*)));
-        }
+        eprintln!("{}", $crate::cli_app::output_text(format!($fmt, $($arg)*)));
     }}    
 }
 
+
+// ASCII replacements for the symbols used in the output (the emoji variation
+// selector is removed together with the symbol)
+const SYMBOLS : [(&str, &str); 7] = [
+    ("✅", "[ok]"),
+    ("⚠\u{fe0f}", "[!]"),
+    ("⚠", "[!]"),
+    ("✓", "+"),
+    ("✗", "x"),
+    ("–", "-"),
+    ("—", "-"),
+];
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+lazy_static::lazy_static! {
+    static ref ASCII_OUTPUT : AtomicBool = AtomicBool::new(detect_ascii_output());
+}
+
+/// Whether the output is restricted to ASCII characters
+pub fn ascii_output() -> bool {
+    ASCII_OUTPUT.load(Ordering::Relaxed)
+}
+
+/// Force the ASCII output mode
+pub fn set_ascii_output(ascii: bool) {
+    ASCII_OUTPUT.store(ascii, Ordering::Relaxed)
+}
+
+// Detect whether the terminal can display the Unicode symbols
+//
+// The GIT_TOOLBOX_ASCII environment variable overrides the detection. Otherwise
+// the Windows console is assumed to be ASCII-only (unless running in Windows
+// Terminal) and on other systems the locale must use UTF-8
+fn detect_ascii_output() -> bool {
+    if let Ok(value) = std::env::var("GIT_TOOLBOX_ASCII") {
+        return !matches!(value.trim().to_lowercase().as_str(), "" | "0" | "false" | "no" | "off");
+    }
+
+    if cfg!(windows) {
+        return std::env::var_os("WT_SESSION").is_none();
+    }
+
+    // the first locale variable that is set determines the character encoding
+    let locale = ["LC_ALL", "LC_CTYPE", "LANG"].iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .unwrap_or_default()
+        .to_lowercase();
+
+    !(locale.contains("utf-8") || locale.contains("utf8"))
+}
+
 // 
 // ####                    ###  
 //  ##                      ##  
@@ -154,33 +795,321 @@ impl Command {
         let args = clap_app_spec().get_matches_safe()?;
 
         let verbose = args.is_present("verbose");
+        if args.is_present("ascii") {
+            set_ascii_output(true);
+        }
+        if args.is_present("profile") {
+            crate::profile::enable();
+        }
+        if args.is_present("wait") {
+            crate::repository::set_wait_for_lock(true);
+        }
+
+        // the output format of the commands that support it (the formats of export and
+        // import are the formats of the exported or imported data instead)
+        if let (name, Some(cmd)) = args.subcommand() {
+            if let Some(format) = cmd.value_of("format").filter(|_| name != "export" && name != "import") {
+                crate::output::set_output_format(format.parse().map_err(anyhow::Error::msg)?);
+            } else if cmd.is_present("porcelain") {
+                crate::output::set_output_format(crate::output::OutputFormat::Porcelain);
+            }
+        }
 
         let command = match args.subcommand() {
             ("setup", Some(cmd)) => {
+                let files = cmd.values_of_lossy("FILES").unwrap_or_default();
+
+                // the records are adopted into exactly one managed file
+                let adopt = match (cmd.value_of("adopt"), files.as_slice()) {
+                    (Some(dir), [file]) => Some((dir.to_owned(), file.to_owned())),
+                    (Some(_), _)        => return Err(anyhow::anyhow!("--adopt requires exactly one managed file")),
+                    (None, [])          => None,
+                    (None, _) if cmd.is_present("uninstall") => None,
+                    (None, _)           => {
+                        return Err(anyhow::anyhow!("the managed files can only be given with --adopt or --uninstall"))
+                    }
+                };
+
                 Command::Setup {
-                    init : cmd.is_present("init")
+                    init            : cmd.is_present("init"),
+                    adopt,
+                    uninstall       : Some(files).filter(|_| cmd.is_present("uninstall")),
+                    remove_contents : cmd.is_present("remove-contents")
+                }
+            },
+            ("adopt", Some(cmd)) => {
+                Command::Adopt {
+                    path       : cmd.value_of_lossy("PATH").expect("fatal: missing path").into(),
+                    record_tag : cmd.value_of("record-tag").map(|tag| tag.trim_start_matches('\\').to_owned())
+                }
+            },
+            ("mv", Some(cmd)) => {
+                Command::Mv {
+                    old : cmd.value_of_lossy("OLD").expect("fatal: missing path").into(),
+                    new : cmd.value_of_lossy("NEW").expect("fatal: missing path").into()
+                }
+            },
+            ("rm", Some(cmd)) => {
+                Command::Rm {
+                    path   : cmd.value_of_lossy("PATH").expect("fatal: missing path").into(),
+                    delete : cmd.is_present("delete")
+                }
+            },
+            ("recover", Some(cmd)) => {
+                use crate::recover::Recovery;
+
+                Command::Recover {
+                    recovery : match (cmd.is_present("resume"), cmd.is_present("rollback"), cmd.is_present("discard")) {
+                        (true, _, _) => Some(Recovery::Resume),
+                        (_, true, _) => Some(Recovery::Rollback),
+                        (_, _, true) => Some(Recovery::Discard),
+                        _            => None
+                    }
+                }
+            },
+            ("check", Some(cmd)) => {
+                Command::Check {
+                    files   : cmd.values_of_lossy("FILES").unwrap_or_default(),
+                    verbose : cmd.is_present("verbose") || verbose,
+                    update_baseline : cmd.is_present("update-baseline")
+                }
+            },
+            ("ids", Some(cmd)) => {
+                Command::Ids {
+                    files  : cmd.values_of_lossy("FILES").unwrap_or_default(),
+                    report : cmd.is_present("report")
+                }
+            },
+            ("log", Some(cmd)) => {
+                Command::Log {
+                    file   : cmd.value_of_lossy("FILE").expect("fatal: missing file").into(),
+                    record : cmd.value_of_lossy("record").expect("fatal: missing record").into()
+                }
+            },
+            ("blame", Some(cmd)) => {
+                Command::Blame {
+                    file   : cmd.value_of_lossy("FILE").expect("fatal: missing file").into(),
+                    record : cmd.value_of_lossy("record").expect("fatal: missing record").into()
+                }
+            },
+            ("verify", Some(cmd)) => {
+                Command::Verify {
+                    files : cmd.values_of_lossy("FILES").unwrap_or_default(),
+                    deep  : cmd.is_present("deep"),
+                    cross_platform : cmd.is_present("cross-platform"),
+                    repos : cmd.values_of_lossy("repo").unwrap_or_default()
+                }
+            },
+            ("query", Some(cmd)) => {
+                Command::Query {
+                    query : cmd.value_of("QUERY").unwrap().to_owned(),
+                    files : cmd.values_of_lossy("FILES").unwrap_or_default(),
+                    full  : cmd.is_present("full"),
+                    count : cmd.is_present("count")
+                }
+            },
+            ("export", Some(cmd)) => {
+                Command::Export {
+                    file   : cmd.value_of_lossy("FILE").expect("fatal: missing file").into(),
+                    format : cmd.value_of("format").unwrap_or("csv").to_owned(),
+                    fields : cmd.values_of_lossy("fields").unwrap_or_default(),
+                    rev    : cmd.value_of_lossy("rev").map(|s| s.into())
+                }
+            },
+            ("import", Some(cmd)) => {
+                Command::Import {
+                    input   : cmd.value_of_lossy("INPUT").expect("fatal: missing input").into(),
+                    file    : cmd.value_of_lossy("FILE").expect("fatal: missing file").into(),
+                    format  : cmd.value_of("format").map(|s| s.to_owned()),
+                    dry_run : cmd.is_present("dry-run")
+                }
+            },
+            ("open", Some(cmd)) => {
+                Command::Open {
+                    name  : cmd.value_of("NAME").unwrap().to_owned(),
+                    files : cmd.values_of_lossy("FILES").unwrap_or_default()
+                }
+            },
+            ("diff", Some(cmd)) => {
+                Command::Diff {
+                    files     : cmd.values_of_lossy("FILES").unwrap_or_default(),
+                    cached    : cmd.is_present("cached"),
+                    namespace : cmd.value_of_lossy("namespace").map(|s| s.into()),
+                    side_by_side : cmd.is_present("side-by-side")
                 }
             },
             ("status", Some(cmd)) => {
                 Command::Status {
-                    files   : cmd.values_of_lossy("FILES").unwrap_or_default(),
-                    verbose : cmd.is_present("verbose") || verbose
+                    files      : cmd.values_of_lossy("FILES").unwrap_or_default(),
+                    verbose    : cmd.is_present("verbose") || verbose,
+                    namespace  : cmd.value_of_lossy("namespace").map(|s| s.into()),
+                    all_issues : cmd.is_present("all-issues"),
+                    all_branches : cmd.is_present("all-branches"),
+                    repos      : cmd.values_of_lossy("repo").unwrap_or_default()
                 }
             },
             ("stage", Some(cmd)) => {
                 Command::Stage {
                     files   : cmd.values_of_lossy("FILES").unwrap_or_default(),
                     verbose : cmd.is_present("verbose") || verbose,
-                    discard_workdir_changes : cmd.is_present("discard-external-changes")
+                    discard_workdir_changes : cmd.is_present("discard-external-changes"),
+                    sign    : cmd.is_present("sign"),
+                    namespace : cmd.value_of_lossy("namespace").map(|s| s.into()),
+                    records : cmd.values_of_lossy("record").unwrap_or_default(),
+                    allow_mass_deletion : cmd.is_present("allow-mass-deletion"),
+                    strict  : cmd.is_present("strict")
                 }
             },            
             ("reset", Some(cmd)) => {
                 Command::Reset {
                     files   : cmd.values_of_lossy("FILES").unwrap_or_default(),
                     verbose : cmd.is_present("verbose") || verbose,
-                    force   : cmd.is_present("force")
+                    force   : cmd.is_present("force"),
+                    repair_contents : cmd.is_present("repair-contents"),
+                    to      : cmd.value_of_lossy("to").map(|s| s.into()),
+                    allow_mass_deletion : cmd.is_present("allow-mass-deletion")
                 }
             },                        
+            ("checkout", Some(cmd)) => {
+                Command::Checkout {
+                    rev     : cmd.value_of_lossy("REV").expect("missing REV").into(),
+                    files   : cmd.values_of_lossy("FILES").unwrap_or_default(),
+                    force   : cmd.is_present("force"),
+                    allow_mass_deletion : cmd.is_present("allow-mass-deletion")
+                }
+            },
+            ("unstage", Some(cmd)) => {
+                Command::Unstage {
+                    files   : cmd.values_of_lossy("FILES").unwrap_or_default(),
+                    verbose : cmd.is_present("verbose") || verbose
+                }
+            },
+            ("reingest", Some(cmd)) => {
+                Command::Reingest {
+                    files   : cmd.values_of_lossy("FILES").unwrap_or_default(),
+                    verbose : cmd.is_present("verbose") || verbose
+                }
+            },
+            ("reconcile", Some(cmd)) => {
+                Command::Reconcile {
+                    file     : cmd.value_of_lossy("FILE").expect("fatal: missing file").into(),
+                    upstream : cmd.value_of("upstream").unwrap_or("@{upstream}").to_owned(),
+                    base     : cmd.value_of("base").map(str::to_owned)
+                }
+            },
+            ("resolve", Some(cmd)) => {
+                Command::Resolve {
+                    file : cmd.value_of_lossy("FILE").expect("fatal: missing file").into()
+                }
+            },
+            ("conflicts", Some(cmd)) => {
+                Command::Conflicts {
+                    files  : cmd.values_of_lossy("FILES").unwrap_or_default(),
+                    ours   : cmd.value_of_lossy("ours").map(|s| s.into()),
+                    theirs : cmd.value_of_lossy("theirs").map(|s| s.into()),
+                    union  : cmd.value_of_lossy("union").map(|s| s.into())
+                }
+            },
+            ("new-record", Some(cmd)) => {
+                Command::NewRecord {
+                    file  : cmd.value_of_lossy("FILE").expect("fatal: missing file").into(),
+                    label : cmd.value_of_lossy("label").map(|s| s.into()),
+                    id    : cmd.value_of_lossy("id").map(|s| s.into())
+                }
+            },
+            ("new-id", Some(cmd)) => {
+                let count = cmd.value_of("count").unwrap_or("1");
+
+                Command::NewId {
+                    file      : cmd.value_of_lossy("FILE").expect("fatal: missing file").into(),
+                    count     : count.parse().map_err(|_| anyhow::anyhow!("invalid number of IDs '{}'", count))?,
+                    namespace : cmd.value_of_lossy("namespace").map(|s| s.into())
+                }
+            },
+            ("edit-field", Some(cmd)) => {
+                let replace = cmd.values_of_lossy("replace").expect("fatal: missing replacement");
+
+                Command::EditField {
+                    file    : cmd.value_of_lossy("FILE").expect("fatal: missing file").into(),
+                    tag     : cmd.value_of_lossy("tag").expect("fatal: missing tag").into(),
+                    old     : replace[0].clone(),
+                    new     : replace[1].clone(),
+                    regex   : cmd.is_present("regex"),
+                    filter  : cmd.value_of_lossy("record-filter").map(|s| s.into()),
+                    dry_run : cmd.is_present("dry-run")
+                }
+            },
+            ("rename-marker", Some(cmd)) => {
+                Command::RenameMarker {
+                    old  : cmd.value_of_lossy("OLD").expect("fatal: missing marker").into(),
+                    new  : cmd.value_of_lossy("NEW").expect("fatal: missing marker").into(),
+                    file : cmd.value_of_lossy("FILE").expect("fatal: missing file").into()
+                }
+            },
+            ("doctor", Some(cmd)) => {
+                Command::Doctor {
+                    repair  : cmd.is_present("repair"),
+                    verbose : cmd.is_present("verbose") || verbose
+                }
+            },
+            ("autostage", Some(cmd)) => {
+                Command::Autostage {
+                    interval : cmd.value_of("interval").unwrap_or("30m").to_owned(),
+                    once     : cmd.is_present("once"),
+                    detach   : cmd.is_present("detach")
+                }
+            },
+            ("snapshot", Some(cmd)) => {
+                let cmd = cmd.subcommand_matches("push").expect("unknown snapshot command");
+
+                Command::SnapshotPush {
+                    remote : cmd.value_of_lossy("REMOTE").map(|s| s.into()),
+                    branch : cmd.value_of_lossy("branch").map(|s| s.into()),
+                    rev    : cmd.value_of_lossy("rev").map(|s| s.into())
+                }
+            },
+            ("pick", Some(cmd)) => {
+                Command::Pick {
+                    rev    : cmd.value_of_lossy("REV").expect("missing REV").into(),
+                    file   : cmd.value_of_lossy("FILE").expect("missing FILE").into(),
+                    record : cmd.value_of_lossy("record").expect("missing record").into(),
+                    force  : cmd.is_present("force")
+                }
+            },
+            ("archive", Some(cmd)) => {
+                Command::Archive {
+                    output   : cmd.value_of_lossy("output").expect("missing output").into(),
+                    rev      : cmd.value_of_lossy("rev").map(|s| s.into()),
+                    contents : cmd.is_present("contents")
+                }
+            },
+            ("stash", Some(cmd)) => {
+                match cmd.subcommand() {
+                    ("push", Some(cmd)) => Command::StashPush {
+                        files   : cmd.values_of_lossy("FILES").unwrap_or_default(),
+                        message : cmd.value_of_lossy("message").map(|s| s.into())
+                    },
+                    ("pop", Some(cmd))  => Command::StashPop {
+                        force : cmd.is_present("force")
+                    },
+                    ("drop", Some(_))   => Command::StashDrop,
+                    _                   => Command::StashList
+                }
+            },
+            ("hook", Some(cmd)) => {
+                Command::Hook {
+                    name : cmd.value_of_lossy("NAME").expect("missing NAME").into(),
+                    args : cmd.values_of_lossy("ARGS").unwrap_or_default()
+                }
+            },
+            ("mergedriver", Some(cmd)) => {
+                Command::MergeDriver {
+                    base   : cmd.value_of_lossy("BASE").expect("missing BASE").into(),
+                    ours   : cmd.value_of_lossy("OURS").expect("missing OURS").into(),
+                    theirs : cmd.value_of_lossy("THEIRS").expect("missing THEIRS").into(),
+                    path   : cmd.value_of_lossy("PATH").expect("missing PATH").into()
+                }
+            },
             ("gitfilter", Some(cmd)) if cmd.is_present("clean") && !cmd.is_present("smudge") => {
                 Command::FilterClean {
                     path: cmd.value_of_lossy("clean").expect("missing PATH").into()
@@ -191,10 +1120,23 @@ impl Command {
                     path: cmd.value_of_lossy("smudge").expect("missing PATH").into()
                 }
             },
+            ("gitfilter", Some(cmd)) if cmd.is_present("process") => {
+                Command::FilterProcess
+            },
+            ("gitfilter", Some(cmd)) if cmd.is_present("textconv") => {
+                Command::FilterTextconv {
+                    path: cmd.value_of_lossy("textconv").expect("missing PATH").into()
+                }
+            },
             ("show", Some(cmd)) => {
                 Command::Reconstruct {
                     pathspec : cmd.value_of_lossy("PATHSPEC").expect("missing PATHSPEC").into(),
-                    bare     : cmd.is_present("bare")
+                    bare     : cmd.is_present("bare"),
+                    merge_base : cmd.values_of_lossy("merge-base").map(|revs| {
+                        (revs[0].clone(), revs[1].clone())
+                    }),
+                    record   : cmd.value_of_lossy("record").map(|s| s.into()),
+                    label    : cmd.value_of_lossy("label").map(|s| s.into())
                 }
             },            
             // otherwise
@@ -13,6 +13,9 @@ pub const CONFIG_FILE : &str = "git-toolbox.toml";
 
 
 use serde::Deserialize;
+use crate::sorting::SortPolicy;
+use crate::toolbox::typ::TypeFile;
+use crate::toolbox::{starts_record, Severity};
 
 #[derive(Deserialize, Debug, Clone, smart_default::SmartDefault)]
 #[serde(rename_all="lowercase")]
@@ -24,18 +27,32 @@ pub enum UserRole {
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct UserConfig {
+    // the git user.name or user.email
     pub name: String,
     #[serde(default)]
     pub role: UserRole,
+    // the ID namespace the user may stage records in
     pub namespace: Option<String>,
+    // the user may stage the public records as well (approved by a manager)
+    #[serde(default)]
+    pub public: bool
 }
 
 #[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all="kebab-case")]
 pub struct DictionaryConfig {
     pub name: String,
+    #[serde(default)]
+    pub kind: FileKind,
+    // the Toolbox database type declared by the header (e.g. `Wordlist`), any type if not set
+    #[serde(default)]
+    pub database_type : Option<String>,
     pub path: String,
-    #[serde(deserialize_with = "deserialize::read_marker")]
+    // derived from the type file if not set (a list if the file mixes several record types)
+    #[serde(default, rename = "record-tag", deserialize_with = "deserialize::read_record_tags")]
+    pub record_tags: Vec<String>,
+    // the first of the record tags (the records of the other tags are stored apart)
+    #[serde(skip)]
     pub record_tag: String,
     #[serde(default)]
     pub unique_id : bool,
@@ -47,8 +64,229 @@ pub struct DictionaryConfig {
     pub id_spec   : regex::Regex,
     #[serde(default)]
     pub lifecycle : bool,
-    #[serde(default, deserialize_with = "deserialize::read_marker_option")]
-    pub lifecycle_tag : Option<String>
+    #[serde(default)]
+    pub template  : Option<TemplateConfig>,
+    #[serde(default)]
+    pub path_encoding : PathEncoding,
+    // how the clobs are spread over the directories of the managed folder
+    #[serde(default)]
+    pub path_layout : PathLayout,
+    // the number of directory levels of the layout
+    #[serde(default = "deserialize::default_prefix_depth", deserialize_with = "deserialize::read_prefix_depth")]
+    pub prefix_depth : usize,
+    // the maximal number of clobs in a directory (setup raises the prefix depth to meet it)
+    #[serde(default)]
+    pub max_files_per_dir : Option<usize>,
+    // which labels that share a clob are reported
+    #[serde(default)]
+    pub label_collisions : LabelCollisions,
+    #[serde(default)]
+    pub encoding  : TextEncoding,
+    // the line endings of a reconstructed file
+    #[serde(default)]
+    pub line_endings : LineEndings,
+    // the number of blank lines between the records of a reconstructed file
+    #[serde(default = "deserialize::default_record_spacing")]
+    pub record_spacing : usize,
+    // the maximal size of a clob in bytes (larger clobs are stored in several parts)
+    #[serde(default)]
+    pub max_clob_size : Option<usize>,
+    // the percentage of the records that stage and reset may delete without confirmation
+    #[serde(default = "deserialize::default_max_deleted_records")]
+    pub max_deleted_records : f64,
+    // the tags that start a sub-record (e.g. MDF subentries), which is kept within its record
+    #[serde(default, deserialize_with = "deserialize::read_markers")]
+    pub subrecord_tags : Vec<String>,
+    // whether the untagged lines after a field continue its value (as wrapped by Toolbox)
+    #[serde(default)]
+    pub continuation_lines : bool,
+    // the fields that are ignored when comparing records (e.g. date stamps)
+    #[serde(default, deserialize_with = "deserialize::read_markers")]
+    pub ignore_fields : Vec<String>,
+    // whether the fields of the records are stored in a canonical order
+    #[serde(default)]
+    pub normalize_field_order : bool,
+    // the canonical order of the fields (the order of the type file if empty)
+    #[serde(default, deserialize_with = "deserialize::read_markers")]
+    pub field_order : Vec<String>,
+    // the aligned tiers of an interlinear text (the word tier followed by the morpheme tiers)
+    #[serde(default = "deserialize::default_tiers", deserialize_with = "deserialize::read_markers")]
+    pub tiers : Vec<String>,
+    // the order of the clobs (and of the records of the reconstructed file)
+    #[serde(default)]
+    pub sort : SortPolicy,
+    // the Toolbox type file that defines the markers (relative to the repository)
+    #[serde(default)]
+    pub typ : Option<String>,
+    // the loaded type file
+    #[serde(skip)]
+    pub typ_file : Option<TypeFile>,
+    // whether the file used CRLF line endings when it was last staged
+    #[serde(skip)]
+    pub staged_crlf : bool,
+    // whether the file started with a UTF-8 byte order mark when it was last staged
+    #[serde(skip)]
+    pub staged_bom : bool,
+    // the external validators that check the records of this dictionary
+    #[serde(skip)]
+    pub validators : Vec<ValidatorConfig>
+}
+
+/// The kind of a Toolbox file
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, smart_default::SmartDefault)]
+#[serde(rename_all="kebab-case")]
+pub enum FileKind {
+    /// A dictionary (records are entries)
+    #[default]
+    Dictionary,
+    /// An interlinear text (records are references with aligned tiers)
+    Text
+}
+
+/// The character encoding of a Toolbox file
+///
+/// The managed contents are always stored as UTF-8, the encoding only applies to 
+/// the Toolbox file in the working directory
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, smart_default::SmartDefault)]
+pub enum TextEncoding {
+    #[default]
+    #[serde(rename = "utf-8", alias = "utf8")]
+    Utf8,
+    #[serde(rename = "windows-1252", alias = "cp1252")]
+    Windows1252,
+    #[serde(rename = "iso-8859-1", alias = "latin1", alias = "latin-1")]
+    Latin1
+}
+
+/// How non-ASCII characters in record IDs and labels are mapped to clob paths
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, smart_default::SmartDefault)]
+#[serde(rename_all="kebab-case")]
+pub enum PathEncoding {
+    /// Replace the characters by their closest ASCII equivalents (e.g. `é` -> `e`)
+    #[default]
+    Transliterate,
+    /// Replace the characters by their percent-encoded UTF-8 bytes (e.g. `ŋ` -> `%C5%8B`)
+    Percent
+}
+
+/// How the clobs are spread over the directories of a managed folder
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, smart_default::SmartDefault)]
+#[serde(rename_all="kebab-case")]
+pub enum PathLayout {
+    /// Directories named by the first letters of the ID or label (e.g. `ka/pa/kapa.txt`)
+    #[default]
+    Alpha,
+    /// Directories named by the hash of the ID or label (e.g. `#3f/#a0/kapa.txt`)
+    Hash
+}
+
+/// The line endings of a reconstructed Toolbox file
+///
+/// The managed contents always use Unix line endings, the line endings only apply to 
+/// the Toolbox file in the working directory
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, smart_default::SmartDefault)]
+#[serde(rename_all="kebab-case")]
+pub enum LineEndings {
+    /// The line endings the file had when it was last staged
+    #[default]
+    Preserve,
+    /// Windows line endings (`\r\n`)
+    Crlf,
+    /// Unix line endings (`\n`)
+    Lf
+}
+
+/// Which different labels are reported when they map to the same clob path
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, smart_default::SmartDefault)]
+#[serde(rename_all="kebab-case")]
+pub enum LabelCollisions {
+    /// Never report the labels
+    Ignore,
+    /// Report the labels that differ in more than case (e.g. `kápa` and `kapa`)
+    #[default]
+    Accents,
+    /// Report all different labels (e.g. also `Kapa` and `kapa`)
+    All
+}
+
+impl LabelCollisions {
+    /// Check whether two (different) labels sharing a clob are reported 
+    pub fn reports(&self, a: &str, b: &str) -> bool {
+        match self {
+            LabelCollisions::Ignore  => false,
+            LabelCollisions::Accents => a.to_lowercase() != b.to_lowercase(),
+            LabelCollisions::All     => a != b
+        }
+    }
+}
+
+/// The fields of a dictionary record
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct TemplateConfig {
+    #[serde(rename = "field", default)]
+    pub fields : Vec<FieldConfig>
+}
+
+/// A field of a dictionary record 
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all="kebab-case")]
+pub struct FieldConfig {
+    #[serde(deserialize_with = "deserialize::read_marker")]
+    pub marker     : String,
+    // human-readable field name (e.g. "gloss")
+    pub name       : Option<String>,
+    #[serde(default)]
+    pub required   : bool,
+    #[serde(default)]
+    pub repeatable : bool,
+    #[serde(rename = "type", default)]
+    pub kind       : FieldType,
+    // the allowed values of a closed set field
+    #[serde(default)]
+    pub values     : Vec<String>
+}
+
+/// The type of a field value
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, smart_default::SmartDefault)]
+#[serde(rename_all="kebab-case")]
+pub enum FieldType {
+    #[default]
+    Text,
+    Date,
+    Number,
+    Set
+}
+
+/// The text that git sees instead of the managed file contents
+///
+/// Variables `{name}`, `{path}` and `{contact}` in the text are substituted
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct PlaceholderConfig {
+    pub text    : Option<String>,
+    pub contact : Option<String>
+}
+
+/// An external command that checks the records of the dictionaries 
+///
+/// The command is run by the shell in the working directory, gets the records as JSON lines 
+/// on the standard input and prints the issues as `<line>\t<message>` lines
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all="kebab-case")]
+pub struct ValidatorConfig {
+    pub name    : String,
+    pub command : String,
+    // the names of the dictionaries to check (all dictionaries if empty)
+    #[serde(default)]
+    pub dictionaries : Vec<String>,
+    #[serde(default = "default_validator_severity")]
+    pub severity : Severity,
+    // the working directory of the repository
+    #[serde(skip)]
+    pub workdir : std::path::PathBuf
+}
+
+fn default_validator_severity() -> Severity {
+    Severity::Warning
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -57,8 +295,39 @@ pub struct Config {
     pub users: Vec<UserConfig>,
     #[serde(rename = "dictionary", default)]
     pub dictionaries: Vec<DictionaryConfig>,
+    #[serde(rename = "validator", default)]
+    pub validators: Vec<ValidatorConfig>,
+    // the severities of the issue kinds (e.g. `ambiguous-id = "error"`)
+    #[serde(rename = "issue-severity", default)]
+    pub issue_severity: std::collections::HashMap<String, Severity>,
+    // staging is refused if the dictionaries have issues with the severity `error`
+    #[serde(rename = "strict-staging", default)]
+    pub strict_staging: bool,
+    #[serde(default)]
+    pub placeholder: PlaceholderConfig
 }
 
+/// The default placeholder text for managed files
+pub const DEFAULT_PLACEHOLDER_TEXT : &str = concat!(
+    "This file is managed by git-toolbox.\n",
+    "\n",
+    "If you see this text, your repository is either misconfigured or has encountered\n",
+    "an error during operation. Please run \"git toolbox reset\" and contact {contact}\n", 
+    "if your issue persists.\n"
+);
+
+/// The maximal number of directory levels of a clob path layout
+pub const MAX_PREFIX_DEPTH : usize = 8;
+
+/// The byte order mark some Toolbox setups put at the start of UTF-8 files
+pub const UTF8_BOM : char = '\u{FEFF}';
+
+/// The default support contact mentioned in the placeholder text
+pub const DEFAULT_PLACEHOLDER_CONTACT : &str = "IT support";
+
+/// The last line of a staged placeholder, which names the tree of the managed folder
+const PLACEHOLDER_CONTENTS_PREFIX : &str = "git-toolbox-contents: ";
+
 
 // 
 // ####                    ###  
@@ -72,7 +341,212 @@ pub struct Config {
 //                  ##
 //                 ####
 
+impl DictionaryConfig {
+    /// Check whether the path is a pattern matching several dictionaries (e.g. `texts/*.txt`)
+    pub fn is_path_pattern(&self) -> bool {
+        is_path_pattern(&self.path)
+    }
+
+    /// Check whether the text contains Toolbox dictionary content 
+    ///
+    /// This is used to tell the dictionary contents from the placeholder text
+    pub fn matches_content(&self, text: &str) -> bool {
+        text.trim_start_matches(UTF8_BOM).lines().any(|line| {
+            line.starts_with("\\_sh ") || starts_record(line, &self.record_tags)
+        })
+    }
+
+    /// The directory of the records with the record tag (relative to the contents root)
+    ///
+    /// The records of the first record tag are stored at the top of the managed folder, the
+    /// records of the other ones in a directory named by their tag (e.g. `+rec`)
+    pub fn record_type_dir(&self, tag: &str) -> Option<String> {
+        use crate::util::{encode_path_component, RECORD_TYPE_MARK};
+
+        if tag == self.record_tag || !self.record_tags.iter().any(|t| t == tag) { return None }
+
+        Some( format!("{}{}", RECORD_TYPE_MARK, encode_path_component(tag.trim_start_matches('\\'), self.path_encoding)) )
+    }
+
+    /// The canonical order of the fields (if the field order is normalized)
+    ///
+    /// The order is configured explicitly or taken from the type file
+    pub fn field_order(&self) -> Option<Vec<String>> {
+        if !self.normalize_field_order || self.kind == FileKind::Text { return None }
+
+        if !self.field_order.is_empty() {
+            return Some( self.field_order.clone() );
+        }
+
+        self.typ_file.as_ref().map(|typ| typ.markers.iter().map(|def| def.marker.clone()).collect())
+    }
+
+    /// The text of a clob without the ignored fields (e.g. the date stamps that Toolbox 
+    /// updates on every save)
+    ///
+    /// The lines that continue an ignored field are removed along with it
+    pub fn without_ignored_fields<'a>(&self, text: &'a str) -> std::borrow::Cow<'a, str> {
+        use std::borrow::Cow;
+
+        if self.ignore_fields.is_empty() { return Cow::Borrowed(text) }
+
+        let mut ignored = false;
+        let lines = text.split_inclusive('\n').filter(|line| {
+            if line.starts_with('\\') || line.trim().is_empty() {
+                ignored = self.ignore_fields.iter().any(|marker| {
+                    line.strip_prefix(marker.as_str())
+                        .map(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+                        .unwrap_or(false)
+                });
+            }
+
+            !ignored
+        });
+
+        Cow::Owned(lines.collect())
+    }
+
+    /// Replace the default dictionary header of a reconstructed file by the header of the database
+    /// type (or of the file kind)
+    ///
+    /// The managed folders created before the header was stored in them are reconstructed with 
+    /// the default dictionary header. The byte order mark the file was staged with (if any) is 
+    /// put back in front of the header
+    pub fn adapt_header(&self, data: Vec<u8>) -> Vec<u8> {
+        let mut data = self.adapt_header_line(data);
+
+        if self.staged_bom && self.encoding == TextEncoding::Utf8 {
+            data.splice(.. 0, UTF8_BOM.to_string().into_bytes());
+        }
+
+        data
+    }
+
+    fn adapt_header_line(&self, mut data: Vec<u8>) -> Vec<u8> {
+        use crate::repository::DICTIONARY_HEADER;
+        const TEXT_HEADER : &str = "\\_sh v3.0  621  Text";
+
+        let header = match (&self.database_type, self.kind) {
+            (Some(database_type), _) => format!("\\_sh v3.0  400  {}", database_type),
+            (None, FileKind::Text)   => TEXT_HEADER.to_owned(),
+            (None, _)                => return data
+        };
+
+        if !data.starts_with(DICTIONARY_HEADER) { return data }
+
+        let end = data.iter().position(|b| *b == b'\n').unwrap_or(data.len());
+        data.splice(.. end, header.into_bytes());
+
+        data
+    }
+
+    /// Adjust the number of blank lines between the records of a reconstructed file
+    ///
+    /// The records are stored without trailing blank lines and are separated by a single blank 
+    /// line when the file is reconstructed. Blank lines within the records are left as they are
+    pub fn space_records(&self, data: Vec<u8>) -> Vec<u8> {
+        if self.record_spacing == 1 { return data }
+
+        let text = match String::from_utf8(data) {
+            Ok( text ) => text,
+            Err( err ) => return err.into_bytes()
+        };
+
+        let mut lines : Vec<&str> = vec!();
+        // the blank lines after the last non-blank line
+        let mut blank : Vec<&str> = vec!();
+
+        for line in text.split_inclusive('\n') {
+            if line.trim().is_empty() {
+                blank.push(line);
+                continue;
+            }
+
+            let starts_record = starts_record(line.trim_end(), &self.record_tags);
+
+            if starts_record && !lines.is_empty() {
+                let separator = blank.first().copied().unwrap_or("\n");
+                blank = vec![separator; self.record_spacing];
+            }
+
+            lines.append(&mut blank);
+            lines.push(line);
+        }
+        lines.append(&mut blank);
+
+        lines.concat().into_bytes()
+    }
+}
+
+#[cfg(test)]
+impl DictionaryConfig {
+    /// The first dictionary of a configuration file (for the unit tests)
+    pub fn for_test(toml: &str) -> DictionaryConfig {
+        use std::convert::TryFrom;
+
+        Config::try_from(toml.as_bytes()).expect("invalid test configuration").dictionaries.remove(0)
+    }
+}
+
 impl Config {
+    /// Replace the dictionaries configured with a path pattern (e.g. `texts/*.txt`) by one
+    /// dictionary per matching path
+    ///
+    /// The candidates are paths relative to the repository (e.g. the files in the working 
+    /// directory and in the index). Paths that are configured explicitly and paths inside
+    /// the managed folders are never matched. The expanded dictionaries share the settings 
+    /// of the pattern and are ordered by their paths
+    pub fn expand_path_patterns(&mut self, candidates: &[String]) {
+        use std::collections::BTreeSet;
+
+        if !self.dictionaries.iter().any(DictionaryConfig::is_path_pattern) { return }
+
+        let explicit = self.dictionaries.iter()
+            .filter(|cfg| !cfg.is_path_pattern())
+            .map(|cfg| cfg.path.clone())
+            .collect::<BTreeSet<_>>();
+        let mut expanded = explicit.clone();
+
+        let dictionaries = std::mem::take(&mut self.dictionaries);
+        
+        for cfg in dictionaries {
+            if !cfg.is_path_pattern() {
+                self.dictionaries.push(cfg);
+                continue;
+            }
+
+            let regex = path_pattern_regex(&cfg.path);
+            let paths = candidates.iter()
+                .filter(|path| !path.contains(".contents/") && regex.is_match(path))
+                .collect::<BTreeSet<_>>();
+
+            for path in paths {
+                // a path matched by several patterns belongs to the first one
+                if !expanded.insert(path.clone()) { continue }
+
+                self.dictionaries.push(DictionaryConfig { path : path.clone(), ..cfg.clone() });
+            }
+        }
+    }
+
+    /// The directories (relative to the repository) that hold the paths matching the 
+    /// configured path patterns, with whether their subdirectories need to be searched
+    pub fn path_pattern_roots(&self) -> Vec<(String, bool)> {
+        self.dictionaries.iter().filter(|cfg| cfg.is_path_pattern()).map(|cfg| {
+            let components = cfg.path.split('/').collect::<Vec<_>>();
+            let literal = components.iter()
+                .take_while(|component| !is_path_pattern(component))
+                .copied()
+                .collect::<Vec<_>>();
+
+            // search the subdirectories if the pattern spans several directory levels
+            let recursive = components.len() - literal.len() > 1 || cfg.path.contains("**");
+
+            (literal.join("/"), recursive)
+        })
+        .collect()
+    }
+
     /// Locate the dictionary config by path
     ///
     /// Path is assumed to be relative to the repository
@@ -94,13 +568,384 @@ impl Config {
 
         Ok( matched_dictionary[0] )
     }
+
+    /// The placeholder text stored in git instead of the managed file contents
+    pub fn managed_file_text(&self, dictionary: &DictionaryConfig) -> String {
+        let template = self.placeholder.text.as_deref().unwrap_or(DEFAULT_PLACEHOLDER_TEXT);
+        let contact = self.placeholder.contact.as_deref().unwrap_or(DEFAULT_PLACEHOLDER_CONTACT);
+
+        template
+            .replace("{name}", &dictionary.name)
+            .replace("{path}", &dictionary.path)
+            .replace("{contact}", contact)
+    }
+
+    /// Whether a staged placeholder is the placeholder text of the managed file (with or 
+    /// without the tree of the managed folder, see `placeholder_with_contents()`)
+    pub fn is_managed_file_text(&self, dictionary: &DictionaryConfig, text: &str) -> bool {
+        let text = split_placeholder_contents(text).map(|(text, _)| text).unwrap_or(text);
+
+        text.trim_end() == self.managed_file_text(dictionary).trim_end()
+    }
+}
+
+/// The placeholder text followed by the tree of the managed folder (replacing the tree it
+/// already names)
+///
+/// The placeholder staged for a managed file names the managed folder it was staged with,
+/// so that the placeholder blob changes with the records and a managed file can be 
+/// reconstructed from the blob (e.g. by the textconv driver, which only gets the blob)
+pub fn placeholder_with_contents(placeholder: &str, tree: &str) -> String {
+    // the placeholder might already name a managed folder
+    let placeholder = split_placeholder_contents(placeholder).map(|(text, _)| text).unwrap_or(placeholder);
+    let separator = if placeholder.ends_with('\n') { "" } else { "\n" };
+
+    format!("{}{}{}{}\n", placeholder, separator, PLACEHOLDER_CONTENTS_PREFIX, tree)
+}
+
+/// The tree of the managed folder named by a staged placeholder (see `placeholder_with_contents()`)
+pub fn placeholder_contents(text: &str) -> Option<&str> {
+    split_placeholder_contents(text).map(|(_, tree)| tree)
+}
+
+// split a staged placeholder into the placeholder text and the tree it names
+fn split_placeholder_contents(text: &str) -> Option<(&str, &str)> {
+    let body = text.strip_suffix('\n')?;
+    let start = body.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let tree = body[start ..].strip_prefix(PLACEHOLDER_CONTENTS_PREFIX)?;
+
+    Some( (&text[.. start], tree) )
+}
+
+
+fn is_path_pattern(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+/// Translate a path pattern into a regex matching the entire path
+///
+/// `*` and `?` do not match the path separator, `**` matches any number of directories and
+/// `[...]` matches a character set (negated with `[!...]`)
+fn path_pattern_regex(pattern: &str) -> regex::Regex {
+    let mut regex = String::from("^");
+    let mut chars = pattern.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    regex.push_str("(?:.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            },
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => {
+                let set = chars.by_ref().take_while(|c| *c != ']').collect::<String>();
+                let (negated, set) = match set.strip_prefix('!') {
+                    Some(set) => ("^", set),
+                    None      => ("", set.as_str())
+                };
+
+                regex.push_str(&format!("[{}{}]", negated, set.replace('\\', "\\\\")));
+            },
+            c => regex.push_str(&regex::escape(&c.to_string()))
+        }
+    }
+    regex.push('$');
+
+    // a malformed character set matches nothing
+    regex::Regex::new(&regex).unwrap_or_else(|_| regex::Regex::new(r"[^\s\S]").unwrap())
+}
+
+
+/// Change the path of a dictionary in the configuration file text
+///
+/// The text is edited line by line so that the comments and the formatting are preserved.
+/// Returns the edited text and the number of changed settings (none if the dictionary is
+/// configured by a path pattern)
+pub fn move_dictionary_in_config(text: &str, old: &str, new: &str) -> (String, usize) {
+    lazy_static::lazy_static! {
+        static ref TABLE_REGEX : regex::Regex = regex::Regex::new(
+            r"^\s*\[\[?\s*([^\]\s]+)\s*\]\]?"
+        ).expect("fatal: invalid regex");
+
+        static ref PATH_REGEX : regex::Regex = regex::Regex::new(
+            r#"^(\s*path\s*=\s*)("[^"]*"|'[^']*')(.*)$"#
+        ).expect("fatal: invalid regex");
+    }
+
+    // whether we are in a dictionary table
+    let mut in_dictionary = false;
+    let mut changed = 0;
+
+    let text = text.split_inclusive('\n').map(|line| {
+        if let Some(table) = TABLE_REGEX.captures(line) {
+            in_dictionary = &table[1] == "dictionary";
+        }
+
+        let content = line.trim_end_matches(['\r', '\n']);
+
+        // the path value is parsed to handle the escapes
+        let setting = PATH_REGEX.captures(content).filter(|setting| {
+            toml::from_str::<toml::value::Table>(&format!("path = {}", &setting[2])).ok()
+                .and_then(|table| table.get("path").and_then(|path| path.as_str().map(|path| path == old)))
+                .unwrap_or(false)
+        });
+
+        match setting {
+            Some(setting) if in_dictionary => {
+                changed += 1;
+
+                format!("{}{}{}{}", &setting[1], toml::Value::from(new), &setting[3], &line[content.len() ..])
+            },
+            _ => line.to_owned()
+        }
+    })
+    .collect();
+
+    (text, changed)
+}
+
+/// Remove a dictionary from the configuration file text
+///
+/// The `[[dictionary]]` table of the dictionary ends with the next table that does not belong
+/// to it. Returns the edited text and the number of removed dictionaries (none if the dictionary 
+/// is configured by a path pattern)
+pub fn remove_dictionary_from_config(text: &str, path: &str) -> (String, usize) {
+    lazy_static::lazy_static! {
+        static ref TABLE_REGEX : regex::Regex = regex::Regex::new(
+            r"^\s*\[\[?\s*([^\]\s]+)\s*\]\]?"
+        ).expect("fatal: invalid regex");
+    }
+
+    let lines = text.split_inclusive('\n').collect::<Vec<_>>();
+
+    // the tables of the dictionaries (first and last line)
+    let mut tables : Vec<(usize, usize)> = vec!();
+    let mut in_dictionary = false;
+    for (i, line) in lines.iter().enumerate() {
+        if let Some(table) = TABLE_REGEX.captures(line) {
+            in_dictionary = match &table[1] {
+                "dictionary" => {
+                    tables.push((i, i + 1));
+                    true
+                },
+                name => name.starts_with("dictionary.") && in_dictionary
+            };
+        }
+
+        if let (true, Some(table)) = (in_dictionary, tables.last_mut()) {
+            table.1 = i + 1;
+        }
+    }
+
+    let table = tables.into_iter().find(|&(start, end)| {
+        lines[start .. end].iter().any(|line| {
+            toml::from_str::<toml::value::Table>(line).ok()
+                .and_then(|table| table.get("path").and_then(|value| value.as_str().map(|value| value == path)))
+                .unwrap_or(false)
+        })
+    });
+
+    match table {
+        Some((start, end)) => {
+            let mut lines = lines;
+            lines.drain(start .. end);
+
+            (lines.concat(), 1)
+        },
+        None => (text.to_owned(), 0)
+    }
+}
+
+/// Set the prefix depth of a dictionary in the configuration file text
+///
+/// The `prefix-depth` setting of the `[[dictionary]]` table of the dictionary is replaced, or 
+/// added after its `path` if the table does not have one. Returns the edited text and the 
+/// number of changed dictionaries (none if the dictionary is configured by a path pattern)
+pub fn set_prefix_depth_in_config(text: &str, path: &str, depth: usize) -> (String, usize) {
+    lazy_static::lazy_static! {
+        static ref TABLE_REGEX : regex::Regex = regex::Regex::new(
+            r"^\s*\[\[?\s*([^\]\s]+)\s*\]\]?"
+        ).expect("fatal: invalid regex");
+
+        static ref DEPTH_REGEX : regex::Regex = regex::Regex::new(
+            r"^(\s*prefix-depth\s*=\s*)[0-9]+(.*)$"
+        ).expect("fatal: invalid regex");
+    }
+
+    let mut lines = text.split_inclusive('\n').map(|line| line.to_owned()).collect::<Vec<_>>();
+
+    // the line of the path and of the prefix depth of the dictionary (if any)
+    let (mut path_line, mut depth_line) = (None, None);
+    let mut in_dictionary = false;
+    let mut is_dictionary = false;
+    for (i, line) in lines.iter().enumerate() {
+        if let Some(table) = TABLE_REGEX.captures(line) {
+            if is_dictionary { break }
+
+            in_dictionary = &table[1] == "dictionary";
+            depth_line = None;
+        }
+        if !in_dictionary { continue }
+
+        let is_path = toml::from_str::<toml::value::Table>(line).ok()
+            .and_then(|table| table.get("path").and_then(|value| value.as_str().map(|value| value == path)))
+            .unwrap_or(false);
+        if is_path {
+            is_dictionary = true;
+            path_line = Some(i);
+        }
+        if DEPTH_REGEX.is_match(line) {
+            depth_line = Some(i);
+        }
+    }
+
+    match (path_line, depth_line) {
+        (Some(_), Some(i)) => {
+            let content = lines[i].trim_end_matches(['\r', '\n']).to_owned();
+            let ending = lines[i][content.len() ..].to_owned();
+            let setting = DEPTH_REGEX.captures(&content).expect("fatal: prefix depth not found");
+
+            lines[i] = format!("{}{}{}{}", &setting[1], depth, &setting[2], ending);
+        },
+        (Some(i), None) => {
+            let ending = if lines[i].ends_with("\r\n") { "\r\n" } else { "\n" };
+            if !lines[i].ends_with('\n') {
+                lines[i].push_str(ending);
+            }
+
+            lines.insert(i + 1, format!("prefix-depth = {}{}", depth, ending));
+        },
+        _ => return (text.to_owned(), 0)
+    }
+
+    (lines.concat(), 1)
+}
+
+/// Rename a marker in the settings of a dictionary in the configuration file text
+///
+/// The dictionary is identified by its position among the `[[dictionary]]` tables. The markers
+/// are given without the backslash. The text is edited line by line so that the comments and
+/// the formatting are preserved. Returns the edited text and the number of changed settings
+pub fn rename_marker_in_config(text: &str, dictionary: usize, old: &str, new: &str) -> (String, usize) {
+    lazy_static::lazy_static! {
+        static ref TABLE_REGEX : regex::Regex = regex::Regex::new(
+            r"^\s*\[\[?\s*([^\]\s]+)\s*\]\]?"
+        ).expect("fatal: invalid regex");
+
+        // the settings with a marker or a list of markers
+        static ref SETTING_REGEX : regex::Regex = regex::Regex::new(
+            r"^\s*(?:marker|record-tag|id-tag|subrecord-tags|ignore-fields|field-order|tiers)\s*=(.*)$"
+        ).expect("fatal: invalid regex");
+    }
+
+    // the index of the dictionary table we are in (if any)
+    let mut current : Option<usize> = None;
+    let mut count = 0;
+    let mut changed = 0;
+    // whether we are within a list of markers that spans several lines
+    let mut in_list = false;
+
+    let text = text.split_inclusive('\n').map(|line| {
+        if let Some(table) = TABLE_REGEX.captures(line).filter(|_| !in_list) {
+            current = match &table[1] {
+                "dictionary" => {
+                    count += 1;
+                    Some(count - 1)
+                },
+                name if name.starts_with("dictionary.") => current,
+                _ => None
+            };
+        }
+
+        // the value of a marker setting (or the next line of a list of markers)
+        let content = line.trim_end_matches(['\r', '\n']);
+        let (key, value) = if in_list {
+            ("", content)
+        } else {
+            match SETTING_REGEX.captures(content).and_then(|setting| setting.get(1)) {
+                Some(value) => content.split_at(value.start()),
+                None        => return line.to_owned()
+            }
+        };
+
+        // a list continues on the next line until it is closed
+        let (value, renamed, list) = rename_marker_in_value(value, old, new);
+        in_list = if in_list { !list.closed } else { list.opened && !list.closed };
+
+        if current != Some(dictionary) || renamed == 0 {
+            return line.to_owned()
+        }
+
+        changed += renamed;
+        format!("{}{}{}", key, value, &line[content.len() ..])
+    })
+    .collect();
+
+    (text, changed)
+}
+
+// Whether a (part of a) setting value opens and closes a list
+#[derive(Default)]
+struct ListBrackets {
+    opened : bool,
+    closed : bool
+}
+
+// Rename the marker in the quoted strings of (a line of) a setting value
+//
+// The backslash before the marker is kept and the comment after the value is left alone.
+// Returns the edited value, the number of renamed markers and the list brackets in the value
+fn rename_marker_in_value(value: &str, old: &str, new: &str) -> (String, usize, ListBrackets) {
+    lazy_static::lazy_static! {
+        static ref TOKEN_REGEX : regex::Regex = regex::Regex::new(
+            r#"(")(\\{0,2})([^"\\]*)"|(')(\\?)([^']*)'|[\[\]#]"#
+        ).expect("fatal: invalid regex");
+    }
+
+    let mut edited = String::new();
+    let mut renamed = 0;
+    let mut brackets = ListBrackets::default();
+    let mut position = 0;
+
+    for token in TOKEN_REGEX.captures_iter(value) {
+        let whole = token.get(0).expect("fatal: regex match without text");
+
+        match whole.as_str() {
+            "#" => break,
+            "[" => brackets.opened = true,
+            "]" => brackets.closed = true,
+            _   => {
+                let (quote, backslash, marker) = match token.get(1) {
+                    Some(quote) => (quote.as_str(), &token[2], &token[3]),
+                    None        => (&token[4], &token[5], &token[6])
+                };
+
+                if marker == old {
+                    edited.push_str(&value[position .. whole.start()]);
+                    edited.push_str(&format!("{0}{1}{2}{0}", quote, backslash, new));
+                    position = whole.end();
+                    renamed += 1;
+                }
+            }
+        }
+    }
+    edited.push_str(&value[position ..]);
+
+    (edited, renamed, brackets)
 }
 
 
 mod deserialize {
     use anyhow::Result;
     use crate::error;
-    use super::Config;
+    use super::{Config, MAX_PREFIX_DEPTH};
     
     impl std::convert::TryFrom<&[u8]> for Config {
         type Error = anyhow::Error;
@@ -116,15 +961,20 @@ mod deserialize {
             })?;
     
             // parse the toml file
-            toml::from_str(text).map_err(|err| {
+            let mut config : Config = toml::from_str(text).map_err(|err| {
                 error::ConfigurationError {
                     text : text.to_owned(),
                     at   : err.line_col(),
                     msg  : err.to_string()
                 }
-                // convert this into anyhow::Error
-                .into()
-            })
+            })?;
+
+            // the first record tag is the main one
+            for cfg in config.dictionaries.iter_mut() {
+                cfg.record_tag = cfg.record_tags.first().cloned().unwrap_or_default();
+            }
+
+            Ok( config )
         }
     }
     
@@ -143,6 +993,40 @@ mod deserialize {
     }
     
     
+    /// Read a marker or a list of markers
+    pub fn read_record_tags<'a, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+    where
+        D: Deserializer<'a>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Tags {
+            One(String),
+            Many(Vec<String>)
+        }
+
+        let tags = match Deserialize::deserialize(deserializer)? {
+            Tags::One(tag)   => vec!(tag),
+            Tags::Many(tags) => tags
+        };
+
+        // add the prefix
+        Ok( tags.into_iter().map(|s| r"\".to_owned() + &s).collect() )
+    }
+
+
+    pub fn read_markers<'a, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+    where
+        D: Deserializer<'a>,
+    {
+        // read the basic strings
+        let markers: Vec<&str> = Deserialize::deserialize(deserializer)?;
+
+        // add the prefix
+        Ok( markers.into_iter().map(|s| r"\".to_owned() + s).collect() )
+    }
+    
+    
     pub fn read_marker_option<'a, D>(deserializer: D) -> Result<Option<String>, D::Error>
     where
         D: Deserializer<'a>,
@@ -186,7 +1070,84 @@ mod deserialize {
     }
     
     
+    pub fn default_tiers() -> Vec<String> {
+        vec!(r"\tx".to_owned(), r"\mb".to_owned(), r"\ge".to_owned())
+    }
+
+
+    pub fn default_record_spacing() -> usize {
+        1
+    }
+
+
+    pub fn default_prefix_depth() -> usize {
+        2
+    }
+
+
+    pub fn read_prefix_depth<'a, D>(deserializer: D) -> Result<usize, D::Error>
+    where
+        D: Deserializer<'a>,
+    {
+        use serde::de::Error;
+
+        let depth: usize = Deserialize::deserialize(deserializer)?;
+
+        if !(1 ..= MAX_PREFIX_DEPTH).contains(&depth) {
+            return Err( 
+                Error::custom(format!("prefix-depth has to be between 1 and {}", MAX_PREFIX_DEPTH))
+            );
+        }
+
+        Ok( depth )
+    }
+
+
+    pub fn default_max_deleted_records() -> f64 {
+        25.0
+    }
+
+
     pub fn default_id_spec() -> regex::Regex {
         regex::Regex::new("$(?P<id>.+)^").expect("Internal error - invalid regex")
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::rename_marker_in_config;
+
+    #[test]
+    fn test_rename_marker_in_config() {
+        let text = "[[dictionary]]\n\
+            path = \"lex.txt\"\n\
+            record-tag = \"lex\"\n\
+            \n\
+            [[dictionary]]\n\
+            path = \"texts.txt\"\n\
+            record-tag = [\"lem\", 'rt', \"\\\\lemma\"] # \"lem\"\r\n\
+            tiers = [\n  \"tx\",\n  \"lem\", # \"lem\" \n]\n\
+            subrecord-tags = [\"se\"]\n\
+            name = \"lem\"\n\
+            \n\
+            [[dictionary.field]]\n\
+            marker = 'lem'\n";
+
+        let (renamed, count) = rename_marker_in_config(text, 1, "lem", "lx");
+        assert_eq!(count, 3);
+        assert_eq!(renamed, text
+            .replace("[\"lem\", 'rt'", "[\"lx\", 'rt'")
+            .replace("  \"lem\", #", "  \"lx\", #")
+            .replace("marker = 'lem'", "marker = 'lx'")
+        );
+
+        // the backslash is kept
+        let (renamed, count) = rename_marker_in_config(text, 1, "lemma", "lx");
+        assert_eq!(count, 1);
+        assert!(renamed.contains("'rt', \"\\\\lx\"] # \"lem\"\r\n"));
+
+        // the other dictionaries are not changed
+        assert_eq!(rename_marker_in_config(text, 0, "lem", "lx"), (text.to_owned(), 0));
+        assert_eq!(rename_marker_in_config(text, 1, "se", "sn").1, 1);
+    }
+}
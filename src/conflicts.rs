@@ -0,0 +1,272 @@
+//
+// src/conflicts.rs
+//
+// Implementation of git-toolbox conflicts
+//
+// Lists the conflicting records left by a merge in the managed folders (by
+// their ID, with the first line of both versions) and resolves the conflicts
+// of a record by taking our version, their version or both. The resolved entry
+// files are added to the index, just like git checkout --ours/--theirs and git
+// add would do for ordinary files.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::repository::{Repository, ConflictedFile, clob_part};
+use crate::toolbox::{ClobOrigin, clob_paths_for_id, starts_record};
+use crate::toolbox::merge::{find_conflicts, Conflict};
+use crate::resolve::replace_conflicts;
+use crate::config::DictionaryConfig;
+use crate::cli_app::style;
+
+use crate::error;
+use anyhow::{Result, bail};
+
+
+/// How the conflicts of a record are resolved
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resolution {
+    /// Our version of the record
+    Ours,
+    /// Their version of the record
+    Theirs,
+    /// Both versions of the record (ours first)
+    Union
+}
+
+impl Resolution {
+    fn name(&self) -> &'static str {
+        match self {
+            Resolution::Ours   => "our version",
+            Resolution::Theirs => "their version",
+            Resolution::Union  => "both versions"
+        }
+    }
+}
+
+
+pub fn conflicts(files: Vec<String>) -> Result<()> {
+    let repo = Repository::open()?;
+
+    let (mut count, mut other_count) = (0, 0);
+    for cfg in dictionaries(&repo, &files)? {
+        let contents_path = format!("{}.contents", cfg.path);
+        let conflicted = repo.conflicted_files(&contents_path)?;
+        if conflicted.is_empty() { continue }
+
+        let (entries, other) : (Vec<_>, Vec<_>) = conflicted.iter().partition(|file| file.path.ends_with(".txt"));
+
+        if !entries.is_empty() {
+            stdout!("Conflicting records in {}:\n", style(&cfg.path).bold());
+        }
+        for file in entries {
+            stdout!("        {}", style(entry_name(&contents_path, &file.path)).bold());
+
+            for (ours, theirs) in conflicting_versions(&repo, cfg, file)? {
+                stdout!("            ours:   {}", ours.unwrap_or_else(|| style("(deleted)".to_owned()).dim().to_string()));
+                stdout!("            theirs: {}", theirs.unwrap_or_else(|| style("(deleted)".to_owned()).dim().to_string()));
+                count += 1;
+            }
+        }
+
+        if !other.is_empty() {
+            if count > 0 { stdout!(""); }
+            stdout!("Other conflicted files in {}:\n", style(&contents_path).bold());
+            for file in other.iter() {
+                stdout!("        {}", file.path);
+            }
+            other_count += other.len();
+        }
+        stdout!("");
+    }
+
+    if count == 0 && other_count == 0 {
+        stdout!("{} No conflicting records", style("✓").green());
+    } else {
+        stdout!("⚠️  There were {} conflicting records and {} other conflicted files.", count, other_count);
+        stdout!("  (use \"{}\" to resolve the conflicts of a record)",
+            style("git toolbox conflicts --ours|--theirs|--union <id>").bold()
+        );
+        stdout!("  (use \"{}\" to resolve them field by field)",
+            style("git toolbox resolve <entry file>").bold()
+        );
+    }
+
+    Ok( () )
+}
+
+/// Resolve the conflicts of a record (given by its ID or by its entry file)
+pub fn resolve(files: Vec<String>, id: String, resolution: Resolution) -> Result<()> {
+    let repo = Repository::open()?;
+
+    // no other git-toolbox operations while we are modifying the index
+    let _lock = repo.lock_operation("conflicts")?;
+
+    // the record can also be given by the path of its entry file
+    let entry_path = repo.get_path_relative_to_repo(&id).ok()
+        .map(|path| path.to_string_lossy().into_owned());
+
+    let mut resolved = vec!();
+    for cfg in dictionaries(&repo, &files)? {
+        let contents_path = format!("{}.contents", cfg.path);
+
+        // the entry files of the ID (case-insensitive, as are the paths of the records)
+        let clob_paths = if cfg.unique_id {
+            clob_paths_for_id(cfg, &id).into_iter().map(|path| format!("{}/{}", contents_path, path).to_lowercase()).collect()
+        } else {
+            vec!()
+        };
+
+        for file in repo.conflicted_files(&contents_path)? {
+            let (clob, _) = clob_part(&file.path);
+
+            if !clob_paths.contains(&clob.to_lowercase()) && Some(&file.path) != entry_path.as_ref() { continue }
+
+            let text = resolved_text(&repo, &file, resolution)?;
+            repo.resolve_conflicted_file(&file.path, text.as_deref())?;
+
+            resolved.push(file.path);
+        }
+    }
+
+    if resolved.is_empty() {
+        bail!(
+            error::NoConflictingRecord {
+                id
+            }
+        )
+    }
+
+    for path in resolved.iter() {
+        stdout!("{} Resolved {} with {}", style("✓").green(), style(path).bold(), resolution.name());
+    }
+
+    let remaining = dictionaries(&repo, &files)?.into_iter().try_fold(0, |count, cfg| -> Result<usize> {
+        Ok( count + repo.conflicted_files(&format!("{}.contents", cfg.path))?.len() )
+    })?;
+
+    if remaining > 0 {
+        stdout!("   ({} conflicted files remain, use \"{}\" to list them)",
+            remaining,
+            style("git toolbox conflicts").bold()
+        );
+    } else {
+        stdout!("✅ All conflicts in the managed folders are resolved. Commit the merge and use {} to rebuild the toolbox dictionaries.",
+            style("\"git toolbox reset --force\"").bold()
+        );
+    }
+
+    Ok( () )
+}
+
+
+// The managed files to check (all managed files if none are given)
+fn dictionaries<'a>(repo: &'a Repository, files: &[String]) -> Result<Vec<&'a DictionaryConfig>> {
+    if files.is_empty() {
+        return Ok( repo.config().dictionaries.iter().collect() );
+    }
+
+    files.iter().map(|path| {
+        let path = repo.get_path_relative_to_repo(path)?.to_string_lossy().into_owned();
+
+        repo.config().dictionary_by_path(path)
+    })
+    .collect()
+}
+
+// The name of the records in an entry file (the ID if the records have one)
+fn entry_name(contents_path: &str, path: &str) -> String {
+    match ClobOrigin::from_managed_path(contents_path, path) {
+        ClobOrigin::Public { id } | ClobOrigin::Private { namespace: _, id } => id.to_owned(),
+        ClobOrigin::Label { label }       => format!("{} (label)", label),
+        ClobOrigin::Quarantine { name }   => format!("{} (quarantine)", name),
+        ClobOrigin::Header                => "the dictionary header".to_owned(),
+        ClobOrigin::Preamble              => "the lines before the first record".to_owned()
+    }
+}
+
+// The first lines of our and their version of the conflicting records in an entry file
+// (`None` if the record was deleted on that side)
+fn conflicting_versions(repo: &Repository, cfg: &DictionaryConfig, file: &ConflictedFile)
+    -> Result<Vec<(Option<String>, Option<String>)>>
+{
+    let first_line = |text: &str| -> Option<String> {
+        let lines = || text.lines().map(str::trim_end).filter(|line| !line.is_empty());
+
+        lines().find(|line| starts_record(line, &cfg.record_tags)).or_else(|| lines().next()).map(str::to_owned)
+    };
+
+    // the conflicts marked by the merge driver
+    let conflicts = workdir_text(repo, &file.path)?.map(|text| find_conflicts(&text)).unwrap_or_default();
+    if !conflicts.is_empty() {
+        return Ok(
+            conflicts.iter().map(|conflict| (first_line(&conflict.ours), first_line(&conflict.theirs))).collect()
+        );
+    }
+
+    // otherwise the whole entry file is in conflict (e.g. it was deleted on one side)
+    Ok( vec!((
+        file.ours.as_deref().and_then(first_line),
+        file.theirs.as_deref().and_then(first_line)
+    )) )
+}
+
+// The resolved text of an entry file (`None` if the file is to be deleted)
+fn resolved_text(repo: &Repository, file: &ConflictedFile, resolution: Resolution) -> Result<Option<String>> {
+    let union = |ours: &str, theirs: &str| -> String {
+        match (ours.trim_end(), theirs.trim_end()) {
+            (ours, theirs) if ours == theirs || theirs.is_empty() => ours.to_owned(),
+            ("", theirs)                                          => theirs.to_owned(),
+            (ours, theirs)                                        => format!("{}\n\n{}", ours, theirs)
+        }
+    };
+
+    let text = match workdir_text(repo, &file.path)? {
+        // the conflicts marked by the merge driver are replaced by the chosen versions
+        Some(text) if !find_conflicts(&text).is_empty() => {
+            let conflicts = find_conflicts(&text);
+            let records = conflicts.iter().enumerate().map(|(i, conflict): (usize, &Conflict)| {
+                let record = match resolution {
+                    Resolution::Ours   => conflict.ours.trim_end().to_owned(),
+                    Resolution::Theirs => conflict.theirs.trim_end().to_owned(),
+                    Resolution::Union  => union(&conflict.ours, &conflict.theirs)
+                };
+
+                (i, if record.is_empty() { record } else { format!("{}\n", record) })
+            })
+            .collect::<Vec<_>>();
+
+            replace_conflicts(&text, &conflicts, &records)
+        },
+        // otherwise the versions in the index are used
+        _ => {
+            let ours = file.ours.as_deref().unwrap_or("");
+            let theirs = file.theirs.as_deref().unwrap_or("");
+
+            match resolution {
+                Resolution::Ours   => ours.to_owned(),
+                Resolution::Theirs => theirs.to_owned(),
+                Resolution::Union  => format!("{}\n", union(ours, theirs))
+            }
+        }
+    };
+
+    // an entry file without any records is removed
+    Ok( Some(text).filter(|text| !text.trim().is_empty()) )
+}
+
+// The text of a file in the working directory (`None` if there is no such file)
+fn workdir_text(repo: &Repository, path: &str) -> Result<Option<String>> {
+    let full_path = repo.workdir()?.join(path);
+    if !full_path.exists() { return Ok( None ) }
+
+    let data = std::fs::read(&full_path).map_err(|err| {
+        error::FileReadError {
+            path : full_path.clone(),
+            msg  : err.to_string()
+        }
+    })?;
+
+    Ok( Some(String::from_utf8_lossy(&data).into_owned()) )
+}
@@ -0,0 +1,289 @@
+//
+// src/diff.rs
+//
+// Implementation of git-toolbox diff
+//
+// Describes the changes to the managed toolbox files in terms of records and
+// their fields rather than as line diffs. The changed field values are shown 
+// inline, with the removed and added words highlighted (e.g. "gloss changed: 
+// [-big-]{+domestic+} dog" without colors).
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::repository::{Repository, ClobDiff};
+use crate::toolbox::{Dictionary, ClobOrigin};
+use crate::toolbox::template::{self, RecordChange, FieldChange};
+use crate::toolbox::word_diff::{self, WordChunk};
+use crate::config::DictionaryConfig;
+use crate::cli_app::style;
+use crate::util::{truncate_text, pad_text};
+use crate::output::{self, Report};
+
+use serde::Serialize;
+
+use anyhow::Result;
+
+
+pub fn diff(paths: Vec<String>, cached: bool, namespace: Option<String>, side_by_side: bool) -> Result<()> {
+    // open the repository
+    let repo = Repository::open()?;
+
+    // dictionary selection
+    let dictionaries : Vec<&DictionaryConfig> = if paths.is_empty() {
+        repo.config().dictionaries.iter().collect()
+    } else {
+        paths.iter().map(|path| {
+            // convert the path to one relative to the repo
+            let path = repo.get_path_relative_to_repo(path)?.to_string_lossy().into_owned();
+
+            repo.config().dictionary_by_path(path)
+        })
+        .collect::<Result<Vec<_>>>()?
+    };
+
+    let mut files = vec!();
+    for cfg in dictionaries {
+        let contents_path = format!("{}.contents", cfg.path);
+
+        // the changed clobs with their old and new content
+        let changes = if cached {
+            // index against HEAD
+            repo.get_staged_clobs(&contents_path)?.into_iter().map(|change| {
+                let old = repo.read_text_from_head(change.path()).unwrap_or_default();
+                let new = repo.read_text_from_index(change.path()).unwrap_or_default();
+
+                (change.path().to_owned(), old, new)
+            })
+            .collect::<Vec<_>>()
+        } else {
+            // working directory against the index
+            let dictionary = Dictionary::load(&repo, cfg, false)?;
+            let (clobs, _) = dictionary.split();
+
+            repo.diff_clobs_at_path(&contents_path, clobs)?.into_iter().map(|change| {
+                let old = repo.read_text_from_index(change.path()).unwrap_or_default();
+
+                match change {
+                    ClobDiff::Add { clob } | ClobDiff::Update { clob } => (clob.path, old, clob.content),
+                    ClobDiff::Delete { path }                          => (path, old, String::new())
+                }
+            })
+            .collect::<Vec<_>>()
+        };
+
+        // restrict the changes to the namespace (if requested) and sort them for display
+        let mut changes = changes.into_iter()
+            .filter(|(path, _, _)| {
+                namespace.as_deref().map(|namespace| {
+                    ClobOrigin::from_managed_path(&contents_path, path).is_in_namespace(namespace)
+                })
+                .unwrap_or(true)
+            })
+            .collect::<Vec<_>>();
+        changes.sort_by(|a, b| cfg.sort.compare(&a.0, &b.0));
+
+        if changes.is_empty() {
+            continue;
+        }
+
+        let mut records = vec!();
+        for (path, old, new) in changes.iter() {
+            let origin = ClobOrigin::from_managed_path(&contents_path, path);
+
+            records.extend(template::record_changes(cfg, old, new).into_iter().map(|change| {
+                RecordDiff::new(&origin, change)
+            }));
+        }
+
+        files.push(FileDiff { path : cfg.path.clone(), records });
+    }
+
+    // the compared versions are the column headers of the side-by-side view
+    let columns = match (side_by_side, cached) {
+        (false, _)    => None,
+        (true, true)  => Some( ("HEAD", "index") ),
+        (true, false) => Some( ("index", "working copy") )
+    };
+
+    output::emit(&DiffReport { files, columns })
+}
+
+
+/// The record changes, as described by git toolbox diff
+#[derive(Serialize)]
+struct DiffReport {
+    files : Vec<FileDiff>,
+    // the column headers (if the values are shown side by side)
+    #[serde(skip)]
+    columns : Option<(&'static str, &'static str)>
+}
+
+#[derive(Serialize)]
+struct FileDiff {
+    /// The managed file (relative to the repository)
+    path    : String,
+    /// The changed records
+    records : Vec<RecordDiff>
+}
+
+#[derive(Serialize)]
+struct RecordDiff {
+    /// The namespace of the record (see `ClobOrigin::namespace`)
+    namespace : String,
+    /// The record id or label
+    key       : String,
+    /// How the record was changed
+    change    : ChangeKind,
+    /// The record label (its first line)
+    label     : String,
+    /// The changed fields of a modified record
+    fields    : Vec<FieldChange>
+}
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+enum ChangeKind {
+    Added,
+    Deleted,
+    Modified
+}
+
+impl ChangeKind {
+    fn name(&self) -> &'static str {
+        match self {
+            ChangeKind::Added    => "added",
+            ChangeKind::Deleted  => "deleted",
+            ChangeKind::Modified => "modified"
+        }
+    }
+}
+
+impl RecordDiff {
+    fn new(origin: &ClobOrigin, change: RecordChange) -> Self {
+        let (change, label, fields) = match change {
+            RecordChange::Added { label }             => (ChangeKind::Added, label, vec!()),
+            RecordChange::Removed { label }           => (ChangeKind::Deleted, label, vec!()),
+            RecordChange::Modified { label, changes } => (ChangeKind::Modified, label, changes)
+        };
+
+        RecordDiff {
+            namespace : origin.namespace(),
+            key       : origin.key().to_owned(),
+            change,
+            label,
+            fields
+        }
+    }
+}
+
+impl Report for DiffReport {
+    fn display(&self) {
+        if self.files.is_empty() {
+            stdout!("No changes in the managed toolbox dictionaries.");
+        }
+
+        for file in self.files.iter() {
+            stdout!("{}", style(&file.path).bold());
+            stdout!("");
+
+            for record in file.records.iter() {
+                display_record_change(record, self.columns);
+            }
+            stdout!("");
+        }
+    }
+
+    /// The lines are:
+    ///
+    /// - `record <managed file> <added|deleted|modified> <namespace>/<key> <label>`
+    /// - `field <managed file> <namespace>/<key> <description>` (following their record)
+    fn porcelain(&self) -> Vec<Vec<String>> {
+        let mut lines = vec!();
+
+        for file in self.files.iter() {
+            for record in file.records.iter() {
+                let key = format!("{}/{}", record.namespace, record.key);
+
+                lines.push(vec!("record".to_owned(), file.path.clone(), record.change.name().to_owned(), key.clone(), record.label.clone()));
+                lines.extend(record.fields.iter().map(|field| {
+                    vec!("field".to_owned(), file.path.clone(), key.clone(), field.to_string())
+                }));
+            }
+        }
+
+        lines
+    }
+}
+
+
+fn display_record_change(record: &RecordDiff, columns: Option<(&str, &str)>) {
+    let key = pad_text(&truncate_text(&format!("{}/{}", record.namespace, record.key), 24), 24);
+    let label = truncate_text(&record.label, 40);
+
+    match record.change {
+        ChangeKind::Added    => {
+            stdout!("  {} {} {}", style("added   ").green(), key, style(label).italic());
+        },
+        ChangeKind::Deleted  => {
+            stdout!("  {} {} {}", style("deleted ").red(), key, style(label).italic());
+        },
+        ChangeKind::Modified => {
+            stdout!("  {} {} {}", style("modified").yellow(), key, style(label).italic());
+
+            if let Some(columns) = columns {
+                display_side_by_side(&record.fields, columns);
+            } else {
+                for change in record.fields.iter() {
+                    stdout!("      {}", display_field_change(change));
+                }
+            }
+        }
+    }
+}
+
+// The old and new values of the changed fields in two columns (fitted to the terminal width)
+fn display_side_by_side(changes: &[FieldChange], (old, new): (&str, &str)) {
+    use crate::listing_formatter::SideBySideFormatter;
+
+    let mut listing = SideBySideFormatter::new();
+    listing.set_header(old, new);
+    for change in changes.iter() {
+        match change {
+            FieldChange::Changed { field, old, new } => listing.push_row(field, Some(old), Some(new)),
+            FieldChange::Added { field, value }      => listing.push_row(field, None, Some(value)),
+            FieldChange::Removed { field, value }    => listing.push_row(field, Some(value), None)
+        };
+    }
+
+    let (_, width) = console::Term::stdout().size();
+    stdout!("{:width$}", listing, width = (width as usize).saturating_sub(4));
+}
+
+/// Describe a field change for display, with the changed words of a replaced value highlighted
+///
+/// The removed words are red and the added words green. Without colors, they are
+/// marked as `[-removed-]{+added+}` (as in `git diff --word-diff`).
+pub fn display_field_change(change: &FieldChange) -> String {
+    match change {
+        FieldChange::Changed { field, old, new } => {
+            format!("{} changed: {}", field, word_diff::diff_words(old, new).into_iter().map(|chunk| {
+                match chunk {
+                    WordChunk::Same(text)                                => text.to_owned(),
+                    WordChunk::Removed(text) if console::colors_enabled() => style(text).red().underlined().to_string(),
+                    WordChunk::Added(text) if console::colors_enabled()   => style(text).green().underlined().to_string(),
+                    WordChunk::Removed(text)                             => format!("[-{}-]", text),
+                    WordChunk::Added(text)                               => format!("{{+{}+}}", text)
+                }
+            })
+            .collect::<String>())
+        },
+        FieldChange::Added { field, value } => {
+            format!("added {} '{}'", field, style(truncate_text(value, 40)).green())
+        },
+        FieldChange::Removed { field, value } => {
+            format!("removed {} '{}'", field, style(truncate_text(value, 40)).red())
+        }
+    }
+}
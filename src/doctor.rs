@@ -0,0 +1,445 @@
+//
+// src/doctor.rs
+//
+// Implementation of git-toolbox doctor
+//
+// Detects repository problems that cannot be dealt with during the normal
+// operation (e.g. because git was misconfigured at some point) and offers
+// guided repairs for them. Every problem is listed with the command that fixes
+// it.
+//
+// The git configuration is checked first: the repository cannot be opened
+// (and the remaining checks cannot run) until it is up to date.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::repository::{Repository, ClobValidationIssue, IndexLock, filter_on_path, filter_version};
+use crate::toolbox::Dictionary;
+use crate::config::DictionaryConfig;
+use crate::cli_app::style;
+
+use crate::error;
+use anyhow::{Result, bail};
+
+
+/// A problem detected by the doctor
+enum Finding {
+    /// The git configuration does not match the configuration file
+    Configuration { problem: String },
+    /// git cannot find git-toolbox to run the filter
+    FilterNotFound,
+    /// git runs a different version of git-toolbox as the filter
+    FilterVersion { version: Option<String> },
+    /// A managed file or folder has no merge attribute
+    MissingMergeAttribute { path: String },
+    /// The placeholder is staged, but the managed folder is not
+    MissingContents { path: String },
+    /// The managed folder was modified outside of git-toolbox
+    ExternalChanges { path: String, count: usize },
+    /// The staged records do not match the record manifest
+    ManifestMismatch { path: String, count: usize },
+    /// The real dictionary content is staged instead of the placeholder
+    PlaceholderDriftInIndex { path: String },
+    /// The real dictionary content was committed instead of the placeholder
+    PlaceholderDriftInHead { path: String },
+    /// The staged placeholder does not match the configured placeholder text
+    StalePlaceholder { path: String },
+    /// The placeholder entry is no longer marked as assume-unchanged
+    UnmarkedPlaceholder { path: String },
+    /// The git index lock was left behind by a crashed git process
+    StaleIndexLock { age: u64 }
+}
+
+
+pub fn doctor(repair: bool, verbose: bool) -> Result<()> {
+    // the repository can only be opened once the git configuration is up to date
+    let problems = Repository::configuration_problems()?;
+    if !problems.is_empty() {
+        for problem in problems.iter() {
+            Finding::Configuration { problem: problem.clone() }.display(verbose);
+        }
+        stdout!("");
+        stdout!("⚠️  Found {} problems with the git configuration. Use {} to fix them and run {} again for the remaining checks.",
+            problems.len(),
+            style("git toolbox setup").bold(),
+            style("git toolbox doctor").bold()
+        );
+
+        return Ok( () )
+    }
+
+    // load the repository
+    let mut repo = Repository::open()?;
+
+    // run the diagnostics (the index lock comes first as it blocks the other repairs)
+    let findings = diagnose_index_lock(&repo).into_iter()
+        .chain(diagnose_filter())
+        .chain(diagnose_attributes(&repo)?)
+        .chain(
+            repo.config().dictionaries.iter().flat_map(|cfg| {
+                diagnose_placeholder(&repo, cfg)
+            })
+        )
+        .chain(
+            repo.config().dictionaries.iter().map(|cfg| {
+                diagnose_contents(&repo, cfg)
+            })
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+        )
+        .collect::<Vec<_>>();
+
+    if findings.is_empty() {
+        stdout!("✅ No problems found.");
+
+        return Ok( () )
+    }
+
+    for finding in findings.iter() {
+        finding.display(verbose);
+    }
+    stdout!("");
+
+    // the list of problems we are able to repair
+    let repairable = findings.iter().filter(|finding| finding.is_repairable()).collect::<Vec<_>>();
+
+    if repairable.is_empty() {
+        stdout!("⚠️  Found {} problems. They cannot be repaired automatically.", findings.len());
+
+        return Ok( () )
+    }
+
+    if !repair {
+        stdout!("⚠️  Found {} problems. Use {} to repair them.",
+            findings.len(),
+            style("git toolbox doctor --fix").bold()
+        );
+
+        return Ok( () )
+    }
+
+    // no other git-toolbox operations while we are repairing
+    let _lock = repo.lock_operation("doctor")?;
+
+    for finding in repairable {
+        finding.repair(&mut repo)?;
+    }
+
+    stdout!("\n✅ Repairs completed. Please review the staged changes and commit them.");
+
+    Ok( () )
+}
+
+
+// Check for a stale git index lock
+fn diagnose_index_lock(repo: &Repository) -> Option<Finding> {
+    match repo.index_lock() {
+        IndexLock::Stale { age } => Some(Finding::StaleIndexLock { age }),
+        _                        => None
+    }
+}
+
+// Check that git can run the filter, and that it runs this version of git-toolbox
+fn diagnose_filter() -> Option<Finding> {
+    if !filter_on_path() {
+        return Some(Finding::FilterNotFound)
+    }
+
+    let version = filter_version();
+    if version.as_deref() != Some(env!("CARGO_PKG_VERSION")) {
+        return Some(Finding::FilterVersion { version })
+    }
+
+    None
+}
+
+// Check the merge attributes (the filter attributes are checked with the configuration)
+fn diagnose_attributes(repo: &Repository) -> Result<Vec<Finding>> {
+    Ok(
+        repo.missing_merge_attributes()?.into_iter().map(|path| {
+            Finding::MissingMergeAttribute { path }
+        })
+        .collect()
+    )
+}
+
+// Check that the managed folder is staged, matches the index and the record manifest
+fn diagnose_contents(repo: &Repository, cfg: &DictionaryConfig) -> Result<Vec<Finding>> {
+    let contents_path = format!("{}.contents", cfg.path);
+    let mut findings = vec!();
+
+    // a staged managed file without a managed folder is reconstructed as an empty dictionary
+    let staged = repo.read_text_from_index(&cfg.path).is_some();
+    if staged && Repository::reconstruct(&contents_path, "", &cfg.sort)?.is_empty() {
+        findings.push(Finding::MissingContents { path: cfg.path.clone() });
+
+        return Ok( findings )
+    }
+
+    let issues = repo.validate_clobs_in_workdir(&contents_path, false)?;
+    if !issues.is_empty() {
+        findings.push(Finding::ExternalChanges { path: cfg.path.clone(), count: issues.len() });
+    }
+
+    if let Some(changes) = repo.verify_staged_records(&contents_path, &cfg.record_tags)? {
+        if !changes.is_empty() {
+            findings.push(Finding::ManifestMismatch { path: cfg.path.clone(), count: changes.len() });
+        }
+    }
+
+    Ok( findings )
+}
+
+// Compare the indexed and the committed managed file to the placeholder
+fn diagnose_placeholder(repo: &Repository, cfg: &DictionaryConfig) -> Vec<Finding> {
+    let mut findings = vec!();
+
+    // check the index
+    match repo.read_text_from_index(&cfg.path) {
+        Some(text) if cfg.matches_content(&text) => {
+            findings.push(Finding::PlaceholderDriftInIndex { path: cfg.path.clone() });
+        },
+        Some(text) if !repo.config().is_managed_file_text(cfg, &text) => {
+            findings.push(Finding::StalePlaceholder { path: cfg.path.clone() });
+        },
+        Some(_) if repo.unmarked_managed_files().contains(&cfg.path) => {
+            findings.push(Finding::UnmarkedPlaceholder { path: cfg.path.clone() });
+        },
+        _ => {}
+    }
+
+    // check the last commit
+    if let Some(text) = repo.read_text_from_head(&cfg.path) {
+        if cfg.matches_content(&text) {
+            findings.push(Finding::PlaceholderDriftInHead { path: cfg.path.clone() });
+        }
+    }
+
+    findings
+}
+
+
+impl Finding {
+    fn path(&self) -> &str {
+        match self {
+            Finding::PlaceholderDriftInIndex { path }       |
+            Finding::PlaceholderDriftInHead { path }        |
+            Finding::StalePlaceholder { path }              |
+            Finding::UnmarkedPlaceholder { path }           |
+            Finding::MissingMergeAttribute { path }         |
+            Finding::MissingContents { path }               |
+            Finding::ExternalChanges { path, count: _ }     |
+            Finding::ManifestMismatch { path, count: _ }    => path,
+            Finding::StaleIndexLock { age: _ }              => "index.lock",
+            Finding::Configuration { problem: _ }           |
+            Finding::FilterNotFound                         |
+            Finding::FilterVersion { version: _ }           => "git-toolbox"
+        }
+    }
+
+    /// The command that fixes the problem
+    fn fix(&self) -> String {
+        match self {
+            Finding::PlaceholderDriftInIndex { path: _ } |
+            Finding::UnmarkedPlaceholder { path: _ }     |
+            Finding::StaleIndexLock { age: _ }           => "git toolbox doctor --fix".to_owned(),
+            Finding::PlaceholderDriftInHead { path: _ }  => "git toolbox doctor --fix && git commit".to_owned(),
+            Finding::Configuration { problem: _ }        |
+            Finding::StalePlaceholder { path: _ }        |
+            Finding::MissingMergeAttribute { path: _ }   => "git toolbox setup".to_owned(),
+            Finding::FilterNotFound                      |
+            Finding::FilterVersion { version: _ }        => "cargo install --path . (in the git-toolbox sources)".to_owned(),
+            Finding::MissingContents { path }            => format!("git toolbox stage {}", path),
+            Finding::ExternalChanges { path, count: _ }  => format!("git toolbox reset --force --repair-contents {}", path),
+            Finding::ManifestMismatch { path, count: _ } => format!("git toolbox verify {}", path)
+        }
+    }
+
+    fn is_repairable(&self) -> bool {
+        matches!(self, 
+            Finding::PlaceholderDriftInIndex { path: _ } | 
+            Finding::UnmarkedPlaceholder { path: _ }     |
+            Finding::StaleIndexLock { age: _ }
+        )
+    }
+
+    fn display(&self, verbose: bool) {
+        let path = style(self.path()).italic();
+
+        match self {
+            Finding::Configuration { problem } => {
+                stdout!("  {} {}", style("✗").red(), problem);
+            },
+            Finding::FilterNotFound => {
+                stdout!("  {} {} is not on the PATH, git cannot run the filter", style("✗").red(), path);
+                if verbose {
+                    stdout!("      (the managed files cannot be checked out or staged by git)");
+                }
+            },
+            Finding::FilterVersion { version } => {
+                stdout!("  {} git runs {} {} as the filter, but this is version {}", 
+                    style("✗").yellow(), 
+                    path,
+                    version.as_deref().unwrap_or("(unknown version)"),
+                    env!("CARGO_PKG_VERSION")
+                );
+                if verbose {
+                    stdout!("      (the git-toolbox on the PATH should be the same version as the one you are using)");
+                }
+            },
+            Finding::MissingMergeAttribute { path: _ } => {
+                stdout!("  {} {} has no merge attribute", style("✗").yellow(), path);
+                if verbose {
+                    stdout!("      (the entries would be merged line by line instead of record by record)");
+                }
+            },
+            Finding::MissingContents { path: _ } => {
+                stdout!("  {} the managed folder of {} is not staged", style("✗").red(), path);
+                if verbose {
+                    stdout!("      (only the placeholder text would be commited)");
+                }
+            },
+            Finding::ExternalChanges { path: _, count } => {
+                stdout!("  {} {} files in the managed folder of {} were externally modified", style("✗").yellow(), count, path);
+                if verbose {
+                    stdout!("      (if these changes are intended, stage them manually using \"git add\")");
+                }
+            },
+            Finding::ManifestMismatch { path: _, count } => {
+                stdout!("  {} {} staged records of {} do not match the record manifest", style("✗").red(), count, path);
+                if verbose {
+                    stdout!("      (the records were probably changed without git-toolbox)");
+                }
+            },
+            Finding::PlaceholderDriftInIndex { path: _ } => {
+                stdout!("  {} the contents of {} were staged directly", style("✗").red(), path);
+                if verbose {
+                    stdout!("      (the git filter was probably not configured when the file was added)");
+                    stdout!("      (repairing will restore the placeholder and split the staged contents)");
+                }
+            },
+            Finding::PlaceholderDriftInHead { path: _ } => {
+                stdout!("  {} the contents of {} were commited directly", style("✗").red(), path);
+                if verbose {
+                    stdout!("      (this will be corrected by the next commit once the index is repaired)");
+                }
+            },
+            Finding::StalePlaceholder { path: _ } => {
+                stdout!("  {} the placeholder text of {} is outdated", style("✗").yellow(), path);
+            },
+            Finding::UnmarkedPlaceholder { path: _ } => {
+                stdout!("  {} the index entry of {} is no longer marked as assume-unchanged", style("✗").yellow(), path);
+                if verbose {
+                    stdout!("      (git compares the placeholder to the file, so it is listed as modified and \"git add\" fails)");
+                    stdout!("      (repairing will mark the entry again)");
+                }
+            },
+            Finding::StaleIndexLock { age } => {
+                stdout!("  {} the git index is locked by {} left behind by a crashed git process", 
+                    style("✗").red(), 
+                    path
+                );
+                if verbose {
+                    stdout!("      (the lock is {} minutes old and no git process is running)", age/60);
+                    stdout!("      (repairing will remove the lock)");
+                }
+            }
+        }
+
+        stdout!("      (fix: {})", style(self.fix()).bold());
+    }
+
+    fn repair(&self, repo: &mut Repository) -> Result<()> {
+        match self {
+            Finding::PlaceholderDriftInIndex { path } => repair_placeholder_drift(repo, path),
+            Finding::UnmarkedPlaceholder { path }     => repair_unmarked_placeholder(repo, path),
+            Finding::StaleIndexLock { age: _ }        => repair_stale_index_lock(repo),
+            _                                         => Ok( () )
+        }
+    }
+}
+
+
+// Split the staged dictionary contents into the managed folder and stage the placeholder
+fn repair_placeholder_drift(repo: &mut Repository, path: &str) -> Result<()> {
+    use crate::repository::DiffStats;
+
+    let cfg = repo.config().dictionary_by_path(path)?.clone();
+    let placeholder = repo.config().managed_file_text(&cfg);
+
+    // the contents that were staged
+    let text = repo.read_text_from_index(&cfg.path).ok_or_else(|| {
+        error::GitObjNotFound {
+            path : cfg.path.clone(),
+            rev  : "the index".to_owned()
+        }
+    })?;
+
+    let dictionary = Dictionary::from_text(&cfg, text);
+    let contents_path = dictionary.contents_root();
+
+    // the managed folder will be updated, so we make sure not to overwrite anything
+    let issues = repo.validate_clobs_in_workdir(&contents_path, false)?;
+    let modified = issues.iter().find(|issue| {
+        !matches!(issue, ClobValidationIssue::InvalidPath { path: _ })
+    });
+    if let Some(issue) = modified {
+        bail!(
+            error::ExternalModificationsWillBeLost {
+                path : issue.path().into()
+            }
+        );
+    }
+
+    // split the contents and diff them against the managed folder
+    let (clobs, _) = dictionary.split();
+    let diff = repo.diff_clobs_at_path(&contents_path, clobs)?;
+    let stats = DiffStats::count(&diff);
+
+    // update the index
+    let mut staging_area = repo.get_staging_area()?;
+    staging_area.stage_diffs(diff.iter(), |_| {})?;
+    staging_area.stage_managed_file(&cfg.path, &placeholder)?;
+    staging_area.commit()?;
+    repo.update_record_manifest(&contents_path, &cfg.record_tags)?;
+
+    let tree = repo.staged_tree_id(&contents_path)?;
+    repo.record_audit_entry(
+        "doctor",
+        &cfg.path,
+        tree,
+        &format!("repair=placeholder-drift added={} modified={} deleted={}",
+            stats.added, stats.changed, stats.deleted
+        )
+    )?;
+
+    stdout!("{} Restored the placeholder for {} and split its staged contents into {}",
+        style("✓").green(),
+        style(&cfg.path).italic(),
+        style(&contents_path).italic()
+    );
+    stdout!("    ({} added, {} modified, {} deleted)", stats.added, stats.changed, stats.deleted);
+
+    Ok( () )
+}
+
+// Mark the placeholder entry as assume-unchanged again
+fn repair_unmarked_placeholder(repo: &mut Repository, path: &str) -> Result<()> {
+    repo.mark_managed_files([path])?;
+
+    stdout!("{} Marked the index entry of {} as assume-unchanged", style("✓").green(), style(path).italic());
+
+    Ok( () )
+}
+
+// Remove the stale git index lock
+fn repair_stale_index_lock(repo: &Repository) -> Result<()> {
+    if repo.remove_stale_index_lock()? {
+        stdout!("{} Removed the stale git index lock", style("✓").green());
+    }
+
+    Ok( () )
+}
@@ -0,0 +1,219 @@
+//
+// src/edit_field.rs
+//
+// Implementation of git-toolbox edit-field
+//
+// Replaces the values of a field across the records of a managed toolbox file
+// (optionally only in the records matching a query). The changes are previewed
+// and written to the working copy of the file only, so that they can be reviewed
+// with git toolbox status/diff before they are staged.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::repository::Repository;
+use crate::toolbox::Dictionary;
+use crate::toolbox::query::Query;
+use crate::cli_app::style;
+use crate::util::truncate_text;
+
+use crate::error;
+use anyhow::Result;
+use regex::Regex;
+use std::collections::{BTreeMap, HashSet};
+use itertools::Itertools;
+
+
+/// How the field values are replaced
+enum Replacement {
+    /// The whole value is replaced if it is equal to the given one
+    Value { old: String, new: String },
+    /// All the matches of the regular expression are replaced
+    Regex { regex: Regex, new: String }
+}
+
+impl Replacement {
+    fn apply(&self, value: &str) -> Option<String> {
+        match self {
+            Replacement::Value { old, new } => {
+                if value == old { Some(new.clone()) } else { None }
+            },
+            Replacement::Regex { regex, new } => {
+                let replaced = regex.replace_all(value, new.as_str());
+                if replaced != value { Some(replaced.into_owned()) } else { None }
+            }
+        }
+    }
+}
+
+
+pub fn edit_field(
+    path: String,
+    tag: String,
+    old: String,
+    new: String,
+    regex: bool,
+    filter: Option<String>,
+    dry_run: bool
+) -> Result<()> {
+    // the marker can be given with or without the backslash
+    let marker = if tag.starts_with('\\') { tag } else { format!("\\{}", tag) };
+
+    // the replacement
+    let replacement = if regex {
+        let regex = Regex::new(&old).map_err(|err| {
+            let msg = err.to_string();
+
+            error::InvalidPattern {
+                pattern : old.clone(),
+                msg     : msg.lines().last().unwrap_or("").trim_start_matches("error: ").to_owned()
+            }
+        })?;
+
+        Replacement::Regex { regex, new }
+    } else {
+        Replacement::Value { old, new }
+    };
+
+    // the record filter
+    let filter = filter.map(|text| {
+        Query::parse(&text).map_err(|err| {
+            error::InvalidQuery {
+                query  : text.clone(),
+                offset : err.offset,
+                msg    : err.msg
+            }
+        })
+    })
+    .transpose()?;
+
+    // load the repository
+    let repo = Repository::open()?;
+
+    // no other git-toolbox operations while we are modifying the repository
+    let _lock = repo.lock_operation("edit-field")?;
+
+    // locate the dictionary
+    let repo_path = repo.get_path_relative_to_repo(&path)?.to_string_lossy().into_owned();
+    let cfg = repo.config().dictionary_by_path(&repo_path)?;
+
+    let file_path = repo.workdir()?.join(&cfg.path);
+    let data = std::fs::read(&file_path).map_err(|err| {
+        error::FileReadError {
+            path : file_path.clone(),
+            msg  : err.to_string()
+        }
+    })?;
+    let text = cfg.decode_text(&file_path, data)?;
+
+    // collect the edited fields (first line number -> (first record line, old lines, new text))
+    // and the continuation lines that are joined into the edited fields
+    let mut edits = BTreeMap::new();
+    let mut joined = HashSet::new();
+
+    let dictionary = Dictionary::from_text(cfg, text.clone());
+    let records = dictionary.records();
+    for record in records.iter().filter(|record| filter.as_ref().map(|query| query.matches(record)).unwrap_or(true)) {
+        let lines = record.text.lines().collect::<Vec<_>>();
+
+        for (i, line) in lines.iter().enumerate() {
+            // the field value (and the whitespace around it)
+            let rest = match line.strip_prefix(marker.as_str()) {
+                Some(rest) if rest.is_empty() || rest.starts_with(char::is_whitespace) => rest,
+                _ => continue
+            };
+
+            // the continuation lines of the field (up to the next marker or blank line)
+            let continued = if cfg.continuation_lines {
+                lines[i + 1 ..].iter().take_while(|line| !line.starts_with('\\') && !line.trim().is_empty()).count()
+            } else {
+                0
+            };
+
+            // the value is joined from its lines (as in the records matched by the filter)
+            let value = std::iter::once(rest).chain(lines[i + 1 .. i + 1 + continued].iter().copied())
+                .map(str::trim)
+                .filter(|part| !part.is_empty())
+                .join(" ");
+
+            if let Some(replaced) = replacement.apply(&value) {
+                let separator = &rest[.. rest.len() - rest.trim_start().len()];
+                let separator = if separator.is_empty() { " " } else { separator };
+
+                edits.insert(
+                    record.line.line + i,
+                    (
+                        record.line.clone(),
+                        lines[i ..= i + continued].iter().map(|line| line.trim_end()).collect::<Vec<_>>(),
+                        format!("{}{}{}", marker, separator, replaced)
+                    )
+                );
+                joined.extend(record.line.line + i + 1 ..= record.line.line + i + continued);
+            }
+        }
+    }
+
+    if edits.is_empty() {
+        stdout!("No {} fields to change in {}", style(&marker).bold(), style(&cfg.path).italic());
+        return Ok( () );
+    }
+
+    // preview the changes
+    let mut last_record = None;
+    for (line, (record, old, new)) in edits.iter() {
+        if last_record != Some(record.line) {
+            stdout!("");
+            stdout!("  {}", style(truncate_text(record.text.trim(), 60)).bold());
+            last_record = Some(record.line);
+        }
+        for (i, old) in old.iter().enumerate() {
+            let location = if i == 0 { format!("line:{:<8}", line + 1) } else { " ".repeat(13) };
+            stdout!("    {} {} {}", style(location).yellow(), style("-").red(), style(old).red());
+        }
+        stdout!("    {} {} {}", " ".repeat(13), style("+").green(), style(new).green());
+    }
+    stdout!("");
+
+    let records = edits.values().map(|(record, _, _)| record.line).unique().count();
+
+    if dry_run {
+        stdout!("{} fields in {} records would be changed (nothing was written)", edits.len(), records);
+        return Ok( () );
+    }
+
+    // rebuild the text, keeping the line endings (the joined continuation lines are dropped)
+    let text = text.split_inclusive('\n').enumerate().map(|(i, line)| {
+        match edits.get(&i) {
+            Some((_, _, new)) => {
+                let content = line.trim_end_matches(['\r', '\n']);
+                format!("{}{}", new, &line[content.len() ..])
+            },
+            None if joined.contains(&i) => String::new(),
+            None => line.to_owned()
+        }
+    })
+    .collect::<String>();
+
+    // the file is written in its encoding
+    let data = cfg.encode_text(text.into_bytes())?;
+    std::fs::write(&file_path, data).map_err(|err| {
+        error::FileWriteError {
+            path : file_path.clone(),
+            msg  : err.to_string()
+        }
+    })?;
+
+    stdout!("{} Changed {} fields in {} records of {}",
+        style("✓").green(),
+        edits.len(),
+        records,
+        style(&cfg.path).italic()
+    );
+    stdout!("  (the changes are not staged, use {} to review them and {} to stage them)",
+        style("git toolbox diff").bold(),
+        style("git toolbox stage").bold()
+    );
+
+    Ok( () )
+}
@@ -79,6 +79,75 @@ define_error!(
     }
 );
 
+define_error!(
+    TooManyRecordsDeleted {
+        pub path    : PathBuf,
+        pub deleted : usize,
+        pub total   : usize,
+        pub limit   : f64
+    }
+    @display(self) {
+        (@err "{deleted} of {total} records ({percentage}) of {path} would be deleted"
+            [
+                deleted    = style::value(self.deleted),
+                total      = self.total,
+                percentage = style::value(format!("{:.1}%", self.deleted as f64 * 100.0 / self.total.max(1) as f64)),
+                path       = style::path(&self.path.display())
+            ]
+        )
+        (@div "This is more than {limit} of the records (max-deleted-records), the file might be truncated or damaged"
+            [
+                limit = format!("{}%", self.limit)
+            ]
+        )
+    }
+);
+
+define_error!(
+    RecordsOutsideNamespace {
+        pub user      : String,
+        pub namespace : Option<String>,
+        pub path      : PathBuf,
+        pub records   : Vec<String>
+    }
+    @display(self) {
+        (@err "{user} may only stage the records in {namespace}, but {count} staged records of {path} are outside of it:"
+            [
+                user      = style::value(&self.user),
+                namespace = match &self.namespace {
+                    Some(namespace) => format!("the namespace {}", style::value(namespace)),
+                    None            => "no namespace".to_owned()
+                },
+                count     = self.records.len(),
+                path      = style::path(&self.path.display())
+            ]
+        )
+        (@div "{records}"
+            [
+                records = self.records.iter().map(|record| format!("        {}", record)).collect::<Vec<_>>().join("\n")
+            ]
+        )
+    }
+);
+
+define_error!(
+    QuarantineConflict {
+        pub path: String
+    }
+    @display(self) {
+        (@err "{path} was modified both in the working directory and via the managed file"
+            [
+                path = style::path(&self.path)
+            ]
+        )
+        (@div "Please stage or reset the managed file before running {cmd}" 
+            [
+                cmd = style::command("git toolbox reingest")
+            ]
+        )
+    }
+);
+
 define_error!(
     NotAManagedFile {
         pub path: PathBuf
@@ -137,6 +206,35 @@ define_error!(
     }
 );
 
+define_error!(
+    NoMergeBase {
+        pub rev_a : String,
+        pub rev_b : String
+    }
+    @display(self) {
+        (@err "{rev_a} and {rev_b} do not have a common ancestor" 
+            [
+                rev_a = style::value(&self.rev_a),
+                rev_b = style::value(&self.rev_b)
+            ]
+        )
+    }
+);
+
+define_error!(
+    AmbiguousRevision {
+        pub pathspec : String
+    }
+    @display(self) {
+        (@err "{pathspec} already specifies a git revision and cannot be used with {opt}"
+            [
+                pathspec = style::value(&self.pathspec),
+                opt      = style::command("--merge-base")
+            ]
+        )
+    }
+);
+
 define_error!(
     InvalidPathSpec {
         pub pathspec: String
@@ -169,44 +267,68 @@ impl From<git2::Error> for OtherGitError {
 }
 
 define_error!(
-    FileWriteError {
-        pub path : PathBuf,
+    SigningFailed {
         pub msg  : String,
     }
     @display(self) {
-        (@err "unable to write {path} {msg}" 
+        (@err "unable to sign the staging manifest {msg}" 
             [
-                path = style::path(get_relative_path(&self.path).display()),
                 msg  = style::comment(&self.msg)
             ]
         )
+        (@div "Please check the {key} and {format} settings in your git configuration"
+            [
+                key    = style::value("user.signingkey"),
+                format = style::value("gpg.format")
+            ]
+        )
     }
 );
 
 define_error!(
-    FileReadError {
-        pub path : PathBuf,
+    LfsFetchFailed {
+        pub path : String,
         pub msg  : String,
     }
     @display(self) {
-        (@err "unable to read {path} {msg}" 
+        (@err "unable to fetch the Git LFS content of {path} {msg}" 
             [
-                path = style::path(get_relative_path(&self.path).display()),
+                path = style::path(&self.path),
                 msg  = style::comment(&self.msg)
             ]
         )
+        (@div "Please make sure that Git LFS is installed and run {cmd} manually"
+            [
+                cmd = style::command(format!("git lfs pull --include {}", &self.path))
+            ]
+        )
     }
 );
 
 define_error!(
-    FileDeleteError {
+    SnapshotPushFailed {
+        pub remote : String,
+        pub msg    : String,
+    }
+    @display(self) {
+        (@err "unable to push the snapshot to {remote} {msg}" 
+            [
+                remote = style::value(&self.remote),
+                msg    = style::comment(&self.msg)
+            ]
+        )
+    }
+);
+
+define_error!(
+    ArchiveFailed {
         pub path : PathBuf,
         pub msg  : String,
     }
     @display(self) {
-        (@err "unable to delete {path} {msg}" 
+        (@err "unable to write the archive {path} {msg}" 
             [
-                path = style::path(get_relative_path(&self.path).display()),
+                path = style::path(&self.path.display()),
                 msg  = style::comment(&self.msg)
             ]
         )
@@ -214,47 +336,870 @@ define_error!(
 );
 
 define_error!(
-    FileNotFound {
-        pub path : PathBuf,
+    NoSnapshotRemote
+    @display(self) {
+        (@err "no remote to push the snapshots to")
+        (@div "Please specify the remote or set it permanently with {cmd}" 
+            [
+                cmd = style::command("git config toolbox.snapshotRemote <remote>")
+            ])
     }
+);
+
+define_error!(
+    NoStashEntries
     @display(self) {
-        (@err "{path} not found" 
+        (@err "there are no stashed changes")
+        (@div "Use {cmd} to stash the changes to the managed toolbox files" 
             [
-                path = style::path(get_relative_path(&self.path).display())
+                cmd = style::command("git toolbox stash push")
+            ])
+    }
+);
+
+define_error!(
+    StashConflicts {
+        pub path      : String,
+        pub conflicts : usize
+    }
+    @display(self) {
+        (@err "{conflicts} conflicting records in {path}" 
+            [
+                conflicts = self.conflicts,
+                path      = style::path(&self.path)
+            ]
+        )
+        (@div "The stashed records were also changed since they were stashed. Resolve the conflict markers in the file (e.g. with {cmd}), then use {drop} to remove the changes from the stash"
+            [
+                cmd  = style::command("git toolbox resolve"),
+                drop = style::command("git toolbox stash drop")
             ]
         )
     }
 );
 
 define_error!(
-    ToolboxDictionaryMissingHeader {
-        pub path : PathBuf,
-        pub text : &'static str,
-        pub line : usize
+    StashWouldOverwrite {
+        pub path : String
     }
     @display(self) {
-        (@err "toolbox dictinary header missing or invalid in {path}"
+        (@err "{path} has unstaged changes that would be overwritten by the stashed changes"
+            [
+                path = style::path(&self.path)
+            ]
+        )
+        (@div "Please stage, stash or reset the changes first")
+    }
+);
+
+define_error!(
+    InvalidInterval {
+        pub interval : String
+    }
+    @display(self) {
+        (@err "invalid interval {interval}" 
+            [
+                interval = style::value(&self.interval)
+            ]
+        )
+        (@div "Please give the interval as a number followed by s, m, h or d (e.g. 30m)")
+    }
+);
+
+define_error!(
+    AutostageStartFailed {
+        pub msg : String
+    }
+    @display(self) {
+        (@err "unable to start git toolbox autostage in the background {msg}" 
+            [
+                msg = style::comment(&self.msg)
+            ]
+        )
+    }
+);
+
+define_error!(
+    DictionaryWithoutIds {
+        pub path : String
+    }
+    @display(self) {
+        (@err "{path} does not use unique record IDs" 
+            [
+                path = style::path(&self.path)
+            ]
+        )
+    }
+);
+
+define_error!(
+    NoConflictingRecord {
+        pub id : String
+    }
+    @display(self) {
+        (@err "no conflicting record {id} in the managed folders" 
+            [
+                id = style::value(&self.id)
+            ]
+        )
+        (@div "Use \"git toolbox conflicts\" to list the conflicting records")
+    }
+);
+
+define_error!(
+    UninstallWithUnstagedChanges {
+        pub path : String
+    }
+    @display(self) {
+        (@err "managed file {path} has unstaged changes" 
+            [
+                path = style::path(&self.path)
+            ]
+        )
+        (@div "Use {stage} to keep them or {reset} to discard them before removing git-toolbox"
+            [
+                stage = style::command(format!("git toolbox stage {}", &self.path)),
+                reset = style::command(format!("git toolbox reset --force {}", &self.path))
+            ]
+        )
+    }
+);
+
+define_error!(
+    PathPatternDictionary {
+        pub path : String
+    }
+    @display(self) {
+        (@err "managed file {path} is configured by a path pattern" 
+            [
+                path = style::path(&self.path)
+            ]
+        )
+        (@div "Please configure it explicitly in {config} first" 
+            [
+                config = crate::config::CONFIG_FILE
+            ]
+        )
+    }
+);
+
+define_error!(
+    MoveTargetExists {
+        pub path : String
+    }
+    @display(self) {
+        (@err "{path} already exists" 
+            [
+                path = style::path(&self.path)
+            ]
+        )
+    }
+);
+
+define_error!(
+    InvalidRecordId {
+        pub id   : String,
+        pub path : String
+    }
+    @display(self) {
+        (@err "{id} is not a valid record ID for {path}" 
+            [
+                id   = style::value(&self.id),
+                path = style::path(&self.path)
+            ]
+        )
+        (@div "Please check the ID specification in the configuration file")
+    }
+);
+
+define_error!(
+    OperationInProgress {
+        pub operation : String,
+        pub pid       : u32,
+        pub path      : PathBuf
+    }
+    @display(self) {
+        (@err "another git-toolbox operation is running ({cmd} in process {pid})" 
+            [
+                cmd = style::command(format!("git toolbox {}", &self.operation)),
+                pid = style::value(self.pid)
+            ]
+        )
+        (@div "Please wait for it to finish and try again (or use {wait}). If no other git-toolbox command is running, remove {path}" 
             [
+                wait = style::command("git toolbox --wait ..."),
                 path = style::path(get_relative_path(&self.path).display())
-            ] 
+            ]
         )
-        (@div "{body}" 
+    }
+);
+
+define_error!(
+    InterruptedOperation {
+        pub operation : String
+    }
+    @display(self) {
+        (@err "an earlier {operation} was interrupted and might have left the managed files in an inconsistent state" 
             [
-                body={
-                    use crate::listing_formatter::ListingFormatter;
+                operation = style::command(format!("git toolbox {}", &self.operation))
+            ]
+        )
+        (@div "Use {resume} to complete it or {rollback} to undo it" 
+            [
+                resume   = style::command("git toolbox recover --resume"),
+                rollback = style::command("git toolbox recover --rollback")
+            ]
+        )
+    }
+);
 
-                    let style = console::Style::new().italic().yellow();
-                    let path  = get_relative_path(&self.path);
+define_error!(
+    NoInterruptedOperation
+    @display(self) {
+        (@err "there is no interrupted operation to recover from")
+    }
+);
 
-                    // setup the listing
-                    let mut listing = ListingFormatter::new_with_issue(
-                        self.text, self.line+1, 0, "expected '\\_sh v3.0  ...  Dictionary' here"
-                    );
-                    listing.set_label(style.apply_to(path.display()).to_string());
+define_error!(
+    FilterProtocolError {
+        pub msg : String
+    }
+    @display(self) {
+        (@err "git filter protocol error {msg}" 
+            [
+                msg = style::comment(&self.msg)
+            ]
+        )
+    }
+);
 
-                    // write the error message
-                    format!("{:80}", listing)
-                }
+define_error!(
+    IndexLocked
+    @display(self) {
+        (@err "the git index is locked by another git process")
+        (@div "Please wait for it to finish and try again. If no git command is running, use {cmd} to remove the lock" 
+            [
+                cmd = style::command("git toolbox doctor --fix")
+            ]
+        )
+    }
+);
+
+define_error!(
+    FileWriteError {
+        pub path : PathBuf,
+        pub msg  : String,
+    }
+    @display(self) {
+        (@err "unable to write {path} {msg}" 
+            [
+                path = style::path(get_relative_path(&self.path).display()),
+                msg  = style::comment(&self.msg)
+            ]
+        )
+    }
+);
+
+define_error!(
+    ReconstructionWriteError {
+        pub msg  : String,
+    }
+    @display(self) {
+        (@err "unable to write the reconstructed contents {msg}" 
+            [
+                msg  = style::comment(&self.msg)
+            ]
+        )
+    }
+);
+
+define_error!(
+    FileReadError {
+        pub path : PathBuf,
+        pub msg  : String,
+    }
+    @display(self) {
+        (@err "unable to read {path} {msg}" 
+            [
+                path = style::path(get_relative_path(&self.path).display()),
+                msg  = style::comment(&self.msg)
+            ]
+        )
+    }
+);
+
+define_error!(
+    UnsafeClobPath {
+        pub path   : String,
+        pub reason : String,
+    }
+    @display(self) {
+        (@err "refusing to write {path} {reason}" 
+            [
+                path   = style::path(&self.path),
+                reason = style::comment(&self.reason)
+            ]
+        )
+    }
+);
+
+define_error!(
+    InvalidTypeFile {
+        pub path : PathBuf,
+        pub msg  : String,
+    }
+    @display(self) {
+        (@err "{path} is not a valid Toolbox type file {msg}" 
+            [
+                path = style::path(get_relative_path(&self.path).display()),
+                msg  = style::comment(&self.msg)
+            ]
+        )
+    }
+);
+
+define_error!(
+    MissingRecordTag {
+        pub name : String,
+    }
+    @display(self) {
+        (@err "no record tag for the dictionary {name}" 
+            [
+                name = style::path(&self.name)
+            ]
+        )
+        (@div "Please set record-tag or a type file (typ) that declares the record marker")
+    }
+);
+
+define_error!(
+    MissingFieldOrder {
+        pub name : String,
+    }
+    @display(self) {
+        (@err "no field order for the dictionary {name}" 
+            [
+                name = style::path(&self.name)
+            ]
+        )
+        (@div "Please set field-order or a type file (typ) when normalize-field-order is enabled")
+    }
+);
+
+define_error!(
+    UnknownValidatorDictionary {
+        pub validator : String,
+        pub name      : String,
+    }
+    @display(self) {
+        (@err "the validator {validator} checks the unknown dictionary {name}" 
+            [
+                validator = style::value(&self.validator),
+                name      = style::path(&self.name)
+            ]
+        )
+        (@div "Please list the names of configured dictionaries under dictionaries")
+    }
+);
+
+define_error!(
+    UnknownIssueKind {
+        pub kind : String,
+    }
+    @display(self) {
+        (@err "unknown issue kind {kind} in issue-severity" 
+            [
+                kind = style::value(&self.kind)
+            ]
+        )
+        (@div "The issue kinds are listed in the porcelain output of git toolbox status (e.g. ambiguous-id)")
+    }
+);
+
+define_error!(
+    IssuesPreventStaging {
+        pub count : usize
+    }
+    @display(self) {
+        (@err "{count} issues with the severity error prevent staging in the strict mode" 
+            [
+                count = self.count
+            ]
+        )
+        (@div "Please fix the issues listed above or change their severity in issue-severity")
+    }
+);
+
+define_error!(
+    UnacknowledgedIssues {
+        pub count : usize
+    }
+    @display(self) {
+        (@err "{count} issues are not acknowledged in {file}" 
+            [
+                count = self.count,
+                file  = style::path(crate::repository::ISSUE_BASELINE_FILE)
+            ]
+        )
+        (@div "Please fix the issues listed above or acknowledge them with git toolbox check --update-baseline")
+    }
+);
+
+define_error!(
+    UnverifiedRecordChanges {
+        pub count : usize
+    }
+    @display(self) {
+        (@err "{count} record changes were not made by {cmd}" 
+            [
+                count = self.count,
+                cmd   = style::command("git toolbox stage")
+            ]
+        )
+        (@div "Please check the changes listed above, they might have been made by editing the managed folders directly")
+    }
+);
+
+define_error!(
+    InvalidPattern {
+        pub pattern : String,
+        pub msg     : String
+    }
+    @display(self) {
+        (@err "invalid regular expression {pattern}: {msg}" 
+            [
+                pattern = style::value(&self.pattern),
+                msg     = self.msg
+            ]
+        )
+    }
+);
+
+define_error!(
+    InvalidMarker {
+        pub marker : String
+    }
+    @display(self) {
+        (@err "invalid field marker {marker}" 
+            [
+                marker = style::value(format!("\\{}", self.marker))
+            ]
+        )
+    }
+);
+
+define_error!(
+    MarkerAlreadyConfigured {
+        pub marker : String,
+        pub path   : String
+    }
+    @display(self) {
+        (@err "the marker {marker} is already configured for {path}" 
+            [
+                marker = style::value(&self.marker),
+                path   = style::path(&self.path)
+            ]
+        )
+        (@div "Renaming a marker to one used by the template, the record tag or the ID tag would merge their settings")
+    }
+);
+
+define_error!(
+    PlatformDependentContents {
+        pub count : usize
+    }
+    @display(self) {
+        (@err "{count} problems would make the managed folders differ between platforms" 
+            [
+                count = self.count
+            ]
+        )
+        (@div "Please check the problems listed above before committing the managed folders")
+    }
+);
+
+define_error!(
+    ReconcileConflicts {
+        pub path      : String,
+        pub conflicts : usize
+    }
+    @display(self) {
+        (@err "{conflicts} conflicting records in {path}" 
+            [
+                conflicts = self.conflicts,
+                path      = style::path(&self.path)
+            ]
+        )
+        (@div "The records were changed differently in the working copy and upstream. Resolve the conflict markers in the file (e.g. with \"git toolbox resolve\") before staging it")
+    }
+);
+
+define_error!(
+    InteractiveTerminalRequired {
+        pub command : String
+    }
+    @display(self) {
+        (@err "{command} requires an interactive terminal" 
+            [
+                command = style::command(&self.command)
+            ]
+        )
+        (@div "The conflict markers can also be resolved in a text editor")
+    }
+);
+
+define_error!(
+    RecordsAlteredByRoundTrip {
+        pub count : usize
+    }
+    @display(self) {
+        (@err "{count} records would be altered by splitting and reassembling the managed files" 
+            [
+                count = self.count
+            ]
+        )
+        (@div "Please check the records listed above before staging the changes")
+    }
+);
+
+define_error!(
+    InvalidQuery {
+        pub query  : String,
+        pub offset : usize,
+        pub msg    : String
+    }
+    @display(self) {
+        (@err "invalid query: {msg}" 
+            [
+                msg = self.msg
+            ]
+        )
+        (@div "{query}\n{marker}" 
+            [
+                query  = style::value(&self.query),
+                marker = format!("{:width$}^", "", width = self.offset)
+            ]
+        )
+    }
+);
+
+define_error!(
+    InvalidEncoding {
+        pub path     : PathBuf,
+        pub encoding : &'static str,
+        pub offset   : usize
+    }
+    @display(self) {
+        (@err "unable to read {path} (not valid {encoding} text at byte {offset})" 
+            [
+                path     = style::path(get_relative_path(&self.path).display()),
+                encoding = self.encoding,
+                offset   = self.offset
+            ]
+        )
+        (@div "If the file uses a legacy encoding, set the {key} of the dictionary in {config}" 
+            [
+                key    = style::value("encoding"),
+                config = style::path(crate::config::CONFIG_FILE)
+            ]
+        )
+    }
+);
+
+define_error!(
+    UnencodableCharacter {
+        pub path      : PathBuf,
+        pub encoding  : &'static str,
+        pub character : char
+    }
+    @display(self) {
+        (@err "the character {character} ({code}) cannot be written to {path} in the {encoding} encoding" 
+            [
+                character = style::value(self.character),
+                code      = format!("U+{:04X}", self.character as u32),
+                path      = style::path(get_relative_path(&self.path).display()),
+                encoding  = self.encoding
+            ]
+        )
+        (@div "To use characters outside of this encoding, change the {key} of the dictionary in {config}" 
+            [
+                key    = style::value("encoding"),
+                config = style::path(crate::config::CONFIG_FILE)
+            ]
+        )
+    }
+);
+
+define_error!(
+    FileDeleteError {
+        pub path : PathBuf,
+        pub msg  : String,
+    }
+    @display(self) {
+        (@err "unable to delete {path} {msg}" 
+            [
+                path = style::path(get_relative_path(&self.path).display()),
+                msg  = style::comment(&self.msg)
+            ]
+        )
+    }
+);
+
+define_error!(
+    AdoptTargetHasContents {
+        pub path : String
+    }
+    @display(self) {
+        (@err "{path} already has contents, only a new managed file can adopt records" 
+            [
+                path = style::path(&self.path)
+            ]
+        )
+    }
+);
+
+define_error!(
+    FileNotTracked {
+        pub path : String
+    }
+    @display(self) {
+        (@err "{path} is not tracked by git" 
+            [
+                path = style::path(&self.path)
+            ]
+        )
+        (@div "Use {cmd} to add it first" 
+            [
+                cmd = style::command(format!("git add {}", &self.path))
+            ]
+        )
+    }
+);
+
+define_error!(
+    AlreadyManagedFile {
+        pub path : String
+    }
+    @display(self) {
+        (@err "{path} is already a managed file" 
+            [
+                path = style::path(&self.path)
+            ]
+        )
+    }
+);
+
+define_error!(
+    NoRecordsToAdopt {
+        pub path : PathBuf
+    }
+    @display(self) {
+        (@err "no records found in {path}" 
+            [
+                path = style::path(get_relative_path(&self.path).display())
+            ]
+        )
+    }
+);
+
+define_error!(
+    FileNotFound {
+        pub path : PathBuf,
+    }
+    @display(self) {
+        (@err "{path} not found" 
+            [
+                path = style::path(get_relative_path(&self.path).display())
+            ]
+        )
+    }
+);
+
+define_error!(
+    CommandFailedInRepositories {
+        pub failed : usize,
+        pub total  : usize
+    }
+    @display(self) {
+        (@err "the command failed in {failed} of {total} repositories" 
+            [
+                failed = &self.failed,
+                total  = &self.total
+            ]
+        )
+        (@div "Please check the errors reported for these repositories above")
+    }
+);
+
+define_error!(
+    RecordNotFound {
+        pub name : String
+    }
+    @display(self) {
+        (@err "no record with the ID or label {name} in the managed toolbox files" 
+            [
+                name = style::value(&self.name)
+            ]
+        )
+    }
+);
+
+define_error!(
+    InvalidImportFile {
+        pub path : String,
+        pub line : usize,
+        pub msg  : String
+    }
+    @display(self) {
+        (@err "unable to import {path} (line {line}) {msg}"
+            [
+                path = style::path(&self.path),
+                line = self.line,
+                msg  = style::comment(&self.msg)
+            ]
+        )
+    }
+);
+
+define_error!(
+    UncommittedRecord {
+        pub id   : String,
+        pub path : String
+    }
+    @display(self) {
+        (@err "the record {id} of {path} has not been committed"
+            [
+                id   = style::value(&self.id),
+                path = style::path(&self.path)
+            ]
+        )
+        (@div "Only the committed records can be blamed, please stage and commit the record first")
+    }
+);
+
+define_error!(
+    PickConflict {
+        pub id   : String,
+        pub path : String,
+        pub rev  : String
+    }
+    @display(self) {
+        (@err "the record {id} of {path} was also changed locally"
+            [
+                id   = style::value(&self.id),
+                path = style::path(&self.path)
+            ]
+        )
+        (@div "Picking the record from {rev} would discard the local changes listed above, use {cmd} to pick it anyway"
+            [
+                rev = style::value(&self.rev),
+                cmd = style::command("git toolbox pick --force")
+            ]
+        )
+    }
+);
+
+define_error!(
+    UnsupportedRecordSelection {
+        pub path   : String,
+        pub option : String
+    }
+    @display(self) {
+        (@err "the records of {path} cannot be selected with {option}" 
+            [
+                path   = style::path(&self.path),
+                option = style::command(&self.option)
+            ]
+        )
+        (@div "Use --record for dictionaries with unique IDs and --label for the other dictionaries")
+    }
+);
+
+define_error!(
+    EditorFailed {
+        pub editor : String,
+        pub msg    : String
+    }
+    @display(self) {
+        (@err "unable to run the editor {editor} {msg}" 
+            [
+                editor = style::command(&self.editor),
+                msg    = style::comment(&self.msg)
+            ]
+        )
+        (@div "Please set the editor with \"git config toolbox.editor <command>\" or the EDITOR variable")
+    }
+);
+
+define_error!(
+    ToolboxDictionaryMissingHeader {
+        pub path : PathBuf,
+        pub text : String,
+        pub line : usize
+    }
+    @display(self) {
+        (@err "toolbox dictinary header missing or invalid in {path}"
+            [
+                path = style::path(get_relative_path(&self.path).display())
+            ] 
+        )
+        (@div "{body}" 
+            [
+                body={
+                    use crate::listing_formatter::ListingFormatter;
+
+                    let style = console::Style::new().italic().yellow();
+                    let path  = get_relative_path(&self.path);
+
+                    // setup the listing
+                    let mut listing = ListingFormatter::new_with_issue(
+                        &self.text, self.line+1, 0, "expected '\\_sh v3.0  ...  Dictionary' here"
+                    );
+                    listing.set_label(style.apply_to(path.display()).to_string());
+
+                    // write the error message
+                    format!("{:80}", listing)
+                }
+            ]
+        )
+    }       
+);
+
+define_error!(
+    ToolboxUnexpectedDatabaseType {
+        pub path     : PathBuf,
+        pub text     : String,
+        pub line     : usize,
+        pub expected : String
+    }
+    @display(self) {
+        (@err "toolbox header in {path} does not declare the database type {expected}"
+            [
+                path     = style::path(get_relative_path(&self.path).display()),
+                expected = style::value(&self.expected)
+            ] 
+        )
+        (@div "{body}" 
+            [
+                body={
+                    use crate::listing_formatter::ListingFormatter;
+
+                    let style = console::Style::new().italic().yellow();
+                    let path  = get_relative_path(&self.path);
+                    let issue = format!("expected '\\_sh v3.0  ...  {}' here", self.expected);
+
+                    // setup the listing
+                    let mut listing = ListingFormatter::new_with_issue(&self.text, self.line+1, 0, &issue);
+                    listing.set_label(style.apply_to(path.display()).to_string());
+
+                    // write the error message
+                    format!("{:80}", listing)
+                }
+            ]
+        )
+        (@div "Please fix the header or change {key} in the configuration" 
+            [
+                key = style::value("database-type")
             ]
         )
     }       
@@ -0,0 +1,217 @@
+//
+// src/export.rs
+//
+// Implementation of git-toolbox export
+//
+// Writes the records of a managed toolbox file as a table (CSV or TSV) with one
+// row per record and one column per selected field, so that the dictionary can
+// be opened in a spreadsheet, or as JSON objects with the full record structure
+// (e.g. for web dictionaries). The records are read from the working copy or
+// reconstructed from a revision.
+//
+// In a table, a field that occurs several times in a record is exported as all 
+// its values joined by a semicolon. The continuation lines of a field are joined 
+// with its first line, as elsewhere.
+//
+// The JSON objects list the fields in their order with their line numbers, the 
+// ID namespace of the record and the issues found in the record. The records are 
+// written as they are converted, either as a JSON array or (with ndjson) as one 
+// object per line, so that large dictionaries can be processed as a stream.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::repository::Repository;
+use crate::toolbox::{Dictionary, Record, ToolboxFileIssue, validator};
+
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::Write;
+
+/// The supported export formats
+pub const EXPORT_FORMATS : &[&str] = &["csv", "tsv", "json", "ndjson"];
+
+// the separator of the repeated field values within a cell
+const VALUE_SEPARATOR : &str = "; ";
+
+
+pub fn export(path: String, format: String, fields: Vec<String>, rev: Option<String>) -> Result<()> {
+    // load the repository
+    let repo = Repository::open()?;
+
+    // locate the dictionary
+    let repo_path = repo.get_path_relative_to_repo(&path)?.to_string_lossy().into_owned();
+    let cfg = repo.config().dictionary_by_path(&repo_path)?;
+
+    // the working copy or the version stored in the revision
+    let dictionary = match rev {
+        Some(rev) => {
+            let data = Repository::reconstruct(format!("{}.contents", cfg.path), rev, &cfg.sort)?;
+
+            Dictionary::from_text(cfg, String::from_utf8_lossy(&data).into_owned())
+        },
+        None => Dictionary::load(&repo, cfg, false)?
+    };
+    let records = dictionary.records();
+
+    // the markers are given without the backslash, all fields are exported by default
+    let markers = fields.iter()
+        .map(|field| format!("\\{}", field.trim().trim_start_matches('\\')))
+        .collect::<Vec<_>>();
+
+    match format.as_str() {
+        "json" | "ndjson" => export_json(&dictionary, &records, &markers, format == "ndjson"),
+        _                 => export_table(&records, &markers, &format)
+    }
+}
+
+
+// Write the records as a table (one row per record and one column per field)
+fn export_table(records: &[Record], markers: &[String], format: &str) -> Result<()> {
+    let markers = if markers.is_empty() {
+        all_markers(records)
+    } else {
+        markers.to_vec()
+    };
+
+    let write_row = |cells: Vec<String>| {
+        let row = match format {
+            "tsv" => cells.iter().map(|cell| tsv_cell(cell)).collect::<Vec<_>>().join("\t"),
+            _     => cells.iter().map(|cell| csv_cell(cell)).collect::<Vec<_>>().join(",")
+        };
+
+        println!("{}", row);
+    };
+
+    // the header row
+    write_row(markers.iter().map(|marker| marker.trim_start_matches('\\').to_owned()).collect());
+
+    for record in records.iter() {
+        write_row(
+            markers.iter()
+                .map(|marker| record.values(marker).collect::<Vec<_>>().join(VALUE_SEPARATOR))
+                .collect()
+        );
+    }
+
+    Ok( () )
+}
+
+
+/// A record as exported to JSON
+#[derive(Serialize)]
+struct RecordObject<'a> {
+    /// The (1-based) line of the record start
+    line      : usize,
+    /// The record ID (if the dictionary uses IDs)
+    id        : Option<&'a str>,
+    /// The value of the record tag
+    label     : Option<&'a str>,
+    /// The ID namespace (`public` for the public IDs, `None` if the record has no valid ID)
+    namespace : Option<&'a str>,
+    fields    : Vec<FieldObject<'a>>,
+    /// The issues found in the record
+    issues    : Vec<&'a ToolboxFileIssue>
+}
+
+/// A field as exported to JSON
+#[derive(Serialize)]
+struct FieldObject<'a> {
+    /// The marker without the backslash
+    marker : &'a str,
+    value  : &'a str,
+    /// The (1-based) line of the field start
+    line   : usize
+}
+
+// Write the records as a JSON array or as newline-delimited JSON objects
+fn export_json(dictionary: &Dictionary, records: &[Record], markers: &[String], ndjson: bool) -> Result<()> {
+    let cfg = dictionary.config();
+
+    // the issues (including those of the validators) by line
+    let mut issues = dictionary.split().1;
+    issues.extend(validator::validate(dictionary));
+
+    // the namespaces of the records by their first line
+    let namespaces = dictionary.record_ids().into_iter()
+        .filter_map(|id| Some( (id.record.line, id.parsed?.0.unwrap_or("public")) ))
+        .collect::<HashMap<_, _>>();
+
+    let stdout = std::io::stdout();
+    let mut out = std::io::BufWriter::new(stdout.lock());
+
+    if !ndjson {
+        writeln!(out, "[")?;
+    }
+
+    for (i, record) in records.iter().enumerate() {
+        let start = record.line.line;
+        let lines = start .. start + record.text.lines().count().max(1);
+
+        // the fields are the tagged lines of the record
+        let field_lines = record.text.lines().enumerate()
+            .filter(|(_, line)| line.starts_with('\\'))
+            .map(|(i, _)| start + i + 1);
+
+        let object = RecordObject {
+            line      : start + 1,
+            id        : cfg.id_tag.as_deref().filter(|_| cfg.unique_id).and_then(|tag| record.value(tag)),
+            label     : record.label(),
+            namespace : namespaces.get(&start).copied(),
+            fields    : record.fields.iter().zip(field_lines)
+                .filter(|((marker, _), _)| markers.is_empty() || markers.iter().any(|m| m == marker))
+                .map(|((marker, value), line)| {
+                    FieldObject { marker : marker.trim_start_matches('\\'), value, line }
+                })
+                .collect(),
+            issues    : issues.iter().filter(|issue| lines.contains(&issue.line())).collect()
+        };
+
+        if !ndjson && i > 0 {
+            writeln!(out, ",")?;
+        }
+        serde_json::to_writer(&mut out, &object)?;
+        if ndjson {
+            writeln!(out)?;
+        }
+    }
+
+    if !ndjson {
+        if !records.is_empty() {
+            writeln!(out)?;
+        }
+        writeln!(out, "]")?;
+    }
+    out.flush()?;
+
+    Ok( () )
+}
+
+// The markers of the records in order of their first occurence
+fn all_markers(records: &[Record]) -> Vec<String> {
+    let mut markers : Vec<String> = vec!();
+
+    for (marker, _) in records.iter().flat_map(|record| record.fields.iter()) {
+        if !markers.iter().any(|m| m == marker) {
+            markers.push((*marker).to_owned());
+        }
+    }
+
+    markers
+}
+
+// A CSV cell, quoted if it contains a separator, a quote or a line end (RFC 4180)
+fn csv_cell(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+// A TSV cell, which cannot contain tabs or line ends (these are replaced by spaces)
+fn tsv_cell(value: &str) -> String {
+    value.replace(['\t', '\n', '\r'], " ")
+}
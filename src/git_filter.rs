@@ -7,8 +7,11 @@
 //
 // This cod
 
-use crate::repository::{Repository, MANAGED_FILE_TEXT};
-use crate::toolbox::Dictionary;
+use crate::repository::{Repository, ClobDiff, DiffStats, IndexLock, assemble_clobs};
+use crate::toolbox::{Dictionary, ClobOrigin, template};
+use crate::config::{DictionaryConfig, DEFAULT_PLACEHOLDER_TEXT, DEFAULT_PLACEHOLDER_CONTACT, placeholder_contents};
+use crate::util::{truncate_text, pad_text};
+use crate::cli_app::style;
 
 use std::path::Path;
 use std::io::Write;
@@ -34,17 +37,44 @@ use crate::error;
 ///
 /// This is accomplished in the following way: we first check if the repository
 /// index lock is active. If it is, we assume that we are in the middle of an "add"
-/// operation, so we abort with an error. If it is not acitve, we asume that the 
-/// filter is run as part of `git status` or `git diff` etc. operation, so we return
-/// a diff message instead. 
+/// operation, so we abort with an error if the file has changed (an unchanged file is
+/// given the staged placeholder, as git also checks the files this way before a checkout 
+/// replaces them). If it is not acitve, we asume that the filter is run as part of 
+/// `git status` or `git diff` etc. operation, so we return a diff message instead. 
 pub fn clean<P : AsRef<str>>(path: P) -> Result<()>  {
-    // if the index is locked, we just return the error
-    if Repository::check_for_lock()? {
-        bail!(
-            error::UnableToStageManagedFile {
-                path : path.as_ref().to_owned().into()
-            }   
-        )
+    let report = clean_text(path)?;
+
+    // print it all to stdout
+    let mut stdout = std::io::stdout();
+    stdout.write_all(report.as_bytes()).expect("fatal - stdout error");
+
+    Ok( () )
+}
+
+// The output of the clean filter (either the placeholder or the diff message)
+fn clean_text<P : AsRef<str>>(path: P) -> Result<String>  {
+    // if the index is locked, the changes must not be staged (unless the lock was left 
+    // behind by a crashed git process)
+    let locked = match Repository::check_for_lock()? {
+        IndexLock::Active => true,
+        IndexLock::Stale { age } => {
+            stderr!("git-toolbox: ignoring a stale git index lock ({} minutes old), use {} to remove it", 
+                age/60,
+                style("git toolbox doctor --fix").bold()
+            );
+            false
+        },
+        IndexLock::Unlocked => false
+    };
+
+    // if another git-toolbox process is modifying the repository, the diff would be unreliable, 
+    // so the file is reported as unchanged (the filter must not make git fail)
+    let busy = match Repository::check_for_operation() {
+        Ok(()) => false,
+        Err(_) => {
+            stderr!("git-toolbox: another git-toolbox operation is running, {} is not checked for changes", path.as_ref());
+            true
+        }
     };
 
     // run the actual clean filter which checks for the changes in the file
@@ -52,21 +82,429 @@ pub fn clean<P : AsRef<str>>(path: P) -> Result<()>  {
     //
     // if the inner filter fails, we don't want to abort the entire procedure
     // we just return a dummy message
-    let mut report = do_clean(path).unwrap_or_default();
+    let report = if busy { Ok( String::new() ) } else { do_clean(&path) };
+
+    // git is updating the index, so we return the error unless the file is unchanged (the 
+    // index on disk might not describe the file yet, so the file is first compared with the 
+    // placeholder it was reconstructed from)
+    if locked {
+        if let Some(placeholder) = smudged_placeholder_text(path.as_ref()) {
+            return Ok( placeholder );
+        }
+    }
+    if locked && !report.as_ref().map(String::is_empty).unwrap_or(false) {
+        bail!(
+            error::UnableToStageManagedFile {
+                path : path.as_ref().to_owned().into()
+            }   
+        )
+    }
+    let mut report = report.unwrap_or_default();
 
-    // if the diff is empty, we want to output the standard content so that git thinks
+    // if the diff is empty, we want to output the placeholder content so that git thinks
     // the file did not change
     if report.is_empty() {
-        report.push_str(MANAGED_FILE_TEXT);
+        report.push_str(&placeholder_text(path).unwrap_or_else(|_| {
+            DEFAULT_PLACEHOLDER_TEXT.replace("{contact}", DEFAULT_PLACEHOLDER_CONTACT)
+        }));
     }
 
-    // print it all to stdout
+    Ok( report )
+}
+
+
+// the placeholder the smudge filter last reconstructed the file from, if the file was 
+// not changed since
+fn smudged_placeholder_text(path: &str) -> Option<String> {
+    let repo = Repository::open().ok()?;
+    let path = repo.get_path_relative_to_repo(Path::new(path)).ok()?.to_string_lossy().into_owned();
+
+    let placeholder = repo.smudged_placeholder(&path)?;
+    let mut expected = vec!();
+    crate::reconstruct::write_reconstructed_placeholder(&path, placeholder.as_bytes(), &mut expected).ok()?;
+
+    let data = std::fs::read(repo.workdir().ok()?.join(&path)).ok()?;
+
+    (data == expected).then_some(placeholder)
+}
+
+
+/// Git smudge filter
+///
+/// # Notes
+///
+/// The smudge filter is run by git to transform the blob (the placeholder) into the file
+/// in the working directory. The file is reconstructed from the managed folder the 
+/// placeholder names (see `reconstruct::write_reconstructed_placeholder()`)
+pub fn smudge<P : AsRef<str>>(path: P) -> Result<()>  {
+    use std::io::Read;
+
+    let mut placeholder = vec!();
+    std::io::stdin().read_to_end(&mut placeholder).map_err(|err| {
+        error::FileReadError {
+            path : "<stdin>".into(),
+            msg  : err.to_string()
+        }
+    })?;
+
+    // stream it all to stdout
+    let stdout = std::io::stdout();
+    let mut out = std::io::BufWriter::new(stdout.lock());
+
+    smudge_to(path.as_ref(), &placeholder, &mut out)?;
+    out.flush().expect("fatal - stdout error");
+
+    Ok( () )
+}
+
+// reconstruct the managed file from the placeholder and remember the placeholder, so that 
+// the clean filter can recognize the unchanged file (see `smudged_placeholder_text()`)
+fn smudge_to<W: Write>(path: &str, placeholder: &[u8], out: &mut W) -> Result<()> {
+    crate::reconstruct::write_reconstructed_placeholder(path, placeholder, out)?;
+
+    let repo = Repository::open()?;
+    let path = repo.get_path_relative_to_repo(Path::new(path))?.to_string_lossy().into_owned();
+
+    repo.record_smudged_placeholder(&path, placeholder)
+}
+
+
+/// Git textconv driver (`diff.toolbox.textconv`)
+///
+/// # Notes
+///
+/// Git runs the driver to convert the blobs of the managed files into the text that is 
+/// shown by `git diff` and friends, which would otherwise only show the placeholder.
+/// The driver only gets a file name: the managed file itself if git can borrow it from 
+/// the working directory, or a temporary file with the blob (named `XXXXXX_<file name>` 
+/// or `<file name>` in a temporary folder). Git usually converts the blob with the smudge 
+/// filter first, otherwise the blob is a placeholder and the managed file is reconstructed 
+/// from the managed folder it names (or from the git index if it does not name one). 
+/// The managed files are split and assembled again, so that both sides list the records 
+/// in the same order. Any other file is printed as it is.
+pub fn textconv<P : AsRef<str>>(path: P) -> Result<()> {
+    let path = Path::new(path.as_ref());
+    let data = std::fs::read(path).map_err(|err| {
+        error::FileReadError {
+            path : path.to_owned(),
+            msg  : err.to_string()
+        }
+    })?;
+
+    let text = textconv_text(path, &data).unwrap_or(data);
+
     let mut stdout = std::io::stdout();
-    stdout.write_all(report.as_bytes()).expect("fatal - stdout error");
+    stdout.write_all(&text).expect("fatal - stdout error");
+
+    Ok( () )
+}
+
+// The text of the managed file the textconv driver was given (`None` for other files)
+fn textconv_text(path: &Path, data: &[u8]) -> Option<Vec<u8>> {
+    let repo = Repository::open().ok()?;
+
+    // the managed file in the working directory
+    if let Some(repo_path) = repo.get_path_relative_to_repo(path).ok().filter(|_| path.exists()) {
+        if let Ok(cfg) = repo.config().dictionary_by_path(repo_path.to_string_lossy()) {
+            return assembled_text(cfg, path, data)
+        }
+    }
+
+    // a temporary file with a blob of a managed file
+    let name = path.file_name()?.to_string_lossy().into_owned();
+    let cfg = repo.config().dictionaries.iter().find(|cfg| {
+        let file_name = Path::new(&cfg.path).file_name().map(|name| name.to_string_lossy().into_owned());
+
+        file_name.map(|file_name| name == file_name || name.ends_with(&format!("_{}", file_name))).unwrap_or(false)
+    })?;
+
+    // the blob was converted by the smudge filter
+    let text = String::from_utf8_lossy(data);
+    if !repo.config().is_managed_file_text(cfg, &text) {
+        return assembled_text(cfg, path, data)
+    }
+
+    let data = match placeholder_contents(&text) {
+        Some(tree) => Repository::reconstruct("", tree, &cfg.sort).ok()?,
+        None       => Repository::reconstruct(format!("{}.contents", cfg.path), "", &cfg.sort).ok()?
+    };
+
+    Some( cfg.space_records(cfg.adapt_header(data)) )
+}
+
+// The managed file in UTF-8 with its records in the order they are reconstructed in
+fn assembled_text(cfg: &DictionaryConfig, path: &Path, data: &[u8]) -> Option<Vec<u8>> {
+    let text = cfg.decode_text(path, data.to_vec()).ok()?;
+    let clobs = Dictionary::from_text(cfg, text).split().0.collect();
+
+    Some( cfg.space_records(cfg.adapt_header(assemble_clobs(clobs, &cfg.sort))) )
+}
+
+// maximal size of the pkt-line payload
+const MAX_PACKET_DATA : usize = 65516;
+
+/// Git long-running filter process
+///
+/// Instead of starting the clean and smudge filters for every file, git starts a
+/// single filter process and sends it the files to be filtered one by one using
+/// the pkt-line protocol (see "Long Running Filter Process" in gitattributes(5)).
+///
+/// The standard output is the protocol channel, messages go to the standard error.
+pub fn process() -> Result<()> {
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+
+    let mut input = stdin.lock();
+    let mut output = stdout.lock();
+
+    // handshake
+    let welcome = read_packet_list(&mut input)?.unwrap_or_default();
+    if !welcome.iter().any(|line| line == "git-filter-client") || 
+       !welcome.iter().any(|line| line == "version=2") {
+        bail!(
+            error::FilterProtocolError {
+                msg : "unsupported protocol version".to_owned()
+            }
+        )
+    }
+    write_packet_list(&mut output, &["git-filter-server", "version=2"])?;
+
+    // capabilities
+    let capabilities = read_packet_list(&mut input)?.unwrap_or_default();
+    let supported = ["capability=clean", "capability=smudge"].iter()
+        .copied()
+        .filter(|capability| capabilities.iter().any(|c| c == capability))
+        .collect::<Vec<_>>();
+    write_packet_list(&mut output, &supported)?;
+
+    // filter the files until git closes the channel
+    while let Some(header) = read_packet_list(&mut input)? {
+        let value = |key: &str| {
+            header.iter()
+                .find_map(|line| line.strip_prefix(key).and_then(|line| line.strip_prefix('=')))
+                .unwrap_or_default()
+                .to_owned()
+        };
+        let (command, path) = (value("command"), value("pathname"));
+
+        // the content has to be consumed (the clean filter reads the dictionary from disk)
+        let content = read_content(&mut input)?;
+
+        let result = match command.as_str() {
+            "clean"  => clean_text(&path).map(String::into_bytes),
+            "smudge" => {
+                // the reconstructed contents are streamed as they are retrieved
+                let mut writer = ContentWriter { output : &mut output, buffer : vec!(), started : false };
+                let result = smudge_to(&path, &content, &mut writer);
+
+                writer.finish(result)?;
+                continue;
+            },
+            _        => {
+                Err(
+                    error::FilterProtocolError {
+                        msg : format!("unknown command '{}'", command)
+                    }
+                    .into()
+                )
+            }
+        };
+
+        match result {
+            Ok(data) => {
+                write_packet_list(&mut output, &["status=success"])?;
+                write_content(&mut output, &data)?;
+                // keep the status
+                write_packet_list::<&str>(&mut output, &[])?;
+            },
+            Err(err) => {
+                stderr!("{}", err);
+                write_packet_list(&mut output, &["status=error"])?;
+            }
+        }
+    }
 
     Ok( () )
 }
 
+// Read a packet (`None` for the flush packet)
+fn read_packet<R: std::io::Read>(input: &mut R) -> Result<Option<Vec<u8>>> {
+    let protocol_error = |msg: &str| -> anyhow::Error {
+        error::FilterProtocolError {
+            msg : msg.to_owned()
+        }
+        .into()
+    };
+
+    let mut header = [0u8; 4];
+    input.read_exact(&mut header).map_err(|_| protocol_error("unexpected end of input"))?;
+
+    let len = std::str::from_utf8(&header).ok()
+        .and_then(|len| usize::from_str_radix(len, 16).ok())
+        .ok_or_else(|| protocol_error("invalid packet header"))?;
+
+    match len {
+        0 => Ok( None ),
+        1..=4 => Err( protocol_error("invalid packet length") ),
+        _ => {
+            let mut data = vec![0u8; len - 4];
+            input.read_exact(&mut data).map_err(|_| protocol_error("unexpected end of input"))?;
+
+            Ok( Some(data) )
+        }
+    }
+}
+
+// Read a list of text packets terminated by a flush packet 
+//
+// Returns `None` if the input has ended (i.e. git has closed the channel)
+fn read_packet_list<R: std::io::BufRead>(input: &mut R) -> Result<Option<Vec<String>>> {
+    // check for the end of input
+    if input.fill_buf().map(|buf| buf.is_empty()).unwrap_or(true) {
+        return Ok( None )
+    }
+
+    let mut lines = vec!();
+    while let Some(data) = read_packet(input)? {
+        lines.push(String::from_utf8_lossy(&data).trim_end_matches('\n').to_owned());
+    }
+
+    Ok( Some(lines) )
+}
+
+// Read the content packets terminated by a flush packet
+fn read_content<R: std::io::Read>(input: &mut R) -> Result<Vec<u8>> {
+    let mut content = vec!();
+    while let Some(data) = read_packet(input)? {
+        content.extend_from_slice(&data);
+    }
+
+    Ok( content )
+}
+
+fn write_packet<W: std::io::Write>(output: &mut W, data: Option<&[u8]>) -> Result<()> {
+    let result = match data {
+        Some(data) => {
+            output.write_all(format!("{:04x}", data.len() + 4).as_bytes())
+                .and_then(|_| output.write_all(data))
+        },
+        None => output.write_all(b"0000")
+    };
+
+    result.map_err(|err| {
+        error::FilterProtocolError {
+            msg : err.to_string()
+        }
+        .into()
+    })
+}
+
+// Write a list of text packets followed by a flush packet
+fn write_packet_list<S: AsRef<str>>(output: &mut impl std::io::Write, lines: &[S]) -> Result<()> {
+    for line in lines {
+        write_packet(output, Some(format!("{}\n", line.as_ref()).as_bytes()))?;
+    }
+    write_packet(output, None)?;
+
+    output.flush().map_err(|err| {
+        error::FilterProtocolError {
+            msg : err.to_string()
+        }
+        .into()
+    })
+}
+
+// Write the content packets followed by a flush packet
+fn write_content(output: &mut impl std::io::Write, content: &[u8]) -> Result<()> {
+    for chunk in content.chunks(MAX_PACKET_DATA) {
+        write_packet(output, Some(chunk))?;
+    }
+    write_packet(output, None)
+}
+
+/// Writes the content packets as the content is produced
+///
+/// The success status is sent before the first packet. If producing the content fails after
+/// some of it was sent, the error status is sent after the content (as the protocol allows)
+struct ContentWriter<'a, W: std::io::Write> {
+    output  : &'a mut W,
+    // the content that does not fill a packet yet
+    buffer  : Vec<u8>,
+    // whether the status and some content were sent
+    started : bool
+}
+
+impl<'a, W: std::io::Write> ContentWriter<'a, W> {
+    // send the success status (once)
+    fn start(&mut self) -> Result<()> {
+        if !self.started {
+            write_packet_list(self.output, &["status=success"])?;
+            self.started = true;
+        }
+
+        Ok( () )
+    }
+
+    // finish the response
+    fn finish(mut self, result: Result<()>) -> Result<()> {
+        match result {
+            Ok( () ) => {
+                self.start()?;
+                if !self.buffer.is_empty() {
+                    write_packet(self.output, Some(&self.buffer))?;
+                }
+                write_packet(self.output, None)?;
+                // keep the status
+                write_packet_list::<&str>(self.output, &[])
+            },
+            Err( err ) => {
+                stderr!("{}", err);
+
+                // end the content that was sent so far
+                if self.started {
+                    write_packet(self.output, None)?;
+                }
+                write_packet_list(self.output, &["status=error"])
+            }
+        }
+    }
+}
+
+impl<'a, W: std::io::Write> std::io::Write for ContentWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let io_error = |err: anyhow::Error| std::io::Error::other(err.to_string());
+
+        self.buffer.extend_from_slice(buf);
+
+        while self.buffer.len() >= MAX_PACKET_DATA {
+            self.start().map_err(io_error)?;
+            write_packet(self.output, Some(&self.buffer[.. MAX_PACKET_DATA])).map_err(io_error)?;
+            self.buffer.drain(.. MAX_PACKET_DATA);
+        }
+
+        Ok( buf.len() )
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok( () )
+    }
+}
+
+
+// The placeholder text staged for the managed file (or the configured one)
+fn placeholder_text<P : AsRef<str>>(path: P) -> Result<String>  {
+    let repo = Repository::open()?;
+    let repo_path = repo.get_path_relative_to_repo(Path::new(path.as_ref()))?;
+    let config = repo.config().dictionary_by_path(repo_path.to_string_lossy())?;
+
+    // the staged placeholder names the managed folder, which has not changed
+    let staged = repo.read_text_from_index(&config.path).filter(|text| {
+        repo.config().is_managed_file_text(config, text)
+    });
+
+    Ok( staged.unwrap_or_else(|| repo.config().managed_file_text(config)) )
+}
+
 // The actual worker function
 fn do_clean<P : AsRef<str>>(path: P) -> Result<String>  {
     // load the repository
@@ -83,23 +521,128 @@ fn do_clean<P : AsRef<str>>(path: P) -> Result<String>  {
     let config = repo.config().dictionary_by_path(&repo_path)?;
     
     // load and split the dictionary 
-    let (clobs, _) = Dictionary::load(&repo, config, false)?.split();
+    let dictionary = Dictionary::load(&repo, config, false)?;
+
+    // the file was checked out before the repository was set up and only contains the 
+    // placeholder text (it is rebuilt by the git hooks)
+    if !config.matches_content(dictionary.text()) {
+        return Ok( String::new() )
+    }
+
+    let (clobs, _) = dictionary.split();
     // run the diff
-    let mut changes = repo.diff_clobs_at_path(&format!("{}.contents", &config.path), clobs)?;
-    changes.sort_by(|a, b| {
-        alphanumeric_sort::compare_str(a.filename(), b.filename())
-    });
+    let mut changes = repo.diff_clobs_at_path(format!("{}.contents", &config.path), clobs)?;
+    changes.sort_by(|a, b| config.sort.compare(a.filename(), b.filename()));
 
     // build a report
-    let report = changes.into_iter()
-        .fold(String::new(), |mut diff, action| {
-            diff.push_str(action.diff_marker());
-            diff.push(' ');
-            diff.push_str(action.filename());
-            diff.push('\n');
-
-            diff
-        });
+    let report = build_report(&repo, config, changes);
 
     Ok( report )
-}
\ No newline at end of file
+}
+
+// Builds a human-readable summary of the changes
+//
+// The summary lists the number of changes per namespace followed by the 
+// changed records (identified by their id or label) 
+fn build_report(repo: &Repository, config: &DictionaryConfig, changes: Vec<ClobDiff>) -> String {
+    use std::fmt::Write;
+    use itertools::Itertools;
+
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    // map the changed clobs back to the records they contain
+    let contents_path = format!("{}.contents", config.path);
+    let changes = changes.iter().map(|change| {
+        let origin = ClobOrigin::from_managed_path(&contents_path, change.path());
+
+        (origin, change)
+    })
+    .collect::<Vec<_>>();
+
+    let mut report = String::new();
+
+    writeln!(report, "git-toolbox: {} changed entries in {}", changes.len(), config.path).unwrap();
+    writeln!(report).unwrap();
+
+    // change counts per namespace
+    let by_namespace = changes.iter()
+        .map(|(origin, change)| (origin.namespace(), *change))
+        .into_group_map();
+
+    for namespace in by_namespace.keys().sorted() {
+        let stats = DiffStats::count(by_namespace[namespace].iter().copied());
+
+        writeln!(report, "  {} {} added, {} modified, {} deleted", 
+            pad_text(&truncate_text(namespace, 16), 16), 
+            stats.added, 
+            stats.changed, 
+            stats.deleted
+        ).unwrap();
+    }
+    writeln!(report).unwrap();
+
+    // the changed records
+    for (origin, change) in changes.iter() {
+        // the content of the record (the index version for the deleted records)
+        let content = match change {
+            ClobDiff::Add { clob } | ClobDiff::Update { clob } => Some(clob.content.clone()),
+            ClobDiff::Delete { path }                          => repo.read_text_from_index(path)
+        };
+
+        // the record labels contained in the clob
+        let labels = content.as_deref()
+            .map(|content| record_labels(content, &config.record_tags))
+            .unwrap_or_default()
+            .join(", ");
+
+        // the fields that were changed in the modified records
+        let fields = match change {
+            ClobDiff::Update { clob } => {
+                repo.read_text_from_index(change.path())
+                    .map(|old| changed_fields(config, &old, &clob.content))
+                    .filter(|fields| !fields.is_empty())
+                    .map(|fields| format!(" (changed {})", fields.join(", ")))
+                    .unwrap_or_default()
+            },
+            _ => String::new()
+        };
+
+        let line = format!("  {} {} {} {}{}", 
+            change.diff_marker(), 
+            pad_text(&truncate_text(&origin.namespace(), 16), 16), 
+            pad_text(&truncate_text(origin.key(), 16), 16), 
+            labels,
+            fields
+        );
+        writeln!(report, "{}", line.trim_end()).unwrap();
+    }
+
+    report
+}
+
+// The markers of the changed fields (in the template order if there is a template)
+fn changed_fields(config: &DictionaryConfig, old: &str, new: &str) -> Vec<String> {
+    let mut markers = template::changed_markers(old, new);
+
+    if let Some(template) = &config.template {
+        markers.sort_by_key(|marker| {
+            template.fields.iter().position(|field| &field.marker == marker).unwrap_or(usize::MAX)
+        });
+    }
+
+    markers
+}
+
+// Extract the record labels (the text of the record tags) from a clob
+fn record_labels(content: &str, record_tags: &[String]) -> Vec<String> {
+    use crate::toolbox::starts_record;
+
+    content.lines()
+        .filter(|line| starts_record(line, record_tags))
+        .map(|line| line.split_once(char::is_whitespace).map_or("", |(_, text)| text.trim()))
+        .filter(|text| !text.is_empty())
+        .map(|text| truncate_text(text, 24))
+        .collect()
+}
@@ -0,0 +1,144 @@
+//
+// src/hook.rs
+//
+// Implementation of git-toolbox hook
+//
+// Run by the git hooks installed by git toolbox setup after a merge, a checkout,
+// a rebase or a commit (which only matters for a cherry-pick). The managed files 
+// are rebuilt from the (updated) managed folders, unless they contain edits of 
+// their own: a managed file is only rebuilt if it still matches the version it had 
+// before the operation (or the commit a rebase started from, or only contains the 
+// placeholder text). Files with edits are left alone and reported, so that the
+// edits can be reconciled with the new records.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::repository::Repository;
+use crate::toolbox::Dictionary;
+use crate::config::DictionaryConfig;
+use crate::cli_app::style;
+
+use crate::error;
+use anyhow::Result;
+
+
+pub fn hook(name: String, args: Vec<String>) -> Result<()> {
+    // the revision the working directory was at before the operation
+    let previous = match (name.as_str(), args.first().map(String::as_str)) {
+        // the index and the working directory are not changed by an amend
+        ("post-rewrite", Some("amend"))  => return Ok( () ),
+        // nothing was checked out
+        ("post-checkout", Some(prev)) if args.get(1).map(|new| new == prev).unwrap_or(false) => {
+            return Ok( () )
+        },
+        // a fresh clone has no previous revision
+        ("post-checkout", Some(prev)) if prev.chars().all(|c| c == '0') => None,
+        ("post-checkout", Some(prev)) => Some(prev.to_owned()),
+        ("post-commit", _)            => Some("HEAD^".to_owned()),
+        _                             => Some("ORIG_HEAD".to_owned())
+    };
+
+    let mut repo = Repository::open()?;
+
+    // no other git-toolbox operations while we are modifying the working directory
+    let _lock = repo.lock_operation("hook")?;
+
+    // the operation might have rewritten the placeholder entries without their assume-unchanged mark
+    let unmarked = repo.unmarked_managed_files();
+    repo.mark_managed_files(unmarked)?;
+
+    // ORIG_HEAD is only valid until the next operation
+    let previous = previous.and_then(|rev| repo.commit_id(rev).ok());
+
+    // a rebase starts by checking out the commit it rebases onto (which rebuilds the managed
+    // files), so the files might have been built from that commit as well
+    let onto = if name == "post-rewrite" { rebase_onto(&repo) } else { None };
+    let built_from = previous.iter().chain(onto.iter()).collect::<Vec<_>>();
+
+    let mut rebuilt_paths = vec!();
+    for cfg in repo.config().dictionaries.iter() {
+        // the dictionary is not managed (yet)
+        let rebuilt = match rebuild(cfg, "")? {
+            Some(data) => data,
+            None       => continue
+        };
+
+        let path = repo.workdir()?.join(&cfg.path);
+        let current = std::fs::read(&path).ok();
+        if current.as_ref() == Some(&rebuilt) { continue }
+
+        // the records of the file (none if it only contains the placeholder text)
+        let dictionary = match current {
+            Some(data) => {
+                let text = cfg.decode_text(&path, data)?;
+
+                if cfg.matches_content(&text) { Some(Dictionary::from_text(cfg, text)) } else { None }
+            },
+            None => None
+        };
+        let matches_rev = |rev: &str| dictionary.as_ref().map(|dictionary| {
+            let clobs = dictionary.split().0.collect();
+
+            repo.clobs_match_rev(&format!("{}.contents", cfg.path), clobs, rev).unwrap_or(false)
+        })
+        .unwrap_or(false);
+
+        // the file only contains the placeholder text or has not been edited since the 
+        // previous revision (its records are the records of that revision)
+        let unchanged = dictionary.is_none() || built_from.iter().any(|rev| matches_rev(rev));
+
+        // a commit only leaves the records of the file behind in a cherry-pick (the records 
+        // staged from the file and the unstaged edits stay as they are)
+        if name == "post-commit" && (!unchanged || matches_rev("HEAD")) { continue }
+
+        if !unchanged {
+            stderr!("git-toolbox: {} has unstaged edits and was not rebuilt", style(&cfg.path).bold());
+            if let Some(rev) = previous.as_ref() {
+                stderr!("  (use \"{}\" to merge them with the new records)",
+                    style(format!("git toolbox reconcile {} --base {} --upstream HEAD", cfg.path, &rev[.. 10])).bold()
+                );
+            }
+            continue;
+        }
+
+        std::fs::write(&path, rebuilt).map_err(|err| {
+            error::FileWriteError {
+                path : path.clone(),
+                msg  : err.to_string()
+            }
+        })?;
+
+        stdout!("git-toolbox: rebuilt {}", style(&cfg.path).bold());
+        rebuilt_paths.push(cfg.path.clone());
+    }
+
+    // git compares the stat data of the rebuilt files when checking out other revisions
+    repo.refresh_managed_files(rebuilt_paths)?;
+
+    Ok( () )
+}
+
+// The commit a rebase started from (the parent of the first rewritten commit)
+//
+// Git passes the rewritten commits to the post-rewrite hook on stdin, as lines with 
+// the old and the new commit
+fn rebase_onto(repo: &Repository) -> Option<String> {
+    use std::io::BufRead;
+
+    let line = std::io::stdin().lock().lines().next()?.ok()?;
+    let rewritten = line.split_whitespace().nth(1)?;
+
+    repo.commit_id(format!("{}^", rewritten)).ok()
+}
+
+// The managed file reconstructed from a revision (the index if empty)
+//
+// Returns `None` if the revision does not contain the managed folder
+fn rebuild(cfg: &DictionaryConfig, rev: &str) -> Result<Option<Vec<u8>>> {
+    let data = Repository::reconstruct(format!("{}.contents", cfg.path), rev, &cfg.sort)?;
+    if data.is_empty() { return Ok( None ) }
+
+    Ok( Some(cfg.encode_text(cfg.space_records(cfg.adapt_header(data)))?) )
+}
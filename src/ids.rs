@@ -0,0 +1,192 @@
+//
+// src/ids.rs
+//
+// Implementation of git-toolbox ids
+//
+// Summarizes the record IDs used in the managed toolbox files to support
+// manual ID bookkeeping: the used ID ranges per namespace, the gaps between
+// them, the next free ID and the IDs that do not match the ID specification.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::repository::Repository;
+use crate::toolbox::{Dictionary, RecordId};
+use crate::config::DictionaryConfig;
+use crate::cli_app::style;
+use crate::util::{truncate_text, pad_text};
+
+use anyhow::Result;
+use itertools::Itertools;
+use std::collections::BTreeMap;
+
+// the maximal number of ranges to show per namespace
+const MAX_RANGES_TO_SHOW: usize = 8;
+
+
+/// The IDs used in a single namespace
+#[derive(Default)]
+struct NamespaceIds<'a> {
+    // numeric IDs (with the width of the widest zero-padded ID)
+    numbers : Vec<u64>,
+    width   : usize,
+    // IDs that are not numeric
+    other   : Vec<&'a str>
+}
+
+
+pub fn ids(paths: Vec<String>, report: bool) -> Result<()> {
+    // open the repository
+    let repo = Repository::open()?;
+
+    // dictionary selection
+    let dictionaries : Vec<&DictionaryConfig> = if paths.is_empty() {
+        repo.config().dictionaries.iter().collect()
+    } else {
+        paths.iter().map(|path| {
+            // convert the path to one relative to the repo
+            let path = repo.get_path_relative_to_repo(path)?.to_string_lossy().into_owned();
+
+            repo.config().dictionary_by_path(path)
+        })
+        .collect::<Result<Vec<_>>>()?
+    };
+
+    for cfg in dictionaries {
+        stdout!("{}", style(&cfg.path).bold());
+        stdout!("");
+
+        if !cfg.unique_id {
+            stdout!("  (this dictionary does not use unique IDs)");
+            stdout!("");
+            continue;
+        }
+
+        let dictionary = Dictionary::load(&repo, cfg, false)?;
+        let ids = dictionary.record_ids();
+
+        // group the valid ids by namespace (the public namespace comes first)
+        let mut namespaces : BTreeMap<Option<&str>, NamespaceIds> = BTreeMap::new();
+        for (namespace, id) in ids.iter().filter_map(|id| id.parsed) {
+            let entry = namespaces.entry(namespace).or_default();
+
+            match id.parse::<u64>() {
+                Ok(number) => {
+                    entry.numbers.push(number);
+                    if id.starts_with('0') {
+                        entry.width = entry.width.max(id.len());
+                    }
+                },
+                Err(_) => {
+                    entry.other.push(id);
+                }
+            }
+        }
+
+        for (namespace, entry) in namespaces.iter_mut() {
+            entry.numbers.sort_unstable();
+            entry.numbers.dedup();
+
+            display_namespace(*namespace, entry, report);
+        }
+
+        if namespaces.is_empty() {
+            stdout!("  (no records with valid IDs)");
+        }
+
+        // the ids that do not match the id spec
+        let invalid = ids.iter().filter(|id| id.parsed.is_none()).collect::<Vec<_>>();
+        if report && !invalid.is_empty() {
+            stdout!("");
+            stdout!("  IDs that do not match the ID specification:");
+            stdout!("");
+            for id in invalid.iter() {
+                display_invalid_id(id);
+            }
+        } else if !invalid.is_empty() {
+            stdout!("");
+            stdout!("  ⚠️  {} IDs do not match the ID specification (use {} to list them)",
+                invalid.len(),
+                style("--report").bold()
+            );
+        }
+        stdout!("");
+    }
+
+    Ok( () )
+}
+
+
+fn display_namespace(namespace: Option<&str>, entry: &NamespaceIds, report: bool) {
+    let prefix = namespace.unwrap_or("");
+    let format_id = |number: u64| format!("{}{:0width$}", prefix, number, width = entry.width);
+
+    let next_free = entry.numbers.last().map(|last| format_id(last + 1))
+        .unwrap_or_else(|| format_id(1));
+
+    stdout!("  {} {:>6} IDs, next free ID: {}",
+        pad_text(&truncate_text(namespace.unwrap_or("public"), 12), 12),
+        entry.numbers.len() + entry.other.len(),
+        style(next_free).green()
+    );
+
+    if !report {
+        return;
+    }
+
+    // the used ranges and the gaps between them
+    let used = ranges(&entry.numbers);
+    let gaps = used.iter().tuple_windows().map(|((_, end), (start, _))| {
+        (end + 1, start - 1)
+    })
+    .collect::<Vec<_>>();
+
+    let display_ranges = |ranges: &[(u64, u64)]| {
+        let mut text = ranges.iter().take(MAX_RANGES_TO_SHOW).map(|(start, end)| {
+            if start == end {
+                format_id(*start)
+            } else {
+                format!("{}–{}", format_id(*start), format_id(*end))
+            }
+        })
+        .join(", ");
+
+        if ranges.len() > MAX_RANGES_TO_SHOW {
+            text.push_str(&format!(" (and {} more)", ranges.len() - MAX_RANGES_TO_SHOW));
+        }
+        if text.is_empty() {
+            text.push('—');
+        }
+
+        text
+    };
+
+    stdout!("      used:       {}", display_ranges(&used));
+    stdout!("      gaps:       {}", display_ranges(&gaps));
+    if !entry.other.is_empty() {
+        stdout!("      other:      {}", entry.other.iter().map(|id| format!("{}{}", prefix, id)).join(", "));
+    }
+}
+
+fn display_invalid_id(id: &RecordId) {
+    stdout!("        {} {} in the record {}",
+        style(format!("line:{:<8}", id.line.line + 1)).italic().yellow(),
+        style(id.line.text.trim()).cyan(),
+        style(id.record.text.trim()).cyan()
+    );
+}
+
+// Collapse sorted numbers into contiguous ranges
+fn ranges(numbers: &[u64]) -> Vec<(u64, u64)> {
+    let mut ranges : Vec<(u64, u64)> = vec!();
+
+    for &number in numbers {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == number => *end = number,
+            _                                    => ranges.push((number, number))
+        }
+    }
+
+    ranges
+}
@@ -0,0 +1,283 @@
+//
+// src/import.rs
+//
+// Implementation of git-toolbox import
+//
+// Converts external data (a CSV/TSV table or a LIFT file) into Toolbox records
+// and merges them into the working copy of a managed toolbox file. The records
+// are matched by their ID (or their label if the dictionary does not use IDs):
+// new records are appended and the fields missing from the existing records
+// are added. A field that has different values in the file and in the record
+// is a conflict, which is reported and left unchanged. As with edit-field, only
+// the working copy is changed, so that the result can be reviewed before it is
+// staged.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::repository::Repository;
+use crate::toolbox::{Dictionary, Record};
+use crate::toolbox::import::{self, ImportedRecord};
+use crate::config::DictionaryConfig;
+use crate::cli_app::style;
+use crate::util::truncate_text;
+
+use crate::error;
+use anyhow::Result;
+use std::collections::{HashMap, HashSet, BTreeMap};
+
+/// The supported import formats
+pub const IMPORT_FORMATS : &[&str] = &["csv", "tsv", "lift"];
+
+
+// A field with different values in the imported data and the record
+struct Conflict {
+    key      : String,
+    marker   : String,
+    existing : String,
+    imported : String
+}
+
+
+pub fn import(input: String, path: String, format: Option<String>, dry_run: bool) -> Result<()> {
+    // load the repository
+    let repo = Repository::open()?;
+
+    // no other git-toolbox operations while we are modifying the repository
+    let _lock = repo.lock_operation("import")?;
+
+    // locate the dictionary
+    let repo_path = repo.get_path_relative_to_repo(&path)?.to_string_lossy().into_owned();
+    let cfg = repo.config().dictionary_by_path(&repo_path)?;
+
+    // convert the imported data
+    let input_path = std::path::PathBuf::from(&input);
+    let data = std::fs::read_to_string(&input_path).map_err(|err| {
+        error::FileReadError {
+            path : input_path.clone(),
+            msg  : err.to_string()
+        }
+    })?;
+    // the format is guessed from the file extension by default
+    let format = format.unwrap_or_else(|| {
+        match input_path.extension().and_then(|ext| ext.to_str()).map(|ext| ext.to_lowercase()).as_deref() {
+            Some("lift") => "lift".to_owned(),
+            Some("tsv")  => "tsv".to_owned(),
+            _            => "csv".to_owned()
+        }
+    });
+    let imported = match format.as_str() {
+        "lift" => import::parse_lift(&data, cfg),
+        "tsv"  => import::parse_table(&data, '\t', cfg),
+        _      => import::parse_table(&data, ',', cfg)
+    }
+    .map_err(|err| {
+        error::InvalidImportFile {
+            path : input.clone(),
+            line : err.line,
+            msg  : err.msg
+        }
+    })?;
+
+    // the working copy of the dictionary
+    let file_path = repo.workdir()?.join(&cfg.path);
+    let data = std::fs::read(&file_path).map_err(|err| {
+        error::FileReadError {
+            path : file_path.clone(),
+            msg  : err.to_string()
+        }
+    })?;
+    let text = cfg.decode_text(&file_path, data)?;
+
+    let dictionary = Dictionary::from_text(cfg, text);
+    let records = dictionary.records();
+
+    // the existing records by their keys (the first record with a key is used)
+    let mut existing : HashMap<String, &Record> = HashMap::new();
+    for record in records.iter() {
+        if let Some(key) = record_key(cfg, record.fields.iter().map(|(m, v)| (*m, v.as_str()))) {
+            existing.entry(key).or_insert(record);
+        }
+    }
+
+    // the fields added to the records (by the line of the record) and the new records
+    let mut additions : BTreeMap<usize, (String, String)> = BTreeMap::new();
+    let mut new_records : Vec<(String, &ImportedRecord)> = vec!();
+    let mut conflicts = vec!();
+    let mut skipped = imported.skipped;
+    let mut seen = HashSet::new();
+
+    for record in imported.records.iter() {
+        let key = match record_key(cfg, record.fields.iter().map(|(m, v)| (m.as_str(), v.as_str()))) {
+            Some(key) => key,
+            None      => { skipped += 1; continue }
+        };
+
+        // the same record occurs several times in the imported data
+        if !seen.insert(key.clone()) {
+            skipped += 1;
+            continue;
+        }
+
+        let target = match existing.get(&key) {
+            Some(target) => target,
+            None => {
+                new_records.push((key, record));
+                continue;
+            }
+        };
+
+        // the imported fields by marker (in the order of their first occurence)
+        let mut markers : Vec<&str> = vec!();
+        for (marker, _) in record.fields.iter() {
+            if !markers.contains(&marker.as_str()) {
+                markers.push(marker);
+            }
+        }
+
+        let mut added = String::new();
+        for marker in markers {
+            let new = record.values(marker).collect::<Vec<_>>();
+            let old = target.values(marker).collect::<Vec<_>>();
+
+            if old.is_empty() {
+                added.push_str(&ImportedRecord {
+                    fields : record.fields.iter().filter(|(tag, _)| tag == marker).cloned().collect()
+                }.text(cfg.continuation_lines));
+            } else if old != new {
+                conflicts.push(Conflict {
+                    key      : key.clone(),
+                    marker   : marker.to_owned(),
+                    existing : old.join("; "),
+                    imported : new.join("; ")
+                });
+            }
+        }
+
+        if !added.is_empty() {
+            additions.insert(target.line.line, (key, added));
+        }
+    }
+
+    // preview the changes
+    for (key, record) in new_records.iter() {
+        stdout!("  {} {}", style("new").green(), style(truncate_text(key, 60)).bold());
+        for line in record.text(cfg.continuation_lines).lines() {
+            stdout!("      {} {}", style("+").green(), style(line).green());
+        }
+    }
+    for (key, added) in additions.values() {
+        stdout!("  {} {}", style("updated").yellow(), style(truncate_text(key, 60)).bold());
+        for line in added.lines() {
+            stdout!("      {} {}", style("+").green(), style(line).green());
+        }
+    }
+    if !conflicts.is_empty() {
+        stdout!("");
+        stdout!("{} {} conflicting fields were not changed:", style("✗").red(), conflicts.len());
+        for conflict in conflicts.iter() {
+            stdout!("  {} {}: {} in the dictionary, {} in {}",
+                style(truncate_text(&conflict.key, 30)).bold(),
+                style(&conflict.marker).bold(),
+                style(format!("'{}'", truncate_text(&conflict.existing, 30))).red(),
+                style(format!("'{}'", truncate_text(&conflict.imported, 30))).green(),
+                style(&input).italic()
+            );
+        }
+    }
+    if skipped > 0 {
+        stdout!("");
+        stdout!("  ({} records were skipped, since they have no record tag or ID or occur more than once)", skipped);
+    }
+    stdout!("");
+
+    if new_records.is_empty() && additions.is_empty() {
+        stdout!("No records to import into {}", style(&cfg.path).italic());
+        return Ok( () );
+    }
+
+    if dry_run {
+        stdout!("{} records would be added and {} updated (nothing was written)", new_records.len(), additions.len());
+        return Ok( () );
+    }
+
+    let mut text = merged_text(dictionary.text(), &records, &additions, &new_records, cfg.continuation_lines);
+    if dictionary.bom() {
+        text.insert(0, '\u{feff}');
+    }
+
+    // the file is written in its encoding
+    let data = cfg.encode_text(text.into_bytes())?;
+    std::fs::write(&file_path, data).map_err(|err| {
+        error::FileWriteError {
+            path : file_path.clone(),
+            msg  : err.to_string()
+        }
+    })?;
+
+    stdout!("{} Added {} and updated {} records of {}",
+        style("✓").green(),
+        new_records.len(),
+        additions.len(),
+        style(&cfg.path).italic()
+    );
+    stdout!("  (the changes are not staged, use {} to review them and {} to stage them)",
+        style("git toolbox diff").bold(),
+        style("git toolbox stage").bold()
+    );
+
+    Ok( () )
+}
+
+
+// The key the records are matched by: the ID (for the dictionaries with unique IDs) or the label
+fn record_key<'a, I: Iterator<Item=(&'a str, &'a str)>>(cfg: &DictionaryConfig, mut fields: I) -> Option<String> {
+    let key = match cfg.id_tag.as_deref().filter(|_| cfg.unique_id) {
+        Some(id_tag) => fields.find(|(marker, _)| *marker == id_tag).map(|(_, value)| value),
+        None         => fields.next().map(|(_, value)| value)
+    };
+
+    key.map(|key| key.trim().to_owned()).filter(|key| !key.is_empty())
+}
+
+// The dictionary text with the added fields and the new records (keeping the line ends)
+fn merged_text(
+    text: &str,
+    records: &[Record],
+    additions: &BTreeMap<usize, (String, String)>,
+    new_records: &[(String, &ImportedRecord)],
+    continuation_lines: bool
+) -> String {
+    let newline = if text.contains("\r\n") { "\r\n" } else { "\n" };
+    let with_newlines = |lines: &str| lines.lines().map(|line| format!("{}{}", line, newline)).collect::<String>();
+
+    let mut merged = String::new();
+    let mut position = 0;
+
+    // the fields are added at the end of the record body
+    for record in records.iter() {
+        if let Some((_, added)) = additions.get(&record.line.line) {
+            let end = record.text.as_ptr() as usize - text.as_ptr() as usize + record.text.len();
+
+            merged.push_str(&text[position .. end]);
+            if !merged.ends_with('\n') {
+                merged.push_str(newline);
+            }
+            merged.push_str(&with_newlines(added));
+            position = end;
+        }
+    }
+    merged.push_str(&text[position ..]);
+
+    // the new records are appended (separated by an empty line)
+    for (_, record) in new_records {
+        if !merged.is_empty() && !merged.ends_with('\n') {
+            merged.push_str(newline);
+        }
+        merged.push_str(newline);
+        merged.push_str(&with_newlines(&record.text(continuation_lines)));
+    }
+
+    merged
+}
@@ -15,16 +15,14 @@ use tap::*;
 pub struct ListingLine {
     line_number : usize, 
     line_text   : String,
-    marker      : Option<(usize, String)>,
-    notes       : Vec<String>
+    marker      : Option<(usize, String)>
 }
 
 /// Writes out a nicely formatted listing
 #[derive(Debug)]
 pub struct ListingFormatter {
     label         : Option<String>,
-    lines         : Vec<ListingLine>,
-    surround      : usize
+    lines         : Vec<ListingLine>
 }
 
 impl ListingLine {
@@ -76,8 +74,7 @@ impl ListingFormatter {
     pub fn new() -> ListingFormatter {
         ListingFormatter {
             label : None, 
-            lines : vec!(),
-            surround : 0
+            lines : vec!()
         }
     }
 
@@ -110,8 +107,7 @@ impl ListingFormatter {
         self.lines.push(ListingLine {
             line_number,
             line_text,
-            marker    : None,
-            notes: vec!()
+            marker    : None
         });
   
         self.lines.last_mut().unwrap()
@@ -121,7 +117,8 @@ impl ListingFormatter {
 
 impl fmt::Display for ListingFormatter {
     fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        use console::{measure_text_width, truncate_str}; 
+        use console::measure_text_width; 
+        use crate::util::truncate_text;
     
         use textwrap::wrap_iter;
     
@@ -131,7 +128,7 @@ impl fmt::Display for ListingFormatter {
         // early bail out if nothign to print
         if self.lines.is_empty() {
             writeln!(formatter)?;
-            writeln!(formatter, "{}", truncate_str("  ...empty listing...  ", wrap_at, "..."))?;
+            writeln!(formatter, "{}", truncate_text("  ...empty listing...  ", wrap_at))?;
             writeln!(formatter)?;
             return Ok( () );
         }
@@ -224,7 +221,7 @@ impl fmt::Display for ListingFormatter {
                             "", // placeholder for the margin, 
                             margin_area_width = margin_area_width
                         )?;
-                        for wrapped_line in wrap_iter(&marker, marker_text_width) {
+                        for wrapped_line in wrap_iter(marker, marker_text_width) {
                             writeln!(formatter, "  {:>margin_area_width$} |   {}", 
                                 "", // placeholder for number marker
                                 &wrapped_line,
@@ -247,6 +244,90 @@ impl fmt::Display for ListingFormatter {
     }
 }
 
+/// Writes out two versions of a list of values side by side
+///
+/// Every row has a label (e.g. the field name) and an old and a new value, which
+/// are wrapped to two columns of equal width. The old values are red and the new
+/// values green, a missing value leaves its column empty.
+#[derive(Debug, Default)]
+pub struct SideBySideFormatter {
+    header : Option<(String, String)>,
+    rows   : Vec<(String, Option<String>, Option<String>)>
+}
+
+impl SideBySideFormatter {
+    pub fn new() -> SideBySideFormatter {
+        SideBySideFormatter::default()
+    }
+
+    pub fn set_header<S: Into<String>>(&mut self, old : S, new : S) -> &mut Self {
+        self.header.replace((old.into(), new.into()));
+
+        self
+    }
+
+    pub fn push_row<S: Into<String>>(&mut self, label : S, old : Option<S>, new : Option<S>) -> &mut Self {
+        self.rows.push((label.into(), old.map(Into::into), new.map(Into::into)));
+
+        self
+    }
+}
+
+impl fmt::Display for SideBySideFormatter {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        use crate::cli_app::style;
+        use crate::util::{pad_text, text_width, truncate_text};
+
+        use textwrap::wrap_iter;
+
+        // the wrap border
+        let wrap_at = formatter.width().unwrap_or(80);
+
+        // the labels take at most a quarter of the width
+        let label_width = self.rows.iter()
+            .map(|(label, _, _)| text_width(label))
+            .max()
+            .unwrap_or(0)
+            .min(wrap_at / 4);
+
+        // compute the width of the columns
+        // 8 is the width of the additional padding and the dividers
+        let column_width = wrap_at.saturating_sub(label_width + 8) / 2;
+
+        // if the columns are too narrow...
+        if column_width < 4 {
+          return Ok( () );
+        };
+
+        if let Some((old, new)) = self.header.as_ref() {
+            writeln!(formatter, "  {} | {} | {}",
+                pad_text("", label_width),
+                style(pad_text(&truncate_text(old, column_width), column_width)).bold(),
+                style(truncate_text(new, column_width)).bold()
+            )?;
+        }
+
+        for (label, old, new) in self.rows.iter() {
+            let old = old.as_deref().map(|text| wrap_iter(text, column_width).collect::<Vec<_>>()).unwrap_or_default();
+            let new = new.as_deref().map(|text| wrap_iter(text, column_width).collect::<Vec<_>>()).unwrap_or_default();
+
+            for i in 0 .. old.len().max(new.len()).max(1) {
+                let label = if i == 0 { truncate_text(label, label_width) } else { String::new() };
+                let old_line = old.get(i).map(|line| pad_text(line, column_width)).unwrap_or_else(|| " ".repeat(column_width));
+                let new_line = new.get(i).map(|line| line.to_string()).unwrap_or_default();
+
+                writeln!(formatter, "  {} | {} | {}",
+                    pad_text(&label, label_width),
+                    style(old_line).red(),
+                    style(new_line).green()
+                )?;
+            }
+        }
+
+        Ok( () )
+    }
+}
+
 // I felt "inspired"
 fn num_digits(x: usize) -> usize {
     match x {
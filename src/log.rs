@@ -0,0 +1,99 @@
+//
+// src/log.rs
+//
+// Implementation of git-toolbox log
+//
+// Lists the commits that changed a record (identified by its ID), i.e. the 
+// history of the clob that holds the record, together with the changes to the
+// record fields.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::repository::{Repository, FileRevision};
+use crate::toolbox::clob_paths_for_id;
+use crate::toolbox::template::{self, RecordChange};
+use crate::config::DictionaryConfig;
+use crate::cli_app::style;
+use crate::util::{truncate_text, format_time};
+
+use crate::error;
+use anyhow::{Result, bail};
+
+// the number of characters of the commit id to show
+const COMMIT_ID_WIDTH : usize = 8;
+
+
+pub fn log(path: String, id: String) -> Result<()> {
+    // load the repository
+    let repo = Repository::open()?;
+
+    // locate the dictionary
+    let repo_path = repo.get_path_relative_to_repo(&path)?.to_string_lossy().into_owned();
+    let cfg = repo.config().dictionary_by_path(&repo_path)?;
+
+    // the clob holding the record (for any of the record types)
+    let clob_paths = clob_paths_for_id(cfg, &id).into_iter()
+        .filter(|_| cfg.unique_id)
+        .map(|clob_path| format!("{}.contents/{}", cfg.path, clob_path))
+        .collect::<Vec<_>>();
+    if clob_paths.is_empty() {
+        bail!(
+            error::InvalidRecordId {
+                id   : id.clone(),
+                path : cfg.path.clone()
+            }
+        )
+    }
+
+    let history = repo.file_history(&clob_paths)?;
+
+    stdout!("{} {}", style(&cfg.path).bold(), style(format!("record {}", id.trim())).cyan());
+    stdout!("");
+
+    if history.is_empty() {
+        stdout!("  (no commits changed this record)");
+        stdout!("");
+
+        return Ok( () )
+    }
+
+    for revision in history.iter() {
+        display_revision(cfg, revision);
+    }
+
+    Ok( () )
+}
+
+
+fn display_revision(cfg: &DictionaryConfig, revision: &FileRevision) {
+    let (seconds, offset) = revision.time;
+
+    stdout!("  {} {} {}",
+        style(&revision.commit[.. COMMIT_ID_WIDTH.min(revision.commit.len())]).yellow(),
+        format_time(seconds, offset),
+        style(&revision.author).italic()
+    );
+    stdout!("      {}", truncate_text(&revision.summary, 72));
+
+    let old = revision.old.as_deref().unwrap_or("");
+    let new = revision.new.as_deref().unwrap_or("");
+
+    for change in template::record_changes(cfg, old, new) {
+        match change {
+            RecordChange::Added { label } => {
+                stdout!("        {} {}", style("added").green(), style(truncate_text(&label, 40)).italic());
+            },
+            RecordChange::Removed { label } => {
+                stdout!("        {} {}", style("deleted").red(), style(truncate_text(&label, 40)).italic());
+            },
+            RecordChange::Modified { label: _, changes } => {
+                for change in changes {
+                    stdout!("        {}", change);
+                }
+            }
+        }
+    }
+    stdout!("");
+}
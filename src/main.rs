@@ -22,6 +22,9 @@ mod repository;
 mod toolbox;
 mod listing_formatter;
 mod util;
+mod sorting;
+mod profile;
+mod output;
 
 // Implementation of CLI commands
 
@@ -31,12 +34,76 @@ mod setup;
 mod status;
 // git-toolbox gitfilter
 mod git_filter;
+// git-toolbox diff
+mod diff;
+// git-toolbox ids
+mod ids;
+// git-toolbox check
+mod check;
+// git-toolbox log
+mod log;
+// git-toolbox blame
+mod blame;
+// git-toolbox verify
+mod verify;
+// git-toolbox query
+mod query;
+// git-toolbox export
+mod export;
+// git-toolbox import
+mod import;
+// git-toolbox open
+mod open;
+// git-toolbox mergedriver
+mod merge_driver;
 // git-toolbox show
 mod reconstruct;
+// git-toolbox adopt
+mod adopt;
+// git-toolbox mv
+mod mv;
+// git-toolbox rm
+mod rm;
 // git-toolbox stage
 mod stage;
-// git-toolbox reset
+// git-toolbox reset, git-toolbox checkout
 mod reset;
+// git-toolbox unstage
+mod unstage;
+// git-toolbox recover
+mod recover;
+// git-toolbox reingest
+mod reingest;
+// git-toolbox reconcile
+mod reconcile;
+// git-toolbox resolve
+mod resolve;
+// git-toolbox conflicts
+mod conflicts;
+// git-toolbox hook
+mod hook;
+// git-toolbox new-record
+mod new_record;
+// git-toolbox new-id
+mod new_id;
+// git-toolbox edit-field
+mod edit_field;
+// git-toolbox rename-marker
+mod rename_marker;
+// git-toolbox doctor
+mod doctor;
+// git-toolbox autostage
+mod autostage;
+// git-toolbox snapshot
+mod snapshot;
+// git-toolbox stash
+mod stash;
+// git-toolbox archive
+mod archive;
+// git-toolbox pick
+mod pick;
+// running the commands in several repositories
+mod workspace;
 
 // Program's entry point
 fn main() {
@@ -45,30 +112,172 @@ fn main() {
     // fetch and run the command from CLI
     let result = Command::from_cli().and_then(|command| {
         match command {
-            Command::Setup { init } => {
-                setup::setup(init)
+            Command::Setup { uninstall: Some(files), remove_contents, .. } => {
+                setup::uninstall(files, remove_contents)
+            },
+            Command::Setup { init, adopt, .. } => {
+                setup::setup(init, adopt)
             }, 
-            Command::Reset { files, verbose, force} => {
-                reset::reset(files, verbose, force)
+            Command::Reset { files, verbose, force, repair_contents, to, allow_mass_deletion } => {
+                reset::reset(files, verbose, force, repair_contents, to, allow_mass_deletion)
+            },
+            Command::Checkout { rev, files, force, allow_mass_deletion } => {
+                reset::checkout(rev, files, force, allow_mass_deletion)
+            },
+            Command::Stage { files, verbose, discard_workdir_changes, sign, namespace, records, allow_mass_deletion, strict } => {
+                let options = stage::StageOptions { discard_workdir_changes, sign, allow_mass_deletion, strict };
+
+                stage::stage(files, verbose, namespace, records, options)
+            },
+            Command::Unstage { files, verbose } => {
+                unstage::unstage(files, verbose)
+            },
+            Command::Reingest { files, verbose } => {
+                reingest::reingest(files, verbose)
+            },
+            Command::Reconcile { file, upstream, base } => {
+                reconcile::reconcile(file, upstream, base)
+            },
+            Command::Resolve { file } => {
+                resolve::resolve(file)
+            },
+            Command::Conflicts { files, ours : Some(id), theirs : _, union : _ } => {
+                conflicts::resolve(files, id, conflicts::Resolution::Ours)
+            },
+            Command::Conflicts { files, ours : None, theirs : Some(id), union : _ } => {
+                conflicts::resolve(files, id, conflicts::Resolution::Theirs)
+            },
+            Command::Conflicts { files, ours : None, theirs : None, union : Some(id) } => {
+                conflicts::resolve(files, id, conflicts::Resolution::Union)
+            },
+            Command::Conflicts { files, ours : None, theirs : None, union : None } => {
+                conflicts::conflicts(files)
+            },
+            Command::NewRecord { file, label, id } => {
+                new_record::new_record(file, label, id)
+            },
+            Command::NewId { file, count, namespace } => {
+                new_id::new_id(file, count, namespace)
+            },
+            Command::EditField { file, tag, old, new, regex, filter, dry_run } => {
+                edit_field::edit_field(file, tag, old, new, regex, filter, dry_run)
+            },
+            Command::RenameMarker { old, new, file } => {
+                rename_marker::rename_marker(old, new, file)
+            },
+            Command::Doctor { repair, verbose } => {
+                doctor::doctor(repair, verbose)
+            },
+            Command::Autostage { interval, once, detach } => {
+                autostage::autostage(interval, once, detach)
+            },
+            Command::SnapshotPush { remote, branch, rev } => {
+                snapshot::push(remote, branch, rev)
+            },
+            Command::StashPush { files, message } => {
+                stash::push(files, message)
+            },
+            Command::StashPop { force } => {
+                stash::pop(force)
+            },
+            Command::StashDrop => {
+                stash::drop()
+            },
+            Command::StashList => {
+                stash::list()
+            },
+            Command::Archive { output, rev, contents } => {
+                archive::archive(output, rev, contents)
             },
-            Command::Stage { files, verbose, discard_workdir_changes} => {
-                stage::stage(files, verbose, discard_workdir_changes)
+            Command::Pick { rev, file, record, force } => {
+                pick::pick(rev, file, record, force)
             },
-            Command::Status { files, verbose } => {
-                status::status(files, verbose)
+            Command::Status { files, verbose, namespace, all_issues, all_branches, repos } if !repos.is_empty() => {
+                workspace::run_in_repositories(repos, || {
+                    status::status(files.clone(), verbose, namespace.clone(), all_issues, all_branches)
+                })
+            },
+            Command::Status { files, verbose, namespace, all_issues, all_branches, repos : _ } => {
+                status::status(files, verbose, namespace, all_issues, all_branches)
             }, 
-            Command::Reconstruct { pathspec, bare} => {
-                reconstruct::reconstruct(pathspec, bare)
+            Command::Adopt { path, record_tag } => {
+                adopt::adopt(path, record_tag)
+            },
+            Command::Mv { old, new } => {
+                mv::mv(old, new)
+            },
+            Command::Rm { path, delete } => {
+                rm::rm(path, delete)
+            },
+            Command::Recover { recovery } => {
+                recover::recover(recovery)
+            },
+            Command::Ids { files, report } => {
+                ids::ids(files, report)
+            },
+            Command::Check { files, verbose, update_baseline } => {
+                check::check(files, verbose, update_baseline)
+            },
+            Command::Diff { files, cached, namespace, side_by_side } => {
+                diff::diff(files, cached, namespace, side_by_side)
+            },
+            Command::Log { file, record } => {
+                log::log(file, record)
+            },
+            Command::Blame { file, record } => {
+                blame::blame(file, record)
+            },
+            Command::Verify { files, deep, cross_platform, repos } if !repos.is_empty() => {
+                workspace::run_in_repositories(repos, || verify::verify(files.clone(), deep, cross_platform))
+            },
+            Command::Verify { files, deep, cross_platform, repos : _ } => {
+                verify::verify(files, deep, cross_platform)
+            },
+            Command::Query { query, files, full, count } => {
+                query::query(query, files, full, count)
+            },
+            Command::Export { file, format, fields, rev } => {
+                export::export(file, format, fields, rev)
+            },
+            Command::Import { input, file, format, dry_run } => {
+                import::import(input, file, format, dry_run)
+            },
+            Command::Open { name, files } => {
+                open::open(name, files)
+            },
+            Command::Reconstruct { pathspec, bare : _, merge_base, record : Some(id), label : _ } => {
+                reconstruct::show_record(pathspec, merge_base, reconstruct::RecordSelection::Id(id))
+            },
+            Command::Reconstruct { pathspec, bare : _, merge_base, record : None, label : Some(label) } => {
+                reconstruct::show_record(pathspec, merge_base, reconstruct::RecordSelection::Label(label))
+            },
+            Command::Reconstruct { pathspec, bare, merge_base, record : None, label : None } => {
+                reconstruct::reconstruct(pathspec, bare, merge_base)
             },            
+            Command::Hook { name, args } => {
+                hook::hook(name, args)
+            },
+            Command::MergeDriver { base, ours, theirs, path } => {
+                merge_driver::merge(base, ours, theirs, path)
+            },
             Command::FilterClean { path } => {
                 git_filter::clean(path)
             },
             Command::FilterSmudge { path } => {
-                reconstruct::reconstruct(path, false)
+                git_filter::smudge(path)
+            },
+            Command::FilterProcess => {
+                git_filter::process()
+            },
+            Command::FilterTextconv { path } => {
+                git_filter::textconv(path)
             }
         }
     });
 
+    // the timings are reported even if the command failed
+    profile::report();
+
     // check if there was an error, display it and die
     if let Err(err) = result {
         stderr!("{}", err);
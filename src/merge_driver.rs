@@ -0,0 +1,99 @@
+//
+// src/merge_driver.rs
+//
+// Implementation of git-toolbox mergedriver
+//
+// Git runs the merge driver for the managed files and the contents of the
+// managed folders when both branches have changed them.
+//
+// - The managed files only contain the placeholder text, so we simply
+//   record the configured placeholder (the actual contents are merged via
+//   the managed folders).
+//
+// - The clobs in the managed folders are merged record by record. Conflicting
+//   records are emitted between conflict markers.
+//
+// - The record checksum manifests are merged record by record as well (the 
+//   records changed on both branches get new checksums once they are staged).
+//
+// The merge result is written to the file with our version (as git expects).
+// A non-zero exit code signals conflicts.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::repository::{Repository, MANIFEST_FILE, merge_manifests};
+use crate::toolbox::merge::merge_records;
+
+use crate::error;
+use anyhow::Result;
+
+use std::path::Path;
+
+
+pub fn merge(base: String, ours: String, theirs: String, path: String) -> Result<()> {
+    // the clobs are always stored as UTF-8, so anything else cannot be merged (without 
+    // corrupting it)
+    let read = |file: &str| -> Result<String> {
+        let data = std::fs::read(file).map_err(|err| {
+            error::FileReadError {
+                path : file.into(),
+                msg  : err.to_string()
+            }
+        })?;
+
+        Ok(
+            String::from_utf8(data).map_err(|err| {
+                error::FileReadError {
+                    path : path.clone().into(),
+                    msg  : format!("not valid UTF-8 text at byte {}", err.utf8_error().valid_up_to())
+                }
+            })?
+        )
+    };
+
+    let repo = Repository::open()?;
+    let config = repo.config();
+
+    // the managed file itself (the merged managed folder is not known yet, so the placeholder
+    // does not name it until the file is staged again)
+    if let Ok(cfg) = config.dictionary_by_path(&path) {
+        return write(&ours, &config.managed_file_text(cfg));
+    }
+
+    // a clob in a managed folder
+    let cfg = config.dictionaries.iter().find(|cfg| {
+        Path::new(&path).starts_with(format!("{}.contents", cfg.path))
+    })
+    .ok_or_else(|| {
+        error::NotAManagedFile {
+            path : path.clone().into()
+        }
+    })?;
+
+    // the record checksums are merged record by record as well
+    if Path::new(&path).file_name() == Some(MANIFEST_FILE.as_ref()) {
+        return write(&ours, &merge_manifests(&read(&base)?, &read(&ours)?, &read(&theirs)?));
+    }
+
+    let result = merge_records(&cfg.record_tags, &read(&base)?, &read(&ours)?, &read(&theirs)?);
+    write(&ours, &result.text)?;
+
+    if result.conflicts > 0 {
+        stderr!("git-toolbox: {} conflicting records in {}", result.conflicts, path);
+        std::process::exit(1);
+    }
+
+    Ok( () )
+}
+
+fn write(path: &str, text: &str) -> Result<()> {
+    std::fs::write(path, text).map_err(|err| {
+        error::FileWriteError {
+            path : path.into(),
+            msg  : err.to_string()
+        }
+        .into()
+    })
+}
@@ -0,0 +1,97 @@
+//
+// src/mv.rs
+//
+// Implementation of git-toolbox mv
+//
+// Moves a managed file along with its managed folder and updates its path in the
+// configuration file. The repository is then configured again, which stages the
+// configuration file and updates the git attributes and the placeholder text of
+// the moved file. Committing the result records the records as moved, so that
+// their history is kept.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::repository::Repository;
+use crate::config::{CONFIG_FILE, move_dictionary_in_config};
+use crate::cli_app::style;
+
+use crate::error;
+use anyhow::{Result, bail};
+
+
+pub fn mv(old: String, new: String) -> Result<()> {
+    let repo = Repository::open()?;
+    let workdir = repo.workdir()?.to_owned();
+
+    let old = repo.get_path_relative_to_repo(&old)?.to_string_lossy().into_owned();
+    let cfg = repo.config().dictionary_by_path(&old)?;
+
+    // a directory as the target keeps the file name (as with git mv)
+    let mut new = repo.get_path_relative_to_repo(&new)?;
+    if workdir.join(&new).is_dir() {
+        new = new.join(std::path::Path::new(&old).file_name().expect("fatal: managed file without a name"));
+    }
+    let new = new.to_string_lossy().into_owned();
+
+    // the target must be free
+    let taken = [new.clone(), format!("{}.contents", new)].iter().find(|path| {
+        workdir.join(path).exists()
+    })
+    .cloned()
+    .or_else(|| Some(new.clone()).filter(|new| repo.config().dictionaries.iter().any(|cfg| &cfg.path == new)));
+
+    if let Some(path) = taken {
+        bail!(
+            error::MoveTargetExists {
+                path
+            }
+        );
+    }
+
+    // update the configuration file text
+    let config_path = workdir.join(CONFIG_FILE);
+    let config_text = std::fs::read_to_string(&config_path).map_err(|err| {
+        error::FileReadError {
+            path : config_path.clone(),
+            msg  : err.to_string()
+        }
+    })?;
+
+    let (config_text, changed) = move_dictionary_in_config(&config_text, &cfg.path, &new);
+    if changed == 0 {
+        bail!(
+            error::PathPatternDictionary {
+                path : cfg.path.clone()
+            }
+        );
+    }
+
+    {
+        // no other git-toolbox operations while we are modifying the index
+        let _lock = repo.lock_operation("mv")?;
+
+        repo.move_managed_file(&old, &new)?;
+
+        std::fs::write(&config_path, config_text).map_err(|err| {
+            error::FileWriteError {
+                path : config_path.clone(),
+                msg  : err.to_string()
+            }
+        })?;
+    }
+
+    stdout!("{} Moved {} to {}", style("✓").green(), style(&old).bold(), style(&new).bold());
+
+    // stage the configuration and update the git attributes
+    Repository::configure()?;
+
+    stdout!("\n✅  {} is now managed as {}. Use {} to record the move",
+        style(&old).bold(),
+        style(&new).bold(),
+        style("\"git commit\"").bold()
+    );
+
+    Ok( () )
+}
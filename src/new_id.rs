@@ -0,0 +1,112 @@
+//
+// src/new_id.rs
+//
+// Implementation of git-toolbox new-id
+//
+// Allocates new record IDs, so that the contributors do not have to agree on
+// them by other means. The IDs used in the working copy, the index and HEAD
+// are all taken into account (a record might be deleted in the working copy
+// but still be staged or committed) and the new IDs continue after the highest
+// numeric ID of the namespace. The namespace is the one of the configured user
+// (see git toolbox stage), or the public one for the users without a namespace.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::repository::Repository;
+use crate::toolbox::{Dictionary, ClobOrigin, clob_path_for_id};
+use crate::config::DictionaryConfig;
+
+use crate::error;
+use anyhow::{Result, bail};
+
+
+pub fn new_id(path: String, count: usize, namespace: Option<String>) -> Result<()> {
+    let repo = Repository::open()?;
+
+    // locate the dictionary
+    let repo_path = repo.get_path_relative_to_repo(&path)?.to_string_lossy().into_owned();
+    let cfg = repo.config().dictionary_by_path(&repo_path)?;
+
+    if !cfg.unique_id {
+        bail!(
+            error::DictionaryWithoutIds {
+                path : cfg.path.clone()
+            }
+        );
+    }
+
+    // the namespace of the new ids ('public' for the public ids)
+    let namespace = namespace
+        .or_else(|| repo.current_user().and_then(|user| user.namespace.clone()))
+        .unwrap_or_else(|| "public".to_owned());
+    let prefix = if namespace == "public" { "" } else { namespace.as_str() };
+
+    // the numeric ids used in the namespace (with the width of the widest zero-padded id)
+    let mut last = 0;
+    let mut width = 0;
+    for text in dictionary_versions(&repo, cfg)? {
+        let dictionary = Dictionary::from_text(cfg, text);
+
+        for (id_namespace, id) in dictionary.record_ids().iter().filter_map(|id| id.parsed) {
+            if id_namespace.unwrap_or("public") != namespace { continue }
+
+            if let Ok(number) = id.parse::<u64>() {
+                last = last.max(number);
+                if id.starts_with('0') {
+                    width = width.max(id.len());
+                }
+            }
+        }
+    }
+
+    let ids = (last + 1 ..= last + count as u64)
+        .map(|number| format!("{}{:0width$}", prefix, number, width = width))
+        .collect::<Vec<_>>();
+
+    // the new ids must be valid ids of the namespace
+    for id in ids.iter() {
+        let valid = clob_path_for_id(cfg, id).map(|clob_path| {
+            ClobOrigin::from_path(&clob_path).is_in_namespace(&namespace)
+        })
+        .unwrap_or(false);
+
+        if !valid {
+            bail!(
+                error::InvalidRecordId {
+                    id   : id.clone(),
+                    path : cfg.path.clone()
+                }
+            );
+        }
+    }
+
+    for id in ids {
+        stdout!("{}", id);
+    }
+
+    Ok( () )
+}
+
+
+// The texts of the dictionary in the working copy, the index and HEAD
+//
+// The versions that do not exist (e.g. before the first commit) are skipped
+fn dictionary_versions(repo: &Repository, cfg: &DictionaryConfig) -> Result<Vec<String>> {
+    let contents_path = format!("{}.contents", cfg.path);
+
+    let mut versions = vec!();
+
+    if repo.workdir()?.join(&cfg.path).exists() {
+        versions.push(Dictionary::load(repo, cfg, false)?.text().to_owned());
+    }
+
+    for rev in ["", "HEAD"] {
+        if let Ok(data) = Repository::reconstruct(&contents_path, rev, &cfg.sort) {
+            versions.push(String::from_utf8_lossy(&data).into_owned());
+        }
+    }
+
+    Ok( versions )
+}
@@ -0,0 +1,97 @@
+//
+// src/new_record.rs
+//
+// Implementation of git-toolbox new-record
+//
+// Appends a new record to a managed toolbox file. The record follows the
+// template of the dictionary (if one is configured).
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::repository::Repository;
+use crate::toolbox::template;
+use crate::cli_app::style;
+
+use crate::error;
+use anyhow::{Result, bail};
+
+
+pub fn new_record(path: String, label: Option<String>, id: Option<String>) -> Result<()> {
+    use std::io::Write;
+
+    // load the repository
+    let repo = Repository::open()?;
+
+    // no other git-toolbox operations while we are modifying the repository
+    let _lock = repo.lock_operation("new-record")?;
+
+    // locate the dictionary
+    let repo_path = repo.get_path_relative_to_repo(&path)?.to_string_lossy().into_owned();
+    let cfg = repo.config().dictionary_by_path(&repo_path)?;
+
+    // validate the id
+    if let Some(id) = &id {
+        if !cfg.unique_id {
+            bail!(
+                error::DictionaryWithoutIds {
+                    path : cfg.path.clone()
+                }
+            );
+        }
+
+        let valid = cfg.id_spec.find(id).map(|m| m.as_str() == id).unwrap_or(false);
+
+        if !valid {
+            bail!(
+                error::InvalidRecordId {
+                    id   : id.clone(),
+                    path : cfg.path.clone()
+                }
+            );
+        }
+    }
+
+    // build the record (an empty id tag is added if no id was given)
+    let id = if cfg.unique_id { Some(id.unwrap_or_default()) } else { None };
+    let record = template::new_record(cfg, label.as_deref().unwrap_or(""), id.as_deref());
+
+    // and append it to the file
+    let file_path = repo.workdir()?.join(&cfg.path);
+    let existing = match std::fs::read(&file_path) {
+        Ok(data) => cfg.decode_text(&file_path, data)?,
+        Err(_)   => String::new()
+    };
+
+    // records are separated by a blank line
+    let separator = match existing.as_str() {
+        ""                               => "",
+        text if text.ends_with("\n\n")   => "",
+        text if text.ends_with('\n')     => "\n",
+        _                                => "\n\n"
+    };
+
+    // the record is written in the encoding of the file
+    let data = cfg.encode_text(format!("{}{}", separator, record).into_bytes())?;
+
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&file_path)
+        .and_then(|mut file| file.write_all(&data))
+        .map_err(|err| {
+            error::FileWriteError {
+                path : file_path.clone(),
+                msg  : err.to_string()
+            }
+        })?;
+
+    stdout!("{} Added a new record to {}", style("✓").green(), style(&cfg.path).italic());
+    stdout!("");
+    for line in record.lines() {
+        stdout!("    {}", style(line).cyan());
+    }
+
+    Ok( () )
+}
@@ -0,0 +1,125 @@
+//
+// src/open.rs
+//
+// Implementation of git-toolbox open
+//
+// Locates a record by its ID or label in the working copies of the managed
+// toolbox files and opens the file in an editor at the first line of the
+// record. The editor is taken from git config (toolbox.editor), VISUAL or
+// EDITOR. If the editor command contains {line}, the line number is put there,
+// otherwise the common "+<line>" argument is used.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::repository::Repository;
+use crate::toolbox::Dictionary;
+use crate::config::DictionaryConfig;
+use crate::cli_app::style;
+
+use crate::error;
+use anyhow::{Result, bail};
+
+
+pub fn open(name: String, paths: Vec<String>) -> Result<()> {
+    // open the repository
+    let repo = Repository::open()?;
+
+    // dictionary selection
+    let dictionaries : Vec<&DictionaryConfig> = if paths.is_empty() {
+        repo.config().dictionaries.iter().collect()
+    } else {
+        paths.iter().map(|path| {
+            // convert the path to one relative to the repo
+            let path = repo.get_path_relative_to_repo(path)?.to_string_lossy().into_owned();
+
+            repo.config().dictionary_by_path(path)
+        })
+        .collect::<Result<Vec<_>>>()?
+    };
+
+    // locate the records (file path and line number)
+    let mut locations = vec!();
+
+    for cfg in dictionaries {
+        let dictionary = Dictionary::load(&repo, cfg, false)?;
+        let records = dictionary.records();
+
+        for record in records {
+            let id = cfg.id_tag.as_ref().and_then(|tag| record.value(tag));
+            let label = record.label();
+
+            if id == Some(name.as_str()) || label == Some(name.as_str()) {
+                locations.push((cfg.path.clone(), record.line.line + 1));
+            }
+        }
+    }
+
+    let (path, line) = match locations.first() {
+        Some( location ) => location.clone(),
+        None             => bail!(error::RecordNotFound { name })
+    };
+
+    if locations.len() > 1 {
+        stdout!("{}  {} records match {}, opening the first one. The other records are at:",
+            style("⚠️").yellow(),
+            locations.len(),
+            style(&name).bold()
+        );
+        for (path, line) in locations.iter().skip(1) {
+            stdout!("        {}:{}", path, line);
+        }
+    }
+
+    // the editor command
+    let editor = repo.configured_editor()
+        .or_else(|| std::env::var("VISUAL").ok())
+        .or_else(|| std::env::var("EDITOR").ok())
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_owned());
+
+    // the editor command is split into words the way the shell would do it, so 
+    // that we can run the editor directly (there might be no shell on Windows)
+    let mut command = shell_words::split(&editor).map_err(|err| {
+        error::EditorFailed {
+            editor : editor.clone(),
+            msg    : err.to_string()
+        }
+    })?;
+
+    if command.is_empty() {
+        command.push("vi".to_owned());
+    }
+
+    if editor.contains("{line}") {
+        for word in command.iter_mut() {
+            *word = word.replace("{line}", &line.to_string());
+        }
+    } else {
+        command.push(format!("+{}", line));
+    }
+
+    let file_path = repo.workdir()?.join(&path);
+    let status = std::process::Command::new(&command[0])
+        .args(&command[1..])
+        .arg(&file_path)
+        .status()
+        .map_err(|err| {
+            error::EditorFailed {
+                editor : editor.clone(),
+                msg    : err.to_string()
+            }
+        })?;
+
+    if !status.success() {
+        bail!(
+            error::EditorFailed {
+                editor,
+                msg    : status.to_string()
+            }
+        );
+    }
+
+    Ok( () )
+}
@@ -0,0 +1,116 @@
+//
+// src/output.rs
+//
+// Output formats of the commands
+//
+// The commands that report on the managed files (status, stage, reset, diff and
+// verify) describe their results with a serializable report, which is written
+// out in the format selected with --format:
+//
+// - human: styled text for the terminal (the default)
+// - json: the report as a single-line JSON document
+// - porcelain: stable, tab-separated lines for scripts
+// - quiet: nothing (the exit code and the errors on stderr only)
+//
+// The progress messages printed with `stdout!` are only shown in the human
+// format, so the commands do not need to check the format themselves and a new
+// format only needs to be added here.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use serde::Serialize;
+use anyhow::Result;
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+
+/// The output format of the commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Human,
+    Json,
+    Porcelain,
+    Quiet
+}
+
+/// The names of the output formats (as given to --format)
+pub const OUTPUT_FORMATS : [&str; 4] = ["human", "json", "porcelain", "quiet"];
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> std::result::Result<Self, Self::Err> {
+        match name {
+            "human"     => Ok( OutputFormat::Human ),
+            "json"      => Ok( OutputFormat::Json ),
+            "porcelain" => Ok( OutputFormat::Porcelain ),
+            "quiet"     => Ok( OutputFormat::Quiet ),
+            _           => Err( format!("unknown output format '{}'", name) )
+        }
+    }
+}
+
+static OUTPUT_FORMAT : AtomicU8 = AtomicU8::new(0);
+
+/// The selected output format
+pub fn output_format() -> OutputFormat {
+    match OUTPUT_FORMAT.load(Ordering::Relaxed) {
+        1 => OutputFormat::Json,
+        2 => OutputFormat::Porcelain,
+        3 => OutputFormat::Quiet,
+        _ => OutputFormat::Human
+    }
+}
+
+/// Select the output format
+pub fn set_output_format(format: OutputFormat) {
+    let value = match format {
+        OutputFormat::Human     => 0,
+        OutputFormat::Json      => 1,
+        OutputFormat::Porcelain => 2,
+        OutputFormat::Quiet     => 3
+    };
+
+    OUTPUT_FORMAT.store(value, Ordering::Relaxed)
+}
+
+/// Whether the text for the terminal is shown
+pub fn human_output() -> bool {
+    output_format() == OutputFormat::Human
+}
+
+
+/// The results of a command
+pub trait Report : Serialize {
+    /// Print the results for the terminal
+    fn display(&self);
+
+    /// The lines of the porcelain format, as lists of fields
+    ///
+    /// Every line starts with the kind of the line. The fields must stay stable, new
+    /// kinds of lines can be added
+    fn porcelain(&self) -> Vec<Vec<String>>;
+}
+
+/// Write out the report in the selected output format
+pub fn emit<R: Report>(report: &R) -> Result<()> {
+    match output_format() {
+        OutputFormat::Human     => report.display(),
+        OutputFormat::Json      => println!("{}", serde_json::to_string(report)?),
+        OutputFormat::Porcelain => {
+            for line in report.porcelain() {
+                println!("{}", line.iter().map(|field| porcelain_field(field)).collect::<Vec<_>>().join("\t"));
+            }
+        },
+        OutputFormat::Quiet     => {}
+    }
+
+    Ok( () )
+}
+
+// The porcelain fields cannot contain the separators (or styling)
+fn porcelain_field(text: &str) -> String {
+    console::strip_ansi_codes(text).replace(['\t', '\n'], " ")
+}
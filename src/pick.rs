@@ -0,0 +1,191 @@
+//
+// src/pick.rs
+//
+// Implementation of git-toolbox pick
+//
+// Takes a record (identified by its ID) as it is stored in another revision,
+// e.g. on a colleague's branch, and splices it into the working copy of the
+// dictionary: the record replaces the working version of the record, or is
+// appended if the dictionary does not have it yet. As with import, only the
+// working copy is changed, so that the result can be reviewed before it is
+// staged.
+//
+// If the record was also changed locally (the working version differs from the
+// committed one), picking it would discard the local edits. This is reported as
+// a conflict and the record is only replaced with --force.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::repository::Repository;
+use crate::toolbox::{Dictionary, Record, clob_paths_for_id};
+use crate::toolbox::template::{self, RecordChange};
+use crate::config::DictionaryConfig;
+use crate::cli_app::style;
+use crate::diff::display_field_change;
+
+use crate::error;
+use anyhow::{Result, bail};
+
+
+pub fn pick(rev: String, path: String, id: String, force: bool) -> Result<()> {
+    // load the repository
+    let repo = Repository::open()?;
+
+    // no other git-toolbox operations while we are modifying the repository
+    let _lock = repo.lock_operation("pick")?;
+
+    // locate the dictionary
+    let repo_path = repo.get_path_relative_to_repo(&path)?.to_string_lossy().into_owned();
+    let cfg = repo.config().dictionary_by_path(&repo_path)?;
+    let id = id.trim();
+
+    if !cfg.unique_id || clob_paths_for_id(cfg, id).is_empty() {
+        bail!(
+            error::InvalidRecordId {
+                id   : id.to_owned(),
+                path : cfg.path.clone()
+            }
+        )
+    }
+
+    // the picked record and the committed record (if any)
+    let picked = match record_at_rev(&repo, cfg, id, &rev)? {
+        Some(record) => record,
+        None         => bail!(error::RecordNotFound { name : id.to_owned() })
+    };
+    let committed = record_at_rev(&repo, cfg, id, "HEAD")?;
+
+    // the working copy of the dictionary
+    let dictionary = Dictionary::load(&repo, cfg, false)?;
+    let text = dictionary.text();
+    let records = dictionary.records();
+    let working = records.iter().find(|record| record_id(cfg, record) == Some(id));
+
+    let working_text = working.map(|record| record.text.trim_end().replace("\r\n", "\n"));
+    if working_text.as_deref() == Some(picked.as_str()) {
+        stdout!("{} The record {} of {} is already the same as in {}",
+            style("✓").green(),
+            style(id).bold(),
+            style(&cfg.path).italic(),
+            style(&rev).bold()
+        );
+        return Ok( () )
+    }
+
+    // the local edits would be lost
+    let changed_locally = working_text.as_deref() != committed.as_deref();
+    if changed_locally && !force {
+        display_changes(cfg, committed.as_deref().unwrap_or_default(), working_text.as_deref().unwrap_or_default());
+
+        bail!(
+            error::PickConflict {
+                id   : id.to_owned(),
+                path : cfg.path.clone(),
+                rev  : rev.clone()
+            }
+        )
+    }
+
+    // splice the record into the working copy (keeping the line ends)
+    let newline = if text.contains("\r\n") { "\r\n" } else { "\n" };
+    let picked_text = picked.lines().map(|line| format!("{}{}", line, newline)).collect::<String>();
+
+    let mut merged = match working {
+        Some(record) => {
+            let start = record.text.as_ptr() as usize - text.as_ptr() as usize;
+            let end = start + record.text.trim_end().len();
+
+            // the spacing after the record is kept
+            format!("{}{}{}", &text[.. start], picked_text.trim_end(), &text[end ..])
+        },
+        None => {
+            // the new record is appended (separated by an empty line)
+            let mut merged = text.to_owned();
+            if !merged.is_empty() && !merged.ends_with('\n') {
+                merged.push_str(newline);
+            }
+            merged.push_str(newline);
+            merged.push_str(&picked_text);
+
+            merged
+        }
+    };
+    if dictionary.bom() {
+        merged.insert(0, '\u{feff}');
+    }
+
+    // the file is written in its encoding
+    let file_path = repo.workdir()?.join(&cfg.path);
+    let data = cfg.encode_text(merged.into_bytes())?;
+    std::fs::write(&file_path, data).map_err(|err| {
+        error::FileWriteError {
+            path : file_path.clone(),
+            msg  : err.to_string()
+        }
+    })?;
+
+    match working_text.as_deref() {
+        Some(working_text) => display_changes(cfg, working_text, &picked),
+        None => for line in picked.lines() {
+            stdout!("      {} {}", style("+").green(), style(line).green());
+        }
+    }
+    stdout!("");
+    stdout!("{} {} the record {} of {} from {}",
+        style("✓").green(),
+        if working.is_some() { "Replaced" } else { "Added" },
+        style(id).bold(),
+        style(&cfg.path).italic(),
+        style(&rev).bold()
+    );
+    if changed_locally {
+        stdout!("  (the local changes to the record were discarded)");
+    }
+    stdout!("  (the changes are not staged, use {} to review them and {} to stage them)",
+        style("git toolbox diff").bold(),
+        style("git toolbox stage").bold()
+    );
+
+    Ok( () )
+}
+
+
+// The text of the record with the ID in the revision (if it has the record)
+fn record_at_rev(repo: &Repository, cfg: &DictionaryConfig, id: &str, rev: &str) -> Result<Option<String>> {
+    // the record might be of any of the record types
+    for clob_path in clob_paths_for_id(cfg, id) {
+        let text = match repo.read_clob_from_rev(format!("{}.contents/{}", cfg.path, clob_path), rev)? {
+            Some(text) => text,
+            None       => continue
+        };
+
+        let clob = Dictionary::from_text(cfg, text);
+        let record = clob.records().into_iter()
+            .find(|record| record_id(cfg, record) == Some(id))
+            .map(|record| record.text.trim_end().to_owned());
+
+        if record.is_some() {
+            return Ok( record )
+        }
+    }
+
+    Ok( None )
+}
+
+// The ID of the record
+fn record_id<'a>(cfg: &DictionaryConfig, record: &'a Record) -> Option<&'a str> {
+    cfg.id_tag.as_deref().and_then(|tag| record.value(tag)).map(|id| id.trim())
+}
+
+// The changed fields of the record
+fn display_changes(cfg: &DictionaryConfig, old: &str, new: &str) {
+    for change in template::record_changes(cfg, old, new) {
+        if let RecordChange::Modified { changes, .. } = change {
+            for change in changes.iter() {
+                stdout!("      {}", display_field_change(change));
+            }
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//
+// src/profile.rs
+//
+// Timing instrumentation (--profile)
+//
+// The time spent in the individual processing phases is collected per
+// dictionary and reported once the command has finished, so that performance
+// problems can be pinned down with actual numbers. Nothing is measured unless
+// profiling was enabled.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::cli_app::style;
+
+use std::time::{Duration, Instant};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// A processing phase of a dictionary
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// reading and decoding the dictionary file
+    Load,
+    /// tokenizing the dictionary text
+    Scan,
+    /// splitting the dictionary into records
+    Split,
+    /// validating the managed folder in the working directory
+    WorkdirValidation,
+    /// comparing the records against the index
+    Diff,
+    /// updating the git index
+    IndexUpdate
+}
+
+const PHASES : [Phase; 6] = [
+    Phase::Load,
+    Phase::Scan,
+    Phase::Split,
+    Phase::WorkdirValidation,
+    Phase::Diff,
+    Phase::IndexUpdate
+];
+
+impl Phase {
+    fn name(&self) -> &'static str {
+        match self {
+            Phase::Load              => "load",
+            Phase::Scan              => "scan",
+            Phase::Split             => "split",
+            Phase::WorkdirValidation => "validation",
+            Phase::Diff              => "diff",
+            Phase::IndexUpdate       => "index"
+        }
+    }
+}
+
+struct Profile {
+    // start of the command
+    started : Instant,
+    // (dictionary path, phase, time spent)
+    timings : Vec<(String, Phase, Duration)>
+}
+
+lazy_static::lazy_static! {
+    static ref PROFILING : AtomicBool = AtomicBool::new(false);
+    static ref PROFILE   : Mutex<Profile> = Mutex::new(Profile { started: Instant::now(), timings: vec!() });
+}
+
+/// Whether the timings are collected
+pub fn enabled() -> bool {
+    PROFILING.load(Ordering::Relaxed)
+}
+
+/// Start collecting the timings
+pub fn enable() {
+    if let Ok(mut profile) = PROFILE.lock() {
+        profile.started = Instant::now();
+    }
+
+    PROFILING.store(true, Ordering::Relaxed)
+}
+
+/// Record the time spent in a phase for a dictionary
+///
+/// The dictionary is identified by its path (or by the path to its managed folder)
+pub fn record<P: AsRef<str>>(dictionary: P, phase: Phase, elapsed: Duration) {
+    if !enabled() { return }
+
+    let dictionary = dictionary.as_ref();
+    let dictionary = dictionary.strip_suffix(".contents").unwrap_or(dictionary);
+
+    if let Ok(mut profile) = PROFILE.lock() {
+        profile.timings.push((dictionary.to_owned(), phase, elapsed));
+    }
+}
+
+/// Run the closure and record the time spent in it
+pub fn measure<P, F, T>(dictionary: P, phase: Phase, f: F) -> T
+where
+    P : AsRef<str>,
+    F : FnOnce() -> T
+{
+    if !enabled() { return f() }
+
+    let start = Instant::now();
+    let result = f();
+    record(dictionary, phase, start.elapsed());
+
+    result
+}
+
+/// Print the collected timings (to stderr, so that the command output is not affected)
+pub fn report() {
+    if !enabled() { return }
+
+    let profile = match PROFILE.lock() {
+        Ok( profile ) => profile,
+        Err( _ )      => return
+    };
+
+    // the dictionaries in the order they were first processed
+    let mut dictionaries : Vec<&str> = vec!();
+    for (dictionary, _, _) in profile.timings.iter() {
+        if !dictionaries.contains(&dictionary.as_str()) {
+            dictionaries.push(dictionary);
+        }
+    }
+
+    let width = dictionaries.iter().map(|d| d.chars().count()).chain(Some(10)).max().unwrap_or(10);
+
+    stderr!("");
+    stderr!("{}", style("Profile (times in milliseconds):").bold());
+    stderr!("");
+    stderr!("    {:<width$} {}", "dictionary",
+        PHASES.iter().map(|phase| format!("{:>12}", phase.name())).collect::<String>(),
+        width = width
+    );
+
+    for dictionary in dictionaries {
+        let columns = PHASES.iter().map(|phase| {
+            let times = profile.timings.iter()
+                .filter(|(d, p, _)| d == dictionary && p == phase)
+                .map(|(_, _, elapsed)| *elapsed)
+                .collect::<Vec<_>>();
+
+            if times.is_empty() {
+                format!("{:>12}", "-")
+            } else {
+                format!("{:>12.1}", millis(times.into_iter().sum()))
+            }
+        })
+        .collect::<String>();
+
+        stderr!("    {:<width$} {}", dictionary, columns, width = width);
+    }
+
+    stderr!("");
+    stderr!("    total time: {:.1} ms", millis(profile.started.elapsed()));
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
@@ -0,0 +1,94 @@
+//
+// src/query.rs
+//
+// Implementation of git-toolbox query
+//
+// Lists the records of the managed toolbox files that match a query (see
+// src/toolbox/query.rs for the query language). The IDs of the matching records
+// (or their first line if the dictionary does not use IDs) are printed one per
+// line, which makes the output easy to process further. With --full, the full
+// record texts are printed instead.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::repository::Repository;
+use crate::toolbox::{Dictionary, Record};
+use crate::toolbox::query::Query;
+use crate::config::DictionaryConfig;
+
+use crate::error;
+use anyhow::Result;
+
+
+pub fn query(text: String, paths: Vec<String>, full: bool, count: bool) -> Result<()> {
+    // parse the query first, so that errors are reported before any work is done
+    let query = Query::parse(&text).map_err(|err| {
+        error::InvalidQuery {
+            query  : text.clone(),
+            offset : err.offset,
+            msg    : err.msg
+        }
+    })?;
+
+    // open the repository
+    let repo = Repository::open()?;
+
+    // dictionary selection
+    let dictionaries : Vec<&DictionaryConfig> = if paths.is_empty() {
+        repo.config().dictionaries.iter().collect()
+    } else {
+        paths.iter().map(|path| {
+            // convert the path to one relative to the repo
+            let path = repo.get_path_relative_to_repo(path)?.to_string_lossy().into_owned();
+
+            repo.config().dictionary_by_path(path)
+        })
+        .collect::<Result<Vec<_>>>()?
+    };
+
+    // like grep, the output is prefixed by the file path when searching multiple files
+    let prefixed = dictionaries.len() > 1;
+
+    for cfg in dictionaries {
+        let dictionary = Dictionary::load(&repo, cfg, false)?;
+        let records = dictionary.records();
+        let matches = records.iter().filter(|record| query.matches(record));
+
+        // the record contents are printed as they are (no ASCII replacements)
+        if count {
+            let n = matches.count();
+
+            if prefixed {
+                println!("{}:{}", cfg.path, n);
+            } else {
+                println!("{}", n);
+            }
+        } else if full {
+            for record in matches {
+                println!("{}", record.text.trim_end());
+                println!();
+            }
+        } else {
+            for record in matches {
+                if prefixed {
+                    println!("{}:{}", cfg.path, record_name(cfg, record));
+                } else {
+                    println!("{}", record_name(cfg, record));
+                }
+            }
+        }
+    }
+
+    Ok( () )
+}
+
+
+// The ID of the record (or its first line if there is no ID)
+fn record_name<'a>(cfg: &DictionaryConfig, record: &'a Record) -> &'a str {
+    cfg.id_tag.as_ref()
+        .and_then(|id_tag| record.value(id_tag))
+        .filter(|id| !id.is_empty())
+        .unwrap_or_else(|| record.line.text.trim())
+}
@@ -0,0 +1,156 @@
+//
+// src/reconcile.rs
+//
+// Implementation of git-toolbox reconcile
+//
+// When a dictionary was edited outside of git (e.g. offline in Toolbox) while
+// its managed folder advanced upstream, neither discarding the edits nor
+// overwriting the upstream changes is acceptable. This command merges the
+// working copy of the dictionary with the upstream version, using the version
+// the edits were based on (the index, unless another revision is given) as the
+// common ancestor. The versions are compared clob by clob and the clobs changed
+// on both sides are merged record by record, as done by the merge driver.
+//
+// The merged dictionary is written to the working directory, with conflicting
+// records between conflict markers.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::repository::{Repository, Clob, assemble_clobs};
+use crate::toolbox::Dictionary;
+use crate::toolbox::merge::merge_records;
+use crate::config::DictionaryConfig;
+use crate::cli_app::style;
+
+use crate::error;
+use anyhow::{Result, bail};
+
+use std::collections::{BTreeMap, BTreeSet};
+
+
+/// The clobs of a dictionary version by their paths
+pub type ClobMap = BTreeMap<String, String>;
+
+/// The outcome of reconciling a dictionary
+#[derive(Default)]
+pub struct ReconcileSummary {
+    // the clobs taken from the working copy
+    pub ours      : usize,
+    // the clobs taken from upstream
+    pub theirs    : usize,
+    // the clobs merged record by record
+    pub merged    : usize,
+    // the number of conflicting records
+    pub conflicts : usize
+}
+
+
+pub fn reconcile(path: String, upstream: String, base: Option<String>) -> Result<()> {
+    let repo = Repository::open()?;
+
+    // no other git-toolbox operations while we are modifying the working directory
+    let _lock = repo.lock_operation("reconcile")?;
+
+    let path = repo.get_path_relative_to_repo(&path)?.to_string_lossy().into_owned();
+    let cfg = repo.config().dictionary_by_path(&path)?;
+    let contents_path = format!("{}.contents", cfg.path);
+
+    // the three versions of the dictionary
+    let dictionary = Dictionary::load(&repo, cfg, false)?;
+    let ours = split(&dictionary);
+    let base = read_clobs(cfg, &contents_path, base.as_deref().unwrap_or(""))?;
+    let theirs = read_clobs(cfg, &contents_path, &upstream)?;
+
+    let (clobs, summary) = merge_clobs(cfg, &base, &ours, &theirs);
+
+    // write the merged dictionary
+    let absolute_path = repo.workdir()?.to_owned().join(&cfg.path);
+    let content = cfg.encode_text(cfg.space_records(cfg.adapt_header(assemble_clobs(clobs, &cfg.sort))))?;
+
+    std::fs::write(&absolute_path, &content).map_err(|err| {
+        error::FileWriteError {
+            path : absolute_path,
+            msg  : err.to_string()
+        }
+    })?;
+
+    stdout!("{} Reconciled {} with {}", style("✓").green(), style(&cfg.path).bold(), style(&upstream).bold());
+    stdout!("");
+    stdout!("        {:>6} entry files kept from the working copy", summary.ours);
+    stdout!("        {:>6} entry files taken from {}", summary.theirs, upstream);
+    stdout!("        {:>6} entry files merged entry by entry", summary.merged);
+    stdout!("");
+
+    if summary.conflicts > 0 {
+        bail!(
+            error::ReconcileConflicts {
+                path      : cfg.path.clone(),
+                conflicts : summary.conflicts
+            }
+        );
+    }
+
+    stdout!("✅ Use {} to add the reconciled dictionary to be commited.",
+        style("\"git toolbox stage\"").bold()
+    );
+
+    Ok( () )
+}
+
+
+/// The clobs of the dictionary by their paths
+pub fn split(dictionary: &Dictionary) -> ClobMap {
+    dictionary.split().0.map(|clob| (clob.path, clob.content)).collect()
+}
+
+/// The clobs of the dictionary stored in the revision (empty for the index)
+pub fn read_clobs(cfg: &DictionaryConfig, contents_path: &str, rev: &str) -> Result<ClobMap> {
+    let text = String::from_utf8_lossy(&Repository::reconstruct(contents_path, rev, &cfg.sort)?).into_owned();
+
+    Ok( split(&Dictionary::from_text(cfg, text)) )
+}
+
+/// Merge the clobs of the working copy and upstream with their common ancestor
+pub fn merge_clobs(
+    cfg: &DictionaryConfig, base: &ClobMap, ours: &ClobMap, theirs: &ClobMap
+) -> (Vec<Clob>, ReconcileSummary) {
+    let mut summary = ReconcileSummary::default();
+    let mut clobs = vec!();
+
+    let paths = base.keys().chain(ours.keys()).chain(theirs.keys()).collect::<BTreeSet<_>>();
+
+    for path in paths {
+        let (b, o, t) = (base.get(path), ours.get(path), theirs.get(path));
+
+        let content = if o == t || b == t {
+            // unchanged upstream (or changed the same way)
+            if o != b { summary.ours += 1 }
+            o.cloned()
+        } else if b == o {
+            // only changed upstream
+            summary.theirs += 1;
+            t.cloned()
+        } else {
+            // changed on both sides
+            let empty = String::new();
+            let result = merge_records(
+                &cfg.record_tags,
+                b.unwrap_or(&empty),
+                o.unwrap_or(&empty),
+                t.unwrap_or(&empty)
+            );
+
+            summary.merged += 1;
+            summary.conflicts += result.conflicts;
+            Some(result.text).filter(|text| !text.trim().is_empty())
+        };
+
+        if let Some(content) = content {
+            clobs.push(Clob { path : path.clone(), content });
+        }
+    }
+
+    (clobs, summary)
+}
@@ -11,18 +11,129 @@
 use std::io::Write;
 
 use crate::repository::Repository;
+use crate::config::DictionaryConfig;
 
-use anyhow::Result;
+use anyhow::{Result, bail};
 use crate::error;
 
-pub fn reconstruct<P : AsRef<str>,>(pathspec: P, bare: bool) -> Result<()>  {
+pub fn reconstruct<P : AsRef<str>,>(
+    pathspec: P, bare: bool, merge_base: Option<(String, String)>
+) -> Result<()>  {
+    // stream it all to stdout
+    let stdout = std::io::stdout();
+    let mut out = std::io::BufWriter::new(stdout.lock());
+
+    write_reconstructed(pathspec, bare, merge_base, &mut out)?;
+    out.flush().expect("fatal - stdout error");
     
-    // split up the the path into revision and the actual path
-    let (rev, path) = parse_path_spec(pathspec.as_ref())?;
+    Ok( () )
+}
 
-    // get the path relative to the repository root
-    let path = Repository::get_path_relative_to_repo_here(path)?
-        .to_string_lossy().into_owned();
+/// A record to show instead of the entire file
+pub enum RecordSelection {
+    /// The records with the ID (in dictionaries with unique IDs)
+    Id(String),
+    /// The records with the label (in the other dictionaries)
+    Label(String)
+}
+
+/// Print the records of a managed toolbox file with the given ID or label
+///
+/// Only the clob that holds the records is retrieved, not the entire file
+pub fn show_record<P : AsRef<str>,>(
+    pathspec: P, merge_base: Option<(String, String)>, selection: RecordSelection
+) -> Result<()>  {
+    use crate::toolbox::{Dictionary, clob_paths_for_id, clob_paths_for_label};
+
+    let (rev, path) = resolve_path_spec(pathspec.as_ref(), merge_base)?;
+
+    let repo = Repository::open()?;
+    let cfg = repo.config().dictionary_by_path(&path)?;
+
+    let (name, clob_paths) = match &selection {
+        RecordSelection::Id(id) if cfg.unique_id => {
+            let clob_paths = clob_paths_for_id(cfg, id);
+            if clob_paths.is_empty() {
+                bail!(
+                    error::InvalidRecordId {
+                        id   : id.clone(),
+                        path : path.clone()
+                    }
+                );
+            }
+
+            (id, clob_paths)
+        },
+        RecordSelection::Label(label) if !cfg.unique_id => {
+            (label, clob_paths_for_label(cfg, label))
+        },
+        selection => {
+            bail!(
+                error::UnsupportedRecordSelection {
+                    path   : path.clone(),
+                    option : match selection {
+                        RecordSelection::Id(_)    => "--record",
+                        RecordSelection::Label(_) => "--label"
+                    }.to_owned()
+                }
+            )
+        }
+    };
+
+    // the records might be of any of the record types and the clob might hold other records 
+    // (e.g. IDs or labels that only differ in their case or diacritics)
+    let mut records = vec!();
+    for clob_path in clob_paths.iter() {
+        let clob = match repo.read_clob_from_rev(format!("{}.contents/{}", path, clob_path), &rev)? {
+            Some(text) => Dictionary::from_text(cfg, text),
+            None       => continue
+        };
+
+        records.extend(
+            clob.records().into_iter()
+                .filter(|record| {
+                    match &selection {
+                        RecordSelection::Id(id)       => {
+                            cfg.id_tag.as_deref().and_then(|tag| record.value(tag)).map(str::trim) == Some(id.trim())
+                        },
+                        RecordSelection::Label(label) => record.label() == Some(label.trim())
+                    }
+                })
+                .map(|record| record.text.trim_end().to_owned())
+        );
+    }
+
+    if records.is_empty() {
+        bail!(error::RecordNotFound { name : name.clone() });
+    }
+
+    // the record contents are printed as they are
+    for (i, record) in records.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        println!("{}", record);
+    }
+
+    Ok( () )
+}
+
+/// Write the reconstituted contents of a managed toolbox file (terminated by a newline)
+///
+/// The contents are streamed to `out` as they are retrieved from the repository, unless 
+/// the records have to be spaced out (which needs the entire file)
+pub fn write_reconstructed<P : AsRef<str>, W: Write>(
+    pathspec: P, bare: bool, merge_base: Option<(String, String)>, out: &mut W
+) -> Result<()>  {
+    let (rev, path) = resolve_path_spec(pathspec.as_ref(), merge_base)?;
+
+    // the managed file is written in its configured encoding 
+    // (if the configuration cannot be read, we fall back to UTF-8)
+    let cfg = if bare {
+        None
+    } else {
+        Repository::open().ok().and_then(|repo| repo.config().dictionary_by_path(&path).ok().cloned())
+    };
 
     let path = if bare {
         path
@@ -31,25 +142,152 @@ pub fn reconstruct<P : AsRef<str>,>(pathspec: P, bare: bool) -> Result<()>  {
         format!("{}.contents", path)
     };
 
-    let data = Repository::reconstruct(&path, rev)?;
+    write_contents(cfg.as_ref(), &path, &rev, out)
+}
+
+/// Write the reconstituted contents of a managed toolbox file given its staged placeholder
+///
+/// The file is reconstructed from the managed folder named by the placeholder (see 
+/// `placeholder_with_contents()`), so that the smudge filter converts the blob git gives it 
+/// rather than the one in `HEAD` (which is used for the placeholders that do not name it) 
+pub fn write_reconstructed_placeholder<P : AsRef<str>, W: Write>(path: P, placeholder: &[u8], out: &mut W) -> Result<()> {
+    use crate::config::placeholder_contents;
+
+    let tree = placeholder_contents(&String::from_utf8_lossy(placeholder)).map(str::to_owned);
 
-    // print it all to stdout
-    let mut stdout = std::io::stdout();
+    let repo = Repository::open()?;
+    let path = repo.get_path_relative_to_repo(std::path::Path::new(path.as_ref()))?.to_string_lossy().into_owned();
+
+    match (tree, repo.config().dictionary_by_path(&path).ok()) {
+        (Some(tree), Some(cfg)) => write_contents(Some(cfg), "", &tree, out),
+        _                       => write_reconstructed(path, false, None, out)
+    }
+}
+
+// write the contents of a managed folder at a revision (or a tree) in the encoding of 
+// the managed file
+fn write_contents<W: Write>(cfg: Option<&DictionaryConfig>, path: &str, rev: &str, out: &mut W) -> Result<()> {
+    let write_error = |err: std::io::Error| -> anyhow::Error {
+        error::ReconstructionWriteError {
+            msg : err.to_string()
+        }
+        .into()
+    };
 
-    stdout.write_all(&data).and_then(|_| {
+    // the records can only be spaced out in the entire file
+    if let Some(cfg) = cfg.filter(|cfg| cfg.record_spacing != 1) {
+        let mut data = Repository::reconstruct(path, rev, &cfg.sort)?;
         if !data.ends_with(b"\n") {
-            stdout.write_all(b"\n")
-        } else {
-            Ok( () )
+            data.push(b'\n');
         }
-    }).expect("fatal - stdout error");
-    
+
+        return out.write_all(&cfg.encode_text(cfg.space_records(cfg.adapt_header(data)))?).map_err(write_error);
+    }
+
+    let mut writer = ManagedFileWriter { out, cfg, last : None, error : None };
+
+    let sort = cfg.map(|cfg| cfg.sort.clone()).unwrap_or_default();
+
+    Repository::reconstruct_to(path, rev, &sort, &mut writer).map_err(|err| {
+        // report the encoding error rather than the failed write
+        writer.error.take().unwrap_or(err)
+    })?;
+
+    if writer.last != Some(b'\n') {
+        // the line ending of the file
+        let newline = match writer.cfg {
+            Some(cfg) => cfg.encode_text(b"\n".to_vec())?,
+            None      => b"\n".to_vec()
+        };
+        writer.out.write_all(&newline).map_err(write_error)?;
+    }
+
     Ok( () )
 }
 
 
+/// Writes the contents of a managed toolbox file in its encoding
+///
+/// The chunks are expected to be complete blobs, so that the characters are never split
+struct ManagedFileWriter<'a, W: Write> {
+    out   : &'a mut W,
+    cfg   : Option<&'a DictionaryConfig>,
+    // the last byte written
+    last  : Option<u8>,
+    // the encoding error (if any)
+    error : Option<anyhow::Error>
+}
+
+impl<'a, W: Write> Write for ManagedFileWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.is_empty() { return Ok( 0 ) }
+
+        match self.cfg {
+            Some(cfg) => {
+                // the header is the first chunk
+                let data = if self.last.is_none() { cfg.adapt_header(buf.to_vec()) } else { buf.to_vec() };
+
+                let data = cfg.encode_text(data).map_err(|err| {
+                    let io_err = std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string());
+                    self.error = Some(err);
+
+                    io_err
+                })?;
+
+                self.out.write_all(&data)?;
+            },
+            None => self.out.write_all(buf)?
+        }
+
+        self.last = buf.last().copied();
+
+        Ok( buf.len() )
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+
+/// Resolve the path specification to a revision and a path relative to the repository root
+///
+/// The revision is `HEAD` unless another revision (or the merge base) is requested, and empty
+/// for the index
+fn resolve_path_spec(pathspec: &str, merge_base: Option<(String, String)>) -> Result<(String, String)> {
+    // split up the the path into revision and the actual path
+    let (rev, path) = parse_path_spec(pathspec)?;
+
+    // resolve the revision 
+    let rev = match (rev, merge_base) {
+        // the merge base of two branches was requested
+        (None, Some((rev_a, rev_b))) => {
+            Repository::merge_base(rev_a, rev_b)?
+        },
+        // we can't have it both ways
+        (Some(_), Some(_)) => {
+            bail!(
+                error::AmbiguousRevision {
+                    pathspec : pathspec.to_owned()
+                }
+            )
+        },
+        (rev, None) => {
+            rev.unwrap_or("HEAD").to_owned()
+        }
+    };
+
+    // get the path relative to the repository root
+    let path = Repository::get_path_relative_to_repo_here(path)?
+        .to_string_lossy().into_owned();
+
+    Ok( (rev, path) )
+}
+
 /// Parse the path specification in form of `rev:path`
-fn parse_path_spec(pathspec: &str) -> Result<(&str, &str)> {
+///
+/// The revision is `None` if the path specification does not contain one
+fn parse_path_spec(pathspec: &str) -> Result<(Option<&str>, &str)> {
     use regex::Regex;
     
     let regex = Regex::new("^((?P<rev>[^:]*):)?(?P<path>.+)$").unwrap();
@@ -60,7 +298,7 @@ fn parse_path_spec(pathspec: &str) -> Result<(&str, &str)> {
         }
     })?;
 
-    let rev = matches.name("rev").map(|m| m.as_str()).unwrap_or("HEAD").trim();
+    let rev = matches.name("rev").map(|m| m.as_str().trim());
     let path = matches.name("path").map(|m| m.as_str()).unwrap_or_default().trim();
 
     Ok( (rev, path) )
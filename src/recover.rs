@@ -0,0 +1,109 @@
+//
+// src/recover.rs
+//
+// Implementation of git-toolbox recover
+//
+// Recovers from an operation (such as stage or reset) that was interrupted and
+// left its journal behind. The interrupted operation can be rolled back (the
+// index, the managed files and the managed folders are restored to the state
+// before the operation), resumed (rolled back and run again) or the journal can
+// be discarded, keeping the files as they are.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::repository::Repository;
+use crate::util::{format_time, local_time_offset};
+use crate::cli_app::style;
+
+use crate::error;
+use anyhow::{Result, bail};
+
+
+/// How to recover from an interrupted operation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Recovery {
+    /// Roll back the operation and run it again
+    Resume,
+    /// Restore the state before the operation
+    Rollback,
+    /// Keep the files as they are
+    Discard
+}
+
+
+pub fn recover(recovery: Option<Recovery>) -> Result<()> {
+    let repo = Repository::open()?;
+
+    // no other git-toolbox operations while we are recovering
+    let lock = repo.lock_recovery()?;
+
+    let entry = match repo.interrupted_operation()? {
+        Some(entry) => entry,
+        None        => bail!(error::NoInterruptedOperation)
+    };
+    let command = format!("git toolbox {}", entry.command.join(" "));
+
+    match recovery {
+        None => {
+            stdout!("An earlier {} (started {} by process {}) was interrupted. The affected files are:\n",
+                style(format!("git toolbox {}", entry.operation)).bold(),
+                format_time(entry.time as i64, local_time_offset()),
+                entry.pid
+            );
+            for (path, _) in entry.files.iter() {
+                stdout!("        {}", path);
+            }
+            stdout!("");
+            stdout!("  (use \"{}\" to complete it by running \"{}\" again)",
+                style("git toolbox recover --resume").bold(),
+                command
+            );
+            stdout!("  (use \"{}\" to restore the files to the state before it)",
+                style("git toolbox recover --rollback").bold()
+            );
+            stdout!("  (use \"{}\" to keep the files as they are)",
+                style("git toolbox recover --discard").bold()
+            );
+        },
+        Some(Recovery::Rollback) => {
+            repo.rollback_journal(&entry)?;
+
+            stdout!("✅  Rolled back the interrupted {}", style(format!("git toolbox {}", entry.operation)).bold());
+        },
+        Some(Recovery::Discard) => {
+            repo.discard_journal()?;
+
+            stdout!("✅  Discarded the journal of the interrupted {}. The files were kept as they are.",
+                style(format!("git toolbox {}", entry.operation)).bold()
+            );
+            stdout!("  (use \"{}\" to check them)", style("git toolbox doctor").bold());
+        },
+        Some(Recovery::Resume) => {
+            repo.rollback_journal(&entry)?;
+
+            stdout!("{} Rolled back the interrupted {}, running \"{}\" again\n",
+                style("✓").green(),
+                style(format!("git toolbox {}", entry.operation)).bold(),
+                command
+            );
+
+            // the operation takes the lock itself
+            drop(lock);
+
+            let exe = std::env::current_exe().map_err(|err| anyhow::anyhow!(err))?;
+            let status = std::process::Command::new(exe)
+                .args(&entry.command)
+                .current_dir(repo.workdir()?)
+                .status()
+                .map_err(|err| anyhow::anyhow!("unable to run \"{}\": {}", command, err))?;
+
+            if !status.success() {
+                bail!("\"{}\" failed", command);
+            }
+        }
+    }
+
+    Ok( () )
+}
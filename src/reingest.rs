@@ -0,0 +1,276 @@
+//
+// src/reingest.rs
+//
+// Implementation of git-toolbox reingest
+//
+// Records that cannot be parsed are quarantined in the `invalid` directory
+// of the managed contents. Once the user has fixed them there, this command
+// parses them again and merges the recovered records back into the managed file.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::repository::{Repository, Clob, ClobValidationIssue, assemble_clobs};
+use crate::toolbox::{Dictionary, ToolboxFileIssue, QUARANTINE_DIR};
+use crate::config::DictionaryConfig;
+use itertools::{Itertools, Either};
+use crate::cli_app::style;
+
+use crate::error;
+use anyhow::{Result, bail};
+
+const MAX_TO_SHOW: usize = 8;
+
+struct ReingestSummary {
+    // managed file name for displaying (relative to current folder)
+    pub display_name  : String,
+    // path to the file (relative to the repository)
+    pub path          : String,
+    // quarantine clobs edited in the working directory (relative to the repository)
+    pub edited        : Vec<String>,
+    // number of entries recovered from the quarantine
+    pub recovered     : usize,
+    // the new contents of the managed file
+    pub content       : Vec<u8>,
+    // issues that remain in the edited quarantine clobs
+    pub toolbox_issues : Vec<(String, ToolboxFileIssue)>
+}
+
+
+pub fn reingest(paths: Vec<String>, verbose: bool) -> Result<()> {
+    // load the repository
+    let repo = Repository::open()?;
+
+    // no other git-toolbox operations while we are modifying the repository
+    let _lock = repo.lock_operation("reingest")?;
+
+    // dictionary selection
+    let dictionaries : Vec<&DictionaryConfig> = if paths.is_empty() {
+        repo.config().dictionaries.iter().collect()
+    } else {
+        paths.iter().map(|path| {
+            // convert the path to one relative to the repo
+            let path = repo.get_path_relative_to_repo(path)?.to_string_lossy().into_owned();
+
+            repo.config().dictionary_by_path(path)
+        })
+        .collect::<Result<Vec<_>>>()?
+    };
+
+    // process on the requested files
+    let (summaries, errors) : (Vec<_>, Vec<_>) = dictionaries.into_iter().map(|cfg| {
+        ReingestSummary::new(&repo, cfg)
+    })
+    // split off and collect sucesses and failures
+    .partition_map(|result| -> Either<_, anyhow::Error> {
+        match result {
+            Ok( val )  => Either::Left(val),
+            Err( err ) => Either::Right(err)
+        }
+    });
+
+    // abort if there are errors
+    if !errors.is_empty() {
+        // collect all errors
+        let err_msg = errors.into_iter().join("\n");
+
+        bail!(
+            "{}\n⚠️  There were errors. Aborting. No changes to the working directory were made",
+            err_msg
+        );
+    }
+
+    // we are only interested in files that have edited quarantine clobs
+    let summaries: Vec<_> = summaries.into_iter().filter(ReingestSummary::any_edited).collect();
+
+    // check if ther is any work to do
+    if summaries.is_empty() {
+        stdout!("✅ No modified quarantine files found. Nothing to do.");
+
+        return Ok( () )
+    }
+
+    for summary in summaries.iter() {
+        // write the updated managed file
+        let absolute_path = repo.workdir()?.to_owned().join(&summary.path);
+        let cfg = repo.config().dictionary_by_path(&summary.path)?;
+        let content = cfg.encode_text(cfg.space_records(cfg.adapt_header(summary.content.clone())))?;
+
+        std::fs::write(&absolute_path, &content).map_err(|err| {
+            error::FileWriteError {
+                path : absolute_path,
+                msg  : err.to_string()
+            }
+        })?;
+
+        // the edits now live in the managed file, so we restore the clobs
+        // to avoid them being reported as external modifications
+        repo.checkout_from_index(summary.edited.iter())?;
+
+        stdout!("{} Reingested {} quarantine files into {} ({} entries recovered)",
+            style("✓").green(),
+            summary.edited.len(),
+            &summary.display_name,
+            summary.recovered
+        );
+    }
+
+    // print the issues that still remain
+    let issue_count = summaries.iter().fold(0, |sum, summary| {
+        sum + summary.toolbox_issues.len()
+    });
+
+    for summary in summaries.iter() {
+        summary.display_toolbox_issues(verbose);
+    }
+
+    stdout!("");
+
+    stdout!("\n✅ Reingested {} managed toolbox dictionaries. Use {} to add them to be commited.",
+        summaries.len(),
+        style("\"git toolbox stage\"").bold()
+    );
+
+    if issue_count != 0 {
+        stdout!(
+            "⚠️  {} issues remain in the reingested records and they will be quarantined again.",
+            issue_count
+        );
+    }
+
+    Ok( () )
+}
+
+
+impl ReingestSummary {
+    pub fn new(repo :&Repository, cfg: &DictionaryConfig) -> Result<Self> {
+        use ClobValidationIssue::*;
+
+        // the file path
+        let path = cfg.path.clone();
+
+        // load and split the dictionary
+        let dictionary = Dictionary::load(repo, cfg, false)?;
+
+        // obtain the printable relative path to the file
+        let display_name = crate::util::get_relative_path(
+            repo.workdir()?.to_owned().join(&cfg.path)
+        ).display().to_string();
+
+        let contents_path = dictionary.contents_root();
+        let quarantine_path = format!("{}/{}/", contents_path, QUARANTINE_DIR);
+        let clobs = dictionary.split().0.collect::<Vec<_>>();
+
+        // locate the quarantine clobs that were edited by the user
+        let edited = repo.validate_clobs_in_workdir(&contents_path, false)?.into_iter()
+            .filter_map(|issue| {
+                match issue {
+                    UpdatedInWorkdir { path } if path.starts_with(&quarantine_path) => Some(path),
+                    _                                                               => None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // the edits cannot be reingested if the same records were also modified
+        // in the managed file, since one of the changes would be lost
+        if !edited.is_empty() {
+            let unstaged_diff = repo.diff_clobs_at_path(
+                &contents_path, Box::new(clobs.clone().into_iter())
+            )?;
+
+            let conflict = unstaged_diff.iter().find(|diff| {
+                edited.iter().any(|path| path == diff.path())
+            });
+
+            if let Some(diff) = conflict {
+                bail!(
+                    error::QuarantineConflict {
+                        path : diff.path().to_owned()
+                    }
+                );
+            }
+        }
+
+        // replace the quarantine clobs by the records parsed from the edited files
+        let mut clobs = clobs.into_iter().filter(|clob| {
+            !edited.contains(&format!("{}/{}", contents_path, clob.path))
+        }).collect::<Vec<_>>();
+
+        let mut recovered = 0;
+        let mut toolbox_issues = vec!();
+
+        for edited_path in edited.iter() {
+            let absolute_path = repo.workdir()?.to_owned().join(edited_path);
+
+            let text = std::fs::read_to_string(&absolute_path).map_err(|err| {
+                error::FileReadError {
+                    path : absolute_path.clone(),
+                    msg  : err.to_string()
+                }
+            })?;
+
+            let fragment = Dictionary::from_fragment(cfg, text);
+            let (reingested, issues) = fragment.split();
+
+            for clob in reingested {
+                if !clob.path.starts_with(QUARANTINE_DIR) {
+                    recovered += 1;
+                }
+                clobs.push(clob);
+            }
+
+            toolbox_issues.extend(issues.into_iter().map(|issue| (edited_path.clone(), issue)));
+        }
+
+        // records that map to the same path are stored together
+        let clobs = clobs.into_iter()
+            .map(|clob| (clob.path.clone(), clob))
+            .into_group_map()
+            .into_iter()
+            .map(|(path, clobs)| {
+                Clob {
+                    path,
+                    content : clobs.into_iter().map(|clob| clob.content).join("\n")
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // return the reingested file
+        Ok(
+            ReingestSummary {
+                display_name,
+                path,
+                edited,
+                recovered,
+                content : assemble_clobs(clobs, &cfg.sort),
+                toolbox_issues
+            }
+        )
+    }
+
+    pub fn any_edited(&self) -> bool {
+        !self.edited.is_empty()
+    }
+
+    pub fn any_toolbox_issues(&self) -> bool {
+        !self.toolbox_issues.is_empty()
+    }
+
+    pub fn display_toolbox_issues(&self, verbose: bool) {
+        if !self.any_toolbox_issues() { return }
+
+        stdout!("\n  Issues remaining in {}:\n", style(&self.display_name).italic());
+        let to_show = if verbose { self.toolbox_issues.len() } else { MAX_TO_SHOW };
+        for (path, e) in self.toolbox_issues.iter().take(to_show) {
+            stdout!("        {} {}", style(path.rsplit('/').next().unwrap_or(path)).italic(), e);
+        }
+        if to_show < self.toolbox_issues.len() {
+            stdout!("        ...");
+            stdout!("        ({} other issues, use \"{}\" to see all)",
+                self.toolbox_issues.len() - to_show,
+                style("git toolbox reingest --verbose").bold()
+            );
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//
+// src/rename_marker.rs
+//
+// Implementation of git-toolbox rename-marker
+//
+// Renames a field marker in all the records of a managed toolbox file and in
+// the settings of the dictionary (template fields, record and ID tags). The
+// changes are written to the working copy and the configuration file, the
+// history of the repository is not modified.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::repository::Repository;
+use crate::toolbox::Dictionary;
+use crate::config::{CONFIG_FILE, rename_marker_in_config};
+use crate::cli_app::style;
+
+use crate::error;
+use anyhow::{Result, bail};
+use itertools::Itertools;
+use std::collections::BTreeSet;
+
+
+pub fn rename_marker(old: String, new: String, path: String) -> Result<()> {
+    // the markers can be given with or without the backslash
+    let old = old.trim_start_matches('\\').to_owned();
+    let new = new.trim_start_matches('\\').to_owned();
+
+    for marker in [&old, &new] {
+        if marker.is_empty() || marker.contains(char::is_whitespace) {
+            bail!(
+                error::InvalidMarker {
+                    marker : marker.clone()
+                }
+            );
+        }
+    }
+
+    let old_marker = format!("\\{}", old);
+    let new_marker = format!("\\{}", new);
+
+    // load the repository
+    let repo = Repository::open()?;
+
+    // no other git-toolbox operations while we are modifying the repository
+    let _lock = repo.lock_operation("rename-marker")?;
+
+    // locate the dictionary
+    let repo_path = repo.get_path_relative_to_repo(&path)?.to_string_lossy().into_owned();
+    let cfg = repo.config().dictionary_by_path(&repo_path)?;
+    let index = repo.config().dictionaries.iter()
+        .position(|dictionary| dictionary.path == cfg.path)
+        .expect("fatal: dictionary not in the configuration");
+
+    // the new marker must not have a different meaning already
+    let used = cfg.record_tags.contains(&new_marker) ||
+        cfg.id_tag.as_ref() == Some(&new_marker) ||
+        cfg.template.as_ref().and_then(|template| template.field(&new_marker)).is_some();
+
+    if used {
+        bail!(
+            error::MarkerAlreadyConfigured {
+                marker : new_marker,
+                path   : cfg.path.clone()
+            }
+        );
+    }
+
+    // read the working copy
+    let file_path = repo.workdir()?.join(&cfg.path);
+    let data = std::fs::read(&file_path).map_err(|err| {
+        error::FileReadError {
+            path : file_path.clone(),
+            msg  : err.to_string()
+        }
+    })?;
+    let text = cfg.decode_text(&file_path, data)?;
+
+    // locate the fields to rename and the records that already use the new marker
+    let mut lines = BTreeSet::new();
+    let mut records = 0;
+    let mut merged = 0;
+
+    for record in Dictionary::from_text(cfg, text.clone()).records() {
+        let fields = record.text.lines().enumerate().filter(|(_, line)| {
+            line.strip_prefix(old_marker.as_str())
+                .map(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+                .unwrap_or(false)
+        })
+        .map(|(i, _)| record.line.line + i)
+        .collect_vec();
+
+        if !fields.is_empty() {
+            records += 1;
+            if record.values(&new_marker).next().is_some() {
+                merged += 1;
+            }
+        }
+
+        lines.extend(fields);
+    }
+
+    // rename the fields, keeping everything else as it is
+    if !lines.is_empty() {
+        let text = text.split_inclusive('\n').enumerate().map(|(i, line)| {
+            if lines.contains(&i) {
+                format!("{}{}", new_marker, &line[old_marker.len() ..])
+            } else {
+                line.to_owned()
+            }
+        })
+        .collect::<String>();
+
+        // the file is written in its encoding
+        let data = cfg.encode_text(text.into_bytes())?;
+        std::fs::write(&file_path, data).map_err(|err| {
+            error::FileWriteError {
+                path : file_path.clone(),
+                msg  : err.to_string()
+            }
+        })?;
+    }
+
+    stdout!("{} Renamed {} to {} in {} fields of {} records of {}",
+        style("✓").green(),
+        style(&old_marker).bold(),
+        style(&new_marker).bold(),
+        lines.len(),
+        records,
+        style(&cfg.path).italic()
+    );
+
+    if merged > 0 {
+        stdout!("{}  {} of these records already had {} fields, please check them",
+            style("⚠️").yellow(),
+            merged,
+            style(&new_marker).bold()
+        );
+    }
+
+    // update the configuration
+    let config_path = repo.workdir()?.join(CONFIG_FILE);
+    let config_text = std::fs::read_to_string(&config_path).map_err(|err| {
+        error::FileReadError {
+            path : config_path.clone(),
+            msg  : err.to_string()
+        }
+    })?;
+
+    let (config_text, settings) = rename_marker_in_config(&config_text, index, &old, &new);
+
+    if settings > 0 {
+        std::fs::write(&config_path, config_text).map_err(|err| {
+            error::FileWriteError {
+                path : config_path.clone(),
+                msg  : err.to_string()
+            }
+        })?;
+
+        stdout!("{} Updated {} settings in {}", style("✓").green(), settings, style(CONFIG_FILE).italic());
+        stdout!("");
+        stdout!("  (run {} to apply the configuration, then {} to stage the renamed fields)",
+            style("git toolbox setup").bold(),
+            style("git toolbox stage").bold()
+        );
+    } else {
+        stdout!("");
+        stdout!("  (the changes are not staged, use {} to stage them)", style("git toolbox stage").bold());
+    }
+
+    Ok( () )
+}
@@ -0,0 +1,76 @@
+//
+// src/toolbox/repository
+//
+// Adopting the records of a per-record directory.
+//
+// Projects converted by other tools might keep their records as one file per
+// record in a directory of their own. Once the records are taken over into a
+// managed file, the adopted files are removed from the index and the working
+// directory, so that the commit that stages the managed folder moves them (git
+// detects the moved files as renames).
+//
+// A dictionary that is tracked as an ordinary file can be taken over as well. Its
+// index entry still has the contents of the file until it is staged as a managed
+// file.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use super::Repository;
+
+use anyhow::Result;
+use crate::error;
+
+use std::path::Path;
+
+impl Repository {
+    /// Whether the path is tracked by git (i.e. is in the index)
+    ///
+    /// Path is assumed to be relative to the repository
+    pub fn is_tracked_here(path: &str) -> Result<bool> {
+        let repo = Repository::__open()?;
+        let index = repo.index().map_err(error::OtherGitError::from)?;
+
+        Ok( index.get_path(Path::new(path), 0).is_some() )
+    }
+
+    /// Remove the adopted files from the index and the working directory
+    ///
+    /// Paths are assumed to be relative to the repository. The files that are not in the
+    /// index are only removed from the working directory, as are the directories that become
+    /// empty
+    pub fn remove_adopted_files(&self, paths: &[String]) -> Result<()> {
+        use std::fs;
+
+        let workdir = self.workdir()?.to_owned();
+        let mut index = self.repository.index().map_err(error::OtherGitError::from)?;
+
+        for path in paths {
+            let full_path = workdir.join(path);
+
+            fs::remove_file(&full_path).map_err(|err| {
+                error::FileDeleteError {
+                    path : full_path.clone(),
+                    msg  : err.to_string()
+                }
+            })?;
+
+            if index.get_path(Path::new(path), 0).is_some() {
+                index.remove_path(Path::new(path)).map_err(error::OtherGitError::from)?;
+            }
+
+            // remove the directories that are now empty
+            let mut parent = full_path.parent();
+            while let Some(dir) = parent {
+                if dir == workdir || fs::remove_dir(dir).is_err() { break }
+
+                parent = dir.parent();
+            }
+        }
+
+        index.write().map_err(error::OtherGitError::from)?;
+
+        Ok( () )
+    }
+}
@@ -0,0 +1,151 @@
+//
+// src/toolbox/repository
+//
+// The audit log. Operations that git-toolbox performs on the repository are
+// recorded in a local log file (one line per operation).
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use super::Repository;
+
+use anyhow::Result;
+use crate::error;
+
+// name of the audit log file (in the git-toolbox data directory)
+const AUDIT_LOG_FILE : &str = "audit.log";
+
+/// An entry in the audit log
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    /// Time of the operation (seconds since the Unix epoch)
+    pub time      : i64,
+    /// The user who performed the operation
+    pub user      : String,
+    /// The operation (e.g. `stage`)
+    pub operation : String,
+    /// The managed file (relative to the repository)
+    pub path      : String,
+    /// Id of the contents tree produced by the operation (if any)
+    pub tree      : Option<String>,
+    /// Operation details
+    pub details   : String
+}
+
+
+impl Repository {
+    /// Record an operation in the audit log
+    pub fn record_audit_entry<S>(
+        &self, operation: S, path: S, tree: Option<String>, details: S
+    ) -> Result<()>
+    where
+        S : AsRef<str>
+    {
+        use std::io::Write;
+        use std::fs::OpenOptions;
+
+        let path_to_log = self.data_dir()?.join(AUDIT_LOG_FILE);
+
+        let entry = AuditEntry {
+            time      : current_time(),
+            user      : self.user_display_name(),
+            operation : operation.as_ref().to_owned(),
+            path      : path.as_ref().to_owned(),
+            tree,
+            details   : details.as_ref().to_owned()
+        };
+
+        OpenOptions::new().create(true).append(true).open(&path_to_log)
+            .and_then(|mut file| writeln!(file, "{}", entry))
+            .map_err(|err| {
+                error::FileWriteError {
+                    path : path_to_log,
+                    msg  : err.to_string()
+                }
+                .into()
+            })
+    }
+
+    /// Read the entries of the audit log (in the order they were recorded)
+    ///
+    /// Malformed lines are skipped
+    pub fn read_audit_log(&self) -> Result<Vec<AuditEntry>> {
+        let path_to_log = self.data_dir()?.join(AUDIT_LOG_FILE);
+
+        let text = match std::fs::read_to_string(&path_to_log) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => {
+                anyhow::bail!(
+                    error::FileReadError {
+                        path : path_to_log,
+                        msg  : err.to_string()
+                    }
+                )
+            }
+        };
+
+        Ok( text.lines().filter_map(|line| line.parse().ok()).collect() )
+    }
+
+    /// The user name and e-mail as configured in git
+    pub fn user_display_name(&self) -> String {
+        self.repository.signature().map(|sig| {
+            format!("{} <{}>", sig.name().unwrap_or("unknown"), sig.email().unwrap_or(""))
+        })
+        .unwrap_or_else(|_| "unknown".to_owned())
+    }
+}
+
+/// Current time in seconds since the Unix epoch
+fn current_time() -> i64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+
+use std::fmt::{Display, Formatter};
+
+/// Audit entries are written as tab-separated fields
+impl Display for AuditEntry {
+    fn fmt(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        // fields cannot contain the separators
+        let field = |text: &str| text.replace(['\t', '\n'], " ");
+
+        write!(formatter, "{}\t{}\t{}\t{}\t{}\t{}",
+            self.time,
+            field(&self.user),
+            field(&self.operation),
+            field(&self.path),
+            self.tree.as_deref().unwrap_or("-"),
+            field(&self.details)
+        )
+    }
+}
+
+/// Parse an audit log line
+impl std::str::FromStr for AuditEntry {
+    type Err = ();
+
+    fn from_str(line: &str) -> Result<Self, ()> {
+        let fields = line.splitn(6, '\t').collect::<Vec<_>>();
+
+        match fields.as_slice() {
+            [time, user, operation, path, tree, details] => {
+                Ok(
+                    AuditEntry {
+                        time      : time.parse().map_err(|_| ())?,
+                        user      : (*user).to_owned(),
+                        operation : (*operation).to_owned(),
+                        path      : (*path).to_owned(),
+                        tree      : Some(*tree).filter(|tree| *tree != "-").map(str::to_owned),
+                        details   : (*details).to_owned()
+                    }
+                )
+            },
+            _ => Err( () )
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//
+// src/toolbox/repository
+//
+// Record IDs introduced on other branches.
+//
+// A record is stored at a path derived from its ID, so two records created
+// independently with the same ID (e.g. by two contributors who picked the same
+// next free ID) end up in the same clob. Git sees this as a conflict at best
+// and the records are silently merged into one at worst. To notice this before
+// the branches are merged, the clobs that the remote-tracking branches added
+// since they diverged from HEAD are compared with the local ones.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use super::{Repository, ClobDiff};
+use super::deletions::apply_diff;
+use super::parts::join_clob_parts;
+
+use anyhow::Result;
+use crate::error;
+use crate::toolbox::ClobOrigin;
+use serde::Serialize;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A record ID added both locally and on another branch (with different records)
+#[derive(Debug, Clone, Serialize)]
+pub struct ClobCollision {
+    /// The remote-tracking branch
+    pub branch : String,
+    /// The clob (relative to the managed folder)
+    pub path   : String,
+    /// The record ID (including the namespace for private records)
+    pub id     : String,
+    /// The first line of the local clob
+    pub ours   : String,
+    /// The first line of the clob on the branch
+    pub theirs : String
+}
+
+
+impl Repository {
+    /// The remote-tracking branches (e.g. `origin/main`)
+    pub fn remote_branches(&self) -> Result<Vec<String>> {
+        let branches = self.repository.branches(Some(git2::BranchType::Remote)).map_err(error::OtherGitError::from)?;
+
+        Ok(
+            branches
+                .filter_map(|branch| branch.ok())
+                .filter(|(branch, _)| branch.get().symbolic_target().is_none())
+                .filter_map(|(branch, _)| branch.name().ok().flatten().map(|name| name.to_owned()))
+                .collect()
+        )
+    }
+
+    /// Find the clobs that were added both locally and on a remote-tracking branch 
+    /// 
+    /// The local clobs (the staged ones with the unstaged diff applied) are compared with 
+    /// the clobs that the branch added since it diverged from HEAD. The branches that are 
+    /// already merged into HEAD are skipped
+    pub fn find_clob_collisions(&self, contents_path: &str, unstaged_diff: &[ClobDiff]) 
+        -> Result<Vec<ClobCollision>> 
+    {
+        let repo = &self.repository;
+        let local : BTreeMap<_, _> = join_clob_parts(
+            apply_diff(contents_path, self.index_clob_parts(contents_path)?, unstaged_diff)
+        )
+        .into_iter()
+        .collect();
+        let mut collisions = vec!();
+
+        let head = match repo.head().ok().and_then(|head| head.target()) {
+            Some(head) => head,
+            None       => return Ok( collisions )
+        };
+
+        for branch in self.remote_branches()? {
+            let tip = repo.revparse_single(&branch).and_then(|obj| obj.peel_to_commit())
+                .map_err(error::OtherGitError::from)?
+                .id();
+
+            // nothing new on the branch
+            if tip == head || repo.graph_descendant_of(head, tip).unwrap_or(false) { continue }
+
+            // the clobs the branch started from
+            let base = match repo.merge_base(head, tip) {
+                Ok(base) => join_clob_parts(self.rev_clob_parts(contents_path, &base.to_string())?)
+                    .into_iter()
+                    .map(|(path, _)| path)
+                    .collect::<BTreeSet<_>>(),
+                Err(_)   => BTreeSet::new()
+            };
+
+            for (path, theirs) in join_clob_parts(self.rev_clob_parts(contents_path, &tip.to_string())?) {
+                if base.contains(&path) { continue }
+
+                // only the records with an ID are stored at a path derived from it
+                let id = match ClobOrigin::from_path(&path) {
+                    ClobOrigin::Public { id }             => id.to_owned(),
+                    ClobOrigin::Private { namespace, id } => format!("{}/{}", namespace, id),
+                    _                                     => continue
+                };
+
+                match local.get(&path) {
+                    Some(ours) if *ours != theirs => {
+                        let first_line = |text: &str| text.lines().find(|line| !line.trim().is_empty()).unwrap_or("").trim_end().to_owned();
+
+                        collisions.push(
+                            ClobCollision { 
+                                branch : branch.clone(), 
+                                ours   : first_line(ours), 
+                                theirs : first_line(&theirs),
+                                path, 
+                                id
+                            }
+                        );
+                    },
+                    _ => {}
+                }
+            }
+        }
+
+        Ok( collisions )
+    }
+}
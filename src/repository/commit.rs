@@ -0,0 +1,141 @@
+//
+// src/toolbox/repository
+//
+// Committing the staged managed files.
+//
+// Only the managed files and their managed folders are committed: the commit
+// tree is the tree of HEAD with the managed paths taken from the index, so
+// that anything else the user has staged stays staged (and uncommited).
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use super::{Repository, DiffStats};
+use super::manifest::MANIFEST_FILE;
+
+use anyhow::Result;
+use crate::error;
+
+use std::path::Path;
+
+/// A commit of the staged managed files
+pub struct ManagedCommit {
+    /// Id of the commit
+    pub commit : String,
+    /// The managed files with committed changes
+    pub paths  : Vec<String>,
+    /// The committed changes of the entry files
+    pub stats  : DiffStats
+}
+
+
+impl Repository {
+    /// Check if git is in the middle of an operation (a merge, rebase, cherry-pick etc.)
+    pub fn git_operation_in_progress(&self) -> bool {
+        self.repository.state() != git2::RepositoryState::Clean
+    }
+
+    /// Commit the staged changes of the managed files to the current branch
+    ///
+    /// The commit message is produced from the committed paths and changes. Returns `None`
+    /// if no changes of the managed files are staged
+    pub fn commit_managed_changes<F>(&self, message: F) -> Result<Option<ManagedCommit>>
+    where
+        F : FnOnce(&[String], &DiffStats) -> String
+    {
+        use git2::{Index, DiffOptions, Delta};
+
+        let repo = &self.repository;
+
+        // the dictionary of a managed path (the managed file or anything in its managed folder)
+        let dictionary_of = |path: &str| {
+            self.config.dictionaries.iter().find(|cfg| {
+                path == cfg.path || path.starts_with(&format!("{}.contents/", cfg.path))
+            })
+        };
+
+        let head = repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+        let head_tree = head.as_ref()
+            .map(|head| head.tree())
+            .transpose()
+            .map_err(error::OtherGitError::from)?;
+
+        // the tree of HEAD with the managed paths from the index
+        let mut index = Index::new().map_err(error::OtherGitError::from)?;
+        if let Some(tree) = &head_tree {
+            index.read_tree(tree).map_err(error::OtherGitError::from)?;
+        }
+
+        let committed = index.iter()
+            .map(|entry| String::from_utf8_lossy(&entry.path).into_owned())
+            .filter(|path| dictionary_of(path).is_some())
+            .collect::<Vec<_>>();
+        for path in committed {
+            index.remove(Path::new(&path), 0).map_err(error::OtherGitError::from)?;
+        }
+
+        let staged = repo.index().map_err(error::OtherGitError::from)?;
+        // entries with conflicts (a non-zero stage) cannot be committed
+        for entry in staged.iter().filter(|entry| (entry.flags >> 12) & 0x3 == 0) {
+            if dictionary_of(&String::from_utf8_lossy(&entry.path)).is_some() {
+                index.add(&entry).map_err(error::OtherGitError::from)?;
+            }
+        }
+
+        let tree_id = index.write_tree_to(repo).map_err(error::OtherGitError::from)?;
+        if head_tree.as_ref().map(|tree| tree.id() == tree_id).unwrap_or(false) {
+            return Ok( None )
+        }
+        let tree = repo.find_tree(tree_id).map_err(error::OtherGitError::from)?;
+
+        // the committed changes
+        let mut stats = DiffStats { added : 0, changed : 0, deleted : 0 };
+        let mut paths : Vec<String> = vec!();
+
+        let diff = repo.diff_tree_to_tree(head_tree.as_ref(), Some(&tree), Some(&mut DiffOptions::new()))
+            .map_err(error::OtherGitError::from)?;
+
+        for delta in diff.deltas() {
+            let path = delta.new_file().path().or_else(|| delta.old_file().path())
+                .map(|path| path.to_string_lossy().into_owned())
+                .unwrap_or_default();
+
+            let cfg = match dictionary_of(&path) {
+                Some(cfg) => cfg,
+                None      => continue
+            };
+
+            if !paths.contains(&cfg.path) {
+                paths.push(cfg.path.clone());
+            }
+
+            // only the entry files are counted
+            if path == cfg.path || path.ends_with(&format!("/{}", MANIFEST_FILE)) {
+                continue;
+            }
+
+            match delta.status() {
+                Delta::Added    => stats.added += 1,
+                Delta::Deleted  => stats.deleted += 1,
+                _               => stats.changed += 1
+            }
+        }
+
+        let message = message(&paths, &stats);
+        let author = repo.signature().map_err(error::OtherGitError::from)?;
+        let parents = head.iter().collect::<Vec<_>>();
+        let commit = repo.commit(Some("HEAD"), &author, &author, &message, &tree, &parents)
+            .map_err(error::OtherGitError::from)?;
+
+        Ok(
+            Some(
+                ManagedCommit {
+                    commit : commit.to_string(),
+                    paths,
+                    stats
+                }
+            )
+        )
+    }
+}
@@ -10,30 +10,54 @@
 //
 // This code is licensed under GPL 3.0
 
-use crate::config::{Config, CONFIG_FILE};
+use crate::config::{Config, FileKind, CONFIG_FILE};
 use anyhow::{Result, bail};
 use crate::error;
 
 // git configuration keys we need to have set
-const GIT_CONFIG: [(&str, &str); 3] = [
+// (git versions without support for the long-running filter process fall back to
+// the clean and smudge filters)
+const GIT_CONFIG: [(&str, &str); 6] = [
     ("filter.toolbox-filter.clean", "git-toolbox gitfilter --clean %f"),
     ("filter.toolbox-filter.smudge", "git-toolbox gitfilter --smudge %f"),
-    ("filter.toolbox-filter.required", "true")
+    ("filter.toolbox-filter.process", "git-toolbox gitfilter --process"),
+    ("filter.toolbox-filter.required", "true"),
+    ("merge.toolbox-merge.name", "git-toolbox record-level merge"),
+    ("merge.toolbox-merge.driver", "git-toolbox mergedriver %O %A %B %P")
+];
+
+// the textconv driver that shows the managed files in git diff (like the merge
+// attribute, it is not required to work with the managed files)
+const GIT_DIFF_CONFIG: [(&str, &str); 1] = [
+    ("diff.toolbox.textconv", "git-toolbox gitfilter --textconv")
 ];
 
 // git filter attribute we need to set on managed files
 const GIT_FILTER_ATTR: & str = r"filter=toolbox-filter";
 
+// git diff attribute we set on managed files
+const GIT_DIFF_ATTR: & str = r"diff=toolbox";
+
+// git merge attribute we need to set on managed files and managed folders
+const GIT_MERGE_ATTR: & str = r"merge=toolbox-merge";
+
 // comment to put in the gitattributes file
-const GIT_COMMENT: & str = concat!(
-    "# this section is managed by git-toolbox. Please do not edit below this line!"
-);
+const GIT_COMMENT: & str = 
+    "# this section is managed by git-toolbox. Please do not edit below this line!";
 
 
 lazy_static::lazy_static! {
     static ref GIT_FILTER_ATTR_REGEX : regex::Regex = regex::Regex::new(
         &format!(r"\b{}\b", GIT_FILTER_ATTR)
     ).expect("fatal: invalid regex");
+
+    static ref GIT_MERGE_ATTR_REGEX : regex::Regex = regex::Regex::new(
+        &format!(r"\b{}\b", GIT_MERGE_ATTR)
+    ).expect("fatal: invalid regex");
+
+    static ref GIT_DIFF_ATTR_REGEX : regex::Regex = regex::Regex::new(
+        &format!(r"\b{}\b", GIT_DIFF_ATTR)
+    ).expect("fatal: invalid regex");
 }
 
 
@@ -74,7 +98,12 @@ pub(super) fn get_validated_config(repo: &Repository) -> Result<Config> {
     };
     
     // parse the configuration file
-    let config = Config::try_from(config.as_slice())?;
+    let mut config = Config::try_from(config.as_slice())?;
+    expand_path_patterns(&mut config, repo)?;
+    load_type_files(&mut config, repo)?;
+    load_validators(&mut config, repo)?;
+    load_issue_policy(&config)?;
+    super::file_format::load_file_formats(&mut config, repo);
 
     // validate the git repository configuration
     let git_config = repo.config().map_err(error::OtherGitError::from)?;
@@ -87,9 +116,7 @@ pub(super) fn get_validated_config(repo: &Repository) -> Result<Config> {
         config_entry.value().and_then(|val| {
             if val.trim() == value.trim() { Some( () ) } else { None }
         })
-    }).ok_or_else(|| {
-        error::ConfigurationNeeded
-    })?;
+    }).ok_or(error::ConfigurationNeeded)?;
 
     // validate the git attributes
     let attributes = read_git_attributes(repo)?;
@@ -126,6 +153,63 @@ pub(super) fn get_validated_config(repo: &Repository) -> Result<Config> {
 } 
 
 
+/// The problems with the git configuration of the repository (empty if it is up to date)
+///
+/// Unlike `get_validated_config()`, all the problems are listed rather than only the first one
+pub(super) fn configuration_problems(repo: &Repository) -> Result<Vec<String>> {
+    use crate::util::c_escape_str;
+    use itertools::Itertools;
+
+    let mut problems = vec!();
+
+    // the configuration file
+    let workdir = repo.workdir().expect("fatal: unable to retrieve git working directory");
+    let local_config = match (try_read_local_config(workdir)?, try_read_staged_config(repo)?) {
+        (Some(local), Some(staged)) if local == staged => local,
+        (Some(local), _) => {
+            problems.push(format!("{} has changed since the repository was set up", CONFIG_FILE));
+            local
+        },
+        (None, _) => {
+            problems.push(format!("{} is missing", CONFIG_FILE));
+            return Ok( problems )
+        }
+    };
+
+    let mut config = Config::try_from(local_config.as_slice())?;
+    expand_path_patterns(&mut config, repo)?;
+
+    // the git configuration keys
+    let git_config = repo.config().map_err(error::OtherGitError::from)?;
+    for (key, value) in GIT_CONFIG.iter() {
+        match git_config.get_string(key) {
+            Ok(val) if val.trim() == value.trim() => {},
+            Ok(_)  => problems.push(format!("the git config {} is outdated", key)),
+            Err(_) => problems.push(format!("the git config {} is not set", key))
+        }
+    }
+
+    // the filter attributes
+    let attributes = read_git_attributes(repo)?;
+    let mut patterns = attributes.lines().filter_map(|line| {
+        let (pattern, attrs) = parse_git_attribute_line(line);
+
+        if GIT_FILTER_ATTR_REGEX.is_match(attrs) { Some(pattern) } else { None }
+    }).collect::<std::collections::HashSet<_>>();
+
+    for path in config.dictionaries.iter().map(|cfg| cfg.path.as_str()) {
+        if !patterns.remove(path) && !patterns.remove(c_escape_str(path).as_str()) {
+            problems.push(format!("{} has no filter attribute", path));
+        }
+    }
+    for pattern in patterns.into_iter().sorted() {
+        problems.push(format!("{} has a filter attribute but is not a managed file", pattern));
+    }
+
+    Ok( problems )
+}
+
+
 /// Configure the repository
 ///
 /// This function makes sure that the repository configuration is up to date.
@@ -135,24 +219,32 @@ pub(super) fn get_validated_config(repo: &Repository) -> Result<Config> {
 ///   nessesary
 ///
 /// - check that the git filter configuration is up to date and update if if
-///   nessesary
+///   nessesary (this includes the merge and textconv drivers)
 ///
 /// - check that the git attributes configuration is up to date and update if if
 ///   nessesary
 ///
-pub(super) fn configure_repository(repo: &mut Repository) -> Result<()> {
+/// - install the git hooks that rebuild the managed files
+///
+/// - take over the managed files that are tracked by Git LFS (the paths of these 
+///   files are returned, as they need to be staged)
+///
+pub(super) fn configure_repository(repo: &mut Repository) -> Result<Vec<String>> {
     use std::collections::HashSet;
     use crate::util::c_escape_str;
     use itertools::Itertools;
 
     // attempt to read the local configuration file
     let workdir = repo.workdir().expect("fatal: unable to retrieve git working directory");
-    let local_config = try_read_local_config(workdir)?.ok_or_else(|| {
-        error::ConfigurationMissing
-    })?;
+    let local_config = try_read_local_config(workdir)?.ok_or(error::ConfigurationMissing)?;
 
     // parse the configuration file
-    let config = Config::try_from(local_config.as_slice())?;
+    let mut config = Config::try_from(local_config.as_slice())?;
+    expand_path_patterns(&mut config, repo)?;
+    load_type_files(&mut config, repo)?;
+    load_validators(&mut config, repo)?;
+    load_issue_policy(&config)?;
+    super::file_format::load_file_formats(&mut config, repo);
 
     // check if the config file needs staging (index version is either different or 
     // does not exist)
@@ -169,10 +261,16 @@ pub(super) fn configure_repository(repo: &mut Repository) -> Result<()> {
         );
     }
 
+    // take over the managed files that are tracked by Git LFS
+    let lfs_paths = super::lfs::take_over_lfs_files(&config, repo)?;
+
+    // update the placeholders of the managed files that are already in the index
+    refresh_placeholders(&config, repo)?;
+
     // update the git config
     let mut git_config = repo.config().map_err(error::OtherGitError::from)?;
 
-    for (key, value) in GIT_CONFIG.iter() {
+    for (key, value) in GIT_CONFIG.iter().chain(GIT_DIFF_CONFIG.iter()) {
         git_config.set_str(key, value).map_err(error::OtherGitError::from)?;
     };
 
@@ -201,8 +299,10 @@ pub(super) fn configure_repository(repo: &mut Repository) -> Result<()> {
             match parse_git_attribute_line(line) {
                 // remove lines matching one of the managed patterns
                 (pattern, _) if managed_paths.contains(pattern)   => None, 
-                // remove lines matching the managed atribute
+                // remove lines matching the managed atributes
                 (_, attr) if GIT_FILTER_ATTR_REGEX.is_match(attr) => None, 
+                (_, attr) if GIT_MERGE_ATTR_REGEX.is_match(attr)  => None, 
+                (_, attr) if GIT_DIFF_ATTR_REGEX.is_match(attr)   => None, 
                 // remove managed comment
                 _         if line.trim() == GIT_COMMENT           => None,
                 // otherwise we want to keep this line
@@ -211,10 +311,13 @@ pub(super) fn configure_repository(repo: &mut Repository) -> Result<()> {
         })
         // add the new patterns for the managed files
         .chain({
-            // generate one line per managed dictionary
-            let new_patterns = config.dictionaries.iter().map(|cfg| 
-                format!("{} {}", c_escape_str(&cfg.path), GIT_FILTER_ATTR)
-            );
+            // generate two lines per managed dictionary (the file and the managed folder)
+            let new_patterns = config.dictionaries.iter().flat_map(|cfg| {
+                vec!(
+                    format!("{} {} {} {}", c_escape_str(&cfg.path), GIT_FILTER_ATTR, GIT_MERGE_ATTR, GIT_DIFF_ATTR),
+                    format!("{} {}", c_escape_str(format!("{}.contents/**", cfg.path)), GIT_MERGE_ATTR)
+                )
+            });
 
             // emit the items
             std::iter::once(GIT_COMMENT.to_owned()).chain(new_patterns)
@@ -227,9 +330,319 @@ pub(super) fn configure_repository(repo: &mut Repository) -> Result<()> {
 
     stdout!("{} updated git attributes file", style("✓").green());
 
+    // install the hooks that rebuild the managed files after a merge or a checkout
+    super::hooks::install_hooks(repo)?;
+
+    Ok( lfs_paths )
+}
+
+
+/// Remove the git configuration of managed files
+///
+/// - remove the git attributes of the managed files and their managed folders
+///
+/// - write and stage the updated configuration file (if given)
+///
+/// - once no managed files remain (`all`), remove the git filter, merge and textconv driver
+///   configuration and the hooks
+///
+pub(super) fn unconfigure_repository(
+    repo: &mut Repository, 
+    paths: &[String], 
+    config_text: Option<&str>, 
+    all: bool
+) -> Result<()> {
+    use std::collections::HashSet;
+    use crate::util::c_escape_str;
+
+    // the patterns of the managed files and their managed folders (both escaped and not)
+    let patterns = paths.iter().flat_map(|path| {
+        let contents = format!("{}.contents/**", path);
+
+        vec!(c_escape_str(path), c_escape_str(&contents), path.clone(), contents)
+    }).collect::<HashSet<String>>();
+
+    let attributes = read_git_attributes(repo)?;
+    let attributes = attributes.lines()
+        .filter(|line| !patterns.contains(parse_git_attribute_line(line).0))
+        // the managed section is removed along with the last managed file
+        .filter(|line| !all || line.trim() != GIT_COMMENT)
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    write_git_attributes(&attributes, repo)?;
+
+    stdout!("{} updated git attributes file", style("✓").green());
+
+    // the configuration file without the managed files
+    if let Some(text) = config_text {
+        write_config_file(repo, text)?;
+    }
+
+    if all {
+        let mut git_config = repo.config().map_err(error::OtherGitError::from)?;
+
+        for (key, _) in GIT_CONFIG.iter().chain(GIT_DIFF_CONFIG.iter()) {
+            match git_config.remove(key) {
+                Ok(_)                                            => {},
+                Err(err) if err.code() == git2::ErrorCode::NotFound => {},
+                Err(err)                                         => bail!(error::OtherGitError::from(err))
+            }
+        }
+
+        stdout!("{} removed the git-toolbox settings from the git config file", style("✓").green());
+
+        super::hooks::remove_hooks(repo)?;
+    }
+
+    Ok( () )
+}
+
+
+/// The managed paths (managed files and managed folders) without the merge attribute
+///
+/// Unlike the filter attribute, the merge attribute is not required to work with the 
+/// managed files, so it is not checked by `get_validated_config()`
+/// Write and stage the configuration file
+pub(super) fn write_config_file(repo: &Repository, text: &str) -> Result<()> {
+    let workdir = repo.workdir().expect("fatal: unable to retrieve git working directory");
+    let path = workdir.join(CONFIG_FILE);
+
+    std::fs::write(&path, text).map_err(|err| {
+        error::FileWriteError {
+            path,
+            msg  : err.to_string()
+        }
+    })?;
+
+    let mut index = repo.index().map_err(error::OtherGitError::from)?;
+    index.add_path(Path::new(CONFIG_FILE)).map_err(error::OtherGitError::from)?;
+    index.write().map_err(error::OtherGitError::from)?;
+
+    stdout!("{} {}", 
+        style("✓").green(),
+        style(format!("git add {}", CONFIG_FILE)).bold()
+    );
+
     Ok( () )
 }
 
+pub(super) fn missing_merge_attributes(config: &Config, repo: &Repository) -> Result<Vec<String>> {
+    use crate::util::c_escape_str;
+
+    let attributes = read_git_attributes(repo)?;
+
+    // all the patterns that have the merge attribute set
+    let patterns = attributes.lines().filter_map(|line| {
+        let (pattern, attrs) = parse_git_attribute_line(line);
+
+        if GIT_MERGE_ATTR_REGEX.is_match(attrs) { Some(pattern) } else { None }
+    }).collect::<std::collections::HashSet<_>>();
+
+    Ok(
+        config.dictionaries.iter()
+            .flat_map(|cfg| vec!(cfg.path.clone(), format!("{}.contents/**", cfg.path)))
+            .filter(|path| !patterns.contains(path.as_str()) && !patterns.contains(c_escape_str(path).as_str()))
+            .collect()
+    )
+}
+
+
+/// Replace the placeholder text of the indexed managed files if it has changed
+///
+/// The placeholder entries are marked as assume-unchanged (see `StagingArea::stage_managed_file()`), 
+/// which is not the case in a fresh clone
+fn refresh_placeholders(config: &Config, repo: &Repository) -> Result<()> {
+    use super::staging_area::{set_placeholder_entry, mark_assume_unchanged};
+    use crate::config::{placeholder_contents, placeholder_with_contents};
+
+    let mut index = repo.index().map_err(error::OtherGitError::from)?;
+    let mut changed = false;
+
+    for cfg in config.dictionaries.iter() {
+        let path = Path::new(&cfg.path);
+        let placeholder = config.managed_file_text(cfg);
+
+        // the file is not yet managed
+        let entry = match index.get_path(path, 0) {
+            Some(entry) => entry,
+            None        => continue
+        };
+
+        // the placeholder is up to date
+        let blob = repo.find_blob(entry.id).map_err(error::OtherGitError::from)?;
+        let text = String::from_utf8_lossy(blob.content());
+        if config.is_managed_file_text(cfg, &text) {
+            drop(text);
+            drop(blob);
+            changed |= mark_assume_unchanged(&mut index, path)?;
+
+            continue
+        }
+
+        // the index contains the file contents rather than a placeholder, which 
+        // is a job for git toolbox doctor
+        if cfg.matches_content(&text) || super::lfs::is_lfs_pointer(blob.content()) { continue }
+
+        // the new placeholder still names the staged managed folder
+        let placeholder = match placeholder_contents(&text) {
+            Some(tree) => placeholder_with_contents(&placeholder, tree),
+            None       => placeholder
+        };
+
+        drop(text);
+        drop(blob);
+
+        set_placeholder_entry(&mut index, path, placeholder.as_bytes())?;
+
+        stdout!("{} updated the placeholder text for {}", style("✓").green(), &cfg.path);
+        changed = true;
+    }
+
+    if changed {
+        index.write().map_err(error::OtherGitError::from)?;
+    }
+
+    Ok( () )
+}
+
+/// Expand the dictionary path patterns of the configuration 
+///
+/// The patterns are matched against the files in the working directory and against the 
+/// managed files in the index (so that a deleted dictionary is still managed)
+fn expand_path_patterns(config: &mut Config, repo: &Repository) -> Result<()> {
+    let roots = config.path_pattern_roots();
+    if roots.is_empty() { return Ok( () ) }
+
+    let workdir = repo.workdir().expect("fatal: unable to retrieve git working directory");
+    let mut candidates = vec!();
+
+    for (root, recursive) in roots {
+        collect_files(workdir, &root, recursive, &mut candidates);
+    }
+
+    let index = repo.index().map_err(error::OtherGitError::from)?;
+    candidates.extend(index.iter().map(|entry| String::from_utf8_lossy(&entry.path).into_owned()));
+
+    config.expand_path_patterns(&candidates);
+
+    Ok( () )
+}
+
+/// Load the type files of the dictionaries 
+///
+/// The record tag and the database type of a dictionary are taken from its type file unless 
+/// they are configured
+fn load_type_files(config: &mut Config, repo: &Repository) -> Result<()> {
+    use crate::toolbox::typ::TypeFile;
+
+    let workdir = repo.workdir().expect("fatal: unable to retrieve git working directory");
+
+    for cfg in config.dictionaries.iter_mut() {
+        if let Some(typ) = &cfg.typ {
+            let typ_file = TypeFile::load(workdir.join(typ))?;
+
+            if cfg.record_tags.is_empty() {
+                cfg.record_tags = typ_file.record_marker.iter().cloned().collect();
+                cfg.record_tag = cfg.record_tags.first().cloned().unwrap_or_default();
+            }
+            if cfg.database_type.is_none() {
+                cfg.database_type = typ_file.database_type.clone();
+            }
+            cfg.typ_file = Some(typ_file);
+        }
+
+        if cfg.record_tag.is_empty() {
+            bail!(
+                error::MissingRecordTag {
+                    name : cfg.name.clone()
+                }
+            );
+        }
+
+        if cfg.normalize_field_order && cfg.field_order().is_none() && cfg.kind != FileKind::Text {
+            bail!(
+                error::MissingFieldOrder {
+                    name : cfg.name.clone()
+                }
+            );
+        }
+    }
+
+    Ok( () )
+}
+
+/// Apply the configured severities of the issue kinds
+fn load_issue_policy(config: &Config) -> Result<()> {
+    use crate::toolbox::{ISSUE_KINDS, set_severity_policy};
+
+    if let Some(kind) = config.issue_severity.keys().find(|kind| !ISSUE_KINDS.contains(&kind.as_str())) {
+        bail!(
+            error::UnknownIssueKind {
+                kind : kind.clone()
+            }
+        );
+    }
+
+    set_severity_policy(&config.issue_severity);
+
+    Ok( () )
+}
+
+/// Assign the external validators to the dictionaries they check
+fn load_validators(config: &mut Config, repo: &Repository) -> Result<()> {
+    let workdir = repo.workdir().expect("fatal: unable to retrieve git working directory");
+    let names = config.dictionaries.iter().map(|cfg| cfg.name.clone()).collect::<Vec<_>>();
+
+    for validator in config.validators.iter_mut() {
+        validator.workdir = workdir.to_owned();
+
+        let unknown = validator.dictionaries.iter().find(|name| !names.contains(name));
+        if let Some(name) = unknown {
+            bail!(
+                error::UnknownValidatorDictionary {
+                    validator : validator.name.clone(),
+                    name      : name.clone()
+                }
+            );
+        }
+    }
+
+    for cfg in config.dictionaries.iter_mut() {
+        cfg.validators = config.validators.iter()
+            .filter(|validator| validator.dictionaries.is_empty() || validator.dictionaries.contains(&cfg.name))
+            .cloned()
+            .collect();
+    }
+
+    Ok( () )
+}
+
+// Collect the paths (relative to the working directory) of the files in the directory
+//
+// The git directory and the managed folders are skipped
+fn collect_files(workdir: &Path, dir: &str, recursive: bool, paths: &mut Vec<String>) {
+    let entries = match std::fs::read_dir(workdir.join(dir)) {
+        Ok( entries ) => entries,
+        Err( _ )      => return
+    };
+
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let path = if dir.is_empty() { name.clone() } else { format!("{}/{}", dir, name) };
+
+        match entry.file_type() {
+            Ok( kind ) if kind.is_dir() => {
+                if recursive && name != ".git" && !name.ends_with(".contents") {
+                    collect_files(workdir, &path, recursive, paths);
+                }
+            },
+            Ok( _ )                     => paths.push(path),
+            Err( _ )                    => {}
+        }
+    }
+}
+
 
 /// Locate and retrieve the contents of the local configuration file
 fn try_read_local_config<P: AsRef<Path>>(workdir: P) -> Result<Option<Vec<u8>>> {
@@ -323,7 +736,7 @@ fn write_git_attributes(text: &str, repo: &mut Repository) -> Result<()> {
 
 
 // need support for git attribute files... 
-fn parse_git_attribute_line(line: &str) -> (&str, &str) {
+pub(super) fn parse_git_attribute_line(line: &str) -> (&str, &str) {
     let line = line.trim();
 
     let prefix_end = if line.starts_with('"') {
@@ -346,10 +759,10 @@ fn parse_git_attribute_line(line: &str) -> (&str, &str) {
             }
         }
 
-        end.unwrap_or_else(|| line.len())
+        end.unwrap_or(line.len())
     } else {
         // this is an unescaped string
-        line.find(' ').unwrap_or_else(|| line.len())
+        line.find(' ').unwrap_or(line.len())
     };
 
     line.split_at(prefix_end)
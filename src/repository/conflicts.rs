@@ -0,0 +1,137 @@
+//
+// src/toolbox/repository
+//
+// The conflicted files of the managed folders.
+//
+// After a merge with conflicts the index contains up to three versions (the
+// common ancestor, ours and theirs) of every conflicted file instead of one.
+// Our and their version are used to list the conflicting records and to resolve
+// conflicts that the merge driver could not mark in the file (e.g. an entry
+// file that was changed on one branch and deleted on the other).
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use super::Repository;
+
+use anyhow::Result;
+use crate::error;
+
+use std::path::Path;
+
+/// A conflicted file (the versions are `None` if the file is absent on that side)
+#[derive(Debug, Clone)]
+pub struct ConflictedFile {
+    /// The path of the file (relative to the repository)
+    pub path   : String,
+    /// Our version
+    pub ours   : Option<String>,
+    /// Their version
+    pub theirs : Option<String>
+}
+
+impl Repository {
+    /// The conflicted files of a managed folder
+    pub fn conflicted_files(&self, contents_path: &str) -> Result<Vec<ConflictedFile>> {
+        let repo = &self.repository;
+        let index = repo.index().map_err(error::OtherGitError::from)?;
+        let prefix = format!("{}/", contents_path);
+
+        if !index.has_conflicts() { return Ok( vec!() ) }
+
+        // the text of a version
+        let text = |entry: Option<git2::IndexEntry>| -> Result<Option<(String, String)>> {
+            match entry {
+                Some(entry) => {
+                    let blob = repo.find_blob(entry.id).map_err(error::OtherGitError::from)?;
+
+                    Ok( Some((
+                        String::from_utf8_lossy(&entry.path).into_owned(),
+                        String::from_utf8_lossy(blob.content()).into_owned()
+                    )) )
+                },
+                None => Ok( None )
+            }
+        };
+
+        let mut files = vec!();
+        for conflict in index.conflicts().map_err(error::OtherGitError::from)? {
+            let conflict = conflict.map_err(error::OtherGitError::from)?;
+
+            let ours = text(conflict.our)?;
+            let theirs = text(conflict.their)?;
+
+            // the path of a file deleted on both sides is only known from the ancestor
+            let base = conflict.ancestor.map(|entry| String::from_utf8_lossy(&entry.path).into_owned());
+
+            let path = match [&ours, &theirs].iter().find_map(|version| version.as_ref()) {
+                Some((path, _)) => path.clone(),
+                None            => match base {
+                    Some(path) => path,
+                    None       => continue
+                }
+            };
+            if !path.starts_with(&prefix) { continue }
+
+            files.push(
+                ConflictedFile {
+                    path,
+                    ours   : ours.map(|(_, text)| text),
+                    theirs : theirs.map(|(_, text)| text)
+                }
+            );
+        }
+
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Ok( files )
+    }
+
+    /// Mark a conflicted file as resolved
+    ///
+    /// The resolved text is written to the working directory and added to the index. A file 
+    /// without a resolved text is removed from both
+    pub fn resolve_conflicted_file(&self, path: &str, text: Option<&str>) -> Result<()> {
+        let full_path = self.workdir()?.join(path);
+        let mut index = self.repository.index().map_err(error::OtherGitError::from)?;
+
+        match text {
+            Some(text) => {
+                if let Some(dir) = full_path.parent() {
+                    std::fs::create_dir_all(dir).map_err(|err| {
+                        error::FileWriteError {
+                            path : dir.to_owned(),
+                            msg  : err.to_string()
+                        }
+                    })?;
+                }
+
+                std::fs::write(&full_path, text).map_err(|err| {
+                    error::FileWriteError {
+                        path : full_path.clone(),
+                        msg  : err.to_string()
+                    }
+                })?;
+
+                index.add_path(Path::new(path)).map_err(error::OtherGitError::from)?;
+            },
+            None => {
+                if full_path.exists() {
+                    std::fs::remove_file(&full_path).map_err(|err| {
+                        error::FileDeleteError {
+                            path : full_path.clone(),
+                            msg  : err.to_string()
+                        }
+                    })?;
+                }
+
+                index.remove_path(Path::new(path)).map_err(error::OtherGitError::from)?;
+            }
+        }
+
+        index.write().map_err(error::OtherGitError::from)?;
+
+        Ok( () )
+    }
+}
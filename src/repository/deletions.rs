@@ -0,0 +1,169 @@
+//
+// src/toolbox/repository
+//
+// Record deletion statistics.
+//
+// A diff that deletes a large part of the records of a dictionary is a typical
+// symptom of a damaged source file (e.g. one that was truncated, or saved with
+// a wrong encoding by another program). The destructive operations (discarding
+// the external changes while staging, resetting the managed files) check how
+// many records they would delete before anything is modified.
+//
+// The records are counted per clob (with its overflow parts), so a record that
+// is moved within a clob is not counted as deleted.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use super::{Repository, ClobDiff};
+use super::manifest::split_records;
+use super::parts::join_clob_parts;
+
+use anyhow::Result;
+use crate::error;
+
+use std::collections::BTreeMap;
+
+/// The records that an operation would delete from a managed file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordDeletions {
+    /// The number of records before the operation
+    pub total   : usize,
+    /// The number of deleted records
+    pub deleted : usize
+}
+
+impl RecordDeletions {
+    /// The deleted records as a percentage of all records
+    pub fn percentage(&self) -> f64 {
+        if self.total == 0 { return 0.0 }
+
+        self.deleted as f64 * 100.0 / self.total as f64
+    }
+
+    /// Check whether more than the percentage of the records would be deleted
+    pub fn exceeds(&self, percentage: f64) -> bool {
+        self.deleted > 0 && self.percentage() > percentage
+    }
+}
+
+
+impl Repository {
+    /// The records of the staged managed folder that staging the diff would delete
+    pub fn records_deleted_by_staging(&self, contents_path: &str, record_tags: &[String], diff: &[ClobDiff])
+        -> Result<RecordDeletions>
+    {
+        let staged = self.index_clob_parts(contents_path)?;
+        let updated = apply_diff(contents_path, staged.clone(), diff);
+
+        Ok( count_deletions(staged, updated, record_tags) )
+    }
+
+    /// The records of the managed file that restoring it would delete
+    ///
+    /// The diff is the unstaged diff of the managed file, which is restored from the index
+    /// or from a revision (if given)
+    pub fn records_deleted_by_restoring(
+        &self,
+        contents_path: &str,
+        record_tags: &[String],
+        diff: &[ClobDiff],
+        rev: Option<&str>
+    ) -> Result<RecordDeletions> {
+        let staged = self.index_clob_parts(contents_path)?;
+        let current = apply_diff(contents_path, staged.clone(), diff);
+
+        let restored = match rev {
+            Some(rev) => self.rev_clob_parts(contents_path, rev)?,
+            None      => staged
+        };
+
+        Ok( count_deletions(current, restored, record_tags) )
+    }
+
+    /// The clobs of a managed folder in a revision, with the overflow parts as separate clobs
+    ///
+    /// The clob paths are relative to the managed folder
+    pub(super) fn rev_clob_parts(&self, contents_path: &str, rev: &str) -> Result<Vec<(String, String)>> {
+        use git2::{TreeWalkMode, TreeWalkResult, ObjectType};
+
+        let repo = &self.repository;
+
+        let tree = repo.revparse_single(rev)
+            .and_then(|obj| obj.peel_to_tree())
+            .map_err(|_| {
+                error::GitRevisionNotFound {
+                    rev : rev.to_owned()
+                }
+            })?;
+
+        // the managed folder does not exist in the revision
+        let entry = match tree.get_path(std::path::Path::new(contents_path)) {
+            Ok(entry) => entry,
+            Err(_)    => return Ok( vec!() )
+        };
+        let tree = repo.find_tree(entry.id()).map_err(error::OtherGitError::from)?;
+
+        let mut clobs = vec!();
+        tree.walk(TreeWalkMode::PreOrder, |root, entry| {
+            let name = String::from_utf8_lossy(entry.name_bytes()).into_owned();
+
+            if entry.kind() == Some(ObjectType::Blob) && name.ends_with(".txt") {
+                if let Ok(blob) = repo.find_blob(entry.id()) {
+                    clobs.push((format!("{}{}", root, name), String::from_utf8_lossy(blob.content()).into_owned()));
+                }
+            }
+
+            TreeWalkResult::Ok
+        }).map_err(error::OtherGitError::from)?;
+
+        Ok( clobs )
+    }
+}
+
+
+// Apply the diff to the clobs of a managed folder (the clob paths are relative to the folder)
+pub(super) fn apply_diff(contents_path: &str, mut clobs: Vec<(String, String)>, diff: &[ClobDiff]) -> Vec<(String, String)> {
+    let prefix = format!("{}/", contents_path);
+
+    for change in diff {
+        let path = match change.path().strip_prefix(&prefix) {
+            Some(path) => path,
+            None       => continue
+        };
+
+        clobs.retain(|(clob, _)| clob != path);
+        match change {
+            ClobDiff::Add { clob } | ClobDiff::Update { clob } => {
+                clobs.push((path.to_owned(), clob.content.clone()));
+            },
+            ClobDiff::Delete { path : _ } => {}
+        }
+    }
+
+    clobs
+}
+
+// The records that are deleted between two versions of the clobs
+fn count_deletions(before: Vec<(String, String)>, after: Vec<(String, String)>, record_tags: &[String]) -> RecordDeletions {
+    let count = |clobs: Vec<(String, String)>| -> BTreeMap<String, usize> {
+        join_clob_parts(clobs).into_iter().map(|(path, text)| {
+            let records = split_records(&text, record_tags).len();
+
+            (path, records)
+        })
+        .collect()
+    };
+
+    let before = count(before);
+    let after = count(after);
+
+    RecordDeletions {
+        total   : before.values().sum(),
+        deleted : before.iter().map(|(path, records)| {
+            records.saturating_sub(after.get(path).copied().unwrap_or(0))
+        })
+        .sum()
+    }
+}
@@ -15,7 +15,7 @@
 use super::Repository;
 
 /// A text data object stored in a filesystem
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Clob {
     /// The path where these records should be stored
     pub path    : String,
@@ -23,7 +23,7 @@ pub struct Clob {
     pub content : String
 }
 
-type ClobStream = Box<dyn Iterator<Item = Clob>>;
+type ClobStream<'a> = Box<dyn Iterator<Item = Clob> + 'a>;
 
 
 /// A filesystem update action
@@ -35,14 +35,20 @@ pub enum ClobDiff {
 }
 
 // Clob validation error
+#[derive(serde::Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
 pub enum ClobValidationIssue {
     AddedInWorkdir   { path: String },
     DeletedInWorkdir { path: String },
     UpdatedInWorkdir { path: String },
-    InvalidPath      { path: Vec<u8> } 
+    InvalidPath      { 
+        #[serde(serialize_with = "serialize_lossy")]
+        path: Vec<u8> 
+    } 
 }
 
 /// Diff summary
+#[derive(serde::Serialize)]
 pub struct DiffStats {
     pub added   : usize, 
     pub changed : usize, 
@@ -51,6 +57,8 @@ pub struct DiffStats {
 
 use anyhow::Result;
 use crate::error;
+use crate::sorting::SortPolicy;
+use crate::config::DictionaryConfig;
 
 impl Repository {
     /// Checks the contents of a managed folder for external modifications
@@ -58,9 +66,12 @@ impl Repository {
     /// This will run a git status check on a managed folder and pick any
     /// *.txt file that was changed in the working directory
     ///
+    /// With `include_untracked`, the files git does not know about are reported as 
+    /// added as well (which requires scanning the whole managed folder)
+    ///
     /// Note: this won't catch external changes if they have been added to 
     /// the index
-    pub fn validate_clobs_in_workdir<P>(&self, root: P) -> Result<Vec<ClobValidationIssue>>
+    pub fn validate_clobs_in_workdir<P>(&self, root: P, include_untracked: bool) -> Result<Vec<ClobValidationIssue>>
     where 
         P: AsRef<str>
 
@@ -74,6 +85,8 @@ impl Repository {
             let mut status_options = StatusOptions::new();
             status_options.pathspec(root.as_ref());
             status_options.include_ignored(false);
+            status_options.include_untracked(include_untracked);
+            status_options.recurse_untracked_dirs(include_untracked);
 
             repo.statuses(Some(&mut status_options)).map_err(error::OtherGitError::from)?
         };
@@ -199,9 +212,66 @@ impl Repository {
 
         Ok( diff )
     }
+    /// Reads the content of a file (e.g. a clob) as currently recorded in the index
+    ///
+    /// Returns `None` if the file does not exist or is not valid text
+    pub fn read_text_from_index<P: AsRef<str>>(&self, path: P) -> Option<String> {
+        let index = self.repository.index().ok()?;
+        let entry = index.get_path(std::path::Path::new(path.as_ref()), 0)?;
+        let blob  = self.repository.find_blob(entry.id).ok()?;
+
+        String::from_utf8(blob.content().to_owned()).ok()
+    }
+
+    /// Reads the content of a file as recorded in the HEAD commit
+    ///
+    /// Returns `None` if the file does not exist or is not valid text
+    pub fn read_text_from_head<P: AsRef<str>>(&self, path: P) -> Option<String> {
+        let tree  = self.repository.head().ok()?.peel_to_tree().ok()?;
+        let entry = tree.get_path(std::path::Path::new(path.as_ref())).ok()?;
+        let blob  = self.repository.find_blob(entry.id()).ok()?;
+
+        String::from_utf8(blob.content().to_owned()).ok()
+    }
+
+    /// Reads the content of a file as recorded in a revision (the index if the revision is empty)
+    ///
+    /// Returns `None` if the file does not exist or is not valid text
+    pub fn read_text_from_rev<P: AsRef<str>, S: AsRef<str>>(&self, path: P, rev: S) -> Result<Option<String>> {
+        let rev = rev.as_ref();
+        if rev.is_empty() {
+            return Ok( self.read_text_from_index(path) )
+        }
+
+        let tree = self.repository.revparse_single(rev)
+            .and_then(|obj| obj.peel_to_tree())
+            .map_err(|_| {
+                error::GitRevisionNotFound {
+                    rev : rev.to_owned()
+                }
+            })?;
+
+        let text = tree.get_path(std::path::Path::new(path.as_ref())).ok()
+            .and_then(|entry| self.repository.find_blob(entry.id()).ok())
+            .and_then(|blob| String::from_utf8(blob.content().to_owned()).ok());
+
+        Ok( text )
+    }
+
+    /// Reconstructs the contents of a managed file as recorded in the HEAD commit
+    ///
+    /// Returns `None` if there are no commits yet or the managed folder is not in HEAD
+    pub fn read_managed_text_from_head<P: AsRef<str>>(&self, contents_root: P, sort: &SortPolicy) -> Option<String> {
+        self.repository.head().ok()?;
+
+        let data = super::reconstruct::reconstruct_from_rev(&self.repository, contents_root, "HEAD", sort).ok()?;
+
+        String::from_utf8(data).ok()
+    }
+
     /// Performs a diff of the clobs and the repository and returns a list
     /// of file actions required to update the clob state
-    pub fn diff_clobs_at_path<P>(&self, root: P, clobs: ClobStream) -> Result<Vec<ClobDiff>> 
+    pub fn diff_clobs_at_path<P>(&self, root: P, clobs: ClobStream<'_>) -> Result<Vec<ClobDiff>> 
     where 
         P: AsRef<str>
     {
@@ -215,7 +285,8 @@ impl Repository {
         // the set of clobs at the path
         //
         // we use this to detect which clobs are updated and which have been deleted
-        let mut clobset = std::collections::HashSet::new(); 
+        // (the paths are compared case-insensitively, but deleted with their original case)
+        let mut clobset = std::collections::HashMap::new(); 
 
         // query the status of the files at the path
         let statuses = {
@@ -233,7 +304,8 @@ impl Repository {
             // ignore files that are deleted or renamed in the index
             if status.status().is_index_deleted() { continue }
             
-            // TODO: detect cases where the contents have been tampered with
+            // the clobs edited by hand are not special here: changes in the working directory
+            // are reported by validate_clobs_in_workdir() and staged ones by hand_edited_clobs()
 
             // get the path, reporting an error if it is not valid unicode
             let path = status.path().ok_or_else(|| {
@@ -242,12 +314,14 @@ impl Repository {
                 error::InvalidManagedPath {
                     path
                 }
-            })?
-            .to_lowercase();
+            })?;
 
-            clobset.insert(path);
+            clobset.insert(path.to_lowercase(), path.to_owned());
         };
 
+        // the dictionary of the managed folder (the changes to its ignored fields are not reported)
+        let cfg = self.config.dictionaries.iter().find(|cfg| root == format!("{}.contents", cfg.path));
+
         // the list of actions to perform
         let mut diff_list = vec!();
         
@@ -270,10 +344,14 @@ impl Repository {
                     let oid = Oid::hash_object(ObjectType::Blob, clob.content.as_bytes())?;
                     // the content has changed if the id OR the content itself has changed
                     let clob_contents = clob.content.as_bytes();
-                    if oid != entry.id || repo.find_blob(entry.id)?.content() != clob_contents {
-                        Some(ClobDiff::Update { clob })
-                    } else {
+                    let stored = repo.find_blob(entry.id)?;
+                    if oid == entry.id && stored.content() == clob_contents {
+                        None
+                    } else if cfg.map(|cfg| same_except_ignored_fields(cfg, stored.content(), &clob.content)).unwrap_or(false) {
+                        // only the ignored fields have changed, the stored clob is kept
                         None
+                    } else {
+                        Some(ClobDiff::Update { clob })
                     }
                 },
                 // no such entry
@@ -288,8 +366,11 @@ impl Repository {
             }
         }
 
-        // all files still in the set must have been deleted
-        for path in clobset {
+        // all files still in the set must have been deleted (listed in a stable order)
+        let mut deleted = clobset.into_values().collect::<Vec<_>>();
+        deleted.sort_unstable();
+
+        for path in deleted {
             // save the file change action
             diff_list.push( ClobDiff::Delete { path } );
         }
@@ -298,18 +379,78 @@ impl Repository {
     } 
 }
 
+// Check whether the stored clob only differs from the content in the ignored fields
+fn same_except_ignored_fields(cfg: &DictionaryConfig, stored: &[u8], content: &str) -> bool {
+    if cfg.ignore_fields.is_empty() { return false }
+
+    match std::str::from_utf8(stored) {
+        Ok(stored) => cfg.without_ignored_fields(stored) == cfg.without_ignored_fields(content),
+        Err(_)     => false
+    }
+}
+
 impl Clob {
+    /// Check that the clob can be safely stored
+    ///
+    /// The splitters never produce clobs with unsafe paths (the records are quarantined 
+    /// instead), so an unsafe path is an internal error
     pub fn validated(self) -> Self {
         assert!(self.path.is_ascii(), 
             "fatal - non-ascii CLOB name '{}' violates internal assumttions", 
             &self.path
         );
 
+        if let Some(problem) = clob_path_problem(&self.path) {
+            panic!("fatal - unsafe CLOB path '{}' ({}) violates internal assumptions", &self.path, problem);
+        }
+
         self
     }
 }
 
 
+/// The file names that are reserved on Windows (regardless of the extension)
+pub const RESERVED_NAMES : [&str; 22] = [
+    "con", "prn", "aux", "nul", 
+    "com1", "com2", "com3", "com4", "com5", "com6", "com7", "com8", "com9",
+    "lpt1", "lpt2", "lpt3", "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9"
+];
+
+/// The reason why a clob path cannot be safely written to the managed folder (if any)
+///
+/// A clob path must stay within its managed folder: it must be relative, must not contain
+/// `.` or `..` components and must only use forward slashes as separators. The components 
+/// must not be reserved names (`.git` or the reserved device names of Windows)
+pub fn clob_path_problem(path: &str) -> Option<&'static str> {
+    if path.is_empty() {
+        return Some("empty path");
+    } 
+    if path.starts_with('/') || path.contains(':') {
+        return Some("absolute path");
+    }
+    if path.contains('\\') {
+        return Some("backslash in path");
+    }
+    if path.chars().any(|c| !c.is_ascii() || c.is_ascii_control()) {
+        return Some("invalid character");
+    }
+
+    for component in path.split('/') {
+        let stem = component.split('.').next().unwrap_or(component).to_ascii_lowercase();
+
+        if component.is_empty() {
+            return Some("empty path component");
+        } else if component == "." || component == ".." {
+            return Some("path traversal");
+        } else if component.eq_ignore_ascii_case(".git") || RESERVED_NAMES.contains(&stem.as_str()) {
+            return Some("reserved name");
+        }
+    }
+
+    None
+}
+
+
 impl ClobDiff {
     pub fn diff_marker(&self) -> &str {
         match self {
@@ -329,8 +470,9 @@ impl ClobDiff {
         }
     }
 
+    /// The file name of the changed clob (for an overflow part, the name of its clob)
     pub fn filename(&self) -> &str {
-        let path = self.path();
+        let (path, _) = super::parts::clob_part(self.path());
 
         path.rsplit('/').next().expect("internal error: clob is not a file")
     }
@@ -341,7 +483,7 @@ impl ClobDiff {
                 &clob.path                
             },
             ClobDiff::Delete { path } => {
-                &path
+                path
             }
         }
     }
@@ -361,11 +503,21 @@ impl ClobValidationIssue {
             }
         }
     }
+
+    /// The kind of the issue (e.g. `added-in-workdir`)
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ClobValidationIssue::AddedInWorkdir   { path: _ } => "added-in-workdir",
+            ClobValidationIssue::DeletedInWorkdir { path: _ } => "deleted-in-workdir",
+            ClobValidationIssue::UpdatedInWorkdir { path: _ } => "updated-in-workdir",
+            ClobValidationIssue::InvalidPath      { path: _ } => "invalid-path"
+        }
+    }
 }
 
 
 impl DiffStats {
-    pub fn count(diff: &[ClobDiff]) -> Self {
+    pub fn count<'a, I: IntoIterator<Item = &'a ClobDiff>>(diff: I) -> Self {
         let mut added = 0;
         let mut changed = 0;
         let mut deleted = 0;
@@ -407,3 +559,26 @@ impl Display for DiffStats {
         Ok( () )
     }
 }
+
+
+/// Diffs are serialized as the change and the path (without the content)
+impl serde::Serialize for ClobDiff {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let change = match self {
+            ClobDiff::Add { clob: _ }    => "added",
+            ClobDiff::Update { clob: _ } => "modified",
+            ClobDiff::Delete { path: _ } => "deleted"
+        };
+
+        let mut state = serializer.serialize_struct("ClobDiff", 2)?;
+        state.serialize_field("change", change)?;
+        state.serialize_field("path", self.path())?;
+        state.end()
+    }
+}
+
+fn serialize_lossy<S: serde::Serializer>(bytes: &[u8], serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&String::from_utf8_lossy(bytes))
+}
@@ -0,0 +1,98 @@
+//
+// src/toolbox/repository
+//
+// The line endings and byte order marks of the managed files.
+//
+// The managed contents always use Unix line endings and never start with a
+// byte order mark. The managed files that used Windows line endings or started
+// with a UTF-8 byte order mark when they were last staged are listed in a local
+// file (tab-separated: the path and the space-separated properties `crlf` and
+// `bom`), so that they are reconstructed byte for byte as they were.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use super::Repository;
+
+use anyhow::Result;
+use crate::config::Config;
+use crate::error;
+
+// name of the file with the formats of the managed files (in the git-toolbox data directory)
+const FILE_FORMATS_FILE : &str = "file-formats";
+
+
+/// The properties of a managed file that are not stored in its managed folder
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileFormat {
+    /// The file uses Windows line endings
+    pub crlf : bool,
+    /// The file starts with a UTF-8 byte order mark
+    pub bom  : bool
+}
+
+
+impl Repository {
+    /// Record the format of the managed file at `path`
+    pub fn record_file_format(&self, path: &str, format: FileFormat) -> Result<()> {
+        let path_to_list = self.data_dir()?.join(FILE_FORMATS_FILE);
+
+        let mut formats = read_file_formats(&self.repository);
+        let previous = formats.iter().position(|(p, _)| p == path);
+        if previous.map(|i| formats[i].1).unwrap_or_default() == format {
+            return Ok( () );
+        }
+
+        if let Some(i) = previous { formats.remove(i); }
+        if format != FileFormat::default() {
+            formats.push((path.to_owned(), format));
+            formats.sort_by(|(a, _), (b, _)| a.cmp(b));
+        }
+
+        let text = formats.iter().map(|(path, format)| {
+            let properties = [("crlf", format.crlf), ("bom", format.bom)].iter()
+                .filter(|(_, set)| *set)
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>();
+
+            format!("{}\t{}\n", path, properties.join(" "))
+        })
+        .collect::<String>();
+
+        std::fs::write(&path_to_list, text).map_err(|err| {
+            error::FileWriteError {
+                path : path_to_list,
+                msg  : err.to_string()
+            }
+            .into()
+        })
+    }
+}
+
+
+/// Set the formats the dictionaries had when they were last staged
+pub(super) fn load_file_formats(config: &mut Config, repo: &git2::Repository) {
+    let formats = read_file_formats(repo);
+
+    for cfg in config.dictionaries.iter_mut() {
+        let format = formats.iter().find(|(path, _)| path == &cfg.path).map(|(_, format)| *format);
+        let format = format.unwrap_or_default();
+
+        cfg.staged_crlf = format.crlf;
+        cfg.staged_bom = format.bom;
+    }
+}
+
+// The recorded file formats (a missing list is empty, malformed lines are skipped)
+fn read_file_formats(repo: &git2::Repository) -> Vec<(String, FileFormat)> {
+    let text = std::fs::read_to_string(repo.path().join("toolbox").join(FILE_FORMATS_FILE)).unwrap_or_default();
+
+    text.lines().filter_map(|line| {
+        let (path, properties) = line.split_once('\t')?;
+        let properties = properties.split_whitespace().collect::<Vec<_>>();
+
+        Some( (path.to_owned(), FileFormat { crlf : properties.contains(&"crlf"), bom : properties.contains(&"bom") }) )
+    })
+    .collect()
+}
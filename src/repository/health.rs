@@ -0,0 +1,129 @@
+//
+// src/toolbox/repository
+//
+// Repository health indicators.
+//
+// Lightweight checks of the setup that the managed files depend on, shown by
+// git toolbox status so that a misconfiguration is noticed before it damages
+// the data (rather than only when git toolbox doctor is run). The git filter
+// configuration and the filter attributes are already validated when the
+// repository is opened, here we check what is not:
+//
+// - git can run the filter (git-toolbox is on the PATH)
+// - the managed files and folders have the merge attribute
+// - the staged managed folders match their record manifests
+//
+// along with the time of the last stage operation in the audit log.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use super::Repository;
+
+use serde::Serialize;
+
+// the command git runs for the filter
+const FILTER_COMMAND : &str = "git-toolbox";
+
+
+/// The health indicators of a repository
+///
+/// The problems are described for each indicator (empty if everything is fine)
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RepositoryHealth {
+    /// Problems with running the git filter
+    pub filter     : Vec<String>,
+    /// Problems with the git attributes
+    pub attributes : Vec<String>,
+    /// Problems with the staged managed folders
+    pub contents   : Vec<String>,
+    /// Time of the last stage operation (seconds since the Unix epoch)
+    pub last_stage : Option<i64>
+}
+
+impl Repository {
+    /// Check the health of the repository
+    ///
+    /// A failing check is reported as a problem rather than an error
+    pub fn health(&self) -> RepositoryHealth {
+        let mut health = RepositoryHealth::default();
+
+        // the filter
+        if !filter_on_path() {
+            health.filter.push(format!("{} is not on the PATH, git cannot run the filter", FILTER_COMMAND));
+        }
+
+        // the attributes
+        match self.missing_merge_attributes() {
+            Ok(paths) => health.attributes.extend(paths.into_iter().map(|path| {
+                format!("{} has no merge attribute", path)
+            })),
+            Err(err)  => health.attributes.push(err.to_string())
+        }
+
+        // the managed folders
+        for cfg in self.config.dictionaries.iter() {
+            let contents_path = format!("{}.contents", cfg.path);
+
+            match self.verify_staged_records(&contents_path, &cfg.record_tags) {
+                Ok(Some(changes)) if !changes.is_empty() => {
+                    health.contents.push(format!("{} records in {} do not match the manifest", changes.len(), contents_path));
+                },
+                Ok(_)    => {},
+                Err(err) => health.contents.push(err.to_string())
+            }
+        }
+
+        // the last stage
+        health.last_stage = self.read_audit_log().ok().and_then(|entries| {
+            entries.iter().rev().find(|entry| entry.operation == "stage").map(|entry| entry.time)
+        });
+
+        health
+    }
+
+    /// The managed paths (managed files and managed folders) without the merge attribute
+    pub fn missing_merge_attributes(&self) -> anyhow::Result<Vec<String>> {
+        super::config::missing_merge_attributes(&self.config, &self.repository)
+    }
+}
+
+
+/// Check if git can find git-toolbox to run the filter
+pub fn filter_on_path() -> bool {
+    command_on_path(FILTER_COMMAND)
+}
+
+/// The version of git-toolbox that git runs as the filter
+///
+/// `None` if it cannot be run or does not report its version (older versions)
+pub fn filter_version() -> Option<String> {
+    let output = std::process::Command::new(FILTER_COMMAND).arg("--version").output().ok()?;
+
+    // the version is printed as `git-toolbox <version>`
+    [output.stdout, output.stderr].iter()
+        .flat_map(|text| String::from_utf8_lossy(text).lines().map(str::to_owned).collect::<Vec<_>>())
+        .find_map(|line| {
+            line.trim().strip_prefix(&format!("{} ", FILTER_COMMAND)).map(|version| version.trim().to_owned())
+        })
+        .filter(|version| !version.is_empty())
+}
+
+
+// Check if the command can be found on the PATH
+fn command_on_path(command: &str) -> bool {
+    let path = match std::env::var_os("PATH") {
+        Some(path) => path,
+        None       => return false
+    };
+
+    let names = if cfg!(windows) {
+        vec!(format!("{}.exe", command), command.to_owned())
+    } else {
+        vec!(command.to_owned())
+    };
+
+    std::env::split_paths(&path).any(|dir| names.iter().any(|name| dir.join(name).is_file()))
+}
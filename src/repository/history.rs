@@ -0,0 +1,166 @@
+//
+// src/toolbox/repository
+//
+// The commit history of individual files (such as the clobs backing a record).
+//
+// Besides the list of commits that changed a file, the lines of a file can be 
+// blamed: each line is attributed to the commit that last changed it.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use super::Repository;
+use super::parts::PARTS_SUFFIX;
+
+use anyhow::Result;
+use crate::error;
+
+/// A commit that changed a file
+pub struct FileRevision {
+    /// Id of the commit
+    pub commit  : String,
+    /// The author name and e-mail
+    pub author  : String,
+    /// Time of the commit (seconds since the Unix epoch) and the timezone offset (in minutes)
+    pub time    : (i64, i32),
+    /// The first line of the commit message
+    pub summary : String,
+    /// The content of the file before the commit (`None` if the file did not exist)
+    pub old     : Option<String>,
+    /// The content of the file after the commit (`None` if the file was deleted)
+    pub new     : Option<String>
+}
+
+/// A line of a file with the commit that last changed it
+pub struct BlamedLine {
+    /// Id of the commit
+    pub commit  : String,
+    /// The author name and e-mail
+    pub author  : String,
+    /// Time of the change (seconds since the Unix epoch) and the timezone offset (in minutes)
+    pub time    : (i64, i32),
+    /// The text of the line (without the line end)
+    pub text    : String
+}
+
+
+impl Repository {
+    /// The commits reachable from HEAD that changed the file (newest first)
+    ///
+    /// As with `git log`, a merge commit is only listed if the file differs from 
+    /// all of its parents. The file can be given by several paths it might have had 
+    /// (e.g. a clob that was moved to a deeper prefix directory), the first one that 
+    /// exists in a commit is used
+    pub fn file_history<P : AsRef<str>>(&self, paths: &[P]) -> Result<Vec<FileRevision>> {
+        use git2::{Sort, Oid};
+
+        let repo = &self.repository;
+        let paths = paths.iter().map(|path| std::path::Path::new(path.as_ref())).collect::<Vec<_>>();
+
+        // a repository without commits has no history
+        if repo.head().is_err() {
+            return Ok( vec!() )
+        }
+
+        let mut revwalk = repo.revwalk().map_err(error::OtherGitError::from)?;
+        revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME).map_err(error::OtherGitError::from)?;
+        revwalk.push_head().map_err(error::OtherGitError::from)?;
+
+        // the blob of the file in a commit (if any)
+        let blob_id = |commit: &git2::Commit| -> Option<Oid> {
+            let tree = commit.tree().ok()?;
+
+            paths.iter().find_map(|path| tree.get_path(path).ok()).map(|entry| entry.id())
+        };
+
+        let read_blob = |id: Option<Oid>| -> Option<String> {
+            let blob = repo.find_blob(id?).ok()?;
+
+            Some( String::from_utf8_lossy(blob.content()).into_owned() )
+        };
+
+        let mut revisions = vec!();
+        for oid in revwalk {
+            let commit = oid.and_then(|oid| repo.find_commit(oid)).map_err(error::OtherGitError::from)?;
+
+            let new = blob_id(&commit);
+            let old = commit.parents().map(|parent| blob_id(&parent)).collect::<Vec<_>>();
+
+            // the file is unchanged relative to (one of) the parents
+            if old.contains(&new) || (old.is_empty() && new.is_none()) {
+                continue;
+            }
+
+            let author = commit.author();
+            revisions.push(
+                FileRevision {
+                    commit  : commit.id().to_string(),
+                    author  : format!("{} <{}>", author.name().unwrap_or("unknown"), author.email().unwrap_or("")),
+                    time    : (commit.time().seconds(), commit.time().offset_minutes()),
+                    summary : commit.summary().unwrap_or("").to_owned(),
+                    old     : read_blob(old.first().copied().flatten()),
+                    new     : read_blob(new)
+                }
+            );
+        }
+
+        Ok( revisions )
+    }
+
+    /// The lines of the file in HEAD, each with the commit that last changed it
+    ///
+    /// The file can be given by several paths it might have (the first one that exists 
+    /// in HEAD is used). The overflow parts of a clob are blamed with the clob, so that
+    /// the lines are the same as those of the complete clob. The result is empty if 
+    /// the file does not exist in HEAD.
+    pub fn blame_lines<P : AsRef<str>>(&self, paths: &[P]) -> Result<Vec<BlamedLine>> {
+        use std::path::Path;
+
+        let repo = &self.repository;
+
+        // a repository without commits has nothing to blame
+        let tree = match repo.head().and_then(|head| head.peel_to_tree()) {
+            Ok(tree) => tree,
+            Err(_)   => return Ok( vec!() )
+        };
+
+        let path = match paths.iter().map(|path| path.as_ref()).find(|path| tree.get_path(Path::new(path)).is_ok()) {
+            Some(path) => path,
+            None       => return Ok( vec!() )
+        };
+
+        // the clob followed by its overflow parts
+        let files = std::iter::once(path.to_owned())
+            .chain((2 ..).map(|part| format!("{}{}/{}.txt", path, PARTS_SUFFIX, part)))
+            .take_while(|file| tree.get_path(Path::new(file)).is_ok());
+
+        let mut lines = vec!();
+        for file in files {
+            let blob = tree.get_path(Path::new(&file))
+                .and_then(|entry| repo.find_blob(entry.id()))
+                .map_err(error::OtherGitError::from)?;
+            let blame = repo.blame_file(Path::new(&file), None).map_err(error::OtherGitError::from)?;
+
+            let content = String::from_utf8_lossy(blob.content());
+            for (i, text) in content.lines().enumerate() {
+                let hunk = match blame.get_line(i + 1) {
+                    Some(hunk) => hunk,
+                    None       => continue
+                };
+                let author = hunk.final_signature();
+
+                lines.push(
+                    BlamedLine {
+                        commit : hunk.final_commit_id().to_string(),
+                        author : format!("{} <{}>", author.name().unwrap_or("unknown"), author.email().unwrap_or("")),
+                        time   : (author.when().seconds(), author.when().offset_minutes()),
+                        text   : text.to_owned()
+                    }
+                );
+            }
+        }
+
+        Ok( lines )
+    }
+}
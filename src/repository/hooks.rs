@@ -0,0 +1,196 @@
+//
+// src/toolbox/repository
+//
+// Git hook installation.
+//
+// Git does not rewrite a managed file when only its managed folder changes
+// (the placeholder text in the index stays the same), so after a pull, a merge
+// or a branch switch the managed file would still show the old records (or the
+// placeholder text, if the file was checked out before the managed folder).
+// The hooks installed here run git-toolbox hook, which rebuilds such files.
+// A commit only needs this after a cherry-pick that merged the placeholder 
+// (which then does not name the managed folder).
+//
+// Hooks that were not installed by git-toolbox (or were extended by hand) are
+// left alone.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use super::Clob;
+use super::parts::join_clob_parts;
+use anyhow::Result;
+use crate::error;
+
+use git2::Repository;
+use std::path::PathBuf;
+use std::collections::BTreeMap;
+use crate::cli_app::style;
+
+/// The hooks that rebuild the managed files
+pub const GIT_HOOKS : [&str; 4] = ["post-merge", "post-checkout", "post-rewrite", "post-commit"];
+
+// the command that identifies the hooks installed by git-toolbox
+const HOOK_COMMAND : &str = "git-toolbox hook";
+
+// the comment of the hooks installed by git-toolbox
+const HOOK_COMMENT : &str = "# installed by git toolbox setup: rebuilds the managed toolbox files";
+
+
+impl super::Repository {
+    /// Check whether the clobs (relative to the managed folder) are the clobs of the managed 
+    /// folder in a revision
+    ///
+    /// The paths are compared case-insensitively and the ignored fields of the dictionary are 
+    /// not compared
+    pub fn clobs_match_rev(&self, contents_path: &str, clobs: Vec<Clob>, rev: &str) -> Result<bool> {
+        // the changes to the ignored fields do not count
+        let cfg = self.config.dictionaries.iter().find(|cfg| contents_path == format!("{}.contents", cfg.path));
+        let content = |text: String| match cfg {
+            Some(cfg) => cfg.without_ignored_fields(&text).into_owned(),
+            None      => text
+        };
+
+        let stored : BTreeMap<_, _> = join_clob_parts(self.rev_clob_parts(contents_path, rev)?)
+            .into_iter()
+            .map(|(path, text)| (path.to_lowercase(), content(text)))
+            .collect();
+        let clobs : BTreeMap<_, _> = clobs.into_iter()
+            .map(|clob| (clob.path.to_lowercase(), content(clob.content)))
+            .collect();
+
+        Ok( stored == clobs )
+    }
+}
+
+
+/// Install the git hooks that rebuild the managed files
+pub(super) fn install_hooks(repo: &Repository) -> Result<()> {
+    let hooks_dir = hooks_dir(repo)?;
+
+    std::fs::create_dir_all(&hooks_dir).map_err(|err| {
+        error::FileWriteError {
+            path : hooks_dir.clone(),
+            msg  : err.to_string()
+        }
+    })?;
+
+    let mut installed = vec!();
+    for hook in GIT_HOOKS.iter() {
+        let path = hooks_dir.join(hook);
+        let line = format!("{} {} \"$@\"", HOOK_COMMAND, hook);
+
+        if let Ok(script) = std::fs::read_to_string(&path) {
+            // a hook of another tool (or of the user)
+            if !script.contains(HOOK_COMMAND) {
+                stdout!("{} {} already exists, add the line {} to it to rebuild the managed files",
+                    style("!").yellow(),
+                    path.display(),
+                    style(&line).bold()
+                );
+                continue;
+            }
+
+            // a hook that was extended by hand already runs git-toolbox
+            if !is_generated_hook(&script) {
+                continue;
+            }
+        }
+
+        let script = format!("#!/bin/sh\n{}\n{}\n", HOOK_COMMENT, line);
+        std::fs::write(&path, script).map_err(|err| {
+            error::FileWriteError {
+                path : path.clone(),
+                msg  : err.to_string()
+            }
+        })?;
+        make_executable(&path)?;
+
+        installed.push(*hook);
+    }
+
+    if !installed.is_empty() {
+        stdout!("{} installed git hooks ({})", style("✓").green(), installed.join(", "));
+    }
+
+    Ok( () )
+}
+
+/// Remove the git hooks that rebuild the managed files
+///
+/// Only the git-toolbox lines are removed from the hooks that were extended by hand
+pub(super) fn remove_hooks(repo: &Repository) -> Result<()> {
+    let hooks_dir = hooks_dir(repo)?;
+
+    let mut removed = vec!();
+    for hook in GIT_HOOKS.iter() {
+        let path = hooks_dir.join(hook);
+        let script = match std::fs::read_to_string(&path) {
+            Ok(script) if script.contains(HOOK_COMMAND) => script,
+            _                                          => continue
+        };
+
+        let rest = script.lines()
+            .filter(|line| !line.contains(HOOK_COMMAND) && line.trim() != HOOK_COMMENT)
+            .collect::<Vec<_>>();
+
+        // nothing but the shebang is left
+        let result = if rest.iter().all(|line| line.trim().is_empty() || line.starts_with("#!")) {
+            std::fs::remove_file(&path)
+        } else {
+            std::fs::write(&path, format!("{}\n", rest.join("\n")))
+        };
+        result.map_err(|err| {
+            error::FileWriteError {
+                path : path.clone(),
+                msg  : err.to_string()
+            }
+        })?;
+
+        removed.push(*hook);
+    }
+
+    if !removed.is_empty() {
+        stdout!("{} removed git hooks ({})", style("✓").green(), removed.join(", "));
+    }
+
+    Ok( () )
+}
+
+// Whether the hook script contains nothing but the lines written by install_hooks
+fn is_generated_hook(script: &str) -> bool {
+    script.lines().map(str::trim).all(|line| {
+        line.is_empty() || line == "#!/bin/sh" || line == HOOK_COMMENT || line.starts_with(HOOK_COMMAND)
+    })
+}
+
+// The hooks directory (core.hooksPath is relative to the working directory)
+fn hooks_dir(repo: &Repository) -> Result<PathBuf> {
+    let config = repo.config().map_err(error::OtherGitError::from)?;
+
+    match config.get_path("core.hooksPath") {
+        Ok(path) if path.is_absolute() => Ok( path ),
+        Ok(path)                       => Ok( repo.workdir().unwrap_or_else(|| repo.path()).join(path) ),
+        Err(_)                         => Ok( repo.path().join("hooks") )
+    }
+}
+
+#[cfg(unix)]
+fn make_executable(path: &std::path::Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).map_err(|err| {
+        error::FileWriteError {
+            path : path.to_owned(),
+            msg  : err.to_string()
+        }
+    })?;
+
+    Ok( () )
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &std::path::Path) -> Result<()> {
+    Ok( () )
+}
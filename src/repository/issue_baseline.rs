@@ -0,0 +1,136 @@
+//
+// src/toolbox/repository
+//
+// The acknowledged issues of the managed files.
+//
+// Long-lived projects often have many issues that nobody is going to fix soon. 
+// These can be acknowledged in a file in the working directory (committed with the
+// project), so that only the issues introduced later are reported. Every line of
+// the file is tab-separated: the managed file, the ID of the record (or its label 
+// if the dictionary has no IDs, `-` for the issues outside of the records) and the
+// kind of the issue.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use super::Repository;
+
+use anyhow::Result;
+use crate::error;
+use crate::toolbox::{Dictionary, ToolboxFileIssue};
+use std::collections::{BTreeSet, HashMap};
+
+/// Name of the file with the acknowledged issues (in the working directory)
+pub const ISSUE_BASELINE_FILE : &str = ".toolbox-ignore";
+
+// the key of the issues that do not occur in a record
+const NO_RECORD : &str = "-";
+
+const BASELINE_HEADER : &str = concat!(
+    "# Issues acknowledged with git toolbox check --update-baseline\n",
+    "# <managed file>\t<record ID or label>\t<issue kind>\n"
+);
+
+
+/// The acknowledged issues (by the managed file, the record and the kind of the issue)
+#[derive(Debug, Clone, Default)]
+pub struct IssueBaseline {
+    entries : BTreeSet<(String, String, String)>
+}
+
+impl IssueBaseline {
+    /// Split the issues of the dictionary into the new and the acknowledged ones
+    pub fn partition(&self, dictionary: &Dictionary, issues: Vec<ToolboxFileIssue>) -> (Vec<ToolboxFileIssue>, Vec<ToolboxFileIssue>) {
+        if self.entries.is_empty() {
+            return (issues, vec!());
+        }
+
+        let path = &dictionary.config().path;
+        let keys = dictionary.record_keys();
+
+        issues.into_iter().partition(|issue| {
+            let entry = (path.clone(), issue_key(&keys, issue), issue.kind().to_owned());
+
+            !self.entries.contains(&entry)
+        })
+    }
+
+    /// Acknowledge the issues of the dictionary (replacing the ones acknowledged before)
+    ///
+    /// Returns the number of acknowledged issues
+    pub fn acknowledge(&mut self, dictionary: &Dictionary, issues: &[ToolboxFileIssue]) -> usize {
+        let path = &dictionary.config().path;
+        let keys = dictionary.record_keys();
+
+        self.entries.retain(|(file, _, _)| file != path);
+        self.entries.extend(issues.iter().map(|issue| {
+            (path.clone(), issue_key(&keys, issue), issue.kind().to_owned())
+        }));
+
+        self.entries.iter().filter(|(file, _, _)| file == path).count()
+    }
+}
+
+// The key of the record the issue occurs in
+fn issue_key(keys: &HashMap<usize, String>, issue: &ToolboxFileIssue) -> String {
+    issue.record()
+        .and_then(|record| keys.get(&record.line))
+        .filter(|key| !key.is_empty())
+        .cloned()
+        .unwrap_or_else(|| NO_RECORD.to_owned())
+}
+
+
+impl Repository {
+    /// The acknowledged issues (none if the baseline file does not exist)
+    pub fn issue_baseline(&self) -> Result<IssueBaseline> {
+        let path = self.workdir()?.join(ISSUE_BASELINE_FILE);
+
+        if !path.exists() {
+            return Ok( IssueBaseline::default() );
+        }
+
+        let text = std::fs::read_to_string(&path).map_err(|err| {
+            error::FileReadError {
+                path : path.clone(),
+                msg  : err.to_string()
+            }
+        })?;
+
+        // comments and malformed lines are skipped
+        let entries = text.lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+            .filter_map(|line| {
+                let mut fields = line.split('\t').map(str::trim);
+
+                match (fields.next(), fields.next(), fields.next()) {
+                    (Some(path), Some(record), Some(kind)) if !path.is_empty() => {
+                        Some( (path.to_owned(), record.to_owned(), kind.to_owned()) )
+                    },
+                    _ => None
+                }
+            })
+            .collect();
+
+        Ok( IssueBaseline { entries } )
+    }
+
+    /// Write the acknowledged issues to the baseline file
+    pub fn write_issue_baseline(&self, baseline: &IssueBaseline) -> Result<()> {
+        let path = self.workdir()?.join(ISSUE_BASELINE_FILE);
+
+        let text = baseline.entries.iter().fold(BASELINE_HEADER.to_owned(), |mut text, (file, record, kind)| {
+            text.push_str(&format!("{}\t{}\t{}\n", file, record, kind));
+            text
+        });
+
+        std::fs::write(&path, text).map_err(|err| {
+            error::FileWriteError {
+                path,
+                msg : err.to_string()
+            }
+            .into()
+        })
+    }
+}
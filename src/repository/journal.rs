@@ -0,0 +1,286 @@
+//
+// src/toolbox/repository
+//
+// The operation journal.
+//
+// Operations that modify the index, the managed folders and the managed files
+// (such as stage and reset) do so in several steps. Before the first step, the
+// operation is recorded in a journal file in the git-toolbox data directory,
+// along with a copy of the git index and (if the operation rewrites them) of
+// the managed files. The journal is removed once the operation is complete, so
+// a journal found by a later git-toolbox process was left behind by an operation
+// that was interrupted (e.g. by a crash or Ctrl-C). Such an operation can be
+// rolled back (the index, the managed files and the managed folders are restored
+// from the copies) or resumed (rolled back and run again).
+//
+// The journal file has tab-separated fields: the first line is the operation,
+// the process id and the time, the second line is the git-toolbox command that
+// runs the operation again and the remaining lines are the managed files (with
+// whether a copy was kept).
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use super::Repository;
+
+use anyhow::Result;
+use crate::error;
+
+use std::path::{Path, PathBuf};
+
+// name of the journal file (in the git-toolbox data directory)
+const JOURNAL_FILE : &str = "journal";
+
+// the copy of the git index (in the git-toolbox data directory)
+const JOURNAL_INDEX : &str = "journal.index";
+
+// the copies of the managed files (in the git-toolbox data directory)
+const JOURNAL_BACKUP_DIR : &str = "journal.backup";
+
+
+/// An operation in progress, the journal is removed by `complete()`
+pub struct Journal {
+    data_dir : PathBuf
+}
+
+/// The journal entry of an interrupted operation
+#[derive(Debug, Clone)]
+pub struct JournalEntry {
+    /// The operation (e.g. `stage`)
+    pub operation : String,
+    /// Id of the process that ran the operation
+    pub pid       : u32,
+    /// Time the operation was started (seconds since the Unix epoch)
+    pub time      : u64,
+    /// The arguments of git-toolbox that run the operation again
+    pub command   : Vec<String>,
+    /// The managed files (relative to the repository) and whether a copy was kept
+    pub files     : Vec<(String, bool)>
+}
+
+
+impl Repository {
+    /// Record the start of an operation that modifies the managed files
+    ///
+    /// The paths are relative to the repository. The `command` runs the operation again
+    /// (without the program name). With `backup_files`, copies of the managed files are
+    /// kept as well (for the operations that rewrite them)
+    pub fn begin_journal(
+        &self,
+        operation: &str,
+        command: Vec<String>,
+        files: &[String],
+        backup_files: bool
+    ) -> Result<Journal> {
+        let data_dir = self.data_dir()?;
+        let workdir = self.workdir()?;
+
+        // remove the leftovers of a previous journal
+        remove_journal(&data_dir)?;
+
+        // the copy of the index
+        let index_path = self.repository.path().join("index");
+        if index_path.exists() {
+            copy_file(&index_path, &data_dir.join(JOURNAL_INDEX))?;
+        }
+
+        // the copies of the managed files
+        let mut journal_files = vec!();
+        for (i, path) in files.iter().enumerate() {
+            let full_path = workdir.join(path);
+            let backup = backup_files && full_path.is_file();
+
+            if backup {
+                let backup_dir = data_dir.join(JOURNAL_BACKUP_DIR);
+                std::fs::create_dir_all(&backup_dir).map_err(|err| {
+                    error::FileWriteError {
+                        path : backup_dir.clone(),
+                        msg  : err.to_string()
+                    }
+                })?;
+
+                copy_file(&full_path, &backup_dir.join(i.to_string()))?;
+            }
+
+            journal_files.push((path.clone(), backup));
+        }
+
+        let entry = JournalEntry {
+            operation : operation.to_owned(),
+            pid       : std::process::id(),
+            time      : current_time(),
+            command,
+            files     : journal_files
+        };
+
+        // the journal file is written last, so that it is only found once the copies are complete
+        let path = data_dir.join(JOURNAL_FILE);
+        let tmp_path = data_dir.join(format!("{}.tmp", JOURNAL_FILE));
+        std::fs::write(&tmp_path, entry.to_string())
+            .and_then(|_| std::fs::rename(&tmp_path, &path))
+            .map_err(|err| {
+                error::FileWriteError {
+                    path,
+                    msg  : err.to_string()
+                }
+            })?;
+
+        Ok( Journal { data_dir } )
+    }
+
+    /// The journal of an interrupted operation (if any)
+    ///
+    /// Only meaningful while holding the operation lock (otherwise the operation might be
+    /// still in progress)
+    pub fn interrupted_operation(&self) -> Result<Option<JournalEntry>> {
+        let path = self.data_dir()?.join(JOURNAL_FILE);
+
+        Ok( std::fs::read_to_string(path).ok().and_then(|text| JournalEntry::parse(&text)) )
+    }
+
+    /// Roll back an interrupted operation
+    ///
+    /// The index and the managed files are restored from their copies and the managed
+    /// folders from the restored index. The journal is removed
+    pub fn rollback_journal(&self, entry: &JournalEntry) -> Result<()> {
+        let data_dir = self.data_dir()?;
+        let workdir = self.workdir()?.to_owned();
+
+        let index_backup = data_dir.join(JOURNAL_INDEX);
+        if index_backup.exists() {
+            copy_file(&index_backup, &self.repository.path().join("index"))?;
+
+            // the index might be cached
+            self.repository.index()
+                .and_then(|mut index| index.read(true))
+                .map_err(error::OtherGitError::from)?;
+        }
+
+        for (i, (path, backup)) in entry.files.iter().enumerate() {
+            if *backup {
+                copy_file(&data_dir.join(JOURNAL_BACKUP_DIR).join(i.to_string()), &workdir.join(path))?;
+            }
+
+            self.restore_folder_from_index(format!("{}.contents", path))?;
+        }
+
+        remove_journal(&data_dir)
+    }
+
+    /// Remove the journal of an interrupted operation without rolling it back
+    pub fn discard_journal(&self) -> Result<()> {
+        remove_journal(&self.data_dir()?)
+    }
+}
+
+
+impl Journal {
+    /// Mark the operation as complete (the journal and the copies are removed)
+    pub fn complete(self) -> Result<()> {
+        remove_journal(&self.data_dir)
+    }
+
+    /// Undo the operation after it failed (see `Repository::rollback_journal()`)
+    pub fn rollback(self, repo: &Repository) -> Result<()> {
+        let path = self.data_dir.join(JOURNAL_FILE);
+        let entry = std::fs::read_to_string(&path).ok()
+            .and_then(|text| JournalEntry::parse(&text))
+            .ok_or_else(|| {
+                error::FileReadError {
+                    path,
+                    msg  : "the journal is missing or damaged".to_owned()
+                }
+            })?;
+
+        repo.rollback_journal(&entry)
+    }
+}
+
+
+impl JournalEntry {
+    // Parse the journal file
+    fn parse(text: &str) -> Option<JournalEntry> {
+        let mut lines = text.lines();
+
+        let mut fields = lines.next()?.split('\t');
+        let operation = fields.next()?.to_owned();
+        let pid = fields.next()?.parse().ok()?;
+        let time = fields.next()?.parse().ok()?;
+
+        let command = lines.next()?.split('\t').map(str::to_owned).collect();
+
+        let files = lines.map(|line| {
+            let (backup, path) = line.split_once('\t')?;
+
+            Some( (path.to_owned(), backup == "1") )
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+        Some( JournalEntry { operation, pid, time, command, files } )
+    }
+}
+
+
+use std::fmt::{Display, Formatter};
+
+impl Display for JournalEntry {
+    fn fmt(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        writeln!(formatter, "{}\t{}\t{}", self.operation, self.pid, self.time)?;
+        writeln!(formatter, "{}", self.command.join("\t"))?;
+
+        for (path, backup) in self.files.iter() {
+            writeln!(formatter, "{}\t{}", if *backup { 1 } else { 0 }, path)?;
+        }
+
+        Ok( () )
+    }
+}
+
+
+// Remove the journal file and the copies (the journal file first, so that a partially
+// removed journal is never found)
+fn remove_journal(data_dir: &Path) -> Result<()> {
+    for name in [JOURNAL_FILE, JOURNAL_INDEX] {
+        let path = data_dir.join(name);
+        if !path.exists() { continue }
+
+        std::fs::remove_file(&path).map_err(|err| {
+            error::FileDeleteError {
+                path : path.clone(),
+                msg  : err.to_string()
+            }
+        })?;
+    }
+
+    let backup_dir = data_dir.join(JOURNAL_BACKUP_DIR);
+    if backup_dir.exists() {
+        std::fs::remove_dir_all(&backup_dir).map_err(|err| {
+            error::FileDeleteError {
+                path : backup_dir.clone(),
+                msg  : err.to_string()
+            }
+        })?;
+    }
+
+    Ok( () )
+}
+
+// Copy a file (reporting the target on errors)
+fn copy_file(from: &Path, to: &Path) -> Result<()> {
+    std::fs::copy(from, to).map_err(|err| {
+        error::FileWriteError {
+            path : to.to_owned(),
+            msg  : err.to_string()
+        }
+    })?;
+
+    Ok( () )
+}
+
+// Current time in seconds since the Unix epoch
+fn current_time() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
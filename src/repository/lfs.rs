@@ -0,0 +1,189 @@
+//
+// src/toolbox/repository
+//
+// Git LFS interoperability.
+//
+// Toolbox dictionaries that are tracked by Git LFS are stored in the repository
+// as LFS pointers. When such a file becomes managed, we fetch the real content,
+// remove the LFS attributes for the path and let git-toolbox take over.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::config::Config;
+use anyhow::Result;
+use crate::error;
+
+use git2::Repository;
+use std::path::Path;
+use crate::cli_app::style;
+
+// all LFS pointer files start with this line
+const LFS_POINTER_PREFIX : &[u8] = b"version https://git-lfs.github.com/spec/v1";
+
+// the git attributes file of the working directory
+const GIT_ATTRIBUTES_FILE : &str = ".gitattributes";
+
+/// Check whether the data is a Git LFS pointer
+pub fn is_lfs_pointer(data: &[u8]) -> bool {
+    data.starts_with(LFS_POINTER_PREFIX)
+}
+
+
+/// Take over the managed files that are currently tracked by Git LFS
+///
+/// A managed file is considered to be tracked by LFS if it is stored as an LFS pointer
+/// in the index. For every such file we make sure that the real content is present in
+/// the working directory and that the LFS attributes no longer apply to it.
+///
+/// Returns the paths of the files that were taken over. These still have to be staged.
+pub(super) fn take_over_lfs_files(config: &Config, repo: &Repository) -> Result<Vec<String>> {
+    let workdir = repo.workdir().expect("fatal: unable to retrieve git working directory");
+    let index = repo.index().map_err(error::OtherGitError::from)?;
+
+    // locate the LFS tracked files
+    let mut paths = vec!();
+    for cfg in config.dictionaries.iter() {
+        let entry = match index.get_path(Path::new(&cfg.path), 0) {
+            Some(entry) => entry,
+            None        => continue
+        };
+
+        let blob = repo.find_blob(entry.id).map_err(error::OtherGitError::from)?;
+        if is_lfs_pointer(blob.content()) {
+            paths.push(cfg.path.clone());
+        }
+    }
+
+    if paths.is_empty() {
+        return Ok( paths )
+    }
+
+    for path in paths.iter() {
+        fetch_lfs_content(workdir, path)?;
+    }
+
+    remove_lfs_attributes(repo, &paths)?;
+
+    Ok( paths )
+}
+
+// Make sure that the working directory contains the real content and not the pointer
+fn fetch_lfs_content(workdir: &Path, path: &str) -> Result<()> {
+    use std::process::Command;
+
+    let absolute_path = workdir.join(path);
+    let is_pointer = || -> Result<bool> {
+        std::fs::read(&absolute_path)
+            .map(|data| is_lfs_pointer(&data))
+            .map_err(|err| {
+                error::FileReadError {
+                    path : absolute_path.clone(),
+                    msg  : err.to_string()
+                }
+                .into()
+            })
+    };
+
+    // the content is already there
+    if !is_pointer()? {
+        return Ok( () )
+    }
+
+    let output = super::pass_lock_to(&mut Command::new("git"))
+        .args(["lfs", "pull", "--include", path])
+        .current_dir(workdir)
+        .output()
+        .map_err(|err| {
+            error::LfsFetchFailed {
+                path : path.to_owned(),
+                msg  : err.to_string()
+            }
+        })?;
+
+    if !output.status.success() || is_pointer()? {
+        anyhow::bail!(
+            error::LfsFetchFailed {
+                path : path.to_owned(),
+                msg  : String::from_utf8_lossy(&output.stderr).trim().to_owned()
+            }
+        );
+    }
+
+    stdout!("{} fetched the LFS content of {}", style("✓").green(), path);
+
+    Ok( () )
+}
+
+// Remove the LFS attributes for the paths from the git attributes file
+//
+// Lines that only concern the path are removed. If the LFS attributes still
+// apply (e.g. via a wildcard pattern), we override them for the path
+fn remove_lfs_attributes(repo: &Repository, paths: &[String]) -> Result<()> {
+    use crate::util::c_escape_str;
+    use git2::AttrCheckFlags;
+    use itertools::Itertools;
+
+    let workdir = repo.workdir().expect("fatal: unable to retrieve git working directory");
+    let attributes_path = workdir.join(GIT_ATTRIBUTES_FILE);
+
+    let attributes = std::fs::read_to_string(&attributes_path).unwrap_or_default();
+
+    let mut lines = attributes.lines()
+        .filter(|line| {
+            let (pattern, attrs) = super::config::parse_git_attribute_line(line);
+
+            let is_lfs = attrs.split_whitespace().any(|attr| attr == "filter=lfs");
+            let is_managed = paths.iter().any(|path| {
+                pattern == path || pattern == c_escape_str(path)
+            });
+
+            !(is_lfs && is_managed)
+        })
+        .map(|line| line.to_owned())
+        .collect::<Vec<_>>();
+
+    write_git_attributes(&attributes_path, &lines.iter().join("\n"))?;
+
+    // override the LFS attributes that still apply
+    let mut overridden = false;
+    for path in paths {
+        let diff = repo.get_attr(Path::new(path), "diff", AttrCheckFlags::FILE_THEN_INDEX)
+            .map_err(error::OtherGitError::from)?;
+
+        if diff == Some("lfs") {
+            lines.push(format!("{} -filter -diff -merge text", c_escape_str(path)));
+            overridden = true;
+        }
+    }
+
+    if overridden {
+        write_git_attributes(&attributes_path, &lines.iter().join("\n"))?;
+    }
+
+    // and stage the attributes file
+    let mut index = repo.index().map_err(error::OtherGitError::from)?;
+    index.add_path(Path::new(GIT_ATTRIBUTES_FILE)).map_err(error::OtherGitError::from)?;
+    index.write().map_err(error::OtherGitError::from)?;
+
+    stdout!("{} removed the LFS attributes for {}", style("✓").green(), paths.join(", "));
+    stdout!("{} {}", style("✓").green(), style(format!("git add {}", GIT_ATTRIBUTES_FILE)).bold());
+
+    Ok( () )
+}
+
+fn write_git_attributes(path: &Path, text: &str) -> Result<()> {
+    let mut text = text.to_owned();
+    if !text.is_empty() {
+        text.push('\n');
+    }
+
+    std::fs::write(path, text).map_err(|err| {
+        error::FileWriteError {
+            path : path.to_owned(),
+            msg  : err.to_string()
+        }
+        .into()
+    })
+}
@@ -0,0 +1,471 @@
+//
+// src/toolbox/repository
+//
+// Repository locks. 
+//
+// The operation lock: commands that modify the repository hold an advisory 
+// lock file in the git-toolbox data directory, so that concurrent git-toolbox
+// processes do not interleave their index and working directory writes. 
+// The lock file records the process id, the operation and the time. It is
+// written to a temporary file first and then linked into place, so that the
+// lock file is never seen half-written. A lock left behind by a crashed 
+// process is detected (the process does not exist anymore or the lock is too 
+// old) and replaced. 
+//
+// With --wait, a command waits for the lock to be released instead of failing.
+//
+// The git index lock: git holds `index.lock` while it updates the index. A 
+// crashed git process leaves it behind, which blocks all index updates. Such
+// a lock is detected by its age and the absence of running git processes. 
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use super::Repository;
+
+use anyhow::Result;
+use crate::error;
+
+use std::path::{Path, PathBuf};
+
+// name of the lock file (in the git-toolbox data directory)
+const LOCK_FILE : &str = "operation.lock";
+
+// locks older than this (in seconds) are considered stale
+const STALE_LOCK_AGE : u64 = 60*60;
+
+// unreadable locks older than this (in seconds) are considered stale (a lock written without
+// linking can be seen before its contents)
+const STALE_UNREADABLE_LOCK_AGE : u64 = 10;
+
+// index locks older than this (in seconds) are considered stale if no git process is running
+const STALE_INDEX_LOCK_AGE : u64 = 10*60;
+
+// how often a waiting command checks the lock (in milliseconds)
+const LOCK_POLL_INTERVAL : u64 = 250;
+
+// the environment variable that passes the lock to the child processes 
+// (e.g. git running our clean filter on behalf of git-toolbox)
+const LOCK_HOLDER_VAR : &str = "GIT_TOOLBOX_LOCK_HOLDER";
+
+
+/// Pass the operation lock to a child process 
+///
+/// git runs our filters in separate processes, which should not fail on the lock held by 
+/// the git-toolbox process that spawned git. The id of this process is passed whether it 
+/// holds the lock or not (the lock of another process is never ignored)
+pub fn pass_lock_to(command: &mut std::process::Command) -> &mut std::process::Command {
+    command.env(LOCK_HOLDER_VAR, std::process::id().to_string())
+}
+
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// whether to wait for the operation lock to be released
+static WAIT_FOR_LOCK : AtomicBool = AtomicBool::new(false);
+
+/// Wait for the operation lock held by another process instead of failing
+pub fn set_wait_for_lock(wait: bool) {
+    WAIT_FOR_LOCK.store(wait, Ordering::Relaxed)
+}
+
+
+/// An acquired operation lock, released when dropped
+pub struct OperationLock {
+    path : PathBuf
+}
+
+/// The state of the git index lock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexLock {
+    /// The index is not locked
+    Unlocked,
+    /// The index is being updated by git
+    Active,
+    /// The lock was left behind by a crashed git process (age in seconds)
+    Stale { age: u64 }
+}
+
+/// The contents of a lock file
+#[derive(Debug, Clone)]
+struct LockInfo {
+    // id of the process holding the lock
+    pid       : u32,
+    // the operation (e.g. `stage`)
+    operation : String,
+    // time the lock was acquired (seconds since the Unix epoch)
+    time      : u64
+}
+
+
+impl Repository {
+    /// Acquire the operation lock for a command that modifies the repository
+    ///
+    /// It is an error if an earlier operation was interrupted (see `git toolbox recover`)
+    pub fn lock_operation<S : AsRef<str>>(&self, operation: S) -> Result<OperationLock> {
+        let lock = OperationLock::acquire(self.repository.path(), operation.as_ref())?;
+
+        if let Some(entry) = self.interrupted_operation()? {
+            return Err(
+                error::InterruptedOperation {
+                    operation : entry.operation
+                }
+                .into()
+            )
+        }
+
+        Ok( lock )
+    }
+
+    /// Acquire the operation lock for recovering from an interrupted operation
+    pub fn lock_recovery(&self) -> Result<OperationLock> {
+        OperationLock::acquire(self.repository.path(), "recover")
+    }
+
+    /// Fail if another git-toolbox process is running an operation (without 
+    /// validating the configuration)
+    ///
+    /// A lock held by the parent git-toolbox process (if any) is ignored
+    pub fn check_for_operation() -> Result<()> {
+        let repository = Repository::__open()?;
+        let path = lock_path(repository.path());
+        
+        let parent = std::env::var(LOCK_HOLDER_VAR).ok().and_then(|pid| pid.parse::<u32>().ok());
+
+        match LockInfo::read(&path) {
+            Some(holder) if !holder.is_stale() && Some(holder.pid) != parent => {
+                Err( holder.into_error(path) )
+            },
+            _ => Ok( () )
+        }
+    }
+
+    /// Check if the git index is locked for writing without validating the configuration
+    pub fn check_for_lock() -> Result<IndexLock> {
+        let repository = Repository::__open()?;
+
+        Ok( index_lock(repository.path()) )
+    }
+
+    /// The state of the git index lock
+    pub fn index_lock(&self) -> IndexLock {
+        index_lock(self.repository.path())
+    }
+
+    /// Remove a stale git index lock
+    ///
+    /// Returns `false` if the index lock is not stale (and was not removed)
+    pub fn remove_stale_index_lock(&self) -> Result<bool> {
+        let path = self.repository.path().join("index.lock");
+
+        if !matches!(self.index_lock(), IndexLock::Stale { age: _ }) {
+            return Ok( false )
+        }
+
+        std::fs::remove_file(&path).map_err(|err| {
+            error::FileDeleteError {
+                path,
+                msg  : err.to_string()
+            }
+        })?;
+
+        Ok( true )
+    }
+}
+
+
+impl OperationLock {
+    /// Acquire the lock for the repository with the given git directory
+    pub(super) fn acquire(git_dir: &Path, operation: &str) -> Result<OperationLock> {
+        let path = lock_path(git_dir);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|err| {
+                error::FileWriteError {
+                    path : dir.to_owned(),
+                    msg  : err.to_string()
+                }
+            })?;
+        }
+
+        let info = LockInfo {
+            pid       : std::process::id(),
+            operation : operation.to_owned(),
+            time      : current_time()
+        };
+
+        // whether we are waiting for another process to release the lock
+        let mut waiting = false;
+        // the stale locks we removed (a stale lock is replaced only once)
+        let mut removed = 0;
+
+        loop {
+            if create_lock_file(&path, &info)? {
+                return Ok( OperationLock { path } );
+            }
+
+            match LockInfo::read(&path) {
+                // a live lock, wait for it to be released
+                Some(holder) if !holder.is_stale() && WAIT_FOR_LOCK.load(Ordering::Relaxed) => {
+                    if !waiting {
+                        stderr!("Waiting for another git-toolbox operation to finish ({} in process {}) ...",
+                            holder.operation,
+                            holder.pid
+                        );
+                        waiting = true;
+                    }
+
+                    std::thread::sleep(std::time::Duration::from_millis(LOCK_POLL_INTERVAL));
+                },
+                // a live lock, bail
+                Some(holder) if !holder.is_stale() => {
+                    return Err( holder.into_error(path) )
+                },
+                // somebody else grabbed the lock in the meantime
+                _ if removed > 0 && !WAIT_FOR_LOCK.load(Ordering::Relaxed) => break,
+                // a stale lock (or one that was unreadable for too long), remove it
+                holder if holder.is_some() || lock_age(&path) > STALE_UNREADABLE_LOCK_AGE => {
+                    removed += 1;
+
+                    remove_stale_lock(&path, holder.as_ref())?;
+                },
+                // a lock that is just being written (or was released in the meantime)
+                _ => {
+                    std::thread::sleep(std::time::Duration::from_millis(LOCK_POLL_INTERVAL));
+                }
+            }
+        }
+
+        // somebody else grabbed the lock in the meantime
+        let holder = LockInfo::read(&path).unwrap_or(
+            LockInfo { pid: 0, operation: "unknown".to_owned(), time: 0 }
+        );
+        
+        Err( holder.into_error(path) )
+    }
+}
+
+impl Drop for OperationLock {
+    fn drop(&mut self) {
+        // nothing we can do if this fails, the lock will be detected as stale 
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+
+// Create the lock file with the lock info
+//
+// The info is written to a temporary file that is then hard-linked to the lock file, which
+// fails if the lock file exists. Where hard links are not supported, the lock file is created
+// and written directly. Returns `false` if the lock file exists
+fn create_lock_file(path: &Path, info: &LockInfo) -> Result<bool> {
+    use std::io::{Write, ErrorKind};
+    use std::fs::OpenOptions;
+
+    let write_error = |err: std::io::Error| -> anyhow::Error {
+        error::FileWriteError {
+            path : path.to_owned(),
+            msg  : err.to_string()
+        }
+        .into()
+    };
+
+    let temp_path = path.with_extension(format!("lock.{}", info.pid));
+    std::fs::write(&temp_path, format!("{}\n", info)).map_err(write_error)?;
+
+    let linked = std::fs::hard_link(&temp_path, path);
+    let _ = std::fs::remove_file(&temp_path);
+
+    match linked {
+        Ok(()) => return Ok( true ),
+        Err(err) if err.kind() == ErrorKind::AlreadyExists => return Ok( false ),
+        Err(_) => {}
+    }
+
+    // hard links are not supported by the file system
+    match OpenOptions::new().write(true).create_new(true).open(path) {
+        Ok(mut file) => {
+            writeln!(file, "{}", info).map_err(write_error)?;
+
+            Ok( true )
+        },
+        Err(err) if err.kind() == ErrorKind::AlreadyExists => Ok( false ),
+        Err(err) => Err( write_error(err) )
+    }
+}
+
+// Remove a stale lock file
+//
+// The lock is moved aside first and checked again, so that a lock acquired by another process
+// since the stale lock was read is put back rather than removed
+fn remove_stale_lock(path: &Path, stale: Option<&LockInfo>) -> Result<()> {
+    use std::io::ErrorKind;
+
+    let aside = path.with_extension(format!("stale.{}", std::process::id()));
+
+    match std::fs::rename(path, &aside) {
+        Ok(()) => {},
+        // somebody else removed it already
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok( () ),
+        Err(err) => {
+            return Err(
+                error::FileDeleteError {
+                    path : path.to_owned(),
+                    msg  : err.to_string()
+                }
+                .into()
+            )
+        }
+    }
+
+    let current = LockInfo::read(&aside);
+    let replaced = match (stale, &current) {
+        (Some(stale), Some(current)) => stale.pid != current.pid || stale.time != current.time,
+        (None, Some(_))              => true,
+        _                            => false
+    };
+    if replaced && !current.map(|holder| holder.is_stale()).unwrap_or(true) {
+        // nothing we can do if this fails, the other process has lost its lock
+        let _ = std::fs::hard_link(&aside, path);
+    }
+
+    let _ = std::fs::remove_file(&aside);
+
+    Ok( () )
+}
+
+// The age of a file in seconds (0 if it cannot be determined)
+fn lock_age(path: &Path) -> u64 {
+    use std::time::SystemTime;
+
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .map(|age| age.as_secs())
+        .unwrap_or(0)
+}
+
+
+impl LockInfo {
+    // Read the lock file (if any)
+    fn read(path: &Path) -> Option<LockInfo> {
+        let text = std::fs::read_to_string(path).ok()?;
+        let mut fields = text.trim().split('\t');
+
+        Some(
+            LockInfo {
+                pid       : fields.next()?.parse().ok()?,
+                operation : fields.next()?.to_owned(),
+                time      : fields.next()?.parse().ok()?
+            }
+        )
+    }
+
+    // Check if the lock was left behind by a crashed process
+    fn is_stale(&self) -> bool {
+        current_time().saturating_sub(self.time) > STALE_LOCK_AGE || !process_exists(self.pid)
+    }
+
+    fn into_error(self, path: PathBuf) -> anyhow::Error {
+        error::OperationInProgress {
+            operation : self.operation,
+            pid       : self.pid,
+            path
+        }
+        .into()
+    }
+}
+
+// The lock file in the git-toolbox data directory
+fn lock_path(git_dir: &Path) -> PathBuf {
+    git_dir.join("toolbox").join(LOCK_FILE)
+}
+
+// The state of the index lock in the git directory
+fn index_lock(git_dir: &Path) -> IndexLock {
+    use std::time::SystemTime;
+
+    let path = git_dir.join("index.lock");
+
+    let modified = match std::fs::metadata(&path) {
+        Ok(metadata) => metadata.modified().ok(),
+        Err(_)       => return IndexLock::Unlocked
+    };
+
+    let age = modified
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .map(|age| age.as_secs())
+        .unwrap_or(0);
+
+    if age > STALE_INDEX_LOCK_AGE && !git_process_running() {
+        IndexLock::Stale { age }
+    } else {
+        IndexLock::Active
+    }
+}
+
+// Check if a git process (other than our ancestors, e.g. the git process running
+// our clean filter) is running. Assumed to be the case where this cannot be checked
+fn git_process_running() -> bool {
+    let proc_dir = Path::new("/proc");
+
+    if !cfg!(target_os = "linux") || !proc_dir.is_dir() {
+        return true
+    }
+
+    // the parent process id (from /proc/<pid>/stat, the field after the command name)
+    let parent = |pid: u32| -> Option<u32> {
+        let stat = std::fs::read_to_string(proc_dir.join(pid.to_string()).join("stat")).ok()?;
+        
+        stat.rsplit(')').next()?.split_whitespace().nth(1)?.parse().ok()
+    };
+
+    let mut ancestors = vec!(std::process::id());
+    while let Some(pid) = ancestors.last().and_then(|pid| parent(*pid)).filter(|pid| *pid > 1) {
+        ancestors.push(pid);
+    }
+
+    let entries = match std::fs::read_dir(proc_dir) {
+        Ok(entries) => entries,
+        Err(_)      => return true
+    };
+
+    entries
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<u32>().ok())
+        .filter(|pid| !ancestors.contains(pid))
+        .any(|pid| {
+            std::fs::read_to_string(proc_dir.join(pid.to_string()).join("comm"))
+                .map(|name| {
+                    let name = name.trim();
+                    name == "git" || (name.starts_with("git-") && !name.starts_with("git-toolbox"))
+                })
+                .unwrap_or(false)
+        })
+}
+
+// Check if the process is running (assumed to be the case where this cannot be checked)
+fn process_exists(pid: u32) -> bool {
+    let proc_dir = Path::new("/proc");
+
+    if cfg!(target_os = "linux") && proc_dir.is_dir() {
+        proc_dir.join(pid.to_string()).exists()
+    } else {
+        true
+    }
+}
+
+// Current time in seconds since the Unix epoch
+fn current_time() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+
+use std::fmt::{Display, Formatter};
+
+/// Lock files contain tab-separated fields
+impl Display for LockInfo {
+    fn fmt(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        write!(formatter, "{}\t{}\t{}", self.pid, self.operation, self.time)
+    }
+}
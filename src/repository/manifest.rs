@@ -0,0 +1,554 @@
+//
+// src/toolbox/repository
+//
+// Record checksum manifests.
+//
+// Every managed folder contains a manifest with the checksums of the records
+// in its clobs, which is updated by git toolbox stage. A record is listed with
+// the hash of its content and a rolling checksum, which is updated with every
+// staged change of the record (and thus summarizes its history).
+//
+// Records that were changed without updating the manifest (e.g. by editing and
+// staging the clobs directly) can be detected by checking the manifest. Changes
+// that were produced by a stage operation recorded in the audit log are trusted
+// even if the manifest does not match.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use super::Repository;
+use super::parts::join_clob_parts;
+
+use anyhow::Result;
+use crate::error;
+use serde::Serialize;
+
+use std::collections::{BTreeMap, HashSet};
+
+/// Name of the manifest file (in the managed folder)
+///
+/// The manifest is not a clob, so it is ignored when the contents are reconstructed
+pub const MANIFEST_FILE : &str = "toolbox.manifest";
+
+// the first line of a manifest
+const MANIFEST_HEADER : &str = "# git-toolbox record checksums";
+
+/// The checksums of a record
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RecordChecksum {
+    /// The rolling checksum (summarizing all staged versions)
+    rolling : String,
+    /// The hash of the record content
+    content : String
+}
+
+/// A record is identified by its clob (relative to the managed folder) and its
+/// position in the clob
+type RecordKey = (String, usize);
+
+/// The records of a managed folder (record -> content hash)
+type RecordHashes = BTreeMap<RecordKey, String>;
+
+/// The manifest of a managed folder
+type Manifest = BTreeMap<RecordKey, RecordChecksum>;
+
+
+/// Why a record change cannot be accounted for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UnverifiedReason {
+    /// The record content does not match the manifest
+    Modified,
+    /// The record is not listed in the manifest
+    Added,
+    /// The record is listed in the manifest, but does not exist
+    Deleted,
+    /// The rolling checksum does not continue the one of the previous version
+    BrokenHistory,
+    /// The manifest was removed
+    ManifestRemoved
+}
+
+impl UnverifiedReason {
+    /// The name of the reason (as in the JSON output)
+    pub fn name(&self) -> &'static str {
+        match self {
+            UnverifiedReason::Modified        => "modified",
+            UnverifiedReason::Added           => "added",
+            UnverifiedReason::Deleted         => "deleted",
+            UnverifiedReason::BrokenHistory   => "broken-history",
+            UnverifiedReason::ManifestRemoved => "manifest-removed"
+        }
+    }
+}
+
+/// The commit in which a change was found
+#[derive(Debug, Clone, Serialize)]
+pub struct ChangeCommit {
+    /// Id of the commit
+    pub id     : String,
+    /// The author name and e-mail
+    pub author : String,
+    /// Time of the commit (seconds since the Unix epoch) and the timezone offset (in minutes)
+    pub time   : (i64, i32)
+}
+
+/// A record change that was not produced by git-toolbox stage
+#[derive(Debug, Clone, Serialize)]
+pub struct UnverifiedChange {
+    /// The commit (`None` for the staged contents)
+    pub commit : Option<ChangeCommit>,
+    /// The clob containing the record (relative to the repository)
+    pub path   : String,
+    /// The position of the record in the clob (starting from 1)
+    pub record : usize,
+    /// What is wrong with the change
+    pub reason : UnverifiedReason
+}
+
+
+impl Repository {
+    /// Update the manifest of the staged managed folder
+    ///
+    /// This must be called after staging the clobs
+    pub fn update_record_manifest(&mut self, contents_path: &str, record_tags: &[String]) -> Result<()> {
+        use super::{Clob, ClobDiff};
+
+        let manifest_path = format!("{}/{}", contents_path, MANIFEST_FILE);
+
+        let records = record_hashes(&self.index_clobs(contents_path)?, record_tags)?;
+        let previous = self.read_text_from_index(&manifest_path).map(|text| parse_manifest(&text));
+
+        let diff = match (records.is_empty(), previous) {
+            // nothing is managed
+            (true, None)            => return Ok( () ),
+            // all the records were deleted
+            (true, Some(_))         => ClobDiff::Delete { path : manifest_path },
+            // the manifest is up to date
+            (false, Some(previous)) if is_current(&previous, &records) => return Ok( () ),
+            (false, previous)       => {
+                let manifest = build_manifest(&records, &previous.unwrap_or_default())?;
+
+                ClobDiff::Update {
+                    clob : Clob { path : manifest_path, content : format_manifest(&manifest) }
+                }
+            }
+        };
+
+        let mut staging_area = self.get_staging_area()?;
+        staging_area.stage_diffs(std::iter::once(&diff), |_| {})?;
+        // the placeholder names the managed folder with its manifest
+        if let Some(path) = contents_path.strip_suffix(".contents") {
+            staging_area.update_managed_file_contents(path)?;
+        }
+        staging_area.commit()
+    }
+
+    /// Check the staged records against the staged manifest
+    ///
+    /// Returns `None` if the managed folder does not have a manifest
+    pub fn verify_staged_records(&self, contents_path: &str, record_tags: &[String]) -> Result<Option<Vec<UnverifiedChange>>> {
+        let manifest_path = format!("{}/{}", contents_path, MANIFEST_FILE);
+
+        let manifest = match self.read_text_from_index(&manifest_path) {
+            Some(text) => parse_manifest(&text),
+            None       => return Ok( None )
+        };
+        let records = record_hashes(&self.index_clobs(contents_path)?, record_tags)?;
+
+        // the staged contents were produced by git toolbox stage
+        let audited = self.audited_trees()?;
+        if self.staged_tree_id(contents_path)?.map(|tree| audited.contains(&tree)).unwrap_or(false) {
+            return Ok( Some( vec!() ) );
+        }
+
+        let changes = check_manifest(&manifest, &records).into_iter().map(|((path, record), reason)| {
+            UnverifiedChange {
+                commit : None,
+                path   : format!("{}/{}", contents_path, path),
+                record,
+                reason
+            }
+        })
+        .collect();
+
+        Ok( Some( changes ) )
+    }
+
+    /// The staged clobs that were edited by hand (relative to the repository)
+    ///
+    /// These are the clobs with records that do not match the staged manifest, i.e. that
+    /// were changed without git toolbox stage. Reconstructing the managed file from the
+    /// index would carry these changes over into it
+    pub fn hand_edited_clobs(&self, contents_path: &str, record_tags: &[String]) -> Result<Vec<String>> {
+        let changes = self.verify_staged_records(contents_path, record_tags)?.unwrap_or_default();
+
+        let mut paths = changes.into_iter()
+            .filter(|change| change.reason != UnverifiedReason::ManifestRemoved)
+            .map(|change| change.path)
+            .collect::<Vec<_>>();
+        paths.sort();
+        paths.dedup();
+
+        Ok( paths )
+    }
+
+    /// Check the record changes in all the commits reachable from HEAD (newest first)
+    ///
+    /// The commits made before the managed folder had a manifest are not checked
+    pub fn verify_record_history(&self, contents_path: &str, record_tags: &[String]) -> Result<Vec<UnverifiedChange>> {
+        use git2::{Sort, Oid};
+
+        let repo = &self.repository;
+        let path = std::path::Path::new(contents_path);
+
+        // a repository without commits has no history
+        if repo.head().is_err() {
+            return Ok( vec!() )
+        }
+
+        let audited = self.audited_trees()?;
+
+        let mut revwalk = repo.revwalk().map_err(error::OtherGitError::from)?;
+        revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME).map_err(error::OtherGitError::from)?;
+        revwalk.push_head().map_err(error::OtherGitError::from)?;
+
+        // the contents tree in a commit (if any)
+        let tree_id = |commit: &git2::Commit| -> Option<Oid> {
+            commit.tree().ok()?.get_path(path).ok().map(|entry| entry.id())
+        };
+
+        let mut changes = vec!();
+        for oid in revwalk {
+            let commit = oid.and_then(|oid| repo.find_commit(oid)).map_err(error::OtherGitError::from)?;
+
+            let tree = tree_id(&commit);
+            let parents = commit.parents().map(|parent| tree_id(&parent)).collect::<Vec<_>>();
+
+            // the managed folder is unchanged relative to (one of) the parents, or was
+            // deleted entirely
+            let tree = match tree {
+                Some(tree) if !parents.contains(&Some(tree)) => tree,
+                _                                             => continue
+            };
+
+            // the changes were produced by git toolbox stage
+            if audited.contains(&tree.to_string()) {
+                continue;
+            }
+
+            // the contents of the managed folder and its first parent
+            let (manifest, records) = self.tree_records(tree, record_tags)?;
+            let (parent_manifest, parent_records) = match parents.first().copied().flatten() {
+                Some(parent) => self.tree_records(parent, record_tags)?,
+                None         => (None, RecordHashes::new())
+            };
+
+            let author = commit.author();
+            let change_commit = ChangeCommit {
+                id     : commit.id().to_string(),
+                author : format!("{} <{}>", author.name().unwrap_or("unknown"), author.email().unwrap_or("")),
+                time   : (commit.time().seconds(), commit.time().offset_minutes())
+            };
+
+            let found = match (manifest, parent_manifest) {
+                // the folder does not use manifests (yet)
+                (None, None)           => vec!(),
+                (None, Some(_))        => vec!( ((MANIFEST_FILE.to_owned(), 0), UnverifiedReason::ManifestRemoved) ),
+                (Some(manifest), parent_manifest) => {
+                    let mut found = check_manifest(&manifest, &records);
+                    if let Some(parent_manifest) = &parent_manifest {
+                        found.extend(check_history(&manifest, parent_manifest));
+                    }
+
+                    // only report the records that changed in this commit
+                    found.retain(|(key, _)| records.get(key) != parent_records.get(key));
+                    found.sort();
+                    found.dedup_by(|(a, _), (b, _)| a == b);
+
+                    found
+                }
+            };
+
+            changes.extend(found.into_iter().map(|((path, record), reason)| {
+                UnverifiedChange {
+                    commit : Some(change_commit.clone()),
+                    path   : format!("{}/{}", contents_path, path),
+                    record,
+                    reason
+                }
+            }));
+        }
+
+        Ok( changes )
+    }
+
+    /// The clobs of a managed folder in the index (path relative to the folder, content)
+    fn index_clobs(&self, contents_path: &str) -> Result<Vec<(String, String)>> {
+        // the records are checked as a whole, including their overflow parts
+        Ok( join_clob_parts(self.index_clob_parts(contents_path)?) )
+    }
+
+    /// The clobs of a managed folder in the index, with the overflow parts as separate clobs
+    pub(super) fn index_clob_parts(&self, contents_path: &str) -> Result<Vec<(String, String)>> {
+        let index = self.repository.index().map_err(error::OtherGitError::from)?;
+        let prefix = format!("{}/", contents_path);
+
+        let mut clobs = vec!();
+        for entry in index.iter() {
+            let path = String::from_utf8_lossy(&entry.path).into_owned();
+
+            let relative = match path.strip_prefix(&prefix) {
+                Some(relative) if relative.ends_with(".txt") => relative.to_owned(),
+                _ => continue
+            };
+
+            let blob = self.repository.find_blob(entry.id).map_err(error::OtherGitError::from)?;
+            clobs.push((relative, String::from_utf8_lossy(blob.content()).into_owned()));
+        }
+
+        Ok( clobs )
+    }
+
+    /// The manifest and the records of a managed folder tree
+    fn tree_records(&self, tree: git2::Oid, record_tags: &[String]) -> Result<(Option<Manifest>, RecordHashes)> {
+        use git2::{TreeWalkMode, TreeWalkResult, ObjectType};
+
+        let repo = &self.repository;
+        let tree = repo.find_tree(tree).map_err(error::OtherGitError::from)?;
+
+        let mut manifest = None;
+        let mut clobs = vec!();
+        tree.walk(TreeWalkMode::PreOrder, |root, entry| {
+            let name = String::from_utf8_lossy(entry.name_bytes()).into_owned();
+            let content = || -> Option<String> {
+                let blob = repo.find_blob(entry.id()).ok()?;
+
+                Some( String::from_utf8_lossy(blob.content()).into_owned() )
+            };
+
+            if entry.kind() == Some(ObjectType::Blob) {
+                if root.is_empty() && name == MANIFEST_FILE {
+                    manifest = content().map(|text| parse_manifest(&text));
+                } else if name.ends_with(".txt") {
+                    clobs.push((format!("{}{}", root, name), content().unwrap_or_default()));
+                }
+            }
+
+            TreeWalkResult::Ok
+        }).map_err(error::OtherGitError::from)?;
+
+        Ok( (manifest, record_hashes(&join_clob_parts(clobs), record_tags)?) )
+    }
+
+    /// The contents trees produced by stage operations recorded in the audit log
+    fn audited_trees(&self) -> Result<HashSet<String>> {
+        let entries = self.read_audit_log()?;
+
+        Ok( entries.into_iter().filter(|entry| entry.operation == "stage").filter_map(|entry| entry.tree).collect() )
+    }
+}
+
+
+/// The hash of a text
+fn checksum(text: &str) -> Result<String> {
+    use git2::{Oid, ObjectType};
+
+    let oid = Oid::hash_object(ObjectType::Blob, text.as_bytes()).map_err(error::OtherGitError::from)?;
+
+    Ok( oid.to_string() )
+}
+
+/// Split the text of a clob into records
+///
+/// A record starts with any of the record tags. Surrounding whitespace is not a part of the record
+pub(super) fn split_records<'a>(text: &'a str, record_tags: &[String]) -> Vec<&'a str> {
+    let is_record_start = |line: &str| crate::toolbox::starts_record(line, record_tags);
+
+    // the offsets at which the records start
+    let mut starts = vec!();
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        if is_record_start(line) {
+            starts.push(offset);
+        }
+        offset += line.len();
+    }
+
+    starts.iter().enumerate().map(|(i, start)| {
+        let end = starts.get(i + 1).copied().unwrap_or(text.len());
+
+        text[*start..end].trim()
+    })
+    .collect()
+}
+
+/// Hash the records of the clobs
+fn record_hashes(clobs: &[(String, String)], record_tags: &[String]) -> Result<RecordHashes> {
+    let mut records = RecordHashes::new();
+
+    for (path, text) in clobs {
+        for (i, record) in split_records(text, record_tags).into_iter().enumerate() {
+            records.insert((path.clone(), i + 1), checksum(record)?);
+        }
+    }
+
+    Ok( records )
+}
+
+/// Check whether the manifest lists exactly the records
+fn is_current(manifest: &Manifest, records: &RecordHashes) -> bool {
+    manifest.len() == records.len() && manifest.iter().all(|(key, checksum)| {
+        records.get(key) == Some(&checksum.content)
+    })
+}
+
+/// Build the manifest for the records, continuing the rolling checksums of the previous one
+fn build_manifest(records: &RecordHashes, previous: &Manifest) -> Result<Manifest> {
+    records.iter().map(|(key, content)| {
+        let rolling = match previous.get(key) {
+            // the record is unchanged
+            Some(checksum) if &checksum.content == content => checksum.rolling.clone(),
+            // the record was changed
+            Some(checksum) => rolling_checksum(&checksum.rolling, content)?,
+            // a new record
+            None           => content.clone()
+        };
+
+        Ok( (key.clone(), RecordChecksum { rolling, content : content.clone() }) )
+    })
+    .collect()
+}
+
+/// Continue a rolling checksum with a new version of the content
+fn rolling_checksum(previous: &str, content: &str) -> Result<String> {
+    checksum(&format!("{} {}", previous, content))
+}
+
+/// Compare the records with the manifest
+fn check_manifest(manifest: &Manifest, records: &RecordHashes) -> Vec<(RecordKey, UnverifiedReason)> {
+    let mut found = vec!();
+
+    for (key, content) in records.iter() {
+        match manifest.get(key) {
+            Some(checksum) if &checksum.content == content => {},
+            Some(_) => found.push((key.clone(), UnverifiedReason::Modified)),
+            None    => found.push((key.clone(), UnverifiedReason::Added))
+        }
+    }
+
+    for key in manifest.keys().filter(|key| !records.contains_key(key)) {
+        found.push((key.clone(), UnverifiedReason::Deleted));
+    }
+
+    found.sort();
+    found
+}
+
+/// Check that the rolling checksums continue the ones of the previous version
+fn check_history(manifest: &Manifest, previous: &Manifest) -> Vec<(RecordKey, UnverifiedReason)> {
+    manifest.iter().filter(|(key, checksum)| {
+        let expected = match previous.get(*key) {
+            Some(previous) if previous.content == checksum.content => Some(previous.rolling.clone()),
+            Some(previous) => rolling_checksum(&previous.rolling, &checksum.content).ok(),
+            None           => Some(checksum.content.clone())
+        };
+
+        expected.as_ref() != Some(&checksum.rolling)
+    })
+    .map(|(key, _)| (key.clone(), UnverifiedReason::BrokenHistory))
+    .collect()
+}
+
+/// Merge two versions of a manifest with their common ancestor
+///
+/// The manifests are merged record by record, so that branches that changed different 
+/// records do not conflict. A record changed differently on both branches is dropped
+/// from the manifest: the record itself conflicts and gets a new entry once the 
+/// resolution is staged
+pub fn merge_manifests(base: &str, ours: &str, theirs: &str) -> String {
+    let (base, ours, theirs) = (parse_manifest(base), parse_manifest(ours), parse_manifest(theirs));
+
+    let keys = ours.keys().chain(theirs.keys()).collect::<HashSet<_>>();
+    let merged = keys.into_iter().filter_map(|key| {
+        let (base, ours, theirs) = (base.get(key), ours.get(key), theirs.get(key));
+
+        let checksum = if ours == theirs || theirs == base {
+            ours
+        } else if ours == base {
+            theirs
+        } else {
+            None
+        };
+
+        checksum.map(|checksum| (key.clone(), checksum.clone()))
+    })
+    .collect::<Manifest>();
+
+    format_manifest(&merged)
+}
+
+/// Parse a manifest (malformed lines are skipped)
+///
+/// Every line lists the rolling checksum, the content hash, the position of the
+/// record in the clob and the clob path (separated by tabs)
+fn parse_manifest(text: &str) -> Manifest {
+    text.lines().filter(|line| !line.starts_with('#')).filter_map(|line| {
+        let fields = line.splitn(4, '\t').collect::<Vec<_>>();
+
+        match fields.as_slice() {
+            [rolling, content, record, path] => {
+                Some(
+                    ((path.to_string(), record.parse().ok()?),
+                    RecordChecksum { rolling : rolling.to_string(), content : content.to_string() })
+                )
+            },
+            _ => None
+        }
+    })
+    .collect()
+}
+
+/// Format a manifest
+fn format_manifest(manifest: &Manifest) -> String {
+    let mut text = format!("{}\n", MANIFEST_HEADER);
+
+    for ((path, record), checksum) in manifest.iter() {
+        text.push_str(&format!("{}\t{}\t{}\t{}\n", checksum.rolling, checksum.content, record, path));
+    }
+
+    text
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{merge_manifests, parse_manifest, format_manifest, build_manifest, RecordHashes};
+
+    fn manifest(previous: &str, records: &[(&str, usize, &str)]) -> String {
+        let records = records.iter().map(|(path, record, content)| {
+            ((path.to_string(), *record), content.to_string())
+        })
+        .collect::<RecordHashes>();
+
+        format_manifest(&build_manifest(&records, &parse_manifest(previous)).unwrap())
+    }
+
+    #[test]
+    fn test_disjoint_record_edits_are_merged() {
+        let base   = manifest("", &[("1.txt", 1, "a"), ("1.txt", 2, "b"), ("2.txt", 1, "c")]);
+        let ours   = manifest(&base, &[("1.txt", 1, "a2"), ("1.txt", 2, "b"), ("2.txt", 1, "c")]);
+        let theirs = manifest(&base, &[("1.txt", 1, "a"), ("1.txt", 2, "b2"), ("3.txt", 1, "d")]);
+
+        // the same as staging both changes one after the other
+        let expected = manifest(&ours, &[("1.txt", 1, "a2"), ("1.txt", 2, "b2"), ("3.txt", 1, "d")]);
+        assert_eq!(merge_manifests(&base, &ours, &theirs), expected);
+        assert_eq!(merge_manifests(&base, &theirs, &ours), expected);
+
+        // the record changed on both branches is left out
+        let theirs = manifest(&base, &[("1.txt", 1, "a3"), ("1.txt", 2, "b"), ("2.txt", 1, "c")]);
+        let merged = parse_manifest(&merge_manifests(&base, &ours, &theirs));
+        assert_eq!(merged.keys().cloned().collect::<Vec<_>>(), vec!(("1.txt".to_owned(), 2), ("2.txt".to_owned(), 1)));
+    }
+}
@@ -9,15 +9,6 @@
 //
 // This code is licensed under GPL 3.0
 
-pub const MANAGED_FILE_TEXT : &str = concat!(
-    "This file is managed by git-toolbox.\n",
-    "\n",
-    "If you see this text, your repository is either misconfigured or has encountered\n",
-    "an error during operation. Please run \"git toolbox reset\" and contact IT support\n", 
-    "if your issue persists.\n"
-);
-
-
 // basic git wrapper
 mod repo;
 // repository configuration (setting git config etc.)
@@ -28,8 +19,67 @@ mod diff;
 mod staging_area;
 // reconstructing managed file contents
 mod reconstruct;
+// the log of git-toolbox operations
+mod audit;
+// signed staging manifests
+mod signing;
+// Git LFS interoperability
+mod lfs;
+// plain text snapshots of the managed files
+mod snapshot;
+// stashed changes of the managed files
+mod stash;
+// the git-toolbox operation lock
+mod lock;
+// the history of individual files
+mod history;
+// record checksum manifests
+mod manifest;
+// overflow parts of large clobs
+mod parts;
+// committing the managed files
+mod commit;
+// repository health indicators
+mod health;
+// record deletion statistics
+mod deletions;
+// adopting per-record directories
+mod adopt;
+// the configured users
+mod users;
+// records added on other branches
+mod branches;
+// the conflicted files after a merge
+mod conflicts;
+// git hooks that rebuild the managed files
+mod hooks;
+// removing git-toolbox management
+mod uninstall;
+// moving managed files
+mod mv;
+// the journal of operations in progress
+mod journal;
+// the line endings and byte order marks of the managed files
+mod file_format;
+// the acknowledged issues of the managed files
+mod issue_baseline;
+// the placeholders the managed files were reconstructed from
+mod smudged;
 
 
-pub use diff::{Clob, ClobDiff, ClobValidationIssue, DiffStats};
+pub use diff::{Clob, ClobDiff, ClobValidationIssue, DiffStats, RESERVED_NAMES, clob_path_problem};
 pub use repo::Repository;
+pub use reconstruct::{assemble_clobs, PREAMBLE_CLOB, HEADER_CLOB, DICTIONARY_HEADER};
+pub use snapshot::SNAPSHOT_BRANCH;
+pub use history::{FileRevision, BlamedLine};
+pub use lock::{IndexLock, set_wait_for_lock, pass_lock_to};
+pub use manifest::{UnverifiedChange, UnverifiedReason, MANIFEST_FILE, merge_manifests};
+pub use parts::{clob_part, compare_clob_paths};
+pub use health::{RepositoryHealth, filter_on_path, filter_version};
+pub use branches::ClobCollision;
+pub use conflicts::ConflictedFile;
+pub use hooks::GIT_HOOKS;
+pub use journal::Journal;
+pub use file_format::FileFormat;
+pub use issue_baseline::ISSUE_BASELINE_FILE;
 
@@ -0,0 +1,93 @@
+//
+// src/toolbox/repository
+//
+// Moving managed files.
+//
+// A managed file is moved together with its managed folder. The index entries are
+// moved as they are (rather than restaged from the working directory), so that the
+// staged changes remain staged and the unstaged ones unstaged.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use super::Repository;
+
+use anyhow::Result;
+use crate::error;
+
+use std::path::Path;
+
+impl Repository {
+    /// Move a managed file and its managed folder in the index and the working directory
+    ///
+    /// Paths are assumed to be relative to the repository. The directories that become
+    /// empty are removed
+    pub fn move_managed_file(&self, old: &str, new: &str) -> Result<()> {
+        use std::fs;
+
+        let workdir = self.workdir()?.to_owned();
+
+        // the working directory
+        let moves = [
+            (old.to_owned(), new.to_owned()),
+            (format!("{}.contents", old), format!("{}.contents", new))
+        ];
+        for (from, to) in moves.iter() {
+            let (from, to) = (workdir.join(from), workdir.join(to));
+            if !from.exists() { continue }
+
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent).map_err(|err| {
+                    error::FileWriteError {
+                        path : to.clone(),
+                        msg  : err.to_string()
+                    }
+                })?;
+            }
+            fs::rename(&from, &to).map_err(|err| {
+                error::FileWriteError {
+                    path : to.clone(),
+                    msg  : err.to_string()
+                }
+            })?;
+
+            // remove the directories that are now empty
+            let mut parent = from.parent();
+            while let Some(dir) = parent {
+                if dir == workdir || fs::remove_dir(dir).is_err() { break }
+
+                parent = dir.parent();
+            }
+        }
+
+        // the index
+        let mut index = self.repository.index().map_err(error::OtherGitError::from)?;
+
+        let contents_prefix = format!("{}.contents/", old);
+        let entries = index.iter().filter(|entry| {
+            let path = String::from_utf8_lossy(&entry.path);
+
+            path == old || path.starts_with(&contents_prefix)
+        })
+        .collect::<Vec<_>>();
+
+        for mut entry in entries {
+            let path = String::from_utf8_lossy(&entry.path).into_owned();
+            index.remove_path(Path::new(&path)).map_err(error::OtherGitError::from)?;
+
+            entry.path = format!("{}{}", new, &path[old.len() ..]).into_bytes();
+            index.add(&entry).map_err(error::OtherGitError::from)?;
+        }
+
+        index.write().map_err(|err| -> anyhow::Error {
+            if err.code() == git2::ErrorCode::Locked {
+                error::IndexLocked.into()
+            } else {
+                error::OtherGitError::from(err).into()
+            }
+        })?;
+
+        Ok( () )
+    }
+}
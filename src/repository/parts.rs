@@ -0,0 +1,164 @@
+//
+// src/toolbox/repository
+//
+// Overflow parts of large clobs.
+//
+// Some hosting providers limit the size of the individual files. A dictionary
+// can be configured with a maximal clob size (`max-clob-size`), in which case a
+// larger clob is stored as a clob with the first lines followed by overflow parts
+// with the remaining lines: `ab/cd/abcd.txt`, `ab/cd/abcd.txt.parts/2.txt`, ...
+//
+// The clob and its parts are cut at line ends and the line end at the cut is
+// dropped, so that joining them with a newline (which is how the clobs are joined
+// when a managed file is reconstructed) restores the original clob. The parts only
+// need to be sorted right after their clob.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use super::{Repository, Clob};
+use crate::sorting::SortPolicy;
+
+use anyhow::Result;
+use std::cmp::Ordering;
+
+// the suffix of the folder with the overflow parts of a clob
+pub(super) const PARTS_SUFFIX : &str = ".parts";
+
+
+impl Clob {
+    /// Split the clob into the clob and its overflow parts, none of which is larger than
+    /// `max_size` bytes (unless a single line is)
+    pub fn into_parts(self, max_size: usize) -> Vec<Clob> {
+        if self.content.len() <= max_size {
+            return vec!(self)
+        }
+
+        let mut chunks : Vec<String> = vec!();
+        let mut chunk = String::new();
+
+        for line in self.content.split_inclusive('\n') {
+            if !chunk.is_empty() && chunk.len() + line.len() > max_size {
+                chunks.push(std::mem::take(&mut chunk));
+            }
+            chunk.push_str(line);
+        }
+        chunks.push(chunk);
+
+        let last = chunks.len() - 1;
+        chunks.into_iter().enumerate().map(|(i, mut content)| {
+            // the newline is restored when the parts are joined
+            if i < last {
+                content.pop();
+            }
+
+            let path = if i == 0 {
+                self.path.clone()
+            } else {
+                format!("{}{}/{}.txt", self.path, PARTS_SUFFIX, i + 1)
+            };
+
+            Clob { path, content }
+        })
+        .collect()
+    }
+}
+
+
+/// The clob a path belongs to and the number of the part (`0` for the clob itself)
+///
+/// This also works for the folder with the overflow parts (with the part number `1`)
+pub fn clob_part(path: &str) -> (&str, usize) {
+    let marker = format!(".txt{}", PARTS_SUFFIX);
+
+    match path.find(&marker) {
+        Some(i) => {
+            let part = path[i + marker.len() ..].trim_start_matches('/').trim_end_matches(".txt");
+
+            (&path[.. i + 4], part.parse().unwrap_or(1))
+        },
+        None => (path, 0)
+    }
+}
+
+/// Compare the clob paths with the sort policy, with the overflow parts right after their clob
+///
+/// The paths are compared folder by folder. The folders of the hash layout are skipped,
+/// so that the clobs are in the same order regardless of the layout
+pub fn compare_clob_paths(sort: &SortPolicy, a: &str, b: &str) -> Ordering {
+    use crate::util::{is_hash_prefix, is_record_type_dir};
+
+    let (clob_a, part_a) = clob_part(a);
+    let (clob_b, part_b) = clob_part(b);
+
+    // the clobs of the other record types follow the ones of the first record tag
+    let in_type_dir = |path: &str| path.split('/').any(is_record_type_dir);
+    let type_dirs = in_type_dir(clob_a).cmp(&in_type_dir(clob_b));
+    if type_dirs != Ordering::Equal {
+        return type_dirs;
+    }
+
+    let mut components_a = clob_a.split('/').filter(|component| !is_hash_prefix(component));
+    let mut components_b = clob_b.split('/').filter(|component| !is_hash_prefix(component));
+
+    loop {
+        match (components_a.next(), components_b.next()) {
+            (Some(a), Some(b)) => match sort.compare(a, b) {
+                Ordering::Equal => continue,
+                ordering        => return ordering
+            },
+            (a, b) => return a.is_some().cmp(&b.is_some()).then(part_a.cmp(&part_b))
+        }
+    }
+}
+
+/// Join the overflow parts with their clobs (given as paths with their contents)
+///
+/// The order of the joined clobs does not matter, so they are sorted in natural order
+pub(super) fn join_clob_parts(mut clobs: Vec<(String, String)>) -> Vec<(String, String)> {
+    clobs.sort_by(|(a, _), (b, _)| compare_clob_paths(&SortPolicy::Natural, a, b));
+
+    let mut joined : Vec<(String, String)> = vec!();
+    for (path, content) in clobs {
+        let (clob, part) = clob_part(&path);
+
+        match joined.last_mut() {
+            Some((last, text)) if part > 0 && last == clob => {
+                text.push('\n');
+                text.push_str(&content);
+            },
+            _ => joined.push((path, content))
+        }
+    }
+
+    joined
+}
+
+
+impl Repository {
+    /// Reads the content of a clob and its overflow parts as recorded in a revision (the
+    /// index if the revision is empty)
+    ///
+    /// Returns `None` if the clob does not exist or is not valid text
+    pub fn read_clob_from_rev<P: AsRef<str>, S: AsRef<str>>(&self, path: P, rev: S) -> Result<Option<String>> {
+        let (path, rev) = (path.as_ref(), rev.as_ref());
+
+        let mut text = match self.read_text_from_rev(path, rev)? {
+            Some(text) => text,
+            None       => return Ok( None )
+        };
+
+        for part in 2 .. {
+            match self.read_text_from_rev(format!("{}{}/{}.txt", path, PARTS_SUFFIX, part), rev)? {
+                Some(content) => {
+                    text.push('\n');
+                    text.push_str(&content);
+                },
+                None => break
+            }
+        }
+
+        Ok( Some(text) )
+    }
+}
@@ -10,6 +10,29 @@
 
 use anyhow::{Result, bail};
 use crate::error;
+use super::Clob;
+use super::parts::compare_clob_paths;
+use crate::sorting::SortPolicy;
+use std::io::Write;
+
+/// The header of a reconstructed toolbox dictionary without a header clob
+pub const DICTIONARY_HEADER : &[u8] = b"\\_sh v3.0  864  Dictionary\n";
+
+/// The clob with the dictionary header line (relative to the managed folder)
+///
+/// The header (e.g. `\_sh v3.0  400  MDF 4.0`) is stored as it was found in the file, so 
+/// that the file is reconstructed with the same header. The records are never stored at 
+/// the top of the managed folder, so this path cannot clash with a record clob
+pub const HEADER_CLOB : &str = "header.txt";
+
+/// The clob with the lines that precede the first record (relative to the managed folder)
+///
+/// The position of these lines (e.g. a comment block at the top of the file) is implied
+/// by the clob path: they are always placed right after the dictionary header when the 
+/// file is reconstructed, regardless of how the other clobs are sorted. The lines are 
+/// stored exactly as they were found, including the blank lines that separate them from
+/// the first record
+pub const PREAMBLE_CLOB : &str = "preamble.txt";
 
 
 /// Retrieve the contents of a managed toolbox file 
@@ -18,22 +41,68 @@ use crate::error;
 ///
 /// * `path` - path to the managed directory, relative to the repository root
 /// * `spec` - revision spec (empty means index)
+/// * `sort` - the order of the clobs
 ///
 /// # Notes
 ///
-/// The files are retrieved in the natural order of their paths. 
-pub(super) fn reconstruct<P, S>(repo: &git2::Repository, path: P, rev: S) -> Result<Vec<u8>>  
+/// The files are retrieved in the order of their paths. 
+pub(super) fn reconstruct<P, S>(repo: &git2::Repository, path: P, rev: S, sort: &SortPolicy) -> Result<Vec<u8>>  
 where 
     P : AsRef<str>,
     S : AsRef<str>
+{
+    let mut content = vec!();
+    reconstruct_to(repo, path, rev, sort, &mut content)?;
+
+    Ok( content )
+}
+
+/// Write the contents of a managed toolbox file to `out`
+///
+/// The contents are written blob by blob, so that large files are never held in memory 
+/// as a whole. Nothing is written if the managed directory cannot be found
+pub(super) fn reconstruct_to<P, S, W>(
+    repo: &git2::Repository, path: P, rev: S, sort: &SortPolicy, out: &mut W
+) -> Result<()>  
+where 
+    P : AsRef<str>,
+    S : AsRef<str>,
+    W : Write
 {
     if rev.as_ref().is_empty() {
         // we are searching the index
-        reconstruct_from_index(repo, path)
+        write_from_index(repo, path, sort, out)
     } else {
         // we are searching a revision
-        reconstruct_from_rev(repo, path, rev)
+        write_from_rev(repo, path, rev, sort, out)
+    }
+}
+
+// write the data to the output
+fn write_data<W: Write>(out: &mut W, data: &[u8]) -> Result<()> {
+    out.write_all(data).map_err(|err| {
+        error::ReconstructionWriteError {
+            msg : err.to_string()
+        }
+        .into()
+    })
+}
+
+// write a clob after the header and the clobs already written
+//
+// The clobs are separated by a blank line, except for the preamble, which directly follows 
+// the header and already ends with its separator. Empty clobs are skipped (they would add 
+// blank lines)
+fn write_clob<W: Write>(out: &mut W, data: &[u8], is_preamble: bool, separate: &mut bool) -> Result<()> {
+    if data.is_empty() { return Ok( () ) }
+
+    if *separate && !is_preamble {
+        write_data(out, b"\n")?;
     }
+    write_data(out, data)?;
+    *separate = !is_preamble;
+
+    Ok( () )
 }
 
 /// Retrieve the contents of a managed toolbox file from index
@@ -51,15 +120,13 @@ where
 ///
 /// Maybe there is a better way of doing it by inspecting the index manually and 
 /// matchign the index entries... but I am not doing it. 
-fn reconstruct_from_index<P>(repo: &git2::Repository, path: P) -> Result<Vec<u8>>  
+fn write_from_index<P, W>(repo: &git2::Repository, path: P, sort: &SortPolicy, out: &mut W) -> Result<()>  
 where 
-    P : AsRef<str>
+    P : AsRef<str>,
+    W : Write
 {
     let path = path.as_ref();
 
-    // accumulator for all the blob contents (with dictionary header)
-    let mut content = b"\\_sh v3.0  864  Dictionary\n".to_vec();
-        
     let index = repo.index().map_err(error::OtherGitError::from)?;
         
     // apply the pathspec to the index
@@ -102,44 +169,75 @@ where
         );
     }
 
-    // sort the paths in natural order
-    alphanumeric_sort::sort_str_slice(paths.as_mut_slice());
-    // retrieve the blob 
-    for path in paths.into_iter() {
+    // sort the paths (the overflow parts after their clobs), with the preamble first
+    paths.sort_by(|a, b| compare_clob_paths(sort, a, b));
+
+    let root = path.trim_end_matches('/');
+    let is_clob = |clob: &str, name: &str| {
+        clob.strip_prefix(root).and_then(|rest| rest.strip_prefix('/')) == Some(name)
+    };
+    if let Some(i) = paths.iter().position(|clob| is_clob(clob, PREAMBLE_CLOB)) {
+        let preamble = paths.remove(i);
+        paths.insert(0, preamble);
+    }
+    let header = paths.iter().position(|clob| is_clob(clob, HEADER_CLOB)).map(|i| paths.remove(i));
+
+    let read_blob = |path: &str| -> Result<git2::Blob> {
         let entry = index.get_path(std::path::Path::new(path), 0).ok_or_else(|| {
             error::GitObjNotFound {
                 path : path.to_owned(),
                 rev  : "the index".to_owned()
             }
         })?;
-        let blob = repo.find_blob(entry.id).map_err(error::OtherGitError::from)?;
-        // push it to the list
-        if !content.is_empty() {
-            content.extend(b"\n");
-        }
-        content.extend(blob.content());
+
+        Ok( repo.find_blob(entry.id).map_err(error::OtherGitError::from)? )
+    };
+
+    // the dictionary header comes first
+    match header {
+        Some(header) => write_data(out, read_blob(header)?.content())?,
+        None         => write_data(out, DICTIONARY_HEADER)?
     }
 
-    Ok( content )
+    // retrieve the blob 
+    let mut separate = true;
+    for path in paths.into_iter() {
+        // write it out
+        write_clob(out, read_blob(path)?.content(), is_clob(path, PREAMBLE_CLOB), &mut separate)?;
+    }
+
+    Ok( () )
 }
 
 /// Retrieve the contents of a managed toolbox file from a revision
 ///
 /// # Notes
 ///
-/// This is an straightforward efficient implementation where we directly
-/// walk a tree in a commit, sorting entries as we go. 
-pub fn reconstruct_from_rev<P, S>(repo: &git2::Repository, path: P, rev: S) -> Result<Vec<u8>>  
+/// This is an straightforward implementation where we directly walk a tree
+/// in a commit and sort the entries by their paths. 
+pub fn reconstruct_from_rev<P, S>(repo: &git2::Repository, path: P, rev: S, sort: &SortPolicy) -> Result<Vec<u8>>  
 where 
     P : AsRef<str>,
     S : AsRef<str>
+{
+    let mut content = vec!();
+    write_from_rev(repo, path, rev, sort, &mut content)?;
+
+    Ok( content )
+}
+
+// write the contents of a managed toolbox file from a revision to `out`
+fn write_from_rev<P, S, W>(
+    repo: &git2::Repository, path: P, rev: S, sort: &SortPolicy, out: &mut W
+) -> Result<()>  
+where 
+    P : AsRef<str>,
+    S : AsRef<str>,
+    W : Write
 {
     let path = path.as_ref();
     let rev = rev.as_ref();
 
-    // accumulator for all the blob contents (with dictionary header)
-    let mut content = b"\\_sh v3.0  864  Dictionary\n".to_vec();
-    
     // find the object at the path 
     let tree = repo.revparse_single(&format!("{}:{}", rev, path))
         .map_err(error::OtherGitError::from)?;
@@ -152,55 +250,159 @@ where
             }
         })?;
 
-    collect_blobs_in_natural_order(tree, repo, &mut |blob : git2::Blob| {
-        // push it to the list
-        if !content.is_empty() {
-            content.extend(b"\n");
+    // the dictionary header comes first
+    let header = tree.get_path(std::path::Path::new(HEADER_CLOB)).ok()
+        .and_then(|entry| entry.to_object(repo).and_then(|obj| obj.peel_to_blob()).ok());
+    match header {
+        Some(blob) => write_data(out, blob.content())?,
+        None       => write_data(out, DICTIONARY_HEADER)?
+    }
+
+    // followed by the preamble
+    let mut separate = true;
+    if let Ok(entry) = tree.get_path(std::path::Path::new(PREAMBLE_CLOB)) {
+        if let Ok(blob) = entry.to_object(repo).and_then(|obj| obj.peel_to_blob()) {
+            write_clob(out, blob.content(), true, &mut separate)?;
         }
-        content.extend(blob.content());
-    })?;
-    
+    }
 
-    Ok( content )
-}
+    // the clobs are sorted by their full paths (the order does not follow the folders
+    // of the hash layout), only the blob ids are collected and the blobs are loaded one 
+    // at a time as they are written
+    let mut blobs = vec!();
+    collect_blobs(tree, repo, "", &mut blobs)?;
+    blobs.sort_by(|(a, _), (b, _)| compare_clob_paths(sort, a, b));
 
+    for (path, id) in blobs {
+        // the header and the preamble were already added
+        if path == HEADER_CLOB || path == PREAMBLE_CLOB {
+            continue;
+        }
 
-/// Internal iterator that yields blobs in a git tree, sorted naturally by path
-fn collect_blobs_in_natural_order<'a, F>(
-    tree: git2::Tree, repo: &'a git2::Repository, callback: &mut F
-) -> Result<(), git2::Error> 
-where 
-    F: FnMut(git2::Blob<'a>)
-{
-    // collect and sort the entris by their path 
-    let mut entries = tree.iter().collect::<Vec<_>>();
-    entries.sort_by(|a, b| {
-        alphanumeric_sort::compare_str(a.name().unwrap_or(""), b.name().unwrap_or(""))
-    });
+        // write it out
+        let blob = repo.find_blob(id).map_err(error::OtherGitError::from)?;
+        write_clob(out, blob.content(), false, &mut separate)?;
+    }
+
+    Ok( () )
+}
 
-    // walk the entires
-    for entry in entries.into_iter() {
+
+/// Collect the ids of the txt blobs in a git tree (with their paths relative to the tree
+/// walked initially)
+fn collect_blobs(
+    tree: git2::Tree, repo: &git2::Repository, prefix: &str, blobs: &mut Vec<(String, git2::Oid)>
+) -> Result<()> {
+    for entry in tree.iter() {
         match &entry.kind() {
             // if this is a tree, we collect blobs from here recursively
             Some(git2::ObjectType::Tree) => {
-                collect_blobs_in_natural_order(
-                    entry.to_object(repo)?.into_tree().expect("Git object type mismatch error"),
+                collect_blobs(
+                    entry.to_object(repo).map_err(error::OtherGitError::from)?
+                        .into_tree().expect("Git object type mismatch error"),
                     repo, 
-                    callback
+                    &format!("{}{}/", prefix, entry.name().unwrap_or_default()),
+                    blobs
                 )?;
             },
-            // if this is an txt blob, yield it
+            // if this is an txt blob, collect it
             Some(git2::ObjectType::Blob) if entry.name().unwrap_or_default().ends_with(".txt") => {
-                callback(
-                    entry.to_object(repo)?.into_blob().expect("Git object type mismatch error")
-                );
+                blobs.push((format!("{}{}", prefix, entry.name().unwrap_or_default()), entry.id()));
             },
             _ => {
                 // ignore the rest
             }
         }
-
     }
 
     Ok( () )
-}
\ No newline at end of file
+}
+
+
+/// Assemble the contents of a managed toolbox file from a list of clobs
+///
+/// # Notes
+///
+/// The clobs are joined in the order of their paths (with the header and the preamble 
+/// first), which produces the same result as reconstructing the file from the repository
+pub fn assemble_clobs(mut clobs: Vec<Clob>, sort: &SortPolicy) -> Vec<u8> {
+    // accumulator for all the clob contents (with dictionary header)
+    let mut content = match clobs.iter().position(|clob| clob.path == HEADER_CLOB) {
+        Some(i) => clobs.remove(i).content.into_bytes(),
+        None    => DICTIONARY_HEADER.to_vec()
+    };
+
+    // sort the clobs, with the preamble first
+    clobs.sort_by(|a, b| {
+        (a.path != PREAMBLE_CLOB).cmp(&(b.path != PREAMBLE_CLOB))
+            .then_with(|| compare_clob_paths(sort, &a.path, &b.path))
+    });
+
+    let mut separate = true;
+    for clob in clobs.into_iter() {
+        write_clob(&mut content, clob.content.as_bytes(), clob.path == PREAMBLE_CLOB, &mut separate)
+            .expect("fatal: unable to write to memory");
+    }
+
+    content
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{assemble_clobs, HEADER_CLOB, PREAMBLE_CLOB};
+    use crate::config::DictionaryConfig;
+    use crate::sorting::SortPolicy;
+    use crate::toolbox::Dictionary;
+
+    fn config() -> DictionaryConfig {
+        let text = "[[dictionary]]\nname = \"Parsing\"\npath = \"parse.txt\"\nrecord-tag = \"lem\"\n";
+
+        DictionaryConfig::for_test(text)
+    }
+
+    #[test]
+    fn test_header_is_preserved() {
+        for header in &["\\_sh v3.0  400  MDF 4.0", "\\_sh v2.0  250  Dictionary", "\\_sh v3.0  864  Dictionary"] {
+            let text = format!("{}\n\n\\lem kiwi\n\\ge kiwi\n", header);
+            let dictionary = Dictionary::from_text(&config(), text.clone());
+            let clobs = dictionary.split().0.collect::<Vec<_>>();
+
+            let stored = clobs.iter().find(|clob| clob.path == HEADER_CLOB).unwrap();
+            assert_eq!(stored.content, format!("{}\n", header));
+
+            let assembled = assemble_clobs(clobs, &SortPolicy::default());
+            assert_eq!(String::from_utf8(assembled).unwrap(), text);
+        }
+    }
+
+    #[test]
+    fn test_preamble_is_preserved() {
+        let id_config = DictionaryConfig::for_test(
+            "[[dictionary]]\nname = \"Lexical\"\npath = \"lex.txt\"\nrecord-tag = \"lem\"\n\
+             unique-id = true\nid-tag = \"id\"\nid-spec = \"(?P<namespace>)(?P<id>[0-9]+)\"\n"
+        );
+
+        let texts = [
+            "\\_sh v3.0  400  MDF 4.0\n\\_DateStampHasFourDigitYear\n\n\\lem apple\n\\id 1\n\n\\lem kiwi\n\\id 2\n",
+            "\\_sh v3.0  400  MDF 4.0\n\n\\_DateStampHasFourDigitYear\n\\lem kiwi\n\\id 1\n",
+            "\\_sh v3.0  400  MDF 4.0\n\\_DateStampHasFourDigitYear\n\n\n"
+        ];
+
+        for config in &[config(), id_config] {
+            for text in texts.iter() {
+                let dictionary = Dictionary::from_text(config, text.to_string());
+                let (clobs, issues) = dictionary.split();
+                let clobs = clobs.collect::<Vec<_>>();
+
+                let stored = clobs.iter().find(|clob| clob.path == PREAMBLE_CLOB).unwrap();
+                assert!(stored.content.trim_start().starts_with("\\_DateStampHasFourDigitYear\n"));
+                assert!(clobs.iter().all(|clob| !clob.content.is_empty()));
+                assert!(issues.is_empty());
+
+                let assembled = assemble_clobs(clobs, &SortPolicy::default());
+                assert_eq!(String::from_utf8(assembled).unwrap(), *text);
+            }
+        }
+    }
+}
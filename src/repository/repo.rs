@@ -1,4 +1,5 @@
 use crate::config::Config;
+use crate::sorting::SortPolicy;
 
 /// The local repository
 pub struct Repository {
@@ -12,7 +13,7 @@ use crate::error;
 use std::path::{Path, PathBuf};
 
 impl Repository {
-    fn __open() -> Result<git2::Repository> {
+    pub(super) fn __open() -> Result<git2::Repository> {
         git2::Repository::open_from_env()
             // process errors
             .map_err(|err| {
@@ -49,18 +50,33 @@ impl Repository {
     }
 
     /// Confgure the repository
-    pub fn configure() -> Result<()> {
+    ///
+    /// Returns the managed files that were taken over from Git LFS
+    pub fn configure() -> Result<Vec<String>> {
         // open the git repository
         let mut repository = Repository::__open()?;
 
+        // no other git-toolbox operations while we are configuring
+        let _lock = super::lock::OperationLock::acquire(repository.path(), "setup")?;
+
         // retrieve the validated config
         super::config::configure_repository(&mut repository)
     }
 
-    /// Reconstruct a path
+    /// The problems with the git configuration of the repository (empty if it is up to date)
+    ///
+    /// The repository cannot be opened while there are problems
+    pub fn configuration_problems() -> Result<Vec<String>> {
+        // open the git repository
+        let repository = Repository::__open()?;
+
+        super::config::configuration_problems(&repository)
+    }
+
+    /// Reconstruct a path, with the clobs in the order given by `sort`
     /// 
     /// Path is assumed to be relative to the repository
-    pub fn reconstruct<P, S>(path: P, rev: S) -> Result<Vec<u8>>  
+    pub fn reconstruct<P, S>(path: P, rev: S, sort: &SortPolicy) -> Result<Vec<u8>>  
     where 
         P : AsRef<str>,
         S : AsRef<str>
@@ -69,7 +85,72 @@ impl Repository {
         let repository = Repository::__open()?;
 
         // forward the reconstruct logic
-        super::reconstruct::reconstruct(&repository, path, rev)
+        super::reconstruct::reconstruct(&repository, path, rev, sort)
+    }
+
+    /// Reconstruct a path, writing the contents to `out` as they are retrieved
+    /// 
+    /// Path is assumed to be relative to the repository
+    pub fn reconstruct_to<P, S, W>(path: P, rev: S, sort: &SortPolicy, out: &mut W) -> Result<()>  
+    where 
+        P : AsRef<str>,
+        S : AsRef<str>,
+        W : std::io::Write
+    {
+        // open the git repository
+        let repository = Repository::__open()?;
+
+        // forward the reconstruct logic
+        super::reconstruct::reconstruct_to(&repository, path, rev, sort, out)
+    }
+
+    /// Find the merge base of two revisions
+    ///
+    /// Returns the id of the merge base commit
+    pub fn merge_base<A, B>(rev_a: A, rev_b: B) -> Result<String>  
+    where 
+        A : AsRef<str>,
+        B : AsRef<str>
+    {
+        // open the git repository
+        let repository = Repository::__open()?;
+
+        // resolve the revisions to commits
+        let resolve = |rev: &str| {
+            repository.revparse_single(rev)
+                .and_then(|obj| obj.peel_to_commit())
+                .map(|commit| commit.id())
+                .map_err(|_| {
+                    error::GitRevisionNotFound {
+                        rev : rev.to_owned()
+                    }
+                })
+        };
+
+        let oid_a = resolve(rev_a.as_ref())?;
+        let oid_b = resolve(rev_b.as_ref())?;
+
+        let merge_base = repository.merge_base(oid_a, oid_b).map_err(|_| {
+            error::NoMergeBase {
+                rev_a : rev_a.as_ref().to_owned(),
+                rev_b : rev_b.as_ref().to_owned()
+            }
+        })?;
+
+        Ok( merge_base.to_string() )
+    }
+
+    /// The id of the commit a revision refers to
+    pub fn commit_id<S : AsRef<str>>(&self, rev: S) -> Result<String> {
+        let commit = self.repository.revparse_single(rev.as_ref())
+            .and_then(|obj| obj.peel_to_commit())
+            .map_err(|_| {
+                error::GitRevisionNotFound {
+                    rev : rev.as_ref().to_owned()
+                }
+            })?;
+
+        Ok( commit.id().to_string() )
     }
 
     pub fn workdir(&self) -> Result<&Path> {
@@ -91,10 +172,40 @@ impl Repository {
     }
 
 
+    /// The git-toolbox data directory (inside the git directory)
+    ///
+    /// The directory is created if it does not exist
+    pub fn data_dir(&self) -> Result<PathBuf> {
+        let path = self.repository.path().join("toolbox");
+
+        std::fs::create_dir_all(&path).map_err(|err| {
+            error::FileWriteError {
+                path : path.clone(),
+                msg  : err.to_string()
+            }
+        })?;
+
+        Ok( path )
+    }
+
     pub fn config(&self) -> &Config {
         &self.config
     }
 
+    /// Write and stage the configuration file
+    ///
+    /// The loaded configuration is not updated, the repository has to be opened again
+    pub fn update_config_file(&self, text: &str) -> Result<()> {
+        super::config::write_config_file(&self.repository, text)
+    }
+
+    /// The editor command set in git config (`toolbox.editor`), if any
+    pub fn configured_editor(&self) -> Option<String> {
+        self.repository.config()
+            .and_then(|config| config.get_string("toolbox.editor"))
+            .ok()
+    }
+
     pub fn head_display_name(&self) -> String {
         use crate::cli_app::style;
 
@@ -131,12 +242,69 @@ impl Repository {
         get_path_relative_to_root(path, workdir)
     }
 
-    /// Check if the git index is locked for writing without validating the configuration
-    pub fn check_for_lock() -> Result<bool> {
-        let repository = Repository::__open()?;
-        let path  = repository.path().to_owned().join("index.lock");
+    /// Restore the working directory copies of the given paths from the index
+    ///
+    /// Paths are assumed to be relative to the repository
+    pub fn checkout_from_index<I, P>(&self, paths: I) -> Result<()> 
+    where
+        I : IntoIterator<Item = P>,
+        P : AsRef<str>
+    {
+        use git2::build::CheckoutBuilder;
+
+        let mut checkout = CheckoutBuilder::new();
+        checkout.force();
+
+        // an empty path list would check out the entire index
+        let mut any_paths = false;
+        for path in paths {
+            checkout.path(path.as_ref());
+            any_paths = true;
+        }
+        if !any_paths { return Ok( () ) }
+
+        self.repository.checkout_index(None, Some(&mut checkout))
+            .map_err(error::OtherGitError::from)?;
+
+        Ok( () )
+    }
+
+    /// Make the working directory copy of a folder match the index
+    ///
+    /// Changed and deleted files are checked out, files unknown to git are removed. The 
+    /// path is assumed to be relative to the repository
+    pub fn restore_folder_from_index<P: AsRef<str>>(&self, path: P) -> Result<()> {
+        use git2::build::CheckoutBuilder;
+
+        let mut checkout = CheckoutBuilder::new();
+        checkout.force().remove_untracked(true).path(path.as_ref());
+
+        self.repository.checkout_index(None, Some(&mut checkout))
+            .map_err(error::OtherGitError::from)?;
+
+        Ok( () )
+    }
+
+    /// Restore a managed folder that is missing from the working directory (e.g. it was deleted)
+    ///
+    /// Returns whether the folder was restored. A folder that has nothing in the index (such as
+    /// the folder of a dictionary that was never staged) is not considered to be missing.
+    pub fn restore_missing_managed_folder<P: AsRef<str>>(&self, path: P) -> Result<bool> {
+        let path = path.as_ref();
+
+        if self.workdir()?.join(path).exists() {
+            return Ok( false )
+        }
+
+        let index = self.repository.index().map_err(error::OtherGitError::from)?;
+        let prefix = format!("{}/", path);
+        if !index.iter().any(|entry| entry.path.starts_with(prefix.as_bytes())) {
+            return Ok( false )
+        }
+
+        self.restore_folder_from_index(path)?;
 
-        Ok( path.exists() )
+        Ok( true )
     }
 }
 
@@ -0,0 +1,183 @@
+//
+// src/toolbox/repository
+//
+// Signed staging manifests.
+//
+// A staging manifest lists the clobs of a staged contents tree together with
+// their hashes. The manifest is signed using the signing setup configured for
+// git (gpg or ssh) and stored as a git note attached to the contents tree, so
+// that it can be later verified which user produced a given managed tree.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use super::Repository;
+
+use anyhow::{Result, bail};
+use crate::error;
+use crate::sorting::SortPolicy;
+
+/// The notes reference used to store the staging manifests
+pub const MANIFEST_NOTES_REF : &str = "refs/notes/toolbox";
+
+// git config key that enables signing for every stage operation
+const SIGN_CONFIG_KEY : &str = "toolbox.signStaging";
+
+
+impl Repository {
+    /// Check whether signing of staging manifests was requested in git config
+    pub fn signing_requested(&self) -> bool {
+        self.repository.config()
+            .and_then(|config| config.get_bool(SIGN_CONFIG_KEY))
+            .unwrap_or(false)
+    }
+
+    /// Retrieve the id of a staged contents tree
+    ///
+    /// # Notes
+    ///
+    /// Git only writes the trees when commiting, so we write the index tree
+    /// ourselves. The resulting tree objects are identical to the ones that
+    /// will be created by the commit.
+    pub fn staged_tree_id<P: AsRef<str>>(&self, contents_path: P) -> Result<Option<String>> {
+        let repo = &self.repository;
+
+        let mut index = repo.index().map_err(error::OtherGitError::from)?;
+        let tree_id = index.write_tree().map_err(error::OtherGitError::from)?;
+        let tree = repo.find_tree(tree_id).map_err(error::OtherGitError::from)?;
+
+        // the contents tree might not exist if all the clobs were deleted
+        let id = tree.get_path(std::path::Path::new(contents_path.as_ref()))
+            .ok()
+            .map(|entry| entry.id().to_string());
+
+        Ok( id )
+    }
+
+    /// Sign the staging manifest for a staged contents tree
+    ///
+    /// The signed manifest is stored as a git note attached to the tree
+    pub fn sign_staged_tree<P: AsRef<str>>(&self, contents_path: P, tree_id: &str) -> Result<()> {
+        use git2::{Oid, TreeWalkMode, TreeWalkResult, ObjectType};
+
+        let repo = &self.repository;
+        let contents_path = contents_path.as_ref();
+
+        let tree_id = Oid::from_str(tree_id).map_err(error::OtherGitError::from)?;
+        let tree = repo.find_tree(tree_id).map_err(error::OtherGitError::from)?;
+
+        // list all the clobs in the tree
+        let mut clobs = vec!();
+        tree.walk(TreeWalkMode::PreOrder, |root, entry| {
+            if entry.kind() == Some(ObjectType::Blob) {
+                let name = String::from_utf8_lossy(entry.name_bytes());
+                clobs.push((format!("{}{}", root, name), entry.id()));
+            }
+            TreeWalkResult::Ok
+        }).map_err(error::OtherGitError::from)?;
+
+        // the manifest does not depend on the configured order
+        clobs.sort_by(|(a, _), (b, _)| SortPolicy::Natural.compare(a, b));
+        let clobs = clobs.into_iter()
+            .map(|(path, id)| format!("{} {}", id, path))
+            .collect::<Vec<_>>();
+
+        // build the manifest
+        let manifest = format!(
+            "git-toolbox staging manifest\nversion {}\nuser {}\npath {}\ntree {}\n\n{}\n",
+            env!("CARGO_PKG_VERSION"),
+            self.user_display_name(),
+            contents_path,
+            tree_id,
+            clobs.join("\n")
+        );
+
+        // sign it
+        let git_config = repo.config().map_err(error::OtherGitError::from)?;
+        let signature = sign_text(&git_config, &manifest)?;
+
+        // and store it in the notes
+        let author = repo.signature().map_err(error::OtherGitError::from)?;
+        repo.note(
+            &author,
+            &author,
+            Some(MANIFEST_NOTES_REF),
+            tree_id,
+            &format!("{}\n{}", manifest, signature),
+            true
+        ).map_err(error::OtherGitError::from)?;
+
+        Ok( () )
+    }
+}
+
+
+/// Produce a detached signature for the text
+///
+/// This uses the same configuration as git itself (`gpg.format`, `gpg.program`,
+/// `gpg.ssh.program` and `user.signingkey`)
+fn sign_text(config: &git2::Config, text: &str) -> Result<String> {
+    use std::process::{Command, Stdio};
+    use std::io::Write;
+
+    let format = config.get_string("gpg.format").unwrap_or_else(|_| "openpgp".to_owned());
+    let key = config.get_string("user.signingkey").ok();
+
+    // set up the signing command
+    let mut command = match format.as_str() {
+        "ssh" => {
+            let program = config.get_string("gpg.ssh.program")
+                .unwrap_or_else(|_| "ssh-keygen".to_owned());
+
+            // ssh signing always requires a key
+            let key = key.ok_or_else(|| {
+                error::SigningFailed {
+                    msg : "user.signingkey is not configured".to_owned()
+                }
+            })?;
+
+            let mut command = Command::new(program);
+            command.args(["-Y", "sign", "-n", "git-toolbox", "-f"]).arg(key);
+
+            command
+        },
+        _ => {
+            let program = config.get_string("gpg.program").unwrap_or_else(|_| "gpg".to_owned());
+
+            let mut command = Command::new(program);
+            command.args(["--armor", "--detach-sign"]);
+            if let Some(key) = key {
+                command.arg("--local-user").arg(key);
+            }
+
+            command
+        }
+    };
+
+    // run the command, feeding it the text
+    let output = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .and_then(|mut child| {
+            child.stdin.take().expect("fatal: no stdin").write_all(text.as_bytes())?;
+            child.wait_with_output()
+        })
+        .map_err(|err| {
+            error::SigningFailed {
+                msg : err.to_string()
+            }
+        })?;
+
+    if !output.status.success() {
+        bail!(
+            error::SigningFailed {
+                msg : String::from_utf8_lossy(&output.stderr).trim().to_owned()
+            }
+        );
+    }
+
+    Ok( String::from_utf8_lossy(&output.stdout).into_owned() )
+}
@@ -0,0 +1,74 @@
+//
+// src/toolbox/repository
+//
+// The placeholders the managed files were last reconstructed from.
+//
+// Git checks the managed files with the clean filter before a checkout or a reset
+// replaces them, also when the index on disk does not describe the files yet. The
+// smudge filter lists the placeholder it converted for every managed file in a local
+// file (tab-separated: the path and the id of the placeholder blob), so that the clean
+// filter can give the same placeholder back for a file that was not edited since.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use super::Repository;
+
+use anyhow::Result;
+use crate::error;
+
+// name of the file with the smudged placeholders (in the git-toolbox data directory)
+const SMUDGED_FILE : &str = "smudged";
+
+
+impl Repository {
+    /// Record that the managed file at `path` was reconstructed from `placeholder`
+    pub fn record_smudged_placeholder(&self, path: &str, placeholder: &[u8]) -> Result<()> {
+        let path_to_list = self.data_dir()?.join(SMUDGED_FILE);
+
+        let id = self.repository.blob(placeholder)?;
+
+        let mut placeholders = read_smudged_placeholders(&self.repository);
+        if placeholders.iter().any(|(p, i)| p == path && *i == id) {
+            return Ok( () );
+        }
+        placeholders.retain(|(p, _)| p != path);
+        placeholders.push((path.to_owned(), id));
+        placeholders.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let text = placeholders.iter()
+            .map(|(path, id)| format!("{}\t{}\n", path, id))
+            .collect::<String>();
+
+        std::fs::write(&path_to_list, text).map_err(|err| {
+            error::FileWriteError {
+                path : path_to_list,
+                msg  : err.to_string()
+            }
+            .into()
+        })
+    }
+
+    /// The placeholder the managed file at `path` was last reconstructed from
+    pub fn smudged_placeholder(&self, path: &str) -> Option<String> {
+        let (_, id) = read_smudged_placeholders(&self.repository).into_iter().find(|(p, _)| p == path)?;
+        let blob = self.repository.find_blob(id).ok()?;
+
+        String::from_utf8(blob.content().to_vec()).ok()
+    }
+}
+
+
+// read the list of smudged placeholders (a missing or malformed list is treated as empty)
+fn read_smudged_placeholders(repo: &git2::Repository) -> Vec<(String, git2::Oid)> {
+    let text = std::fs::read_to_string(repo.path().join("toolbox").join(SMUDGED_FILE)).unwrap_or_default();
+
+    text.lines()
+        .filter_map(|line| {
+            let (path, id) = line.split_once('\t')?;
+
+            Some( (path.to_owned(), git2::Oid::from_str(id).ok()?) )
+        })
+        .collect()
+}
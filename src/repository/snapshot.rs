@@ -0,0 +1,280 @@
+//
+// src/toolbox/repository
+//
+// Snapshots of reconstructed managed files.
+//
+// A snapshot is a commit on a dedicated orphan branch that contains the managed
+// files as plain text (reconstructed from a revision). This gives a backup that
+// can be read without git-toolbox. The same tree is written to an archive file 
+// for the releases of a dataset.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use super::Repository;
+
+use anyhow::Result;
+use crate::error;
+
+/// The default branch used to store the snapshots
+pub const SNAPSHOT_BRANCH : &str = "toolbox-snapshots";
+
+/// An archive of the managed files
+pub struct Archive {
+    /// Id of the commit the archive was reconstructed from
+    pub source : String,
+    /// The managed files contained in the archive
+    pub paths  : Vec<String>
+}
+
+/// A snapshot commit
+pub struct Snapshot {
+    /// Id of the snapshot commit
+    pub commit : String,
+    /// Id of the commit the snapshot was reconstructed from
+    pub source : String,
+    /// The managed files contained in the snapshot
+    pub paths  : Vec<String>
+}
+
+
+impl Repository {
+    /// Record a snapshot of the managed files at the revision on the snapshot branch
+    ///
+    /// Returns `None` if the snapshot branch is already up to date
+    pub fn create_snapshot<R, B>(&self, rev: R, branch: B) -> Result<Option<Snapshot>>
+    where
+        R : AsRef<str>,
+        B : AsRef<str>
+    {
+        let repo = &self.repository;
+        let rev = rev.as_ref();
+        let refname = format!("refs/heads/{}", branch.as_ref());
+
+        let source = self.source_commit(rev)?;
+        let (tree_id, paths) = self.snapshot_tree(&source, false)?;
+        let tree = repo.find_tree(tree_id).map_err(error::OtherGitError::from)?;
+
+        // the previous snapshot (if any)
+        let parent = repo.find_reference(&refname).ok()
+            .and_then(|reference| reference.peel_to_commit().ok());
+
+        // nothing changed since the last snapshot
+        if parent.as_ref().map(|parent| parent.tree_id() == tree_id).unwrap_or(false) {
+            return Ok( None )
+        }
+
+        let message = format!(
+            "git-toolbox snapshot of {}\n\nReconstructed from commit {}\n",
+            rev,
+            source.id()
+        );
+
+        let author = repo.signature().map_err(error::OtherGitError::from)?;
+        let parents = parent.iter().collect::<Vec<_>>();
+        let commit = repo.commit(Some(&refname), &author, &author, &message, &tree, &parents)
+            .map_err(error::OtherGitError::from)?;
+
+        Ok(
+            Some(
+                Snapshot {
+                    commit : commit.to_string(),
+                    source : source.id().to_string(),
+                    paths
+                }
+            )
+        )
+    }
+
+    /// Write the managed files reconstructed at the revision to an archive
+    ///
+    /// The format (`zip`, `tar` or `tar.gz`) is given by the extension of the output 
+    /// file. With `include_contents`, the managed folders are added to the archive as 
+    /// well. We rely on `git archive` to write the archive
+    pub fn write_archive<R>(&self, rev: R, output: &std::path::Path, include_contents: bool) -> Result<Archive>
+    where
+        R : AsRef<str>
+    {
+        use std::process::Command;
+
+        let archive_error = |msg: String| {
+            error::ArchiveFailed {
+                path : output.to_owned(),
+                msg
+            }
+        };
+
+        let format = archive_format(output).ok_or_else(|| {
+            archive_error("unknown archive format, use .zip, .tar, .tar.gz or .tgz".to_owned())
+        })?;
+
+        let source = self.source_commit(rev.as_ref())?;
+        let (tree_id, paths) = self.snapshot_tree(&source, include_contents)?;
+
+        let output_path = crate::util::absolute_path(output);
+        let result = super::pass_lock_to(&mut Command::new("git"))
+            .arg("archive")
+            .arg(format!("--format={}", format))
+            .arg("-o")
+            .arg(&output_path)
+            .arg(tree_id.to_string())
+            .current_dir(self.workdir()?)
+            .output()
+            .map_err(|err| archive_error(err.to_string()))?;
+
+        if !result.status.success() {
+            anyhow::bail!(archive_error(String::from_utf8_lossy(&result.stderr).trim().to_owned()));
+        }
+
+        Ok(
+            Archive {
+                source : source.id().to_string(),
+                paths
+            }
+        )
+    }
+
+    // Resolve the revision the snapshot is taken from
+    fn source_commit(&self, rev: &str) -> Result<git2::Commit<'_>> {
+        let commit = self.repository.revparse_single(rev)
+            .and_then(|obj| obj.peel_to_commit())
+            .map_err(|_| {
+                error::GitRevisionNotFound {
+                    rev : rev.to_owned()
+                }
+            })?;
+
+        Ok( commit )
+    }
+
+    // Build the tree with the managed files reconstructed from the commit (and their managed 
+    // folders if requested), returning the tree and the managed files in it
+    fn snapshot_tree(&self, source: &git2::Commit, include_contents: bool) -> Result<(git2::Oid, Vec<String>)> {
+        use git2::{Index, IndexEntry, IndexTime, TreeWalkMode, TreeWalkResult};
+
+        let repo = &self.repository;
+        let source_tree = source.tree().map_err(error::OtherGitError::from)?;
+
+        let entry = |path: String, id: git2::Oid, mode: u32, size: usize| {
+            IndexEntry {
+                ctime          : IndexTime::new(0, 0),
+                mtime          : IndexTime::new(0, 0),
+                dev            : 0,
+                ino            : 0,
+                mode,
+                uid            : 0,
+                gid            : 0,
+                file_size      : size as u32,
+                id,
+                flags          : 0,
+                flags_extended : 0,
+                path           : path.into_bytes()
+            }
+        };
+
+        // build the snapshot tree in an in-memory index
+        let mut index = Index::new().map_err(error::OtherGitError::from)?;
+        let mut paths = vec!();
+
+        for cfg in self.config.dictionaries.iter() {
+            let contents_path = format!("{}.contents", cfg.path);
+
+            // the dictionary might not exist in this revision
+            let contents = match source_tree.get_path(std::path::Path::new(&contents_path)) {
+                Ok(contents) => contents,
+                Err(_)       => continue
+            };
+
+            let data = super::reconstruct::reconstruct_from_rev(
+                repo, &contents_path, source.id().to_string(), &cfg.sort
+            )?;
+            // encode the file exactly as it would be written to the working copy
+            let data = cfg.encode_text(cfg.space_records(cfg.adapt_header(data)))?;
+            let blob = repo.blob(&data).map_err(error::OtherGitError::from)?;
+            index.add(&entry(cfg.path.clone(), blob, 0o100644, data.len())).map_err(error::OtherGitError::from)?;
+
+            // the clobs as they are stored in the revision
+            if include_contents {
+                let tree = repo.find_tree(contents.id()).map_err(error::OtherGitError::from)?;
+
+                let mut clobs = vec!();
+                tree.walk(TreeWalkMode::PreOrder, |root, clob| {
+                    if clob.kind() == Some(git2::ObjectType::Blob) {
+                        let path = format!("{}/{}{}", contents_path, root, clob.name().unwrap_or_default());
+                        clobs.push((path, clob.id(), clob.filemode() as u32));
+                    }
+                    TreeWalkResult::Ok
+                })
+                .map_err(error::OtherGitError::from)?;
+
+                for (path, id, mode) in clobs {
+                    let size = repo.find_blob(id).map(|blob| blob.size()).unwrap_or(0);
+                    index.add(&entry(path, id, mode, size)).map_err(error::OtherGitError::from)?;
+                }
+            }
+
+            paths.push(cfg.path.clone());
+        }
+
+        let tree_id = index.write_tree_to(repo).map_err(error::OtherGitError::from)?;
+
+        Ok( (tree_id, paths) )
+    }
+
+    /// Push the snapshot branch to a remote
+    ///
+    /// We rely on the git command line here, so that the user's credential setup is used
+    pub fn push_snapshot<R, B>(&self, remote: R, branch: B) -> Result<()>
+    where
+        R : AsRef<str>,
+        B : AsRef<str>
+    {
+        use std::process::Command;
+
+        let remote = remote.as_ref();
+        let refspec = format!("refs/heads/{0}:refs/heads/{0}", branch.as_ref());
+
+        let output = super::pass_lock_to(&mut Command::new("git"))
+            .args(["push", "--quiet", remote, &refspec])
+            .current_dir(self.workdir()?)
+            .output()
+            .map_err(|err| {
+                error::SnapshotPushFailed {
+                    remote : remote.to_owned(),
+                    msg    : err.to_string()
+                }
+            })?;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                error::SnapshotPushFailed {
+                    remote : remote.to_owned(),
+                    msg    : String::from_utf8_lossy(&output.stderr).trim().to_owned()
+                }
+            );
+        }
+
+        Ok( () )
+    }
+
+    /// The git config value of a git-toolbox setting (if set)
+    pub fn git_config_value<K: AsRef<str>>(&self, key: K) -> Option<String> {
+        self.repository.config().ok()?.get_string(key.as_ref()).ok()
+    }
+}
+
+// The `git archive` format for the output file (if the extension is known)
+fn archive_format(output: &std::path::Path) -> Option<&'static str> {
+    let name = output.file_name().map(|name| name.to_string_lossy().to_lowercase()).unwrap_or_default();
+
+    if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some("tar.gz")
+    } else if name.ends_with(".tar") {
+        Some("tar")
+    } else if name.ends_with(".zip") {
+        Some("zip")
+    } else {
+        None
+    }
+}
@@ -8,15 +8,19 @@
 // This code is licensed under GPL 3.0
 
 
-use super::{Repository, MANAGED_FILE_TEXT, ClobDiff};
+use super::{Repository, ClobDiff};
 use std::marker::PhantomData;
 
-use anyhow::Result;
+use anyhow::{Result, bail};
 use crate::error;
 
+// the assume-unchanged bit of the index entry flags (as set by `git update-index --assume-unchanged`)
+const ASSUME_UNCHANGED : u16 = 0x8000;
+
 /// A repository updater
 pub struct StagingArea<'repo> {
     repo    : PhantomData<&'repo mut Repository>,
+    git     : &'repo git2::Repository,
     index   : git2::Index,
     workdir : &'repo std::path::Path
 }
@@ -24,18 +28,147 @@ pub struct StagingArea<'repo> {
 
 impl Repository {
      /// Get a staging area object for updating the repository
-    pub fn get_staging_area(&mut self) -> Result<StagingArea> {
+    pub fn get_staging_area(&mut self) -> Result<StagingArea<'_>> {
+        // fail early if the index cannot be written, before any files are changed
+        if self.index_lock() != super::IndexLock::Unlocked {
+            anyhow::bail!(error::IndexLocked);
+        }
+
         let index = self.repository.index().map_err(error::OtherGitError::from)?;
         let workdir = self.workdir()?;         
 
         Ok(
             StagingArea {
                 repo    : PhantomData,
+                git     : &self.repository,
                 index,
                 workdir
             }
         )
     }  
+
+    /// Restore the index entries of the given paths to their state in HEAD
+    ///
+    /// Entries that are not in HEAD are removed from the index (analogue to `git reset -- <paths>`).
+    /// Paths are assumed to be relative to the repository
+    pub fn unstage_paths<I, P>(&mut self, paths: I) -> Result<()>
+    where
+        I : IntoIterator<Item = P>,
+        P : AsRef<str>
+    {
+        // there is nothing to restore the entries from in a repository without commits
+        let head = self.repository.head().ok().map(|head| head.peel(git2::ObjectType::Commit)).transpose()
+            .map_err(error::OtherGitError::from)?;
+
+        self.reset_index_paths(head.as_ref(), paths)
+    }
+
+    /// Set the index entries of the given paths to their state in a revision
+    ///
+    /// Entries that are not in the revision are removed from the index (analogue to 
+    /// `git reset <rev> -- <paths>`). Paths are assumed to be relative to the repository
+    pub fn reset_paths_to_rev<I, P>(&mut self, rev: &str, paths: I) -> Result<()>
+    where
+        I : IntoIterator<Item = P>,
+        P : AsRef<str>
+    {
+        let commit = self.repository.revparse_single(rev)
+            .and_then(|obj| obj.peel(git2::ObjectType::Commit))
+            .map_err(|_| {
+                error::GitRevisionNotFound {
+                    rev : rev.to_owned()
+                }
+            })?;
+
+        self.reset_index_paths(Some(&commit), paths)
+    }
+
+    // set the index entries to the ones of the target commit (or remove them)
+    fn reset_index_paths<I, P>(&self, target: Option<&git2::Object>, paths: I) -> Result<()>
+    where
+        I : IntoIterator<Item = P>,
+        P : AsRef<str>
+    {
+        if self.index_lock() != super::IndexLock::Unlocked {
+            anyhow::bail!(error::IndexLocked);
+        }
+
+        let paths : Vec<String> = paths.into_iter().map(|path| path.as_ref().to_owned()).collect();
+
+        self.repository.reset_default(target, paths.iter())
+            .map_err(|err| -> anyhow::Error {
+                if err.code() == git2::ErrorCode::Locked {
+                    error::IndexLocked.into()
+                } else {
+                    error::OtherGitError::from(err).into()
+                }
+            })?;
+
+        Ok( () )
+    }
+
+    /// The managed files whose placeholder entry is no longer marked as assume-unchanged
+    ///
+    /// Tools such as `git update-index --really-refresh` clear the mark, after which git
+    /// compares the placeholder to the real file (see `set_placeholder_entry()`). Entries
+    /// that do not contain the placeholder text are left to `git toolbox doctor`
+    pub fn unmarked_managed_files(&self) -> Vec<String> {
+        let index = match self.repository.index() {
+            Ok( index ) => index,
+            Err( _ )    => return vec!()
+        };
+
+        self.config().dictionaries.iter().filter(|cfg| {
+            index.get_path(std::path::Path::new(&cfg.path), 0)
+                .filter(|entry| entry.flags & ASSUME_UNCHANGED == 0)
+                .and_then(|entry| self.repository.find_blob(entry.id).ok())
+                .map(|blob| self.config().is_managed_file_text(cfg, &String::from_utf8_lossy(blob.content())))
+                .unwrap_or(false)
+        })
+        .map(|cfg| cfg.path.clone())
+        .collect()
+    }
+
+    /// Record the stat data of the given managed files after they were reconstructed from the 
+    /// index (e.g. by a git hook or `git toolbox reset`)
+    ///
+    /// The placeholder entries keep the stat data of the files in the working directory, which 
+    /// git compares before checking out another revision of a placeholder (it refuses to replace
+    /// a file that might have changed). Paths are assumed to be relative to the repository
+    pub fn refresh_managed_files<I, P>(&mut self, paths: I) -> Result<()>
+    where
+        I : IntoIterator<Item = P>,
+        P : AsRef<str>
+    {
+        let mut paths = paths.into_iter().peekable();
+        if paths.peek().is_none() { return Ok( () ) }
+
+        let mut staging_area = self.get_staging_area()?;
+        for path in paths {
+            staging_area.refresh_managed_file(path)?;
+        }
+
+        staging_area.commit()
+    }
+
+    /// Mark the index entries of the given managed files as assume-unchanged again
+    ///
+    /// Paths are assumed to be relative to the repository
+    pub fn mark_managed_files<I, P>(&mut self, paths: I) -> Result<()>
+    where
+        I : IntoIterator<Item = P>,
+        P : AsRef<str>
+    {
+        let mut paths = paths.into_iter().peekable();
+        if paths.peek().is_none() { return Ok( () ) }
+
+        let mut staging_area = self.get_staging_area()?;
+        for path in paths {
+            mark_assume_unchanged(&mut staging_area.index, std::path::Path::new(path.as_ref()))?;
+        }
+
+        staging_area.commit()
+    }
 }
 
 /// Represents the git staging area for the repository
@@ -61,38 +194,59 @@ impl<'repo> StagingArea<'repo> {
         // folders afterwards
         let mut deleted_path_parents = HashSet::new();
 
+        // nothing is written unless all the paths are safe
+        let diffs = diffs.collect::<Vec<_>>();
+        for diff in diffs.iter() {
+            if let Some(reason) = super::clob_path_problem(diff.path()) {
+                bail!(
+                    error::UnsafeClobPath {
+                        path   : diff.path().to_owned(),
+                        reason : reason.to_owned()
+                    }
+                );
+            }
+        }
+
         // run though the actions
         for diff in diffs {
             // run the callback
-            notify(&diff);
+            notify(diff);
 
             match diff {
                 ClobDiff::Add { clob } | ClobDiff::Update {clob } => {
                     // construct the full path
                     let full_path = workdir.to_owned().join(&clob.path);
 
-                    // write the file to the filesystem
-                    std::fs::create_dir_all(
-                        &full_path.parent().expect("fatal — missing prefix directory")
-                    ).map_err(|err| {
-                        error::FileWriteError {
-                            path : full_path.clone(),
-                            msg  : err.to_string()
-                        }
-                    })?;
+                    // the file might already have the right content (e.g. after a reset or 
+                    // when the index was out of date), in which case we avoid rewriting it 
+                    let unchanged = fs::read(&full_path)
+                        .map(|content| content == clob.content.as_bytes())
+                        .unwrap_or(false);
 
-                    fs::write(&clob.path, &clob.content).map_err(|err| {
-                        error::FileWriteError {
-                            path : full_path.clone(),
-                            msg  : err.to_string()
-                        }
-                    })?;
+                    // write the file to the filesystem
+                    if !unchanged {
+                        std::fs::create_dir_all(
+                            full_path.parent().expect("fatal — missing prefix directory")
+                        ).map_err(|err| {
+                            error::FileWriteError {
+                                path : full_path.clone(),
+                                msg  : err.to_string()
+                            }
+                        })?;
+
+                        fs::write(&full_path, &clob.content).map_err(|err| {
+                            error::FileWriteError {
+                                path : full_path.clone(),
+                                msg  : err.to_string()
+                            }
+                        })?;
+                    }
 
                     // stage the file in the repository
                     index.add_path(Path::new(&clob.path)).map_err(error::OtherGitError::from)?;
                 },
                 ClobDiff::Delete { path } => {
-                    let full_path = workdir.to_owned().join(&path);
+                    let full_path = workdir.to_owned().join(path);
 
                     // remove the file from the filesystem
                     fs::remove_file(&full_path).map_err(|err| {
@@ -144,61 +298,162 @@ impl<'repo> StagingArea<'repo> {
     /// # Notes
     ///
     /// - The real content of managed files is stored in the `.contents` directory
-    /// and is reconstructed on the fly using the git filter. We put a placeholder
-    /// text in the repository itself to alert the user if somethign went wrong. 
+    ///   and is reconstructed on the fly using the git filter. We put a placeholder
+    ///   text in the repository itself to alert the user if somethign went wrong. 
     /// 
     /// - Git checks whether a file has changed in the working directory by comparing
-    /// it's stats with the ones in the index. This is a problem, since the placeholder
-    /// text size is guaranteed to be different from the size of the actual file. To
-    /// circumvent this, we have to change the file size of the index entry to match
-    /// the actual file on disk. This makes `git status` and friends work correctly. 
-    /// Since git does not seem to use the file size info in any other way, this should
-    /// be safe
+    ///   it's stats with the ones in the index, which never match for the placeholder.
+    ///   The entry is therefore marked as assume-unchanged (see `set_placeholder_entry()`),
+    ///   so that git does not compare it to the working directory at all. The changes 
+    ///   to the managed files are tracked by git-toolbox instead
     ///
     /// - The API lacks any convenient way of constructing git index entries and doing
-    /// it from scratch seems error-prone. We first stage the real file to have git
-    /// build an entry for us and then replace it's contents by the placeholder
-    /// API lacks any convenient way of doing it. This may create an orphaned blob
-    /// in the database, but that is the price we have to pay
-    pub fn stage_managed_file<P: AsRef<str>>(&mut self, path: P) -> Result<()> {
+    ///   it from scratch seems error-prone. We first stage the real file to have git
+    ///   build an entry for us and then replace it's contents by the placeholder.
+    ///   This may create an orphaned blob in the database, but that is the price we 
+    ///   have to pay
+    ///
+    /// - The placeholder names the tree of the managed folder (see `placeholder_with_contents()`), 
+    ///   so the managed folder must be staged first
+    pub fn stage_managed_file<P: AsRef<str>>(&mut self, path: P, placeholder: &str) -> Result<()> {
         use std::path::Path;
+        use crate::config::placeholder_with_contents;
+
+        let path = Path::new(path.as_ref());
 
-        let path = path.as_ref();
+        let placeholder = match self.contents_tree(path)? {
+            Some(tree) => placeholder_with_contents(placeholder, &tree),
+            None       => placeholder.to_owned()
+        };
 
         // stage the real file to build the index entry
-        self.index.add_path(Path::new(path)).map_err(error::OtherGitError::from)?;
-        let entry = self.index.get_path(Path::new(path), 0).ok_or_else(|| {
-            error::OtherGitError {
-                msg : "unable to retrieve entry from index".to_owned()
-            }
-        })?;
+        self.index.add_path(path).map_err(error::OtherGitError::from)?;
 
-        // save the file size
-        let file_size = entry.file_size;
+        set_placeholder_entry(&mut self.index, path, placeholder.as_bytes())
+    }
 
-        // now re-add the same entry as a placeholder 
-        self.index.add_frombuffer(&entry, MANAGED_FILE_TEXT.as_bytes())
-            .map_err(error::OtherGitError::from)?;
+    /// Record the stat data of a managed file that matches its staged managed folder (see 
+    /// `Repository::refresh_managed_files()`)
+    pub fn refresh_managed_file<P: AsRef<str>>(&mut self, path: P) -> Result<()> {
+        let path = std::path::Path::new(path.as_ref());
+
+        let placeholder = match self.index.get_path(path, 0) {
+            Some(entry) => entry,
+            None        => return Ok( () )
+        };
+
+        // stage the real file to have git build the stat data for us
+        self.index.add_path(path).map_err(error::OtherGitError::from)?;
 
-        // add_frombuffer changes the file size, but we want to keep the size of the 
-        // file on disk. So we need to do this dance one more time
-        let mut entry = self.index.get_path(Path::new(path), 0).ok_or_else(|| {
+        let mut entry = self.index.get_path(path, 0).ok_or_else(|| {
             error::OtherGitError {
                 msg : "unable to retrieve entry from index".to_owned()
             }
         })?;
-        entry.file_size = file_size;
-        self.index.add(&entry)?;
+        entry.id = placeholder.id;
+        entry.flags = placeholder.flags | ASSUME_UNCHANGED;
+        self.index.add(&entry).map_err(error::OtherGitError::from)?;
 
         Ok( () )
     }
 
+    /// Update the managed folder named by the staged placeholder of a managed file 
+    /// (e.g. after staging the record manifest)
+    ///
+    /// Placeholders that do not name a managed folder are left alone
+    pub fn update_managed_file_contents<P: AsRef<str>>(&mut self, path: P) -> Result<()> {
+        use std::path::Path;
+        use crate::config::{placeholder_contents, placeholder_with_contents};
+
+        let path = Path::new(path.as_ref());
+
+        let text = match self.index.get_path(path, 0).and_then(|entry| self.git.find_blob(entry.id).ok()) {
+            Some(blob) => String::from_utf8_lossy(blob.content()).into_owned(),
+            None       => return Ok( () )
+        };
+        if placeholder_contents(&text).is_none() { return Ok( () ) }
+
+        match self.contents_tree(path)? {
+            Some(tree) => set_placeholder_entry(&mut self.index, path, placeholder_with_contents(&text, &tree).as_bytes()),
+            None       => Ok( () )
+        }
+    }
+
+    // the tree of the staged managed folder of a managed file (it does not exist if all 
+    // the clobs were deleted)
+    fn contents_tree(&mut self, path: &std::path::Path) -> Result<Option<String>> {
+        let tree_id = self.index.write_tree().map_err(error::OtherGitError::from)?;
+        let tree = self.git.find_tree(tree_id).map_err(error::OtherGitError::from)?;
+
+        let contents = tree.get_path(std::path::Path::new(&format!("{}.contents", path.display()))).ok()
+            .map(|entry| entry.id().to_string());
+
+        Ok( contents )
+    }
+
     /// Write the git index, confirming any changes made to the staging area
     pub fn commit(mut self) -> Result<()> {
-        self.index.write().map_err(error::OtherGitError::from)?;
+        self.index.write().map_err(|err| -> anyhow::Error {
+            if err.code() == git2::ErrorCode::Locked {
+                error::IndexLocked.into()
+            } else {
+                error::OtherGitError::from(err).into()
+            }
+        })?;
 
         Ok( () )
     }
 
 }
 
+
+/// Replace the contents of the index entry of a managed file by the placeholder
+///
+/// The entry is marked as assume-unchanged: git does not compare it to the working directory 
+/// (where the file has its real contents), so that `git status`, `git add` and `git update-index 
+/// --refresh` leave it alone. The entry must exist
+pub(super) fn set_placeholder_entry(index: &mut git2::Index, path: &std::path::Path, placeholder: &[u8]) -> Result<()> {
+    let entry = index.get_path(path, 0).ok_or_else(|| {
+        error::OtherGitError {
+            msg : "unable to retrieve entry from index".to_owned()
+        }
+    })?;
+
+    index.add_frombuffer(&entry, placeholder).map_err(error::OtherGitError::from)?;
+
+    // `add_frombuffer()` replaces the size by the size of the placeholder, but the stat data 
+    // describes the file in the working directory (as for any file git stages through the 
+    // clean filter): git only replaces a file with matching stat data when it checks out 
+    // another revision of the placeholder (see `Repository::refresh_managed_files()`)
+    if let Some(mut staged) = index.get_path(path, 0).filter(|staged| staged.file_size != entry.file_size) {
+        staged.file_size = entry.file_size;
+        index.add(&staged).map_err(error::OtherGitError::from)?;
+    }
+
+    mark_assume_unchanged(index, path).map(|_| ())
+}
+
+/// Mark the index entry of a managed file as assume-unchanged (see `set_placeholder_entry()`)
+///
+/// Returns whether the entry was changed (it exists and was not marked yet)
+pub(super) fn mark_assume_unchanged(index: &mut git2::Index, path: &std::path::Path) -> Result<bool> {
+    let mut entry = match index.get_path(path, 0) {
+        Some(entry) if entry.flags & ASSUME_UNCHANGED == 0 => entry,
+        _                                                  => return Ok( false )
+    };
+
+    entry.flags |= ASSUME_UNCHANGED;
+    index.add(&entry).map_err(error::OtherGitError::from)?;
+
+    Ok( true )
+}
+
+/// Clear the assume-unchanged mark of an index entry (e.g. of a file that is no longer managed)
+pub(super) fn clear_assume_unchanged(index: &mut git2::Index, path: &std::path::Path) -> Result<()> {
+    if let Some(mut entry) = index.get_path(path, 0).filter(|entry| entry.flags & ASSUME_UNCHANGED != 0) {
+        entry.flags &= !ASSUME_UNCHANGED;
+        index.add(&entry).map_err(error::OtherGitError::from)?;
+    }
+
+    Ok( () )
+}
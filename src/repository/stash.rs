@@ -0,0 +1,164 @@
+//
+// src/toolbox/repository
+//
+// Stashed changes of the managed files.
+//
+// `git stash` does not work with the managed files: their index entries are
+// placeholders, so the stash would only record the placeholders (or fail
+// because the clean filter runs while the index is locked). Instead, the
+// managed files are stashed as their clob trees, as split from the working
+// copy: a stash entry is a commit with the managed folders of the stashed
+// files. Its first parent records the index the changes were based on (as a
+// commit of the index tree on top of HEAD), so that the changes can be merged
+// with the managed files when they have moved on in the meantime. The entries
+// are chained by their second parent under a dedicated ref, the latest entry
+// being the ref itself (as with `refs/stash`).
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use super::{Repository, Clob};
+
+use anyhow::Result;
+use crate::error;
+
+/// The ref of the latest stash entry
+pub const STASH_REF : &str = "refs/toolbox/stash";
+
+/// A stash entry
+pub struct StashEntry {
+    /// Id of the stash commit
+    pub commit  : String,
+    /// Id of the commit with the index the changes were based on
+    pub base    : String,
+    /// The stash message
+    pub message : String,
+    /// The time the entry was recorded (seconds and offset in minutes)
+    pub time    : (i64, i32),
+    /// The stashed managed files
+    pub paths   : Vec<String>
+}
+
+
+impl Repository {
+    /// Record the clobs of the managed files as a new stash entry
+    ///
+    /// The clobs are given per managed file (the clob paths are relative to its managed folder)
+    pub fn stash_clobs(&self, files: &[(String, Vec<Clob>)], message: &str) -> Result<StashEntry> {
+        use git2::{Index, IndexEntry, IndexTime};
+
+        let repo = &self.repository;
+
+        // build the stash tree in an in-memory index
+        let mut index = Index::new().map_err(error::OtherGitError::from)?;
+
+        for (path, clob) in files.iter().flat_map(|(path, clobs)| clobs.iter().map(move |clob| (path, clob))) {
+            let blob = repo.blob(clob.content.as_bytes()).map_err(error::OtherGitError::from)?;
+
+            let entry = IndexEntry {
+                ctime          : IndexTime::new(0, 0),
+                mtime          : IndexTime::new(0, 0),
+                dev            : 0,
+                ino            : 0,
+                mode           : 0o100644,
+                uid            : 0,
+                gid            : 0,
+                file_size      : clob.content.len() as u32,
+                id             : blob,
+                flags          : 0,
+                flags_extended : 0,
+                path           : format!("{}.contents/{}", path, clob.path).into_bytes()
+            };
+            index.add(&entry).map_err(error::OtherGitError::from)?;
+        }
+
+        let tree_id = index.write_tree_to(repo).map_err(error::OtherGitError::from)?;
+        let tree = repo.find_tree(tree_id).map_err(error::OtherGitError::from)?;
+
+        let author = repo.signature().map_err(error::OtherGitError::from)?;
+
+        // the index the changes are based on
+        let index_tree = repo.index().and_then(|mut index| index.write_tree())
+            .and_then(|tree| repo.find_tree(tree))
+            .map_err(error::OtherGitError::from)?;
+        let head = repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+        let base = repo.commit(None, &author, &author, &format!("index on {}", message), &index_tree, &head.iter().collect::<Vec<_>>())
+            .map_err(error::OtherGitError::from)?;
+        let base = repo.find_commit(base).map_err(error::OtherGitError::from)?;
+
+        // the previous entry (if any)
+        let previous = repo.find_reference(STASH_REF).ok()
+            .and_then(|reference| reference.peel_to_commit().ok());
+
+        let parents = std::iter::once(&base).chain(previous.iter()).collect::<Vec<_>>();
+        let commit = repo.commit(Some(STASH_REF), &author, &author, message, &tree, &parents)
+            .map_err(error::OtherGitError::from)?;
+
+        Ok(
+            StashEntry {
+                commit  : commit.to_string(),
+                base    : base.id().to_string(),
+                message : message.to_owned(),
+                time    : (author.when().seconds(), author.when().offset_minutes()),
+                paths   : files.iter().map(|(path, _)| path.clone()).collect()
+            }
+        )
+    }
+
+    /// The stash entries, latest first
+    pub fn stash_entries(&self) -> Result<Vec<StashEntry>> {
+        let repo = &self.repository;
+
+        let mut entries = vec!();
+        let mut next = repo.find_reference(STASH_REF).ok()
+            .and_then(|reference| reference.peel_to_commit().ok());
+
+        while let Some(commit) = next {
+            let tree = commit.tree().map_err(error::OtherGitError::from)?;
+
+            // the managed files whose managed folders were stashed
+            let paths = self.config.dictionaries.iter()
+                .filter(|cfg| tree.get_path(std::path::Path::new(&format!("{}.contents", cfg.path))).is_ok())
+                .map(|cfg| cfg.path.clone())
+                .collect();
+
+            entries.push(
+                StashEntry {
+                    commit  : commit.id().to_string(),
+                    base    : commit.parent_id(0).map_err(error::OtherGitError::from)?.to_string(),
+                    message : commit.message().unwrap_or_default().trim().to_owned(),
+                    time    : (commit.time().seconds(), commit.time().offset_minutes()),
+                    paths
+                }
+            );
+
+            next = commit.parent(1).ok();
+        }
+
+        Ok( entries )
+    }
+
+    /// Remove the latest stash entry
+    pub fn drop_stash_entry(&self) -> Result<()> {
+        let repo = &self.repository;
+
+        let mut reference = match repo.find_reference(STASH_REF) {
+            Ok(reference) => reference,
+            Err(_)        => return Ok( () )
+        };
+        let commit = reference.peel_to_commit().map_err(error::OtherGitError::from)?;
+
+        // the previous entry becomes the latest one
+        match commit.parent(1) {
+            Ok(parent) => {
+                reference.set_target(parent.id(), "git-toolbox stash drop").map_err(error::OtherGitError::from)?;
+            },
+            Err(_) => {
+                reference.delete().map_err(error::OtherGitError::from)?;
+            }
+        }
+
+        Ok( () )
+    }
+}
@@ -0,0 +1,101 @@
+//
+// src/toolbox/repository
+//
+// Removing git-toolbox management from a repository.
+//
+// The index entry of a managed file only contains the placeholder text. Once the
+// file is no longer managed, the entry is replaced by the real (reconstructed)
+// contents of the file, so that the next commit records the file as an ordinary
+// text file. The managed folder can be removed along with it.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use super::Repository;
+
+use anyhow::Result;
+use crate::error;
+
+use std::path::Path;
+
+impl Repository {
+    /// Replace the placeholder index entry of a managed file by its contents
+    ///
+    /// Path is assumed to be relative to the repository. The working file is written
+    /// as well. Without contents, the file is removed from the index and the working 
+    /// directory instead. With `remove_contents`, the managed folder is removed from the
+    /// index and the working directory
+    pub fn unmanage_file(&self, path: &str, data: Option<&[u8]>, remove_contents: bool) -> Result<()> {
+        let full_path = self.workdir()?.join(path);
+        let mut index = self.repository.index().map_err(error::OtherGitError::from)?;
+
+        if let Some(data) = data {
+            std::fs::write(&full_path, data).map_err(|err| {
+                error::FileWriteError {
+                    path : full_path.clone(),
+                    msg  : err.to_string()
+                }
+            })?;
+
+            // stage the file to build the index entry (there might not be one yet)
+            index.add_path(Path::new(path)).map_err(error::OtherGitError::from)?;
+            let entry = index.get_path(Path::new(path), 0).ok_or_else(|| {
+                error::OtherGitError {
+                    msg : "unable to retrieve entry from index".to_owned()
+                }
+            })?;
+
+            // the blob is built from the data itself, not from the file on disk
+            index.add_frombuffer(&entry, data).map_err(error::OtherGitError::from)?;
+            super::staging_area::clear_assume_unchanged(&mut index, Path::new(path))?;
+        } else {
+            if full_path.exists() {
+                std::fs::remove_file(&full_path).map_err(|err| {
+                    error::FileDeleteError {
+                        path : full_path.clone(),
+                        msg  : err.to_string()
+                    }
+                })?;
+            }
+
+            if index.get_path(Path::new(path), 0).is_some() {
+                index.remove_path(Path::new(path)).map_err(error::OtherGitError::from)?;
+            }
+        }
+
+        if remove_contents {
+            let contents_path = format!("{}.contents", path);
+
+            index.remove_dir(Path::new(&contents_path), 0).map_err(error::OtherGitError::from)?;
+
+            let full_path = self.workdir()?.join(&contents_path);
+            if full_path.exists() {
+                std::fs::remove_dir_all(&full_path).map_err(|err| {
+                    error::FileDeleteError {
+                        path : full_path.clone(),
+                        msg  : err.to_string()
+                    }
+                })?;
+            }
+        }
+
+        index.write().map_err(|err| -> anyhow::Error {
+            if err.code() == git2::ErrorCode::Locked {
+                error::IndexLocked.into()
+            } else {
+                error::OtherGitError::from(err).into()
+            }
+        })?;
+
+        Ok( () )
+    }
+
+    /// Remove the git configuration of the managed files
+    ///
+    /// The updated configuration file (if given) is written and staged. With `all`, the
+    /// git filter configuration and the hooks are removed as well
+    pub fn unconfigure(&mut self, paths: &[String], config_text: Option<&str>, all: bool) -> Result<()> {
+        super::config::unconfigure_repository(&mut self.repository, paths, config_text, all)
+    }
+}
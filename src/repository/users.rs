@@ -0,0 +1,30 @@
+//
+// src/toolbox/repository
+//
+// The git-toolbox user of the repository.
+//
+// The users listed in the configuration file are identified by the git identity
+// (user.name or user.email) of the repository, which determines their role and
+// the ID namespace they work in.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use super::Repository;
+use crate::config::UserConfig;
+
+impl Repository {
+    /// The configured user that matches the git identity (if any)
+    pub fn current_user(&self) -> Option<&UserConfig> {
+        let git_config = self.repository.config().ok()?;
+
+        let identity = ["user.name", "user.email"].iter()
+            .filter_map(|key| git_config.get_string(key).ok())
+            .collect::<Vec<_>>();
+
+        self.config.users.iter().find(|user| {
+            identity.iter().any(|value| value.trim().eq_ignore_ascii_case(user.name.trim()))
+        })
+    }
+}
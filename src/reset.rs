@@ -8,16 +8,22 @@
 // This code is licensed under GPL 3.0
 
 
-use crate::repository::{Repository, ClobDiff, DiffStats};
+use crate::repository::{Repository, ClobDiff, ClobValidationIssue, DiffStats, Journal};
 use crate::toolbox::{Dictionary, ToolboxFileIssue};
 use crate::config::DictionaryConfig;
+use crate::sorting::SortPolicy;
 use itertools::{Itertools, Either};
 use crate::cli_app::style;
+use crate::profile::{self, Phase};
+use crate::util::truncate_text;
+use crate::output::{self, Report};
+use serde::Serialize;
 
 use crate::error;
 use anyhow::{Result, bail};
 
 const MAX_TO_SHOW: usize = 8;
+const MAX_FILENAME_WIDTH: usize = 60;
 
 struct ManagedFileSummary {
     // managed file name for displaying (relative to current folder)
@@ -26,18 +32,193 @@ struct ManagedFileSummary {
     pub path          : String, 
     // path to the managed content
     pub contents_path : String,
+    // the order of the clobs
+    pub sort          : SortPolicy,
     // the unstaged diff
     pub unstaged_diff : Vec<ClobDiff>,
     // the issues
     pub toolbox_issues : Vec<ToolboxFileIssue>,
+    // managed folder contents that do not match the index
+    pub workdir_issues : Vec<ClobValidationIssue>,
+    // the staged clobs that were edited by hand
+    pub hand_edited : Vec<String>
+}
+
+
+pub fn reset(
+    paths: Vec<String>, 
+    verbose: bool, 
+    force: bool, 
+    repair_contents: bool, 
+    to: Option<String>, 
+    allow_mass_deletion: bool
+) -> Result<()> {
+    // load the repository
+    let mut repo = Repository::open()?;
+
+    // no other git-toolbox operations while we are modifying the repository
+    let _lock = repo.lock_operation("reset")?;
+
+    let summaries = collect_summaries(&repo, &paths)?;
+
+    // the files are restored from a revision instead of the index
+    if let Some(rev) = to {
+        let command = format!("git toolbox reset --force --to {}", rev);
+        return reset_to_rev(&mut repo, &summaries, &rev, force, allow_mass_deletion, &paths, &command);
+    }
+
+    // we are only interested in files that have changes
+    let summaries: Vec<_> = summaries.into_iter().filter(|s| {
+        s.any_unstaged() || s.missing_header() || s.any_workdir_issues()
+    }).collect();
+
+    // check if ther is any work to do
+    if summaries.is_empty() {
+        return output::emit(&ResetReport { rev : None, files : vec!() });
+    }
+
+    // print the unstaged changes
+    for summary in summaries.iter() {
+        summary.display_unstaged_diff(verbose);
+    }
+
+    // verify that the managed folders match the index
+    //
+    // the dictionaries are reconstructed from the index, so the managed folders 
+    // would be left in an inconsistent state otherwise
+    let any_workdir_issues = summaries.iter().any(ManagedFileSummary::any_workdir_issues);
+    if any_workdir_issues {
+        stdout!("{warning}: the managed folders do not match the git index:",
+            warning=style("warning").bold().yellow()
+        );
+        stdout!("");
+
+        for summary in summaries.iter() {
+            summary.display_workdir_issues(verbose);
+        }
+    }
+
+    if any_workdir_issues && !repair_contents {
+        let cmd = format!("git toolbox reset --force --repair-contents {}", paths.join(" "));
+
+        bail!(concat!( 
+                "⚠️  The managed folders were modified outside of git-toolbox.\n",
+                "      (use \"{}\" to restore them from the git index as well)"
+            ), style(cmd.trim_end()).bold()
+        );
+    }
+
+    // the hand edits of the staged clobs end up in the reconstructed files
+    if summaries.iter().any(|summary| !summary.hand_edited.is_empty()) {
+        stderr!("{warning}: some staged files managed by git-toolbox were edited by hand, resetting will carry the edits over:",
+            warning=style("warning").bold().yellow()
+        );
+        stderr!("");
+        for summary in summaries.iter() {
+            summary.display_hand_edited(verbose);
+        }
+        stderr!("  (use \"{}\" to see the affected records)\n", style("git toolbox verify").bold());
+    }
+
+    if !force {
+        let flags = if repair_contents { "--force --repair-contents" } else { "--force" };
+        let cmd = format!("git toolbox reset {} {}", flags, paths.join(" "));
+
+        bail!(concat!( 
+                "⚠️  Resetting will discard any changes you have made to the files.\n",
+                "      (if you understand this and still wish to proceed, use \"{}\")"
+            ), style(cmd.trim_end()).bold()
+        );
+    }
 
+    // restoring a damaged index could delete most of the records of the files
+    if !allow_mass_deletion {
+        let flags = if repair_contents { "--force --repair-contents" } else { "--force" };
+        let cmd = format!("git toolbox reset --allow-mass-deletion {} {}", flags, paths.join(" "));
+        check_record_deletions(&repo, &summaries, None, &cmd)?;
+    }
+
+    let mut command = vec!("reset".to_owned(), "--force".to_owned());
+    if repair_contents { command.push("--repair-contents".to_owned()); }
+    if allow_mass_deletion { command.push("--allow-mass-deletion".to_owned()); }
+    let journal = begin_journal(&repo, command, &summaries)?;
+
+    // restore the managed folders 
+    for summary in summaries.iter().filter(|summary| summary.any_workdir_issues()) {
+        summary.restore_contents(&repo)?;
+
+        stdout!("{} Restored {} from git index ({} files)",
+            style("✓").green(),
+            &summary.contents_path,
+            summary.workdir_issues.len()
+        );
+    }
+
+    // reset all files
+    let mut files = vec!();
+    for summary in summaries.iter() {
+        let data = Repository::reconstruct(&summary.contents_path, "", &summary.sort)?;
+        write_managed_file(&repo, &summary.path, data)?;
+
+        let stats = summary.restore_stats();
+        stdout!("{} Restored {} from git index ({} added, {} modified, {} deleted)",
+            style("✓").green(),
+            &summary.display_name,
+            stats.added,
+            stats.changed, 
+            stats.deleted
+        );
+
+        files.push(ResetFile { path : summary.path.clone(), stats : Some(stats) });
+    }
+
+    // git compares the stat data of the restored files when checking out other revisions
+    repo.refresh_managed_files(files.iter().map(|file| &file.path))?;
+
+    journal.complete()?;
+
+    output::emit(&ResetReport { rev : None, files })
 }
 
 
-pub fn reset(paths: Vec<String>, verbose: bool, force: bool) -> Result<()> {
+// helper to reset the files to a revision
+//
+// The managed folders are checked out from the revision (both in the index and in the
+// working directory) and the files are reconstructed from them
+/// Restore the managed files from a revision without moving HEAD (analogue to
+/// `git checkout <rev> -- <path>`)
+///
+/// The files and their managed folders are restored as by `git toolbox reset --to`, 
+/// but the unstaged changes only have to be discarded explicitly (with `force`)
+pub fn checkout(rev: String, paths: Vec<String>, force: bool, allow_mass_deletion: bool) -> Result<()> {
     // load the repository
-    let repo = Repository::open()?;
+    let mut repo = Repository::open()?;
+
+    // no other git-toolbox operations while we are modifying the repository
+    let _lock = repo.lock_operation("checkout")?;
+
+    let summaries = collect_summaries(&repo, &paths)?;
+
+    if !force && summaries.iter().any(ManagedFileSummary::any_unstaged) {
+        for summary in summaries.iter() {
+            summary.display_unstaged_diff(false);
+        }
+
+        let cmd = format!("git toolbox checkout --force {} {}", rev, paths.join(" "));
+        bail!(concat!( 
+                "⚠️  Checking out {} will discard the unstaged changes to the files.\n",
+                "      (stage or stash them first, or use \"{}\" to discard them)"
+            ), style(&rev).bold(), style(cmd.trim_end()).bold()
+        );
+    }
 
+    let command = format!("git toolbox checkout --force {}", rev);
+    reset_to_rev(&mut repo, &summaries, &rev, true, allow_mass_deletion, &paths, &command)
+}
+
+
+// Collect the summaries of the requested files (all files by default), aborting on errors
+fn collect_summaries(repo: &Repository, paths: &[String]) -> Result<Vec<ManagedFileSummary>> {
     // dictionary selection
     let dictionaries : Vec<&DictionaryConfig> = if paths.is_empty() {
         repo.config().dictionaries.iter().collect()
@@ -53,7 +234,7 @@ pub fn reset(paths: Vec<String>, verbose: bool, force: bool) -> Result<()> {
 
     // process on the requested files
     let (summaries, errors) : (Vec<_>, Vec<_>) = dictionaries.into_iter().map(|cfg| {
-        ManagedFileSummary::new(&repo, cfg)
+        ManagedFileSummary::new(repo, cfg)
     })
     // split off and collect sucesses and failures
     .partition_map(|result| -> Either<_, anyhow::Error> {
@@ -74,63 +255,202 @@ pub fn reset(paths: Vec<String>, verbose: bool, force: bool) -> Result<()> {
         );
     }
 
-    // we are only interested in files that have changes
-    let summaries: Vec<_> = summaries.into_iter().filter(|s| {
-        s.any_unstaged() || s.missing_header()
-    }).collect();
-
-    // check if ther is any work to do
-    if summaries.is_empty() {
-        stdout!("✅ Nothing to do.");
-
-        return Ok( () )
-    }
+    Ok( summaries )
+}
 
-    // print the unstaged changes
-    for summary in summaries.iter() {
-        summary.display_unstaged_diff(verbose);
-    }
 
+fn reset_to_rev(
+    repo: &mut Repository, 
+    summaries: &[ManagedFileSummary], 
+    rev: &str, 
+    force: bool, 
+    allow_mass_deletion: bool,
+    paths: &[String],
+    command: &str
+) -> Result<()> {
     if !force {
-        let cmd = format!("git reset --force {}", paths.join(" "));
+        let cmd = format!("{} {}", command, paths.join(" "));
 
         bail!(concat!( 
                 "⚠️  Resetting will discard any changes you have made to the files.\n",
                 "      (if you understand this and still wish to proceed, use \"{}\")"
-            ), style(cmd).bold()
+            ), style(cmd.trim_end()).bold()
         );
     }
 
-    // reset all files
+    if !allow_mass_deletion {
+        let cmd = format!("{} --allow-mass-deletion {}", command, paths.join(" "));
+        check_record_deletions(repo, summaries, Some(rev), &cmd)?;
+    }
+
+    // reconstruct all the files first, so that nothing is changed if the revision
+    // does not contain some of them
+    let contents = summaries.iter().map(|summary| {
+        Repository::reconstruct(&summary.contents_path, rev, &summary.sort)
+    })
+    .collect::<Result<Vec<_>>>()?;
+
+    let mut command = vec!("reset".to_owned(), "--force".to_owned(), "--to".to_owned(), rev.to_owned());
+    if allow_mass_deletion { command.push("--allow-mass-deletion".to_owned()); }
+    let journal = begin_journal(repo, command, summaries)?;
+
+    // update the index
+    repo.reset_paths_to_rev(rev, summaries.iter().map(|summary| &summary.contents_path))?;
+
+    // the placeholders name the restored managed folders
+    let mut staging_area = repo.get_staging_area()?;
     for summary in summaries.iter() {
-        let absolute_path = repo.workdir()?.to_owned().join(&summary.path);
+        staging_area.update_managed_file_contents(&summary.path)?;
+    }
+    staging_area.commit()?;
 
-        let data = Repository::reconstruct(&summary.contents_path, "")?;
-        std::fs::write(&absolute_path, data).map_err(|err| {
-            error::FileWriteError {
-                path : absolute_path,
-                msg  : err.to_string()
-            }
-        })?;
+    let mut files = vec!();
+    for (summary, data) in summaries.iter().zip(contents) {
+        // the managed folder has to match the index
+        repo.restore_folder_from_index(&summary.contents_path)?;
 
+        write_managed_file(repo, &summary.path, data)?;
 
-        let stats = summary.restore_stats();
-        stdout!("{} Restored {} from git index ({} added, {} modified, {} deleted)",
+        stdout!("{} Restored {} from {}",
             style("✓").green(),
             &summary.display_name,
-            stats.added,
-            stats.changed, 
-            stats.deleted
+            style(rev).bold()
         );
+
+        files.push(ResetFile { path : summary.path.clone(), stats : None });
     }
 
-    stdout!("\n✅  Reset {} managed toolbox dictionaries.", summaries.len());
+    // git compares the stat data of the restored files when checking out other revisions
+    repo.refresh_managed_files(files.iter().map(|file| &file.path))?;
 
-    Ok( () )
+    journal.complete()?;
+
+    output::emit(&ResetReport { rev : Some(rev.to_owned()), files })
+}
 
+
+// helper to record the reset in the operation journal (the managed files are rewritten, so
+// copies of them are kept)
+fn begin_journal(repo: &Repository, mut command: Vec<String>, summaries: &[ManagedFileSummary]) -> Result<Journal> {
+    let files = summaries.iter().map(|summary| summary.path.clone()).collect::<Vec<_>>();
+    command.extend(files.iter().cloned());
+
+    repo.begin_journal("reset", command, &files, true)
 }
 
 
+/// The results of git toolbox reset
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct ResetReport {
+    /// The revision the files were restored from (the index if `None`)
+    rev   : Option<String>,
+    /// The restored managed files
+    files : Vec<ResetFile>
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct ResetFile {
+    /// The managed file (relative to the repository)
+    path  : String,
+    /// The restored changes of the entry files (only known when restoring from the index)
+    stats : Option<DiffStats>
+}
+
+impl Report for ResetReport {
+    fn display(&self) {
+        match &self.rev {
+            _ if self.files.is_empty() => {
+                stdout!("✅ Nothing to do.");
+            },
+            None      => {
+                stdout!("\n✅  Reset {} managed toolbox dictionaries.", self.files.len());
+            },
+            Some(rev) => {
+                stdout!("\n✅  Reset {} managed toolbox dictionaries to {}.", self.files.len(), rev);
+                stdout!("  (the restored contents are staged, use {} to unstage them)", 
+                    style("git toolbox unstage").bold()
+                );
+            }
+        }
+    }
+
+    /// The lines are `reset <managed file> <revision> <added> <modified> <deleted>`, the 
+    /// revision is empty for the index and the counts are empty when restoring a revision
+    fn porcelain(&self) -> Vec<Vec<String>> {
+        self.files.iter().map(|file| {
+            let count = |count: fn(&DiffStats) -> usize| {
+                file.stats.as_ref().map(|stats| count(stats).to_string()).unwrap_or_default()
+            };
+
+            vec!(
+                "reset".to_owned(),
+                file.path.clone(),
+                self.rev.clone().unwrap_or_default(),
+                count(|stats| stats.added),
+                count(|stats| stats.changed),
+                count(|stats| stats.deleted)
+            )
+        })
+        .collect()
+    }
+}
+
+// helper to check that resetting does not delete more records than allowed
+fn check_record_deletions(
+    repo: &Repository, 
+    summaries: &[ManagedFileSummary], 
+    rev: Option<&str>, 
+    cmd: &str
+) -> Result<()> {
+    let mut errors = vec!();
+
+    for summary in summaries.iter() {
+        let cfg = repo.config().dictionary_by_path(&summary.path)?;
+        let deletions = repo.records_deleted_by_restoring(
+            &summary.contents_path, &cfg.record_tags, &summary.unstaged_diff, rev
+        )?;
+
+        if deletions.exceeds(cfg.max_deleted_records) {
+            errors.push(
+                error::TooManyRecordsDeleted {
+                    path    : summary.display_name.clone().into(),
+                    deleted : deletions.deleted,
+                    total   : deletions.total,
+                    limit   : cfg.max_deleted_records
+                }
+            );
+        }
+    }
+
+    if !errors.is_empty() {
+        bail!(
+            "{}\n\nUse {} if the records should be deleted. No changes to the working directory were made.", 
+            errors.into_iter().join("\n"),
+            style(format!("\"{}\"", cmd.trim_end())).bold()
+        );
+    }
+
+    Ok( () )
+}
+
+// write the reconstructed contents to the managed file
+fn write_managed_file(repo: &Repository, path: &str, data: Vec<u8>) -> Result<()> {
+    let absolute_path = repo.workdir()?.to_owned().join(path);
+
+    let cfg = repo.config().dictionary_by_path(path)?;
+    let data = cfg.encode_text(cfg.space_records(cfg.adapt_header(data)))?;
+    std::fs::write(&absolute_path, data).map_err(|err| {
+        error::FileWriteError {
+            path : absolute_path,
+            msg  : err.to_string()
+        }
+    })?;
+
+    Ok( () )
+}
+
 
 impl ManagedFileSummary {
     pub fn new(repo :&Repository, cfg: &DictionaryConfig) -> Result<Self> {
@@ -138,7 +458,7 @@ impl ManagedFileSummary {
         let path = cfg.path.clone();
 
         // load and split the dictionary
-        let dictionary = Dictionary::load(&repo, cfg, false)?;
+        let dictionary = Dictionary::load(repo, cfg, false)?;
 
         // obtain the printable relative path to the file
         let display_name = crate::util::get_relative_path(
@@ -149,8 +469,18 @@ impl ManagedFileSummary {
         let (clobs, toolbox_issues) = dictionary.split();
 
         // run the diff 
-        let unstaged_diff = repo.diff_clobs_at_path(&contents_path, clobs)?;
+        let unstaged_diff = profile::measure(&contents_path, Phase::Diff, || {
+            repo.diff_clobs_at_path(&contents_path, clobs)
+        })?;
+
+        // verify the managed folder against the index (the files git does not know about
+        // would be left behind by the reset)
+        let workdir_issues = profile::measure(&contents_path, Phase::WorkdirValidation, || {
+            repo.validate_clobs_in_workdir(&contents_path, true)
+        })?;
 
+        // the staged clobs that were changed without git toolbox stage
+        let hand_edited = repo.hand_edited_clobs(&contents_path, &cfg.record_tags)?;
 
         // return the diff and the issues
         Ok( 
@@ -158,8 +488,11 @@ impl ManagedFileSummary {
                 display_name,
                 path, 
                 contents_path, 
+                sort : cfg.sort.clone(),
                 unstaged_diff,
-                toolbox_issues
+                toolbox_issues,
+                workdir_issues,
+                hand_edited
             }
         )
 
@@ -169,6 +502,93 @@ impl ManagedFileSummary {
         !self.unstaged_diff.is_empty()
     }
 
+    pub fn any_workdir_issues(&self) -> bool {
+        !self.workdir_issues.is_empty()
+    }
+
+    // Restore the managed folder from the index
+    //
+    // Modified and deleted files are checked out, files unknown to git are removed
+    pub fn restore_contents(&self, repo: &Repository) -> Result<()> {
+        use ClobValidationIssue::*;
+
+        let workdir = repo.workdir()?;
+
+        let checkout = self.workdir_issues.iter().filter_map(|issue| {
+            match issue {
+                UpdatedInWorkdir { path } | DeletedInWorkdir { path } => Some(path),
+                _                                                     => None
+            }
+        });
+        repo.checkout_from_index(checkout)?;
+
+        for issue in self.workdir_issues.iter() {
+            if let AddedInWorkdir { path } = issue {
+                let absolute_path = workdir.join(path);
+
+                std::fs::remove_file(&absolute_path).map_err(|err| {
+                    error::FileDeleteError {
+                        path : absolute_path.clone(),
+                        msg  : err.to_string()
+                    }
+                })?;
+            }
+        }
+
+        Ok( () )
+    }
+
+    pub fn display_workdir_issues(&self, verbose: bool) {
+        use ClobValidationIssue::*;
+
+        if !self.any_workdir_issues() { return }
+
+        stdout!("  {}:\n", style(&self.display_name).italic());
+        let to_show = if verbose { self.workdir_issues.len() } else { MAX_TO_SHOW };
+        for e in self.workdir_issues.iter().take(to_show) {
+            let (path, status) = match e {
+                AddedInWorkdir { path }   => (path.clone(), "new in the working directory"),
+                UpdatedInWorkdir { path } => (path.clone(), "modified in working directory"),
+                DeletedInWorkdir { path } => (path.clone(), "deleted in working directory"),
+                InvalidPath { path }      => {
+                    use crate::util::escape_unicode_only;
+
+                    (
+                        escape_unicode_only(&String::from_utf8_lossy(path)), 
+                        "invalid managed file path (will not be restored)"
+                    )
+                }
+            };
+
+            stdout!("        {}: {}", path, style(status).red());
+        }
+        if to_show < self.workdir_issues.len() {
+            stdout!("        ...");
+            stdout!("        ({} other external changes, use \"{}\" to see all)", 
+                self.workdir_issues.len() - to_show,
+                style("\"git toolbox reset --verbose\"").bold()
+            );
+        }
+        stdout!(""); 
+    }
+
+    pub fn display_hand_edited(&self, verbose: bool) {
+        if self.hand_edited.is_empty() { return }
+
+        let to_show = if verbose { self.hand_edited.len() } else { MAX_TO_SHOW };
+        for path in self.hand_edited.iter().take(to_show) {
+            stderr!("        {}: {}", path, style("edited by hand").red());
+        }
+        if to_show < self.hand_edited.len() {
+            stderr!("        ...");
+            stderr!("        ({} other hand-edited files, use \"{}\" to see all)",
+                self.hand_edited.len() - to_show,
+                style("\"git toolbox reset --verbose\"").bold()
+            );
+        }
+        stderr!("");
+    }
+
     pub fn restore_stats(&self) -> DiffStats {
         let stats = DiffStats::count(&self.unstaged_diff);
        
@@ -193,7 +613,7 @@ impl ManagedFileSummary {
         stdout!("\n  {}:\n", style(&self.display_name).italic());
         let to_show = if verbose { self.unstaged_diff.len() } else { MAX_TO_SHOW };
         for e in self.unstaged_diff.iter().take(to_show) {
-            stdout!("        {} {}", e.display_diff_marker(), e.filename());
+            stdout!("        {} {}", e.display_diff_marker(), truncate_text(e.filename(), MAX_FILENAME_WIDTH));
         }
         if to_show < self.unstaged_diff.len() {
             stdout!("        ...");
@@ -0,0 +1,348 @@
+//
+// src/resolve.rs
+//
+// Implementation of git-toolbox resolve
+//
+// An interactive editor for the conflicting records left by git toolbox
+// reconcile (in a managed toolbox file) or by the merge driver (in a clob of a
+// managed folder). The fields of the common ancestor, our and their version of
+// every conflicting record are shown side by side. The side of each field can
+// be picked (or the field edited inline) and the merged record replaces the
+// conflict markers in the file.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::repository::Repository;
+use crate::toolbox::starts_record;
+use crate::toolbox::merge::{find_conflicts, match_fields, Conflict, FieldVersions, Side};
+use crate::config::DictionaryConfig;
+use crate::cli_app::style;
+use crate::util::{truncate_text, pad_text};
+
+use crate::error;
+use anyhow::{Result, bail};
+use console::{Key, Term};
+
+use std::path::Path;
+
+
+// How a conflicting field is resolved
+#[derive(Clone, PartialEq)]
+enum Choice {
+    // both sides changed the field differently
+    Unresolved,
+    // the version of one of the sides
+    Side(Side),
+    // the field is edited by hand (the text includes the marker)
+    Edited(String),
+    // the field is removed
+    Dropped
+}
+
+// The outcome of the editor for a conflicting record
+enum Outcome {
+    // the merged record
+    Resolved(String),
+    // the record is left with the conflict markers
+    Skipped,
+    // the remaining records are left with the conflict markers
+    Quit,
+    // nothing is written
+    Aborted
+}
+
+
+pub fn resolve(path: String) -> Result<()> {
+    let repo = Repository::open()?;
+
+    // no other git-toolbox operations while we are modifying the working directory
+    let _lock = repo.lock_operation("resolve")?;
+
+    let path = repo.get_path_relative_to_repo(&path)?.to_string_lossy().into_owned();
+    let config = repo.config();
+
+    // the managed file itself (with the conflicts written by git toolbox reconcile)
+    // or a clob in a managed folder (with the conflicts written by the merge driver)
+    let (cfg, managed_file) = match config.dictionary_by_path(&path) {
+        Ok(cfg) => (cfg, true),
+        Err(_)  => {
+            let cfg = config.dictionaries.iter().find(|cfg| {
+                Path::new(&path).starts_with(format!("{}.contents", cfg.path))
+            })
+            .ok_or_else(|| {
+                error::NotAManagedFile {
+                    path : path.clone().into()
+                }
+            })?;
+
+            (cfg, false)
+        }
+    };
+
+    let file_path = repo.workdir()?.join(&path);
+    let data = std::fs::read(&file_path).map_err(|err| {
+        error::FileReadError {
+            path : file_path.clone(),
+            msg  : err.to_string()
+        }
+    })?;
+    let text = if managed_file {
+        cfg.decode_text(&file_path, data)?
+    } else {
+        String::from_utf8_lossy(&data).into_owned()
+    };
+
+    let conflicts = find_conflicts(&text);
+    if conflicts.is_empty() {
+        stdout!("{} No conflicting records in {}", style("✓").green(), style(&path).bold());
+        return Ok( () );
+    }
+
+    let term = Term::stdout();
+    if !term.features().is_attended() {
+        bail!(
+            error::InteractiveTerminalRequired {
+                command : "git toolbox resolve".to_owned()
+            }
+        );
+    }
+
+    // the merged records (by the index of the conflict)
+    let mut resolved : Vec<(usize, String)> = vec!();
+    for (i, conflict) in conflicts.iter().enumerate() {
+        match edit_conflict(&term, cfg, &path, conflict, (i, conflicts.len()))? {
+            Outcome::Resolved(record) => resolved.push((i, record)),
+            Outcome::Skipped          => {},
+            Outcome::Quit             => break,
+            Outcome::Aborted          => {
+                term.clear_screen()?;
+                stdout!("Nothing was written to {}", style(&path).bold());
+                return Ok( () );
+            }
+        }
+    }
+    term.clear_screen()?;
+
+    // replace the conflict markers by the merged records
+    if !resolved.is_empty() {
+        let text = replace_conflicts(&text, &conflicts, &resolved);
+        let data = if managed_file { cfg.encode_text(text.into_bytes())? } else { text.into_bytes() };
+
+        std::fs::write(&file_path, data).map_err(|err| {
+            error::FileWriteError {
+                path : file_path.clone(),
+                msg  : err.to_string()
+            }
+        })?;
+    }
+
+    stdout!("{} Resolved {} of {} conflicting records in {}",
+        style("✓").green(),
+        resolved.len(),
+        conflicts.len(),
+        style(&path).bold()
+    );
+
+    if resolved.len() < conflicts.len() {
+        stdout!("The remaining records are still between conflict markers.");
+    } else if managed_file {
+        stdout!("✅ Use {} to add the resolved dictionary to be commited.",
+            style("\"git toolbox stage\"").bold()
+        );
+    } else {
+        stdout!("✅ Use {} to mark the entry file as resolved.",
+            style(format!("\"git add {}\"", path)).bold()
+        );
+    }
+
+    Ok( () )
+}
+
+
+// Let the user resolve a conflicting record field by field
+fn edit_conflict(
+    term: &Term, cfg: &DictionaryConfig, path: &str, conflict: &Conflict, (index, count): (usize, usize)
+) -> Result<Outcome> {
+    let fields = match_fields(conflict.base.as_deref(), &conflict.ours, &conflict.theirs);
+    if fields.is_empty() {
+        return Ok( Outcome::Skipped );
+    }
+
+    // the fields are initially resolved as by a three-way merge
+    let mut choices : Vec<Choice> = fields.iter().map(|field| {
+        field.merged_side().map(Choice::Side).unwrap_or(Choice::Unresolved)
+    })
+    .collect();
+
+    let mut selected = choices.iter().position(|choice| *choice == Choice::Unresolved).unwrap_or(0);
+    let mut message = String::new();
+
+    // the record is labeled by the first line of any of its versions
+    let label = [Some(&conflict.ours), Some(&conflict.theirs), conflict.base.as_ref()].iter()
+        .flatten()
+        .filter_map(|version| version.lines().next())
+        .find(|line| starts_record(line, &cfg.record_tags))
+        .unwrap_or("")
+        .to_owned();
+
+    loop {
+        display_conflict(term, path, &label, &fields, &choices, selected, (index, count))?;
+        if !message.is_empty() {
+            term.write_line(&format!("{}", style(&message).yellow()))?;
+            message.clear();
+        }
+
+        let field = &fields[selected];
+        let choice = choices[selected].clone();
+        match term.read_key()? {
+            Key::ArrowUp   | Key::Char('k') => selected = selected.saturating_sub(1),
+            Key::ArrowDown | Key::Char('j') => selected = (selected + 1).min(fields.len().saturating_sub(1)),
+            Key::Char('o') => choices[selected] = Choice::Side(Side::Ours),
+            Key::Char('t') => choices[selected] = Choice::Side(Side::Theirs),
+            Key::Char('b') => choices[selected] = Choice::Side(Side::Base),
+            Key::Char('d') => choices[selected] = Choice::Dropped,
+            Key::Char('e') => {
+                // the current value of the field without the marker
+                let current = field_text(field, &choice).unwrap_or_default();
+                let value = current.trim_end().strip_prefix(field.marker.as_str()).unwrap_or("").trim();
+
+                term.write_str(&format!("{} ", field.marker))?;
+                let value = term.read_line_initial_text(&value.replace('\n', " "))?;
+
+                choices[selected] = Choice::Edited(format!("{} {}\n", field.marker, value.trim()).replace(" \n", "\n"));
+            },
+            Key::Char('s') => return Ok( Outcome::Skipped ),
+            Key::Char('q') => return Ok( Outcome::Quit ),
+            Key::Escape    => return Ok( Outcome::Aborted ),
+            Key::Enter     => {
+                let unresolved = choices.iter().filter(|choice| **choice == Choice::Unresolved).count();
+                if unresolved > 0 {
+                    message = format!("{} fields are still unresolved", unresolved);
+                    continue;
+                }
+
+                let record = fields.iter().zip(choices.iter())
+                    .filter_map(|(field, choice)| field_text(field, choice))
+                    .collect::<String>();
+
+                return Ok( Outcome::Resolved(record) );
+            },
+            _ => {}
+        }
+    }
+}
+
+// The text of a field resolved by the choice (`None` if the field is removed)
+fn field_text(field: &FieldVersions, choice: &Choice) -> Option<String> {
+    match choice {
+        Choice::Unresolved   => None,
+        Choice::Side(side)   => field.version(*side).cloned(),
+        Choice::Edited(text) => Some(text.clone()),
+        Choice::Dropped      => None
+    }
+}
+
+fn display_conflict(
+    term: &Term,
+    path: &str,
+    label: &str,
+    fields: &[FieldVersions],
+    choices: &[Choice],
+    selected: usize,
+    (index, count): (usize, usize)
+) -> Result<()> {
+    let (_, width) = term.size();
+    // the marker column and the three versions
+    let marker_width = fields.iter().map(|field| field.marker.len()).max().unwrap_or(0).min(12);
+    let column = ((width as usize).saturating_sub(marker_width + 12) / 3).max(10);
+
+    term.clear_screen()?;
+    term.write_line(&format!("Conflict {} of {} in {}", index + 1, count, style(path).bold()))?;
+    term.write_line(&format!("  {}", style(truncate_text(label, width as usize - 4)).italic()))?;
+    term.write_line("")?;
+    term.write_line(&format!("  {}  {}  {}  {}",
+        pad_text("", marker_width),
+        pad_text("base", column),
+        pad_text("ours", column),
+        pad_text("theirs", column)
+    ))?;
+
+    for (i, (field, choice)) in fields.iter().zip(choices.iter()).enumerate() {
+        // the value of the field in a version, highlighting the version that was picked
+        let version = |side: Side| {
+            let text = field.version(side)
+                .map(|text| text.strip_prefix(field.marker.as_str()).unwrap_or(text).trim().replace('\n', " "))
+                .map(|text| if text.is_empty() { "∅".to_owned() } else { text });
+
+            let cell = pad_text(&truncate_text(text.as_deref().unwrap_or("—"), column), column);
+            if *choice == Choice::Side(side) {
+                style(cell).green().bold()
+            } else if text.is_none() {
+                style(cell).dim()
+            } else {
+                style(cell)
+            }
+        };
+
+        let marker = pad_text(&truncate_text(&field.marker, marker_width), marker_width);
+        term.write_line(&format!("{} {}  {}  {}  {}",
+            if i == selected { "›" } else { " " },
+            match choice {
+                Choice::Unresolved => style(marker).red().bold(),
+                Choice::Edited(_)  => style(marker).cyan(),
+                Choice::Dropped    => style(marker).dim(),
+                Choice::Side(_)    => style(marker)
+            },
+            version(Side::Base),
+            version(Side::Ours),
+            version(Side::Theirs)
+        ))?;
+    }
+
+    // the merged value of the selected field
+    let merged = fields.get(selected).and_then(|field| {
+        match &choices[selected] {
+            Choice::Unresolved => Some(style("(unresolved)".to_owned()).red()),
+            Choice::Dropped    => Some(style("(removed)".to_owned()).dim()),
+            choice             => field_text(field, choice).map(|text| style(text.trim_end().to_owned()))
+        }
+    });
+
+    term.write_line("")?;
+    if let Some(merged) = merged {
+        term.write_line(&format!("  {}", merged))?;
+        term.write_line("")?;
+    }
+    term.write_line(&format!("  {}",
+        style("↑/↓ field   o ours   t theirs   b base   d drop   e edit   ⏎ accept   s skip   q save and quit   esc abort").dim()
+    ))?;
+
+    Ok( () )
+}
+
+/// Replace the conflicts by the merged records (by the index of the conflict)
+pub fn replace_conflicts(text: &str, conflicts: &[Conflict], resolved: &[(usize, String)]) -> String {
+    let mut merged = String::new();
+    // the merged records use the line endings of the file
+    let crlf = text.contains("\r\n");
+
+    for (i, line) in text.split_inclusive('\n').enumerate() {
+        let conflict = conflicts.iter().enumerate().find(|(_, conflict)| conflict.lines.contains(&i));
+
+        match conflict {
+            // the merged record replaces the first line of the conflict
+            Some((index, conflict)) => if let Some((_, record)) = resolved.iter().find(|(r, _)| *r == index) {
+                if i == conflict.lines.start {
+                    merged.push_str(&if crlf { record.replace('\n', "\r\n") } else { record.clone() });
+                }
+            } else {
+                merged.push_str(line);
+            },
+            None => merged.push_str(line)
+        }
+    }
+
+    merged
+}
@@ -0,0 +1,69 @@
+//
+// src/rm.rs
+//
+// Implementation of git-toolbox rm
+//
+// Stops managing a single toolbox file: the dictionary is removed from the
+// configuration file and its git attributes, and its managed folder is removed
+// from the index and the working directory. The file itself is staged as an
+// ordinary file with its staged contents, or deleted with --delete. The other
+// managed files are not affected.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::repository::Repository;
+use crate::config::{CONFIG_FILE, remove_dictionary_from_config};
+use crate::cli_app::style;
+
+use crate::error;
+use anyhow::{Result, bail};
+
+
+pub fn rm(path: String, delete: bool) -> Result<()> {
+    let mut repo = Repository::open()?;
+
+    let path = repo.get_path_relative_to_repo(&path)?.to_string_lossy().into_owned();
+    let cfg = repo.config().dictionary_by_path(&path)?.clone();
+
+    let config_path = repo.workdir()?.join(CONFIG_FILE);
+    let config_text = std::fs::read_to_string(&config_path).map_err(|err| {
+        error::FileReadError {
+            path : config_path.clone(),
+            msg  : err.to_string()
+        }
+    })?;
+
+    let config_text = match remove_dictionary_from_config(&config_text, &cfg.path) {
+        (_, 0)    => bail!(error::PathPatternDictionary { path : cfg.path.clone() }),
+        (text, _) => text
+    };
+
+    // the contents of the file (its unstaged changes would be lost)
+    let data = if delete { None } else { Some(crate::setup::staged_contents(&repo, &cfg)?) };
+
+    // no other git-toolbox operations while we are modifying the index
+    let _lock = repo.lock_operation("rm")?;
+
+    repo.unmanage_file(&cfg.path, data.as_deref(), true)?;
+
+    if delete {
+        stdout!("{} removed {} and {}.contents", style("✓").green(), style(&cfg.path).bold(), cfg.path);
+    } else {
+        stdout!("{} {} is now an ordinary file ({}.contents was removed)",
+            style("✓").green(),
+            style(&cfg.path).bold(),
+            cfg.path
+        );
+    }
+
+    repo.unconfigure(std::slice::from_ref(&cfg.path), Some(&config_text), false)?;
+
+    stdout!("\n✅  {} is no longer managed by git-toolbox. Use {} to record the change",
+        style(&cfg.path).bold(),
+        style("\"git commit\"").bold()
+    );
+
+    Ok( () )
+}
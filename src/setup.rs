@@ -3,12 +3,26 @@
 //
 // Implementation of git-toolbox setup 
 //
+// With --adopt, the records of a directory with one file per record (e.g. created
+// by the scripts of another tool) are taken over into a managed file: the records
+// are assembled into the managed file, which is then staged as usual (the records
+// are stored in the managed folder in the configured layout). The adopted files
+// are removed, so that the next commit moves them into the managed folder. If the
+// directory is the managed folder itself, the records that keep their paths keep
+// their history as well.
+//
+// With --uninstall, the managed files become ordinary files again: their placeholder
+// index entries are replaced by the reconstructed contents and their git attributes 
+// and configuration are removed (along with the git settings and the hooks once no
+// managed files remain).
+//
 // (C) 2020 Taras Zakharko
 //
 // This code is licensed under GPL 3.0
 
-use crate::repository::Repository;
-use crate::config::CONFIG_FILE;
+use crate::repository::{Repository, Clob, assemble_clobs, DICTIONARY_HEADER, HEADER_CLOB, MANIFEST_FILE};
+use crate::config::{CONFIG_FILE, DictionaryConfig, remove_dictionary_from_config, set_prefix_depth_in_config};
+use crate::toolbox::Dictionary;
 use crate::cli_app::style;
 
 use anyhow::{Result, anyhow, bail};
@@ -36,9 +50,33 @@ id-spec   = "(?P<namespace>[a-zA-Z]*)(?P<id>[0-9]+)"
 name = "Test Parsing Dictionary"
 path = "dictionaries/ParsingDic.txt"
 record-tag = "lex"
+
+# the fields of the dictionary entries (optional)
+# the template is used to validate and create entries
+#
+# [[dictionary.template.field]]
+# marker   = "ps"
+# required = true
+# type     = "set"
+# values   = ["n", "v", "adj"]
+#
+# [[dictionary.template.field]]
+# marker     = "ge"
+# repeatable = true
+
+
+# the text git shows instead of the dictionary contents (optional)
+# {name}, {path} and {contact} are replaced by the dictionary name, path 
+# and the support contact
+#
+# [placeholder]
+# contact = "Jane Doe <jane.doe@example.org>"
+# text    = """
+# This file ({name}) is managed by git-toolbox. Please contact {contact}.
+# """
 "#;
 
-pub fn setup(init: bool) -> Result<()> {
+pub fn setup(init: bool, adopt: Option<(String, String)>) -> Result<()> {
     // init flag is set, we want to create an example config file
     if init {
         let config_path = Repository::workdir_for_repo_here()?.join(CONFIG_FILE);
@@ -47,14 +85,14 @@ pub fn setup(init: bool) -> Result<()> {
             bail!(error::ConfigurationExists)
         }
 
-        std::fs::write(&config_path, &CONFIG_FILE_EXAMPLE).map_err(|err| {
+        std::fs::write(&config_path, CONFIG_FILE_EXAMPLE).map_err(|err| {
             error::FileWriteError {
                 path : config_path,
                 msg  : err.to_string()
             }
         })?;
 
-        stdout!("\n✅  Written a sample configuration file. Please edit it and run \"{}\" again", 
+        stdout!("\n✅  Written a sample configuration file. Please edit it and run \"{cmd}\" again", 
             cmd = style("git toolbox setup").bold()
         );
 
@@ -62,7 +100,7 @@ pub fn setup(init: bool) -> Result<()> {
     }
 
     // run the repository configuration
-    Repository::configure().map_err(|err| {
+    let lfs_paths = Repository::configure().map_err(|err| {
         // update the error message
         anyhow!(
             "{err}\n\n⚠️  There were errors. Configuration might be incomplete.",
@@ -70,6 +108,377 @@ pub fn setup(init: bool) -> Result<()> {
         )
     })?;
 
+    // the folders of the dictionaries with many entries get more levels
+    raise_prefix_depths()?;
+
     stdout!("\n✅  Configuration succesfully updated");
+
+    // the files taken over from Git LFS need to be split into the managed folders
+    if !lfs_paths.is_empty() {
+        stdout!("\nStaging the dictionaries previously tracked by Git LFS ...\n");
+
+        let workdir = Repository::workdir_for_repo_here()?;
+        let paths = lfs_paths.iter().map(|path| {
+            workdir.join(path).to_string_lossy().into_owned()
+        })
+        .collect();
+
+        crate::stage::stage(paths, false, None, vec!(), Default::default())?;
+    }
+
+    if let Some((dir, path)) = adopt {
+        adopt_records(dir, path)?;
+    }
+
+    Ok( () )
+}
+
+
+// Raise the prefix depth of the dictionaries whose folders would hold more than `max-files-per-dir` entries
+//
+// The depth is written to the configuration file, so that the path of an entry only depends on 
+// its ID (or label) and every clone stores it at the same path. The depth is never lowered, which
+// would move all the entries again
+fn raise_prefix_depths() -> Result<()> {
+    let repo = Repository::open()?;
+
+    let config_path = repo.workdir()?.join(CONFIG_FILE);
+    let mut text = std::fs::read_to_string(&config_path).map_err(|err| {
+        error::FileReadError {
+            path : config_path.clone(),
+            msg  : err.to_string()
+        }
+    })?;
+
+    let mut raised = vec!();
+    for cfg in repo.config().dictionaries.iter() {
+        let max_files = match cfg.max_files_per_dir {
+            Some(max_files) => max_files,
+            None            => continue
+        };
+        // nothing to store yet
+        if !repo.workdir()?.join(&cfg.path).exists() { continue }
+
+        let depth = Dictionary::load(&repo, cfg, false)?.required_prefix_depth(max_files);
+        if depth <= cfg.prefix_depth { continue }
+
+        match set_prefix_depth_in_config(&text, &cfg.path, depth) {
+            (_, 0) => {
+                stdout!("{} The folders of {} hold more than {} entries, set {} to {} in {}",
+                    style("⚠️").yellow(),
+                    style(&cfg.path).bold(),
+                    max_files,
+                    style("prefix-depth").bold(),
+                    depth,
+                    style(CONFIG_FILE).italic()
+                );
+            },
+            (updated, _) => {
+                text = updated;
+                raised.push((cfg.path.clone(), depth));
+            }
+        }
+    }
+
+    if raised.is_empty() {
+        return Ok( () )
+    }
+
+    repo.update_config_file(&text)?;
+    for (path, depth) in raised {
+        stdout!("{} The entries of {} are stored {} folder levels deep (max-files-per-dir)", 
+            style("✓").green(),
+            style(&path).bold(),
+            depth
+        );
+    }
+    stdout!("  (use {} to move the entries to the new folders)", style("git toolbox stage").bold());
+
+    Ok( () )
+}
+
+
+/// Remove git-toolbox management from the managed files (all managed files if none are given)
+///
+/// The managed files are staged as ordinary files with their reconstructed contents
+pub fn uninstall(files: Vec<String>, remove_contents: bool) -> Result<()> {
+    let mut repo = Repository::open()?;
+
+    let all = files.is_empty();
+    let dictionaries = if all {
+        repo.config().dictionaries.clone()
+    } else {
+        files.iter().map(|path| -> Result<DictionaryConfig> {
+            let path = repo.get_path_relative_to_repo(path)?.to_string_lossy().into_owned();
+
+            Ok( repo.config().dictionary_by_path(path)?.clone() )
+        })
+        .collect::<Result<Vec<_>>>()?
+    };
+
+    // no other git-toolbox operations while we are modifying the index
+    let lock = repo.lock_operation("setup")?;
+
+    // the dictionaries are removed from the configuration file unless all of them are uninstalled
+    let config_text = if all {
+        None
+    } else {
+        let path = repo.workdir()?.join(CONFIG_FILE);
+        let text = std::fs::read_to_string(&path).map_err(|err| {
+            error::FileReadError {
+                path,
+                msg  : err.to_string()
+            }
+        })?;
+
+        Some( dictionaries.iter().try_fold(text, |text, cfg| -> Result<String> {
+            match remove_dictionary_from_config(&text, &cfg.path) {
+                (_, 0)    => bail!(error::PathPatternDictionary { path : cfg.path.clone() }),
+                (text, _) => Ok( text )
+            }
+        })? )
+    };
+
+    let contents = dictionaries.iter().map(|cfg| staged_contents(&repo, cfg)).collect::<Result<Vec<_>>>()?;
+
+    for (cfg, data) in dictionaries.iter().zip(contents) {
+        repo.unmanage_file(&cfg.path, Some(&data), remove_contents)?;
+
+        stdout!("{} {} is now an ordinary file{}", 
+            style("✓").green(), 
+            style(&cfg.path).bold(),
+            if remove_contents { format!(" ({}.contents was removed)", cfg.path) } else { "".to_owned() }
+        );
+    }
+
+    let paths = dictionaries.iter().map(|cfg| cfg.path.clone()).collect::<Vec<_>>();
+    repo.unconfigure(&paths, config_text.as_deref(), all)?;
+
+    drop(lock);
+
+    stdout!("\n✅  git-toolbox was removed from {} managed files. Use {} to record the change",
+        paths.len(),
+        style("\"git commit\"").bold()
+    );
+    if all {
+        stdout!("The configuration file {} is no longer used and can be deleted", style(CONFIG_FILE).bold());
+    }
+
+    Ok( () )
+}
+
+/// The staged contents of a managed file (reconstructed from the index)
+///
+/// It is an error if the managed file has unstaged changes, since they would be lost
+pub fn staged_contents(repo: &Repository, cfg: &DictionaryConfig) -> Result<Vec<u8>> {
+    let path = repo.workdir()?.join(&cfg.path);
+    let text = std::fs::read(&path).ok().map(|data| cfg.decode_text(&path, data)).transpose()?;
+
+    if text.map(|text| cfg.matches_content(&text)).unwrap_or(false) {
+        let dictionary = Dictionary::load(repo, cfg, false)?;
+        let (clobs, _) = dictionary.split();
+
+        if !repo.diff_clobs_at_path(format!("{}.contents", cfg.path), clobs)?.is_empty() {
+            bail!(
+                error::UninstallWithUnstagedChanges {
+                    path : cfg.path.clone()
+                }
+            );
+        }
+    }
+
+    let data = Repository::reconstruct(format!("{}.contents", cfg.path), "", &cfg.sort)?;
+
+    cfg.encode_text(cfg.space_records(cfg.adapt_header(data)))
+}
+
+
+// The records of a per-record directory
+struct AdoptedRecords {
+    // the header line (if one of the files starts with it)
+    header  : Option<String>,
+    // the text of the files with records (in the order of their paths)
+    records : Vec<String>,
+    // the adopted files (relative to the directory)
+    files   : Vec<String>,
+    // the files without records (relative to the directory)
+    skipped : Vec<String>
+}
+
+// Take over the records of a per-record directory into the managed file
+fn adopt_records(dir: String, path: String) -> Result<()> {
+    let repo = Repository::open()?;
+
+    let path = repo.get_path_relative_to_repo(&path)?.to_string_lossy().into_owned();
+    let cfg = repo.config().dictionary_by_path(&path)?.clone();
+    let contents_path = format!("{}.contents", cfg.path);
+
+    // the managed file must not have any contents yet
+    let absolute_path = repo.workdir()?.join(&cfg.path);
+    if let Ok(data) = std::fs::read(&absolute_path) {
+        if cfg.matches_content(&String::from_utf8_lossy(&data)) {
+            bail!(
+                error::AdoptTargetHasContents {
+                    path : cfg.path.clone()
+                }
+            );
+        }
+    }
+
+    let dir_path = crate::util::absolute_path(&dir);
+    if !dir_path.is_dir() {
+        bail!(
+            error::FileNotFound {
+                path : dir_path
+            }
+        );
+    }
+
+    let adopted = read_records(&cfg, &dir_path)?;
+    if adopted.records.is_empty() {
+        bail!(
+            error::NoRecordsToAdopt {
+                path : dir_path
+            }
+        );
+    }
+
+    // assemble the managed file in the order it is reconstructed in
+    let header = adopted.header.clone().unwrap_or_else(|| String::from_utf8_lossy(DICTIONARY_HEADER).into_owned());
+    let text = format!("{}\n{}\n", header.trim_end(), adopted.records.join("\n\n"));
+    let clobs = Dictionary::from_text(&cfg, text).split().0.collect::<Vec<Clob>>();
+    let entry_files = clobs.iter().filter(|clob| clob.path != HEADER_CLOB).count();
+    let content = cfg.encode_text(cfg.space_records(cfg.adapt_header(assemble_clobs(clobs, &cfg.sort))))?;
+
+    if let Some(parent) = absolute_path.parent() {
+        std::fs::create_dir_all(parent).map_err(|err| {
+            error::FileWriteError {
+                path : absolute_path.clone(),
+                msg  : err.to_string()
+            }
+        })?;
+    }
+    write_managed_file(&absolute_path, &content).map_err(|err| {
+        error::FileWriteError {
+            path : absolute_path.clone(),
+            msg  : err.to_string()
+        }
+    })?;
+
+    for file in adopted.skipped.iter() {
+        stdout!("  {} {} contains no records and was not adopted", style("!").yellow(), file);
+    }
+
+    // the records are moved to the new layout, so the clobs of the old one are all deleted
+    let options = crate::stage::StageOptions {
+        discard_workdir_changes : true,
+        allow_mass_deletion     : true,
+        ..Default::default()
+    };
+    crate::stage::stage(vec!(absolute_path.to_string_lossy().into_owned()), false, None, vec!(), options)?;
+
+    // the adopted files are now in the managed folder (unless they are the managed folder)
+    let dir_in_repo = repo.get_path_relative_to_repo(&dir_path).ok().map(|dir| dir.to_string_lossy().into_owned());
+    let moved = match &dir_in_repo {
+        Some(dir) if *dir == contents_path || dir.starts_with(&format!("{}/", contents_path)) => false,
+        Some(_)  => true,
+        None     => false
+    };
+    if let (true, Some(dir)) = (moved, &dir_in_repo) {
+        let files = adopted.files.iter().map(|file| {
+            if dir.is_empty() { file.clone() } else { format!("{}/{}", dir, file) }
+        })
+        .collect::<Vec<_>>();
+
+        Repository::open()?.remove_adopted_files(&files)?;
+    }
+
+    stdout!("\n✅  Adopted {} records from {} into {} ({} entry files)",
+        adopted.records.len(),
+        style(&dir).bold(),
+        style(&cfg.path).bold(),
+        entry_files
+    );
+    if moved {
+        stdout!("The adopted files were removed, use {} to record the move", style("\"git commit\"").bold());
+    }
+
+    Ok( () )
+}
+
+// Write the assembled managed file
+//
+// The file is staged right away, and git would not trust the file stats of the index entry 
+// if the file was modified in the same second as the index (it would run the filter again 
+// on the next commit). The modification time is set back to avoid this
+fn write_managed_file(path: &std::path::Path, content: &[u8]) -> std::io::Result<()> {
+    use std::time::{Duration, SystemTime};
+
+    std::fs::write(path, content)?;
+
+    let file = std::fs::File::options().write(true).open(path)?;
+    file.set_modified(SystemTime::now() - Duration::from_secs(2))
+}
+
+// Read the records of the directory (in the order of their paths)
+fn read_records(cfg: &DictionaryConfig, dir: &std::path::Path) -> Result<AdoptedRecords> {
+    let mut paths = vec!();
+    list_files(dir, "", &mut paths)?;
+    paths.sort_by(|a, b| crate::repository::compare_clob_paths(&cfg.sort, a, b));
+
+    let mut adopted = AdoptedRecords { header : None, records : vec!(), files : vec!(), skipped : vec!() };
+    for path in paths {
+        let full_path = dir.join(&path);
+        let data = std::fs::read(&full_path).map_err(|err| {
+            error::FileReadError {
+                path : full_path.clone(),
+                msg  : err.to_string()
+            }
+        })?;
+        let text = cfg.decode_text(&full_path, data)?;
+
+        // the header line is taken from the first file that starts with one
+        let mut text = text.trim();
+        if text.starts_with("\\_sh ") {
+            let (header, rest) = text.split_once('\n').unwrap_or((text, ""));
+            adopted.header.get_or_insert_with(|| header.trim_end().to_owned());
+            text = rest.trim();
+        }
+
+        if cfg.matches_content(text) {
+            adopted.records.push(text.to_owned());
+        } else if !text.is_empty() {
+            adopted.skipped.push(path.clone());
+            continue;
+        }
+
+        adopted.files.push(path);
+    }
+
+    Ok( adopted )
+}
+
+// List the files in the directory recursively (relative to it), skipping the hidden files
+fn list_files(root: &std::path::Path, prefix: &str, paths: &mut Vec<String>) -> Result<()> {
+    let dir = root.join(prefix);
+    let entries = std::fs::read_dir(&dir).map_err(|err| {
+        error::FileReadError {
+            path : dir.clone(),
+            msg  : err.to_string()
+        }
+    })?;
+
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') || name == MANIFEST_FILE { continue }
+
+        let path = if prefix.is_empty() { name } else { format!("{}/{}", prefix, name) };
+        if entry.path().is_dir() {
+            list_files(root, &path, paths)?;
+        } else {
+            paths.push(path);
+        }
+    }
+
     Ok( () )
 }
\ No newline at end of file
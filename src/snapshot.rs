@@ -0,0 +1,77 @@
+//
+// src/snapshot.rs 
+//
+// Implementation of git-toolbox snapshot push
+//
+// Records the reconstructed managed files as plain text on a dedicated branch
+// and pushes it to a remote. This is meant to be run on a regular basis 
+// (e.g. from a scheduled job) to maintain a backup that can be read without 
+// git-toolbox.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::repository::{Repository, SNAPSHOT_BRANCH};
+use crate::cli_app::style;
+
+use crate::error;
+use anyhow::{Result, bail};
+
+// git config keys for the snapshot defaults
+const SNAPSHOT_REMOTE_KEY : &str = "toolbox.snapshotRemote";
+const SNAPSHOT_BRANCH_KEY : &str = "toolbox.snapshotBranch";
+
+
+pub fn push(remote: Option<String>, branch: Option<String>, rev: Option<String>) -> Result<()> {
+    // load the repository
+    let repo = Repository::open()?;
+
+    // no other git-toolbox operations while we are modifying the repository
+    let _lock = repo.lock_operation("snapshot push")?;
+
+    // the remote and the branch can be configured permanently via git config
+    let remote = match remote.or_else(|| repo.git_config_value(SNAPSHOT_REMOTE_KEY)) {
+        Some(remote) => remote,
+        None         => bail!(error::NoSnapshotRemote)
+    };
+    let branch = branch
+        .or_else(|| repo.git_config_value(SNAPSHOT_BRANCH_KEY))
+        .unwrap_or_else(|| SNAPSHOT_BRANCH.to_owned());
+    let rev = rev.unwrap_or_else(|| "HEAD".to_owned());
+
+    // record the snapshot
+    match repo.create_snapshot(&rev, &branch)? {
+        Some(snapshot) => {
+            stdout!("{} Recorded a snapshot of {} on branch {} ({} dictionaries)",
+                style("✓").green(),
+                &snapshot.source[..8],
+                style(&branch).italic(),
+                snapshot.paths.len()
+            );
+
+            repo.record_audit_entry(
+                "snapshot", 
+                &snapshot.paths.join(","), 
+                Some(snapshot.commit), 
+                &format!("source={} branch={}", snapshot.source, branch)
+            )?;
+        },
+        None => {
+            stdout!("{} The snapshot on branch {} is up to date", 
+                style("✓").green(), 
+                style(&branch).italic()
+            );
+        }
+    }
+
+    // and push it
+    repo.push_snapshot(&remote, &branch)?;
+
+    stdout!("\n✅ Pushed the snapshot branch {} to {}", 
+        style(&branch).bold(), 
+        style(&remote).bold()
+    );
+
+    Ok( () )
+}
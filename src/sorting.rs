@@ -0,0 +1,150 @@
+//
+// src/sorting.rs
+//
+// The order of the clobs (and thus of the records of a reconstructed file)
+//
+// A managed file is reconstructed by joining its clobs in the order of their
+// paths, the same order is used whenever clobs or records are listed (e.g. in
+// diffs and in the filter report). The order is configured per dictionary:
+//
+//   sort = "natural"                          # the default, "a2" before "a10"
+//   sort = "codepoint"                        # plain string comparison
+//   sort = { collation = ["a", "b", "ch"] }   # the alphabet of the language
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use serde::Deserialize;
+use std::cmp::Ordering;
+
+
+/// How the clob paths are ordered
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, smart_default::SmartDefault)]
+#[serde(rename_all="kebab-case")]
+pub enum SortPolicy {
+    /// Natural order (numbers are compared by their value)
+    #[default]
+    Natural,
+    /// Order of the Unicode code points
+    Codepoint,
+    /// Order of the letters of an alphabet
+    Collation(Collation)
+}
+
+impl SortPolicy {
+    /// Compare two strings
+    pub fn compare(&self, a: &str, b: &str) -> Ordering {
+        match self {
+            SortPolicy::Natural              => alphanumeric_sort::compare_str(a, b),
+            SortPolicy::Codepoint            => a.cmp(b),
+            SortPolicy::Collation(collation) => collation.compare(a, b)
+        }
+    }
+}
+
+
+/// An alphabet, with letters that can consist of several characters (e.g. "ch")
+///
+/// Letters are matched regardless of case, the characters that are not part of the
+/// alphabet are sorted after all of its letters
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(from = "Vec<String>")]
+pub struct Collation {
+    // the letters with their positions in the alphabet (the longest letters first)
+    letters : Vec<(String, u32)>
+}
+
+impl From<Vec<String>> for Collation {
+    fn from(alphabet: Vec<String>) -> Self {
+        let mut letters = alphabet.into_iter()
+            .filter(|letter| !letter.is_empty())
+            .enumerate()
+            .map(|(i, letter)| (letter.to_lowercase(), i as u32))
+            .collect::<Vec<_>>();
+        letters.sort_by_key(|(letter, _)| std::cmp::Reverse(letter.chars().count()));
+
+        Collation { letters }
+    }
+}
+
+impl Collation {
+    /// Compare two strings by the positions of their letters (ties are broken by code points)
+    pub fn compare(&self, a: &str, b: &str) -> Ordering {
+        self.sort_key(a).cmp(&self.sort_key(b)).then_with(|| a.cmp(b))
+    }
+
+    // the positions of the letters of the text
+    fn sort_key(&self, text: &str) -> Vec<u32> {
+        let text = text.to_lowercase();
+        let unknown = self.letters.len() as u32;
+
+        let mut key = vec!();
+        let mut rest = text.as_str();
+
+        while let Some(c) = rest.chars().next() {
+            match self.letters.iter().find(|(letter, _)| rest.starts_with(letter.as_str())) {
+                Some((letter, position)) => {
+                    key.push(*position);
+                    rest = &rest[letter.len() ..];
+                },
+                None => {
+                    key.push(unknown + c as u32);
+                    rest = &rest[c.len_utf8() ..];
+                }
+            }
+        }
+
+        key
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{Collation, SortPolicy};
+
+    fn sorted(policy: &SortPolicy, words: &[&str]) -> Vec<String> {
+        let mut words = words.iter().map(|word| word.to_string()).collect::<Vec<_>>();
+        words.sort_by(|a, b| policy.compare(a, b));
+
+        words
+    }
+
+    fn collation(alphabet: &[&str]) -> SortPolicy {
+        SortPolicy::Collation(Collation::from(alphabet.iter().map(|letter| letter.to_string()).collect::<Vec<_>>()))
+    }
+
+    #[test]
+    fn test_collation() {
+        let spanish = collation(&["a", "c", "ch", "d", "n", "ñ", "o"]);
+
+        // multi-character letters are matched first
+        assert_eq!(sorted(&spanish, &["chorro", "coco", "dado"]), vec!("coco", "chorro", "dado"));
+        assert_eq!(sorted(&spanish, &["ño", "no", "oca"]), vec!("no", "ño", "oca"));
+
+        // the letters are matched regardless of case, ties are broken by code points
+        assert_eq!(sorted(&spanish, &["Chad", "cosa", "chad"]), vec!("cosa", "Chad", "chad"));
+
+        // the characters that are not part of the alphabet come last (in code point order)
+        assert_eq!(sorted(&spanish, &["ax", "ab", "ao"]), vec!("ao", "ab", "ax"));
+
+        // a prefix comes before the longer words
+        assert_eq!(sorted(&spanish, &["coca", "co"]), vec!("co", "coca"));
+    }
+
+    #[test]
+    fn test_collation_from_config() {
+        let config : std::collections::HashMap<String, SortPolicy> = toml::from_str(
+            "natural = \"natural\"\ncodepoint = \"codepoint\"\nalphabet = { collation = [\"b\", \"a\", \"\"] }"
+        ).unwrap();
+
+        assert_eq!(config["natural"], SortPolicy::Natural);
+        assert_eq!(sorted(&config["codepoint"], &["a10", "a2"]), vec!("a10", "a2"));
+        assert_eq!(sorted(&config["natural"], &["a10", "a2"]), vec!("a2", "a10"));
+
+        // empty letters are ignored
+        assert_eq!(config["alphabet"], collation(&["b", "a"]));
+        assert_eq!(sorted(&config["alphabet"], &["ab", "ba"]), vec!("ba", "ab"));
+    }
+}
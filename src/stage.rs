@@ -7,16 +7,22 @@
 //
 // This code is licensed under GPL 3.0
 
-use crate::repository::{Repository, ClobDiff, ClobValidationIssue};
-use crate::toolbox::{Dictionary, ToolboxFileIssue};
-use crate::config::DictionaryConfig;
+use crate::repository::{Repository, ClobDiff, ClobValidationIssue, DiffStats, FileFormat};
+use crate::toolbox::{Dictionary, ToolboxFileIssue, ClobOrigin, clob_paths_for_id, validator};
+use crate::config::{DictionaryConfig, UserRole};
 use itertools::{Itertools, Either};
-use crate::cli_app::style;
+use crate::cli_app::{style, ascii_output};
+use crate::profile::{self, Phase};
+use crate::util::truncate_text;
+use crate::output::{self, Report};
+use crate::status::issue_line;
+use serde::Serialize;
 
 use crate::error;
 use anyhow::{Result, bail};
 
 const MAX_TO_SHOW: usize = 8;
+const MAX_FILENAME_WIDTH: usize = 60;
 
 struct StagedFileSummary {
     // managed file name for displaying (relative to current folder)
@@ -25,19 +31,58 @@ struct StagedFileSummary {
     pub path          : String, 
     // path to the managed content
     pub contents_path : String,
+    // the managed folder was missing from the working directory and has been restored
+    pub contents_restored : bool,
     // the unstaged diff
     pub unstaged_diff : Vec<ClobDiff>,
     // externally modified files
     pub workdir_issues : Vec<ClobValidationIssue>,
     // toolbox contents issues
-    pub toolbox_issues : Vec<ToolboxFileIssue>
+    pub toolbox_issues : Vec<ToolboxFileIssue>,
+    // the line endings and the byte order mark of the file
+    pub format        : FileFormat
 }
 
+/// The options of git toolbox stage
+#[derive(Debug, Clone, Default)]
+pub struct StageOptions {
+    /// Overwrite the external changes to the managed folders
+    pub discard_workdir_changes : bool,
+    /// Sign the staging manifest
+    pub sign                    : bool,
+    /// Discard the external changes even if this deletes many records
+    pub allow_mass_deletion     : bool,
+    /// Do not stage if the dictionaries have issues with the severity error
+    pub strict                  : bool
+}
+
+
+pub fn stage(
+    paths: Vec<String>, 
+    verbose: bool, 
+    namespace: Option<String>,
+    records: Vec<String>,
+    options: StageOptions
+) -> Result<()> {
+    let StageOptions { discard_workdir_changes, sign, allow_mass_deletion, strict } = options;
 
-pub fn stage(paths: Vec<String>, verbose: bool, discard_workdir_changes: bool) -> Result<()> {
     // load the repository
     let mut repo = Repository::open()?;
 
+    // no other git-toolbox operations while we are modifying the repository
+    let _lock = repo.lock_operation("stage")?;
+
+    // git refuses to add the managed files once their placeholder entries lost the 
+    // assume-unchanged mark (e.g. after "git update-index --really-refresh")
+    let unmarked = repo.unmarked_managed_files();
+    repo.mark_managed_files(unmarked)?;
+
+    // signing can be requested permanently via git config
+    let sign = sign || repo.signing_requested();
+
+    // the strict mode can be required by the configuration
+    let strict = strict || repo.config().strict_staging;
+
     // dictionary selection
     let dictionaries : Vec<&DictionaryConfig> = if paths.is_empty() {
         repo.config().dictionaries.iter().collect()
@@ -53,7 +98,7 @@ pub fn stage(paths: Vec<String>, verbose: bool, discard_workdir_changes: bool) -
 
     // process on the requested files
     let (summaries, errors) : (Vec<_>, Vec<_>) = dictionaries.into_iter().map(|cfg| {
-        StagedFileSummary::new(&repo, cfg)
+        StagedFileSummary::new(&repo, cfg, namespace.as_deref(), &records)
     })
     // split off and collect sucesses and failures
     .partition_map(|result| -> Either<_, anyhow::Error> {
@@ -74,6 +119,11 @@ pub fn stage(paths: Vec<String>, verbose: bool, discard_workdir_changes: bool) -
         );
     }
 
+    // the managed folders that had to be regenerated
+    for summary in summaries.iter() {
+        summary.display_restored_contents();
+    }
+
     // check for external modifications in the working directory
     let any_workdir_issues = summaries.iter().any(StagedFileSummary::any_workdir_issues);
 
@@ -108,69 +158,318 @@ pub fn stage(paths: Vec<String>, verbose: bool, discard_workdir_changes: bool) -
         );
     }
         
-    // check if there is anythign to do
+    // check if there is anythign to do (only the line endings or the byte order marks might 
+    // have changed)
     if !summaries.iter().any(StagedFileSummary::any_unstaged) {
-        stdout!("✅ No changes detected.");
-        return Ok( () )
+        record_file_formats(&repo, &summaries)?;
+
+        return output::emit(&StageReport::new(&summaries, false, any_workdir_issues));
     }
 
     for summary in summaries.iter() {
         summary.display_unstaged_diff(verbose);
     }
 
+    // the users may only stage the records in their namespace
+    check_namespaces(&repo, &summaries)?;
+
+    // in the strict mode, the issues with the severity error prevent staging
+    if strict {
+        check_issue_severities(&summaries, verbose)?;
+    }
+
+    // discarding the external changes of a damaged file could delete most of its records
+    if discard_workdir_changes && !allow_mass_deletion {
+        check_record_deletions(&repo, &summaries, &paths)?;
+    }
+
+    // the command that stages the same changes again (if the staging is interrupted)
+    let mut command = vec!("stage".to_owned());
+    if discard_workdir_changes { command.push("--discard-external-changes".to_owned()); }
+    if sign { command.push("--sign".to_owned()); }
+    if let Some(namespace) = namespace.as_ref() { command.extend(vec!("--namespace".to_owned(), namespace.clone())); }
+    for record in records.iter() { command.extend(vec!("--record".to_owned(), record.clone())); }
+    if allow_mass_deletion { command.push("--allow-mass-deletion".to_owned()); }
+    if strict { command.push("--strict".to_owned()); }
+    command.extend(summaries.iter().map(|summary| summary.path.clone()));
+
+    let files = summaries.iter().map(|summary| summary.path.clone()).collect::<Vec<_>>();
+    let journal = repo.begin_journal("stage", command, &files, false)?;
+
     // apply the changes
-    if let Err(err) = stage_changes(&mut repo, &summaries) {
+    //
+    // the index is written before the record manifest is updated, the manifest is signed
+    // and the audit entry is recorded, so a failure in one of these steps is rolled back
+    // (nothing is staged unless all of them succeed)
+    if let Err(err) = stage_changes(&mut repo, &summaries, sign) {
+        if journal.rollback(&repo).is_ok() {
+            bail!("\n{}\n\n⚠️  There were critical issues, aborting. Nothing added to be commited.", err)
+        }
+
         bail!(concat!(
                 "\n{}\n\n",
                 "⚠️  There were critical issues, aborting. Nothing added to be commited,",
-                "contents of the managed folders might have changed."
+                "contents of the managed folders might have changed.\n",
+                "      (use \"{}\" to undo the changes)"
             ),
-            err
+            err,
+            style("git toolbox recover --rollback").bold()
         )        
     };
 
-    // print the toolbox issues
-    let issue_count = summaries.iter().fold(0, |sum, summary| {
-        sum + summary.toolbox_issues.len()
-    });
+    journal.complete()?;
 
+    // print the toolbox issues
     for summary in summaries.iter() {
         summary.display_toolbox_issues(verbose);
     }
 
-    // print the final summary
+    output::emit(&StageReport::new(&summaries, sign, any_workdir_issues))
+}
+
+
+/// The results of git toolbox stage
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct StageReport {
+    /// The managed files
+    files : Vec<StagedFile>,
+    /// Some managed files were externally modified
+    externally_modified : bool
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct StagedFile {
+    /// The managed file (relative to the repository)
+    path   : String,
+    /// The staged changes of the entry files
+    stats  : DiffStats,
+    /// The staging manifest was signed
+    signed : bool,
+    /// The issues in the toolbox file
+    issues : Vec<ToolboxFileIssue>
+}
+
+impl StageReport {
+    fn new(summaries: &[StagedFileSummary], sign: bool, externally_modified: bool) -> Self {
+        let files = summaries.iter().map(|summary| {
+            StagedFile {
+                path   : summary.path.clone(),
+                stats  : DiffStats::count(&summary.unstaged_diff),
+                signed : sign && summary.any_unstaged(),
+                issues : summary.toolbox_issues.clone()
+            }
+        })
+        .collect();
+
+        StageReport { files, externally_modified }
+    }
+
+    fn staged_files(&self) -> impl Iterator<Item=&StagedFile> {
+        self.files.iter().filter(|file| file.stats.added + file.stats.changed + file.stats.deleted > 0)
+    }
+}
+
+impl Report for StageReport {
+    fn display(&self) {
+        if self.staged_files().count() == 0 {
+            stdout!("✅ No changes detected.");
+            return
+        }
+
+        let issue_count = self.files.iter().fold(0, |sum, file| sum + file.issues.len());
+
+        // print the final summary
+        stdout!("");
+
+        stdout!("\n✅ Added {} managed toolbox dictionaries to be commited.", self.staged_files().count());
+
+        stdout!("");
+
+        if issue_count != 0 {
+            stdout!(concat!(            
+                    "⚠️  There were {} issues in toolbox dictionaries!",
+                    " Please check the list above and/or run {}."
+                ),
+                issue_count, 
+                style("git status --verbose").bold()
+            );
+        }
+
+        if self.externally_modified {
+            stdout!("⚠️  Some managed files were externally modified.");
+        }
+    }
+
+    /// The lines are:
+    ///
+    /// - `staged <managed file> <added> <modified> <deleted>` (for the files with staged changes)
+    /// - `issue <managed file> <error|warning|info> <kind> <line> <message>`
+    fn porcelain(&self) -> Vec<Vec<String>> {
+        let mut lines = vec!();
+
+        for file in self.staged_files() {
+            lines.push(vec!(
+                "staged".to_owned(),
+                file.path.clone(),
+                file.stats.added.to_string(),
+                file.stats.changed.to_string(),
+                file.stats.deleted.to_string()
+            ));
+        }
+        for file in self.files.iter() {
+            lines.extend(file.issues.iter().map(|issue| issue_line(&file.path, None, issue)));
+        }
+
+        lines
+    }
+}
+
+// helper to check that the dictionaries have no issues with the severity error (strict mode)
+fn check_issue_severities(summaries: &[StagedFileSummary], verbose: bool) -> Result<()> {
+    use crate::toolbox::Severity;
+
+    let count = summaries.iter()
+        .flat_map(|summary| summary.toolbox_issues.iter())
+        .filter(|issue| issue.severity() == Severity::Error)
+        .count();
+
+    if count == 0 {
+        return Ok( () );
+    }
+
+    for summary in summaries.iter() {
+        summary.display_toolbox_issues(verbose);
+    }
     stdout!("");
 
-    stdout!("\n✅ Added {} managed toolbox dictionaries to be commited.", 
-        summaries.iter().filter(|s| s.any_unstaged()).count()
+    bail!(
+        error::IssuesPreventStaging {
+            count
+        }
     );
+}
 
-    stdout!("");
+// helper to check that the user only stages the records in their namespace
+//
+// The managers and the users that are not configured can stage all the records. The public 
+// records (and the header and quarantined records, which are shared) can only be staged by 
+// the users approved for them. Dictionaries without IDs have no namespaces
+fn check_namespaces(repo: &Repository, summaries: &[StagedFileSummary]) -> Result<()> {
+    let user = match repo.current_user() {
+        Some(user) if matches!(user.role, UserRole::User) => user,
+        _ => return Ok( () )
+    };
 
-    if issue_count != 0 {
-        stdout!(concat!(            
-                "⚠️  There were {} issues in toolbox dictionaries!",
-                " Please check the list above and/or run {}."
+    let mut errors = vec!();
+
+    for summary in summaries.iter().filter(|summary| summary.any_unstaged()) {
+        let cfg = repo.config().dictionary_by_path(&summary.path)?;
+        if !cfg.unique_id { continue }
+
+        let records = summary.unstaged_diff.iter().filter_map(|diff| {
+            let origin = ClobOrigin::from_managed_path(&summary.contents_path, diff.path());
+
+            let allowed = match origin {
+                ClobOrigin::Private { namespace, id : _ } => user.namespace.as_deref() == Some(namespace),
+                ClobOrigin::Label { label : _ }           => true,
+                _                                         => user.public
+            };
+
+            if allowed { return None }
+
+            Some(
+                match origin {
+                    ClobOrigin::Public { id } | ClobOrigin::Private { namespace : _, id } => id.to_owned(),
+                    ClobOrigin::Header => "the dictionary header".to_owned(),
+                    ClobOrigin::Preamble => "the lines before the first record".to_owned(),
+                    _                  => format!("{}/{}", origin.namespace(), origin.key())
+                }
+            )
+        })
+        .unique()
+        .collect::<Vec<_>>();
+
+        if !records.is_empty() {
+            errors.push(
+                error::RecordsOutsideNamespace {
+                    user      : user.name.clone(),
+                    namespace : user.namespace.clone(),
+                    path      : summary.display_name.clone().into(),
+                    records
+                }
+            );
+        }
+    }
+
+    if !errors.is_empty() {
+        let hint = match &user.namespace {
+            Some(namespace) => format!(
+                "\n\nUse {} to only stage your own records.", 
+                style(format!("\"git toolbox stage --namespace {}\"", namespace)).bold()
             ),
-            issue_count, 
-            style("git status --verbose").bold()
+            None            => String::new()
+        };
+
+        bail!(
+            "{}{} Nothing added to be commited.", 
+            errors.into_iter().join("\n"),
+            hint
         );
     }
 
-    if any_workdir_issues {
-        stdout!("⚠️  Some managed files were externally modified.");
+    Ok( () )
+}
+
+// helper to check that staging does not delete more records than allowed
+fn check_record_deletions(repo: &Repository, summaries: &[StagedFileSummary], paths: &[String]) -> Result<()> {
+    let mut errors = vec!();
+
+    for summary in summaries.iter().filter(|summary| summary.any_unstaged()) {
+        let cfg = repo.config().dictionary_by_path(&summary.path)?;
+        let deletions = repo.records_deleted_by_staging(
+            &summary.contents_path, &cfg.record_tags, &summary.unstaged_diff
+        )?;
+
+        if deletions.exceeds(cfg.max_deleted_records) {
+            errors.push(
+                error::TooManyRecordsDeleted {
+                    path    : summary.display_name.clone().into(),
+                    deleted : deletions.deleted,
+                    total   : deletions.total,
+                    limit   : cfg.max_deleted_records
+                }
+            );
+        }
     }
 
+    if !errors.is_empty() {
+        let cmd = format!("git toolbox stage --discard-external-changes --allow-mass-deletion {}", paths.join(" "));
 
-    Ok( () )
+        bail!(
+            "{}\n\nUse {} if the records should be deleted. Nothing added to be commited.", 
+            errors.into_iter().join("\n"),
+            style(format!("\"{}\"", cmd.trim_end())).bold()
+        );
+    }
 
+    Ok( () )
 }
 
 // helper to stage the repository
-fn stage_changes(repo: &mut Repository, summaries: &[StagedFileSummary]) -> Result<()> {
+fn stage_changes(repo: &mut Repository, summaries: &[StagedFileSummary], sign: bool) -> Result<()> {
     use indicatif::{ProgressBar, ProgressDrawTarget};
     use console::Term;
 
+    // the placeholder texts for the managed files
+    let placeholders = summaries.iter().map(|summary| {
+        repo.config().dictionary_by_path(&summary.path).map(|cfg| {
+            repo.config().managed_file_text(cfg)
+        })
+    })
+    .collect::<Result<Vec<_>>>()?;
+
     let mut staging_area = repo.get_staging_area()?;
 
     // number of changes to apply
@@ -184,24 +483,31 @@ fn stage_changes(repo: &mut Repository, summaries: &[StagedFileSummary]) -> Resu
 
     pb.set_draw_target(ProgressDrawTarget::to_term(term.clone(), Some(10)));
     
-    pb.set_style(indicatif::ProgressStyle::default_spinner()
-        .template("  {spinner:.cyan/blue} {pos:>7}/{len} changes applied")
+    let spinner = indicatif::ProgressStyle::default_spinner()
+        .template("  {spinner:.cyan/blue} {pos:>7}/{len} changes applied");
+    pb.set_style(
+        if ascii_output() { spinner.tick_chars("|/-\\ ") } else { spinner }
     );
 
     stdout!("Applying changes to the git repository index ...");
 
     // stage the affected toolbox files
     let (mut added, mut modified, mut deleted) = (0, 0, 0);
-    for summary in summaries.iter().filter(|summary| summary.any_unstaged()) {
-        staging_area.stage_managed_file(&summary.path)?;
-        staging_area.stage_diffs(summary.unstaged_diff.iter(), |entry| {
-            match entry {
-                ClobDiff::Add { clob : _}    => added += 1,
-                ClobDiff::Update { clob : _} => modified += 1,
-                ClobDiff::Delete { path : _} => deleted += 1
-            }
+    for (summary, placeholder) in summaries.iter().zip(placeholders.iter()) {
+        if !summary.any_unstaged() { continue }
+
+        profile::measure(&summary.path, Phase::IndexUpdate, || -> Result<()> {
+            staging_area.stage_diffs(summary.unstaged_diff.iter(), |entry| {
+                match entry {
+                    ClobDiff::Add { clob : _}    => added += 1,
+                    ClobDiff::Update { clob : _} => modified += 1,
+                    ClobDiff::Delete { path : _} => deleted += 1
+                }
 
-            pb.inc(1)
+                pb.inc(1)
+            })?;
+            // the placeholder names the staged managed folder
+            staging_area.stage_managed_file(&summary.path, placeholder)
         })?;
     }
 
@@ -221,17 +527,81 @@ fn stage_changes(repo: &mut Repository, summaries: &[StagedFileSummary]) -> Resu
     );
 
     // commit the changes
-    staging_area.commit()
+    staging_area.commit()?;
+
+    // update the record checksums of the staged contents
+    for summary in summaries.iter().filter(|summary| summary.any_unstaged()) {
+        let record_tags = repo.config().dictionary_by_path(&summary.path)?.record_tags.clone();
+
+        repo.update_record_manifest(&summary.contents_path, &record_tags)?;
+    }
+
+    // sign the staged trees and record the operation in the audit log
+    for summary in summaries.iter().filter(|summary| summary.any_unstaged()) {
+        let tree = repo.staged_tree_id(&summary.contents_path)?;
+
+        if let (true, Some(tree)) = (sign, &tree) {
+            repo.sign_staged_tree(&summary.contents_path, tree)?;
+
+            stdout!("{} Signed the staging manifest for {}", 
+                style("✓").green(), 
+                &summary.display_name
+            );
+        }
+
+        let stats = DiffStats::count(&summary.unstaged_diff);
+        repo.record_audit_entry(
+            "stage", 
+            &summary.path, 
+            tree, 
+            &format!("added={} modified={} deleted={} signed={}", 
+                stats.added, stats.changed, stats.deleted, sign
+            )
+        )?;
+    }
+
+    // the formats are only recorded once the changes are staged (they are not rolled back)
+    record_file_formats(repo, summaries)
+}
+
+// The files are reconstructed with the line endings and byte order mark they were staged with
+fn record_file_formats(repo: &Repository, summaries: &[StagedFileSummary]) -> Result<()> {
+    for summary in summaries.iter() {
+        repo.record_file_format(&summary.path, summary.format)?;
+    }
+
+    Ok( () )
 }
 
 
 impl StagedFileSummary {
-    pub fn new(repo :&Repository, cfg: &DictionaryConfig) -> Result<Self> {
+    pub fn new(repo :&Repository, cfg: &DictionaryConfig, namespace: Option<&str>, records: &[String]) -> Result<Self> {
         // the file path
         let path = cfg.path.clone();
 
+        // the clobs of the requested records (if any)
+        //
+        // records can only be selected by ID, so nothing is staged in dictionaries without IDs
+        // (the records of every record type are stored in their own directory)
+        let mut record_paths = vec!();
+        for id in if cfg.unique_id { records } else { &[] }.iter() {
+            let clob_paths = clob_paths_for_id(cfg, id);
+            if clob_paths.is_empty() {
+                bail!(
+                    error::InvalidRecordId {
+                        id   : id.clone(),
+                        path : cfg.path.clone()
+                    }
+                );
+            }
+
+            record_paths.extend(clob_paths.into_iter().map(|clob_path| {
+                format!("{}.contents/{}", cfg.path, clob_path).to_lowercase()
+            }));
+        }
+
         // load and split the dictionary
-        let dictionary = Dictionary::load(&repo, cfg, true)?;
+        let dictionary = Dictionary::load(repo, cfg, true)?;
 
         // obtain the printable relative path to the file
         let display_name = crate::util::get_relative_path(
@@ -239,14 +609,38 @@ impl StagedFileSummary {
         ).display().to_string();
 
         let contents_path = dictionary.contents_root();
-        let (clobs, toolbox_issues) = dictionary.split();
+        let format = FileFormat { crlf : dictionary.text().contains("\r\n"), bom : dictionary.bom() };
+        let (clobs, mut toolbox_issues) = dictionary.split();
+        toolbox_issues.extend(validator::validate(&dictionary));
+        toolbox_issues.sort_by_key(ToolboxFileIssue::line);
+
+        // the acknowledged issues are not reported (and do not prevent strict staging)
+        let (toolbox_issues, _) = repo.issue_baseline()?.partition(&dictionary, toolbox_issues);
+
+        // a deleted managed folder is regenerated from the index before it is validated
+        let contents_restored = repo.restore_missing_managed_folder(&contents_path)?;
 
         // run the validation
-        let workdir_issues = repo.validate_clobs_in_workdir(&contents_path)?;
+        let workdir_issues = profile::measure(&contents_path, Phase::WorkdirValidation, || {
+            repo.validate_clobs_in_workdir(&contents_path, false)
+        })?;
 
         // run the diff 
-        let unstaged_diff = repo.diff_clobs_at_path(&contents_path, clobs)?;
+        let mut unstaged_diff = profile::measure(&contents_path, Phase::Diff, || {
+            repo.diff_clobs_at_path(&contents_path, clobs)
+        })?;
 
+        // only stage the changes in the namespace (if requested)
+        if let Some(namespace) = namespace {
+            unstaged_diff.retain(|diff| {
+                ClobOrigin::from_managed_path(&contents_path, diff.path()).is_in_namespace(namespace)
+            });
+        }
+
+        // only stage the changes to the requested records (if any)
+        if !records.is_empty() {
+            unstaged_diff.retain(|diff| record_paths.contains(&diff.path().to_lowercase()));
+        }
 
         // return the diff and the issues
         Ok( 
@@ -254,14 +648,28 @@ impl StagedFileSummary {
                 display_name,
                 path, 
                 contents_path,
+                contents_restored,
                 unstaged_diff,
                 workdir_issues,
-                toolbox_issues
+                toolbox_issues,
+                format
             }
         )
 
     }
 
+    pub fn display_restored_contents(&self) {
+        if !self.contents_restored { return }
+
+        stdout!("{note}: the managed folder {path} was missing from the working directory.",
+            note = style("note").bold().yellow(),
+            path = style(&self.contents_path).italic()
+        );
+        stdout!("  (it was restored from the git index before staging {})\n",
+            style(&self.display_name).italic()
+        );
+    }
+
     pub fn any_workdir_issues(&self) -> bool {
         !self.workdir_issues.is_empty()
     }
@@ -309,7 +717,7 @@ impl StagedFileSummary {
         stdout!("\n  {}:\n", style(&self.display_name).italic());
         let to_show = if verbose { self.unstaged_diff.len() } else { MAX_TO_SHOW };
         for e in self.unstaged_diff.iter().take(to_show) {
-            stdout!("        {} {}", e.display_diff_marker(), e.filename());
+            stdout!("        {} {}", e.display_diff_marker(), truncate_text(e.filename(), MAX_FILENAME_WIDTH));
         }
         if to_show < self.unstaged_diff.len() {
             stdout!("        ...");
@@ -366,7 +774,7 @@ impl StagedFileSummary {
                 },
                 InvalidPath { path } => {
                     use crate::util::escape_unicode_only;
-;
+
                     stdout!("        {path}: {status}",
                         path = escape_unicode_only(&String::from_utf8_lossy(path)), 
                         status = style("invalid managed file path").red()
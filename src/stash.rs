@@ -0,0 +1,231 @@
+//
+// src/stash.rs
+//
+// Implementation of git-toolbox stash
+//
+// Parks the unstaged changes to the managed toolbox files, so that the working
+// copies can be restored from the index (e.g. to pull or to switch branches)
+// and the changes can be picked up again later. `git stash` cannot be used for
+// this, since it only sees the placeholders of the managed files.
+//
+// The stashed files are recorded as their clob trees (split from the working
+// copy), along with the index they were based on. When the changes are popped,
+// they are merged into the working copy with that index as the common ancestor
+// (as done by reconcile), so that the changes staged or pulled in the meantime
+// are kept.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::repository::{Repository, Clob, assemble_clobs};
+use crate::toolbox::Dictionary;
+use crate::reconcile::{split, read_clobs, merge_clobs};
+use crate::config::DictionaryConfig;
+use crate::cli_app::style;
+use crate::util::{format_time, local_time_offset};
+
+use crate::error;
+use anyhow::{Result, bail};
+
+// the message of the stash entries without a message
+const DEFAULT_MESSAGE : &str = "work in progress";
+
+
+pub fn push(paths: Vec<String>, message: Option<String>) -> Result<()> {
+    // load the repository
+    let repo = Repository::open()?;
+
+    // no other git-toolbox operations while we are modifying the repository
+    let _lock = repo.lock_operation("stash push")?;
+
+    // the dictionaries with unstaged changes
+    let mut files : Vec<(String, Vec<Clob>)> = vec!();
+    for cfg in select_dictionaries(&repo, &paths)? {
+        let dictionary = Dictionary::load(&repo, cfg, false)?;
+        let contents_path = dictionary.contents_root();
+        let clobs = dictionary.split().0.collect::<Vec<_>>();
+
+        if !repo.diff_clobs_at_path(&contents_path, Box::new(clobs.clone().into_iter()))?.is_empty() {
+            files.push((cfg.path.clone(), clobs));
+        }
+    }
+
+    if files.is_empty() {
+        stdout!("No unstaged changes to the managed toolbox files to stash");
+        return Ok( () )
+    }
+
+    let entry = repo.stash_clobs(&files, message.as_deref().unwrap_or(DEFAULT_MESSAGE))?;
+
+    // the working copies are restored from the index
+    for (path, _) in files.iter() {
+        let cfg = repo.config().dictionary_by_path(path)?;
+        let data = Repository::reconstruct(format!("{}.contents", cfg.path), "", &cfg.sort)?;
+
+        write_managed_file(&repo, cfg, data)?;
+        stdout!("{} Stashed the changes to {}", style("✓").green(), style(path).italic());
+    }
+
+    repo.record_audit_entry("stash push", &entry.paths.join(","), Some(entry.commit.clone()), "")?;
+
+    stdout!("");
+    stdout!("  (use {} to restore them)", style("git toolbox stash pop").bold());
+
+    Ok( () )
+}
+
+
+pub fn pop(force: bool) -> Result<()> {
+    // load the repository
+    let repo = Repository::open()?;
+
+    // no other git-toolbox operations while we are modifying the repository
+    let _lock = repo.lock_operation("stash pop")?;
+
+    let entry = match repo.stash_entries()?.into_iter().next() {
+        Some(entry) => entry,
+        None        => bail!(error::NoStashEntries)
+    };
+
+    // the unstaged changes would be lost
+    let dictionaries = entry.paths.iter()
+        .map(|path| repo.config().dictionary_by_path(path))
+        .collect::<Result<Vec<_>>>()?;
+
+    if !force {
+        for cfg in dictionaries.iter() {
+            let dictionary = Dictionary::load(&repo, cfg, false)?;
+            let contents_path = dictionary.contents_root();
+            let (clobs, _) = dictionary.split();
+
+            if !repo.diff_clobs_at_path(&contents_path, clobs)?.is_empty() {
+                bail!(error::StashWouldOverwrite { path : cfg.path.clone() });
+            }
+        }
+    }
+
+    // the stashed changes are merged into the working copies
+    let mut conflicts = vec!();
+    for cfg in dictionaries.iter() {
+        let contents_path = format!("{}.contents", cfg.path);
+
+        let ours = split(&Dictionary::load(&repo, cfg, false)?);
+        let theirs = read_clobs(cfg, &contents_path, &entry.commit)?;
+        // the managed file might not have been staged when the changes were stashed
+        let base = read_clobs(cfg, &contents_path, &entry.base).unwrap_or_default();
+
+        let (clobs, summary) = merge_clobs(cfg, &base, &ours, &theirs);
+        write_managed_file(&repo, cfg, assemble_clobs(clobs, &cfg.sort))?;
+
+        if summary.conflicts > 0 {
+            stdout!("{} Merged the stashed changes to {} with {} conflicting records", 
+                style("⚠️").yellow(), 
+                style(&cfg.path).italic(),
+                summary.conflicts
+            );
+            conflicts.push((cfg.path.clone(), summary.conflicts));
+        } else {
+            stdout!("{} Restored the stashed changes to {}", style("✓").green(), style(&cfg.path).italic());
+        }
+    }
+
+    // the entry is kept until the conflicts are resolved
+    if let Some((path, count)) = conflicts.into_iter().next() {
+        bail!(
+            error::StashConflicts {
+                path,
+                conflicts : count
+            }
+        );
+    }
+
+    repo.drop_stash_entry()?;
+    repo.record_audit_entry("stash pop", &entry.paths.join(","), Some(entry.commit.clone()), "")?;
+
+    stdout!("");
+    stdout!("  (the changes are not staged, use {} to review them)", style("git toolbox status").bold());
+
+    Ok( () )
+}
+
+
+pub fn drop() -> Result<()> {
+    // load the repository
+    let repo = Repository::open()?;
+
+    // no other git-toolbox operations while we are modifying the repository
+    let _lock = repo.lock_operation("stash drop")?;
+
+    let entry = match repo.stash_entries()?.into_iter().next() {
+        Some(entry) => entry,
+        None        => bail!(error::NoStashEntries)
+    };
+
+    repo.drop_stash_entry()?;
+    repo.record_audit_entry("stash drop", &entry.paths.join(","), Some(entry.commit.clone()), "")?;
+
+    stdout!("{} Dropped the stashed changes {} ({})", 
+        style("✓").green(), 
+        style(&entry.message).bold(),
+        &entry.commit[.. 8]
+    );
+
+    Ok( () )
+}
+
+
+pub fn list() -> Result<()> {
+    // load the repository
+    let repo = Repository::open()?;
+
+    let entries = repo.stash_entries()?;
+    if entries.is_empty() {
+        stdout!("No stashed changes");
+        return Ok( () )
+    }
+
+    for (i, entry) in entries.iter().enumerate() {
+        let (seconds, _) = entry.time;
+
+        stdout!("{} {} {} ({})",
+            style(format!("stash@{{{}}}", i)).yellow(),
+            format_time(seconds, local_time_offset()),
+            style(&entry.message).bold(),
+            entry.paths.join(", ")
+        );
+    }
+
+    Ok( () )
+}
+
+
+// The requested dictionaries (all by default)
+fn select_dictionaries<'a>(repo: &'a Repository, paths: &[String]) -> Result<Vec<&'a DictionaryConfig>> {
+    if paths.is_empty() {
+        return Ok( repo.config().dictionaries.iter().collect() )
+    }
+
+    paths.iter().map(|path| {
+        // convert the path to one relative to the repo
+        let path = repo.get_path_relative_to_repo(path)?.to_string_lossy().into_owned();
+
+        repo.config().dictionary_by_path(path)
+    })
+    .collect()
+}
+
+// Write a reconstructed managed file to the working directory
+fn write_managed_file(repo: &Repository, cfg: &DictionaryConfig, data: Vec<u8>) -> Result<()> {
+    let absolute_path = repo.workdir()?.to_owned().join(&cfg.path);
+
+    let data = cfg.encode_text(cfg.space_records(cfg.adapt_header(data)))?;
+    std::fs::write(&absolute_path, data).map_err(|err| {
+        error::FileWriteError {
+            path : absolute_path,
+            msg  : err.to_string()
+        }
+    })?;
+
+    Ok( () )
+}
@@ -3,45 +3,106 @@
 //
 // Implementation of git-toolbox status 
 //
+// The status of the managed files is collected by `toolbox_status()` into 
+// (serializable) summaries, which are written out as a report in the format 
+// selected with --format. 
+//
+// The issues in the toolbox files are compared with the issues in the HEAD 
+// version, so that only the issues introduced by the current edits are 
+// highlighted. 
+//
 // (C) 2020 Taras Zakharko
 //
 // This code is licensed under GPL 3.0
 
-use crate::repository::{Repository, ClobDiff, ClobValidationIssue, DiffStats};
-use crate::toolbox::{Dictionary, ToolboxFileIssue};
+use crate::repository::{Repository, RepositoryHealth, ClobDiff, ClobValidationIssue, ClobCollision, DiffStats, ISSUE_BASELINE_FILE};
+use crate::toolbox::{Dictionary, ToolboxFileIssue, ClobOrigin, validator};
+use crate::toolbox::template::{self, RecordChange};
 use crate::config::DictionaryConfig;
 use crate::cli_app::style;
+use crate::output::{self, Report};
+use crate::profile::{self, Phase};
+use crate::util::{truncate_text, pad_text, text_width, format_time, local_time_offset};
 use itertools::{Itertools, Either};
 
-use anyhow::{Result,bail};
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use std::collections::HashMap;
 
 
 const MAX_TO_SHOW: usize = 8;
-
-struct ManagedFileSummary {
-    // managed file name for displaying (relative to current folder)
-    pub display_name  : String,
-    // path to the managed content
-    pub contents_path : String,
-    // the unstaged diff
-    pub unstaged_diff : Vec<ClobDiff>,
-    // the staged diff
-    pub staged_diff : Vec<ClobDiff>,
-    // externally modified files
+const MAX_FILENAME_WIDTH: usize = 60;
+
+/// The status of a managed toolbox file
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ManagedFileSummary {
+    /// The managed file (relative to the repository)
+    pub path           : String,
+    /// The managed file name for displaying (relative to current folder)
+    pub display_name   : String,
+    /// The path to the managed content
+    pub contents_path  : String,
+    /// The managed folder was missing from the working directory and has been restored
+    pub contents_restored : bool,
+    /// The changes to the managed content not yet staged
+    pub unstaged_diff  : Vec<ClobDiff>,
+    /// The staged changes to the managed content
+    pub staged_diff    : Vec<ClobDiff>,
+    /// The files in the managed folder that were externally modified
     pub workdir_issues : Vec<ClobValidationIssue>,
-    // toolbox contents issues
-    pub toolbox_issues : Vec<ToolboxFileIssue>
+    /// The staged clobs that were edited by hand (their records do not match the manifest)
+    pub hand_edited    : Vec<String>,
+    /// The issues in the toolbox file contents introduced since the last commit
+    pub toolbox_issues : Vec<ToolboxFileIssue>,
+    /// The issues in the toolbox file contents already present in the last commit
+    pub preexisting_issues : Vec<ToolboxFileIssue>,
+    /// The number of issues acknowledged in the issue baseline (not reported)
+    pub acknowledged_issues : usize,
+    /// The record IDs that were also added on a remote-tracking branch (only if requested)
+    pub id_collisions  : Vec<ClobCollision>,
+    /// The record changes in the modified unstaged clobs by path (only if requested)
+    #[serde(skip)]
+    pub unstaged_changes : HashMap<String, Vec<RecordChange>>,
+    /// The record changes in the modified staged clobs by path (only if requested)
+    #[serde(skip)]
+    pub staged_changes : HashMap<String, Vec<RecordChange>>
 }
 
-pub fn status(files: Vec<String>, verbose: bool) -> Result<()> {
-    assert!(files.is_empty());
+/// The managed files to include in the status
+#[derive(Debug, Clone, Default)]
+pub struct StatusFilters {
+    /// The managed files (all managed files if empty)
+    pub paths     : Vec<String>,
+    /// Only include the records in this namespace
+    pub namespace : Option<String>,
+    /// Look for the record IDs that were also added on the remote-tracking branches
+    pub all_branches : bool,
+    /// Describe the fields changed in the modified clobs
+    pub field_changes : bool
+}
 
-    // open the repository
-    let repo = Repository::open()?;
+
+/// Collect the status of the managed toolbox files
+///
+/// The errors encountered with the individual files are combined into one
+pub fn toolbox_status(repo: &Repository, filters: &StatusFilters) -> Result<Vec<ManagedFileSummary>> {
+    // dictionary selection
+    let dictionaries : Vec<&DictionaryConfig> = if filters.paths.is_empty() {
+        repo.config().dictionaries.iter().collect()
+    } else {
+        filters.paths.iter().map(|path| {
+            // convert the path to one relative to the repo
+            let path = repo.get_path_relative_to_repo(path)?.to_string_lossy().into_owned();
+
+            repo.config().dictionary_by_path(path)
+        })
+        .collect::<Result<Vec<_>>>()?
+    };
 
     // process on the requested files
-    let (summaries, errors) : (Vec<_>, Vec<_>) = repo.config().dictionaries.iter().map(|cfg| {
-        ManagedFileSummary::new(&repo, cfg)
+    let (summaries, errors) : (Vec<_>, Vec<_>) = dictionaries.into_iter().map(|cfg| {
+        ManagedFileSummary::new(repo, cfg, filters)
     })
     // split off and collect sucesses and failures
     .partition_map(|result| -> Either<_, anyhow::Error> {
@@ -53,122 +114,361 @@ pub fn status(files: Vec<String>, verbose: bool) -> Result<()> {
     
     if !errors.is_empty() {
         // collect all errors
-        let err_msg = errors.into_iter().join("\n");
-
-        bail!("{}\n⚠️  There were errors. Aborting.", err_msg);
+        return Err( anyhow!("{}", errors.into_iter().join("\n")) )
     }
 
-    stdout!("On branch {}", repo.head_display_name());
+    Ok( summaries )
+}
 
-    // display work directory issues
-    let any_workdir_issues = summaries.iter().any(ManagedFileSummary::any_workdir_issues);
 
-    if any_workdir_issues {
-        stdout!("\n{warning}: some files managed by git-toolbox were externally modified.",
-            warning=style("warning").bold().yellow()
-        );
-        stdout!("  (these changes will be lost if you run {cmd})", 
-            cmd = style("\"git toolbox stage\"").bold()
-        );
-        stdout!("  (if these changes are intended stage them manually using {cmd})",
-            cmd = style("\"git add ...\"").bold()
-        );
+/// The status of the repository, as reported by git toolbox status
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct StatusReport {
+    /// The current branch
+    pub branch    : String,
+    /// The repository health indicators
+    pub health    : RepositoryHealth,
+    /// The namespace the changes are restricted to (if any)
+    pub namespace : Option<String>,
+    /// The status of the managed files
+    pub files     : Vec<ManagedFileSummary>,
+    // the display options
+    #[serde(skip)]
+    verbose       : bool,
+    #[serde(skip)]
+    all_issues    : bool
+}
 
-        stdout!("");
 
+pub fn status(files: Vec<String>, verbose: bool, namespace: Option<String>, all_issues: bool, all_branches: bool) -> Result<()> {
+    // open the repository
+    let repo = Repository::open()?;
+
+    let filters = StatusFilters { paths: files, namespace: namespace.clone(), all_branches, field_changes: verbose };
+
+    let summaries = toolbox_status(&repo, &filters).map_err(|err| {
+        anyhow!("{}\n⚠️  There were errors. Aborting.", err)
+    })?;
+
+    let report = StatusReport {
+        branch : repo.head_display_name(),
+        health : repo.health(),
+        namespace,
+        files  : summaries,
+        verbose,
+        all_issues
+    };
+
+    output::emit(&report)
+}
+
+impl Report for StatusReport {
+    fn display(&self) {
+        let summaries = &self.files;
+        let (verbose, all_issues) = (self.verbose, self.all_issues);
+
+        stdout!("On branch {}", self.branch);
+        display_health(&self.health);
+        if let Some(namespace) = &self.namespace {
+            stdout!("Showing the changes in namespace {}", style(namespace).bold());
+        }
+
+        // the managed folders that had to be regenerated
         for summary in summaries.iter() {
-            summary.display_workdir_issues(verbose);
+            summary.display_restored_contents();
         }
-    }
 
-    // find the width of the file name for formatting 
-    let max_display_path_width = summaries.iter().fold(0, |w, summary| {
-        std::cmp::max(console::measure_text_width(&summary.display_name), w)
-    });
+        // display work directory issues
+        let any_workdir_issues = summaries.iter().any(ManagedFileSummary::any_workdir_issues);
 
+        if any_workdir_issues {
+            stdout!("\n{warning}: some files managed by git-toolbox were externally modified.",
+                warning=style("warning").bold().yellow()
+            );
+            stdout!("  (these changes will be lost if you run {cmd})", 
+                cmd = style("\"git toolbox stage\"").bold()
+            );
+            stdout!("  (if these changes are intended stage them manually using {cmd})",
+                cmd = style("\"git add ...\"").bold()
+            );
+
+            stdout!("");
 
-    // staged diffs
-    let any_staged = summaries.iter().any(ManagedFileSummary::any_staged);
+            for summary in summaries.iter() {
+                summary.display_workdir_issues(verbose);
+            }
+        }
 
-    if any_staged {
-        stdout!("Changes to be commited:");
+        // display the clobs that were edited by hand and staged
+        let any_hand_edited = summaries.iter().any(ManagedFileSummary::any_hand_edited);
+
+        if any_hand_edited {
+            stdout!("\n{warning}: some files managed by git-toolbox were edited by hand and staged.",
+                warning=style("warning").bold().yellow()
+            );
+            stdout!("  (these changes will be carried over into the toolbox files by {cmd} or a checkout)",
+                cmd = style("\"git toolbox reset\"").bold()
+            );
+            stdout!("  (use {cmd} to see the affected records)",
+                cmd = style("\"git toolbox verify\"").bold()
+            );
+
+            stdout!("");
+
+            for summary in summaries.iter() {
+                summary.display_hand_edited(verbose);
+            }
+        }
+
+        // find the width of the file name for formatting 
+        let max_display_path_width = summaries.iter().fold(0, |w, summary| {
+            std::cmp::max(text_width(&summary.display_name), w)
+        });
+
+
+        // staged diffs
+        let any_staged = summaries.iter().any(ManagedFileSummary::any_staged);
+
+        if any_staged {
+            stdout!("Changes to be commited:");
+            stdout!("");
+
+            // display summaries
+            for summary in summaries.iter() {
+                stdout!("        {} : {}", 
+                    style(pad_text(&summary.display_name, max_display_path_width)).green(), 
+                    summary.staged_diff_stats()
+                );
+            }
+
+            // display diffs
+            for summary in summaries.iter() {
+                summary.display_staged_diff(verbose);
+            }
+
+            stdout!("");
+        }
+
+        // Unstaged changes
+        stdout!("Changes not staged for commit:");
+        stdout!(
+            "  (use \"{}\" to stage the Toolbox dictionaries to be commited", 
+            style("\"git toolbox stage\"").bold()
+        );
+        // stdout!(
+        //     "  (use \"{}\" to discard local changes in the Toolbox dictionaries", 
+        //     style("git toolbox reset").bold()
+        // );
         stdout!("");
 
+
         // display summaries
         for summary in summaries.iter() {
-            stdout!("        {:<width$} : {}", 
-                style(&summary.display_name).green(), 
-                summary.staged_diff_stats(), 
-                width=max_display_path_width
+            stdout!("        {} : {}", 
+                pad_text(&summary.display_name, max_display_path_width), 
+                summary.unstaged_diff_stats()
             );
         }
 
         // display diffs
         for summary in summaries.iter() {
-            summary.display_staged_diff(verbose);
+            summary.display_unstaged_diff(verbose);
         }
 
         stdout!("");
-    }
 
-    // Unstaged changes
-    stdout!("Changes not staged for commit:");
-    stdout!(
-        "  (use \"{}\" to stage the Toolbox dictionaries to be commited", 
-        style("\"git toolbox stage\"").bold()
-    );
-    // stdout!(
-    //     "  (use \"{}\" to discard local changes in the Toolbox dictionaries", 
-    //     style("git toolbox reset").bold()
-    // );
-    stdout!("");
-
-
-    // display summaries
-    for summary in summaries.iter() {
-        stdout!("        {:<width$} : {}", 
-            &summary.display_name, 
-            summary.unstaged_diff_stats(), 
-            width=max_display_path_width
-        );
-    }
 
-    // display diffs
-    for summary in summaries.iter() {
-        summary.display_unstaged_diff(verbose);
-    }
+        // display toolbox issues
+        let issue_count = summaries.iter().fold(0, |sum, summary| {
+            sum + summary.toolbox_issues.len()
+        });
+        let preexisting_count = summaries.iter().fold(0, |sum, summary| {
+            sum + summary.preexisting_issues.len()
+        });
+        let acknowledged_count = summaries.iter().fold(0, |sum, summary| {
+            sum + summary.acknowledged_issues
+        });
 
-    stdout!("");
+        for summary in summaries.iter() {
+            summary.display_toolbox_issues(verbose);
+            if all_issues {
+                summary.display_preexisting_issues(verbose);
+            }
+        }
 
+        // record IDs also added on other branches
+        let collision_count = summaries.iter().fold(0, |sum, summary| {
+            sum + summary.id_collisions.len()
+        });
 
-    // display toolbox issues
-    let issue_count = summaries.iter().fold(0, |sum, summary| {
-        sum + summary.toolbox_issues.len()
-    });
+        for summary in summaries.iter() {
+            summary.display_id_collisions(verbose);
+        }
+     
+        stdout!("");
 
-    for summary in summaries.iter() {
-        summary.display_toolbox_issues(verbose);
+        if issue_count != 0 {
+            stdout!("⚠️  There were {} new issues in toolbox dictionaries! Please check the list above.", 
+                issue_count
+            );
+        }
+        if preexisting_count != 0 && !all_issues {
+            stdout!("   ({} issues were already present in the last commit, use \"{}\" to see them)", 
+                preexisting_count,
+                style("git toolbox status --all-issues").bold()
+            );
+        } else if preexisting_count != 0 {
+            stdout!("   ({} issues were already present in the last commit)", preexisting_count);
+        }
+        if acknowledged_count != 0 {
+            stdout!("   ({} acknowledged issues are not shown, see {})", acknowledged_count, ISSUE_BASELINE_FILE);
+        }
+        if any_workdir_issues {
+            stdout!("⚠️  Some managed files were externally modified. Please check the list above.");        
+        }
+        if any_hand_edited {
+            stdout!("⚠️  Some managed files were edited by hand and staged. Please check the list above.");
+        }
+        if collision_count != 0 {
+            stdout!("⚠️  {} record IDs were also added on other branches! Please assign new IDs before merging.", 
+                collision_count
+            );
+        }
     }
- 
-    stdout!("");
 
-    if issue_count != 0 {
-        stdout!("⚠️  There were {} issues in toolbox dictionaries! Please check the list above.", 
-            issue_count
-        );
-    }
-    if any_workdir_issues {
-        stdout!("⚠️  Some managed files were externally modified. Please check the list above.");        
+    /// The status in a stable, machine-readable format 
+    ///
+    /// The lines are:
+    ///
+    /// - `branch <name>`
+    /// - `file <managed file>` 
+    /// - `staged <added|modified|deleted> <path>`
+    /// - `unstaged <added|modified|deleted> <path>`
+    /// - `workdir <kind> <path>` (external modifications of the managed folders)
+    /// - `hand-edited <path>` (staged clobs that do not match the manifest)
+    /// - `issue <managed file> <new|preexisting> <error|warning|info> <kind> <line> <message>`
+    /// - `collision <managed file> <id> <branch> <clob>` (with `--all-branches`)
+    ///
+    /// The paths are relative to the repository. Lines that belong to a managed file follow its 
+    /// `file` line
+    fn porcelain(&self) -> Vec<Vec<String>> {
+        let mut lines = vec!( vec!("branch".to_owned(), self.branch.clone()) );
+
+        for summary in self.files.iter() {
+            lines.push(vec!("file".to_owned(), summary.path.clone()));
+
+            if summary.contents_restored {
+                lines.push(vec!("restored".to_owned(), summary.contents_path.clone()));
+            }
+
+            for diff in summary.staged_diff.iter() {
+                lines.push(vec!("staged".to_owned(), diff.diff_marker().trim().to_owned(), diff.path().to_owned()));
+            }
+            for diff in summary.unstaged_diff.iter() {
+                lines.push(vec!("unstaged".to_owned(), diff.diff_marker().trim().to_owned(), diff.path().to_owned()));
+            }
+            for issue in summary.workdir_issues.iter() {
+                let path = match issue {
+                    ClobValidationIssue::InvalidPath { path } => String::from_utf8_lossy(path).into_owned(),
+                    issue                                    => issue.path().to_owned()
+                };
+
+                lines.push(vec!("workdir".to_owned(), issue.kind().to_owned(), path));
+            }
+
+            for path in summary.hand_edited.iter() {
+                lines.push(vec!("hand-edited".to_owned(), path.clone()));
+            }
+
+            let issues = summary.toolbox_issues.iter().map(|issue| ("new", issue))
+                .chain(summary.preexisting_issues.iter().map(|issue| ("preexisting", issue)));
+
+            for (origin, issue) in issues {
+                lines.push(issue_line(&summary.path, Some(origin), issue));
+            }
+
+            for collision in summary.id_collisions.iter() {
+                lines.push(vec!(
+                    "collision".to_owned(), 
+                    summary.path.clone(), 
+                    collision.id.clone(), 
+                    collision.branch.clone(), 
+                    format!("{}/{}", summary.contents_path, collision.path)
+                ));
+            }
+        }
+
+        lines
     }
+}
 
+/// The porcelain line of a toolbox issue in a managed file
+///
+/// `issue <managed file> [<origin>] <error|warning|info> <kind> <line> <message>`
+pub fn issue_line(path: &str, origin: Option<&str>, issue: &ToolboxFileIssue) -> Vec<String> {
+    let mut line = vec!("issue".to_owned(), path.to_owned());
+    line.extend(origin.map(|origin| origin.to_owned()));
+    line.extend(vec!(
+        issue.severity().name().to_owned(),
+        issue.kind().to_owned(),
+        (issue.line() + 1).to_string(),
+        issue.description()
+    ));
+
+    line
+}
 
-    Ok( () )
+// Print the repository health indicators (and the problems, if any)
+fn display_health(health: &RepositoryHealth) {
+    let indicator = |name: &str, problems: &[String]| {
+        if problems.is_empty() {
+            format!("{} {}", style("✓").green(), name)
+        } else {
+            format!("{} {}", style("✗").red(), style(name).red())
+        }
+    };
+
+    let last_stage = match health.last_stage {
+        Some(time) => format!("last staged {}", format_time(time, local_time_offset())),
+        None       => "never staged".to_owned()
+    };
+
+    stdout!("Health: {}  {}  {}  {}",
+        indicator("filter", &health.filter),
+        indicator("attributes", &health.attributes),
+        indicator("contents", &health.contents),
+        style(last_stage).dim()
+    );
+
+    // the problems with a hint how to deal with them
+    let hints = [
+        (&health.filter, "install git-toolbox so that git can find it"),
+        (&health.attributes, "run \"git toolbox setup\""),
+        (&health.contents, "run \"git toolbox verify\"")
+    ];
+    for (problems, hint) in hints.iter() {
+        for problem in problems.iter() {
+            stdout!("  ⚠️  {} ({})", problem, style(hint).bold());
+        }
+    }
+}
+
+// The changed fields of a modified clob (below its name)
+fn display_record_changes(changes: Option<&Vec<RecordChange>>) {
+    for change in changes.into_iter().flatten() {
+        if let RecordChange::Modified { changes, .. } = change {
+            for change in changes.iter() {
+                stdout!("            {}", crate::diff::display_field_change(change));
+            }
+        }
+    }
 }
 
 impl ManagedFileSummary {
-    pub fn new(repo :&Repository, cfg: &DictionaryConfig) -> Result<Self> {
+    fn new(repo :&Repository, cfg: &DictionaryConfig, filters: &StatusFilters) -> Result<Self> {
         // load and split the dictionary
-        let dictionary = Dictionary::load(&repo, cfg, false)?;
+        let dictionary = Dictionary::load(repo, cfg, false)?;
 
         // obtain the printable relative path to the file
         let display_name = crate::util::get_relative_path(
@@ -176,35 +476,130 @@ impl ManagedFileSummary {
         ).display().to_string();
 
         let contents_path = dictionary.contents_root();
-        let (clobs, toolbox_issues) = dictionary.split();
+        let (clobs, mut toolbox_issues) = dictionary.split();
+        toolbox_issues.extend(validator::validate(&dictionary));
+        toolbox_issues.sort_by_key(ToolboxFileIssue::line);
+
+        // a deleted managed folder is regenerated from the index before it is validated
+        let contents_restored = repo.restore_missing_managed_folder(&contents_path)?;
+
+        // the issues that were already present in the last commit are reported separately
+        let baseline = repo.read_managed_text_from_head(&contents_path, &cfg.sort).map(|text| {
+            let dictionary = Dictionary::from_text(cfg, text);
+            let mut issues = dictionary.split().1;
+            issues.extend(validator::validate(&dictionary));
+
+            issues
+        })
+        .unwrap_or_default();
+        let (toolbox_issues, preexisting_issues) = ToolboxFileIssue::partition_new(toolbox_issues, &baseline);
+
+        // the acknowledged issues are not reported at all
+        let issue_baseline = repo.issue_baseline()?;
+        let (toolbox_issues, acknowledged) = issue_baseline.partition(&dictionary, toolbox_issues);
+        let (preexisting_issues, acknowledged_preexisting) = issue_baseline.partition(&dictionary, preexisting_issues);
+        let acknowledged_issues = acknowledged.len() + acknowledged_preexisting.len();
 
         // run the validation
-        let workdir_issues = repo.validate_clobs_in_workdir(&contents_path)?;
+        let workdir_issues = profile::measure(&contents_path, Phase::WorkdirValidation, || {
+            repo.validate_clobs_in_workdir(&contents_path, false)
+        })?;
+
+        // the staged clobs that were changed without git toolbox stage
+        let mut hand_edited = profile::measure(&contents_path, Phase::WorkdirValidation, || {
+            repo.hand_edited_clobs(&contents_path, &cfg.record_tags)
+        })?;
 
         // run the diff 
-        let unstaged_diff = repo.diff_clobs_at_path(&contents_path, clobs)?;
+        let mut unstaged_diff = profile::measure(&contents_path, Phase::Diff, || {
+            repo.diff_clobs_at_path(&contents_path, clobs)
+        })?;
 
         // get the files already in index
-        let staged_diff = repo.get_staged_clobs(&contents_path)?;
+        let mut staged_diff = profile::measure(&contents_path, Phase::Diff, || {
+            repo.get_staged_clobs(&contents_path)
+        })?;
+
+        // the record IDs that were independently added on other branches
+        let mut id_collisions = if filters.all_branches && cfg.unique_id {
+            repo.find_clob_collisions(&contents_path, &unstaged_diff)?
+        } else {
+            vec!()
+        };
+
+        // restrict the changes to the namespace (if requested)
+        if let Some(namespace) = filters.namespace.as_deref() {
+            let in_namespace = |diff: &ClobDiff| {
+                ClobOrigin::from_managed_path(&contents_path, diff.path()).is_in_namespace(namespace)
+            };
+
+            unstaged_diff.retain(in_namespace);
+            staged_diff.retain(in_namespace);
+            hand_edited.retain(|path| ClobOrigin::from_managed_path(&contents_path, path).is_in_namespace(namespace));
+            id_collisions.retain(|collision| ClobOrigin::from_path(&collision.path).is_in_namespace(namespace));
+        }
+
+        // the changed fields of the modified clobs (working directory against the index
+        // and index against HEAD)
+        let (mut unstaged_changes, mut staged_changes) = (HashMap::new(), HashMap::new());
+        if filters.field_changes {
+            for diff in unstaged_diff.iter() {
+                if let ClobDiff::Update { clob } = diff {
+                    let old = repo.read_text_from_index(&clob.path).unwrap_or_default();
+                    unstaged_changes.insert(clob.path.clone(), template::record_changes(cfg, &old, &clob.content));
+                }
+            }
+            for diff in staged_diff.iter() {
+                if let ClobDiff::Update { clob } = diff {
+                    let old = repo.read_text_from_head(&clob.path).unwrap_or_default();
+                    let new = repo.read_text_from_index(&clob.path).unwrap_or_default();
+                    staged_changes.insert(clob.path.clone(), template::record_changes(cfg, &old, &new));
+                }
+            }
+        }
 
         // return the diff and the issues
         Ok( 
             ManagedFileSummary {
+                path : cfg.path.clone(),
                 display_name,
                 contents_path,
+                contents_restored,
                 unstaged_diff,
                 staged_diff,
                 workdir_issues,
-                toolbox_issues
+                hand_edited,
+                toolbox_issues,
+                preexisting_issues,
+                acknowledged_issues,
+                id_collisions,
+                unstaged_changes,
+                staged_changes
             }
         )
 
     }
 
+    pub fn display_restored_contents(&self) {
+        if !self.contents_restored { return }
+
+        stdout!("\n{note}: the managed folder {path} was missing from the working directory.",
+            note = style("note").bold().yellow(),
+            path = style(&self.contents_path).italic()
+        );
+        stdout!("  (it was restored from the git index, the changes to {} are not affected)\n",
+            style(&self.display_name).italic()
+        );
+    }
+
     pub fn any_workdir_issues(&self) -> bool {
         !self.workdir_issues.is_empty()
     }
 
+    pub fn any_hand_edited(&self) -> bool {
+        !self.hand_edited.is_empty()
+    }
+
     pub fn any_toolbox_issues(&self) -> bool {
         !self.toolbox_issues.is_empty()
     }
@@ -228,7 +623,7 @@ impl ManagedFileSummary {
     pub fn display_toolbox_issues(&self, verbose: bool) {
         if !self.any_toolbox_issues() { return }
 
-        stdout!("\n  Issues in {}:\n", style(&self.display_name).italic());
+        stdout!("\n  New issues in {}:\n", style(&self.display_name).italic());
         let to_show = if verbose { self.toolbox_issues.len() } else { MAX_TO_SHOW };
         for e in self.toolbox_issues.iter().take(to_show) {
             stdout!("        {}", e);
@@ -242,13 +637,50 @@ impl ManagedFileSummary {
         }
     }
 
+    pub fn display_id_collisions(&self, verbose: bool) {
+        if self.id_collisions.is_empty() { return }
+
+        stdout!("\n  Record IDs in {} also added on other branches:\n", style(&self.display_name).italic());
+        let to_show = if verbose { self.id_collisions.len() } else { MAX_TO_SHOW };
+        for collision in self.id_collisions.iter().take(to_show) {
+            stdout!("        {} ({})", style(&collision.id).bold(), collision.branch);
+            stdout!("            ours:   {}", collision.ours);
+            stdout!("            theirs: {}", collision.theirs);
+        }
+        if to_show < self.id_collisions.len() {
+            stdout!("        ...");
+            stdout!("        ({} other IDs, use \"{}\" to see all)", 
+                self.id_collisions.len() - to_show,
+                style("git status --verbose").bold()
+            );
+        }
+    }
+
+    pub fn display_preexisting_issues(&self, verbose: bool) {
+        if self.preexisting_issues.is_empty() { return }
+
+        stdout!("\n  Issues already present in the last commit of {}:\n", style(&self.display_name).italic());
+        let to_show = if verbose { self.preexisting_issues.len() } else { MAX_TO_SHOW };
+        for e in self.preexisting_issues.iter().take(to_show) {
+            stdout!("        {}", style(e).dim());
+        }
+        if to_show < self.preexisting_issues.len() {
+            stdout!("        ...");
+            stdout!("        ({} other issues, use \"{}\" to see all)", 
+                self.preexisting_issues.len() - to_show,
+                style("\"git toolbox status --all-issues --verbose\"").bold()
+            );
+        }
+    }
+
     pub fn display_unstaged_diff(&self, verbose: bool) {
         if !self.any_unstaged() { return }
 
         stdout!("\n  {}:\n", style(&self.display_name).italic());
         let to_show = if verbose { self.unstaged_diff.len() } else { MAX_TO_SHOW };
         for e in self.unstaged_diff.iter().take(to_show) {
-            stdout!("        {} {}", e.display_diff_marker(), e.filename());
+            stdout!("        {} {}", e.display_diff_marker(), truncate_text(e.filename(), MAX_FILENAME_WIDTH));
+            display_record_changes(self.unstaged_changes.get(e.path()));
         }
         if to_show < self.unstaged_diff.len() {
             stdout!("        ...");
@@ -267,8 +699,9 @@ impl ManagedFileSummary {
         for e in self.staged_diff.iter().take(to_show) {
             stdout!("        {} {}", 
                 style(e.diff_marker()).green(), 
-                style(e.filename()).green()
-            )
+                style(truncate_text(e.filename(), MAX_FILENAME_WIDTH)).green()
+            );
+            display_record_changes(self.staged_changes.get(e.path()));
         }
         if to_show < self.staged_diff.len() {
             stdout!("        ...");
@@ -328,6 +761,27 @@ impl ManagedFileSummary {
 
         stdout!("");
     }
-}
 
+    pub fn display_hand_edited(&self, verbose: bool) {
+        if !self.any_hand_edited() { return }
+
+        let to_show = if verbose { self.hand_edited.len() } else { MAX_TO_SHOW };
+
+        for path in self.hand_edited.iter().take(to_show) {
+            stdout!("        {path}: {status}",
+                path = path,
+                status = style("edited by hand").red()
+            );
+        }
+
+        if to_show < self.hand_edited.len() {
+            stdout!("        ...");
+            stdout!("        ({} other hand-edited files, use \"{}\" to see all)",
+                self.hand_edited.len() - to_show,
+                style("\"git status --verbose\"").bold()
+            );
+        }
 
+        stdout!("");
+    }
+}
@@ -3,30 +3,58 @@
 //
 // Toolbox dictionary header detection
 //
+// The header identifies the program version (Toolbox or the older Shoebox) and
+// the database type of the file, e.g. `\_sh v3.0  400  Dictionary`. Any version
+// is accepted and the header is preserved as it is. The database type (e.g.
+// `Dictionary`, `MDF 4.0`, `Wordlist` or `Text`) is returned along with the header
+// so that it can be checked against the database type configured for the file.
+//
 // (C) 2020 Taras Zakharko
 //
 // This code is licensed under GPL 3.0
 
 use crate::toolbox::Scanner;
+use crate::toolbox::scanner::Line;
+
+/// A toolbox header line
+#[derive(Debug, Clone)]
+pub struct ToolboxHeader<'a> {
+    /// The header line
+    pub line          : Line<'a>,
+    /// The database type (empty if the header does not name one)
+    pub database_type : &'a str
+}
+
+impl<'a> ToolboxHeader<'a> {
+    /// Check whether the header names the database type
+    ///
+    /// The types are compared ignoring the case and the amount of whitespace 
+    /// (e.g. `MDF 4.0` matches `mdf  4.0`)
+    pub fn has_database_type(&self, database_type: &str) -> bool {
+        let normalize = |text: &str| text.split_whitespace().map(str::to_lowercase).collect::<Vec<_>>();
+
+        normalize(self.database_type) == normalize(database_type)
+    }
+}
 
 impl<'a> Scanner<'a> {
-    /// Expect a toolbox dictionary header
+    /// Expect a toolbox header
     ///
     /// Advances the scanner to the next non-blank line and returns an error
-    /// if this lien is not a toolbox dictionary header. The error returned 
-    /// is the number of the offending line in the file
-    pub fn expect_toolbox_dictionary_header(mut self) -> Result<Self, usize> {
+    /// if this lien is not a toolbox header. The error returned is the number 
+    /// of the offending line in the file
+    pub fn expect_toolbox_header(mut self) -> Result<(Self, ToolboxHeader<'a>), usize> {
         use regex::Regex;
         use crate::toolbox::scanner::Token;
 
-        // compile the toolbox dictionary regex
+        // compile the toolbox header regex
         // note: this could have been a global variable, but since this is not a performance-
         //       critical path, we can afford to recompile it again every time
         let re_header = Regex::new(
-            r"^\\_sh[[:space:]]+v3\.0[[:space:]]+[0-9]+[[:space:]]+Dictionary[[:space:]]*$"
+            r"^\\_sh[[:space:]]+v[0-9]+(\.[0-9]+)*([[:space:]]+[0-9]+)?([[:space:]]+(?P<type>[^[:space:]].*?))?[[:space:]]*$"
         ).expect("Internal regular expression error");
 
-        // scan the file until we detect a toolbox dictionary header
+        // scan the file until we detect a toolbox header
         // abort on unexpected string
         let error_line = loop {
             match self.next() {
@@ -37,8 +65,13 @@ impl<'a> Scanner<'a> {
                 },
                 // header line detected
                 Some( (line, _) ) if re_header.is_match(line.text) => {
+                    let database_type = re_header.captures(line.text)
+                        .and_then(|captures| captures.name("type"))
+                        .map(|database_type| database_type.as_str())
+                        .unwrap_or("");
+
                     //  return success
-                    return Ok( self );
+                    return Ok( (self, ToolboxHeader { line, database_type }) );
                 },
                 // any other line
                 Some( (line, _) ) => {
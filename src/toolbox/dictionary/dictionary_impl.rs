@@ -7,24 +7,37 @@
 //
 // This code is licensed under GPL 3.0
 
-use crate::config::DictionaryConfig;
+use crate::config::{DictionaryConfig, UTF8_BOM};
 use crate::repository::Repository;
 use crate::toolbox::{Scanner, ToolboxFileIssue};
+use crate::toolbox::scanner::OwnedLine;
+use super::dictionary_header::ToolboxHeader;
+use crate::profile::{self, Phase};
 
 use anyhow::Result;
 use crate::error;
 
 /// A Toolbox dictionary
+///
+/// The dictionary owns its text, the scanner and everything produced from it 
+/// (records, clobs) borrow from the dictionary
 #[derive(Debug)]
 pub struct Dictionary {
     pub(super) config  : DictionaryConfig,
-    pub(super) text    : &'static str,
-    pub(super) scanner : Scanner<'static>,
+    pub(super) text    : String,
+    // whether the text starts with a dictionary header (skipped when scanning)
+    pub(super) header  : bool,
+    // whether the file started with a byte order mark (which is not part of the text)
+    pub(super) bom     : bool,
     pub(super) issues  : Vec<ToolboxFileIssue>
 }
 
 impl Dictionary {
     pub fn load(repo: &Repository, config: &DictionaryConfig, strict: bool) -> Result<Dictionary> {
+        profile::measure(&config.path, Phase::Load, || Dictionary::load_impl(repo, config, strict))
+    }
+
+    fn load_impl(repo: &Repository, config: &DictionaryConfig, strict: bool) -> Result<Dictionary> {
         use std::fs;
 
         let config = config.clone();
@@ -33,11 +46,7 @@ impl Dictionary {
         let mut issues = vec!();
 
         // load the dictionary text 
-        // we leak the memory here to simplify lifetime handling
-        // this is not a problem since the tool only loads a dictionary once
-        let text : &'static str = fs::read_to_string(&path)
-            // leak the string
-            .map(|text| Box::leak(text.into_boxed_str()))
+        let data = fs::read(&path)
             // process the errors
             .map_err(|err| -> anyhow::Error {
                 use std::io::ErrorKind;
@@ -59,45 +68,191 @@ impl Dictionary {
                 }
             })?;
 
+        // decode the text (the file might use a legacy encoding)
+        let text = config.decode_text(&path, data)?;
+        let (text, bom) = strip_bom(text);
 
-        // start the toolbox scanner and check that the file has a dictionary header
+        // check that the file has a dictionary header
         // if we are in the strict mode, we want to flag missign header as an error
         // in the non-strict mode, we tolerate the absence of the header 
-        let scanner = Scanner::from(text, &config.record_tag)
-            .expect_toolbox_dictionary_header()
-            .or_else(|line| {
-                if strict {
-                    // return an error
-                    Err(
-                        error::ToolboxDictionaryMissingHeader {
-                            path : path.clone(), 
-                            text, 
-                            line
-                        }
-                    )
-                } else {
-                    // simply reset the scanner
-                    issues.push(ToolboxFileIssue::MissingDictionaryHeader { line });
-                    
-                    Ok( Scanner::from(text, &config.record_tag) )
-                }
-            })?;
+        let header = Scanner::from(&text, &config.record_tag)
+            .expect_toolbox_header()
+            .map(|(_, header)| check_database_type(&config, &header));
+        let header = match header {
+            Ok( None )                   => true,
+            Ok( Some((line, expected)) ) if strict => {
+                // return an error
+                return Err(
+                    error::ToolboxUnexpectedDatabaseType {
+                        path : path.clone(),
+                        text,
+                        line : line.line,
+                        expected
+                    }
+                    .into()
+                )
+            },
+            Ok( Some((line, expected)) ) => {
+                issues.push(ToolboxFileIssue::UnexpectedDatabaseType { line, expected });
+
+                true
+            },
+            Err( line ) if strict        => {
+                // return an error
+                return Err(
+                    error::ToolboxDictionaryMissingHeader {
+                        path : path.clone(), 
+                        text, 
+                        line
+                    }
+                    .into()
+                )
+            },
+            Err( line )                  => {
+                issues.push(ToolboxFileIssue::MissingDictionaryHeader { line });
+
+                false
+            }
+        };
 
         Ok (
             Dictionary {
                 config, 
                 text, 
-                scanner,
+                header,
+                bom,
                 issues
             }
         )
     }
 
-    pub fn _config(&self) -> &DictionaryConfig {
+    /// Create a dictionary from the full Toolbox text (e.g. as stored in a git blob)
+    ///
+    /// The dictionary header is optional, as with the non-strict `load()`
+    pub fn from_text(config: &DictionaryConfig, text: String) -> Dictionary {
+        let config = config.clone();
+        let mut issues = vec!();
+        let (text, bom) = strip_bom(text);
+
+        let header = match Scanner::from(&text, &config.record_tag).expect_toolbox_header() {
+            Ok( (_, header) ) => {
+                if let Some((line, expected)) = check_database_type(&config, &header) {
+                    issues.push(ToolboxFileIssue::UnexpectedDatabaseType { line, expected });
+                }
+
+                true
+            },
+            Err( line )       => {
+                issues.push(ToolboxFileIssue::MissingDictionaryHeader { line });
+
+                false
+            }
+        };
+
+        Dictionary {
+            config, 
+            text, 
+            header,
+            bom,
+            issues
+        }
+    }
+
+    /// Create a dictionary from a fragment of Toolbox text (such as a clob)
+    ///
+    /// The fragment is not expected to start with a dictionary header
+    pub fn from_fragment(config: &DictionaryConfig, text: String) -> Dictionary {
+        Dictionary {
+            config : config.clone(), 
+            text, 
+            header : false,
+            bom    : false,
+            issues : vec!()
+        }
+    }
+
+    /// A scanner over the dictionary text (positioned after the dictionary header, if any)
+    pub(super) fn scanner(&self) -> Scanner<'_> {
+        let scanner = Scanner::for_dictionary(&self.text, &self.config);
+
+        if self.header {
+            scanner.clone().expect_toolbox_header().map(|(scanner, _)| scanner).unwrap_or(scanner)
+        } else {
+            scanner
+        }
+    }
+
+    /// The dictionary header line (if any)
+    pub fn header_line(&self) -> Option<&str> {
+        use crate::toolbox::scanner::Token;
+
+        if !self.header { return None }
+
+        Scanner::from(&self.text, &self.config.record_tag)
+            .find(|(_, token)| *token != Token::Blank)
+            .map(|(line, _)| line.text)
+    }
+
+    /// The lines between the dictionary header and the first record (if any of them are not blank)
+    ///
+    /// The lines are returned exactly as they are in the text, including the blank lines that 
+    /// separate them from the first record
+    pub fn preamble(&self) -> Option<&str> {
+        use crate::toolbox::scanner::Token;
+
+        // the line after the header and the line of the first record
+        let start = if self.header {
+            Scanner::from(&self.text, &self.config.record_tag)
+                .find(|(_, token)| *token != Token::Blank)
+                .map(|(line, _)| line.line + 1)
+                .unwrap_or(0)
+        } else {
+            0
+        };
+        let end = self.scanner().find(|(_, token)| *token == Token::RecordBegin).map(|(line, _)| line.line);
+
+        let offset = |line: usize| self.text.split_inclusive('\n').take(line).map(str::len).sum::<usize>();
+        let preamble = &self.text[offset(start) .. end.map(offset).unwrap_or(self.text.len())];
+
+        Some(preamble).filter(|text| !text.trim().is_empty())
+    }
+
+    /// The full dictionary text (including the header)
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Whether the file started with a UTF-8 byte order mark
+    pub fn bom(&self) -> bool {
+        self.bom
+    }
+
+    /// The configuration of the dictionary
+    pub fn config(&self) -> &DictionaryConfig {
         &self.config
     }
 
     pub fn contents_root(&self) -> String {
         format!("{}.contents", &self.config.path)
     }
-} 
\ No newline at end of file
+} 
+
+
+// Check the database type of the header against the configured one
+//
+// Returns the header line and the expected database type if they differ
+fn check_database_type(config: &DictionaryConfig, header: &ToolboxHeader) -> Option<(OwnedLine, String)> {
+    let expected = config.database_type.as_ref()?;
+
+    if header.has_database_type(expected) { return None }
+
+    Some( (header.line.to_owned_line(), expected.clone()) )
+}
+
+// Remove the byte order mark at the start of the text (if any)
+fn strip_bom(text: String) -> (String, bool) {
+    match text.strip_prefix(UTF8_BOM) {
+        Some(rest) => (rest.to_owned(), true),
+        None       => (text, false)
+    }
+}
@@ -10,8 +10,12 @@
 // the dictionary parser implementation
 mod dictionary_impl;
 mod dictionary_header;
+// record parsing
+mod records;
 
 // dictionary splitting
 mod split;
 
 pub use dictionary_impl::Dictionary;
+pub use records::Record;
+pub use split::{QUARANTINE_DIR, ClobOrigin, RecordId, clob_path_for_id, clob_paths_for_id, clob_paths_for_label};
@@ -0,0 +1,105 @@
+//
+// src/toolbox/dictionary/records.rs
+//
+// Parsing of the dictionary records into fields, for inspecting the dictionary
+// contents (e.g. by git toolbox query)
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use super::Dictionary;
+use crate::toolbox::scanner::{Line, Token};
+use std::collections::HashMap;
+
+
+/// A parsed dictionary record (borrowing from the dictionary text)
+#[derive(Debug, Clone)]
+pub struct Record<'a> {
+    /// The first line of the record
+    pub line   : Line<'a>,
+    /// The full record text
+    pub text   : &'a str,
+    /// The fields of the record in order as (marker, value), starting with the record tag
+    ///
+    /// Untagged lines are continuations of the previous field value
+    pub fields : Vec<(&'a str, String)>
+}
+
+impl Record<'_> {
+    /// All values of the field with the given marker
+    pub fn values<'a>(&'a self, marker: &'a str) -> impl Iterator<Item=&'a str> + 'a {
+        self.fields.iter()
+            .filter(move |(tag, _)| *tag == marker)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The value of the first field with the given marker
+    pub fn value(&self, marker: &str) -> Option<&str> {
+        self.fields.iter()
+            .find(|(tag, _)| *tag == marker)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The label of the record (the value of its record tag, whichever record type it is)
+    pub fn label(&self) -> Option<&str> {
+        self.fields.first().map(|(_, value)| value.as_str())
+    }
+}
+
+
+impl Dictionary {
+    /// Parse the dictionary records (in the order of occurence)
+    ///
+    /// The content before the first record is ignored
+    pub fn records(&self) -> Vec<Record<'_>> {
+        let mut records = vec!();
+        let mut current : Option<Record> = None;
+
+        for (line, token) in self.scanner() {
+            match token {
+                Token::RecordBegin => {
+                    current = Some(Record { line, text: "", fields: vec!() });
+                },
+                Token::Tagged { tag, text } | Token::NestedRecordTag { tag, text } => {
+                    if let Some(record) = current.as_mut() {
+                        record.fields.push((tag, text.trim().to_owned()));
+                    }
+                },
+                Token::Untagged { text } | Token::Continuation { text } => {
+                    let field = current.as_mut().and_then(|record| record.fields.last_mut());
+
+                    if let Some((_, value)) = field {
+                        if !value.is_empty() { value.push(' ') }
+                        value.push_str(text.trim());
+                    }
+                },
+                Token::RecordEnd { body } => {
+                    if let Some(mut record) = current.take() {
+                        record.text = body;
+                        records.push(record);
+                    }
+                },
+                Token::Blank => {}
+            }
+        }
+
+        records
+    }
+
+    /// The keys of the records by their first line: the ID (for the dictionaries with unique 
+    /// IDs) or the label
+    pub fn record_keys(&self) -> HashMap<usize, String> {
+        let id_tag = self.config.id_tag.as_deref().filter(|_| self.config.unique_id);
+
+        self.records().iter().map(|record| {
+            let key = match id_tag {
+                Some(tag) => record.value(tag),
+                None      => record.label()
+            };
+
+            (record.line.line, key.unwrap_or("").to_owned())
+        })
+        .collect()
+    }
+}
@@ -14,8 +14,10 @@
 use crate::toolbox::Dictionary;
 use crate::toolbox::scanner::*;
 use crate::toolbox::ToolboxFileIssue;
+use crate::config::DictionaryConfig;
 
-use super::SplitterOutput;
+use super::{SplitterOutput, QUARANTINE_DIR};
+use crate::repository::clob_path_problem;
 
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone)]
@@ -33,7 +35,7 @@ fn extract_id<'a>(text : &'a str, regex: &regex::Regex) -> Result<ID<'a>, ()> {
             captures.get(0).expect("Internal error: invalid ID regex").as_str() == text 
         })
         // turn it into a result<ID>
-        .ok_or_else(|| () )?;
+        .ok_or(())?;
 
     // extract the namespace component
     let namespace = captures.name("namespace")
@@ -51,67 +53,134 @@ fn extract_id<'a>(text : &'a str, regex: &regex::Regex) -> Result<ID<'a>, ()> {
     }
 }
 
+/// An id tag found in a dictionary record (borrowing from the dictionary text)
+#[derive(Debug, Clone)]
+pub struct RecordId<'a> {
+    /// The first line of the record
+    pub record    : Line<'a>,
+    /// The id tag line
+    pub line      : Line<'a>,
+    /// The namespace and the id component (`None` if the id does not match the id spec)
+    pub parsed    : Option<(Option<&'a str>, &'a str)>
+}
+
+impl Dictionary {
+    /// Collect the ids of the records (in the order of occurence)
+    ///
+    /// Only the first id tag of every record is considered
+    pub fn record_ids(&self) -> Vec<RecordId<'_>> {
+        let id_tag = match self.config.id_tag.as_ref() {
+            Some(id_tag) => id_tag,
+            None         => return vec!()
+        };
+
+        let mut ids = vec!();
+        let mut record_start = None;
+
+        for token in self.scanner() {
+            match token {
+                (line, Token::Tagged { tag, text: _ }) if self.config.record_tags.iter().any(|t| t == tag) => {
+                    record_start = Some(line);
+                },
+                (line, Token::Tagged { tag, text }) if tag == id_tag => {
+                    // the first id of the record
+                    if let Some(record) = record_start.take() {
+                        let parsed = extract_id(text.trim(), &self.config.id_spec).ok().map(|id| {
+                            (id.namespace, id.id)
+                        });
+
+                        ids.push(RecordId { record, line, parsed });
+                    }
+                },
+                _ => {}
+            }
+        }
+
+        ids
+    }
+}
+
+/// The path of the clob that holds the records with the id (relative to the contents root)
+///
+/// Returns `None` if the id does not match the id specification or does not map to a safe path
+pub fn clob_path_for_id(config: &DictionaryConfig, id: &str) -> Option<String> {
+    extract_id(id.trim(), &config.id_spec).ok()
+        .map(|id| clob_path(None, &id, config, config.prefix_depth))
+        .filter(|path| clob_path_problem(path).is_none())
+}
+
+/// The paths the clob that holds the records with the id can have (relative to the contents root)
+///
+/// These are the paths for every record type (the records of the other record types are stored
+/// in their own directories). Empty if the id is not valid
+pub fn clob_paths_for_id(config: &DictionaryConfig, id: &str) -> Vec<String> {
+    let id = match extract_id(id.trim(), &config.id_spec) {
+        Ok( id ) => id,
+        Err( _ ) => return vec!()
+    };
+
+    super::record_type_dirs(config).iter()
+        .map(|type_dir| clob_path(type_dir.as_deref(), &id, config, config.prefix_depth))
+        .filter(|path| clob_path_problem(path).is_none())
+        .collect()
+}
+
+/// The clob path for the id of a record type at a prefix depth (non-ASCII characters are encoded as configured)
+fn clob_path(type_dir: Option<&str>, id: &ID, config: &DictionaryConfig, depth: usize) -> String {
+    use crate::util::{build_path_prefix, encode_path_component};
+
+    let encoding = config.path_encoding;
+
+    let name = encode_path_component(id.full, encoding);
+
+    let path = if let Some(ns) = id.namespace {
+        format!("private/{}/{}.txt", encode_path_component(ns, encoding), name)
+    } else {
+        let prefix = build_path_prefix(&encode_path_component(id.id, encoding), config.path_layout, depth);
+
+        format!("public/{}/{}.txt", prefix, name)
+    };
+
+    super::in_record_type_dir(type_dir, path)
+}
+
 /// A basic toolbox dictionary splitter (no uniqiue identifiers or lifecycle management)
-pub fn split(dictionary: Dictionary) -> SplitterOutput {
+pub fn split(dictionary: &Dictionary) -> SplitterOutput<'_> {
     use crate::repository::Clob;
     use multimap::MultiMap;
     use itertools::Itertools;
-
-    use crate::util::*;
+    use crate::util::normalize_nfc;
 
     // decosntruct the dictionary
-    let mut scanner = dictionary.scanner;
-    let config  = dictionary.config;
-    let mut issues = dictionary.issues;
+    let mut scanner = dictionary.scanner();
+    let config  = &dictionary.config;
+    let mut issues = dictionary.issues.clone();
 
     // cache the id tag 
     let id_tag = config.id_tag.as_ref().expect("Internal error: wrong splitting algorithm");
   
-     // report any lines orphaned before the first record
-    let mut orphaned_lines = vec!();
-
-    scanner.try_for_each(|token| {
-        use Token::*;
-
-        match token {
-            // record start - quit the initial scan
-            (_, RecordBegin) => {
-                return None
-            },
-            (line, Tagged { tag: _, text: _}) | (line, Untagged { text: _ }) => {
-                issues.push(
-                    ToolboxFileIssue::LineBeforeFirstRecord {
-                        line: line.clone()
-                    }
-                );
-
-                orphaned_lines.push(line.text);
-            }, 
-            (_, Blank) => {
-                // push an empty line if it does not create lare blanks of space
-                if orphaned_lines.last().map(|line| !line.trim().is_empty()).unwrap_or(false) {
-                    orphaned_lines.push(""); 
-                }
-            }
-            _ => {
-            }
-        }
-
-        Some( () )
-    });
+    // skip the lines before the first record (they are stored as the preamble)
+    scanner.find(|(_, token)| *token == Token::RecordBegin);
 
     // a map from IDs to records
     // 
-    // ID -> (first record line, id line, record contents)
+    // (record type directory, ID) -> (first record line, id line, record contents)
     let mut id_map = MultiMap::new();
 
     // list of records that do not have ids
     let mut id_missing = vec!();
 
+    // the records that cannot be stored at the path of their id
+    let mut unsafe_path = vec!();
+
+    // the first id (and its line) stored at every clob path (to detect different ids sharing a clob)
+    let mut first_ids : std::collections::HashMap<String, (&str, Line)> = std::collections::HashMap::new();
+
     // current record label
     let mut record_start   = Line { line : 0, text : "" };
     let mut record_id_line = Line { line : 0, text : "" };
     let mut record_id      = None; 
+    let mut record_type    = None;
     
 
     for token in scanner {
@@ -119,12 +188,13 @@ pub fn split(dictionary: Dictionary) -> SplitterOutput {
 
         match token {
             // record start tag
-            (line, Tagged {tag, text}) if tag == config.record_tag => {
+            (line, Tagged {tag, text}) if config.record_tags.iter().any(|t| t == tag) => {
                 record_start = line.clone();
+                record_type = config.record_type_dir(tag);
                 if text.trim().is_empty() {
                     issues.push(
                         ToolboxFileIssue::MissingRecordLabel { 
-                            line
+                            line: line.to_owned_line()
                         }
                     )    
                 }
@@ -135,8 +205,8 @@ pub fn split(dictionary: Dictionary) -> SplitterOutput {
                 if record_id.is_some() {
                     issues.push(
                         ToolboxFileIssue::ExtraneousID {
-                            record : record_start.clone(),
-                            line   : line.clone(),    
+                            record : record_start.to_owned_line(),
+                            line   : line.to_owned_line(),    
                         }
                     )
                 };
@@ -153,25 +223,77 @@ pub fn split(dictionary: Dictionary) -> SplitterOutput {
                 }).map_err(|_| {
                     issues.push(
                         ToolboxFileIssue::InvalidID {
-                            record : record_start.clone(),
-                            line   : line.clone(),
+                            record : record_start.to_owned_line(),
+                            line   : line.to_owned_line(),
                         }
                     )
                 });
             },
+            // record tag within a subentry (the line stays in the record)
+            (line, NestedRecordTag { tag: _, text: _ }) => {
+                issues.push(
+                    ToolboxFileIssue::NestedRecordTag {
+                        record : record_start.to_owned_line(),
+                        line   : line.to_owned_line()
+                    }
+                )
+            },
             // untagged line
             (line, Untagged {text: _}) => {
                 issues.push(
                     ToolboxFileIssue::UntaggedLine {
-                        line: line.clone()
+                        line: line.to_owned_line()
                     }
                 )
             },
             // record end — add new record
             (_, RecordEnd { body }) => {
+                let type_dir = record_type.take();
+
                 if let Some(id) = record_id.take() {  
-                    // record this id occurence
-                    id_map.insert(id.clone(), (record_start.clone(), record_id_line.clone(), body));
+                    let path = clob_path(type_dir.as_deref(), &id, config, config.prefix_depth);
+
+                    // the records are never written outside of the managed folder
+                    if let Some(reason) = clob_path_problem(&path) {
+                        issues.push(
+                            ToolboxFileIssue::UnsafeClobPath {
+                                record : record_start.to_owned_line(),
+                                path,
+                                reason : reason.to_owned()
+                            }
+                        );
+                        unsafe_path.push(body);
+                    } else {
+                        // different ids can be encoded to the same clob path (e.g. `kápa` and `kapa` 
+                        // with the transliterated encoding), the records then share the clob
+                        match first_ids.get(&path.to_ascii_lowercase()) {
+                            Some((full, other)) if *full != id.full && normalize_nfc(full) == normalize_nfc(id.full) => {
+                                issues.push(
+                                    ToolboxFileIssue::NormalizationCollision {
+                                        record : record_start.to_owned_line(),
+                                        line   : record_id_line.to_owned_line(),
+                                        other  : other.to_owned_line()
+                                    }
+                                );
+                            },
+                            Some((full, other)) if config.label_collisions.reports(full, id.full) => {
+                                issues.push(
+                                    ToolboxFileIssue::IdCollision {
+                                        record : record_start.to_owned_line(),
+                                        line   : record_id_line.to_owned_line(),
+                                        other  : other.to_owned_line()
+                                    }
+                                );
+                            },
+                            Some(_) => {},
+                            None    => {
+                                first_ids.insert(path.to_ascii_lowercase(), (id.full, record_id_line.clone()));
+                            }
+                        }
+
+                        // record this id occurence
+                        id_map.insert((type_dir, id.clone()), (record_start.clone(), record_id_line.clone(), body));
+                    }
                 } else {
                     // this record does not have an ID which make 
                     id_missing.push(body);
@@ -179,7 +301,7 @@ pub fn split(dictionary: Dictionary) -> SplitterOutput {
                     // report the problem
                     issues.push(
                         ToolboxFileIssue::MissingID {
-                            line: record_start.clone()
+                            line: record_start.to_owned_line()
                         }
                     );
                 }
@@ -194,8 +316,8 @@ pub fn split(dictionary: Dictionary) -> SplitterOutput {
         for (record, line, _) in records.iter() { 
             issues.push(
                 ToolboxFileIssue::AmbiguousID {
-                    record : record.clone(), 
-                    line   : line.clone()
+                    record : record.to_owned_line(), 
+                    line   : line.to_owned_line()
                 }
             );    
         }
@@ -204,48 +326,110 @@ pub fn split(dictionary: Dictionary) -> SplitterOutput {
     // sort the issues
     issues.sort_unstable_by_key(|issue| issue.line());
 
-    // construct the result iterator
-    let result = id_map.into_iter().map(move |(id, records)| {
-        // build a path for the record
-        let path = if let Some(ns) = id.namespace {
-            format!("private/{}/{}.txt", ns, &id.full)
-        } else {
-            format!("public/{}/{}.txt", build_path_prefix(&id.id), &id.full)
-        };
+    // the clobs are ordered by their paths and the records of the ids that only differ in 
+    // case share a clob (case-insensitive file systems cannot hold both), so that the 
+    // clob tree is the same on every platform
+    let mut clobs : Vec<(String, Vec<&str>)> = vec!();
+    
+    let paths = id_map.into_iter()
+        .map(|((type_dir, id), records)| (clob_path(type_dir.as_deref(), &id, config, config.prefix_depth), records))
+        .sorted_by(|(a, _), (b, _)| {
+            a.to_ascii_lowercase().cmp(&b.to_ascii_lowercase()).then_with(|| a.cmp(b))
+        });
+
+    for (path, records) in paths {
+        let bodies = records.into_iter().map(|(_, _, body)| body);
 
+        match clobs.last_mut() {
+            Some((last, group)) if last.eq_ignore_ascii_case(&path) => group.extend(bodies),
+            _ => clobs.push((path, bodies.collect()))
+        }
+    }
+
+    // construct the result iterator
+    let result = clobs.into_iter().map(move |(path, records)| {
         // build the clob contents by joining the records 
         // together
         // TODO: do we sort the records somehow?
-        let content = records.into_iter().map(|(_, _, body)| body).join("\n");
+        let content = records.join("\n");
     
         Clob { path, content }
      })
-    // add the id_missing records
+    // add the records with unsafe paths
     .chain({
-        std::iter::once(id_missing.join("\n")).map(|content| {
-            Clob { path: "invalid/id_missing.txt".to_owned(), content }
-        })
-     })
-     // add the orphaned lines
+        std::iter::once(unsafe_path.join("\n"))
+            .filter(|content| !content.is_empty())
+            .map(|content| Clob { path: format!("{}/unsafe_path.txt", QUARANTINE_DIR), content })
+    })
+    // add the id_missing records
     .chain({
-        std::iter::once(orphaned_lines.join("\n")).map(|mut text| {
-            // add line end (if nessesary)
-            if !text.ends_with('\n') {
-                text.push('\n')
-            }
-
-            text
-        })
-        // ignore the orphaned lines block if it is empty
-        .filter(|text| {
-            !text.trim().is_empty()
-        })
-        // make it into a clob
-        .map(|content| {
-            Clob { path: "invalid/__.txt".to_owned(), content }
-        })
+        std::iter::once(id_missing.join("\n"))
+            .filter(|content| !content.is_empty())
+            .map(|content| Clob { path: format!("{}/id_missing.txt", QUARANTINE_DIR), content })
     })
     .map(Clob::validated);
 
     ( Box::new(result.map(Clob::validated)), issues )
 }
+
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{DictionaryConfig, PathEncoding};
+    use crate::toolbox::{Dictionary, ToolboxFileIssue};
+
+    fn config(encoding: PathEncoding) -> DictionaryConfig {
+        let text = "[[dictionary]]\nname = \"Lexical\"\npath = \"lex.txt\"\nrecord-tag = \"lex\"\n\
+                    unique-id = true\nid-tag = \"id\"\n";
+
+        let mut cfg = DictionaryConfig::for_test(text);
+        cfg.id_spec = regex::Regex::new("(?P<namespace>)(?P<id>.+)").unwrap();
+        cfg.path_encoding = encoding;
+
+        cfg
+    }
+
+    #[test]
+    fn test_id_collisions_are_reported() {
+        let text = "\\_sh v3.0  400  Dictionary\n\n\
+            \\lex one\n\\id kapa\n\n\
+            \\lex two\n\\id kápa\n\n\
+            \\lex three\n\\id kaŋa\n";
+
+        let collisions = |encoding: PathEncoding| -> Vec<(usize, usize)> {
+            Dictionary::from_text(&config(encoding), text.to_owned()).split().1.into_iter().filter_map(|issue| {
+                match issue {
+                    ToolboxFileIssue::IdCollision { record : _, line, other } => Some((line.line, other.line)),
+                    _ => None
+                }
+            })
+            .collect()
+        };
+
+        // `kápa` is transliterated to `kapa`
+        assert_eq!(collisions(PathEncoding::Transliterate), vec!((6, 3)));
+        assert!(collisions(PathEncoding::Percent).is_empty());
+    }
+
+    #[test]
+    fn test_unsafe_ids_are_quarantined() {
+        use crate::repository::clob_path_problem;
+
+        let text = "\\_sh v3.0  400  Dictionary\n\n\
+            \\lex up\n\\id ../../hooks/pre-commit\n\n\
+            \\lex root\n\\id /etc/passwd\n\n\
+            \\lex device\n\\id con\n\n\
+            \\lex fine\n\\id 12\n";
+
+        let dictionary = Dictionary::from_text(&config(PathEncoding::Transliterate), text.to_owned());
+        let (clobs, issues) = dictionary.split();
+        let clobs = clobs.collect::<Vec<_>>();
+
+        assert!(clobs.iter().all(|clob| clob_path_problem(&clob.path).is_none()));
+        assert_eq!(issues.iter().filter(|issue| issue.kind() == "unsafe-clob-path").count(), 3);
+
+        let quarantined = clobs.iter().find(|clob| clob.path == "invalid/unsafe_path.txt").unwrap();
+        assert!(["up", "root", "device"].iter().all(|label| quarantined.content.contains(label)));
+        assert!(!quarantined.content.contains("fine"));
+    }
+}
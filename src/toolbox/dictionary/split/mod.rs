@@ -9,29 +9,399 @@
 // This code is licensed under GPL 3.0
 
 
-use crate::repository::Clob;
+use crate::repository::{Clob, PREAMBLE_CLOB, HEADER_CLOB, clob_part};
 use crate::toolbox::ToolboxFileIssue;
+use crate::config::{DictionaryConfig, MAX_PREFIX_DEPTH};
 
-type SplitterOutput = (Box<dyn Iterator<Item=Clob> + 'static>, Vec<ToolboxFileIssue>);
+type SplitterOutput<'a> = (Box<dyn Iterator<Item=Clob> + 'a>, Vec<ToolboxFileIssue>);
+
+/// Directory (relative to the contents root) where unparseable content is quarantined
+pub const QUARANTINE_DIR : &str = "invalid";
 
 use super::Dictionary;
 
+/// The record group a clob was split out from
+///
+/// This is the reverse of the path mapping performed by the splitters and allows
+/// us to describe a clob path in terms of the dictionary records
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClobOrigin<'a> {
+    /// A record with a public (non-namespaced) id
+    Public     { id: &'a str },
+    /// A record with a namespaced (private) id
+    Private    { namespace: &'a str, id: &'a str },
+    /// Records sharing the same (sanitized) label
+    Label      { label: &'a str },
+    /// Quarantined content that could not be parsed
+    Quarantine { name: &'a str },
+    /// The dictionary header
+    Header,
+    /// The lines before the first record
+    Preamble
+}
+
+impl<'a> ClobOrigin<'a> {
+    /// Map a clob path (relative to the contents root) back to its origin
+    ///
+    /// The overflow parts of a clob have the origin of the clob
+    pub fn from_path(path: &'a str) -> Self {
+        let (path, _) = clob_part(path);
+        // the records of the other record types are stored like the ones of the first tag
+        let path = match path.split_once('/') {
+            Some((dir, rest)) if crate::util::is_record_type_dir(dir) => rest,
+            _                                                         => path
+        };
+        let mut components = path.split('/');
+        let first = components.next().unwrap_or("");
+        let stem = path.rsplit('/').next().unwrap_or(path).trim_end_matches(".txt");
+
+        match first {
+            HEADER_CLOB    => ClobOrigin::Header,
+            PREAMBLE_CLOB  => ClobOrigin::Preamble,
+            QUARANTINE_DIR => ClobOrigin::Quarantine { name: stem },
+            "public"       => ClobOrigin::Public { id: stem },
+            "private"      => {
+                ClobOrigin::Private { 
+                    namespace : components.next().unwrap_or(""),
+                    id        : stem
+                }
+            },
+            _              => ClobOrigin::Label { label: stem }
+        }
+    }
+
+    /// Map a clob path (relative to the repository) back to its origin
+    pub fn from_managed_path(contents_root: &str, path: &'a str) -> Self {
+        let path = path.strip_prefix(contents_root)
+            .map(|path| path.trim_start_matches('/'))
+            .unwrap_or(path);
+
+        ClobOrigin::from_path(path)
+    }
+
+    /// Check whether the origin is in the namespace 
+    ///
+    /// The namespace `public` selects all the records with public ids
+    pub fn is_in_namespace(&self, namespace: &str) -> bool {
+        match self {
+            ClobOrigin::Public { id: _ }                => namespace == "public",
+            ClobOrigin::Private { namespace: ns, id: _ } => *ns == namespace,
+            _                                           => false
+        }
+    }
+
+    /// The namespace this origin belongs to (for grouping and display)
+    pub fn namespace(&self) -> String {
+        match self {
+            ClobOrigin::Public { id: _ }                => "public".to_owned(),
+            ClobOrigin::Private { namespace, id: _ }    => format!("private/{}", namespace),
+            ClobOrigin::Label { label: _ }              => "records".to_owned(),
+            ClobOrigin::Quarantine { name: _ }          => QUARANTINE_DIR.to_owned(),
+            ClobOrigin::Header                          => "header".to_owned(),
+            ClobOrigin::Preamble                        => "preamble".to_owned()
+        }
+    }
+
+    /// The record id or label 
+    pub fn key(&self) -> &'a str {
+        match self {
+            ClobOrigin::Public { id } | ClobOrigin::Private { namespace: _, id } => id,
+            ClobOrigin::Label { label }                                         => label,
+            ClobOrigin::Quarantine { name }                                     => name,
+            ClobOrigin::Header                                                  => "header",
+            ClobOrigin::Preamble                                                => "preamble"
+        }
+    }
+}
+
 mod record_splitter;
 mod id_splitter;
+mod text_splitter;
 
+pub use id_splitter::{RecordId, clob_path_for_id, clob_paths_for_id};
+pub use record_splitter::clob_paths_for_label;
+
+
+/// The directories of the record types (`None` for the records of the first record tag)
+fn record_type_dirs(config: &DictionaryConfig) -> Vec<Option<String>> {
+    config.record_tags.iter().map(|tag| config.record_type_dir(tag)).collect()
+}
+
+/// The clob path within the directory of a record type
+fn in_record_type_dir(type_dir: Option<&str>, path: String) -> String {
+    match type_dir {
+        Some(dir) => format!("{}/{}", dir, path),
+        None      => path
+    }
+}
 
 impl Dictionary {
-    pub fn split(self) -> SplitterOutput {
+    /// The prefix depth at which no directory of the managed folder holds more than `max_files` clobs
+    ///
+    /// The depth is at least the configured one. A deeper level is only used as long as it splits 
+    /// up the largest directory. The directories are compared case-insensitively (the clobs that 
+    /// only differ in case are one clob)
+    pub fn required_prefix_depth(&self, max_files: usize) -> usize {
+        use std::collections::{HashMap, HashSet};
+
+        // the number of clobs in the largest directory at a depth
+        let largest_dir = |depth: usize| -> usize {
+            let mut config = self.config.clone();
+            config.prefix_depth = depth;
+            let dictionary = Dictionary::from_text(&config, self.text.clone());
+
+            let mut dirs : HashMap<String, HashSet<String>> = HashMap::new();
+            for clob in dictionary.split().0 {
+                let path = clob.path.to_ascii_lowercase();
+                let dir = path.rsplit_once('/').map(|(dir, _)| dir.to_owned()).unwrap_or_default();
+                dirs.entry(dir).or_default().insert(path);
+            }
+
+            dirs.values().map(HashSet::len).max().unwrap_or_default()
+        };
+
+        let mut depth = self.config.prefix_depth;
+        let mut files = largest_dir(depth);
+        while files > max_files && depth < MAX_PREFIX_DEPTH {
+            let deeper = largest_dir(depth + 1);
+            if deeper >= files { break }
+
+            depth += 1;
+            files = deeper;
+        }
+
+        depth
+    }
+}
+
+
+impl Dictionary {
+    pub fn split(&self) -> SplitterOutput<'_> {
+        use crate::profile::{self, Phase};
+        use crate::config::FileKind;
+
+        // the splitters scan the text as they go, so the scanning is timed in a separate pass
+        let path = self.config.path.clone();
+        let scanning = if profile::enabled() {
+            let start = std::time::Instant::now();
+            self.scanner().count();
+            let elapsed = start.elapsed();
+            profile::record(&path, Phase::Scan, elapsed);
+
+            elapsed
+        } else {
+            std::time::Duration::default()
+        };
+        let start = std::time::Instant::now();
+
+        // validate the records against the template and the type file (if any)
+        let mut template_issues = crate::toolbox::template::validate(&self.text, &self.config);
+        template_issues.extend(crate::toolbox::typ::validate(&self.text, &self.config));
+
         // lifecycle-managed dictionary
-        if self.config.lifecycle {
+        let (clobs, mut issues) = if self.config.lifecycle {
             panic!("Lifecycle dictionaries are not yet implemented")
         } 
+        // interlinear text
+        else if self.config.kind == FileKind::Text {
+            text_splitter::split(self)
+        }
         // id-managed dictionary
         else if self.config.unique_id { 
             id_splitter::split(self)
         } else {
             record_splitter::split(self)
+        };
+
+        if !template_issues.is_empty() {
+            issues.extend(template_issues);
+            issues.sort_by_key(|issue| issue.line());
         }
+
+        profile::record(&path, Phase::Split, start.elapsed().checked_sub(scanning).unwrap_or_default());
+
+        // the header is stored as it is, so that the file is reconstructed with the same header
+        let header = self.header_line().map(|line| {
+            Clob { path : HEADER_CLOB.to_owned(), content : format!("{}\n", line) }
+        });
+        // as are the lines before the first record
+        let preamble = self.preamble().map(|text| {
+            Clob { path : PREAMBLE_CLOB.to_owned(), content : text.to_owned() }
+        });
+        let clobs = header.into_iter().chain(preamble).chain(clobs);
+
+        // the clobs are stored with Unix line endings, whatever the platform the file was edited on
+        let clobs = clobs.map(|clob| {
+            if clob.content.contains('\r') {
+                Clob { content : clob.content.replace("\r\n", "\n"), ..clob }
+            } else {
+                clob
+            }
+        });
+
+        // the fields of the records are stored in the canonical order (if requested), so that 
+        // the fields reordered by Toolbox are not reported as changes
+        let field_order = self.config.field_order();
+        let clobs = clobs.map(move |clob| {
+            match ClobOrigin::from_path(&clob.path) {
+                ClobOrigin::Header | ClobOrigin::Preamble | ClobOrigin::Quarantine { .. } => clob,
+                _ => match field_order.as_ref() {
+                    Some(order) => Clob { content : order_fields(&clob.content, &self.config, order), ..clob },
+                    None        => clob
+                }
+            }
+        });
+
+        // the clobs that are too large are stored in several parts (except for the header and 
+        // the preamble, which have to stay in place)
+        let clobs : Box<dyn Iterator<Item=Clob>> = match self.config.max_clob_size {
+            Some(max_size) => Box::new(clobs.flat_map(move |clob| {
+                if clob.path == PREAMBLE_CLOB || clob.path == HEADER_CLOB { 
+                    vec!(clob) 
+                } else { 
+                    clob.into_parts(max_size) 
+                }
+            })),
+            None           => Box::new(clobs)
+        };
+
+        (clobs, issues)
     }    
 }
 
+
+
+// Sort the fields of the records in the text by their position in `order` 
+//
+// The record marker stays first and the fields that are not in `order` come after 
+// the ones that are (keeping their order). The lines that do not start with a marker 
+// belong to the preceding field, while the trailing blank lines stay at the end. The
+// fields of each sub-record (e.g. a subentry) are sorted separately, after its tag
+fn order_fields(text: &str, config: &DictionaryConfig, order: &[String]) -> String {
+    let rank = |line: &str| {
+        let marker = line.split_whitespace().next().unwrap_or("");
+
+        order.iter().position(|m| m == marker).unwrap_or(order.len())
+    };
+    let is_record_start = |line: &str| crate::toolbox::starts_record(line, &config.record_tags);
+
+    // the records as lists of fields
+    let mut records : Vec<Vec<Vec<&str>>> = vec!();
+    for line in text.split_inclusive('\n') {
+        match records.last_mut() {
+            Some(fields) if !is_record_start(line) => if line.starts_with('\\') {
+                fields.push(vec!(line))
+            } else {
+                fields.last_mut().unwrap().push(line)
+            },
+            _ => records.push(vec!(vec!(line)))
+        }
+    }
+
+    let mut ordered = String::with_capacity(text.len());
+    for mut fields in records {
+        // the blank lines at the end of the record
+        let last = fields.last_mut().unwrap();
+        let blank = last.iter().rev().take_while(|line| line.trim().is_empty()).count();
+        let trailing = last.split_off(last.len() - blank.min(last.len() - 1));
+
+        let mut start = 1;
+        while start <= fields.len() {
+            let end = fields[start ..].iter()
+                .position(|field| config.subrecord_tags.iter().any(|tag| field[0].split_whitespace().next() == Some(tag)))
+                .map_or(fields.len(), |i| start + i);
+
+            fields[start .. end].sort_by_key(|field| rank(field[0]));
+            start = end + 1;
+        }
+
+        ordered.extend(fields.into_iter().flatten());
+        ordered.extend(trailing);
+    }
+
+    ordered
+}
+
+
+// The invariants that make the clob trees byte-identical on Windows, macOS and Linux
+#[cfg(test)]
+mod tests {
+    use crate::config::DictionaryConfig;
+    use crate::toolbox::Dictionary;
+    use crate::repository::HEADER_CLOB;
+
+    fn config(unique_id: bool) -> DictionaryConfig {
+        let text = if unique_id {
+            "[[dictionary]]\nname = \"Lexical\"\npath = \"lex.txt\"\nrecord-tag = \"lex\"\n\
+             unique-id = true\nid-tag = \"id\"\n"
+        } else {
+            "[[dictionary]]\nname = \"Parsing\"\npath = \"parse.txt\"\nrecord-tag = \"lem\"\n"
+        };
+
+        DictionaryConfig::for_test(text)
+    }
+
+    // the record clobs (without the header)
+    fn split(config: &DictionaryConfig, text: &str) -> Vec<(String, String)> {
+        Dictionary::from_text(config, text.to_owned()).split().0
+            .filter(|clob| clob.path != HEADER_CLOB)
+            .map(|clob| (clob.path, clob.content))
+            .collect()
+    }
+
+    const LEXICON : &str = "\\_sh v3.0  400  Dictionary\n\n\
+        \\lex zebra\n\\id 30\n\\ge zebra\n\n\
+        \\lex apple\n\\id 4\n\\ge apple\n\n\
+        \\lex mango\n\\id 170\n\\ge mango\n\n\
+        \\lex kiwi\n\\id 4000\n\\ge kiwi\n";
+
+    const LEMMAS : &str = "\\_sh v3.0  400  Dictionary\n\n\
+        \\lem zebra\n\\ge zebra\n\n\
+        \\lem apple\n\\ge apple\n\n\
+        \\lem Mango\n\\ge mango\n\n\
+        \\lem kiwi\n\\ge kiwi\n";
+
+    #[test]
+    fn test_split_is_repeatable() {
+        for (config, text) in &[(config(true), LEXICON), (config(false), LEMMAS)] {
+            assert_eq!(split(config, text), split(config, text));
+        }
+    }
+
+    #[test]
+    fn test_clobs_are_ordered_by_path() {
+        for (config, text) in &[(config(true), LEXICON), (config(false), LEMMAS)] {
+            let paths = split(config, text).into_iter().map(|(path, _)| path).collect::<Vec<_>>();
+
+            let mut sorted = paths.clone();
+            sorted.sort_by_key(|path| path.to_ascii_lowercase());
+
+            assert_eq!(paths, sorted);
+        }
+    }
+
+    #[test]
+    fn test_line_endings_are_normalized() {
+        for (config, text) in &[(config(true), LEXICON), (config(false), LEMMAS)] {
+            let clobs = split(config, text);
+
+            assert_eq!(clobs, split(config, &text.replace('\n', "\r\n")));
+            assert!(clobs.iter().all(|(_, content)| !content.contains('\r')));
+        }
+    }
+
+    #[test]
+    fn test_paths_are_case_insensitive() {
+        let text = "\\_sh v3.0  400  Dictionary\n\n\
+            \\lex first\n\\id ab1\n\n\
+            \\lex second\n\\id AB1\n";
+
+        let clobs = split(&config(true), text);
+        assert_eq!(clobs.len(), 1);
+        assert!(clobs[0].1.contains("first") && clobs[0].1.contains("second"));
+
+        // labels are lowercased
+        let clobs = split(&config(false), LEMMAS);
+        assert!(clobs.iter().all(|(path, _)| *path == path.to_ascii_lowercase()));
+    }
+}
@@ -13,90 +13,146 @@
 use crate::toolbox::Dictionary;
 use crate::toolbox::scanner::*;
 
-use super::SplitterOutput;
+use super::{SplitterOutput, QUARANTINE_DIR};
+use crate::config::DictionaryConfig;
+
+/// The paths the clob that holds the records with the label can have (relative to the contents root)
+///
+/// These are the paths for every record type (the records of the other record types are stored
+/// in their own directories)
+pub fn clob_paths_for_label(config: &DictionaryConfig, label: &str) -> Vec<String> {
+    let label = crate::util::sanitize_label(label.trim(), config.path_encoding);
+
+    super::record_type_dirs(config).iter()
+        .map(|type_dir| clob_path(type_dir.as_deref(), &label, config, config.prefix_depth))
+        .collect()
+}
+
+/// The clob path for the sanitized label of a record type at a prefix depth
+fn clob_path(type_dir: Option<&str>, label: &str, config: &DictionaryConfig, depth: usize) -> String {
+    use crate::util::build_path_prefix;
+
+    if label.is_empty() {
+        format!("{}/label_missing.txt", QUARANTINE_DIR)
+    } else {
+        super::in_record_type_dir(type_dir, format!("{}/{}.txt", build_path_prefix(label, config.path_layout, depth), label))
+    }
+}
 
 /// A basic toolbox dictionary splitter (no uniqiue identifiers or lifecycle management)
-pub fn split(dictionary: Dictionary) -> SplitterOutput {
+pub fn split(dictionary: &Dictionary) -> SplitterOutput<'_> {
     use crate::repository::Clob;
     use crate::toolbox::ToolboxFileIssue;
     use multimap::MultiMap;
+    use itertools::Itertools;
 
     use crate::util::*;
+    use crate::repository::clob_path_problem;
+    use std::collections::HashMap;
   
     // deconstruct the dictionary
-    let mut scanner = dictionary.scanner;
-    let config  = dictionary.config;
-    let mut issues = dictionary.issues;
-
-    // report any lines orphaned before the first record
-    let mut orphaned_lines = vec!();
+    let mut scanner = dictionary.scanner();
+    let config  = &dictionary.config;
+    let mut issues = dictionary.issues.clone();
 
-    scanner.try_for_each(|token| {
-        use Token::*;
+    // skip the lines before the first record (they are stored as the preamble)
+    scanner.find(|(_, token)| *token == Token::RecordBegin);
 
-        match token {
-            // record start - quit the initial scan
-            (_, RecordBegin) => {
-                return None
-            },
-            (line, Tagged { tag: _, text: _}) | (line, Untagged { text: _ }) => {
-                issues.push(
-                    ToolboxFileIssue::LineBeforeFirstRecord {
-                        line: line.clone()
-                    }
-                );
-
-                orphaned_lines.push(line.text);
-            }, 
-            (_, Blank) => {
-                // push an empty line if it does not create lare blanks of space
-                if orphaned_lines.last().map(|line| !line.trim().is_empty()).unwrap_or(false) {
-                    orphaned_lines.push(""); 
-                }
-            }
-            _ => {
-            }
-        }
 
-        Some( () )
-    });
+    let mut clobs = MultiMap::new();
 
+    // the records that cannot be stored at the path of their label
+    let mut unsafe_path = vec!();
 
-    let mut clobs = MultiMap::new();
+    // the first record of every sanitized label (to detect different labels sharing a clob)
+    let mut first_records : HashMap<(Option<String>, String), Line> = HashMap::new();
     
-    // current record label
+    // current record label (and the directory of its record type)
+    let mut record_start = Line { line : 0, text : "" };
     let mut record_label = String::new();
+    let mut record_type  = None;
     
     for token in scanner {
         use Token::*;
 
         match token {
             // record start tag
-            (line, Tagged {tag, text}) if tag == config.record_tag => {
+            (line, Tagged {tag, text}) if config.record_tags.iter().any(|t| t == tag) => {
+                record_start = line.clone();
+                record_type = config.record_type_dir(tag);
+
                 // remove the trailing spaces
                 let text = text.trim();
                 if text.is_empty() {
                     issues.push(
                         ToolboxFileIssue::MissingRecordLabel { 
-                            line
+                            line: line.to_owned_line()
                         }
                     )    
                 }
 
                 // use the acii-only sanitized label
-                record_label = sanitize_label(text.trim());
+                record_label = sanitize_label(text.trim(), config.path_encoding);
+
+                // different labels can be sanitized to the same one
+                match first_records.get(&(record_type.clone(), record_label.clone())) {
+                    Some(first) if label_of(first, tag) != text && normalize_nfc(label_of(first, tag)) == normalize_nfc(text) => {
+                        issues.push(
+                            ToolboxFileIssue::NormalizationCollision {
+                                record : line.to_owned_line(),
+                                line   : line.to_owned_line(),
+                                other  : first.to_owned_line()
+                            }
+                        );
+                    },
+                    Some(first) if config.label_collisions.reports(label_of(first, tag), text) => {
+                        issues.push(
+                            ToolboxFileIssue::LabelCollision {
+                                record : line.to_owned_line(),
+                                other  : first.to_owned_line()
+                            }
+                        );
+                    },
+                    Some(_) => {},
+                    None    => { first_records.insert((record_type.clone(), record_label.clone()), line.clone()); }
+                }
+            },
+            // record tag within a subentry (the line stays in the record)
+            (line, NestedRecordTag { tag: _, text: _ }) => {
+                issues.push(
+                    ToolboxFileIssue::NestedRecordTag {
+                        record : record_start.to_owned_line(),
+                        line   : line.to_owned_line()
+                    }
+                )
             },
             // untagged line
             (line, Untagged {text:_}) => {
                 issues.push(
                     ToolboxFileIssue::UntaggedLine {
-                        line: line.clone()
+                        line: line.to_owned_line()
                     }
                 )
             },
             // record end — add new record
             (_, RecordEnd { body }) => {
-                clobs.insert(std::mem::take(&mut record_label), body);
+                let label = std::mem::take(&mut record_label);
+                let type_dir = record_type.take();
+                let path = clob_path(type_dir.as_deref(), &label, config, config.prefix_depth);
+
+                // the records are never written outside of the managed folder
+                if let Some(reason) = clob_path_problem(&path) {
+                    issues.push(
+                        ToolboxFileIssue::UnsafeClobPath {
+                            record : record_start.to_owned_line(),
+                            path,
+                            reason : reason.to_owned()
+                        }
+                    );
+                    unsafe_path.push(body);
+                } else {
+                    clobs.insert((type_dir, label), body);
+                }
             },
             _ => {
             }
@@ -104,14 +160,13 @@ pub fn split(dictionary: Dictionary) -> SplitterOutput {
     };
 
 
-    let result = clobs.into_iter().map(move |(label, records)| {
-        // build a path for the record
-        let path = if label.is_empty() {
-            "invalid/label_missing.txt".to_owned()
-        } else {
-            format!("{}/{}.txt", build_path_prefix(&label), &label)
-        };
+    // the clobs are ordered by their paths, so that the output does not depend on the 
+    // hashing of the labels
+    let clobs = clobs.into_iter()
+        .map(|((type_dir, label), records)| (clob_path(type_dir.as_deref(), &label, config, config.prefix_depth), records))
+        .sorted_by(|(a, _), (b, _)| a.cmp(b));
 
+    let result = clobs.map(move |(path, records)| {
         // build the clob contents by joining the records 
         // together
         // TODO: do we sort the records somehow?
@@ -119,26 +174,75 @@ pub fn split(dictionary: Dictionary) -> SplitterOutput {
     
         Clob { path, content }
      })
-    // add the orphaned lines
+    // add the records with unsafe paths
     .chain({
-        std::iter::once(orphaned_lines.join("\n")).map(|mut text| {
-            // add line end (if nessesary)
-            if !text.ends_with('\n') {
-                text.push('\n')
-            }
-
-            text
-        })
-        // ignore the orphaned lines block if it is empty
-        .filter(|text| {
-            !text.trim().is_empty()
-        })
-        // make it into a clob
-        .map(|content| {
-            Clob { path: "invalid/__.txt".to_owned(), content }
-        })
+        std::iter::once(unsafe_path.join("\n"))
+            .filter(|content| !content.is_empty())
+            .map(|content| Clob { path: format!("{}/unsafe_path.txt", QUARANTINE_DIR), content })
     });
 
     
     ( Box::new(result.map(Clob::validated)), issues )
 }
+
+// The label of a record (the text of its first line after the record tag)
+fn label_of<'a>(record: &Line<'a>, tag: &str) -> &'a str {
+    record.text.get(tag.len() ..).unwrap_or("").trim()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{DictionaryConfig, LabelCollisions};
+    use crate::toolbox::{Dictionary, ToolboxFileIssue};
+
+    fn config() -> DictionaryConfig {
+        let text = "[[dictionary]]\nname = \"Parsing\"\npath = \"parse.txt\"\nrecord-tag = \"lem\"\n";
+
+        DictionaryConfig::for_test(text)
+    }
+
+    #[test]
+    fn test_unsafe_labels_are_quarantined() {
+        // labels are sanitized, but can still be reserved names
+        let text = "\\_sh v3.0  400  Dictionary\n\n\\lem Aux\n\\ge help\n\n\\lem kiwi\n\\ge kiwi\n";
+
+        let dictionary = Dictionary::from_text(&config(), text.to_owned());
+        let (clobs, issues) = dictionary.split();
+        let paths = clobs.map(|clob| clob.path).collect::<Vec<_>>();
+
+        assert!(paths.contains(&"invalid/unsafe_path.txt".to_owned()));
+        assert!(!paths.iter().any(|path| path.to_ascii_lowercase().starts_with("aux")));
+        assert_eq!(issues.iter().filter(|issue| issue.kind() == "unsafe-clob-path").count(), 1);
+    }
+
+    #[test]
+    fn test_label_collisions_are_reported() {
+        let text = "\\_sh v3.0  400  Dictionary\n\n\
+            \\lem kapa\n\\ge one\n\n\
+            \\lem kápa\n\\ge two\n\n\
+            \\lem Kapa\n\\ge three\n\n\
+            \\lem kapa\n\\ge four\n";
+
+        let collisions = |sensitivity: LabelCollisions| -> Vec<(usize, usize)> {
+            let mut cfg = config();
+            cfg.label_collisions = sensitivity;
+
+            Dictionary::from_text(&cfg, text.to_owned()).split().1.into_iter().filter_map(|issue| {
+                match issue {
+                    ToolboxFileIssue::LabelCollision { record, other } => Some((record.line, other.line)),
+                    _ => None
+                }
+            })
+            .collect()
+        };
+
+        // the records still share a clob
+        let dictionary = Dictionary::from_text(&config(), text.to_owned());
+        assert_eq!(dictionary.split().0.filter(|clob| clob.path.ends_with("kapa.txt")).count(), 1);
+
+        assert_eq!(collisions(LabelCollisions::Accents), vec!((5, 2)));
+        assert_eq!(collisions(LabelCollisions::All), vec!((5, 2), (8, 2)));
+        assert!(collisions(LabelCollisions::Ignore).is_empty());
+    }
+}
@@ -0,0 +1,197 @@
+//
+// src/toolbox/dictionary/split/text_splitter.rs
+//
+// Splitter that handles interlinear texts
+//
+// The records of an interlinear text are references (e.g. `\ref`), which hold
+// bundles of aligned tiers: a word tier (e.g. `\tx`) followed by the morpheme
+// tiers (e.g. `\mb` and `\ge`). Every reference is stored with all of its tiers,
+// which are laid out as the labelled records of a dictionary (one CLOB per
+// reference label). In addition, the alignment of the tiers is validated: the
+// words must start at morpheme boundaries and the morpheme tiers must be aligned
+// with each other.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+
+use crate::toolbox::{Dictionary, ToolboxFileIssue};
+use crate::toolbox::scanner::*;
+
+use super::{SplitterOutput, record_splitter};
+
+/// An interlinear text splitter
+pub fn split(dictionary: &Dictionary) -> SplitterOutput<'_> {
+    let (clobs, mut issues) = record_splitter::split(dictionary);
+
+    issues.extend(validate_alignment(dictionary));
+    issues.sort_by_key(|issue| issue.line());
+
+    (clobs, issues)
+}
+
+
+/// Check the alignment of the tiers in every bundle of the text
+fn validate_alignment(dictionary: &Dictionary) -> Vec<ToolboxFileIssue> {
+    let tiers = &dictionary.config.tiers;
+    if tiers.len() < 2 { return vec!() }
+
+    let mut issues = vec!();
+
+    // the current record and the tier lines of the current bundle
+    let mut record : Option<Line> = None;
+    let mut bundle : Vec<(&str, Line)> = vec!();
+
+    let mut finish_bundle = |record: &Option<Line>, bundle: &mut Vec<(&str, Line)>| {
+        if let Some(record) = record {
+            issues.extend(check_bundle(record, bundle, tiers));
+        }
+        bundle.clear();
+    };
+
+    for (line, token) in dictionary.scanner() {
+        match token {
+            Token::RecordBegin => {
+                finish_bundle(&record, &mut bundle);
+                record = Some(line);
+            },
+            // the word tier starts a new bundle
+            Token::Tagged { tag, text: _ } if tag == tiers[0] => {
+                finish_bundle(&record, &mut bundle);
+                bundle.push((tag, line));
+            },
+            Token::Tagged { tag, text: _ } if !bundle.is_empty() && tiers.iter().any(|tier| tier == tag) => {
+                bundle.push((tag, line));
+            },
+            Token::RecordEnd { body: _ } => {
+                finish_bundle(&record, &mut bundle);
+                record = None;
+            },
+            _ => {}
+        }
+    }
+    finish_bundle(&record, &mut bundle);
+
+    issues
+}
+
+/// Check the alignment of the tiers in a bundle
+///
+/// The words must start where a morpheme starts and the other morpheme tiers must start
+/// their items where the first morpheme tier does
+fn check_bundle(record: &Line, bundle: &[(&str, Line)], tiers: &[String]) -> Vec<ToolboxFileIssue> {
+    let columns = |tier: &str| -> Option<Vec<usize>> {
+        bundle.iter().find(|(tag, _)| *tag == tier).map(|(tag, line)| item_columns(line.text, tag))
+    };
+
+    let words = columns(&tiers[0]).unwrap_or_default();
+    let morphemes = match columns(&tiers[1]) {
+        Some(morphemes) if !morphemes.is_empty() => morphemes,
+        _                                        => return vec!()
+    };
+
+    bundle.iter().filter(|(tag, _)| tiers[1 ..].iter().any(|tier| tier == tag)).filter_map(|(tag, line)| {
+        let items = item_columns(line.text, tag);
+
+        let (aligned, above) = if *tag == tiers[1] {
+            (words.iter().all(|column| items.contains(column)), &tiers[0])
+        } else {
+            (items.is_empty() || items == morphemes, &tiers[1])
+        };
+
+        if aligned {
+            None
+        } else {
+            Some(
+                ToolboxFileIssue::MisalignedTier {
+                    record : record.to_owned_line(),
+                    line   : line.to_owned_line(),
+                    tier   : above.clone()
+                }
+            )
+        }
+    })
+    .collect()
+}
+
+/// The columns (in characters) at which the items of a tier line start
+fn item_columns(line: &str, tag: &str) -> Vec<usize> {
+    let mut columns = vec!();
+    let mut after_space = false;
+
+    for (column, c) in line.chars().enumerate().skip(tag.chars().count()) {
+        if c.is_whitespace() {
+            after_space = true;
+        } else if after_space {
+            columns.push(column);
+            after_space = false;
+        }
+    }
+
+    columns
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::item_columns;
+    use crate::config::DictionaryConfig;
+    use crate::toolbox::{Dictionary, ToolboxFileIssue};
+
+    fn config() -> DictionaryConfig {
+        let text = "[[dictionary]]\nname = \"Stories\"\npath = \"stories.txt\"\nkind = \"text\"\n\
+                    record-tag = \"ref\"\ntiers = [\"tx\", \"mb\", \"ge\"]\n";
+
+        DictionaryConfig::for_test(text)
+    }
+
+    // the lines and tiers of the misaligned tiers in the text
+    fn misaligned(bundles: &str) -> Vec<(usize, String)> {
+        let text = format!("\\_sh v3.0  400  Text\n\n\\ref story.001\n{}\n", bundles);
+
+        Dictionary::from_text(&config(), text).split().1.into_iter().filter_map(|issue| {
+            match issue {
+                ToolboxFileIssue::MisalignedTier { record : _, line, tier } => Some((line.line, tier)),
+                _ => None
+            }
+        })
+        .collect()
+    }
+
+    #[test]
+    fn test_item_columns() {
+        assert_eq!(item_columns("\\tx  kapa   ŋa", "\\tx"), vec!(5, 12));
+        assert_eq!(item_columns("\\mb kapa  -ŋa ", "\\mb"), vec!(4, 10));
+        assert_eq!(item_columns("\\ge", "\\ge"), Vec::<usize>::new());
+        assert_eq!(item_columns("\\ge   ", "\\ge"), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_check_bundle() {
+        // the words start at morpheme boundaries and the glosses are aligned with the morphemes
+        assert!(misaligned("\\tx kapaŋa   mo\n\\mb kapa -ŋa mo\n\\ge dog  -PL this").is_empty());
+
+        // a word that does not start at a morpheme boundary
+        assert_eq!(
+            misaligned("\\tx kapaŋa  mo\n\\mb kapa -ŋa mo\n\\ge dog  -PL this"),
+            vec!((4, "\\tx".to_owned()))
+        );
+
+        // glosses that are not aligned with the morphemes
+        assert_eq!(
+            misaligned("\\tx kapaŋa   mo\n\\mb kapa -ŋa mo\n\\ge dog -PL  this"),
+            vec!((5, "\\mb".to_owned()))
+        );
+
+        // empty morpheme tiers are not checked
+        assert!(misaligned("\\tx kapaŋa  mo\n\\mb\n\\ge dog").is_empty());
+        assert!(misaligned("\\tx kapaŋa   mo\n\\mb kapa -ŋa mo\n\\ge").is_empty());
+
+        // every bundle is checked on its own
+        assert_eq!(
+            misaligned("\\tx kapa\n\\mb kapa\n\\ge dog\n\\tx mo\n\\mb  mo\n\\ge this"),
+            vec!((7, "\\tx".to_owned()), (8, "\\mb".to_owned()))
+        );
+    }
+}
@@ -0,0 +1,195 @@
+//
+// src/toolbox/encoding.rs
+//
+// Character encodings of Toolbox files
+//
+// Toolbox projects are often stored in legacy single-byte encodings. The
+// Toolbox files are decoded when loaded and encoded again when written to
+// the working directory, while the managed contents are always UTF-8.
+//
+// The decoding is lossless: the bytes that are not assigned in windows-1252
+// are mapped to the corresponding C1 control characters (as browsers do), so
+// that encoding the text again produces the original bytes.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::config::{DictionaryConfig, LineEndings, TextEncoding};
+use crate::error;
+
+use anyhow::Result;
+use std::path::Path;
+
+
+/// The characters of windows-1252 in the range 0x80-0x9F
+const WINDOWS_1252_HIGH : [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}'
+];
+
+
+impl TextEncoding {
+    /// The name of the encoding (as used in the configuration)
+    pub fn name(&self) -> &'static str {
+        match self {
+            TextEncoding::Utf8        => "utf-8",
+            TextEncoding::Windows1252 => "windows-1252",
+            TextEncoding::Latin1      => "iso-8859-1"
+        }
+    }
+
+    /// Decode the text
+    ///
+    /// Returns the offset of the first invalid byte on failure
+    pub fn decode(&self, data: Vec<u8>) -> Result<String, usize> {
+        match self {
+            TextEncoding::Utf8        => {
+                String::from_utf8(data).map_err(|err| err.utf8_error().valid_up_to())
+            },
+            TextEncoding::Windows1252 => {
+                Ok( data.into_iter().map(|byte| {
+                    match byte {
+                        0x80..=0x9F => WINDOWS_1252_HIGH[(byte - 0x80) as usize],
+                        _           => byte as char
+                    }
+                })
+                .collect() )
+            },
+            TextEncoding::Latin1      => {
+                Ok( data.into_iter().map(|byte| byte as char).collect() )
+            }
+        }
+    }
+
+    /// Encode the text
+    ///
+    /// Returns the first character that cannot be represented on failure
+    pub fn encode(&self, text: &str) -> Result<Vec<u8>, char> {
+        match self {
+            TextEncoding::Utf8        => {
+                Ok( text.as_bytes().to_vec() )
+            },
+            TextEncoding::Windows1252 => {
+                text.chars().map(|c| {
+                    match c as u32 {
+                        0x00..=0x7F | 0xA0..=0xFF => Ok( c as u8 ),
+                        _ => {
+                            WINDOWS_1252_HIGH.iter()
+                                .position(|&high| high == c)
+                                .map(|i| 0x80 + i as u8)
+                                .ok_or(c)
+                        }
+                    }
+                })
+                .collect()
+            },
+            TextEncoding::Latin1      => {
+                text.chars().map(|c| {
+                    if (c as u32) <= 0xFF { Ok( c as u8 ) } else { Err( c ) }
+                })
+                .collect()
+            }
+        }
+    }
+}
+
+
+impl DictionaryConfig {
+    /// Decode the contents of the Toolbox file at `path`
+    pub fn decode_text(&self, path: &Path, data: Vec<u8>) -> Result<String> {
+        self.encoding.decode(data).map_err(|offset| {
+            error::InvalidEncoding {
+                path     : path.to_owned(),
+                encoding : self.encoding.name(),
+                offset
+            }
+            .into()
+        })
+    }
+
+    /// Encode the managed contents (UTF-8) in the encoding of the Toolbox file
+    ///
+    /// The line endings are converted to the ones of the Toolbox file as well
+    pub fn encode_text(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        let data = match (self.line_endings, self.staged_crlf) {
+            (LineEndings::Crlf, _) | (LineEndings::Preserve, true) => to_crlf(data),
+            (LineEndings::Lf, _)                                   => to_lf(data),
+            // the managed contents already use Unix line endings
+            (LineEndings::Preserve, false)                         => data
+        };
+
+        // the contents are already in the right encoding
+        if self.encoding == TextEncoding::Utf8 {
+            return Ok( data );
+        }
+
+        self.encoding.encode(&String::from_utf8_lossy(&data)).map_err(|character| {
+            error::UnencodableCharacter {
+                path      : self.path.clone().into(),
+                encoding  : self.encoding.name(),
+                character
+            }
+            .into()
+        })
+    }
+}
+
+
+// Replace the Windows line endings by Unix line endings
+fn to_lf(data: Vec<u8>) -> Vec<u8> {
+    if !data.windows(2).any(|pair| pair == b"\r\n") {
+        return data;
+    }
+
+    let mut converted = Vec::with_capacity(data.len());
+    for (i, byte) in data.iter().enumerate() {
+        if *byte == b'\r' && data.get(i + 1) == Some(&b'\n') { continue }
+
+        converted.push(*byte);
+    }
+
+    converted
+}
+
+// Replace the Unix line endings by Windows line endings (the text might already 
+// contain some Windows line endings, e.g. when it was read from the working file)
+fn to_crlf(data: Vec<u8>) -> Vec<u8> {
+    let data = to_lf(data);
+
+    let mut converted = Vec::with_capacity(data.len() + data.len()/32);
+    for byte in data {
+        if byte == b'\n' { converted.push(b'\r') }
+
+        converted.push(byte);
+    }
+
+    converted
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::WINDOWS_1252_HIGH;
+    use crate::config::TextEncoding;
+
+    #[test]
+    fn test_windows_1252_round_trip() {
+        let bytes = (0 ..= 0xFF_u8).collect::<Vec<_>>();
+
+        // every byte decodes to a character that encodes back to it
+        let text = TextEncoding::Windows1252.decode(bytes.clone()).unwrap();
+        assert_eq!(text.chars().count(), 256);
+        assert_eq!(TextEncoding::Windows1252.encode(&text).unwrap(), bytes);
+
+        assert_eq!(TextEncoding::Windows1252.decode(vec!(0x80, 0x8D, 0x9F)).unwrap(), "€\u{8D}Ÿ");
+        assert_eq!(TextEncoding::Windows1252.encode("‘ŋ’"), Err('ŋ'));
+
+        // the table has no duplicates (which would break the round trip)
+        for (i, c) in WINDOWS_1252_HIGH.iter().enumerate() {
+            assert_eq!(WINDOWS_1252_HIGH.iter().position(|other| other == c), Some(i));
+        }
+    }
+}
@@ -0,0 +1,434 @@
+//
+// src/toolbox/import.rs
+//
+// Conversion of external dictionary data into Toolbox records
+//
+// Tables (CSV or TSV, as written by git toolbox export) have a header row with
+// the field markers and one record per row. The repeated values of a field are
+// separated by a semicolon followed by a space.
+//
+// LIFT (the XML format of FieldWorks and other lexicon tools) entries are mapped
+// to the MDF fields: the lexical unit to the record tag, the entry id to the ID
+// field, the citation form to \lc, the pronunciation to \ph, the grammatical
+// info of a sense to \ps, its glosses to \ge, its definitions to \de, the
+// examples and their translations to \xv and \xe, the notes to \nt and the
+// custom fields to the marker given by their type.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::config::DictionaryConfig;
+
+
+/// A record converted from external data, as a list of (marker, value) with the
+/// record tag first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportedRecord {
+    pub fields : Vec<(String, String)>
+}
+
+impl ImportedRecord {
+    /// The values of the field with the given marker
+    ///
+    /// The lines of a multi-line value are joined by spaces (as in the values of the 
+    /// dictionary records)
+    pub fn values<'a>(&'a self, marker: &'a str) -> impl Iterator<Item=String> + 'a {
+        self.fields.iter()
+            .filter(move |(tag, _)| tag == marker)
+            .map(|(_, value)| value_lines(value).join(" "))
+    }
+
+    /// The record text
+    ///
+    /// With `continuation_lines`, the lines of a multi-line value are written as the 
+    /// continuation lines of the field (as Toolbox wraps them), otherwise they are joined 
+    /// by spaces
+    pub fn text(&self, continuation_lines: bool) -> String {
+        self.fields.iter().map(|(marker, value)| {
+            let lines = value_lines(value);
+
+            // a line that starts with a backslash would be read as a field of its own
+            let value = if continuation_lines && !lines.iter().any(|line| line.starts_with('\\')) {
+                lines.join("\n")
+            } else {
+                lines.join(" ")
+            };
+
+            if value.is_empty() {
+                format!("{}\n", marker)
+            } else {
+                format!("{} {}\n", marker, value)
+            }
+        })
+        .collect()
+    }
+
+    // Move the first record tag to the front (the record is dropped if there is none)
+    fn normalize(mut self, config: &DictionaryConfig) -> Option<Self> {
+        let i = self.fields.iter().position(|(marker, _)| config.record_tags.contains(marker))?;
+        let field = self.fields.remove(i);
+        self.fields.insert(0, field);
+
+        Some( self )
+    }
+}
+
+
+// The non-empty lines of a value (without the surrounding whitespace)
+fn value_lines(value: &str) -> Vec<&str> {
+    value.lines().map(|line| line.trim()).filter(|line| !line.is_empty()).collect()
+}
+
+
+/// An error in the imported data, with the (1-based) line number
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportError {
+    pub line : usize,
+    pub msg  : String
+}
+
+
+/// The result of an import: the records and the number of skipped records
+/// (which do not have a record tag)
+#[derive(Debug)]
+pub struct Imported {
+    pub records : Vec<ImportedRecord>,
+    pub skipped : usize
+}
+
+fn imported(records: Vec<ImportedRecord>, config: &DictionaryConfig) -> Imported {
+    let total = records.len();
+    let records = records.into_iter().filter_map(|record| record.normalize(config)).collect::<Vec<_>>();
+
+    Imported { skipped : total - records.len(), records }
+}
+
+
+/// Read the records from a table with the given separator (`,` for CSV, `\t` for TSV)
+pub fn parse_table(text: &str, separator: char, config: &DictionaryConfig) -> Result<Imported, ImportError> {
+    let mut rows = if separator == ',' {
+        csv_rows(text)?
+    } else {
+        text.lines().enumerate()
+            .map(|(i, line)| (i + 1, line.split(separator).map(|cell| cell.to_owned()).collect()))
+            .collect()
+    };
+    rows.retain(|(_, cells)| cells.iter().any(|cell| !cell.trim().is_empty()));
+
+    if rows.is_empty() {
+        return Ok( Imported { records : vec!(), skipped : 0 } )
+    }
+
+    // the header row with the markers (with or without the backslash)
+    let markers = rows.remove(0).1.iter()
+        .map(|marker| format!("\\{}", marker.trim().trim_start_matches('\\')))
+        .collect::<Vec<_>>();
+
+    let mut records = vec!();
+    for (line, cells) in rows {
+        if cells.len() > markers.len() {
+            return Err( ImportError {
+                line,
+                msg : format!("the row has {} cells, but there are only {} markers", cells.len(), markers.len())
+            })
+        }
+
+        let fields = markers.iter().zip(cells.iter()).flat_map(|(marker, cell)| {
+            cell.split("; ")
+                .map(|value| value.trim())
+                .filter(|value| !value.is_empty())
+                .map(move |value| (marker.clone(), value.to_owned()))
+        })
+        .collect();
+
+        records.push(ImportedRecord { fields });
+    }
+
+    Ok( imported(records, config) )
+}
+
+// The rows of a CSV text (RFC 4180) with the line they start on
+fn csv_rows(text: &str) -> Result<Vec<(usize, Vec<String>)>, ImportError> {
+    let mut rows = vec!();
+    let mut row = vec!();
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut line = 1;
+    let mut row_line = 1;
+
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    cell.push('"');
+                } else {
+                    quoted = false;
+                }
+            },
+            '"' if cell.is_empty() => quoted = true,
+            ',' if !quoted => row.push(std::mem::take(&mut cell)),
+            '\r' if !quoted => {},
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut cell));
+                rows.push((row_line, std::mem::take(&mut row)));
+
+                line += 1;
+                row_line = line;
+            },
+            c => {
+                if c == '\n' {
+                    line += 1;
+                }
+                cell.push(c);
+            }
+        }
+    }
+
+    if quoted {
+        return Err( ImportError { line : row_line, msg : "the quoted cell is not closed".to_owned() } )
+    }
+    if !cell.is_empty() || !row.is_empty() {
+        row.push(cell);
+        rows.push((row_line, row));
+    }
+
+    Ok( rows )
+}
+
+
+/// Read the entries of a LIFT file
+pub fn parse_lift(text: &str, config: &DictionaryConfig) -> Result<Imported, ImportError> {
+    use quick_xml::{Reader, XmlVersion};
+    use quick_xml::escape::resolve_predefined_entity;
+    use quick_xml::events::{Event, BytesStart};
+
+    // the whitespace within the text elements is kept
+    let mut reader = Reader::from_str(text);
+    reader.config_mut().trim_text(false);
+
+    let error = |offset: u64, msg: String| {
+        let offset = (offset as usize).min(text.len());
+        let line = text.as_bytes()[.. offset].iter().filter(|b| **b == b'\n').count() + 1;
+
+        ImportError { line, msg }
+    };
+
+    let attribute = |element: &BytesStart, name: &str| -> Option<String> {
+        element.attributes()
+            .filter_map(|attr| attr.ok())
+            .find(|attr| attr.key.as_ref() == name)
+            .and_then(|attr| attr.normalized_value(XmlVersion::Implicit1_0).ok().map(|value| value.into_owned()))
+    };
+
+    let record_tag = config.record_tag.clone();
+    let id_tag = config.id_tag.clone();
+
+    let mut records = vec!();
+    let mut current : Option<ImportedRecord> = None;
+    // the open elements within the entry
+    let mut stack : Vec<String> = vec!();
+    // the type of the custom field
+    let mut field_type = None;
+    // the text of the current <text> element
+    let mut value : Option<String> = None;
+
+    loop {
+        let event = reader.read_event().map_err(|err| error(reader.error_position(), err.to_string()))?;
+
+        match event {
+            Event::Start(ref element) | Event::Empty(ref element) => {
+                let name = element.local_name().as_ref().to_owned();
+                let empty = matches!(event, Event::Empty(_));
+
+                match name.as_str() {
+                    // the deleted entries are skipped
+                    "entry" if attribute(element, "dateDeleted").is_none() => {
+                        let mut record = ImportedRecord { fields : vec!() };
+                        if let (Some(id_tag), Some(id)) = (&id_tag, attribute(element, "id")) {
+                            record.fields.push((id_tag.clone(), id));
+                        }
+                        current = Some(record);
+                    },
+                    "grammatical-info" => if let (Some(record), Some(ps)) = (current.as_mut(), attribute(element, "value")) {
+                        record.fields.push(("\\ps".to_owned(), ps));
+                    },
+                    "field" => {
+                        field_type = attribute(element, "type");
+                    },
+                    "text" if current.is_some() => {
+                        value = Some(String::new());
+                    },
+                    _ => {}
+                }
+
+                if !empty && current.is_some() {
+                    stack.push(name);
+                }
+            },
+            Event::Text(ref text) => {
+                if let Some(value) = value.as_mut() {
+                    value.push_str(&text.xml10_content());
+                }
+            },
+            // the character and entity references within the text
+            Event::GeneralRef(ref reference) => {
+                if let Some(value) = value.as_mut() {
+                    let position = reader.buffer_position();
+                    match reference.resolve_char_ref().map_err(|err| error(position, err.to_string()))? {
+                        Some(c) => value.push(c),
+                        None    => {
+                            let entity = reference.xml10_content();
+                            let resolved = resolve_predefined_entity(&entity).ok_or_else(|| {
+                                error(position, format!("unknown entity &{};", entity))
+                            })?;
+
+                            value.push_str(resolved);
+                        }
+                    }
+                }
+            },
+            Event::CData(ref text) => {
+                if let Some(value) = value.as_mut() {
+                    value.push_str(&text.xml10_content());
+                }
+            },
+            Event::End(ref element) => {
+                let name = element.local_name().as_ref().to_owned();
+
+                if name == "text" {
+                    let marker = match stack.iter().rev().skip(1).take(3).map(|s| s.as_str()).collect::<Vec<_>>().as_slice() {
+                        ["form", "lexical-unit", ..]          => Some(record_tag.clone()),
+                        ["form", "citation", ..]              => Some("\\lc".to_owned()),
+                        ["form", "pronunciation", ..]         => Some("\\ph".to_owned()),
+                        ["gloss", ..]                         => Some("\\ge".to_owned()),
+                        ["form", "definition", ..]            => Some("\\de".to_owned()),
+                        ["form", "translation", "example"]    => Some("\\xe".to_owned()),
+                        ["form", "example", ..]               => Some("\\xv".to_owned()),
+                        ["form", "note", ..]                  => Some("\\nt".to_owned()),
+                        ["form", "field", ..]                 => field_type.as_ref().map(|t| format!("\\{}", t)),
+                        _                                     => None
+                    };
+
+                    if let (Some(record), Some(marker), Some(value)) = (current.as_mut(), marker, value.take()) {
+                        // only the first form of the lexical unit is the label
+                        if marker != record_tag || record.values(&record_tag).next().is_none() {
+                            record.fields.push((marker, value.trim().to_owned()));
+                        }
+                    }
+                }
+
+                stack.pop();
+                if name == "entry" {
+                    records.extend(current.take());
+                    stack.clear();
+                }
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok( imported(records, config) )
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{ImportError, ImportedRecord, parse_lift, parse_table};
+    use crate::config::DictionaryConfig;
+
+    fn config() -> DictionaryConfig {
+        let text = "[[dictionary]]\nname = \"Lexical\"\npath = \"lex.txt\"\nrecord-tag = \"lex\"\n\
+                    unique-id = true\nid-tag = \"id\"\n";
+
+        DictionaryConfig::for_test(text)
+    }
+
+    fn record(fields: &[(&str, &str)]) -> ImportedRecord {
+        ImportedRecord { fields : fields.iter().map(|(m, v)| (m.to_string(), v.to_string())).collect() }
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let text = "lex,\\id,ge\r\n\
+            dog,1,dog; hound\r\n\
+            \"say \"\"hi\"\"\",2,\"greet,\nwelcome\"\r\n\
+            ,3,nameless\r\n\
+            ,,\r\n";
+
+        let imported = parse_table(text, ',', &config()).unwrap();
+        assert_eq!(imported.records, vec!(
+            record(&[("\\lex", "dog"), ("\\id", "1"), ("\\ge", "dog"), ("\\ge", "hound")]),
+            record(&[("\\lex", "say \"hi\""), ("\\id", "2"), ("\\ge", "greet,\nwelcome")])
+        ));
+        // the record without a record tag
+        assert_eq!(imported.skipped, 1);
+
+        // the rows are reported by the line they start on
+        assert_eq!(
+            parse_table("lex,id\ndog,1\n\"cat\nmouse\",2,3\n", ',', &config()).err(),
+            Some(ImportError { line : 3, msg : "the row has 3 cells, but there are only 2 markers".to_owned() })
+        );
+        assert_eq!(
+            parse_table("lex,id\ndog,1\n\"cat,2\n", ',', &config()).err(),
+            Some(ImportError { line : 3, msg : "the quoted cell is not closed".to_owned() })
+        );
+    }
+
+    #[test]
+    fn test_parse_tsv() {
+        let imported = parse_table("ge\tlex\ncat\tcat\n", '\t', &config()).unwrap();
+
+        // the record tag is moved to the front
+        assert_eq!(imported.records, vec!(record(&[("\\lex", "cat"), ("\\ge", "cat")])));
+    }
+
+    #[test]
+    fn test_parse_lift() {
+        let text = r#"<?xml version="1.0" encoding="UTF-8"?>
+<lift version="0.13">
+  <entry id="7">
+    <lexical-unit><form lang="x"><text>fish &amp; chips</text></form><form lang="y"><text>fisch</text></form></lexical-unit>
+    <sense>
+      <grammatical-info value="n"/>
+      <gloss lang="en"><text>fish&#x2019;s</text></gloss>
+      <definition><form lang="en"><text><![CDATA[a <meal>]]></text></form></definition>
+      <example>
+        <form lang="x"><text>ex</text></form>
+        <translation><form lang="en"><text>example</text></form></translation>
+      </example>
+    </sense>
+    <field type="dt"><form lang="en"><text>2020</text></form></field>
+  </entry>
+  <entry id="8" dateDeleted="2021-01-01"><lexical-unit><form lang="x"><text>gone</text></form></lexical-unit></entry>
+</lift>"#;
+
+        let imported = parse_lift(text, &config()).unwrap();
+        assert_eq!(imported.records, vec!(
+            record(&[
+                ("\\lex", "fish & chips"), ("\\id", "7"), ("\\ps", "n"), ("\\ge", "fish’s"), ("\\de", "a <meal>"),
+                ("\\xv", "ex"), ("\\xe", "example"), ("\\dt", "2020")
+            ])
+        ));
+        assert_eq!(imported.skipped, 0);
+
+        assert_eq!(parse_lift("<lift>\n<entry><text>a &nope; b</text></entry>\n</lift>", &config()).unwrap_err().line, 2);
+        assert_eq!(parse_lift("<lift>\n<entry>\n</lift>", &config()).unwrap_err().line, 3);
+    }
+
+    #[test]
+    fn test_record_text() {
+        let record = record(&[("\\lex", "dog"), ("\\de", " a four-legged\n  animal "), ("\\nt", "\n")]);
+
+        assert_eq!(record.text(false), "\\lex dog\n\\de a four-legged animal\n\\nt\n");
+        assert_eq!(record.text(true), "\\lex dog\n\\de a four-legged\nanimal\n\\nt\n");
+        assert_eq!(record.values("\\de").collect::<Vec<_>>(), vec!("a four-legged animal"));
+
+        // a continuation line cannot start with a backslash
+        let record = super::ImportedRecord { fields : vec!(("\\de".to_owned(), "see\n\\lex".to_owned())) };
+        assert_eq!(record.text(true), "\\de see \\lex\n");
+    }
+}
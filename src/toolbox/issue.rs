@@ -3,50 +3,186 @@
 //
 // All issues that we can detect in Toolbox files are defined here. 
 //
+// The issues are serializable (with their kind, severity, line numbers and the
+// record they occur in), for machine-readable reports.
+//
 // (C) 2020 Taras Zakharko
 //
 // This code is licensed under GPL 3.0
 
 
-use super::scanner::Line;
+use super::scanner::OwnedLine;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// The severity of an issue
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Severity {
+    /// The affected content cannot be managed properly (e.g. it is quarantined)
+    Error,
+    /// The content is managed, but should be reviewed
+    Warning,
+    /// The content is fine, the issue is only reported for information
+    Info
+}
+
+impl Severity {
+    /// The name of the severity (as used in the configuration and the reports)
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Error   => "error",
+            Severity::Warning => "warning",
+            Severity::Info    => "info"
+        }
+    }
+}
+
+/// The kinds of all issues (see `ToolboxFileIssue::kind()`)
+pub const ISSUE_KINDS : &[&str] = &[
+    "untagged-line",
+    "missing-record-label",
+    "missing-id",
+    "invalid-id",
+    "extraneous-id",
+    "ambiguous-id",
+    "missing-dictionary-header",
+    "unexpected-database-type",
+    "missing-field",
+    "repeated-field",
+    "invalid-field-value",
+    "misaligned-tier",
+    "unknown-marker",
+    "label-collision",
+    "normalization-collision",
+    "nested-record-tag",
+    "unsafe-clob-path",
+    "validator-issue",
+    "validator-failed"
+];
+
+lazy_static::lazy_static! {
+    // the severities configured for the issue kinds (see `issue-severity`)
+    static ref SEVERITY_POLICY : RwLock<HashMap<String, Severity>> = RwLock::new(HashMap::new());
+}
+
+/// Set the severities of the issue kinds (the other kinds keep their default severity)
+pub fn set_severity_policy(policy: &HashMap<String, Severity>) {
+    *SEVERITY_POLICY.write().expect("fatal: severity policy poisoned") = policy.clone();
+}
 
 /// An error in a toolbox file's contents
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ToolboxFileIssue {
-    /// Content occurs before the first record
-    LineBeforeFirstRecord { 
-        line: Line<'static>
-    }, 
     /// Untagged line in a dictionary file
     UntaggedLine { 
-        line: Line<'static> 
+        line: OwnedLine 
     }, 
     /// Record without a label
     MissingRecordLabel { 
-        line : Line<'static> 
+        line : OwnedLine 
     }, 
     /// Missing ID
     MissingID { 
-        line : Line<'static> 
+        line : OwnedLine 
     },
     /// Invalid ID
     InvalidID { 
-        record : Line<'static>,
-        line   : Line<'static>
+        record : OwnedLine,
+        line   : OwnedLine
     },
     /// Multiple IDs per record
     ExtraneousID {
-        record : Line<'static>,
-        line   : Line<'static>  
+        record : OwnedLine,
+        line   : OwnedLine  
     },
     /// Ambiguous ID (same id found in multiple records)
     AmbiguousID {
-        record : Line<'static>,
-        line   : Line<'static>  
+        record : OwnedLine,
+        line   : OwnedLine  
     },
     /// Missing dictionary header
     MissingDictionaryHeader {
         line : usize
+    },
+    /// The header names a different database type than the one configured for the file
+    UnexpectedDatabaseType {
+        line     : OwnedLine,
+        expected : String
+    },
+    /// A required template field is missing or empty
+    MissingField {
+        record : OwnedLine,
+        marker : String
+    },
+    /// A non-repeatable template field occurs multiple times in a record
+    RepeatedField {
+        record : OwnedLine,
+        line   : OwnedLine
+    },
+    /// The field value does not match the type declared by the template
+    InvalidFieldValue {
+        record   : OwnedLine,
+        line     : OwnedLine,
+        expected : String
+    },
+    /// A tier of an interlinear text is not aligned with the tier above it
+    MisalignedTier {
+        record : OwnedLine,
+        line   : OwnedLine,
+        tier   : String
+    },
+    /// The marker is not defined by the type file of the dictionary
+    UnknownMarker {
+        record : OwnedLine,
+        line   : OwnedLine
+    },
+    /// The label of the record differs from the label of an earlier record, but both are 
+    /// stored in the same clob (e.g. `kápa` and `kapa`)
+    LabelCollision {
+        record : OwnedLine,
+        other  : OwnedLine
+    },
+    /// The ID or label of the record only differs from the one of an earlier record in its
+    /// Unicode normalization (e.g. a composed and a decomposed `é`), both are stored in the
+    /// same clob
+    NormalizationCollision {
+        record : OwnedLine,
+        line   : OwnedLine,
+        other  : OwnedLine
+    },
+    /// The ID of the record differs from the ID of an earlier record, but both are encoded
+    /// to the same clob path (e.g. `kápa` and `kapa`), so the records share the clob
+    IdCollision {
+        record : OwnedLine,
+        line   : OwnedLine,
+        other  : OwnedLine
+    },
+    /// A record tag within a sub-record (e.g. a subentry) that is not separated from it by a 
+    /// blank line (the line stays in the record)
+    NestedRecordTag {
+        record : OwnedLine,
+        line   : OwnedLine
+    },
+    /// The ID or label of the record maps to a path outside of the managed folder or to a 
+    /// reserved file name (the record is quarantined)
+    UnsafeClobPath {
+        record : OwnedLine,
+        path   : String,
+        reason : String
+    },
+    /// An issue reported by an external validator (with the severity configured for it)
+    ValidatorIssue {
+        validator : String,
+        record    : OwnedLine,
+        line      : OwnedLine,
+        message   : String,
+        severity  : Severity
+    },
+    /// An external validator could not be run or reported an issue outside of the records
+    ValidatorFailed {
+        validator : String,
+        msg       : String
     }
 }
 
@@ -68,95 +204,373 @@ use std::fmt;
 
 
 impl ToolboxFileIssue {
-    pub fn line(&self) -> usize {
+    /// The kind of the issue (e.g. `invalid-id`)
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ToolboxFileIssue::UntaggedLine { .. }            => "untagged-line",
+            ToolboxFileIssue::MissingRecordLabel { .. }      => "missing-record-label",
+            ToolboxFileIssue::MissingID { .. }               => "missing-id",
+            ToolboxFileIssue::InvalidID { .. }               => "invalid-id",
+            ToolboxFileIssue::ExtraneousID { .. }            => "extraneous-id",
+            ToolboxFileIssue::AmbiguousID { .. }             => "ambiguous-id",
+            ToolboxFileIssue::MissingDictionaryHeader { .. } => "missing-dictionary-header",
+            ToolboxFileIssue::UnexpectedDatabaseType { .. }  => "unexpected-database-type",
+            ToolboxFileIssue::MissingField { .. }            => "missing-field",
+            ToolboxFileIssue::RepeatedField { .. }           => "repeated-field",
+            ToolboxFileIssue::InvalidFieldValue { .. }       => "invalid-field-value",
+            ToolboxFileIssue::MisalignedTier { .. }          => "misaligned-tier",
+            ToolboxFileIssue::UnknownMarker { .. }           => "unknown-marker",
+            ToolboxFileIssue::LabelCollision { .. }          => "label-collision",
+            ToolboxFileIssue::NormalizationCollision { .. }  => "normalization-collision",
+            ToolboxFileIssue::IdCollision { .. }             => "id-collision",
+            ToolboxFileIssue::NestedRecordTag { .. }         => "nested-record-tag",
+            ToolboxFileIssue::UnsafeClobPath { .. }          => "unsafe-clob-path",
+            ToolboxFileIssue::ValidatorIssue { .. }          => "validator-issue",
+            ToolboxFileIssue::ValidatorFailed { .. }         => "validator-failed"
+        }
+    }
+
+    /// The severity of the issue (as configured for its kind)
+    pub fn severity(&self) -> Severity {
+        let policy = SEVERITY_POLICY.read().expect("fatal: severity policy poisoned");
+
+        policy.get(self.kind()).copied().unwrap_or_else(|| self.default_severity())
+    }
+
+    fn default_severity(&self) -> Severity {
+        match self {
+            ToolboxFileIssue::ExtraneousID { .. }            | 
+            ToolboxFileIssue::MissingDictionaryHeader { .. } |
+            ToolboxFileIssue::UnexpectedDatabaseType { .. }  |
+            ToolboxFileIssue::MissingField { .. }            |
+            ToolboxFileIssue::RepeatedField { .. }           |
+            ToolboxFileIssue::InvalidFieldValue { .. }       |
+            ToolboxFileIssue::MisalignedTier { .. }          |
+            ToolboxFileIssue::UnknownMarker { .. }           |
+            ToolboxFileIssue::LabelCollision { .. }          |
+            ToolboxFileIssue::NormalizationCollision { .. }  |
+            ToolboxFileIssue::IdCollision { .. }             |
+            ToolboxFileIssue::NestedRecordTag { .. }         => Severity::Warning,
+            ToolboxFileIssue::ValidatorIssue { severity, .. } => *severity,
+            _                                                => Severity::Error
+        }
+    }
+
+    /// The first line of the record the issue occurs in (if known)
+    pub fn record(&self) -> Option<&OwnedLine> {
+        match self {
+            ToolboxFileIssue::MissingRecordLabel { line }      |
+            ToolboxFileIssue::MissingID { line }               => Some(line),
+            ToolboxFileIssue::InvalidID { record, line : _ }   |  
+            ToolboxFileIssue::ExtraneousID { record, line : _} |
+            ToolboxFileIssue::AmbiguousID { record, line : _ } |
+            ToolboxFileIssue::MissingField { record, marker : _ } |
+            ToolboxFileIssue::RepeatedField { record, line : _ } |
+            ToolboxFileIssue::InvalidFieldValue { record, line : _, expected : _ } |
+            ToolboxFileIssue::MisalignedTier { record, line : _, tier : _ } |
+            ToolboxFileIssue::UnknownMarker { record, line : _ } |
+            ToolboxFileIssue::LabelCollision { record, other : _ } |
+            ToolboxFileIssue::NormalizationCollision { record, line : _, other : _ } |
+            ToolboxFileIssue::IdCollision { record, line : _, other : _ } |
+            ToolboxFileIssue::NestedRecordTag { record, line : _ } |
+            ToolboxFileIssue::UnsafeClobPath { record, path : _, reason : _ } |
+            ToolboxFileIssue::ValidatorIssue { record, .. } => Some(record),
+            _ => None
+        }
+    }
+
+    /// The line the issue refers to (if any)
+    fn issue_line(&self) -> Option<&OwnedLine> {
         match self {
-            ToolboxFileIssue::LineBeforeFirstRecord { line }   |
             ToolboxFileIssue::UntaggedLine { line }            |
             ToolboxFileIssue::MissingRecordLabel { line }      |
             ToolboxFileIssue::MissingID { line }               |
+            ToolboxFileIssue::UnexpectedDatabaseType { line, expected : _ } |
             ToolboxFileIssue::InvalidID { record : _, line }   |  
             ToolboxFileIssue::ExtraneousID { record : _, line} |
-            ToolboxFileIssue::AmbiguousID { record : _, line }  => {
-                line.line
-            },
-            ToolboxFileIssue::MissingDictionaryHeader { line } => {
-                *line
-            }
+            ToolboxFileIssue::AmbiguousID { record : _, line } |
+            ToolboxFileIssue::MissingField { record : line, marker : _ } |
+            ToolboxFileIssue::RepeatedField { record : _, line } |
+            ToolboxFileIssue::InvalidFieldValue { record : _, line, expected : _ } |
+            ToolboxFileIssue::MisalignedTier { record : _, line, tier : _ } |
+            ToolboxFileIssue::UnknownMarker { record : _, line } |
+            ToolboxFileIssue::LabelCollision { record : line, other : _ } |
+            ToolboxFileIssue::NormalizationCollision { record : _, line, other : _ } |
+            ToolboxFileIssue::IdCollision { record : _, line, other : _ } |
+            ToolboxFileIssue::NestedRecordTag { record : _, line } |
+            ToolboxFileIssue::UnsafeClobPath { record : line, path : _, reason : _ } |
+            ToolboxFileIssue::ValidatorIssue { line, .. } => Some(line),
+            ToolboxFileIssue::MissingDictionaryHeader { line : _ } |
+            ToolboxFileIssue::ValidatorFailed { .. } => None
         }
     }
+
+    pub fn line(&self) -> usize {
+        match self {
+            ToolboxFileIssue::MissingDictionaryHeader { line } => *line,
+            _ => self.issue_line().map(|line| line.line).unwrap_or_default()
+        }
+    }
+
+    /// Identifies the issue independently of its position in the file
+    ///
+    /// Used to match the issues across different versions of a file
+    pub fn signature(&self) -> (&'static str, Option<&str>, Option<&str>, Option<&str>) {
+        let detail = match self {
+            ToolboxFileIssue::MissingField { record : _, marker } => Some(marker.as_str()),
+            ToolboxFileIssue::InvalidFieldValue { record : _, line : _, expected } => Some(expected.as_str()),
+            ToolboxFileIssue::UnexpectedDatabaseType { line : _, expected } => Some(expected.as_str()),
+            ToolboxFileIssue::MisalignedTier { record : _, line : _, tier } => Some(tier.as_str()),
+            ToolboxFileIssue::LabelCollision { record : _, other } => Some(other.text.trim()),
+            ToolboxFileIssue::NormalizationCollision { record : _, line : _, other } => Some(other.text.trim()),
+            ToolboxFileIssue::IdCollision { record : _, line : _, other } => Some(other.text.trim()),
+            ToolboxFileIssue::UnsafeClobPath { record : _, path, reason : _ } => Some(path.as_str()),
+            ToolboxFileIssue::ValidatorIssue { message, .. } => Some(message.as_str()),
+            ToolboxFileIssue::ValidatorFailed { validator, msg : _ } => Some(validator.as_str()),
+            _ => None
+        };
+
+        (
+            self.kind(),
+            self.record().map(|line| line.text.trim()),
+            self.issue_line().map(|line| line.text.trim()),
+            detail
+        )
+    }
+
+    /// Split the issues into the new ones and the ones already present in the `baseline`
+    ///
+    /// Returns `(new issues, pre-existing issues)`. An issue is pre-existing if the baseline
+    /// has an issue with the same signature (each baseline issue is matched at most once)
+    pub fn partition_new(issues: Vec<Self>, baseline: &[Self]) -> (Vec<Self>, Vec<Self>) {
+        use std::collections::HashMap;
+
+        // count the occurences of each issue in the baseline
+        let mut counts = HashMap::new();
+        for issue in baseline {
+            *counts.entry(issue.signature()).or_insert(0_usize) += 1;
+        }
+
+        let is_new = issues.iter().map(|issue| {
+            match counts.get_mut(&issue.signature()) {
+                Some(count) if *count > 0 => {
+                    *count -= 1;
+                    false
+                },
+                _ => true
+            }
+        })
+        .collect::<Vec<_>>();
+
+        let (new, preexisting) : (Vec<_>, Vec<_>) = issues.into_iter()
+            .zip(is_new)
+            .partition(|(_, is_new)| *is_new);
+
+        (
+            new.into_iter().map(|(issue, _)| issue).collect(),
+            preexisting.into_iter().map(|(issue, _)| issue).collect()
+        )
+    }
 }
 
 impl std::error::Error for ToolboxFileIssue {}
 
-impl fmt::Display for ToolboxFileIssue {
-    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+/// Issues are serialized with their kind, severity, the (1-based) line number, 
+/// the record label and the plain text message
+impl serde::Serialize for ToolboxFileIssue {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        // the record label is the text after the record tag
+        let label = self.record().map(|record| {
+            record.text.trim().split_once(char::is_whitespace).map(|(_, label)| label.trim()).unwrap_or("")
+        });
+
+        let (marker, expected) = match self {
+            ToolboxFileIssue::MissingField { record : _, marker } => (Some(marker), None),
+            ToolboxFileIssue::InvalidFieldValue { record : _, line : _, expected } => (None, Some(expected)),
+            ToolboxFileIssue::UnexpectedDatabaseType { line : _, expected } => (None, Some(expected)),
+            _ => (None, None)
+        };
+
+        let mut state = serializer.serialize_struct("ToolboxFileIssue", 9)?;
+        state.serialize_field("kind", self.kind())?;
+        state.serialize_field("severity", &self.severity())?;
+        state.serialize_field("line", &(self.line() + 1))?;
+        state.serialize_field("text", &self.issue_line().map(|line| line.text.trim_end()))?;
+        state.serialize_field("record", &self.record())?;
+        state.serialize_field("label", &label)?;
+        state.serialize_field("marker", &marker)?;
+        state.serialize_field("expected", &expected)?;
+        state.serialize_field("message", &console::strip_ansi_codes(&self.description()))?;
+        state.end()
+    }
+}
+
+impl ToolboxFileIssue {
+    /// The description of the issue (without the line number)
+    pub fn description(&self) -> String {
         use crate::util::truncate_text;
         use style::*;
 
-        // build the error message
-        let message = match self {
-            ToolboxFileIssue::LineBeforeFirstRecord { line } => {
-                format!(
-                    "{} line {} occurs before the first record",
-                    header(line.line),
-                    value(truncate_text(line.text, 30))
-                )
-            },
+        match self {
             ToolboxFileIssue::UntaggedLine { line } => {
                 format!(
-                    "{} untagged line {}",
-                    header(line.line),
-                    value(truncate_text(line.text, 30))
+                    "untagged line {}",
+                    value(truncate_text(&line.text, 30))
                 )
             },
             ToolboxFileIssue::MissingRecordLabel { line } => {
                 format!(
-                    "{} missing a label in the record {}",
-                    header(line.line),
-                    value(line.text.trim())
+                    "missing a label in the record {}",
+                    value(truncate_text(line.text.trim(), 40))
                 )
             },
             ToolboxFileIssue::MissingID { line } => {
                 format!(
-                    "{} missing ID tag in the record {}",
-                    header(line.line),
-                    value(line.text.trim())
+                    "missing ID tag in the record {}",
+                    value(truncate_text(line.text.trim(), 40))
                 )
             },
             ToolboxFileIssue::InvalidID { record, line } => {
                 format!(
-                    "{} invalid ID tag {} in the record {}",
-                    header(line.line),
-                    value(line.text.trim()),
-                    value(record.text.trim())
+                    "invalid ID tag {} in the record {}",
+                    value(truncate_text(line.text.trim(), 40)),
+                    value(truncate_text(record.text.trim(), 40))
                 )
             }, 
             ToolboxFileIssue::ExtraneousID { record, line } => {
                 format!(
-                    "{} extraneous ID tag {} will be ingored in the record {}",
-                    header(line.line),
-                    value(line.text.trim()),
-                    value(record.text.trim())
+                    "extraneous ID tag {} will be ingored in the record {}",
+                    value(truncate_text(line.text.trim(), 40)),
+                    value(truncate_text(record.text.trim(), 40))
                 )
             }, 
             ToolboxFileIssue::AmbiguousID { record, line } => {
                 format!(
-                    "{} ID tag {} in the record {} is not unique",
-                    header(line.line),
-                    value(line.text.trim()),
-                    value(record.text.trim())
+                    "ID tag {} in the record {} is not unique",
+                    value(truncate_text(line.text.trim(), 40)),
+                    value(truncate_text(record.text.trim(), 40))
+                )
+            },
+            ToolboxFileIssue::MissingDictionaryHeader { line : _ } => {
+                "Missing Toolbox dictionary header".to_string()
+            },
+            ToolboxFileIssue::UnexpectedDatabaseType { line, expected } => {
+                format!(
+                    "the header {} does not declare the database type {}",
+                    value(truncate_text(line.text.trim(), 40)),
+                    value(expected)
+                )
+            },
+            ToolboxFileIssue::MissingField { record, marker } => {
+                format!(
+                    "missing required field {} in the record {}",
+                    value(marker),
+                    value(truncate_text(record.text.trim(), 40))
+                )
+            },
+            ToolboxFileIssue::RepeatedField { record, line } => {
+                format!(
+                    "field {} is repeated in the record {}",
+                    value(truncate_text(line.text.trim(), 30)),
+                    value(truncate_text(record.text.trim(), 40))
+                )
+            },
+            ToolboxFileIssue::InvalidFieldValue { record, line, expected } => {
+                format!(
+                    "field {} in the record {} should be {}",
+                    value(truncate_text(line.text.trim(), 30)),
+                    value(truncate_text(record.text.trim(), 40)),
+                    expected
+                )
+            },
+            ToolboxFileIssue::MisalignedTier { record, line, tier } => {
+                format!(
+                    "tier {} is not aligned with {} in the record {}",
+                    value(truncate_text(line.text.trim(), 30)),
+                    value(tier),
+                    value(truncate_text(record.text.trim(), 40))
                 )
             },
-            ToolboxFileIssue::MissingDictionaryHeader { line } => {
+            ToolboxFileIssue::UnknownMarker { record, line } => {
                 format!(
-                    "{} Missing Toolbox dictionary header",
-                    header(*line)
-                )  
+                    "field {} in the record {} is not defined by the type file",
+                    value(truncate_text(line.text.trim(), 30)),
+                    value(truncate_text(record.text.trim(), 40))
+                )
+            },
+            ToolboxFileIssue::LabelCollision { record, other } => {
+                format!(
+                    "the record {} is stored in the same file as the record {} on line {}",
+                    value(truncate_text(record.text.trim(), 40)),
+                    value(truncate_text(other.text.trim(), 40)),
+                    other.line + 1
+                )
+            },
+            ToolboxFileIssue::NormalizationCollision { record, line, other } if record.line == line.line => {
+                format!(
+                    "the record {} only differs from the record {} on line {} in its Unicode normalization (both are stored in the same file)",
+                    value(truncate_text(record.text.trim(), 40)),
+                    value(truncate_text(other.text.trim(), 40)),
+                    other.line + 1
+                )
+            },
+            ToolboxFileIssue::NormalizationCollision { record, line, other } => {
+                format!(
+                    "the ID {} of the record {} only differs from {} on line {} in its Unicode normalization (both are stored in the same file)",
+                    value(truncate_text(line.text.trim(), 30)),
+                    value(truncate_text(record.text.trim(), 40)),
+                    value(truncate_text(other.text.trim(), 30)),
+                    other.line + 1
+                )
+            },
+            ToolboxFileIssue::IdCollision { record, line, other } => {
+                format!(
+                    "the ID {} of the record {} is stored in the same file as {} on line {}",
+                    value(truncate_text(line.text.trim(), 30)),
+                    value(truncate_text(record.text.trim(), 40)),
+                    value(truncate_text(other.text.trim(), 30)),
+                    other.line + 1
+                )
+            },
+            ToolboxFileIssue::NestedRecordTag { record, line } => {
+                format!(
+                    "{} within a subentry of the record {} does not start a new record (a blank line is missing before it)",
+                    value(truncate_text(line.text.trim(), 30)),
+                    value(truncate_text(record.text.trim(), 40))
+                )
+            },
+            ToolboxFileIssue::UnsafeClobPath { record, path, reason } => {
+                format!(
+                    "the record {} cannot be stored at {} ({}) and is quarantined",
+                    value(truncate_text(record.text.trim(), 40)),
+                    value(truncate_text(path, 40)),
+                    reason
+                )
+            },
+            ToolboxFileIssue::ValidatorIssue { validator, record, line : _, message, severity : _ } => {
+                format!(
+                    "{} in the record {} (reported by {})",
+                    message,
+                    value(truncate_text(record.text.trim(), 40)),
+                    value(validator)
+                )
+            },
+            ToolboxFileIssue::ValidatorFailed { validator, msg } => {
+                format!(
+                    "the validator {} failed: {}",
+                    value(validator),
+                    msg
+                )
             }
-        };
+        }
+    }
+}
 
-        // and write it
-        write!(formatter, "{}", message)
+impl fmt::Display for ToolboxFileIssue {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{} {}", style::header(self.line()), self.description())
     }
 }
 
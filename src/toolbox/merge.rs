@@ -0,0 +1,349 @@
+//
+// src/toolbox/merge.rs
+//
+// Record-level three-way merge of Toolbox text (such as clobs)
+//
+// The records of the three versions are matched by their first line (the
+// record tag and the label). A record changed on one side only is taken from
+// that side. If both sides changed a record differently, both versions are
+// emitted between conflict markers (along with the common ancestor, as with
+// git's `diff3` conflict style), so that the conflict is limited to that
+// record.
+//
+// The conflicts can then be resolved field by field: the fields of the three
+// versions of a conflicting record are matched by their markers (and their
+// occurence in the record).
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use super::Scanner;
+use super::scanner::Token;
+
+// the size of the conflict markers (as used by git)
+const MARKER_SIZE : usize = 7;
+
+
+/// The result of a merge
+pub struct MergeResult {
+    /// The merged text
+    pub text      : String,
+    /// The number of conflicting records
+    pub conflicts : usize
+}
+
+
+/// Merge two versions of a text with a common ancestor, record by record
+///
+/// Text that does not belong to any record (such as the quarantined lines) is
+/// merged as a whole
+pub fn merge_records(record_tags: &[String], base: &str, ours: &str, theirs: &str) -> MergeResult {
+    let base_records   = records(base, record_tags);
+    let our_records    = records(ours, record_tags);
+    let their_records  = records(theirs, record_tags);
+
+    let mut text = String::new();
+    let mut conflicts = 0;
+
+    // the text before the first record
+    let prefix = merge_versions(
+        Some(base_records.prefix), Some(our_records.prefix), Some(their_records.prefix)
+    );
+    match prefix {
+        Merged::Clean(Some(prefix)) => text.push_str(prefix),
+        Merged::Clean(None)         => {},
+        Merged::Conflict(ours, theirs) => {
+            push_conflict(&mut text, Some(base_records.prefix), ours, theirs);
+            conflicts += 1;
+        }
+    }
+
+    // the records in our order followed by the records that only exist in theirs
+    let keys = our_records.records.iter()
+        .chain(their_records.records.iter())
+        .map(|(key, _)| key)
+        .fold(vec!(), |mut keys, key| {
+            if !keys.contains(&key) { keys.push(key) }
+            keys
+        });
+
+    let mut merged_records = vec!();
+    for key in keys {
+        match merge_versions(base_records.get(key), our_records.get(key), their_records.get(key)) {
+            Merged::Clean(Some(record)) => {
+                merged_records.push(record.trim_end_matches('\n').to_owned());
+            },
+            Merged::Clean(None) => {},
+            Merged::Conflict(ours, theirs) => {
+                let mut record = String::new();
+                push_conflict(&mut record, base_records.get(key), ours, theirs);
+
+                merged_records.push(record.trim_end_matches('\n').to_owned());
+                conflicts += 1;
+            }
+        }
+    }
+
+    // the records are separated by an empty line
+    text.push_str(&merged_records.join("\n\n"));
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+
+    MergeResult { text, conflicts }
+}
+
+
+// The records of a text, keyed by the first line (and the occurence of that line)
+struct Records<'a> {
+    prefix  : &'a str,
+    records : Vec<((&'a str, usize), &'a str)>
+}
+
+impl<'a> Records<'a> {
+    fn get(&self, key: &(&'a str, usize)) -> Option<&'a str> {
+        self.records.iter().find(|(k, _)| k == key).map(|(_, record)| *record)
+    }
+}
+
+fn records<'a>(text: &'a str, record_tags: &[String]) -> Records<'a> {
+    let mut records : Vec<((&'a str, usize), &'a str)> = vec!();
+    let scanner = || Scanner::from(text, "").with_record_tags(record_tags);
+
+    // the text before the first record
+    let prefix_end = scanner()
+        .find(|(_, token)| *token == Token::RecordBegin)
+        .map(|(line, _)| line.text.as_ptr() as usize - text.as_ptr() as usize)
+        .unwrap_or(text.len());
+    let prefix = &text[.. prefix_end];
+
+    for (_, token) in scanner() {
+        if let Token::RecordEnd { body } = token {
+            let first_line = body.lines().next().unwrap_or("").trim_end();
+            let occurence = records.iter().filter(|((line, _), _)| *line == first_line).count();
+
+            records.push(((first_line, occurence), body));
+        }
+    }
+
+    Records { prefix, records }
+}
+
+
+enum Merged<'a> {
+    Clean(Option<&'a str>),
+    Conflict(Option<&'a str>, Option<&'a str>)
+}
+
+// Three-way merge of a single item (`None` means that the item does not exist)
+fn merge_versions<'a>(base: Option<&'a str>, ours: Option<&'a str>, theirs: Option<&'a str>)
+    -> Merged<'a>
+{
+    let normalize = |text: Option<&'a str>| {
+        text.map(|text| text.trim_end()).filter(|text| !text.is_empty())
+    };
+
+    match (normalize(base), normalize(ours), normalize(theirs)) {
+        (_, o, t) if o == t => Merged::Clean(ours.filter(|_| o.is_some())),
+        (b, o, _) if b == o => Merged::Clean(theirs.filter(|_| normalize(theirs).is_some())),
+        (b, _, t) if b == t => Merged::Clean(ours.filter(|_| normalize(ours).is_some())),
+        _                   => Merged::Conflict(ours, theirs)
+    }
+}
+
+fn push_conflict(text: &mut String, base: Option<&str>, ours: Option<&str>, theirs: Option<&str>) {
+    let push_version = |text: &mut String, version: Option<&str>| {
+        if let Some(version) = version.map(|v| v.trim_end_matches('\n')).filter(|v| !v.is_empty()) {
+            text.push_str(version);
+            text.push('\n');
+        }
+    };
+
+    text.push_str(&format!("{} ours\n", "<".repeat(MARKER_SIZE)));
+    push_version(text, ours);
+    text.push_str(&format!("{} base\n", "|".repeat(MARKER_SIZE)));
+    push_version(text, base);
+    text.push_str(&format!("{}\n", "=".repeat(MARKER_SIZE)));
+    push_version(text, theirs);
+    text.push_str(&format!("{} theirs\n", ">".repeat(MARKER_SIZE)));
+}
+
+
+/// A conflict between two versions of a record, as found between the conflict markers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// The range of lines with the conflict (including the markers)
+    pub lines  : std::ops::Range<usize>,
+    /// The common ancestor (`None` if the markers do not include it)
+    pub base   : Option<String>,
+    pub ours   : String,
+    pub theirs : String
+}
+
+/// Find the conflicts in a text
+pub fn find_conflicts(text: &str) -> Vec<Conflict> {
+    let is_marker = |line: &str, c: char| {
+        line.len() >= MARKER_SIZE && line.chars().take(MARKER_SIZE).all(|m| m == c) && 
+            line[MARKER_SIZE ..].chars().next().map(|c| c == ' ').unwrap_or(true)
+    };
+
+    let mut conflicts = vec!();
+    // the start of the current conflict and the versions read so far
+    let mut current : Option<(usize, Vec<String>)> = None;
+
+    for (i, line) in text.lines().enumerate() {
+        match current.as_mut() {
+            None => if is_marker(line, '<') {
+                current = Some((i, vec!(String::new())));
+            },
+            Some((start, versions)) => if is_marker(line, '|') || is_marker(line, '=') {
+                versions.push(String::new());
+            } else if is_marker(line, '>') {
+                let mut versions = std::mem::take(versions);
+                let theirs = versions.pop().unwrap_or_default();
+                let ours = versions.remove(0);
+
+                conflicts.push(Conflict { lines : *start .. i + 1, base : versions.pop(), ours, theirs });
+                current = None;
+            } else if let Some(version) = versions.last_mut() {
+                version.push_str(line);
+                version.push('\n');
+            }
+        }
+    }
+
+    conflicts
+}
+
+
+/// A field of a conflicting record in the three versions (the field texts include the marker)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldVersions {
+    pub marker : String,
+    pub base   : Option<String>,
+    pub ours   : Option<String>,
+    pub theirs : Option<String>
+}
+
+/// The side of a conflict
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Base,
+    Ours,
+    Theirs
+}
+
+impl FieldVersions {
+    /// The version of the field in a conflict side
+    pub fn version(&self, side: Side) -> Option<&String> {
+        match side {
+            Side::Base   => self.base.as_ref(),
+            Side::Ours   => self.ours.as_ref(),
+            Side::Theirs => self.theirs.as_ref()
+        }
+    }
+
+    /// The side the field is taken from by a three-way merge (`None` for a conflict)
+    pub fn merged_side(&self) -> Option<Side> {
+        if self.ours == self.theirs || self.base == self.theirs {
+            Some(Side::Ours)
+        } else if self.base == self.ours {
+            Some(Side::Theirs)
+        } else {
+            None
+        }
+    }
+}
+
+/// Match the fields of the three versions of a record by their markers
+///
+/// The fields are listed in the order of our version, the fields missing from it are 
+/// placed after the field that precedes them in their version
+pub fn match_fields(base: Option<&str>, ours: &str, theirs: &str) -> Vec<FieldVersions> {
+    let mut keys : Vec<(String, usize)> = vec!();
+    let mut matched : Vec<FieldVersions> = vec!();
+
+    for &(side, text) in &[(Side::Ours, ours), (Side::Theirs, theirs), (Side::Base, base.unwrap_or(""))] {
+        // the position after the last field of this version
+        let mut position = 0;
+
+        for (key, field) in fields(text) {
+            let index = match keys.iter().position(|k| *k == key) {
+                Some(index) => index,
+                None => {
+                    let field = FieldVersions { marker : key.0.clone(), base : None, ours : None, theirs : None };
+                    keys.insert(position, key);
+                    matched.insert(position, field);
+
+                    position
+                }
+            };
+
+            let versions = &mut matched[index];
+            *match side {
+                Side::Base   => &mut versions.base,
+                Side::Ours   => &mut versions.ours,
+                Side::Theirs => &mut versions.theirs
+            } = Some(field);
+
+            position = index + 1;
+        }
+    }
+
+    matched
+}
+
+// The fields of a record keyed by their marker (and the occurence of that marker)
+//
+// A field is a line starting with a marker followed by its continuation lines
+fn fields(text: &str) -> Vec<((String, usize), String)> {
+    let mut fields : Vec<((String, usize), String)> = vec!();
+
+    for line in text.lines() {
+        match fields.last_mut() {
+            Some((_, field)) if !line.starts_with('\\') => {
+                field.push_str(line);
+                field.push('\n');
+            },
+            _ => {
+                let marker = line.split_whitespace().next().unwrap_or("").to_owned();
+                let occurence = fields.iter().filter(|((m, _), _)| *m == marker).count();
+
+                fields.push(((marker, occurence), format!("{}\n", line)));
+            }
+        }
+    }
+
+    fields
+}
+
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_conflicting_fields() {
+        use super::{merge_records, find_conflicts, match_fields, Side};
+
+        let base   = "\\lx a\n\\ps n\n\\ge one\n";
+        let ours   = "\\lx a\n\\ps v\n\\ge two\n";
+        let theirs = "\\lx a\n\\ps n\n\\ge three\n\\nt note\n";
+
+        let result = merge_records(&["\\lx".to_owned()], base, ours, theirs);
+        assert_eq!(result.conflicts, 1);
+
+        let conflicts = find_conflicts(&result.text);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].lines, 0 .. result.text.lines().count());
+        assert_eq!(conflicts[0].base.as_deref(), Some(base));
+        assert_eq!(conflicts[0].ours, ours);
+        assert_eq!(conflicts[0].theirs, theirs);
+
+        let fields = match_fields(Some(base), ours, theirs);
+        let markers = fields.iter().map(|field| field.marker.as_str()).collect::<Vec<_>>();
+        assert_eq!(markers, vec!("\\lx", "\\ps", "\\ge", "\\nt"));
+
+        let sides = fields.iter().map(|field| field.merged_side()).collect::<Vec<_>>();
+        assert_eq!(sides, vec!(Some(Side::Ours), Some(Side::Ours), None, Some(Side::Theirs)));
+    }
+}
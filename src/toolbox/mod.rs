@@ -14,10 +14,26 @@ mod scanner;
 mod dictionary;
 // Toolbox file issues
 mod issue;
-
-pub use scanner::Scanner;
-pub use dictionary::Dictionary;
-pub use issue::ToolboxFileIssue;
+// Character encodings of Toolbox files
+mod encoding;
+// Record templates
+pub mod template;
+// Toolbox marker definition (.typ) files
+pub mod typ;
+// Record-level merging
+pub mod merge;
+// Record queries
+pub mod query;
+// External record validators
+pub mod validator;
+// Conversion of external data (CSV, LIFT) into records
+pub mod import;
+// Word-level differences of field values
+pub mod word_diff;
+
+pub use scanner::{Scanner, Token, starts_record};
+pub use dictionary::{Dictionary, Record, QUARANTINE_DIR, ClobOrigin, RecordId, clob_path_for_id, clob_paths_for_id, clob_paths_for_label};
+pub use issue::{ToolboxFileIssue, Severity, ISSUE_KINDS, set_severity_policy};
 
 
 
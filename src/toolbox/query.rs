@@ -0,0 +1,398 @@
+//
+// src/toolbox/query.rs
+//
+// A small expression language for selecting dictionary records, e.g.
+//
+//   \ps = "n" and missing(\ge)
+//
+// Expressions are built from field comparisons (`=`, `!=`, `~` and `!~` for
+// regular expression matches), field tests (`missing`, `present`, `repeated`),
+// `and`, `or`, `not` and parentheses. A comparison holds if any value of the
+// field satisfies it (the negated operators hold if none does).
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use super::dictionary::Record;
+
+use regex::Regex;
+
+
+/// A parsed record query
+#[derive(Debug)]
+pub struct Query {
+    expr : Expr
+}
+
+/// A query parsing error, with the character offset in the query text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryError {
+    pub offset : usize,
+    pub msg    : String
+}
+
+#[derive(Debug)]
+enum Expr {
+    Equals   { marker: String, value: String },
+    Matches  { marker: String, regex: Regex },
+    Missing  { marker: String },
+    Repeated { marker: String },
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>)
+}
+
+impl Query {
+    /// Parse the query text
+    pub fn parse(text: &str) -> Result<Query, QueryError> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens, pos: 0, end: text.chars().count() };
+
+        let expr = parser.parse_or()?;
+
+        // the whole query must be consumed
+        match parser.peek() {
+            None => Ok( Query { expr } ),
+            Some((offset, _)) => Err( QueryError::at(offset, "unexpected input after the end of the query") )
+        }
+    }
+
+    /// Check whether the record matches the query
+    pub fn matches(&self, record: &Record) -> bool {
+        self.expr.eval(record)
+    }
+}
+
+impl QueryError {
+    fn at<S: Into<String>>(offset: usize, msg: S) -> Self {
+        QueryError { offset, msg : msg.into() }
+    }
+}
+
+
+impl Expr {
+    fn eval(&self, record: &Record) -> bool {
+        match self {
+            Expr::Equals { marker, value } => {
+                record.values(marker).any(|v| v == value)
+            },
+            Expr::Matches { marker, regex } => {
+                record.values(marker).any(|v| regex.is_match(v))
+            },
+            Expr::Missing { marker } => {
+                record.values(marker).all(|v| v.is_empty())
+            },
+            Expr::Repeated { marker } => {
+                record.values(marker).count() > 1
+            },
+            Expr::Not(expr)        => !expr.eval(record),
+            Expr::And(left, right) => left.eval(record) && right.eval(record),
+            Expr::Or(left, right)  => left.eval(record) || right.eval(record)
+        }
+    }
+}
+
+
+//
+// ####                    ###
+//  ##                      ##
+//  ##                      ##
+//  ##  ## ##  ##   ## ##   ##
+//  ##  ### ### ##  ### ##  ##
+//  ##  ##  ##  ##  ##  ##  ##
+//  ##  ##  ##  ##  ##  ##  ##
+// #### ##  ##  ##  #####  ####
+//                  ##
+//                 ####
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Marker(String),
+    Word(String),
+    Text(String),
+    Equals,
+    NotEquals,
+    Matches,
+    NotMatches,
+    Open,
+    Close
+}
+
+// Split the query into tokens (with their character offsets)
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, QueryError> {
+    let mut tokens = vec!();
+    let mut chars = text.chars().enumerate().peekable();
+
+    // characters that end a marker or a word
+    let is_delimiter = |c: char| c.is_whitespace() || "()=!~\"'".contains(c);
+
+    while let Some((offset, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '=' => Token::Equals,
+            '~' => Token::Matches,
+            '!' => {
+                match chars.next() {
+                    Some((_, '=')) => Token::NotEquals,
+                    Some((_, '~')) => Token::NotMatches,
+                    _              => return Err( QueryError::at(offset, "expected '!=' or '!~'") )
+                }
+            },
+            '"' | '\'' => {
+                let quote = c;
+                let mut value = String::new();
+
+                loop {
+                    match chars.next() {
+                        Some((_, c)) if c == quote => break,
+                        Some((_, '\\')) => {
+                            match chars.next() {
+                                Some((_, c)) => value.push(c),
+                                None         => return Err( QueryError::at(offset, "unterminated string") )
+                            }
+                        },
+                        Some((_, c)) => value.push(c),
+                        None         => return Err( QueryError::at(offset, "unterminated string") )
+                    }
+                }
+
+                Token::Text(value)
+            },
+            _ => {
+                let mut word = c.to_string();
+                while let Some((_, c)) = chars.peek().filter(|(_, c)| !is_delimiter(*c)) {
+                    word.push(*c);
+                    chars.next();
+                }
+
+                if word == "\\" {
+                    return Err( QueryError::at(offset, "expected a field marker after '\\'") );
+                }
+
+                if word.starts_with('\\') { Token::Marker(word) } else { Token::Word(word) }
+            }
+        };
+
+        tokens.push((offset, token));
+    }
+
+    Ok( tokens )
+}
+
+
+// A recursive descent parser
+//
+//   or      := and ("or" and)*
+//   and     := not ("and" not)*
+//   not     := "not" not | primary
+//   primary := "(" or ")" | test "(" marker ")" | marker op string
+struct Parser {
+    tokens : Vec<(usize, Token)>,
+    pos    : usize,
+    // the offset of the query end (for error reporting)
+    end    : usize
+}
+
+impl Parser {
+    fn peek(&self) -> Option<(usize, &Token)> {
+        self.tokens.get(self.pos).map(|(offset, token)| (*offset, token))
+    }
+
+    fn next(&mut self, expected: &str) -> Result<(usize, Token), QueryError> {
+        match self.tokens.get(self.pos) {
+            Some(token) => {
+                self.pos += 1;
+                Ok( token.clone() )
+            },
+            None => Err( QueryError::at(self.end, format!("unexpected end of the query, expected {}", expected)) )
+        }
+    }
+
+    // consume the keyword if it is next
+    fn keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some((_, Token::Word(word))) if word.eq_ignore_ascii_case(keyword) => {
+                self.pos += 1;
+                true
+            },
+            _ => false
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.parse_and()?;
+        while self.keyword("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+
+        Ok( expr )
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.parse_not()?;
+        while self.keyword("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_not()?));
+        }
+
+        Ok( expr )
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, QueryError> {
+        if self.keyword("not") {
+            Ok( Expr::Not(Box::new(self.parse_not()?)) )
+        } else {
+            self.parse_primary()
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, QueryError> {
+        const EXPECTED : &str = "a field marker, a test or '('";
+
+        match self.next(EXPECTED)? {
+            (_, Token::Open) => {
+                let expr = self.parse_or()?;
+                self.expect(Token::Close, "')'")?;
+
+                Ok( expr )
+            },
+            (offset, Token::Word(word)) => {
+                let test = word.to_lowercase();
+                if !["missing", "present", "repeated"].contains(&test.as_str()) {
+                    return Err( QueryError::at(offset,
+                        format!("unknown test '{}' (expected missing, present or repeated)", word)
+                    ));
+                }
+
+                self.expect(Token::Open, "'('")?;
+                let marker = self.marker()?;
+                self.expect(Token::Close, "')'")?;
+
+                Ok(
+                    match test.as_str() {
+                        "missing"  => Expr::Missing { marker },
+                        "present"  => Expr::Not(Box::new(Expr::Missing { marker })),
+                        _          => Expr::Repeated { marker }
+                    }
+                )
+            },
+            (_, Token::Marker(marker)) => {
+                let (offset, op) = self.next("'=', '!=', '~' or '!~'")?;
+                let (value_offset, value) = match self.next("a quoted value")? {
+                    (offset, Token::Text(value)) => (offset, value),
+                    (offset, _)                  => return Err( QueryError::at(offset, "expected a quoted value") )
+                };
+
+                let regex = || {
+                    Regex::new(&value).map_err(|err| {
+                        // only keep the last line of the (multi-line) regex syntax errors
+                        let msg = err.to_string();
+                        let msg = msg.lines().last().unwrap_or("").trim_start_matches("error: ").to_owned();
+
+                        QueryError::at(value_offset, format!("invalid regular expression ({})", msg))
+                    })
+                };
+
+                match op {
+                    Token::Equals     => Ok( Expr::Equals { marker, value } ),
+                    Token::NotEquals  => Ok( Expr::Not(Box::new(Expr::Equals { marker, value })) ),
+                    Token::Matches    => Ok( Expr::Matches { marker, regex: regex()? } ),
+                    Token::NotMatches => Ok( Expr::Not(Box::new(Expr::Matches { marker, regex: regex()? })) ),
+                    _                 => Err( QueryError::at(offset, "expected '=', '!=', '~' or '!~'") )
+                }
+            },
+            (offset, _) => Err( QueryError::at(offset, format!("expected {}", EXPECTED)) )
+        }
+    }
+
+    fn expect(&mut self, expected: Token, description: &str) -> Result<(), QueryError> {
+        match self.next(description)? {
+            (_, token) if token == expected => Ok( () ),
+            (offset, _)                     => Err( QueryError::at(offset, format!("expected {}", description)) )
+        }
+    }
+
+    fn marker(&mut self) -> Result<String, QueryError> {
+        match self.next("a field marker")? {
+            (_, Token::Marker(marker)) => Ok( marker ),
+            (offset, _)                => Err( QueryError::at(offset, "expected a field marker (e.g. \\ge)") )
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{Query, QueryError};
+    use crate::config::DictionaryConfig;
+    use crate::toolbox::Dictionary;
+
+    fn config() -> DictionaryConfig {
+        let text = "[[dictionary]]\nname = \"Lexical\"\npath = \"lex.txt\"\nrecord-tag = \"lex\"\n";
+
+        DictionaryConfig::for_test(text)
+    }
+
+    // the labels of the records matching the query
+    fn matching(query: &str) -> Vec<String> {
+        let text = "\\_sh v3.0  400  Dictionary\n\n\
+            \\lex dog\n\\ps n\n\\ge dog\n\n\
+            \\lex run\n\\ps v\n\n\
+            \\lex say \"hi\"\n\\ps v\n\\ge greet\n\\ge say hi\n";
+
+        let query = Query::parse(query).unwrap();
+        let dictionary = Dictionary::from_text(&config(), text.to_owned());
+        let labels = dictionary.records().iter()
+            .filter(|record| query.matches(record))
+            .filter_map(|record| record.label().map(str::to_owned))
+            .collect();
+
+        labels
+    }
+
+    fn error(query: &str) -> (usize, String) {
+        let QueryError { offset, msg } = Query::parse(query).unwrap_err();
+
+        (offset, msg)
+    }
+
+    #[test]
+    fn test_precedence() {
+        // `and` binds tighter than `or`
+        assert_eq!(matching(r#"\ps = "n" or \ps = "v" and missing(\ge)"#), vec!("dog", "run"));
+        assert_eq!(matching(r#"(\ps = "n" or \ps = "v") and missing(\ge)"#), vec!("run"));
+
+        // `not` binds tighter than `and`
+        assert_eq!(matching(r#"not \ps = "n" and present(\ge)"#), vec!("say \"hi\""));
+        assert_eq!(matching(r#"not (\ps = "n" and present(\ge))"#), vec!("run", "say \"hi\""));
+
+        // the keywords are case-insensitive
+        assert_eq!(matching(r#"\ps = "v" AND Repeated(\ge)"#), vec!("say \"hi\""));
+    }
+
+    #[test]
+    fn test_quoting() {
+        assert_eq!(matching(r#"\ge = 'say hi'"#), vec!("say \"hi\""));
+        assert_eq!(matching(r#"\lex = "say \"hi\"""#), vec!("say \"hi\""));
+        assert_eq!(matching(r#"\lex = 'say "hi"'"#), vec!("say \"hi\""));
+        assert_eq!(matching(r#"\lex ~ "^\\w+$""#), vec!("dog", "run"));
+        assert_eq!(matching(r#"\ge != "dog""#), vec!("run", "say \"hi\""));
+    }
+
+    #[test]
+    fn test_error_positions() {
+        assert_eq!(error(r#"\ps = "n"#), (6, "unterminated string".to_owned()));
+        assert_eq!(error(r#"\ps = n"#), (6, "expected a quoted value".to_owned()));
+        assert_eq!(error(r#"\ps ! "n""#), (4, "expected '!=' or '!~'".to_owned()));
+        assert_eq!(error(r#"absent(\ge)"#).0, 0);
+        assert_eq!(error(r#"missing(\ge) and"#), (16, "unexpected end of the query, expected a field marker, a test or '('".to_owned()));
+        assert_eq!(error(r#"missing(\ge) \ps"#), (13, "unexpected input after the end of the query".to_owned()));
+        assert_eq!(error(r#"(missing(\ge)"#), (13, "unexpected end of the query, expected ')'".to_owned()));
+        assert_eq!(error(r#"\lex ~ "(""#).0, 7);
+
+        // the offsets count characters, not bytes
+        assert_eq!(error(r#"\ps = "ŋ" \"#), (10, "expected a field marker after '\\'".to_owned()));
+    }
+}
@@ -16,6 +16,56 @@ pub struct Line<'a> {
     pub text : &'a str,
 }
 
+/// A copy of a line that does not borrow the text it was scanned from
+///
+/// Used for things that outlive the text (such as the issues found in a dictionary)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnedLine {
+    pub line : usize,
+    pub text : String,
+}
+
+impl<'a> Line<'a> {
+    /// Copy the line out of the scanned text
+    pub fn to_owned_line(&self) -> OwnedLine {
+        OwnedLine::from(self)
+    }
+}
+
+impl<'a> From<&Line<'a>> for OwnedLine {
+    fn from(line: &Line<'a>) -> Self {
+        OwnedLine { line : line.line, text : line.text.to_owned() }
+    }
+}
+
+impl<'a> From<Line<'a>> for OwnedLine {
+    fn from(line: Line<'a>) -> Self {
+        OwnedLine::from(&line)
+    }
+}
+
+/// Lines are serialized with 1-based line numbers (as displayed to the user)
+impl<'a> serde::Serialize for Line<'a> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_line(self.line, self.text, serializer)
+    }
+}
+
+impl serde::Serialize for OwnedLine {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_line(self.line, &self.text, serializer)
+    }
+}
+
+fn serialize_line<S: serde::Serializer>(line: usize, text: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    use serde::ser::SerializeStruct;
+
+    let mut state = serializer.serialize_struct("Line", 2)?;
+    state.serialize_field("line", &(line + 1))?;
+    state.serialize_field("text", text.trim_end())?;
+    state.end()
+}
+
 /// A token that represents a basic structural elements of a toolbox file
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token<'a> {
@@ -27,6 +77,12 @@ pub enum Token<'a> {
     Tagged {tag: &'a str, text: &'a str},
     /// An untagged text line
     Untagged {text: &'a str},
+    /// A line with the record tag within a sub-record (e.g. a subentry), which does not start 
+    /// a new record (only issued if sub-record tags are set)
+    NestedRecordTag {tag: &'a str, text: &'a str},
+    /// An untagged text line that continues the value of the preceding tagged line (only 
+    /// issued in the continuation mode)
+    Continuation {text: &'a str},
     /// A blank line (either empty or containing whitespaces only)
     Blank
 }
@@ -52,14 +108,22 @@ pub struct Scanner<'a> {
     //       more complicated. We duplicate the information here for the sake
     //       of the implementation clarity
     next_line_i : usize,
-    // the tag that marks a start of a new record
-    record_tag  : String,
+    // the tags that mark a start of a new record
+    record_tags : Vec<String>,
     // a queue used to map a single line to multiple tokens
     queue       : ArrayVec<[Token<'a>; 3]>,
     // the last scanned line
     pub(super) last_line  : Line<'a>,
     // marker for where the last record started
-    start       : Option<&'a str>
+    start       : Option<&'a str>,
+    // whether the untagged lines after a tagged line continue its value
+    continuation : bool,
+    // whether the last line was a tagged line or its continuation (within a record)
+    in_field    : bool,
+    // the tags that start a sub-record (with the initial '\')
+    subrecord_tags : Vec<String>,
+    // whether the last line belongs to a sub-record (sub-records end at a blank line)
+    in_subrecord : bool
 }
 
 impl<'a>  Scanner<'a> {
@@ -67,15 +131,76 @@ impl<'a>  Scanner<'a> {
         Scanner {
             text,
             next_line_i : 0,
-            record_tag  : record_tag.into(), 
+            record_tags : vec!(record_tag.into()), 
             queue       : ArrayVec::new(),
             // the only case where this field can be read before it was 
             // "correctly" set is if the file is empty
             // setting last line to file contents in this case is correct
             last_line   : Line { line : 0, text }, 
-            start       : None
+            start       : None,
+            continuation : false,
+            in_field    : false,
+            subrecord_tags : vec!(),
+            in_subrecord : false
         }   
     }
+
+    /// A scanner for the text of a dictionary (with its record tags and scanning modes)
+    pub fn for_dictionary(text: &'a str, config: &crate::config::DictionaryConfig) -> Scanner<'a> {
+        Scanner::from(text, &config.record_tag)
+            .with_record_tags(&config.record_tags)
+            .with_continuation_lines(config.continuation_lines)
+            .with_subrecord_tags(&config.subrecord_tags)
+    }
+
+    /// Start a record at any of the `tags` (for the files that mix several record types)
+    ///
+    /// The record tag the scanner was created with is kept if no tags are given
+    pub fn with_record_tags(mut self, tags: &[String]) -> Self {
+        if !tags.is_empty() {
+            self.record_tags = tags.to_vec();
+        }
+
+        self
+    }
+
+    /// Treat the untagged lines that follow a tagged line in a record as the continuation 
+    /// of its value
+    ///
+    /// Toolbox wraps long field values across several lines, such lines are scanned as 
+    /// `Continuation` tokens instead of `Untagged` ones. The lines are not modified
+    pub fn with_continuation_lines(mut self, continuation: bool) -> Self {
+        self.continuation = continuation;
+
+        self
+    }
+
+    /// Keep the sub-records that start with one of the `tags` (e.g. MDF subentries) within 
+    /// their record
+    ///
+    /// A sub-record extends to the next blank line (Toolbox separates the records by blank 
+    /// lines). A record tag within a sub-record does not start a new record, it is scanned 
+    /// as a `NestedRecordTag` token instead
+    pub fn with_subrecord_tags(mut self, tags: &[String]) -> Self {
+        self.subrecord_tags = tags.to_vec();
+
+        self
+    }
+}
+
+impl<'a> Scanner<'a> {
+    fn is_record_tag(&self, tag: &str) -> bool {
+        self.record_tags.iter().any(|record_tag| record_tag == tag)
+    }
+}
+
+/// Check whether the line starts a record (with any of the record tags)
+pub fn starts_record(line: &str, record_tags: &[String]) -> bool {
+    record_tags.iter().any(|tag| {
+        line.strip_prefix(tag.as_str())
+            .map(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+            .unwrap_or(false)
+    })
 }
 
 pub type ScannerItem<'a> = (Line<'a>, Token<'a>);
@@ -114,13 +239,24 @@ impl<'a>  Iterator for Scanner<'a> {
             let (line, tail) = self.text.split_at(end);
             // remove the trailing end line markers from the line
             // TODO: there must be a better way of doing this
-            (line.trim_end_matches(|c| c == '\r' || c == '\n'), tail)
+            (line.trim_end_matches(['\r', '\n']), tail)
         };
 
         // scan the line and produce the token
-        let token = match ParsedLine::from(line) {
+        let parsed = ParsedLine::from(line);
+        let field_line = match parsed {
+            ParsedLine::Tagged(_, _)  => true,
+            ParsedLine::Untagged(_)   => self.continuation && self.in_field,
+            ParsedLine::Blank         => false
+        };
+
+        let token = match parsed {
+            // record tag within a sub-record
+            ParsedLine::Tagged(tag, text) if self.is_record_tag(tag) && self.in_subrecord => {
+                Token::NestedRecordTag { tag, text }
+            },
             // new record
-            ParsedLine::Tagged(tag, text) if tag == self.record_tag => {
+            ParsedLine::Tagged(tag, text) if self.is_record_tag(tag) => {
                 // add the extra tokens to the queue
                 self.queue.push(Token::Tagged { tag, text });
                 self.queue.push(Token::RecordBegin);
@@ -142,6 +278,10 @@ impl<'a>  Iterator for Scanner<'a> {
             ParsedLine::Tagged(tag, text) => {
                 Token::Tagged { tag, text }
             },           
+            // untagged line that continues a field value
+            ParsedLine::Untagged(text) if self.continuation && self.in_field => {
+                Token::Continuation { text }
+            },
             // untagged line
             ParsedLine::Untagged(text) => {
                 Token::Untagged { text }
@@ -153,6 +293,16 @@ impl<'a>  Iterator for Scanner<'a> {
         };
 
 
+        // the following untagged lines continue a field value within a record
+        self.in_field = field_line && self.start.is_some();
+
+        // the following lines belong to the sub-record (until a blank line or a new record)
+        self.in_subrecord = self.start.is_some() && match &token {
+            Token::Blank | Token::RecordBegin | Token::RecordEnd { .. } => false,
+            Token::Tagged { tag, text: _ }  => self.in_subrecord || self.subrecord_tags.iter().any(|t| t == tag),
+            _                               => self.in_subrecord
+        };
+
         // set the remaining text to the tail
         self.text = tail;
         
@@ -208,7 +358,7 @@ mod internal {
             // find where the tag end 
             // this is either the first whitespace
             // or the end of the line (if there is no value part)
-            let end = line.find(char::is_whitespace).unwrap_or_else(|| line.len());
+            let end = line.find(char::is_whitespace).unwrap_or(line.len());
             // split the line into tag, value pair
             let (tag, value) = line.split_at(end);
     
@@ -0,0 +1,438 @@
+//
+// src/toolbox/template.rs
+//
+// Record templates. A template declares the fields of the dictionary records
+// (markers, whether they are required or repeatable and the types of their
+// values). It is used to validate the records, to create new records and to
+// describe the changes to records.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::config::{DictionaryConfig, TemplateConfig, FieldConfig, FieldType};
+use crate::toolbox::{Scanner, ToolboxFileIssue};
+use crate::toolbox::scanner::{Line, Token};
+
+lazy_static::lazy_static! {
+    // dates are either ISO (2020-05-31) or Toolbox-style (31/May/2020)
+    static ref DATE_REGEX : regex::Regex = regex::Regex::new(
+        r"^(?:(?P<year>\d{4})-(?P<month>\d{2})-(?P<day>\d{2})|(?P<tb_day>\d{1,2})/(?P<tb_month>[A-Za-z]{3})/(?P<tb_year>\d{4}))$"
+    ).expect("fatal: invalid regex");
+}
+
+const MONTHS : [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"
+];
+
+
+/// Validate the records of a dictionary against its template
+pub fn validate(text: &str, config: &DictionaryConfig) -> Vec<ToolboxFileIssue> {
+    let template = match &config.template {
+        Some(template) => template,
+        None           => return vec!()
+    };
+
+    let mut issues = vec!();
+
+    // the record being validated (start line and the fields seen so far)
+    let mut record : Option<Line> = None;
+    let mut seen : Vec<(&str, bool)> = vec!();
+
+    for (line, token) in Scanner::for_dictionary(text, config) {
+        match token {
+            Token::RecordBegin => {
+                record = Some(line);
+                seen.clear();
+            },
+            Token::Tagged { tag, text } if record.is_some() => {
+                let record = record.as_ref().unwrap();
+                let value = text.trim();
+
+                let field = match template.field(tag) {
+                    Some(field) => field,
+                    None        => continue
+                };
+
+                // check repetition
+                if !field.repeatable && seen.iter().any(|(marker, _)| *marker == tag) {
+                    issues.push(
+                        ToolboxFileIssue::RepeatedField {
+                            record : record.to_owned_line(),
+                            line   : line.to_owned_line()
+                        }
+                    );
+                }
+                seen.push((tag, !value.is_empty()));
+
+                // check the value type
+                if !value.is_empty() && !field.accepts(value) {
+                    issues.push(
+                        ToolboxFileIssue::InvalidFieldValue {
+                            record   : record.to_owned_line(),
+                            line     : line.to_owned_line(),
+                            expected : field.describe_type()
+                        }
+                    );
+                }
+            },
+            Token::RecordEnd { body: _ } => {
+                if let Some(record) = record.take() {
+                    // check the required fields
+                    for field in template.fields.iter().filter(|field| field.required) {
+                        let present = seen.iter().any(|(marker, non_empty)| {
+                            *marker == field.marker && *non_empty
+                        });
+
+                        if !present {
+                            issues.push(
+                                ToolboxFileIssue::MissingField {
+                                    record : record.to_owned_line(),
+                                    marker : field.marker.clone()
+                                }
+                            );
+                        }
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+
+    issues
+}
+
+
+/// Build the text of a new record following the template
+///
+/// The record contains the record tag (with the label), the id tag (if any)
+/// and all template fields. Date fields are set to the current date.
+pub fn new_record(config: &DictionaryConfig, label: &str, id: Option<&str>) -> String {
+    let mut lines = vec!(format!("{} {}", config.record_tag, label));
+
+    if let (Some(id_tag), Some(id)) = (&config.id_tag, id) {
+        lines.push(format!("{} {}", id_tag, id));
+    }
+
+    let fields = config.template.iter().flat_map(|template| template.fields.iter());
+    for field in fields {
+        // these were already written
+        if field.marker == config.record_tag || Some(&field.marker) == config.id_tag.as_ref() {
+            continue;
+        }
+
+        if field.kind == FieldType::Date {
+            lines.push(format!("{} {}", field.marker, toolbox_date_today()));
+        } else {
+            lines.push(field.marker.clone());
+        }
+    }
+
+    let mut text = lines.join("\n");
+    text.push('\n');
+
+    text
+}
+
+
+/// The markers of the fields that differ between two versions of a record text
+///
+/// The markers are listed in the order of their first occurence
+pub fn changed_markers(old: &str, new: &str) -> Vec<String> {
+    use itertools::Itertools;
+
+    // collect the values of all the fields by marker
+    let fields = |text: &str| -> Vec<(String, Vec<String>)> {
+        text.lines()
+            .filter(|line| line.starts_with('\\'))
+            .map(|line| {
+                let end = line.find(char::is_whitespace).unwrap_or(line.len());
+                let (marker, value) = line.split_at(end);
+
+                (marker.to_owned(), value.trim().to_owned())
+            })
+            .into_group_map()
+            .into_iter()
+            .collect()
+    };
+
+    let old_fields = fields(old);
+    let new_fields = fields(new);
+
+    // the order in which the markers appear
+    let markers = new.lines().chain(old.lines())
+        .filter(|line| line.starts_with('\\'))
+        .map(|line| line.split(char::is_whitespace).next().unwrap_or(line))
+        .unique();
+
+    let values = |fields: &[(String, Vec<String>)], marker: &str| {
+        fields.iter()
+            .find(|(m, _)| m == marker)
+            .map(|(_, values)| values.clone())
+            .unwrap_or_default()
+    };
+
+    markers
+        .filter(|marker| values(&old_fields, marker) != values(&new_fields, marker))
+        .map(|marker| marker.to_owned())
+        .collect()
+}
+
+
+/// A change to a field of a record
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "change", rename_all = "kebab-case")]
+pub enum FieldChange {
+    /// The field value was replaced
+    Changed { field: String, old: String, new: String },
+    /// A field was added
+    Added   { field: String, value: String },
+    /// A field was removed
+    Removed { field: String, value: String }
+}
+
+/// A change to a record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordChange {
+    Added    { label: String },
+    Removed  { label: String },
+    Modified { label: String, changes: Vec<FieldChange> }
+}
+
+
+/// Describe the changes between two versions of a clob in terms of records and fields
+///
+/// Records are matched by their position in the clob. Fields are described by their 
+/// template name where available (otherwise by the marker).
+pub fn record_changes(config: &DictionaryConfig, old: &str, new: &str) -> Vec<RecordChange> {
+    use itertools::{Itertools, EitherOrBoth};
+
+    let old_records = records(old, config);
+    let new_records = records(new, config);
+
+    old_records.iter().zip_longest(new_records.iter()).filter_map(|pair| {
+        match pair {
+            EitherOrBoth::Both(old, new) => {
+                let changes = field_changes(config, old, new);
+                
+                if changes.is_empty() {
+                    None
+                } else {
+                    Some(RecordChange::Modified { label: record_label(new), changes })
+                }
+            },
+            EitherOrBoth::Left(old)  => Some(RecordChange::Removed { label: record_label(old) }),
+            EitherOrBoth::Right(new) => Some(RecordChange::Added { label: record_label(new) })
+        }
+    })
+    .collect()
+}
+
+// The changes to the fields of a record
+//
+// Values present in both versions are ignored. The remaining values of every 
+// marker are paired up in order as changes, the rest are additions or removals
+fn field_changes(config: &DictionaryConfig, old: &[(String, String)], new: &[(String, String)]) 
+    -> Vec<FieldChange> 
+{
+    use itertools::{Itertools, EitherOrBoth};
+
+    // the order in which the markers appear
+    let markers = new.iter().chain(old.iter()).map(|(marker, _)| marker).unique();
+    
+    let mut changes = vec!();
+    for marker in markers {
+        let field = field_name(config, marker);
+
+        let mut old_values = values_of(old, marker);
+        let mut new_values = values_of(new, marker);
+        
+        // drop the unchanged values
+        old_values.retain(|value| {
+            match new_values.iter().position(|v| v == value) {
+                Some(i) => { new_values.remove(i); false },
+                None    => true
+            }
+        });
+
+        for pair in old_values.into_iter().zip_longest(new_values) {
+            changes.push(
+                match pair {
+                    EitherOrBoth::Both(old, new) => {
+                        FieldChange::Changed { field: field.clone(), old, new }
+                    },
+                    EitherOrBoth::Left(value)  => FieldChange::Removed { field: field.clone(), value },
+                    EitherOrBoth::Right(value) => FieldChange::Added { field: field.clone(), value }
+                }
+            );
+        }
+    }
+
+    changes
+}
+
+// Split the text into records, each a list of (marker, value) fields
+//
+// Untagged lines are continuations of the previous field value
+fn records(text: &str, config: &DictionaryConfig) -> Vec<Vec<(String, String)>> {
+    let mut records : Vec<Vec<(String, String)>> = vec!();
+
+    for (_, token) in Scanner::for_dictionary(text, config) {
+        match token {
+            Token::RecordBegin => {
+                records.push(vec!());
+            },
+            Token::Tagged { tag, text } | Token::NestedRecordTag { tag, text } => {
+                if let Some(record) = records.last_mut() {
+                    record.push((tag.to_owned(), text.trim().to_owned()));
+                }
+            },
+            Token::Untagged { text } | Token::Continuation { text } => {
+                if let Some((_, value)) = records.last_mut().and_then(|record| record.last_mut()) {
+                    if !value.is_empty() { value.push(' ') }
+                    value.push_str(text.trim());
+                }
+            },
+            _ => {}
+        }
+    }
+
+    records
+}
+
+fn values_of(record: &[(String, String)], marker: &str) -> Vec<String> {
+    record.iter().filter(|(m, _)| m == marker).map(|(_, value)| value.clone()).collect()
+}
+
+fn record_label(record: &[(String, String)]) -> String {
+    record.first().map(|(_, label)| label.clone()).unwrap_or_default()
+}
+
+// The display name of a field (from the template or else from the type file)
+fn field_name(config: &DictionaryConfig, marker: &str) -> String {
+    config.template.as_ref()
+        .and_then(|template| template.field(marker))
+        .and_then(|field| field.name.clone())
+        .or_else(|| config.typ_file.as_ref()?.marker(marker)?.name.clone())
+        .unwrap_or_else(|| marker.to_owned())
+}
+
+impl std::fmt::Display for FieldChange {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use crate::util::truncate_text;
+
+        match self {
+            FieldChange::Changed { field, old, new } => {
+                write!(formatter, "{} changed from '{}' to '{}'", 
+                    field, truncate_text(old, 40), truncate_text(new, 40)
+                )
+            },
+            FieldChange::Added { field, value } => {
+                write!(formatter, "added {} '{}'", field, truncate_text(value, 40))
+            },
+            FieldChange::Removed { field, value } => {
+                write!(formatter, "removed {} '{}'", field, truncate_text(value, 40))
+            }
+        }
+    }
+}
+
+
+impl TemplateConfig {
+    /// Locate the field by marker
+    pub fn field(&self, marker: &str) -> Option<&FieldConfig> {
+        self.fields.iter().find(|field| field.marker == marker)
+    }
+}
+
+impl FieldConfig {
+    /// Check whether the value is compatible with the field type
+    pub fn accepts(&self, value: &str) -> bool {
+        match self.kind {
+            FieldType::Text   => true,
+            FieldType::Date   => is_valid_date(value),
+            FieldType::Number => value.parse::<f64>().is_ok(),
+            FieldType::Set    => self.values.iter().any(|v| v == value)
+        }
+    }
+
+    /// Human-readable description of the field type
+    pub fn describe_type(&self) -> String {
+        match self.kind {
+            FieldType::Text   => "text".to_owned(),
+            FieldType::Date   => "a date".to_owned(),
+            FieldType::Number => "a number".to_owned(),
+            FieldType::Set    => format!("one of {}", self.values.join(", "))
+        }
+    }
+}
+
+
+// Check that the value is an existing date (either ISO or Toolbox-style)
+fn is_valid_date(value: &str) -> bool {
+    let captures = match DATE_REGEX.captures(value) {
+        Some(captures) => captures,
+        None           => return false
+    };
+
+    let number = |name: &str| captures.name(name).and_then(|m| m.as_str().parse::<u32>().ok());
+
+    let (year, month, day) = match captures.name("tb_month") {
+        // Toolbox-style date
+        Some(month) => (
+            number("tb_year"),
+            MONTHS.iter().position(|name| *name == month.as_str()).map(|i| i as u32 + 1),
+            number("tb_day")
+        ),
+        // ISO date
+        None => (number("year"), number("month"), number("day"))
+    };
+
+    let (year, month, day) = match (year, month, day) {
+        (Some(year), Some(month), Some(day)) => (year, month, day),
+        _ => return false
+    };
+
+    let leap_year = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    let days_in_month = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap_year => 29,
+        2 => 28,
+        _ => return false
+    };
+
+    day >= 1 && day <= days_in_month
+}
+
+
+/// The current date in Toolbox format (e.g. 31/May/2020)
+fn toolbox_date_today() -> String {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let days = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() / 86400)
+        .unwrap_or(0) as i64;
+
+    let (year, month, day) = crate::util::civil_date(days);
+
+    format!("{:02}/{}/{}", day, MONTHS[(month - 1) as usize], year)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::is_valid_date;
+
+    #[test]
+    fn test_dates() {
+        for date in ["2020-05-31", "2020-02-29", "2000-02-29", "31/May/2020", "1/Jan/2021", "29/Feb/2024"] {
+            assert!(is_valid_date(date), "{} should be accepted", date);
+        }
+
+        for date in ["2020-13-01", "2020-00-10", "2020-04-31", "2021-02-29", "1900-02-29", "2020-05-00", 
+                     "31/Apr/2020", "0/May/2020", "12/Foo/2020", "12/may/2020", "2020-5-31", "today"] {
+            assert!(!is_valid_date(date), "{} should be rejected", date);
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//
+// src/toolbox/typ.rs
+//
+// Toolbox marker definition (.typ) files. A database type declares the record
+// marker of a Toolbox file and all of its markers (with their names). A dictionary
+// configured with a type file derives its record tag and the field names from
+// it, and the markers that are not defined by the type are reported as issues.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::config::DictionaryConfig;
+use crate::toolbox::{Scanner, ToolboxFileIssue};
+use crate::toolbox::scanner::{Line, Token};
+
+use crate::error;
+use anyhow::Result;
+use std::path::Path;
+
+
+/// A Toolbox database type
+#[derive(Debug, Clone, Default)]
+pub struct TypeFile {
+    /// The name of the database type (e.g. `MDF 4.0`)
+    pub database_type : Option<String>,
+    /// The record marker (with the backslash)
+    pub record_marker : Option<String>,
+    /// The marker definitions in the order of the file
+    pub markers       : Vec<MarkerDefinition>
+}
+
+/// The definition of a marker
+#[derive(Debug, Clone)]
+pub struct MarkerDefinition {
+    /// The marker (with the backslash)
+    pub marker : String,
+    /// The name of the field (e.g. `Gloss (English)`)
+    pub name   : Option<String>
+}
+
+
+impl TypeFile {
+    /// Load a type file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<TypeFile> {
+        let path = path.as_ref();
+
+        let data = std::fs::read(path).map_err(|err| {
+            error::FileReadError {
+                path : path.to_owned(),
+                msg  : err.to_string()
+            }
+        })?;
+
+        // type files are usually not UTF-8, but the markers are ASCII anyway
+        TypeFile::parse(&String::from_utf8_lossy(&data)).map_err(|msg| {
+            error::InvalidTypeFile {
+                path : path.to_owned(),
+                msg
+            }
+            .into()
+        })
+    }
+
+    /// Parse the text of a type file
+    fn parse(text: &str) -> std::result::Result<TypeFile, String> {
+        let mut typ : Option<TypeFile> = None;
+        // the marker being defined
+        let mut definition : Option<MarkerDefinition> = None;
+        // the nested blocks (e.g. fonts) in the marker definition
+        let mut depth = 0;
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if !line.starts_with('\\') { continue }
+
+            let end = line.find(char::is_whitespace).unwrap_or(line.len());
+            let (tag, value) = (&line[1 .. end], line[end ..].trim());
+            let value = if value.is_empty() { None } else { Some(value.to_owned()) };
+
+            let typ = match typ.as_mut() {
+                Some(typ) => typ,
+                None if tag == "+DatabaseType" => {
+                    typ = Some(TypeFile { database_type : value, ..TypeFile::default() });
+                    continue;
+                },
+                None => return Err( format!("line {}: expected \\+DatabaseType", i + 1) )
+            };
+
+            match definition.as_mut() {
+                // the settings of the database type
+                None => match tag {
+                    "+mkr" => {
+                        let marker = value.ok_or_else(|| format!("line {}: missing marker", i + 1))?;
+
+                        definition = Some(
+                            MarkerDefinition { marker : format!("\\{}", marker), name : None }
+                        );
+                    },
+                    "mkrRecord" => {
+                        typ.record_marker = value.map(|marker| format!("\\{}", marker));
+                    },
+                    _ => {}
+                },
+                // the settings of a marker
+                Some(current) => match tag {
+                    "-mkr" if depth == 0 => {
+                        typ.markers.extend(definition.take());
+                    },
+                    "nam" if depth == 0 => {
+                        current.name = value;
+                    },
+                    _ if tag.starts_with('+') => depth += 1,
+                    _ if tag.starts_with('-') => depth -= 1,
+                    _ => {}
+                }
+            }
+        }
+
+        if let Some(definition) = definition {
+            return Err( format!("the definition of {} is not closed", definition.marker) );
+        }
+
+        typ.ok_or_else(|| "expected \\+DatabaseType".to_owned())
+    }
+
+    /// The definition of a marker (with the backslash)
+    pub fn marker(&self, marker: &str) -> Option<&MarkerDefinition> {
+        self.markers.iter().find(|definition| definition.marker == marker)
+    }
+}
+
+
+/// Report the markers of the dictionary records that are not defined by its type file
+///
+/// Markers starting with an underscore (e.g. `\_DateStampHasFourDigitYear`) are used by
+/// Toolbox itself and are never reported
+pub fn validate(text: &str, config: &DictionaryConfig) -> Vec<ToolboxFileIssue> {
+    let typ = match &config.typ_file {
+        Some(typ) => typ,
+        None      => return vec!()
+    };
+
+    let mut issues = vec!();
+    let mut record : Option<Line> = None;
+
+    for (line, token) in Scanner::for_dictionary(text, config) {
+        match token {
+            Token::RecordBegin => {
+                record = Some(line);
+            },
+            Token::Tagged { tag, text: _ } if record.is_some() => {
+                if tag.starts_with("\\_") || typ.marker(tag).is_some() {
+                    continue;
+                }
+
+                issues.push(
+                    ToolboxFileIssue::UnknownMarker {
+                        record : record.as_ref().unwrap().to_owned_line(),
+                        line   : line.to_owned_line()
+                    }
+                );
+            },
+            Token::RecordEnd { body: _ } => {
+                record = None;
+            },
+            _ => {}
+        }
+    }
+
+    issues
+}
@@ -0,0 +1,136 @@
+//
+// src/toolbox/validator.rs
+//
+// External validators check the records of a dictionary for issues that only the
+// project knows about (such as its orthography rules). 
+//
+// A validator is a command that is run by the shell in the working directory. It 
+// gets the records of the dictionary on its standard input, one JSON object per 
+// line (with the line number, the label, the fields and the text of the record), 
+// and prints one issue per line as `<line>\t<message>`, where the line is the 
+// (1-based) number of the line in the dictionary file. The path of the dictionary 
+// is passed in the `GIT_TOOLBOX_DICTIONARY` environment variable.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use super::{Dictionary, Record, ToolboxFileIssue};
+use super::scanner::OwnedLine;
+use crate::config::ValidatorConfig;
+
+
+/// Run the external validators of the dictionary and collect the issues they report
+pub fn validate(dictionary: &Dictionary) -> Vec<ToolboxFileIssue> {
+    let config = dictionary.config();
+    if config.validators.is_empty() {
+        return vec!();
+    }
+
+    let records = dictionary.records();
+    let input = records.iter().map(|record| format!("{}\n", record_json(record))).collect::<String>();
+
+    let mut issues = vec!();
+    for validator in config.validators.iter() {
+        let output = match run_validator(validator, &config.path, &input) {
+            Ok( output ) => output,
+            Err( msg )   => {
+                issues.push(ToolboxFileIssue::ValidatorFailed { validator : validator.name.clone(), msg });
+                continue;
+            }
+        };
+
+        for line in output.lines().filter(|line| !line.trim().is_empty()) {
+            issues.push(
+                read_issue(validator, line, &records, dictionary.text()).unwrap_or_else(|msg| {
+                    ToolboxFileIssue::ValidatorFailed { validator : validator.name.clone(), msg }
+                })
+            );
+        }
+    }
+
+    issues.sort_by_key(|issue| issue.line());
+
+    issues
+}
+
+// The record as passed to the validators (the markers are given without the backslash)
+fn record_json(record: &Record) -> serde_json::Value {
+    let fields = record.fields.iter().map(|(marker, value)| {
+        serde_json::json!({ "marker" : marker.trim_start_matches('\\'), "value" : value })
+    })
+    .collect::<Vec<_>>();
+
+    serde_json::json!({
+        "line"   : record.line.line + 1,
+        "label"  : record.label(),
+        "fields" : fields,
+        "text"   : record.text
+    })
+}
+
+// Run the validator command with the records as input, returning its output
+//
+// A validator may exit with 1 to signal that it found issues, any other non-zero
+// exit status is a failure
+fn run_validator(validator: &ValidatorConfig, path: &str, input: &str) -> Result<String, String> {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(&validator.command)
+        .current_dir(&validator.workdir)
+        .env("GIT_TOOLBOX_DICTIONARY", path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| err.to_string())?;
+
+    // the input is written from a separate thread, so that a validator that reports
+    // issues before it has read all the records does not block
+    let mut stdin = child.stdin.take().expect("fatal: validator input not captured");
+    let input = input.to_owned();
+    let writer = std::thread::spawn(move || {
+        // a validator does not need to read all of its input
+        let _ = stdin.write_all(input.as_bytes());
+    });
+
+    let output = child.wait_with_output().map_err(|err| err.to_string())?;
+    let _ = writer.join();
+
+    match output.status.code() {
+        Some(0) | Some(1) => Ok( String::from_utf8_lossy(&output.stdout).into_owned() ),
+        _                 => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+
+            Err( stderr.lines().next().map(str::to_owned).unwrap_or_else(|| output.status.to_string()) )
+        }
+    }
+}
+
+// Read an issue line of the validator output (`<line>\t<message>`)
+fn read_issue(validator: &ValidatorConfig, line: &str, records: &[Record], text: &str) -> Result<ToolboxFileIssue, String> {
+    let unexpected = || format!("unexpected output '{}'", crate::util::truncate_text(line.trim(), 40));
+
+    let (number, message) = line.split_once('\t').ok_or_else(unexpected)?;
+    let number = number.trim().parse::<usize>().ok().filter(|number| *number > 0).ok_or_else(unexpected)?;
+
+    // the record the line belongs to
+    let index = number - 1;
+    let record = records.iter().find(|record| {
+        record.line.line <= index && index < record.line.line + record.text.lines().count()
+    })
+    .ok_or_else(|| format!("line {} is not in a record", number))?;
+
+    Ok(
+        ToolboxFileIssue::ValidatorIssue {
+            validator : validator.name.clone(),
+            record    : record.line.to_owned_line(),
+            line      : OwnedLine { line : index, text : text.lines().nth(index).unwrap_or("").to_owned() },
+            message   : message.trim().to_owned(),
+            severity  : validator.severity
+        }
+    )
+}
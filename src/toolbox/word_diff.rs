@@ -0,0 +1,160 @@
+//
+// src/toolbox/word_diff.rs
+//
+// Word-level differences between two field values
+//
+// The values are split at the Unicode word boundaries (so that the words, the
+// whitespace and the punctuation are separate tokens) and compared by their
+// longest common subsequence. The common prefix and suffix are skipped first,
+// since the edits to a field are usually local. Values that are too long to
+// compare word by word are reported as replaced as a whole.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use unicode_segmentation::UnicodeSegmentation;
+
+// the maximal size of the comparison table (old tokens × new tokens)
+const MAX_TABLE_SIZE: usize = 1_000_000;
+
+
+/// A part of the compared values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WordChunk<'a> {
+    /// Present in both values
+    Same(&'a str),
+    /// Only present in the old value
+    Removed(&'a str),
+    /// Only present in the new value
+    Added(&'a str)
+}
+
+impl<'a> WordChunk<'a> {
+    pub fn text(&self) -> &'a str {
+        match self {
+            WordChunk::Same(text) | WordChunk::Removed(text) | WordChunk::Added(text) => text
+        }
+    }
+}
+
+
+/// The differences between the old and the new value
+///
+/// The chunks are in the order of the text, with the removed chunk preceding the
+/// added chunk that replaces it.
+pub fn diff_words<'a>(old: &'a str, new: &'a str) -> Vec<WordChunk<'a>> {
+    let old_tokens = old.split_word_bounds().collect::<Vec<_>>();
+    let new_tokens = new.split_word_bounds().collect::<Vec<_>>();
+
+    // the common prefix and suffix
+    let prefix = old_tokens.iter().zip(new_tokens.iter()).take_while(|(a, b)| a == b).count();
+    let suffix = old_tokens[prefix ..].iter().rev()
+        .zip(new_tokens[prefix ..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let old_changed = &old_tokens[prefix .. old_tokens.len() - suffix];
+    let new_changed = &new_tokens[prefix .. new_tokens.len() - suffix];
+
+    let mut chunks = vec!();
+    chunks.extend(old_tokens[.. prefix].iter().map(|token| WordChunk::Same(token)));
+    if old_changed.len() * new_changed.len() > MAX_TABLE_SIZE {
+        chunks.extend(old_changed.iter().map(|token| WordChunk::Removed(token)));
+        chunks.extend(new_changed.iter().map(|token| WordChunk::Added(token)));
+    } else {
+        chunks.extend(lcs_diff(old_changed, new_changed));
+    }
+    chunks.extend(old_tokens[old_tokens.len() - suffix ..].iter().map(|token| WordChunk::Same(token)));
+
+    merge_chunks(old, new, chunks)
+}
+
+// The token differences by the longest common subsequence
+fn lcs_diff<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<WordChunk<'a>> {
+    let (n, m) = (old.len(), new.len());
+
+    // the length of the common subsequence of old[i ..] and new[j ..]
+    let mut table = vec![0usize; (n + 1) * (m + 1)];
+    let at = |i: usize, j: usize| i * (m + 1) + j;
+    for i in (0 .. n).rev() {
+        for j in (0 .. m).rev() {
+            table[at(i, j)] = if old[i] == new[j] {
+                table[at(i + 1, j + 1)] + 1
+            } else {
+                table[at(i + 1, j)].max(table[at(i, j + 1)])
+            };
+        }
+    }
+
+    let mut chunks = vec!();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old[i] == new[j] {
+            chunks.push(WordChunk::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && table[at(i + 1, j)] >= table[at(i, j + 1)]) {
+            chunks.push(WordChunk::Removed(old[i]));
+            i += 1;
+        } else {
+            chunks.push(WordChunk::Added(new[j]));
+            j += 1;
+        }
+    }
+
+    chunks
+}
+
+// Join the adjacent chunks of the same kind, moving the removals before the additions
+//
+// The tokens are slices of the compared values, so the joined chunks are slices too
+fn merge_chunks<'a>(old: &'a str, new: &'a str, chunks: Vec<WordChunk<'a>>) -> Vec<WordChunk<'a>> {
+    // the byte ranges of the merged chunks within their value
+    let mut ranges : Vec<(u8, usize, usize)> = vec!();
+    let (mut old_offset, mut new_offset) = (0, 0);
+
+    // the removed and added chunks between two common chunks
+    let mut removed : Option<(usize, usize)> = None;
+    let mut added   : Option<(usize, usize)> = None;
+    let extend = |range: &mut Option<(usize, usize)>, start: usize, len: usize| {
+        *range = Some( range.map_or((start, start + len), |(s, _)| (s, start + len)) );
+    };
+
+    for chunk in chunks {
+        let len = chunk.text().len();
+
+        match chunk {
+            WordChunk::Same(_) => {
+                ranges.extend(removed.take().map(|(s, e)| (1, s, e)));
+                ranges.extend(added.take().map(|(s, e)| (2, s, e)));
+
+                match ranges.last_mut() {
+                    Some((0, _, end)) => *end += len,
+                    _                 => ranges.push((0, old_offset, old_offset + len))
+                }
+                old_offset += len;
+                new_offset += len;
+            },
+            WordChunk::Removed(_) => {
+                extend(&mut removed, old_offset, len);
+                old_offset += len;
+            },
+            WordChunk::Added(_) => {
+                extend(&mut added, new_offset, len);
+                new_offset += len;
+            }
+        }
+    }
+    ranges.extend(removed.map(|(s, e)| (1, s, e)));
+    ranges.extend(added.map(|(s, e)| (2, s, e)));
+
+    ranges.into_iter().map(|(kind, start, end)| {
+        match kind {
+            0 => WordChunk::Same(&old[start .. end]),
+            1 => WordChunk::Removed(&old[start .. end]),
+            _ => WordChunk::Added(&new[start .. end])
+        }
+    })
+    .collect()
+}
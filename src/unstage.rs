@@ -0,0 +1,205 @@
+//
+// src/unstage.rs
+//
+// Implementation of git-toolbox unstage
+//
+// Removes the staged changes of managed toolbox files from the git index
+// (analogue to git reset -- <files>). The managed folders are restored to
+// match the index, the toolbox files themselves are left as they are, so the
+// changes become unstaged again.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::repository::{Repository, ClobDiff, ClobValidationIssue, DiffStats};
+use crate::config::DictionaryConfig;
+use itertools::{Itertools, Either};
+use crate::cli_app::style;
+use crate::util::truncate_text;
+
+use crate::error;
+use anyhow::{Result, bail};
+
+const MAX_TO_SHOW: usize = 8;
+const MAX_FILENAME_WIDTH: usize = 60;
+
+struct StagedFileSummary {
+    // managed file name for displaying (relative to current folder)
+    pub display_name  : String,
+    // path to the file (relative to the repository)
+    pub path          : String,
+    // path to the managed content
+    pub contents_path : String,
+    // the staged diff
+    pub staged_diff   : Vec<ClobDiff>,
+    // the managed file entry differs from HEAD (e.g. it was newly added)
+    pub file_staged   : bool,
+    // externally modified files
+    pub workdir_issues : Vec<ClobValidationIssue>
+}
+
+
+pub fn unstage(paths: Vec<String>, verbose: bool) -> Result<()> {
+    // load the repository
+    let mut repo = Repository::open()?;
+
+    // no other git-toolbox operations while we are modifying the repository
+    let _lock = repo.lock_operation("unstage")?;
+
+    // dictionary selection
+    let dictionaries : Vec<&DictionaryConfig> = if paths.is_empty() {
+        repo.config().dictionaries.iter().collect()
+    } else {
+        paths.iter().map(|path| {
+            // convert the path to one relative to the repo
+            let path = repo.get_path_relative_to_repo(path)?.to_string_lossy().into_owned();
+
+            repo.config().dictionary_by_path(path)
+        })
+        .collect::<Result<Vec<_>>>()?
+    };
+
+    // process on the requested files
+    let (summaries, errors) : (Vec<_>, Vec<_>) = dictionaries.into_iter().map(|cfg| {
+        StagedFileSummary::new(&repo, cfg)
+    })
+    // split off and collect sucesses and failures
+    .partition_map(|result| -> Either<_, anyhow::Error> {
+        match result {
+            Ok( val )  => Either::Left(val),
+            Err( err ) => Either::Right(err)
+        }
+    });
+
+    // abort if there are errors
+    if !errors.is_empty() {
+        // collect all errors
+        let err_msg = errors.into_iter().join("\n");
+
+        bail!(
+            "{}\n⚠️  There were errors. Aborting. No changes to the repository were made",
+            err_msg
+        );
+    }
+
+    // we are only interested in files that have staged changes
+    let summaries: Vec<_> = summaries.into_iter().filter(StagedFileSummary::any_staged).collect();
+
+    if summaries.is_empty() {
+        stdout!("✅ Nothing to unstage.");
+        return Ok( () )
+    }
+
+    // the managed folders are restored from the index, so external modifications would be lost
+    if summaries.iter().any(StagedFileSummary::any_workdir_issues) {
+        let err_msg = summaries.iter()
+            .filter(|summary| summary.any_workdir_issues())
+            .map(|summary| {
+                error::ExternalModificationsWillBeLost {
+                    path: summary.contents_path.clone().into()
+                }
+            })
+            .join("\n");
+
+        bail!(
+            "{}\n\nUse {cmd} to restore the managed folders first.",
+            err_msg,
+            cmd = style("\"git toolbox reset --repair-contents ...\"")
+        );
+    }
+
+    for summary in summaries.iter() {
+        summary.display_staged_diff(verbose);
+    }
+
+    // restore the index entries from HEAD
+    repo.unstage_paths(summaries.iter().flat_map(|summary| {
+        vec!(&summary.path, &summary.contents_path)
+    }))?;
+
+    // the managed folders have to match the index again
+    for summary in summaries.iter() {
+        repo.restore_folder_from_index(&summary.contents_path)?;
+
+        let stats = DiffStats::count(&summary.staged_diff);
+        stdout!("{} Unstaged {} ({} added, {} modified, {} deleted)",
+            style("✓").green(),
+            &summary.display_name,
+            stats.added,
+            stats.changed,
+            stats.deleted
+        );
+
+        repo.record_audit_entry(
+            "unstage",
+            &summary.path,
+            None,
+            &format!("added={} modified={} deleted={}", stats.added, stats.changed, stats.deleted)
+        )?;
+    }
+
+    stdout!("\n✅ Unstaged {} managed toolbox dictionaries.", summaries.len());
+    stdout!("  (the changes are kept in the files, use {} to stage them again)",
+        style("git toolbox stage").bold()
+    );
+
+    Ok( () )
+}
+
+
+impl StagedFileSummary {
+    pub fn new(repo :&Repository, cfg: &DictionaryConfig) -> Result<Self> {
+        // the file path
+        let path = cfg.path.clone();
+
+        // obtain the printable relative path to the file
+        let display_name = crate::util::get_relative_path(
+            repo.workdir()?.to_owned().join(&cfg.path)
+        ).display().to_string();
+
+        let contents_path = format!("{}.contents", &cfg.path);
+
+        // the changes between HEAD and the index
+        let staged_diff = repo.get_staged_clobs(&contents_path)?;
+        let file_staged = repo.read_text_from_index(&path) != repo.read_text_from_head(&path);
+
+        // verify the managed folder against the index
+        let workdir_issues = repo.validate_clobs_in_workdir(&contents_path, false)?;
+
+        Ok(
+            StagedFileSummary {
+                display_name,
+                path,
+                contents_path,
+                staged_diff,
+                file_staged,
+                workdir_issues
+            }
+        )
+    }
+
+    pub fn any_staged(&self) -> bool {
+        !self.staged_diff.is_empty() || self.file_staged
+    }
+
+    pub fn any_workdir_issues(&self) -> bool {
+        !self.workdir_issues.is_empty()
+    }
+
+    pub fn display_staged_diff(&self, verbose: bool) {
+        stdout!("\n  {}:\n", style(&self.display_name).italic());
+        let to_show = if verbose { self.staged_diff.len() } else { MAX_TO_SHOW };
+        for e in self.staged_diff.iter().take(to_show) {
+            stdout!("        {} {}", e.display_diff_marker(), truncate_text(e.filename(), MAX_FILENAME_WIDTH));
+        }
+        if to_show < self.staged_diff.len() {
+            stdout!("        ...");
+            stdout!("        ({} other changes, use \"{}\" to see all)",
+                self.staged_diff.len() - to_show,
+                style("\"git toolbox unstage -v\"").bold()
+            );
+        }
+        stdout!("");
+    }
+}
@@ -12,6 +12,8 @@
 
 use std::path::Path;
 
+use crate::config::{PathEncoding, PathLayout};
+
 use anyhow::Result;
 
 /// Reads a file into memory with static lifetime
@@ -60,21 +62,32 @@ pub fn _read_file<P: AsRef<Path>>(path: P) -> Result<Option<Vec<u8>>> {
 /// Sanitizes a label, making sure it can be used as a cross-platform 
 /// file name
 ///
-/// This will translate unicode glyphs to ascii sequences and replace
-/// punctuation and other symbols
+/// This will translate unicode glyphs to ascii sequences (or percent-encode
+/// them, depending on the `encoding`) and replace punctuation and other symbols
+///
+/// The label is normalized to Unicode NFC first (see `normalize_nfc()`)
 ///
 /// # Notes
 ///
 /// It is possible for two labels that compare as not equal to produce
 /// equal sanitized strings
-pub fn sanitize_label(label: &str) -> String {
+pub fn sanitize_label(label: &str, encoding: PathEncoding) -> String {
     use deunicode::AsciiChars;
 
-    let sanitized = label.ascii_chars()
-        .map(|chars| chars.unwrap_or("_").chars())
-        .flatten()
+    let label = &*normalize_nfc(label);
+
+    let encoded = match encoding {
+        PathEncoding::Transliterate => {
+            label.ascii_chars().map(|chars| chars.unwrap_or("_")).collect::<String>()
+        },
+        PathEncoding::Percent => {
+            encode_path_component(&label.to_lowercase(), encoding)
+        }
+    };
+
+    let sanitized = encoded.chars()
         .map(|c| {
-            if c.is_ascii_alphanumeric() {
+            if c.is_ascii_alphanumeric() || (c == '%' && encoding == PathEncoding::Percent) {
                 c.to_ascii_lowercase()
             } else {
                 '_'
@@ -93,12 +106,66 @@ pub fn sanitize_label(label: &str) -> String {
     sanitized
 }
 
+/// Encodes a record ID (or a namespace) as a path component 
+///
+/// ASCII characters are preserved, the rest are encoded according to the `encoding`,
+/// so that IDs in any script map deterministically to ASCII-only paths. Percent 
+/// encoding also escapes the `%` sign, which makes it lossless. The text is normalized
+/// to Unicode NFC first (see `normalize_nfc()`)
+pub fn encode_path_component(text: &str, encoding: PathEncoding) -> String {
+    normalize_nfc(text).chars().fold(String::new(), |mut buff, c| {
+        if c.is_ascii() && !c.is_ascii_control() && !(c == '%' && encoding == PathEncoding::Percent) {
+            buff.push(c);
+        } else {
+            buff.push_str(&encode_char(c, encoding));
+        }
+
+        buff
+    })
+}
+
+/// Normalizes the text to Unicode NFC (composed characters)
+///
+/// The same text can be entered with composed characters (usual on Windows and Linux) or
+/// with base characters followed by combining marks (usual on macOS). The record IDs and
+/// labels are normalized before they are mapped to clob paths, so that they map to the
+/// same clob on every platform
+pub fn normalize_nfc(text: &str) -> std::borrow::Cow<'_, str> {
+    use unicode_normalization::{is_nfc_quick, IsNormalized, UnicodeNormalization};
+
+    match is_nfc_quick(text.chars()) {
+        IsNormalized::Yes => std::borrow::Cow::Borrowed(text),
+        _                 => std::borrow::Cow::Owned(text.nfc().collect())
+    }
+}
+
+/// Encodes a single character as an ASCII sequence
+///
+/// Characters without an ASCII transliteration are percent-encoded
+fn encode_char(c: char, encoding: PathEncoding) -> String {
+    use deunicode::deunicode_char;
+
+    let transliterated = match encoding {
+        PathEncoding::Transliterate => deunicode_char(c)
+            .map(|text| text.chars().filter(char::is_ascii_alphanumeric).collect::<String>())
+            .filter(|text| !text.is_empty()),
+        PathEncoding::Percent => None
+    };
+
+    transliterated.unwrap_or_else(|| {
+        let mut bytes = [0; 4];
+
+        c.encode_utf8(&mut bytes).bytes().map(|byte| format!("%{:02X}", byte)).collect()
+    })
+}
+
 /// Generate a nested path prefix for a name
 ///
-/// This function will construct a path from the first four characters 
-/// of the `name`, after unicode normalization and removal of all non-letter-like
-/// unicode components. If the name is too short, the prefix will be appended with
-/// the undesrore sign '_' in order to produce uniform-sized paths
+/// With the `alpha` layout, this function will construct a path from the first
+/// letters of the `name` (two per directory level), after unicode normalization
+/// and removal of all non-letter-like unicode components. If the name is too short,
+/// the prefix will be appended with the undesrore sign '_' in order to produce
+/// uniform-sized paths
 ///
 /// This function is used to spead files over multiple directories to prevent
 /// directory overcrowding. All the directories have transparent names, which 
@@ -109,27 +176,33 @@ pub fn sanitize_label(label: &str) -> String {
 /// This functionality is usually achieved by gegerating a prefix from a
 /// hash. This ensures more balanced distribution of files at the cost of
 /// discoverability. Since we want the users to be able to navigate to 
-/// a specific file quickly, we don't use hashes (and have to live with 
-/// the fact that some directories will have more files in them)
-pub fn build_path_prefix(name: &str) -> String {
+/// a specific file quickly, we don't use hashes by default (and have to live with 
+/// the fact that some directories will have more files in them). Languages where
+/// most words start with the same syllables can use the `hash` layout instead,
+/// see `hash_path_prefix()`
+pub fn build_path_prefix(name: &str, layout: PathLayout, depth: usize) -> String {
     use unicode_normalization::UnicodeNormalization;
     use itertools::Itertools;
     use std::iter;
+
+    if layout == PathLayout::Hash {
+        return hash_path_prefix(name, depth);
+    }
   
-    // extract a four letter prefix from the name
+    // extract a prefix from the name
     let prefixes = name
         // use canonical decomposition
         // to split up and eliminate combining marks etc.
         // leaving only base letter-like components 
         .nfd()
         .filter(|c| { c.is_alphanumeric() })
-        // limit the sequence to 4 letter-likes
-        .take(4)
+        // limit the sequence to two letter-likes per level
+        .take(depth*2)
         // extend with sequence of _ in case the prefix itself
         // is too short
         .chain(iter::repeat_with(|| '_'))
-        // limit the sequence to 4 letter-likes
-        .take(4)
+        // limit the sequence to two letter-likes per level
+        .take(depth*2)
         // consume the prefixes in chunks of two 
         .chunks(2);
     
@@ -142,23 +215,162 @@ pub fn build_path_prefix(name: &str) -> String {
   })
 }
 
+/// Generate a nested path prefix for a name from its hash
+///
+/// Every directory level is named by two hex digits of the hash of the lowercased
+/// name, prefixed by `#` (e.g. `#3f/#a0`). The `#` tells these directories apart from 
+/// the ones of the `alpha` layout (which only use letters, digits and `_`), so that the
+/// clobs can be ordered by their names regardless of the layout (see `is_hash_prefix()`).
+/// The names that only differ in case get the same prefix
+pub fn hash_path_prefix(name: &str, depth: usize) -> String {
+    use git2::{Oid, ObjectType};
+
+    let hash = Oid::hash_object(ObjectType::Blob, name.to_lowercase().as_bytes())
+        .map(|oid| oid.to_string())
+        .expect("Internal error: unable to hash a clob name");
+
+    (0 .. depth.min(hash.len()/2)).map(|level| {
+        format!("{}{}", HASH_PREFIX_MARK, &hash[level*2 .. level*2 + 2])
+    })
+    .collect::<Vec<_>>()
+    .join("/")
+}
+
+// the first character of the directories of the hash layout
+const HASH_PREFIX_MARK : char = '#';
+
+/// Check whether a path component is a directory of the hash layout
+pub fn is_hash_prefix(component: &str) -> bool {
+    component.starts_with(HASH_PREFIX_MARK)
+}
+
+/// The first character of the directories with the records of the other record types
+pub const RECORD_TYPE_MARK : char = '+';
+
+/// Check whether a path component is a directory of a record type
+pub fn is_record_type_dir(component: &str) -> bool {
+    component.starts_with(RECORD_TYPE_MARK)
+}
+
 
-/// Truncate the text to the given display length, adding ellipsis dots if truncated
-pub fn truncate_text(text: &str, length : usize) -> String {
+/// Truncate the text to the given display width, adding ellipsis dots if truncated
+///
+/// This is the utility to use for all displayed text. The width is measured in 
+/// terminal columns (wide characters take two columns), grapheme clusters are never 
+/// split and ANSI escape codes are preserved (they do not take up any space).
+pub fn truncate_text(text: &str, width : usize) -> String {
   use unicode_segmentation::UnicodeSegmentation;
+  use unicode_width::UnicodeWidthStr;
+  use console::AnsiCodeIterator;
 
-  let mut result = String::with_capacity(length + 3);
+  const ELLIPSIS : &str = "...";
 
-  for grapheme in text.graphemes(true).take(length.saturating_sub(3)) {
-    result.push_str(grapheme);
+  // nothing to truncate
+  if text_width(text) <= width {
+    return text.to_owned();
   }
-  if result.len() < text.len() {
-    result.push_str("...");
+
+  let available = width.saturating_sub(ELLIPSIS.len());
+  let mut result = String::with_capacity(text.len());
+  let mut used = 0;
+  let mut truncated = false;
+
+  for (segment, is_ansi) in AnsiCodeIterator::new(text) {
+    // we keep the escape codes so that the styles are properly terminated
+    if is_ansi {
+      result.push_str(segment);
+      continue;
+    }
+    if truncated { continue; }
+
+    for grapheme in segment.graphemes(true) {
+      let grapheme_width = grapheme.width();
+
+      if used + grapheme_width > available {
+        result.push_str(ELLIPSIS);
+        truncated = true;
+        break;
+      }
+
+      result.push_str(grapheme);
+      used += grapheme_width;
+    }
   }
 
   result
 }
 
+/// The display width of the text (in terminal columns, ignoring ANSI escape codes)
+pub fn text_width(text: &str) -> usize {
+  console::measure_text_width(text)
+}
+
+/// Pad the text with spaces to the given display width (the text is not truncated)
+///
+/// Unlike the `{:<width}` format specifier, this takes wide characters and ANSI
+/// escape codes into account, which makes it suitable for table cells
+pub fn pad_text(text: &str, width: usize) -> String {
+  let padding = width.saturating_sub(text_width(text));
+
+  format!("{}{}", text, " ".repeat(padding))
+}
+
+/// Convert the days since the Unix epoch to a civil date (year, month, day)
+///
+/// See http://howardhinnant.github.io/date_algorithms.html
+pub fn civil_date(days: i64) -> (i64, u32, u32) {
+  let z = days + 719468;
+  let era = z.div_euclid(146097);
+  let doe = z - era * 146097;
+  let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let day = doy - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+  (year, month as u32, day as u32)
+}
+
+/// Format a time (seconds since the Unix epoch) in the given timezone 
+/// (offset in minutes) as e.g. 2020-05-31 14:02 +0200
+pub fn format_time(seconds: i64, offset: i32) -> String {
+  let local = seconds + offset as i64 * 60;
+  let (year, month, day) = civil_date(local.div_euclid(86400));
+  let minutes = local.rem_euclid(86400) / 60;
+
+  format!("{}-{:02}-{:02} {:02}:{:02} {}{:02}{:02}", 
+    year, month, day, 
+    minutes / 60, minutes % 60,
+    if offset < 0 { '-' } else { '+' }, offset.abs() / 60, offset.abs() % 60
+  )
+}
+
+/// Parse a time interval such as `30m`, `2h` or `1d` (in seconds)
+///
+/// A number without a unit is taken to be minutes
+pub fn parse_interval(text: &str) -> Option<u64> {
+  let text = text.trim();
+  let (number, unit) = text.split_at(text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len()));
+
+  let factor = match unit.trim() {
+    "s"       => 1,
+    "m" | ""  => 60,
+    "h"       => 60*60,
+    "d"       => 24*60*60,
+    _         => return None
+  };
+
+  number.parse::<u64>().ok().filter(|number| *number > 0).map(|number| number * factor)
+}
+
+/// The offset of the local timezone (in minutes)
+///
+/// libgit2 knows the local timezone (as used for the commit times)
+pub fn local_time_offset() -> i32 {
+  git2::Signature::now("git-toolbox", "").map(|signature| signature.when().offset_minutes()).unwrap_or(0)
+}
+
 /// Obtain the path relative to the current directory
 pub fn get_relative_path<P: AsRef<std::path::Path>>(path: P) -> std::path::PathBuf {
     use pathdiff::diff_paths;
@@ -0,0 +1,438 @@
+//
+// src/verify.rs
+//
+// Implementation of git-toolbox verify
+//
+// Checks that the records of the managed toolbox files survive a stage/reset
+// cycle: the working copy is split into clobs, reassembled and the records of
+// both versions are compared (the order of the records is not significant, as
+// the records are stored by their IDs or labels).
+//
+// In addition, the records are checked against the record checksums maintained
+// by git toolbox stage, reporting the records that were changed without it
+// (e.g. by editing the managed folders directly). With --deep, every commit in
+// the history is checked as well. With --cross-platform, the split output is
+// checked for anything that would make the managed folders differ between
+// Windows, macOS and Linux.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::repository::{Repository, UnverifiedChange, UnverifiedReason, RESERVED_NAMES, assemble_clobs};
+use crate::toolbox::{Dictionary, Record};
+use crate::config::DictionaryConfig;
+use crate::cli_app::style;
+use crate::util::{format_time, truncate_text};
+use crate::output::{self, Report};
+
+use crate::error;
+use anyhow::{Result, bail};
+use itertools::Itertools;
+use serde::Serialize;
+
+// the number of characters of the commit id to show
+const COMMIT_ID_WIDTH : usize = 8;
+
+
+pub fn verify(paths: Vec<String>, deep: bool, cross_platform: bool) -> Result<()> {
+    // open the repository
+    let repo = Repository::open()?;
+
+    // dictionary selection
+    let dictionaries : Vec<&DictionaryConfig> = if paths.is_empty() {
+        repo.config().dictionaries.iter().collect()
+    } else {
+        paths.iter().map(|path| {
+            // convert the path to one relative to the repo
+            let path = repo.get_path_relative_to_repo(path)?.to_string_lossy().into_owned();
+
+            repo.config().dictionary_by_path(path)
+        })
+        .collect::<Result<Vec<_>>>()?
+    };
+
+    let mut files = vec!();
+    for cfg in dictionaries {
+        let contents_path = format!("{}.contents", cfg.path);
+
+        // the round trip of the working copy
+        let original = Dictionary::load(&repo, cfg, false)?;
+        let reassembled = reassemble(&original, cfg)?;
+        let (changed, introduced) = check_round_trip(&original, &reassembled);
+
+        let altered = changed.iter().map(|record| (Some(record.line.line + 1), record))
+            .chain(introduced.iter().map(|record| (None, record)))
+            .map(|(line, record)| AlteredRecord { line, text : record.line.text.trim().to_owned() })
+            .collect();
+
+        files.push(
+            VerifiedFile {
+                path            : cfg.path.clone(),
+                altered,
+                // the platform independence of the split output
+                platform        : if cross_platform { Some(check_cross_platform(&original, cfg)) } else { None },
+                // the staged contents
+                staged_changes  : repo.verify_staged_records(&contents_path, &cfg.record_tags)?,
+                // the history
+                history_changes : if deep { Some(repo.verify_record_history(&contents_path, &cfg.record_tags)?) } else { None }
+            }
+        );
+    }
+
+    let report = VerifyReport { files };
+    output::emit(&report)?;
+
+    let count = |count: fn(&VerifiedFile) -> usize| report.files.iter().map(count).sum::<usize>();
+
+    // the records introduced by the round trip count as well
+    let altered = count(|file| file.altered.len());
+    if altered > 0 {
+        bail!(
+            error::RecordsAlteredByRoundTrip {
+                count : altered
+            }
+        );
+    }
+
+    let platform_dependent = count(|file| file.platform.as_ref().map(Vec::len).unwrap_or(0));
+    if platform_dependent > 0 {
+        bail!(
+            error::PlatformDependentContents {
+                count : platform_dependent
+            }
+        );
+    }
+
+    let unverified = count(|file| {
+        file.staged_changes.as_ref().map(Vec::len).unwrap_or(0) +
+            file.history_changes.as_ref().map(Vec::len).unwrap_or(0)
+    });
+    if unverified > 0 {
+        bail!(
+            error::UnverifiedRecordChanges {
+                count : unverified
+            }
+        );
+    }
+
+    Ok( () )
+}
+
+
+/// The results of git toolbox verify
+#[derive(Serialize)]
+struct VerifyReport {
+    files : Vec<VerifiedFile>
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct VerifiedFile {
+    /// The managed file (relative to the repository)
+    path            : String,
+    /// The records altered by splitting and reassembling the file
+    altered         : Vec<AlteredRecord>,
+    /// The problems that make the managed folder differ between platforms (if checked)
+    platform        : Option<Vec<PlatformProblem>>,
+    /// The staged records changed without git toolbox stage (`None` if there are no checksums)
+    staged_changes  : Option<Vec<UnverifiedChange>>,
+    /// The committed records changed without git toolbox stage (if checked)
+    history_changes : Option<Vec<UnverifiedChange>>
+}
+
+/// A record altered by the round trip
+#[derive(Serialize)]
+struct AlteredRecord {
+    /// The line of the original record (`None` for a record only found in the reassembled file)
+    line : Option<usize>,
+    /// The first line of the record
+    text : String
+}
+
+/// A problem that makes the managed folder differ between platforms
+#[derive(Serialize)]
+struct PlatformProblem {
+    /// `ordering`, `newlines`, `case` or `path`
+    kind        : &'static str,
+    description : String
+}
+
+impl Report for VerifyReport {
+    fn display(&self) {
+        for file in self.files.iter() {
+            file.display();
+        }
+    }
+
+    /// The lines are:
+    ///
+    /// - `file <managed file>`
+    /// - `altered <line> <record>` (the line is empty for a record only found after reassembling)
+    /// - `platform <kind> <description>`
+    /// - `unverified <staged|committed> <reason> <clob> <record> <commit>`
+    ///
+    /// The lines that belong to a managed file follow its `file` line
+    fn porcelain(&self) -> Vec<Vec<String>> {
+        let mut lines = vec!();
+
+        for file in self.files.iter() {
+            lines.push(vec!("file".to_owned(), file.path.clone()));
+
+            for record in file.altered.iter() {
+                lines.push(vec!(
+                    "altered".to_owned(),
+                    record.line.map(|line| line.to_string()).unwrap_or_default(),
+                    record.text.clone()
+                ));
+            }
+            for problem in file.platform.iter().flatten() {
+                lines.push(vec!("platform".to_owned(), problem.kind.to_owned(), problem.description.clone()));
+            }
+
+            let changes = file.staged_changes.iter().flatten().map(|change| ("staged", change))
+                .chain(file.history_changes.iter().flatten().map(|change| ("committed", change)));
+            for (origin, change) in changes {
+                lines.push(vec!(
+                    "unverified".to_owned(),
+                    origin.to_owned(),
+                    change.reason.name().to_owned(),
+                    change.path.clone(),
+                    change.record.to_string(),
+                    change.commit.as_ref().map(|commit| commit.id.clone()).unwrap_or_default()
+                ));
+            }
+        }
+
+        lines
+    }
+}
+
+impl VerifiedFile {
+    fn display(&self) {
+        stdout!("{}", style(&self.path).bold());
+        stdout!("");
+
+        if self.altered.is_empty() {
+            stdout!("  {} the records are preserved by splitting and reassembling the file", style("✓").green());
+        } else {
+            stdout!("  Records that would be altered by {} and {}:", 
+                style("git toolbox stage").bold(),
+                style("git toolbox reset").bold()
+            );
+            stdout!("");
+            for record in self.altered.iter() {
+                let location = match record.line {
+                    Some(line) => style(format!("line:{:<8}", line)).yellow(),
+                    None       => style(format!("{:<13}", "reassembled")).red()
+                };
+
+                stdout!("        {} {}", location, truncate_text(&record.text, 60));
+            }
+            stdout!("");
+        }
+
+        match &self.platform {
+            None => {},
+            Some(problems) if problems.is_empty() => {
+                stdout!("  {} the managed folder is the same on every platform", style("✓").green());
+            },
+            Some(problems) => {
+                stdout!("  Problems that would make the managed folder differ between platforms:");
+                stdout!("");
+                for problem in problems.iter() {
+                    stdout!("        {} {}", style(problem.kind).red(), problem.description);
+                }
+                stdout!("");
+            }
+        }
+
+        match &self.staged_changes {
+            None => {
+                stdout!("  (no record checksums yet, they are created by {})",
+                    style("git toolbox stage").bold()
+                );
+            },
+            Some(changes) if changes.is_empty() => {
+                stdout!("  {} the staged records match their checksums", style("✓").green());
+            },
+            Some(changes) => {
+                stdout!("  Staged records changed without {}:", style("git toolbox stage").bold());
+                stdout!("");
+                display_changes(changes);
+            }
+        }
+
+        match &self.history_changes {
+            None => {},
+            Some(changes) if changes.is_empty() => {
+                stdout!("  {} all the committed record changes were made by {}",
+                    style("✓").green(),
+                    style("git toolbox stage").bold()
+                );
+            },
+            Some(changes) => {
+                stdout!("  Committed records changed without {}:", style("git toolbox stage").bold());
+                stdout!("");
+                display_changes(changes);
+            }
+        }
+
+        stdout!("");
+    }
+}
+
+
+// Split the dictionary and reassemble it from the clobs
+fn reassemble(dictionary: &Dictionary, cfg: &DictionaryConfig) -> Result<Dictionary> {
+    let (clobs, _) = dictionary.split();
+    let text = String::from_utf8(assemble_clobs(clobs.collect(), &cfg.sort))?;
+
+    Ok( Dictionary::from_text(cfg, text) )
+}
+
+// Compare the records of the working copy of the dictionary and of its reassembled version
+//
+// Returns the original records that are not preserved and the reassembled records that 
+// do not match any original one. Trailing whitespace is not significant.
+fn check_round_trip<'a>(original: &'a Dictionary, reassembled: &'a Dictionary) -> (Vec<Record<'a>>, Vec<Record<'a>>) {
+    use std::collections::HashMap;
+
+    // the records with their normalized texts (computed once for both directions)
+    let normalized = |dictionary: &'a Dictionary| -> Vec<(String, Record<'a>)> {
+        dictionary.records().into_iter().map(|record| {
+            let text = record.text.lines().map(|line| line.trim_end()).join("\n").trim_end().to_owned();
+            (text, record)
+        })
+        .collect()
+    };
+
+    let original = normalized(original);
+    let reassembled = normalized(reassembled);
+
+    // the records of `records` that have no counterpart in `other`
+    let unmatched = |records: &[(String, Record<'a>)], other: &[(String, Record<'a>)]| -> Vec<Record<'a>> {
+        let mut counts = HashMap::new();
+        for (text, _) in other {
+            *counts.entry(text.as_str()).or_insert(0_usize) += 1;
+        }
+
+        records.iter().filter(|(text, _)| {
+            match counts.get_mut(text.as_str()) {
+                Some(count) if *count > 0 => {
+                    *count -= 1;
+                    false
+                },
+                _ => true
+            }
+        })
+        .map(|(_, record)| record.clone())
+        .collect()
+    };
+
+    (unmatched(&original, &reassembled), unmatched(&reassembled, &original))
+}
+
+
+// the maximal length of a path component (in bytes) supported by the common file systems
+const MAX_COMPONENT_LENGTH : usize = 255;
+
+// Check that the split output of the dictionary does not depend on the platform
+//
+// The dictionary is split repeatedly and with both Unix and Windows line endings, which 
+// must all produce the same clobs. The clob paths must be valid on Windows and must not 
+// collide on case-insensitive file systems (the default on Windows and macOS).
+fn check_cross_platform(dictionary: &Dictionary, cfg: &DictionaryConfig) -> Vec<PlatformProblem> {
+    use std::collections::HashMap;
+
+    let split = |dictionary: &Dictionary| -> Vec<(String, String)> {
+        dictionary.split().0.map(|clob| (clob.path, clob.content)).collect()
+    };
+
+    let mut problems = vec!();
+
+    let clobs = split(dictionary);
+
+    // the order of the clobs
+    if split(dictionary) != clobs {
+        problems.push(PlatformProblem { kind : "ordering", description : "splitting the file twice produces different clobs".to_owned() });
+    }
+
+    // the line endings
+    let unix = dictionary.text().replace("\r\n", "\n");
+    let windows = unix.replace('\n', "\r\n");
+    if split(&Dictionary::from_text(cfg, unix)) != split(&Dictionary::from_text(cfg, windows)) {
+        problems.push(PlatformProblem { kind : "newlines", description : "the clobs depend on the line endings of the file".to_owned() });
+    }
+
+    // the clob paths
+    let mut seen : HashMap<String, &str> = HashMap::new();
+    for (path, _) in clobs.iter() {
+        if let Some(other) = seen.insert(path.to_ascii_lowercase(), path) {
+            problems.push(PlatformProblem { kind : "case", description : format!("{} and {}", other, path) });
+        }
+
+        for component in path.split('/') {
+            if let Some(reason) = windows_path_problem(component) {
+                problems.push(PlatformProblem { kind : "path", description : format!("{} ({})", path, reason) });
+                break;
+            }
+        }
+    }
+
+    problems
+}
+
+// The reason why a path component is not valid on Windows (if any)
+fn windows_path_problem(component: &str) -> Option<&'static str> {
+    let stem = component.split('.').next().unwrap_or(component);
+
+    if component.len() > MAX_COMPONENT_LENGTH {
+        Some("name too long")
+    } else if RESERVED_NAMES.contains(&stem.to_ascii_lowercase().as_str()) {
+        Some("reserved name")
+    } else if component.chars().any(|c| c.is_control() || "<>:\"|?*\\".contains(c)) {
+        Some("invalid character")
+    } else if component.ends_with('.') || component.ends_with(' ') {
+        Some("trailing dot or space")
+    } else {
+        None
+    }
+}
+
+
+fn display_changes(changes: &[UnverifiedChange]) {
+    for change in changes {
+        let reason = match change.reason {
+            UnverifiedReason::Modified        => style("modified").red(),
+            UnverifiedReason::Added           => style("added").red(),
+            UnverifiedReason::Deleted         => style("deleted").red(),
+            UnverifiedReason::BrokenHistory   => style("checksum history broken").red(),
+            UnverifiedReason::ManifestRemoved => style("checksums removed").red()
+        };
+
+        let record = if change.reason == UnverifiedReason::ManifestRemoved {
+            change.path.clone()
+        } else {
+            format!("{} (record {})", change.path, change.record)
+        };
+
+        match &change.commit {
+            Some(commit) => {
+                let (seconds, offset) = commit.time;
+
+                stdout!("        {} {} {} {}",
+                    style(&commit.id[.. COMMIT_ID_WIDTH.min(commit.id.len())]).yellow(),
+                    format_time(seconds, offset),
+                    style(&commit.author).italic(),
+                    reason
+                );
+                stdout!("            {}", record);
+            },
+            None => {
+                stdout!("        {} {}", reason, record);
+            }
+        }
+    }
+    stdout!("");
+}
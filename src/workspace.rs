@@ -0,0 +1,101 @@
+//
+// src/workspace.rs
+//
+// Running git-toolbox commands across several repositories (--repo)
+//
+// A command is run in each of the repositories in turn (as if it was started
+// in their working directories), followed by a combined summary that lists the
+// repositories where the command failed. This allows to supervise several
+// projects with a single invocation.
+//
+// (C) 2020 Taras Zakharko
+//
+// This code is licensed under GPL 3.0
+
+use crate::cli_app::style;
+use crate::output::{output_format, OutputFormat};
+
+use crate::error;
+use anyhow::{Result, bail};
+
+
+/// Run the command in each of the repositories
+///
+/// In the porcelain format, the output of every repository is introduced by a 
+/// `repository <path>` line. The summary is only shown in the human format
+pub fn run_in_repositories<F>(repos: Vec<String>, mut command: F) -> Result<()>
+where
+    F : FnMut() -> Result<()>
+{
+    let initial_dir = std::env::current_dir().map_err(|err| {
+        error::FileReadError {
+            path : ".".into(),
+            msg  : err.to_string()
+        }
+    })?;
+
+    // (repository, error message if the command failed)
+    let mut results = vec!();
+
+    for repo in repos.iter() {
+        // the repository paths are relative to the initial directory
+        let path = initial_dir.join(repo);
+
+        if output_format() == OutputFormat::Porcelain {
+            println!("repository\t{}", repo.replace(['\t', '\n'], " "));
+        }
+        stdout!("{}", style(format!("== {} ==", repo)).bold());
+        stdout!("");
+
+        let result = if path.is_dir() {
+            std::env::set_current_dir(&path).map_err(|err| {
+                error::FileReadError {
+                    path : path.clone(),
+                    msg  : err.to_string()
+                }
+                .into()
+            })
+            .and_then(|_| command())
+        } else {
+            Err( error::FileNotFound { path : path.clone() }.into() )
+        };
+
+        // the paths in the messages are relative to the initial directory again
+        std::env::set_current_dir(&initial_dir).map_err(|err| {
+            error::FileReadError {
+                path : initial_dir.clone(),
+                msg  : err.to_string()
+            }
+        })?;
+
+        if let Err(err) = &result {
+            stderr!("{}", err);
+        }
+
+        stdout!("");
+
+        results.push((repo, result.err()));
+    }
+
+    let failed = results.iter().filter(|(_, err)| err.is_some()).count();
+
+    stdout!("Summary for {} repositories:", results.len());
+    for (repo, err) in results.iter() {
+        match err {
+            None         => stdout!("    {} {}", style("✓").green(), repo),
+            Some( _ )    => stdout!("    {} {} {}", style("✗").red(), repo, style("(failed)").red())
+        }
+    }
+    stdout!("");
+
+    if failed > 0 {
+        bail!(
+            error::CommandFailedInRepositories {
+                failed,
+                total : results.len()
+            }
+        );
+    }
+
+    Ok( () )
+}